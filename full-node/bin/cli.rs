@@ -91,6 +91,14 @@ pub struct CliOptionsRun {
     /// Maximum number of JSON-RPC clients that can be connected simultaneously. Ignored if no server.
     #[arg(long, default_value = "64")]
     pub json_rpc_max_clients: u32,
+    /// Origin allowed to access the JSON-RPC server from a browser. Can be passed multiple times.
+    /// All origins are allowed if not passed or if equal to "all".
+    #[arg(long)]
+    pub json_rpc_cors: Vec<String>,
+    /// Maximum amount of fuel (roughly, number of WebAssembly instructions) of runtime calls
+    /// made on behalf of JSON-RPC clients or network peers. 0 for no limit.
+    #[arg(long, default_value = "10000000000")]
+    pub runtime_call_fuel_limit: u64,
     /// List of secret phrases to insert in the keystore of the node. Used to author blocks.
     #[arg(long, value_parser = decode_sr25519_private_key)]
     // TODO: also automatically add the same keys through ed25519?
@@ -98,6 +106,9 @@ pub struct CliOptionsRun {
     /// Address of a Jaeger agent to send traces to (hint: port is typically 6831).
     #[arg(long)]
     pub jaeger: Option<SocketAddr>,
+    /// Bind point of the HTTP server exposing Prometheus metrics under `/metrics`.
+    #[arg(long)]
+    pub prometheus_address: Option<SocketAddr>,
     /// Do not load or store anything on disk.
     #[arg(long)]
     pub tmp: bool,
@@ -108,6 +119,10 @@ pub struct CliOptionsRun {
     /// chain is not a parachain.
     #[arg(long, default_value = "256M", value_parser = parse_max_bytes)]
     pub relay_chain_database_cache_size: MaxBytes,
+    /// After a warp sync, download the entire storage of the finalized block before importing
+    /// further blocks, instead of downloading storage items on demand.
+    #[arg(long)]
+    pub state_sync: bool,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -187,6 +187,14 @@ async fn run(cli_options: cli::CliOptionsRun) {
         cli::Output::Auto => unreachable!(), // Handled above.
     };
 
+    // Must be kept in sync with the filtering performed by `log_callback` above.
+    let debug_logs_enabled = match (&cli_output, &cli_options.log_level) {
+        (cli::Output::None, _) => false,
+        (_, Some(log_level)) => matches!(log_level, cli::LogLevel::Debug | cli::LogLevel::Trace),
+        (cli::Output::Informant, None) => false,
+        (_, None) => true,
+    };
+
     let chain_spec =
         fs::read(&cli_options.path_to_chain_spec).expect("Failed to read chain specification");
     let parsed_chain_spec = {
@@ -223,6 +231,10 @@ async fn run(cli_options: cli::CliOptionsRun) {
     let keystore_path = base_storage_directory
         .as_ref()
         .map(|path| path.join(parsed_chain_spec.id()).join("keys"));
+    // Directory supposed to contain the compiled runtimes.
+    let compiled_runtimes_cache_path = base_storage_directory
+        .as_ref()
+        .map(|path| path.join(parsed_chain_spec.id()).join("compiled-runtimes"));
 
     // Build the relay chain information if relevant.
     let (relay_chain, relay_chain_name) =
@@ -261,7 +273,11 @@ async fn run(cli_options: cli::CliOptionsRun) {
                 keystore_path: base_storage_directory
                     .as_ref()
                     .map(|path| path.join(parsed_relay_spec.id()).join("keys")),
+                compiled_runtimes_cache_path: base_storage_directory
+                    .as_ref()
+                    .map(|path| path.join(parsed_relay_spec.id()).join("compiled-runtimes")),
                 json_rpc_listen: None,
+                state_sync: cli_options.state_sync,
             };
 
             (Some(cfg), Some(relay_chain_name.to_owned()))
@@ -371,14 +387,23 @@ async fn run(cli_options: cli::CliOptionsRun) {
             sqlite_database_path,
             sqlite_cache_size: cli_options.database_cache_size.0,
             keystore_path,
+            compiled_runtimes_cache_path,
             json_rpc_listen: if let Some(address) = cli_options.json_rpc_address.0 {
                 Some(smoldot_full_node::JsonRpcListenConfig {
                     address,
                     max_json_rpc_clients: cli_options.json_rpc_max_clients,
+                    cors_allowed_origins: if cli_options.json_rpc_cors.is_empty()
+                        || cli_options.json_rpc_cors.iter().any(|o| o == "all")
+                    {
+                        None
+                    } else {
+                        Some(cli_options.json_rpc_cors.clone())
+                    },
                 })
             } else {
                 None
             },
+            state_sync: cli_options.state_sync,
         },
         relay_chain,
         libp2p_key,
@@ -388,7 +413,14 @@ async fn run(cli_options: cli::CliOptionsRun) {
             Arc::new(move |task| executor.spawn(task).detach())
         },
         log_callback: log_callback.clone(),
+        debug_logs_enabled,
         jaeger_agent: cli_options.jaeger,
+        metrics_listen: cli_options.prometheus_address,
+        runtime_call_fuel_limit: if cli_options.runtime_call_fuel_limit == 0 {
+            None
+        } else {
+            Some(cli_options.runtime_call_fuel_limit)
+        },
     })
     .await;
 
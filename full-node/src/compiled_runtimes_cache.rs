@@ -0,0 +1,276 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! On-disk cache of compiled runtimes.
+//!
+//! Compiling a runtime with wasmtime takes several seconds. In order to avoid recompiling the
+//! same runtime every time the node restarts or switches back to a previously-seen runtime, the
+//! output of the compilation is stored in a directory, one file per compiled runtime.
+//!
+//! Files are named after [`host::HostVmPrototype::compiled_module_cache_key`], which covers the
+//! runtime code, the version of wasmtime, and the features of the CPU. Each file contains a
+//! checksum of the compiled code followed with the compiled code itself. Files that fail to load
+//! are simply overwritten. At most [`MAX_ENTRIES`] files are kept, and the least recently used
+//! ones are removed.
+//!
+//! The content of the files is machine code that is executed without further verification. For
+//! this reason, the directory must be trusted: nobody other than the node must be able to write
+//! in it. On Unix platforms, the directory is made accessible only to its owner, and the cache
+//! isn't used at all if other users are allowed to write in it.
+
+use crate::{LogCallback, LogLevel};
+use smoldot::executor::host;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// Maximum number of compiled runtimes stored in the directory.
+const MAX_ENTRIES: usize = 16;
+
+/// See [the module-level documentation](..).
+pub struct CompiledRuntimesCache {
+    /// Directory where compiled runtimes are stored. If `None`, nothing is cached.
+    directory: Option<PathBuf>,
+
+    /// Function called in order to notify of something.
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+}
+
+impl CompiledRuntimesCache {
+    /// Initializes a new cache. The directory is created if necessary when a runtime is stored.
+    ///
+    /// If `directory` is `None`, runtimes are always compiled and never stored.
+    pub fn new(
+        directory: Option<PathBuf>,
+        log_callback: Arc<dyn LogCallback + Send + Sync>,
+    ) -> Self {
+        CompiledRuntimesCache {
+            directory,
+            log_callback,
+        }
+    }
+
+    /// Builds a runtime. Equivalent to [`host::HostVmPrototype::new`], except that the compiled
+    /// code is loaded from the cache if possible, and stored in the cache otherwise.
+    ///
+    /// Compiling the runtime and accessing the file system are blocking operations, and are
+    /// thus done on a separate thread.
+    pub async fn build_runtime(
+        &self,
+        config: host::Config<impl AsRef<[u8]> + Send + 'static>,
+    ) -> Result<host::HostVmPrototype, host::NewErr> {
+        let directory = self.directory.clone();
+        let log_callback = self.log_callback.clone();
+        smol::unblock(move || build_runtime_blocking(directory.as_deref(), &*log_callback, config))
+            .await
+    }
+}
+
+/// Synchronous implementation of [`CompiledRuntimesCache::build_runtime`].
+fn build_runtime_blocking(
+    directory: Option<&Path>,
+    log_callback: &(dyn LogCallback + Send + Sync),
+    config: host::Config<impl AsRef<[u8]>>,
+) -> Result<host::HostVmPrototype, host::NewErr> {
+    let Some((directory, path)) = directory.and_then(|directory| {
+        let key = host::HostVmPrototype::compiled_module_cache_key(&config)?;
+        Some((directory, directory.join(hex::encode(key))))
+    }) else {
+        return host::HostVmPrototype::new(config);
+    };
+
+    if !is_directory_trusted(directory) {
+        log_callback.log(
+            LogLevel::Warn,
+            format!(
+                "compiled-runtimes-cache-untrusted-directory; path={}",
+                directory.display()
+            ),
+        );
+        return host::HostVmPrototype::new(config);
+    }
+
+    // Try loading the runtime from the cache.
+    if let Ok(file_content) = fs::read(&path) {
+        if let Some(compiled) = verify_checksum(&file_content) {
+            // Safety: the compiled code is executed as is. The checksum only protects against
+            // files that have been partially written or accidentally corrupted, and not against
+            // malicious modifications. This is sound under the assumption that the directory is
+            // trusted, in other words that only this code writes in it. This assumption is
+            // enforced as much as possible by `is_directory_trusted` and by the permissions set
+            // in `store`.
+            // Note that wasmtime verifies by itself that the compiled code has been generated by
+            // the same version and configuration.
+            let config = host::Config {
+                module: config.module.as_ref(),
+                heap_pages: config.heap_pages,
+                exec_hint: config.exec_hint,
+                fuel_metering: config.fuel_metering,
+                allow_unresolved_imports: config.allow_unresolved_imports,
+            };
+            match unsafe { host::HostVmPrototype::from_compiled_module(config, compiled) } {
+                Ok(runtime) => {
+                    // Mark the entry as recently used, in order to not evict it. Failing to do
+                    // so isn't a big deal.
+                    let _ = fs::File::options()
+                        .append(true)
+                        .open(&path)
+                        .and_then(|file| file.set_modified(SystemTime::now()));
+                    return Ok(runtime);
+                }
+                Err(host::NewErr::VirtualMachine(
+                    smoldot::executor::vm::NewErr::InvalidCompiledModule(_),
+                )) => {}
+                // Any other error would also happen when compiling the module.
+                Err(err) => return Err(err),
+            }
+        }
+
+        log_callback.log(
+            LogLevel::Debug,
+            format!(
+                "compiled-runtimes-cache-invalid-entry; path={}",
+                path.display()
+            ),
+        );
+    }
+
+    // Cache miss. Compile the runtime then store it.
+    let runtime = host::HostVmPrototype::new(config)?;
+    if let Some(compiled) = runtime.serialize_compiled_module() {
+        if let Err(err) = store(directory, &path, &compiled) {
+            log_callback.log(
+                LogLevel::Warn,
+                format!(
+                    "compiled-runtimes-cache-write-error; path={}, error={err}",
+                    path.display()
+                ),
+            );
+        }
+
+        if let Err(err) = evict(directory) {
+            log_callback.log(
+                LogLevel::Warn,
+                format!(
+                    "compiled-runtimes-cache-eviction-error; path={}, error={err}",
+                    directory.display()
+                ),
+            );
+        }
+    }
+
+    Ok(runtime)
+}
+
+/// Writes the given compiled module to the given path, prefixed with its checksum.
+///
+/// The directory is created if necessary, and its permissions are restricted to its owner.
+///
+/// The file is first written to a temporary location then renamed, in order to never leave a
+/// partially-written file behind.
+fn store(directory: &Path, path: &Path, compiled: &[u8]) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt as _, PermissionsExt as _};
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(directory)?;
+        fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(directory)?;
+
+    let mut file_content = Vec::with_capacity(32 + compiled.len());
+    file_content.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], compiled).as_bytes());
+    file_content.extend_from_slice(compiled);
+
+    let tmp_path = path.with_extension("tmp");
+    let mut options = fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(&tmp_path)?, &file_content)?;
+    fs::rename(&tmp_path, path)
+}
+
+/// Removes the least recently modified files of the directory until at most [`MAX_ENTRIES`]
+/// remain.
+fn evict(directory: &Path) -> Result<(), io::Error> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        entries.push((metadata.modified()?, entry.path()));
+    }
+
+    if entries.len() <= MAX_ENTRIES {
+        return Ok(());
+    }
+
+    entries.sort_unstable_by_key(|(modified, _)| *modified);
+    let num_to_remove = entries.len() - MAX_ENTRIES;
+    for (_, path) in entries.into_iter().take(num_to_remove) {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns `false` if users other than the owner of the given directory are allowed to write
+/// in it, in which case its content must not be executed.
+///
+/// Returns `true` if the directory doesn't exist, as nothing can be loaded from it anyway.
+fn is_directory_trusted(directory: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        match fs::metadata(directory) {
+            Ok(metadata) => metadata.permissions().mode() & 0o022 == 0,
+            Err(_) => true,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = directory;
+        true
+    }
+}
+
+/// Verifies the checksum at the start of the given file content, and returns the compiled
+/// module that follows it. Returns `None` if the checksum doesn't match.
+fn verify_checksum(file_content: &[u8]) -> Option<&[u8]> {
+    if file_content.len() < 32 {
+        return None;
+    }
+
+    let (checksum, compiled) = file_content.split_at(32);
+    if blake2_rfc::blake2b::blake2b(32, &[], compiled).as_bytes() != checksum {
+        return None;
+    }
+
+    Some(compiled)
+}
@@ -24,7 +24,10 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{database_thread, jaeger_service, network_service, LogCallback, LogLevel};
+use crate::{
+    compiled_runtimes_cache, database_thread, jaeger_service, metrics_service, network_service,
+    LogCallback, LogLevel,
+};
 
 use core::num::NonZeroU32;
 use futures_channel::{mpsc, oneshot};
@@ -44,9 +47,9 @@ use smoldot::{
     header,
     identity::keystore,
     informant::HashDisplay,
-    libp2p,
+    libp2p, metadata,
     network::{self, codec::BlockData},
-    sync::all,
+    sync::{all, state_sync},
     trie,
     verify::body_only,
 };
@@ -70,6 +73,10 @@ pub struct Config {
     /// Function called in order to notify of something.
     pub log_callback: Arc<dyn LogCallback + Send + Sync>,
 
+    /// `true` if [`Config::log_callback`] reports the logs of level [`LogLevel::Debug`]. If
+    /// `false`, the information that only appears in these logs isn't gathered.
+    pub debug_logs_enabled: bool,
+
     /// Database to use to read and write information about the chain.
     pub database: Arc<database_thread::DatabaseThread>,
 
@@ -99,6 +106,12 @@ pub struct Config {
     /// Service to use to report traces.
     pub jaeger_service: Arc<jaeger_service::JaegerService>,
 
+    /// Cache used to avoid recompiling runtimes that have already been compiled in the past.
+    pub compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
+    /// Where to report the duration of block imports.
+    pub metrics: Arc<metrics_service::ChainMetrics>,
+
     /// A node has the authorization to author a block during a slot.
     ///
     /// In order for the network to perform well, a block should be authored and propagated
@@ -116,6 +129,15 @@ pub struct Config {
     /// Note that this value doesn't determine the moment when creating the block has ended, but
     /// the moment when creating the block should start its final phase.
     pub slot_duration_author_ratio: u16,
+
+    /// If `true`, the entire storage of the finalized block is downloaded from peers after a warp
+    /// sync has finished, and no block is imported until this download is complete.
+    ///
+    /// If `false`, the storage items are instead downloaded on demand.
+    ///
+    /// > **Note**: A state sync that was in progress when the node was last shut down is resumed
+    /// >           regardless of the value of this field.
+    pub state_sync_after_warp_sync: bool,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -178,6 +200,8 @@ pub enum InitError {
     FinalizedHeapPagesInvalid(executor::InvalidHeapPagesError),
     /// Error initializing the runtime of the finalized block.
     FinalizedRuntimeInit(executor::host::NewErr),
+    /// The progress of the state sync stored in the database is invalid.
+    StateSyncProgressInvalid(state_sync::DecodeProgressError),
 }
 
 impl ConsensusService {
@@ -191,6 +215,7 @@ impl ConsensusService {
             best_block_hash,
             best_block_number,
             finalized_chain_information,
+            state_sync_progress,
         ) = config
             .database
             .with_database({
@@ -259,6 +284,9 @@ impl ConsensusService {
                         Err(full_sqlite::StorageAccessError::IncompleteStorage)
                         | Err(full_sqlite::StorageAccessError::UnknownBlock) => unreachable!(),
                     };
+                    let state_sync_progress = database
+                        .state_sync_progress()
+                        .map_err(InitError::DatabaseCorruption)?;
                     Ok((
                         finalized_block_number,
                         finalized_heap_pages,
@@ -266,6 +294,7 @@ impl ConsensusService {
                         best_block_hash,
                         best_block_number,
                         finalized_chain_information,
+                        state_sync_progress,
                     ))
                 }
             })
@@ -325,21 +354,50 @@ impl ConsensusService {
             // saved in the database, hence the large number of unwraps here.
             let heap_pages = executor::storage_heap_pages_to_value(finalized_heap_pages.as_deref())
                 .map_err(InitError::FinalizedHeapPagesInvalid)?;
-            executor::host::HostVmPrototype::new(executor::host::Config {
-                module: finalized_code,
-                heap_pages,
-                exec_hint: executor::vm::ExecHint::ValidateAndCompile, // TODO: probably should be decided by the optimisticsync
-                allow_unresolved_imports: false,
-            })
-            .map_err(InitError::FinalizedRuntimeInit)?
+            config
+                .compiled_runtimes_cache
+                .build_runtime(executor::host::Config {
+                    module: finalized_code,
+                    heap_pages,
+                    exec_hint: executor::vm::ExecHint::ValidateAndCompile, // TODO: probably should be decided by the optimisticsync
+                    // Runtime calls that are part of verifying or authoring blocks are never
+                    // limited.
+                    fuel_metering: false,
+                    allow_unresolved_imports: false,
+                })
+                .await
+                .map_err(InitError::FinalizedRuntimeInit)?
         };
 
         let block_author_sync_source = sync
             .prepare_add_source(best_block_number, best_block_hash)
             .add_source(None, NonFinalizedBlock::NotVerified);
 
+        // Resume the state sync that was in progress during the previous run, if any.
+        let state_sync = match state_sync_progress {
+            Some(progress) => Some(
+                state_sync::StateSync::decode_progress(&progress)
+                    .map_err(InitError::StateSyncProgressInvalid)?,
+            ),
+            None => None,
+        };
+
         let (to_background_tx, to_background_rx) = mpsc::channel(4);
 
+        // Decoding the calls of the blocks that are verified requires obtaining and decoding the
+        // metadata of the runtime, which is expensive. This is done by a separate task, and only
+        // if the logs are shown.
+        let block_calls_logs = if config.debug_logs_enabled {
+            let (tx, rx) = mpsc::channel(16);
+            (config.tasks_executor)(Box::pin(run_block_calls_logs(
+                config.log_callback.clone(),
+                rx,
+            )));
+            Some(tx)
+        } else {
+            None
+        };
+
         let background_sync = SyncBackground {
             sync,
             block_author_sync_source,
@@ -360,10 +418,17 @@ impl ConsensusService {
             database_catch_up_download: DatabaseCatchUpDownload::NoDownloadInProgress,
             database_catch_up_download_block_verification:
                 DatabaseCatchUpDownloadBlockVerification::None,
+            state_sync_after_warp_sync: config.state_sync_after_warp_sync,
+            state_sync,
+            state_sync_request_in_progress: false,
+            state_sync_queued: None,
             peers_source_id_map: Default::default(),
             sub_tasks: FuturesUnordered::new(),
             log_callback: config.log_callback,
             jaeger_service: config.jaeger_service,
+            compiled_runtimes_cache: config.compiled_runtimes_cache,
+            block_calls_logs,
+            metrics: config.metrics,
         };
 
         (config.tasks_executor)(Box::pin(background_sync.run()));
@@ -689,8 +754,35 @@ struct SyncBackground {
     /// must be downloaded.
     database_catch_up_download_block_verification: DatabaseCatchUpDownloadBlockVerification,
 
+    /// See [`Config::state_sync_after_warp_sync`].
+    state_sync_after_warp_sync: bool,
+
+    /// If `Some`, the storage of the finalized block is currently being downloaded. No block is
+    /// verified as long as this is the case.
+    ///
+    /// The progress of the state sync is mirrored in the database.
+    state_sync: Option<state_sync::StateSync>,
+
+    /// `true` if a state request is currently in progress for [`SyncBackground::state_sync`].
+    state_sync_request_in_progress: bool,
+
+    /// State sync that replaces [`SyncBackground::state_sync`] once the state request in
+    /// progress has finished. Set when a warp sync finishes while a state request is in
+    /// progress. Only the most recent warp sync target is kept.
+    state_sync_queued: Option<state_sync::StateSync>,
+
     /// How to report events about blocks.
     jaeger_service: Arc<jaeger_service::JaegerService>,
+
+    /// See [`Config::compiled_runtimes_cache`].
+    compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
+    /// Sending side of the channel towards the task that shows the calls of the blocks that are
+    /// verified in the logs. `None` if [`Config::debug_logs_enabled`] is `false`.
+    block_calls_logs: Option<mpsc::Sender<BlockCallsLog>>,
+
+    /// See [`Config::metrics`].
+    metrics: Arc<metrics_service::ChainMetrics>,
 }
 
 #[derive(Clone)]
@@ -739,6 +831,10 @@ enum SubtaskFinished {
         source_id: all::SourceId,
         result: Result<network::service::EncodedMerkleProof, ()>,
     },
+    StateRequestFinished {
+        peer_id: libp2p::PeerId,
+        result: Result<network::service::EncodedStateResponse, network_service::StateRequestError>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -786,6 +882,9 @@ impl SyncBackground {
                     request: all::DesiredRequest,
                     database_catch_up_type: DbCatchUpType,
                 },
+                StartStateSyncRequest {
+                    peer_id: libp2p::PeerId,
+                },
                 NetworkEvent(network_service::Event),
                 NetworkLocalChainUpdate,
                 AnnounceBlock(Vec<u8>, [u8; 32], u64),
@@ -984,6 +1083,27 @@ impl SyncBackground {
                             };
                         }
 
+                        // While a state sync is in progress, the storage is downloaded through
+                        // state requests rather than through the database catch-up mechanism.
+                        if let Some(state_sync) = &self.state_sync {
+                            if !self.state_sync_request_in_progress && !state_sync.is_finished() {
+                                // The virtual source of the block author doesn't have any user
+                                // data and is thus never chosen.
+                                if let Some(peer_id) = self
+                                    .sync
+                                    .sources()
+                                    .filter_map(|s| self.sync[s].as_ref())
+                                    .filter(|info| !info.is_disconnected)
+                                    .map(|info| info.peer_id.clone())
+                                    .choose(&mut rand::thread_rng())
+                                {
+                                    return WakeUpReason::StartStateSyncRequest { peer_id };
+                                }
+                            }
+
+                            future::pending().await
+                        }
+
                         match self.database_catch_up_download_block_verification.clone() {
                             _ if !matches!(
                                 self.database_catch_up_download,
@@ -1171,8 +1291,9 @@ impl SyncBackground {
                         self.database_catch_up_download_block_verification,
                         DatabaseCatchUpDownloadBlockVerification::None
                     );
+                    let is_state_syncing = self.state_sync.is_some();
                     async move {
-                        if !process_sync || !is_downloading {
+                        if !process_sync || !is_downloading || is_state_syncing {
                             future::pending().await
                         }
                         WakeUpReason::SyncProcess
@@ -1733,6 +1854,191 @@ impl SyncBackground {
                     }));
                 }
 
+                WakeUpReason::StartStateSyncRequest { peer_id } => {
+                    let request = self.state_sync.as_ref().unwrap().desired_request().unwrap();
+                    let block_hash = *request.block_hash;
+                    let (child_trie, start_key) = match request.start_key {
+                        network::codec::StateRequestStart::MainTrie(key) => (None, key.to_vec()),
+                        network::codec::StateRequestStart::ChildTrieDefault { child_trie, key } => {
+                            (Some(child_trie.to_vec()), key.to_vec())
+                        }
+                    };
+
+                    self.state_sync_request_in_progress = true;
+
+                    let network_service = self.network_service.clone();
+                    let network_chain_id = self.network_chain_id;
+                    self.sub_tasks.push(Box::pin(async move {
+                        let result = network_service
+                            .state_request(
+                                peer_id.clone(),
+                                network_chain_id,
+                                network::codec::StateRequest {
+                                    block_hash: &block_hash,
+                                    start_key: match &child_trie {
+                                        None => {
+                                            network::codec::StateRequestStart::MainTrie(&start_key)
+                                        }
+                                        Some(child_trie) => {
+                                            network::codec::StateRequestStart::ChildTrieDefault {
+                                                child_trie,
+                                                key: &start_key,
+                                            }
+                                        }
+                                    },
+                                },
+                            )
+                            .await;
+                        SubtaskFinished::StateRequestFinished { peer_id, result }
+                    }));
+                }
+
+                WakeUpReason::SubtaskFinished(SubtaskFinished::StateRequestFinished {
+                    peer_id,
+                    result,
+                }) => {
+                    self.state_sync_request_in_progress = false;
+
+                    // If a warp sync has finished while the request was in progress, the
+                    // response concerns a block whose storage is no longer needed.
+                    if let Some(queued) = self.state_sync_queued.take() {
+                        self.start_state_sync(queued).await;
+                        continue;
+                    }
+
+                    let Some(state_sync) = &mut self.state_sync else {
+                        // The state sync can't be cancelled while a request is in progress.
+                        unreachable!()
+                    };
+
+                    let trie_nodes = match &result {
+                        Ok(response) => state_sync
+                            .inject_response(response.decode())
+                            .map_err(|err| err.to_string()),
+                        Err(err) => Err(err.to_string()),
+                    };
+
+                    let trie_nodes = match trie_nodes {
+                        Ok(trie_nodes) => trie_nodes,
+                        Err(error) => {
+                            self.log_callback.log(
+                                LogLevel::Debug,
+                                format!(
+                                    "state-sync-request-error; peer_id={peer_id}; error={error}"
+                                ),
+                            );
+
+                            if !matches!(
+                                result,
+                                Err(network_service::StateRequestError::NoConnection)
+                            ) {
+                                self.network_service
+                                    .ban_and_disconnect(
+                                        peer_id,
+                                        self.network_chain_id,
+                                        network_service::BanSeverity::Low,
+                                        "state-request-error",
+                                    )
+                                    .await;
+                            }
+                            continue;
+                        }
+                    };
+
+                    let is_finished = state_sync.is_finished();
+                    let num_entries = state_sync.num_entries_downloaded();
+                    let block_hash = *state_sync.block_hash();
+                    let progress = if is_finished {
+                        None
+                    } else {
+                        Some(state_sync.encode_progress())
+                    };
+
+                    let database_result = self
+                        .database
+                        .with_database(move |database| {
+                            for trie_entry_version in
+                                [trie::TrieEntryVersion::V0, trie::TrieEntryVersion::V1]
+                            {
+                                database.insert_trie_nodes(
+                                    trie_nodes
+                                        .iter()
+                                        .filter(|node| {
+                                            node.trie_entry_version == trie_entry_version
+                                        })
+                                        .map(|node| full_sqlite::InsertTrieNode {
+                                            merkle_value: Cow::Borrowed(&node.merkle_value),
+                                            partial_key_nibbles: Cow::Owned(
+                                                node.partial_key_nibbles
+                                                    .iter()
+                                                    .map(|n| u8::from(*n))
+                                                    .collect(),
+                                            ),
+                                            children_merkle_values: array::from_fn(|n| {
+                                                node.children_merkle_values[n]
+                                                    .as_deref()
+                                                    .map(Cow::Borrowed)
+                                            }),
+                                            storage_value: match &node.storage_value {
+                                                Some(value) => {
+                                                    full_sqlite::InsertTrieNodeStorageValue::Value {
+                                                        value: Cow::Borrowed(value),
+                                                        references_merkle_value: node
+                                                            .references_merkle_value,
+                                                    }
+                                                }
+                                                None => {
+                                                    full_sqlite::InsertTrieNodeStorageValue::NoValue
+                                                }
+                                            },
+                                        }),
+                                    u8::from(trie_entry_version),
+                                )?;
+                            }
+
+                            database.set_state_sync_progress(progress.as_deref())
+                        })
+                        .await;
+
+                    if let Err(error) = database_result {
+                        // Without a working database, the downloaded entries can't be stored.
+                        // The state sync is aborted, and the storage items are instead
+                        // downloaded on demand when verifying blocks.
+                        self.log_callback.log(
+                            LogLevel::Warn,
+                            format!(
+                                "state-sync-database-error; block_hash={}; error={error}",
+                                HashDisplay(&block_hash)
+                            ),
+                        );
+                        self.state_sync = None;
+                        self.database_catch_up_download =
+                            DatabaseCatchUpDownload::NoDownloadInProgress;
+                        continue;
+                    }
+
+                    if is_finished {
+                        self.log_callback.log(
+                            LogLevel::Info,
+                            format!(
+                                "state-sync-finished; block_hash={}; num_entries={num_entries}",
+                                HashDisplay(&block_hash)
+                            ),
+                        );
+                        self.state_sync = None;
+                        self.database_catch_up_download =
+                            DatabaseCatchUpDownload::NoDownloadInProgress;
+                    } else {
+                        self.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "state-sync-progress; block_hash={}; num_entries={num_entries}",
+                                HashDisplay(&block_hash)
+                            ),
+                        );
+                    }
+                }
+
                 WakeUpReason::SubtaskFinished(SubtaskFinished::BlocksRequestFinished {
                     request_id,
                     source_id,
@@ -2489,6 +2795,41 @@ impl SyncBackground {
         ));
     }
 
+    /// Sets [`SyncBackground::state_sync`] and stores its progress in the database, so that it
+    /// can be resumed after a restart.
+    ///
+    /// Must only be called when no state request is in progress.
+    async fn start_state_sync(&mut self, state_sync: state_sync::StateSync) {
+        debug_assert!(!self.state_sync_request_in_progress);
+
+        let progress = state_sync.encode_progress();
+        let database_result = self
+            .database
+            .with_database(move |database| database.set_state_sync_progress(Some(&progress)))
+            .await;
+
+        if let Err(error) = database_result {
+            self.log_callback.log(
+                LogLevel::Warn,
+                format!(
+                    "state-sync-database-error; block_hash={}; error={error}",
+                    HashDisplay(state_sync.block_hash())
+                ),
+            );
+            self.state_sync = None;
+            return;
+        }
+
+        self.log_callback.log(
+            LogLevel::Info,
+            format!(
+                "state-sync-started; block_hash={}",
+                HashDisplay(state_sync.block_hash())
+            ),
+        );
+        self.state_sync = Some(state_sync);
+    }
+
     async fn process_blocks(mut self) -> (Self, bool) {
         // The sync state machine can be in a few various states. At the time of writing:
         // idle, verifying header, verifying block, verifying grandpa warp sync proof,
@@ -2552,7 +2893,7 @@ impl SyncBackground {
             }
             all::ProcessOne::WarpSyncBuildRuntime(build_runtime) => {
                 let (new_sync, outcome) =
-                    build_runtime.build(all::ExecHint::ValidateAndCompile, true);
+                    build_runtime.build(all::ExecHint::ValidateAndCompile, false, true);
                 self.sync = new_sync;
                 if let Err(err) = outcome {
                     self.log_callback.log(
@@ -2612,6 +2953,26 @@ impl SyncBackground {
                     self.database_catch_up_download = DatabaseCatchUpDownload::NoDownloadInProgress;
                 }
 
+                // Start downloading the entire storage of the new finalized block, if configured
+                // to do so. If a state request is in progress, the new state sync is started
+                // once it has finished, as the response is no longer relevant.
+                if self.state_sync_after_warp_sync {
+                    let finalized_block = self
+                        .sync
+                        .as_chain_information()
+                        .as_ref()
+                        .finalized_block_header;
+                    let state_sync = state_sync::StateSync::new(state_sync::Config {
+                        block_hash: finalized_block.hash(self.sync.block_number_bytes()),
+                        state_root: *finalized_block.state_root,
+                    });
+                    if self.state_sync_request_in_progress {
+                        self.state_sync_queued = Some(state_sync);
+                    } else {
+                        self.start_state_sync(state_sync).await;
+                    }
+                }
+
                 (self, true)
             }
             all::ProcessOne::WarpSyncFinished {
@@ -2667,8 +3028,18 @@ impl SyncBackground {
                 let scale_encoded_header =
                     header_verification_success.scale_encoded_header().to_vec();
 
+                // The extrinsics are copied only if they are going to be shown in the logs.
+                let block_calls_extrinsics = self.block_calls_logs.is_some().then(|| {
+                    header_verification_success
+                        .scale_encoded_extrinsics()
+                        .unwrap()
+                        .map(|extrinsic| extrinsic.as_ref().to_vec())
+                        .collect::<Vec<_>>()
+                });
+
                 let execute_block_success = match execute_block_and_insert(
                     &self.database,
+                    &self.compiled_runtimes_cache,
                     (*parent_runtime_arc).clone(),
                     &header_verification_success.parent_hash(),
                     header_verification_success.scale_encoded_header(),
@@ -2764,6 +3135,9 @@ impl SyncBackground {
                     }
                 };
 
+                self.metrics
+                    .report_block_import(when_verification_started.elapsed());
+
                 self.log_callback.log(
                     LogLevel::Debug,
                     format!(
@@ -2779,6 +3153,20 @@ impl SyncBackground {
                     ),
                 );
 
+                if let (Some(block_calls_logs), Some(extrinsics)) =
+                    (&mut self.block_calls_logs, block_calls_extrinsics)
+                {
+                    // The extrinsics of the block are executed by the runtime of the parent.
+                    // If the task is lagging behind, the calls of the block are simply not
+                    // shown.
+                    let _ = block_calls_logs.try_send(BlockCallsLog {
+                        runtime: parent_runtime_arc.clone(),
+                        hash: hash_to_verify,
+                        height,
+                        extrinsics,
+                    });
+                }
+
                 match execute_block_success.block_insertion {
                     Ok(()) => {}
                     Err(full_sqlite::InsertError::Duplicate) => {} // TODO: this should be an error ; right now we silence them because non-finalized blocks aren't loaded from the database at startup, resulting in them being downloaded again
@@ -2967,6 +3355,7 @@ impl SyncBackground {
 // TODO: use a config struct for the parameters?
 pub async fn execute_block_and_insert(
     database: &database_thread::DatabaseThread,
+    compiled_runtimes_cache: &compiled_runtimes_cache::CompiledRuntimesCache,
     mut parent_runtime: host::HostVmPrototype,
     parent_block_hash: &[u8; 32],
     block_header: &[u8],
@@ -3017,6 +3406,9 @@ pub async fn execute_block_and_insert(
             &call_parameter,
             runtime_call::StorageProofSizeBehavior::Unimplemented,
             storage_changes,
+            None,
+            false,
+            false,
         )
         .await
         {
@@ -3152,13 +3544,16 @@ pub async fn execute_block_and_insert(
             };
 
             let before_runtime_build = Instant::now();
-            let vm = host::HostVmPrototype::new(host::Config {
-                module: &new_code,
-                heap_pages: new_heap_pages,
-                exec_hint: executor::vm::ExecHint::ValidateAndCompile,
-                allow_unresolved_imports: false,
-            })
-            .map_err(ExecuteBlockInvalidBlockError::InvalidNewRuntime)?;
+            let vm = compiled_runtimes_cache
+                .build_runtime(host::Config {
+                    module: new_code.into_owned(),
+                    heap_pages: new_heap_pages,
+                    exec_hint: executor::vm::ExecHint::ValidateAndCompile,
+                    fuel_metering: false,
+                    allow_unresolved_imports: false,
+                })
+                .await
+                .map_err(ExecuteBlockInvalidBlockError::InvalidNewRuntime)?;
             runtime_build_duration += before_runtime_build.elapsed();
             Some(vm)
         }
@@ -3346,7 +3741,107 @@ pub enum ExecuteBlockInvalidBlockError {
     InvalidNewRuntime(host::NewErr),
 }
 
+/// Re-executes the given block on top of its parent and returns the list of storage accesses
+/// and logs performed by the runtime.
+///
+/// Contrary to [`execute_block_and_insert`], the block isn't executed through
+/// `Core_execute_block` but split into a call to `Core_initialize_block`, one call to
+/// `BlockBuilder_apply_extrinsic` per extrinsic, and a call to `BlockBuilder_finalize_block`, in
+/// order to be able to attribute each storage access to a specific extrinsic.
+///
+/// The outputs of the runtime calls are not verified, and nothing is written to the database.
+pub async fn trace_block(
+    database: &database_thread::DatabaseThread,
+    parent_runtime: host::HostVmPrototype,
+    parent_block_hash: &[u8; 32],
+    block_header: &[u8],
+    block_number_bytes: usize,
+    block_body: impl ExactSizeIterator<Item = impl AsRef<[u8]>>,
+) -> Result<Vec<TracedRuntimeCall>, TraceBlockError> {
+    // `Core_initialize_block` expects the header without its seal.
+    let initialize_parameter = {
+        let mut header = header::decode(block_header, block_number_bytes)
+            .map_err(TraceBlockError::InvalidHeader)?;
+        let _ = header.digest.pop_seal();
+        header.scale_encoding_vec(block_number_bytes)
+    };
+
+    let calls = iter::once(("Core_initialize_block", None, initialize_parameter))
+        .chain(block_body.enumerate().map(|(index, extrinsic)| {
+            (
+                "BlockBuilder_apply_extrinsic",
+                Some(u32::try_from(index).unwrap()),
+                extrinsic.as_ref().to_vec(),
+            )
+        }))
+        .chain(iter::once((
+            "BlockBuilder_finalize_block",
+            None,
+            Vec::new(),
+        )))
+        .collect::<Vec<_>>();
+
+    let mut runtime = parent_runtime;
+    let mut storage_changes = runtime_call::StorageChanges::empty();
+    let mut traced_calls = Vec::with_capacity(calls.len());
+
+    for (function_name, extrinsic_index, parameter) in calls {
+        let success = runtime_call(
+            database,
+            parent_block_hash,
+            runtime,
+            function_name,
+            &parameter,
+            runtime_call::StorageProofSizeBehavior::Unimplemented,
+            storage_changes,
+            // The block has already been verified, and its execution is thus known to be
+            // bounded.
+            None,
+            true,
+            false,
+        )
+        .await
+        .map_err(TraceBlockError::RuntimeCall)?;
+
+        traced_calls.push(TracedRuntimeCall {
+            function_name,
+            extrinsic_index,
+            trace: success.trace.unwrap_or_default(),
+        });
+        runtime = success.runtime;
+        storage_changes = success.storage_changes;
+    }
+
+    Ok(traced_calls)
+}
+
+/// Runtime call performed by [`trace_block`].
+#[derive(Debug)]
+pub struct TracedRuntimeCall {
+    /// Name of the runtime function that has been called.
+    pub function_name: &'static str,
+
+    /// If the call applied an extrinsic, index of this extrinsic within the block body.
+    pub extrinsic_index: Option<u32>,
+
+    /// Storage accesses and logs performed during the call, in order.
+    pub trace: Vec<runtime_call::TraceEvent>,
+}
+
+/// Error returned by [`trace_block`].
+#[derive(Debug, derive_more::Display)]
+pub enum TraceBlockError {
+    /// Failed to decode the header of the block.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidHeader(header::Error),
+    /// Error while executing one of the runtime calls.
+    #[display(fmt = "{_0}")]
+    RuntimeCall(RuntimeCallError),
+}
+
 /// Perform a runtime call, using the database as the source for storage data.
+///
+/// See [`runtime_call::Config::fuel_limit`] for an explanation of `fuel_limit`.
 pub async fn runtime_call(
     database: &database_thread::DatabaseThread,
     storage_block_hash: &[u8; 32],
@@ -3355,6 +3850,9 @@ pub async fn runtime_call(
     parameter: &[u8],
     storage_proof_size_behavior: runtime_call::StorageProofSizeBehavior,
     initial_storage_changes: runtime_call::StorageChanges,
+    fuel_limit: Option<u64>,
+    record_trace: bool,
+    record_storage_proof: bool,
 ) -> Result<RuntimeCallSuccess, RuntimeCallError> {
     let mut call = runtime_call::run(runtime_call::Config {
         virtual_machine: runtime,
@@ -3362,8 +3860,12 @@ pub async fn runtime_call(
         parameter: iter::once(&parameter),
         storage_proof_size_behavior,
         storage_main_trie_changes: initial_storage_changes.into_main_trie_diff(),
-        max_log_level: 0,
+        // Logs are only useful when they are part of the trace.
+        max_log_level: if record_trace { 5 } else { 0 },
+        fuel_limit,
         calculate_trie_changes: true,
+        record_storage_proof,
+        record_trace,
     })
     .map_err(|(err, _)| RuntimeCallError::RuntimeStartError(err))?;
 
@@ -3378,7 +3880,8 @@ pub async fn runtime_call(
                 virtual_machine,
                 storage_changes,
                 state_trie_version,
-                ..
+                trace,
+                storage_proof,
             })) => {
                 let output = virtual_machine.value().as_ref().to_owned();
                 return Ok(RuntimeCallSuccess {
@@ -3387,6 +3890,8 @@ pub async fn runtime_call(
                     storage_changes,
                     state_trie_version,
                     database_accesses_duration,
+                    trace,
+                    storage_proof,
                 });
             }
 
@@ -3497,7 +4002,7 @@ pub async fn runtime_call(
                 call = req.resume();
             }
             runtime_call::RuntimeCall::LogEmit(req) => {
-                // Logs are ignored.
+                // Logs are ignored, except for being added to the trace if enabled.
                 call = req.resume();
             }
             runtime_call::RuntimeCall::SignatureVerification(sig) => {
@@ -3507,6 +4012,42 @@ pub async fn runtime_call(
                 // Offchain storage calls are forbidden.
                 return Err(RuntimeCallError::ForbiddenHostFunction);
             }
+            runtime_call::RuntimeCall::StorageProofNodeValue(req) => {
+                let when_database_access_started = Instant::now();
+
+                let parent_paths = req.child_trie().map(|child_trie| {
+                    trie::bytes_to_nibbles(b":child_storage:default:".iter().copied())
+                        .chain(trie::bytes_to_nibbles(child_trie.as_ref().iter().copied()))
+                        .map(u8::from)
+                        .collect::<Vec<_>>()
+                });
+                let key_nibbles = req.key().map(u8::from).collect::<Vec<_>>();
+                let merkle_value = req.merkle_value().map(|mv| mv.to_vec());
+
+                let storage_block_hash = *storage_block_hash;
+                let node_value = database
+                    .with_database(move |db| {
+                        // The Merkle value of the root of a trie isn't known by the runtime call
+                        // and must be found in the database.
+                        let merkle_value = match merkle_value {
+                            Some(mv) => Some(mv),
+                            None => db.block_storage_closest_descendant_merkle_value(
+                                &storage_block_hash,
+                                parent_paths.into_iter().map(|p| p.into_iter()),
+                                key_nibbles.iter().copied(),
+                            )?,
+                        };
+                        merkle_value.map(|mv| db.trie_node_value(&mv)).transpose()
+                    })
+                    .await;
+                let node_value = match node_value {
+                    Ok(nv) => nv,
+                    Err(error) => return Err(RuntimeCallError::DatabaseParentAccess(error)),
+                };
+
+                database_accesses_duration += when_database_access_started.elapsed();
+                call = req.inject_node_value(node_value.as_deref());
+            }
         }
     }
 }
@@ -3528,6 +4069,12 @@ pub struct RuntimeCallSuccess {
 
     /// Total time the database accesses combined took.
     pub database_accesses_duration: Duration,
+
+    /// Storage accesses and logs performed during the call, if tracing was enabled.
+    pub trace: Option<Vec<runtime_call::TraceEvent>>,
+
+    /// Trie nodes accessed during the call, if the recording of a storage proof was enabled.
+    pub storage_proof: Option<runtime_call::StorageProof>,
 }
 
 /// Error returned by [`runtime_call()`].
@@ -3547,3 +4094,86 @@ pub enum RuntimeCallError {
     /// Runtime has tried to call a forbidden host function.
     ForbiddenHostFunction,
 }
+
+/// Block that has been verified and whose calls must be shown in the logs.
+struct BlockCallsLog {
+    /// Runtime that has executed the extrinsics of the block, in other words the runtime of
+    /// its parent.
+    runtime: Arc<host::HostVmPrototype>,
+    /// Hash of the block.
+    hash: [u8; 32],
+    /// Height of the block.
+    height: u64,
+    /// SCALE-encoded extrinsics of the block.
+    extrinsics: Vec<Vec<u8>>,
+}
+
+/// Task that decodes the extrinsics of the blocks received on `blocks` and shows the list of
+/// calls they contain in the logs, in the format `Pallet.call`. Extrinsics that fail to decode
+/// are shown as `?`. Nothing is shown for blocks whose runtime metadata can't be obtained.
+///
+/// The metadata is obtained and decoded once, then kept for as long as the blocks that are
+/// received use the same runtime.
+async fn run_block_calls_logs(
+    log_callback: Arc<dyn LogCallback + Send + Sync>,
+    mut blocks: mpsc::Receiver<BlockCallsLog>,
+) {
+    let mut next = blocks.next().await;
+
+    while let Some(runtime) = next.as_ref().map(|block| block.runtime.clone()) {
+        let metadata = runtime_metadata((*runtime).clone());
+        let metadata = metadata
+            .as_deref()
+            .and_then(|metadata| metadata::decode(metadata).ok());
+
+        while let Some(block) = next.as_ref() {
+            if !Arc::ptr_eq(&block.runtime, &runtime) {
+                break;
+            }
+
+            if let Some(metadata) = &metadata {
+                let calls = block
+                    .extrinsics
+                    .iter()
+                    .map(
+                        |extrinsic| match metadata::decode_extrinsic(metadata, extrinsic) {
+                            Ok(decoded) => format!("{}.{}", decoded.pallet_name, decoded.call_name),
+                            Err(_) => "?".to_owned(),
+                        },
+                    )
+                    .collect::<Vec<_>>();
+                log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "block-calls; hash={}; height={}; calls={}",
+                        HashDisplay(&block.hash),
+                        block.height,
+                        calls.join(",")
+                    ),
+                );
+            }
+
+            next = blocks.next().await;
+        }
+    }
+}
+
+/// Obtains the metadata of the given runtime. Returns `None` if the runtime doesn't support
+/// version 14 of the metadata, or if the call fails or tries to access the storage.
+fn runtime_metadata(runtime: host::HostVmPrototype) -> Option<Vec<u8>> {
+    let mut call = metadata::metadata_at_version(runtime, 14).ok()?;
+    loop {
+        match call {
+            runtime_call::RuntimeCall::Finished(Ok(success)) => {
+                return metadata::decode_metadata_at_version_output(
+                    success.virtual_machine.value().as_ref(),
+                )
+                .ok()?
+                .map(|metadata| metadata.to_vec());
+            }
+            runtime_call::RuntimeCall::LogEmit(req) => call = req.resume(),
+            // The metadata isn't supposed to depend on the storage.
+            _ => return None,
+        }
+    }
+}
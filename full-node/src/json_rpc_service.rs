@@ -15,14 +15,17 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{consensus_service, database_thread, network_service, LogCallback, LogLevel};
+use crate::{
+    compiled_runtimes_cache, consensus_service, database_thread, metrics_service, network_service,
+    LogCallback, LogLevel,
+};
 use futures_channel::oneshot;
 use futures_util::FutureExt;
 use smol::{
     future,
     net::{TcpListener, TcpStream},
 };
-use smoldot::json_rpc::{methods, service};
+use smoldot::json_rpc::{methods, parse, service};
 use std::{
     future::Future,
     io, mem,
@@ -37,6 +40,7 @@ use std::{
 };
 
 mod chain_head_subscriptions;
+mod http;
 mod legacy_api_subscriptions;
 mod requests_handler;
 mod runtime_caches_service;
@@ -70,6 +74,13 @@ pub struct Config {
     /// Maximum number of JSON-RPC clients until new ones are rejected.
     pub max_json_rpc_clients: u32,
 
+    /// List of values of the `Origin` header that are allowed to connect to the server, or
+    /// `None` to allow all origins. An entry equal to `*` also allows all origins.
+    ///
+    /// Requests that don't have an `Origin` header, which is the case of requests that don't
+    /// come from a browser, are always allowed.
+    pub cors_allowed_origins: Option<Vec<String>>,
+
     /// Name of the chain, as found in the chain specification.
     pub chain_name: String,
 
@@ -88,11 +99,24 @@ pub struct Config {
 
     /// Consensus service of the chain.
     pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// On-disk cache used to avoid recompiling runtimes.
+    pub compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
+    /// Maximum amount of fuel that the runtime calls performed on behalf of JSON-RPC clients can
+    /// consume, or `None` for no limit.
+    pub runtime_call_fuel_limit: Option<u64>,
+
+    /// Where to report the duration of JSON-RPC requests.
+    pub metrics: Arc<metrics_service::ChainMetrics>,
 }
 
 /// Running JSON-RPC service.
 ///
 /// If [`Config::bind_address`] is `Some`, holds a TCP server open for as long as it is alive.
+/// This server accepts both WebSocket connections and plain HTTP `POST` requests. Plain HTTP
+/// requests can't start subscriptions. Additionally, `GET /health` returns the output of the
+/// `system_health` JSON-RPC function.
 ///
 /// In addition to a TCP/IP server, this service also provides a virtual JSON-RPC endpoint that
 /// can be used through [`JsonRpcService::send_request`] and [`JsonRpcService::next_response`].
@@ -167,6 +191,8 @@ impl JsonRpcService {
                 log_callback: config.log_callback.clone(),
                 database: config.database.clone(),
                 num_cache_entries: NonZeroUsize::new(16).unwrap(), // TODO: configurable?
+                compiled_runtimes_cache: config.compiled_runtimes_cache.clone(),
+                fuel_metering: config.runtime_call_fuel_limit.is_some(),
             },
         ));
 
@@ -184,6 +210,8 @@ impl JsonRpcService {
                 genesis_block_hash: config.genesis_block_hash,
                 consensus_service: config.consensus_service.clone(),
                 runtime_caches_service: runtime_caches_service.clone(),
+                runtime_call_fuel_limit: config.runtime_call_fuel_limit,
+                metrics: config.metrics.clone(),
             });
         }
 
@@ -198,6 +226,7 @@ impl JsonRpcService {
                 to_requests_handlers,
                 num_json_rpc_clients: Arc::new(AtomicU32::new(0)),
                 max_json_rpc_clients: config.max_json_rpc_clients,
+                cors_allowed_origins: config.cors_allowed_origins.map(Arc::from),
             };
 
             (config.tasks_executor)(Box::pin(async move { background.run().await }));
@@ -284,6 +313,9 @@ struct JsonRpcBackground {
 
     /// See [`Config::max_json_rpc_clients`].
     max_json_rpc_clients: u32,
+
+    /// See [`Config::cors_allowed_origins`].
+    cors_allowed_origins: Option<Arc<[String]>>,
 }
 
 impl JsonRpcBackground {
@@ -344,47 +376,131 @@ impl JsonRpcBackground {
                 continue;
             }
 
-            // Spawn two tasks: one for the socket I/O, and one to process requests.
+            // Spawn a task for the socket I/O. Once the type of connection is known, this task
+            // spawns a second task that processes requests.
             self.log_callback.log(
                 LogLevel::Debug,
                 format!("json-rpc-incoming-connection; address={}", address),
             );
-            let (client_main_task, io) = service::client_main_task(service::Config {
-                max_active_subscriptions: 128,
-                max_pending_requests: NonZeroU32::new(64).unwrap(),
-            });
+            let start_client_main_task = {
+                let tasks_executor = self.tasks_executor.clone();
+                let log_callback = self.log_callback.clone();
+                let consensus_service = self.consensus_service.clone();
+                let database = self.database.clone();
+                let to_requests_handlers = self.to_requests_handlers.clone();
+                move |max_active_subscriptions| {
+                    let (client_main_task, io) = service::client_main_task(service::Config {
+                        max_active_subscriptions,
+                        max_pending_requests: NonZeroU32::new(64).unwrap(),
+                    });
+                    spawn_client_main_task(
+                        tasks_executor,
+                        log_callback,
+                        consensus_service,
+                        database,
+                        to_requests_handlers,
+                        client_main_task,
+                    );
+                    io
+                }
+            };
             spawn_client_io_task(
                 &self.tasks_executor,
                 self.log_callback.clone(),
                 tcp_socket,
                 address,
-                io,
+                start_client_main_task,
+                self.cors_allowed_origins.clone(),
                 self.num_json_rpc_clients.clone(),
             );
-            spawn_client_main_task(
-                self.tasks_executor.clone(),
-                self.log_callback.clone(),
-                self.consensus_service.clone(),
-                self.database.clone(),
-                self.to_requests_handlers.clone(),
-                client_main_task,
-            );
         }
     }
 }
 
+/// Spawns a task that handles the given socket.
+///
+/// `start_client_main_task` is called with the maximum number of subscriptions of the client,
+/// and must start processing the requests sent to the returned object.
 fn spawn_client_io_task(
     tasks_executor: &Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
     log_callback: Arc<dyn LogCallback + Send + Sync>,
     tcp_socket: TcpStream,
     socket_address: SocketAddr,
-    io: service::SerializedRequestsIo,
+    start_client_main_task: impl FnOnce(u32) -> service::SerializedRequestsIo + Send + 'static,
+    cors_allowed_origins: Option<Arc<[String]>>,
     num_json_rpc_clients: Arc<AtomicU32>,
 ) {
     let run_future = async move {
+        let mut socket = http::BufferedSocket::new(tcp_socket);
+
+        // Every connection starts with an HTTP request, which is either a WebSocket handshake
+        // or a plain HTTP request.
+        let request_head = match peek_request_head_or_timeout(&mut socket).await {
+            Ok(Some(head)) => head,
+            Ok(None) => return,
+            Err(error) => {
+                log_callback.log(
+                    LogLevel::Debug,
+                    format!("json-rpc-connection-error; address={socket_address}, error={error}"),
+                );
+                return;
+            }
+        };
+
+        if !request_head.is_websocket_upgrade() {
+            // Plain HTTP clients have no way to receive notifications. Passing a maximum of 0
+            // subscriptions makes the client main task refuse all subscription requests.
+            let io = start_client_main_task(0);
+            match run_http(
+                &mut socket,
+                &io,
+                &log_callback,
+                socket_address,
+                cors_allowed_origins.as_deref(),
+            )
+            .await
+            {
+                Ok(()) => {
+                    log_callback.log(
+                        LogLevel::Debug,
+                        format!("json-rpc-connection-closed; address={socket_address}"),
+                    );
+                }
+                Err(error) => {
+                    log_callback.log(
+                        LogLevel::Debug,
+                        format!(
+                            "json-rpc-connection-error; address={socket_address}, error={error}"
+                        ),
+                    );
+                }
+            }
+            return;
+        }
+
+        // Browsers don't enforce the same-origin policy on WebSocket connections, and it is
+        // thus the responsibility of the server to check the origin.
+        if let Some(origin) = request_head.header("Origin") {
+            if !is_origin_allowed(origin, cors_allowed_origins.as_deref()) {
+                log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "json-rpc-connection-forbidden-origin; address={socket_address}, \
+                        origin={origin}"
+                    ),
+                );
+                let _ = socket
+                    .write_response("403 Forbidden", &[("Connection", "close")], &[])
+                    .await;
+                return;
+            }
+        }
+
+        let io = start_client_main_task(128);
+
         // Perform the WebSocket handshake.
         let (mut ws_sender, mut ws_receiver) = {
-            let mut ws_server = soketto::handshake::Server::new(tcp_socket);
+            let mut ws_server = soketto::handshake::Server::new(socket);
 
             // TODO: enabling the `deflate` extension leads to "flate stream corrupted" errors
             //let deflate = soketto::extension::deflate::Deflate::new(soketto::Mode::Server);
@@ -545,6 +661,233 @@ fn spawn_client_io_task(
     }))
 }
 
+/// Maximum size of the body of a plain HTTP request.
+const MAX_HTTP_REQUEST_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maximum duration to wait for a client to send the next HTTP request, or the body of a
+/// request, before closing the connection. Applies both to new connections and to connections
+/// kept alive between two plain HTTP requests, and prevents idle connections from accumulating.
+const HTTP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Same as [`http::BufferedSocket::peek_request_head`], except that `Ok(None)` is also returned
+/// if nothing is received after [`HTTP_IDLE_TIMEOUT`].
+async fn peek_request_head_or_timeout(
+    socket: &mut http::BufferedSocket<TcpStream>,
+) -> Result<Option<http::RequestHead>, http::RequestHeadError> {
+    future::or(socket.peek_request_head(), async {
+        smol::Timer::after(HTTP_IDLE_TIMEOUT).await;
+        Ok(None)
+    })
+    .await
+}
+
+/// Answers plain HTTP requests sent on the given socket, until the connection is closed.
+async fn run_http(
+    socket: &mut http::BufferedSocket<TcpStream>,
+    io: &service::SerializedRequestsIo,
+    log_callback: &Arc<dyn LogCallback + Send + Sync>,
+    socket_address: SocketAddr,
+    cors_allowed_origins: Option<&[String]>,
+) -> Result<(), String> {
+    loop {
+        let request_head = match peek_request_head_or_timeout(socket).await {
+            Ok(Some(head)) => head,
+            Ok(None) => return Ok(()),
+            Err(error) => return Err(error.to_string()),
+        };
+        socket.consume(request_head.len);
+
+        let keep_alive = request_head.keep_alive();
+        let mut headers = vec![(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        )];
+
+        if let Some(origin) = request_head.header("Origin") {
+            if !is_origin_allowed(origin, cors_allowed_origins) {
+                socket
+                    .write_response("403 Forbidden", &[("Connection", "close")], &[])
+                    .await
+                    .map_err(|err| err.to_string())?;
+                return Ok(());
+            }
+
+            headers.push(("Access-Control-Allow-Origin", origin));
+            headers.push(("Vary", "Origin"));
+        }
+
+        let body = match request_head.header("Content-Length") {
+            Some(content_length) => match content_length.trim().parse::<usize>() {
+                Ok(len) if len <= MAX_HTTP_REQUEST_BODY_SIZE => {
+                    future::or(socket.read_body(len), async {
+                        smol::Timer::after(HTTP_IDLE_TIMEOUT).await;
+                        Err(io::ErrorKind::TimedOut.into())
+                    })
+                    .await
+                    .map_err(|err| err.to_string())?
+                }
+                Ok(_) => {
+                    socket
+                        .write_response("413 Payload Too Large", &[("Connection", "close")], &[])
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    return Ok(());
+                }
+                Err(_) => {
+                    socket
+                        .write_response("400 Bad Request", &[("Connection", "close")], &[])
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    return Ok(());
+                }
+            },
+            None if request_head.header("Transfer-Encoding").is_some() => {
+                // Chunked bodies aren't supported.
+                socket
+                    .write_response("411 Length Required", &[("Connection", "close")], &[])
+                    .await
+                    .map_err(|err| err.to_string())?;
+                return Ok(());
+            }
+            None => Vec::new(),
+        };
+
+        let path = request_head.path.split('?').next().unwrap_or_default();
+        let (status, response_body) = match (request_head.method.as_str(), path) {
+            ("OPTIONS", _) => {
+                // CORS preflight request.
+                headers.push(("Access-Control-Allow-Methods", "GET, POST, OPTIONS"));
+                headers.push(("Access-Control-Allow-Headers", "Content-Type"));
+                headers.push(("Access-Control-Max-Age", "86400"));
+                ("204 No Content", None)
+            }
+            ("GET", "/health") => {
+                let response = send_request_wait_response(
+                    io,
+                    log_callback,
+                    socket_address,
+                    r#"{"jsonrpc":"2.0","id":0,"method":"system_health","params":[]}"#.to_owned(),
+                )
+                .await;
+                match response.as_deref().map(parse::parse_response) {
+                    Some(Ok(parse::Response::Success { result_json, .. })) => {
+                        ("200 OK", Some(result_json.to_owned()))
+                    }
+                    _ => ("500 Internal Server Error", None),
+                }
+            }
+            ("POST", _) => match String::from_utf8(body) {
+                Ok(body) => {
+                    // The body is either a single JSON-RPC request or a batch of requests, both of
+                    // which are handled by the client main task.
+                    match send_request_wait_response(io, log_callback, socket_address, body).await {
+                        Some(response) => ("200 OK", Some(response)),
+                        // The request only contained notifications.
+                        None => ("204 No Content", None),
+                    }
+                }
+                Err(_) => ("400 Bad Request", None),
+            },
+            ("GET", _) => ("404 Not Found", None),
+            _ => ("405 Method Not Allowed", None),
+        };
+
+        if response_body.is_some() {
+            headers.push(("Content-Type", "application/json; charset=utf-8"));
+        }
+
+        socket
+            .write_response(
+                status,
+                &headers,
+                response_body.as_ref().map_or(&[][..], |b| b.as_bytes()),
+            )
+            .await
+            .map_err(|err| err.to_string())?;
+
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends a request or batch of requests to the client main task and waits for the response.
+/// Returns `None` if the request is a notification or the batch only contains notifications, in
+/// which case no response is generated.
+async fn send_request_wait_response(
+    io: &service::SerializedRequestsIo,
+    log_callback: &Arc<dyn LogCallback + Send + Sync>,
+    socket_address: SocketAddr,
+    request: String,
+) -> Option<String> {
+    log_callback.log(
+        LogLevel::Debug,
+        format!(
+            "json-rpc-request; address={}; request={}",
+            socket_address,
+            crate::util::truncated_str(request.chars().filter(|c| !c.is_control()), 128)
+        ),
+    );
+
+    // Notifications are silently discarded by the client main task.
+    let expects_response = {
+        let is_request =
+            |rq: &str| parse::parse_request(rq).map_or(true, |rq| rq.id_json.is_some());
+        if parse::is_batch(&request) {
+            // Empty or malformed batches are answered with an error.
+            parse::parse_batch(&request).map_or(true, |rqs| {
+                rqs.is_empty() || rqs.into_iter().any(is_request)
+            })
+        } else {
+            is_request(&request)
+        }
+    };
+
+    match io.send_request(request).await {
+        Ok(()) => {}
+        Err(service::SendRequestError {
+            cause: service::SendRequestErrorCause::ClientMainTaskDestroyed,
+            ..
+        }) => {
+            // The client main task never closes by itself but only as a consequence to the
+            // I/O task closing.
+            unreachable!()
+        }
+    }
+
+    if !expects_response {
+        return None;
+    }
+
+    let response = match io.wait_next_response().await {
+        Ok(response) => response,
+        Err(service::WaitNextResponseError::ClientMainTaskDestroyed) => unreachable!(),
+    };
+
+    log_callback.log(
+        LogLevel::Debug,
+        format!(
+            "json-rpc-response; address={}; response={}",
+            socket_address,
+            crate::util::truncated_str(response.chars().filter(|c| !c.is_control()), 128)
+        ),
+    );
+
+    Some(response)
+}
+
+/// Returns `true` if the given value of an `Origin` header is allowed to access the server.
+/// See [`Config::cors_allowed_origins`].
+fn is_origin_allowed(origin: &str, cors_allowed_origins: Option<&[String]>) -> bool {
+    let Some(cors_allowed_origins) = cors_allowed_origins else {
+        return true;
+    };
+
+    cors_allowed_origins
+        .iter()
+        .any(|allowed| allowed == "*" || allowed.trim_end_matches('/') == origin)
+}
+
 fn spawn_client_main_task(
     tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
     log_callback: Arc<dyn LogCallback + Send + Sync>,
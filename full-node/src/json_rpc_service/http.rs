@@ -0,0 +1,247 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Minimal server-side HTTP/1.1 implementation.
+//!
+//! Every connection to the JSON-RPC server starts with an HTTP request. This request is either a
+//! WebSocket handshake, in which case the connection is handed over to `soketto`, or a plain
+//! HTTP request that is answered directly.
+//!
+//! Because `soketto` needs to read the handshake request by itself, [`BufferedSocket`] makes it
+//! possible to look at a request head without removing it from the stream.
+
+use smol::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Maximum size of the head of a request (i.e. the request line and the headers).
+const MAX_REQUEST_HEAD_SIZE: usize = 16 * 1024;
+
+/// Initial capacity of the buffer of the body of a request.
+const BODY_READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Wraps around a socket and keeps the data that has been read but not consumed yet.
+pub struct BufferedSocket<T> {
+    socket: T,
+    /// Data read from [`BufferedSocket::socket`] but not consumed yet.
+    read_buffer: Vec<u8>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> BufferedSocket<T> {
+    /// Wraps around the given socket.
+    pub fn new(socket: T) -> Self {
+        BufferedSocket {
+            socket,
+            read_buffer: Vec::new(),
+        }
+    }
+
+    /// Waits for the head of the next request to be available and parses it. The head isn't
+    /// consumed, and [`BufferedSocket::consume`] must be called with [`RequestHead::len`] in
+    /// order to do so.
+    ///
+    /// Returns `Ok(None)` if the remote has closed the connection before sending anything.
+    pub async fn peek_request_head(&mut self) -> Result<Option<RequestHead>, RequestHeadError> {
+        loop {
+            if !self.read_buffer.is_empty() {
+                let mut headers = [httparse::EMPTY_HEADER; 64];
+                let mut request = httparse::Request::new(&mut headers);
+                match request.parse(&self.read_buffer) {
+                    Ok(httparse::Status::Complete(len)) => {
+                        return Ok(Some(RequestHead {
+                            method: request.method.unwrap_or_default().to_owned(),
+                            path: request.path.unwrap_or_default().to_owned(),
+                            minor_version: request.version.unwrap_or_default(),
+                            headers: request
+                                .headers
+                                .iter()
+                                .map(|h| {
+                                    (
+                                        h.name.to_owned(),
+                                        String::from_utf8_lossy(h.value).into_owned(),
+                                    )
+                                })
+                                .collect(),
+                            len,
+                        }))
+                    }
+                    Ok(httparse::Status::Partial) => {}
+                    Err(error) => return Err(RequestHeadError::Parse(error)),
+                }
+
+                if self.read_buffer.len() >= MAX_REQUEST_HEAD_SIZE {
+                    return Err(RequestHeadError::TooLarge);
+                }
+            }
+
+            let mut buffer = [0; 4096];
+            let num_read = self
+                .socket
+                .read(&mut buffer)
+                .await
+                .map_err(RequestHeadError::Io)?;
+            if num_read == 0 {
+                if self.read_buffer.is_empty() {
+                    return Ok(None);
+                }
+                return Err(RequestHeadError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            self.read_buffer.extend_from_slice(&buffer[..num_read]);
+        }
+    }
+
+    /// Discards the given number of bytes from the data that has been read.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `num_bytes` is larger than the number of bytes that have been peeked.
+    ///
+    pub fn consume(&mut self, num_bytes: usize) {
+        self.read_buffer.drain(..num_bytes);
+    }
+
+    /// Reads exactly `len` bytes from the socket.
+    ///
+    /// The returned buffer grows as data arrives, so that announcing a large body without
+    /// sending it doesn't allocate memory.
+    pub async fn read_body(&mut self, len: usize) -> Result<Vec<u8>, io::Error> {
+        let mut body = Vec::with_capacity(len.min(BODY_READ_CHUNK_SIZE));
+        (&mut *self)
+            .take(u64::try_from(len).unwrap_or(u64::MAX))
+            .read_to_end(&mut body)
+            .await?;
+        if body.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(body)
+    }
+
+    /// Writes an HTTP response to the socket and flushes it.
+    ///
+    /// The `Content-Length` header is added automatically and must not be included in
+    /// `headers`.
+    pub async fn write_response(
+        &mut self,
+        status: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<(), io::Error> {
+        let mut response = format!("HTTP/1.1 {status}\r\n");
+        for (name, value) in headers {
+            response.push_str(name);
+            response.push_str(": ");
+            response.push_str(value);
+            response.push_str("\r\n");
+        }
+        response.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+        self.socket.write_all(response.as_bytes()).await?;
+        self.socket.write_all(body).await?;
+        self.socket.flush().await
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for BufferedSocket<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        if self.read_buffer.is_empty() {
+            return Pin::new(&mut self.socket).poll_read(cx, buf);
+        }
+
+        let num_bytes = buf.len().min(self.read_buffer.len());
+        buf[..num_bytes].copy_from_slice(&self.read_buffer[..num_bytes]);
+        self.read_buffer.drain(..num_bytes);
+        Poll::Ready(Ok(num_bytes))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for BufferedSocket<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.socket).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.socket).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.socket).poll_close(cx)
+    }
+}
+
+/// Request line and headers of an HTTP request.
+#[derive(Debug)]
+pub struct RequestHead {
+    /// HTTP method, such as `GET` or `POST`.
+    pub method: String,
+    /// Path and query string of the request.
+    pub path: String,
+    /// `0` for HTTP/1.0, `1` for HTTP/1.1.
+    pub minor_version: u8,
+    /// List of headers, in the order in which they have been received.
+    pub headers: Vec<(String, String)>,
+    /// Number of bytes the request head occupies in the stream.
+    pub len: usize,
+}
+
+impl RequestHead {
+    /// Returns the value of the first header with the given name. The name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if the request asks for the connection to be upgraded to a WebSocket.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        self.header("Upgrade")
+            .map_or(false, |v| v.eq_ignore_ascii_case("websocket"))
+    }
+
+    /// Returns `true` if the connection should remain open after the response has been sent.
+    pub fn keep_alive(&self) -> bool {
+        match self.header("Connection") {
+            Some(v) if v.eq_ignore_ascii_case("close") => false,
+            Some(v) if v.eq_ignore_ascii_case("keep-alive") => true,
+            _ => self.minor_version >= 1,
+        }
+    }
+}
+
+/// Error potentially returned by [`BufferedSocket::peek_request_head`].
+#[derive(Debug, derive_more::Display)]
+pub enum RequestHeadError {
+    /// Error while reading from the socket.
+    #[display(fmt = "{_0}")]
+    Io(io::Error),
+    /// The request isn't a valid HTTP request.
+    #[display(fmt = "Invalid HTTP request: {_0}")]
+    Parse(httparse::Error),
+    /// The head of the request exceeds the maximum allowed size.
+    TooLarge,
+}
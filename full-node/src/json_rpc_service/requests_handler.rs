@@ -18,6 +18,7 @@
 use futures_lite::future;
 use smol::stream::StreamExt as _;
 use smoldot::{
+    database::full_sqlite,
     executor,
     json_rpc::{methods, parse, service},
     trie,
@@ -32,7 +33,7 @@ use std::{
 use crate::{
     consensus_service, database_thread,
     json_rpc_service::{legacy_api_subscriptions, runtime_caches_service},
-    network_service, LogCallback, LogLevel,
+    metrics_service, network_service, LogCallback, LogLevel,
 };
 
 pub struct Config {
@@ -77,8 +78,17 @@ pub struct Config {
 
     /// Runtime caches service of the JSON-RPC service.
     pub runtime_caches_service: Arc<runtime_caches_service::RuntimeCachesService>,
+
+    /// Maximum amount of fuel that runtime calls can consume, or `None` for no limit.
+    pub runtime_call_fuel_limit: Option<u64>,
+
+    /// Where to report the duration of requests.
+    pub metrics: Arc<metrics_service::ChainMetrics>,
 }
 
+/// Maximum number of keys that a `state_getReadProof` request can contain.
+const MAX_READ_PROOF_KEYS: usize = 1024;
+
 pub enum Message {
     Request(service::RequestProcess),
     SubscriptionStart(service::SubscriptionStartProcess),
@@ -89,7 +99,20 @@ pub fn spawn_requests_handler(config: Config) {
     tasks_executor(Box::pin(async move {
         let mut receiver = pin::pin!(config.receiver);
         loop {
-            match receiver.next().await {
+            let message = receiver.next().await;
+
+            // Requests are answered before the end of the loop iteration, at which point the
+            // timer is destroyed and the duration of the request reported.
+            let _request_timer = match &message {
+                Some(Message::Request(request)) => Some(
+                    config
+                        .metrics
+                        .start_json_rpc_request(request.request().name()),
+                ),
+                _ => None,
+            };
+
+            match message {
                 Some(Message::Request(request)) => match request.request() {
                     methods::MethodCall::rpc_methods {} => {
                         request.respond(methods::Response::rpc_methods(methods::RpcMethods {
@@ -328,9 +351,12 @@ pub fn spawn_requests_handler(config: Config) {
                                 function_to_call: "Metadata_metadata",
                                 parameter: iter::empty::<&'static [u8]>(),
                                 max_log_level: 0,
+                                fuel_limit: config.runtime_call_fuel_limit,
                                 storage_proof_size_behavior: executor::runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
                                 storage_main_trie_changes: Default::default(),
                                 calculate_trie_changes: false,
+                                record_storage_proof: false,
+                                record_trace: false,
                             }) {
                                 Ok(c) => c,
                                 Err(_) => {
@@ -481,6 +507,65 @@ pub fn spawn_requests_handler(config: Config) {
                                     // Logs are ignored.
                                     call = req.resume();
                                 }
+                                executor::runtime_call::RuntimeCall::StorageProofNodeValue(_) => {
+                                    // Storage proofs are never recorded.
+                                    unreachable!()
+                                }
+                            }
+                        }
+                    }
+                    methods::MethodCall::state_getReadProof { keys, at } => {
+                        // Building the proof is done while holding the database lock, so the
+                        // number of keys is bounded.
+                        if keys.len() > MAX_READ_PROOF_KEYS {
+                            request.fail(service::ErrorResponse::InvalidParams);
+                            continue;
+                        }
+
+                        // Convert the list of keys into a format suitable for the database.
+                        let keys_nibbles = keys
+                            .iter()
+                            .map(|key| {
+                                trie::bytes_to_nibbles(key.0.iter().copied())
+                                    .map(u8::from)
+                                    .collect::<Vec<_>>()
+                            })
+                            .collect::<Vec<_>>();
+
+                        // The bulk of the request is performed in the database thread.
+                        let result = config
+                            .database
+                            .with_database(move |db| {
+                                let at = match at {
+                                    Some(h) => h.0,
+                                    None => db.best_block_hash()?,
+                                };
+
+                                let proof = db.block_storage_merkle_proof(
+                                    &at,
+                                    keys_nibbles.iter().map(|k| k.iter().copied()),
+                                )?;
+
+                                Ok(methods::ReadProof {
+                                    at: methods::HashHexString(at),
+                                    proof: proof.into_iter().map(methods::HexString).collect(),
+                                })
+                            })
+                            .await;
+
+                        // Send back the response.
+                        match result {
+                            Ok(out) => {
+                                request.respond(methods::Response::state_getReadProof(out));
+                            }
+                            Err(database_thread::StorageAccessError::IncompleteStorage)
+                            | Err(database_thread::StorageAccessError::UnknownBlock) => {
+                                // Note that it is unclear how the function should behave in
+                                // that situation.
+                                request.fail(service::ErrorResponse::InvalidParams);
+                            }
+                            Err(database_thread::StorageAccessError::Corrupted(_)) => {
+                                request.fail(service::ErrorResponse::InternalError);
                             }
                         }
                     }
@@ -601,6 +686,195 @@ pub fn spawn_requests_handler(config: Config) {
                             }
                         }
                     }
+                    methods::MethodCall::state_traceBlock {
+                        block,
+                        storage_keys,
+                        ..
+                    } => {
+                        // Parse the comma-separated list of hexadecimal key prefixes. An empty
+                        // list means that all storage accesses are reported.
+                        let storage_keys = storage_keys.map(|k| k.into_owned()).unwrap_or_default();
+                        let prefixes = match storage_keys
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|prefix| !prefix.is_empty())
+                            .map(|prefix| hex::decode(prefix.strip_prefix("0x").unwrap_or(prefix)))
+                            .collect::<Result<Vec<_>, _>>()
+                        {
+                            Ok(p) => p,
+                            Err(_) => {
+                                request.fail(service::ErrorResponse::InvalidParams);
+                                continue;
+                            }
+                        };
+
+                        let block_hash = block.0;
+                        let block_content = config
+                            .database
+                            .with_database(move |db| {
+                                let Some(header) = db.block_scale_encoded_header(&block_hash)?
+                                else {
+                                    return Ok(None);
+                                };
+                                let Some(body) = db.block_extrinsics(&block_hash)? else {
+                                    return Ok(None);
+                                };
+                                Ok::<_, full_sqlite::CorruptedError>(Some((
+                                    header,
+                                    body.collect::<Vec<_>>(),
+                                )))
+                            })
+                            .await;
+                        let (header, body) = match block_content {
+                            Ok(Some(c)) => c,
+                            Ok(None) => {
+                                // Note that it is unclear how the function should behave in
+                                // that situation.
+                                request.respond_null();
+                                continue;
+                            }
+                            Err(_) => {
+                                request.fail(service::ErrorResponse::InternalError);
+                                continue;
+                            }
+                        };
+
+                        let block_number_bytes = config.consensus_service.block_number_bytes();
+                        let parent_hash = match smoldot::header::decode(&header, block_number_bytes)
+                        {
+                            Ok(h) => *h.parent_hash,
+                            Err(_) => {
+                                request.fail(service::ErrorResponse::InternalError);
+                                continue;
+                            }
+                        };
+
+                        let parent_runtime =
+                            match config.runtime_caches_service.get(parent_hash).await {
+                                Ok(runtime) => (*runtime).clone(),
+                                Err(runtime_caches_service::GetError::UnknownBlock)
+                                | Err(runtime_caches_service::GetError::Pruned) => {
+                                    request.respond_null();
+                                    continue;
+                                } // TODO: unclear if correct error
+                                Err(runtime_caches_service::GetError::InvalidRuntime(_))
+                                | Err(runtime_caches_service::GetError::NoCode)
+                                | Err(runtime_caches_service::GetError::InvalidHeapPages)
+                                | Err(runtime_caches_service::GetError::CorruptedDatabase) => {
+                                    request.fail(service::ErrorResponse::InternalError);
+                                    continue;
+                                }
+                            };
+
+                        let traced_calls = match consensus_service::trace_block(
+                            &config.database,
+                            parent_runtime,
+                            &parent_hash,
+                            &header,
+                            block_number_bytes,
+                            body.iter(),
+                        )
+                        .await
+                        {
+                            Ok(c) => c,
+                            Err(error) => {
+                                config.log_callback.log(
+                                    LogLevel::Warn,
+                                    format!("json-rpc-trace-block-error; error={error}"),
+                                );
+                                request.fail(service::ErrorResponse::InternalError);
+                                continue;
+                            }
+                        };
+
+                        let key_matches = |key: &[u8]| {
+                            prefixes.is_empty()
+                                || prefixes.iter().any(|prefix| key.starts_with(prefix))
+                        };
+
+                        let segments = traced_calls
+                            .into_iter()
+                            .map(|call| methods::BlockTraceSegment {
+                                function: call.function_name.to_owned(),
+                                extrinsic_index: call.extrinsic_index,
+                                events: call
+                                    .trace
+                                    .into_iter()
+                                    .filter_map(|event| {
+                                        match event {
+                                        executor::runtime_call::TraceEvent::StorageGet {
+                                            child_trie,
+                                            key,
+                                            value,
+                                        } => key_matches(&key).then(|| {
+                                            methods::BlockTraceEvent::StorageGet {
+                                                child_trie: child_trie.map(methods::HexString),
+                                                key: methods::HexString(key),
+                                                value: value.map(methods::HexString),
+                                            }
+                                        }),
+                                        executor::runtime_call::TraceEvent::StorageSet {
+                                            child_trie,
+                                            key,
+                                            value,
+                                        } => key_matches(&key).then(|| {
+                                            methods::BlockTraceEvent::StorageSet {
+                                                child_trie: child_trie.map(methods::HexString),
+                                                key: methods::HexString(key),
+                                                value: value.map(methods::HexString),
+                                            }
+                                        }),
+                                        executor::runtime_call::TraceEvent::StorageAppend {
+                                            child_trie,
+                                            key,
+                                            value,
+                                        } => key_matches(&key).then(|| {
+                                            methods::BlockTraceEvent::StorageAppend {
+                                                child_trie: child_trie.map(methods::HexString),
+                                                key: methods::HexString(key),
+                                                value: methods::HexString(value),
+                                            }
+                                        }),
+                                        executor::runtime_call::TraceEvent::StorageClearPrefix {
+                                            child_trie,
+                                            prefix,
+                                        } => {
+                                            // A prefix being cleared is reported if it overlaps
+                                            // with one of the requested prefixes.
+                                            (prefixes.is_empty()
+                                                || prefixes.iter().any(|p| {
+                                                    p.starts_with(&prefix) || prefix.starts_with(p)
+                                                }))
+                                            .then(|| {
+                                                methods::BlockTraceEvent::StorageClearPrefix {
+                                                    child_trie: child_trie.map(methods::HexString),
+                                                    prefix: methods::HexString(prefix),
+                                                }
+                                            })
+                                        }
+                                        executor::runtime_call::TraceEvent::StorageRoot {
+                                            child_trie,
+                                            root_hash,
+                                        } => Some(methods::BlockTraceEvent::StorageRoot {
+                                            child_trie: child_trie.map(methods::HexString),
+                                            root_hash: methods::HashHexString(root_hash),
+                                        }),
+                                        executor::runtime_call::TraceEvent::Log(message) => {
+                                            Some(methods::BlockTraceEvent::Log { message })
+                                        }
+                                    }
+                                    })
+                                    .collect(),
+                            })
+                            .collect();
+
+                        request.respond(methods::Response::state_traceBlock(methods::BlockTrace {
+                            block_hash: methods::HashHexString(block_hash),
+                            parent_hash: methods::HashHexString(parent_hash),
+                            storage_keys,
+                            segments,
+                        }));
+                    }
                     methods::MethodCall::system_chain {} => {
                         request
                             .respond(methods::Response::system_chain((&config.chain_name).into()));
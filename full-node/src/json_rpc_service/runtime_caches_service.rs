@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{database_thread, LogCallback};
+use crate::{compiled_runtimes_cache, database_thread, LogCallback};
 
 use futures_channel::oneshot;
 use futures_lite::{Future, StreamExt as _};
@@ -41,6 +41,13 @@ pub struct Config {
 
     /// Number of entries in the cache of runtimes.
     pub num_cache_entries: NonZeroUsize,
+
+    /// On-disk cache used to avoid recompiling runtimes.
+    pub compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
+    /// Whether fuel metering is enabled in the runtimes. See
+    /// [`executor::host::Config::fuel_metering`].
+    pub fuel_metering: bool,
 }
 
 /// A running runtime caches service.
@@ -101,15 +108,17 @@ impl RuntimeCachesService {
                                 match executor::storage_heap_pages_to_value(
                                     heap_pages.as_ref().map(|(h, _)| &h[..]),
                                 ) {
-                                    Ok(heap_pages) => executor::host::HostVmPrototype::new(
-                                        executor::host::Config {
-                                            module: &code,
+                                    Ok(heap_pages) => config
+                                        .compiled_runtimes_cache
+                                        .build_runtime(executor::host::Config {
+                                            module: code,
                                             heap_pages,
                                             exec_hint: executor::vm::ExecHint::ValidateAndCompile,
+                                            fuel_metering: config.fuel_metering,
                                             allow_unresolved_imports: true, // TODO: configurable? or if not, document
-                                        },
-                                    )
-                                    .map_err(GetError::InvalidRuntime),
+                                        })
+                                        .await
+                                        .map_err(GetError::InvalidRuntime),
                                     Err(_) => Err(GetError::InvalidHeapPages),
                                 }
                             }
@@ -35,10 +35,12 @@ use smoldot::{
 };
 use std::{array, borrow::Cow, io, iter, mem, net::SocketAddr, path::PathBuf, sync::Arc};
 
+mod compiled_runtimes_cache;
 mod consensus_service;
 mod database_thread;
 mod jaeger_service;
 mod json_rpc_service;
+mod metrics_service;
 mod network_service;
 mod util;
 
@@ -58,8 +60,19 @@ pub struct Config<'a> {
     pub tasks_executor: Arc<dyn Fn(future::BoxFuture<'static, ()>) + Send + Sync>,
     /// Function called whenever a part of the node wants to notify of something.
     pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+    /// `true` if [`Config::log_callback`] reports the logs of level [`LogLevel::Debug`]. If
+    /// `false`, the information that only appears in these logs isn't gathered.
+    pub debug_logs_enabled: bool,
     /// Address of a Jaeger agent to send traces to. If `None`, do not send Jaeger traces.
     pub jaeger_agent: Option<SocketAddr>,
+    /// Bind point of the HTTP server that serves Prometheus metrics. If `None`, no server is
+    /// started.
+    pub metrics_listen: Option<SocketAddr>,
+    /// Maximum amount of fuel that a runtime call performed on behalf of a JSON-RPC client or
+    /// of a peer of the network can consume, or `None` for no limit. The amount of fuel is
+    /// roughly the number of WebAssembly instructions executed. Runtime calls that are part of
+    /// verifying or authoring blocks are never limited.
+    pub runtime_call_fuel_limit: Option<u64>,
 }
 
 /// See [`ChainConfig::json_rpc_listen`].
@@ -69,6 +82,9 @@ pub struct JsonRpcListenConfig {
     pub address: SocketAddr,
     /// Maximum number of JSON-RPC clients that can be connected at the same time.
     pub max_json_rpc_clients: u32,
+    /// List of values of the `Origin` header that are allowed to access the JSON-RPC server,
+    /// or `None` to allow all origins. Requests without an `Origin` header are always allowed.
+    pub cors_allowed_origins: Option<Vec<String>>,
 }
 
 /// Allow generating logs.
@@ -114,8 +130,16 @@ pub struct ChainConfig<'a> {
     ///
     /// If `None`, no keys are stored in disk.
     pub keystore_path: Option<PathBuf>,
+    /// Path to the directory where compiled runtimes are cached, in order to avoid recompiling
+    /// them after a restart.
+    ///
+    /// If `None`, runtimes are compiled every time they are needed.
+    pub compiled_runtimes_cache_path: Option<PathBuf>,
     /// Configuration of the JSON-RPC server. If `None`, no TCP server is started.
     pub json_rpc_listen: Option<JsonRpcListenConfig>,
+    /// If `true`, the entire storage of the finalized block is downloaded after a warp sync,
+    /// before any further block is imported.
+    pub state_sync: bool,
 }
 
 /// Running client. As long as this object is alive, the client reads/writes the database and has
@@ -127,6 +151,7 @@ pub struct Client {
     relay_chain_consensus_service: Option<Arc<consensus_service::ConsensusService>>,
     network_service: Arc<network_service::NetworkService>,
     network_known_best: Arc<Mutex<Option<u64>>>,
+    metrics_service: Option<metrics_service::MetricsService>,
 }
 
 impl Client {
@@ -148,6 +173,13 @@ impl Client {
             .and_then(|j| j.listen_addr())
     }
 
+    /// Returns the address the Prometheus metrics server is listening on.
+    ///
+    /// Returns `None` if and only if [`Config::metrics_listen`] was `None`.
+    pub fn metrics_server_addr(&self) -> Option<SocketAddr> {
+        self.metrics_service.as_ref().map(|m| m.listen_addr())
+    }
+
     /// Returns the best block according to the networking.
     pub async fn network_known_best(&self) -> Option<u64> {
         *self.network_known_best.lock().await
@@ -252,6 +284,8 @@ pub enum StartError {
     RelayChainKeystoreInit(io::Error),
     /// Error initializing the Jaeger service.
     JaegerInit(io::Error),
+    /// Error initializing the metrics service.
+    MetricsInit(metrics_service::InitError),
 }
 
 /// Error potentially returned by [`Client::relay_chain_send_json_rpc_request`].
@@ -370,6 +404,23 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
         None
     };
 
+    let compiled_runtimes_cache = Arc::new(compiled_runtimes_cache::CompiledRuntimesCache::new(
+        config.chain.compiled_runtimes_cache_path.clone(),
+        config.log_callback.clone(),
+    ));
+    let relay_chain_compiled_runtimes_cache = config.relay_chain.as_ref().map(|relay_chain| {
+        Arc::new(compiled_runtimes_cache::CompiledRuntimesCache::new(
+            relay_chain.compiled_runtimes_cache_path.clone(),
+            config.log_callback.clone(),
+        ))
+    });
+
+    let chain_metrics = Arc::new(metrics_service::ChainMetrics::default());
+    let relay_chain_metrics = config
+        .relay_chain
+        .as_ref()
+        .map(|_| Arc::new(metrics_service::ChainMetrics::default()));
+
     let database_finalized_block_hash = database
         .with_database(|db| db.finalized_block_hash().unwrap())
         .await;
@@ -417,6 +468,7 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                 fork_id: chain_spec.fork_id().map(|n| n.to_owned()),
                 block_number_bytes: usize::from(chain_spec.block_number_bytes()),
                 database: database.clone(),
+                compiled_runtimes_cache: compiled_runtimes_cache.clone(),
                 grandpa_protocol_finalized_block_height: if matches!(
                     genesis_chain_information.as_ref().finality,
                     chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
@@ -489,6 +541,9 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                         fork_id: relay_chains_specs.fork_id().map(|n| n.to_owned()),
                         block_number_bytes: usize::from(relay_chains_specs.block_number_bytes()),
                         database: relay_chain_database.clone().unwrap(),
+                        compiled_runtimes_cache: relay_chain_compiled_runtimes_cache
+                            .clone()
+                            .unwrap(),
                         grandpa_protocol_finalized_block_height: if matches!(
                             genesis_chain_information.as_ref().finality,
                             chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
@@ -572,6 +627,7 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
             },
             log_callback: config.log_callback.clone(),
             jaeger_service: jaeger_service.clone(),
+            runtime_call_fuel_limit: config.runtime_call_fuel_limit,
         })
         .await
         .map_err(StartError::NetworkInit)?;
@@ -595,6 +651,7 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
             Box::new(move |task| executor(task))
         },
         log_callback: config.log_callback.clone(),
+        debug_logs_enabled: config.debug_logs_enabled,
         genesis_block_hash,
         network_events_receiver: network_events_receivers.next().unwrap(),
         network_service: (network_service.clone(), network_service_chain_ids[0]),
@@ -602,7 +659,10 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
         block_number_bytes: usize::from(chain_spec.block_number_bytes()),
         keystore,
         jaeger_service: jaeger_service.clone(),
+        compiled_runtimes_cache: compiled_runtimes_cache.clone(),
+        metrics: chain_metrics.clone(),
         slot_duration_author_ratio: 43691_u16,
+        state_sync_after_warp_sync: config.chain.state_sync,
     })
     .await
     .map_err(StartError::ConsensusServiceInit)?;
@@ -615,6 +675,7 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                     Box::new(move |task| executor(task))
                 },
                 log_callback: config.log_callback.clone(),
+                debug_logs_enabled: config.debug_logs_enabled,
                 genesis_block_hash: relay_genesis_chain_information
                     .as_ref()
                     .unwrap()
@@ -645,7 +706,10 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                     keystore
                 }),
                 jaeger_service, // TODO: consider passing a different jaeger service with a different service name
+                compiled_runtimes_cache: relay_chain_compiled_runtimes_cache.clone().unwrap(),
+                metrics: relay_chain_metrics.clone().unwrap(),
                 slot_duration_author_ratio: 43691_u16,
+                state_sync_after_warp_sync: config.relay_chain.as_ref().unwrap().state_sync,
             })
             .await
             .map_err(StartError::RelayChainConsensusServiceInit)?,
@@ -654,6 +718,40 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
         None
     };
 
+    // Start the metrics service.
+    // Just like the JSON-RPC service below, it only needs to be kept alive in order to function.
+    let metrics_service = if let Some(bind_address) = config.metrics_listen {
+        Some(
+            metrics_service::MetricsService::new(metrics_service::Config {
+                tasks_executor: config.tasks_executor.clone(),
+                log_callback: config.log_callback.clone(),
+                bind_address,
+                network_service: network_service.clone(),
+                chains: iter::once(metrics_service::ChainConfig {
+                    log_name: chain_spec.id().to_owned(),
+                    metrics: chain_metrics.clone(),
+                    consensus_service: consensus_service.clone(),
+                    network_service_chain_id: network_service_chain_ids[0],
+                    database: database.clone(),
+                })
+                .chain(relay_chain_consensus_service.as_ref().map(
+                    |relay_chain_consensus_service| metrics_service::ChainConfig {
+                        log_name: relay_chain_spec.as_ref().unwrap().id().to_owned(),
+                        metrics: relay_chain_metrics.clone().unwrap(),
+                        consensus_service: relay_chain_consensus_service.clone(),
+                        network_service_chain_id: network_service_chain_ids[1],
+                        database: relay_chain_database.clone().unwrap(),
+                    },
+                ))
+                .collect(),
+            })
+            .await
+            .map_err(StartError::MetricsInit)?,
+        )
+    } else {
+        None
+    };
+
     // Start the JSON-RPC service.
     // It only needs to be kept alive in order to function.
     //
@@ -672,7 +770,12 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
         max_json_rpc_clients: config
             .chain
             .json_rpc_listen
+            .as_ref()
             .map_or(0, |cfg| cfg.max_json_rpc_clients),
+        cors_allowed_origins: config
+            .chain
+            .json_rpc_listen
+            .and_then(|cfg| cfg.cors_allowed_origins),
         chain_name: chain_spec.name().to_owned(),
         chain_type: chain_spec.chain_type().to_owned(),
         chain_properties_json: chain_spec.properties().to_owned(),
@@ -681,6 +784,9 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
             .as_ref()
             .finalized_block_header
             .hash(usize::from(chain_spec.block_number_bytes())),
+        compiled_runtimes_cache,
+        runtime_call_fuel_limit: config.runtime_call_fuel_limit,
+        metrics: chain_metrics,
     })
     .await
     .map_err(StartError::JsonRpcServiceInit)?;
@@ -703,7 +809,11 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                 max_parallel_requests: 32,
                 max_json_rpc_clients: relay_chain_cfg
                     .json_rpc_listen
+                    .as_ref()
                     .map_or(0, |cfg| cfg.max_json_rpc_clients),
+                cors_allowed_origins: relay_chain_cfg
+                    .json_rpc_listen
+                    .and_then(|cfg| cfg.cors_allowed_origins),
                 chain_name: relay_chain_spec.name().to_owned(),
                 chain_type: relay_chain_spec.chain_type().to_owned(),
                 chain_properties_json: relay_chain_spec.properties().to_owned(),
@@ -714,6 +824,9 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
                     .as_ref()
                     .finalized_block_header
                     .hash(usize::from(relay_chain_spec.block_number_bytes())),
+                compiled_runtimes_cache: relay_chain_compiled_runtimes_cache.unwrap(),
+                runtime_call_fuel_limit: config.runtime_call_fuel_limit,
+                metrics: relay_chain_metrics.unwrap(),
             })
             .await
             .map_err(StartError::JsonRpcServiceInit)?,
@@ -793,6 +906,7 @@ pub async fn start(mut config: Config<'_>) -> Result<Client, StartError> {
         relay_chain_json_rpc_service,
         network_service,
         network_known_best,
+        metrics_service,
     })
 }
 
@@ -856,6 +970,7 @@ async fn open_database(
                 )
                 .unwrap(),
                 exec_hint: executor::vm::ExecHint::ValidateAndExecuteOnce,
+                fuel_metering: false,
                 allow_unresolved_imports: true,
             })
             .unwrap()
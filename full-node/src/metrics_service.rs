@@ -0,0 +1,509 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Prometheus integration.
+//!
+//! See <https://prometheus.io/> for an introduction.
+//!
+//! Each chain has a [`ChainMetrics`] object that the other services report measurements to, such
+//! as the duration of block imports or of JSON-RPC requests. The [`MetricsService`] holds an
+//! HTTP server that answers `GET /metrics` requests using the Prometheus text exposition format.
+//!
+//! Values that can be obtained at any time, such as the height of the best block or the number
+//! of peers, aren't reported to the [`ChainMetrics`] but queried when the server is scraped.
+
+use crate::{consensus_service, database_thread, network_service, LogCallback, LogLevel};
+use smol::{
+    future,
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use std::{
+    fmt::Write as _,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Measurements reported by the other services about a specific chain.
+#[derive(Default)]
+pub struct ChainMetrics {
+    /// Duration of the verification and insertion in the database of each block.
+    block_imports: Mutex<Histogram>,
+
+    /// Duration of JSON-RPC requests, per method name.
+    json_rpc_requests: Mutex<hashbrown::HashMap<&'static str, Histogram, fnv::FnvBuildHasher>>,
+}
+
+impl ChainMetrics {
+    /// Reports that a block has been verified and imported in the given amount of time.
+    pub fn report_block_import(&self, duration: Duration) {
+        self.block_imports.lock().unwrap().observe(duration);
+    }
+
+    /// Starts measuring the duration of a JSON-RPC request to the given method. The duration is
+    /// reported when the returned object is destroyed.
+    pub fn start_json_rpc_request(self: &Arc<Self>, method: &'static str) -> JsonRpcRequestTimer {
+        JsonRpcRequestTimer {
+            metrics: self.clone(),
+            method,
+            when_started: Instant::now(),
+        }
+    }
+}
+
+/// See [`ChainMetrics::start_json_rpc_request`].
+pub struct JsonRpcRequestTimer {
+    metrics: Arc<ChainMetrics>,
+    method: &'static str,
+    when_started: Instant,
+}
+
+impl Drop for JsonRpcRequestTimer {
+    fn drop(&mut self) {
+        self.metrics
+            .json_rpc_requests
+            .lock()
+            .unwrap()
+            .entry(self.method)
+            .or_default()
+            .observe(self.when_started.elapsed());
+    }
+}
+
+/// Configuration for a [`MetricsService`].
+pub struct Config {
+    /// Function that can be used to spawn background tasks.
+    ///
+    /// The tasks passed as parameter must be executed until they shut down.
+    pub tasks_executor: Arc<dyn Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync>,
+
+    /// Function called in order to notify of something.
+    pub log_callback: Arc<dyn LogCallback + Send + Sync>,
+
+    /// Where to bind the HTTP server.
+    pub bind_address: SocketAddr,
+
+    /// Networking service, used to report the number of peers and connections of each chain.
+    pub network_service: Arc<network_service::NetworkService>,
+
+    /// List of chains whose metrics to report.
+    pub chains: Vec<ChainConfig>,
+}
+
+/// See [`Config::chains`].
+pub struct ChainConfig {
+    /// Value of the `chain` label of the metrics of this chain.
+    pub log_name: String,
+
+    /// Measurements reported by the other services.
+    pub metrics: Arc<ChainMetrics>,
+
+    /// Consensus service of the chain.
+    pub consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Identifier of the chain from the point of view of the network service.
+    pub network_service_chain_id: network_service::ChainId,
+
+    /// Database of the chain.
+    pub database: Arc<database_thread::DatabaseThread>,
+}
+
+/// Running metrics service. Holds a TCP server open for as long as it is alive.
+pub struct MetricsService {
+    /// This events listener is notified when the service is dropped.
+    service_dropped: event_listener::Event,
+
+    /// Address the server is listening on. Not necessarily equal to [`Config::bind_address`].
+    listen_addr: SocketAddr,
+}
+
+impl Drop for MetricsService {
+    fn drop(&mut self) {
+        self.service_dropped.notify(usize::MAX);
+    }
+}
+
+impl MetricsService {
+    /// Initializes a new [`MetricsService`].
+    pub async fn new(config: Config) -> Result<Self, InitError> {
+        let (tcp_listener, listen_addr) = match TcpListener::bind(config.bind_address).await {
+            Ok(listener) => match listener.local_addr() {
+                Ok(addr) => (listener, addr),
+                Err(error) => {
+                    return Err(InitError::ListenError {
+                        bind_address: config.bind_address,
+                        error,
+                    })
+                }
+            },
+            Err(error) => {
+                return Err(InitError::ListenError {
+                    bind_address: config.bind_address,
+                    error,
+                })
+            }
+        };
+
+        let service_dropped = event_listener::Event::new();
+        let mut on_service_dropped = service_dropped.listen();
+
+        let sources = Arc::new(Sources {
+            network_service: config.network_service,
+            chains: config.chains,
+        });
+
+        let tasks_executor = config.tasks_executor.clone();
+        (config.tasks_executor)(Box::pin(async move {
+            loop {
+                let Some(accept_result) = future::or(
+                    async {
+                        (&mut on_service_dropped).await;
+                        None
+                    },
+                    async { Some(tcp_listener.accept().await) },
+                )
+                .await
+                else {
+                    return;
+                };
+
+                let (tcp_socket, address) = match accept_result {
+                    Ok(v) => v,
+                    Err(error) => {
+                        // Failing to accept an incoming TCP connection generally happens due
+                        // to the limit of file descriptors being reached.
+                        // Sleep a little bit and try again.
+                        config.log_callback.log(
+                            LogLevel::Warn,
+                            format!("metrics-tcp-listener-error; error={error}"),
+                        );
+                        smol::Timer::after(Duration::from_millis(50)).await;
+                        continue;
+                    }
+                };
+
+                let sources = sources.clone();
+                let log_callback = config.log_callback.clone();
+                tasks_executor(Box::pin(async move {
+                    // Scrapers are expected to send their request immediately. A timeout
+                    // prevents idle connections from accumulating.
+                    let result = future::or(serve_connection(tcp_socket, &sources), async {
+                        smol::Timer::after(Duration::from_secs(10)).await;
+                        Err(io::Error::from(io::ErrorKind::TimedOut))
+                    })
+                    .await;
+
+                    if let Err(error) = result {
+                        log_callback.log(
+                            LogLevel::Debug,
+                            format!("metrics-connection-error; address={address}, error={error}"),
+                        );
+                    }
+                }));
+            }
+        }));
+
+        Ok(MetricsService {
+            service_dropped,
+            listen_addr,
+        })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn listen_addr(&self) -> SocketAddr {
+        self.listen_addr
+    }
+}
+
+/// Error potentially returned by [`MetricsService::new`].
+#[derive(Debug, derive_more::Display)]
+pub enum InitError {
+    /// Failed to listen on the server address.
+    #[display(fmt = "Failed to listen on TCP address {bind_address}: {error}")]
+    ListenError {
+        /// Address that was attempted.
+        bind_address: SocketAddr,
+        /// Error returned by the operating system.
+        error: io::Error,
+    },
+}
+
+/// Everything the metrics are gathered from.
+struct Sources {
+    /// See [`Config::network_service`].
+    network_service: Arc<network_service::NetworkService>,
+
+    /// See [`Config::chains`].
+    chains: Vec<ChainConfig>,
+}
+
+/// Reads one HTTP request from the socket, answers it, then closes the connection.
+async fn serve_connection(mut tcp_socket: TcpStream, sources: &Sources) -> Result<(), io::Error> {
+    // Read until the end of the request headers. The body, if any, is ignored.
+    let mut request = Vec::with_capacity(1024);
+    loop {
+        if request.len() >= 16 * 1024 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request headers too large",
+            ));
+        }
+
+        let mut buffer = [0; 1024];
+        let num_read = tcp_socket.read(&mut buffer).await?;
+        if num_read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&buffer[..num_read]);
+        if request.windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+
+    let request_line = request
+        .split(|b| *b == b'\r')
+        .next()
+        .unwrap_or_default()
+        .split(|b| *b == b' ')
+        .collect::<Vec<_>>();
+
+    let (status, body) = match &request_line[..] {
+        [b"GET", path, _] if path.split(|b| *b == b'?').next() == Some(b"/metrics") => {
+            ("200 OK", render(sources).await)
+        }
+        [b"GET", _, _] => ("404 Not Found", String::new()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\
+        \r\n\
+        {body}",
+        body.len()
+    );
+    tcp_socket.write_all(response.as_bytes()).await?;
+    tcp_socket.flush().await
+}
+
+/// Builds the content of the `/metrics` page.
+async fn render(sources: &Sources) -> String {
+    let mut best_block_heights = Vec::with_capacity(sources.chains.len());
+    let mut finalized_block_heights = Vec::with_capacity(sources.chains.len());
+    let mut peers = Vec::with_capacity(sources.chains.len());
+    let mut connections = Vec::with_capacity(sources.chains.len());
+    let mut database_sizes = Vec::with_capacity(sources.chains.len());
+    let mut database_cache_hits = Vec::with_capacity(sources.chains.len());
+    let mut database_cache_misses = Vec::with_capacity(sources.chains.len());
+
+    for chain in &sources.chains {
+        let labels = format!("chain=\"{}\"", escape_label_value(&chain.log_name));
+
+        let sync_state = chain.consensus_service.sync_state().await;
+        best_block_heights.push((labels.clone(), sync_state.best_block_number));
+        finalized_block_heights.push((labels.clone(), sync_state.finalized_block_number));
+
+        let num_peers = sources
+            .network_service
+            .num_peers(chain.network_service_chain_id)
+            .await;
+        peers.push((labels.clone(), u64::try_from(num_peers).unwrap_or(u64::MAX)));
+
+        let num_connections = sources
+            .network_service
+            .num_chain_connections(chain.network_service_chain_id)
+            .await;
+        connections.push((
+            labels.clone(),
+            u64::try_from(num_connections).unwrap_or(u64::MAX),
+        ));
+
+        // Database statistics are skipped if they can't be obtained, as there is no way to
+        // report an error through metrics.
+        if let Ok(statistics) = chain
+            .database
+            .with_database(|database| database.statistics())
+            .await
+        {
+            database_sizes.push((labels.clone(), statistics.size_bytes));
+            database_cache_hits.push((labels.clone(), statistics.cache_hits));
+            database_cache_misses.push((labels, statistics.cache_misses));
+        }
+    }
+
+    let mut out = String::new();
+    render_values(
+        &mut out,
+        "smoldot_best_block_height",
+        "gauge",
+        "Height of the current best block.",
+        &best_block_heights,
+    );
+    render_values(
+        &mut out,
+        "smoldot_finalized_block_height",
+        "gauge",
+        "Height of the latest finalized block.",
+        &finalized_block_heights,
+    );
+    render_values(
+        &mut out,
+        "smoldot_peers",
+        "gauge",
+        "Number of peers we have a substream with.",
+        &peers,
+    );
+    render_values(
+        &mut out,
+        "smoldot_network_connections",
+        "gauge",
+        "Number of network connections, both handshaking and established, with the peers \
+        desired by the chain. Connections shared between chains are counted once per chain.",
+        &connections,
+    );
+    render_values(
+        &mut out,
+        "smoldot_database_size_bytes",
+        "gauge",
+        "Size of the database, not including the write-ahead log.",
+        &database_sizes,
+    );
+    render_values(
+        &mut out,
+        "smoldot_database_cache_hits_total",
+        "counter",
+        "Number of database pages found in the cache.",
+        &database_cache_hits,
+    );
+    render_values(
+        &mut out,
+        "smoldot_database_cache_misses_total",
+        "counter",
+        "Number of database pages missing from the cache.",
+        &database_cache_misses,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP smoldot_block_import_duration_seconds Time to verify and import a block."
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE smoldot_block_import_duration_seconds histogram"
+    );
+    for chain in &sources.chains {
+        let labels = format!("chain=\"{}\"", escape_label_value(&chain.log_name));
+        chain.metrics.block_imports.lock().unwrap().render(
+            &mut out,
+            "smoldot_block_import_duration_seconds",
+            &labels,
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP smoldot_json_rpc_request_duration_seconds Time to answer a JSON-RPC request."
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE smoldot_json_rpc_request_duration_seconds histogram"
+    );
+    for chain in &sources.chains {
+        let json_rpc_requests = chain.metrics.json_rpc_requests.lock().unwrap();
+        let mut methods = json_rpc_requests.keys().copied().collect::<Vec<_>>();
+        methods.sort_unstable();
+        for method in methods {
+            let labels = format!(
+                "chain=\"{}\",method=\"{}\"",
+                escape_label_value(&chain.log_name),
+                escape_label_value(method)
+            );
+            json_rpc_requests[method].render(
+                &mut out,
+                "smoldot_json_rpc_request_duration_seconds",
+                &labels,
+            );
+        }
+    }
+
+    out
+}
+
+/// Writes a metric with one value per set of labels.
+fn render_values(out: &mut String, name: &str, ty: &str, help: &str, values: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {ty}");
+    for (labels, value) in values {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+/// Escapes a string so that it can be used as the value of a label.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Upper bounds, in seconds, of the buckets of all histograms.
+const HISTOGRAM_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Distribution of durations.
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations smaller than or equal to the corresponding entry in
+    /// [`HISTOGRAM_BUCKETS`].
+    buckets: [u64; HISTOGRAM_BUCKETS.len()],
+    /// Total number of observations.
+    count: u64,
+    /// Sum of all observations, in seconds.
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(HISTOGRAM_BUCKETS) {
+            if seconds <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        for (count, upper_bound) in self.buckets.iter().zip(HISTOGRAM_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"{upper_bound}\"}} {count}"
+            );
+        }
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+    }
+}
@@ -27,7 +27,10 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{database_thread, jaeger_service, LogCallback, LogLevel};
+use crate::{
+    compiled_runtimes_cache, consensus_service, database_thread, jaeger_service, LogCallback,
+    LogLevel,
+};
 
 use core::{cmp, future::Future, mem, pin::Pin, task::Poll, time::Duration};
 use futures_channel::oneshot;
@@ -42,6 +45,7 @@ use smol::{
 };
 use smoldot::{
     database::full_sqlite,
+    executor::{self, host, runtime_call},
     header,
     informant::{BytesDisplay, HashDisplay},
     libp2p::{
@@ -50,9 +54,10 @@ use smoldot::{
         peer_id::{self, PeerId},
     },
     network::{basic_peering_strategy, codec, service},
+    trie,
 };
 use std::{
-    io,
+    io, iter,
     net::{IpAddr, SocketAddr},
     sync::Arc,
     time::Instant,
@@ -63,6 +68,11 @@ pub use smoldot::network::service::ChainId;
 
 mod tasks;
 
+/// Maximum number of call proof requests received from remotes that are answered at the same
+/// time, per chain. Answering a call proof request requires executing the runtime, and
+/// additional requests are denied in order to bound the resources that remotes can consume.
+const MAX_INCOMING_CALL_PROOF_REQUESTS_PER_CHAIN: usize = 4;
+
 /// Configuration for a [`NetworkService`].
 pub struct Config {
     /// Closure that spawns background tasks.
@@ -90,6 +100,10 @@ pub struct Config {
 
     /// Service to use to report traces.
     pub jaeger_service: Arc<jaeger_service::JaegerService>,
+
+    /// Maximum amount of fuel that the runtime calls performed in order to answer call proof
+    /// requests can consume, or `None` for no limit.
+    pub runtime_call_fuel_limit: Option<u64>,
 }
 
 /// Configuration for one chain.
@@ -104,6 +118,9 @@ pub struct ChainConfig {
     /// Database to use to read blocks from when answering requests.
     pub database: Arc<database_thread::DatabaseThread>,
 
+    /// Cache to use in order to build the runtimes used to answer call proof requests.
+    pub compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
     /// Hash of the genesis block of the chain. Sent to other nodes in order to determine whether
     /// the chains match.
     pub genesis_block_hash: [u8; 32],
@@ -209,6 +226,13 @@ enum ToBackground {
         config: codec::StorageProofRequestConfig<vec::IntoIter<Vec<u8>>>,
         result_tx: oneshot::Sender<Result<service::EncodedMerkleProof, ()>>,
     },
+    ForegroundStateRequest {
+        target: PeerId,
+        chain_id: ChainId,
+        block_hash: [u8; 32],
+        start_key: StateRequestStartOwned,
+        result_tx: oneshot::Sender<Result<service::EncodedStateResponse, StateRequestError>>,
+    },
     ForegroundCallProofRequest {
         target: PeerId, // TODO: takes by value because of futures longevity issue
         chain_id: ChainId,
@@ -218,6 +242,10 @@ enum ToBackground {
     ForegroundGetNumConnections {
         result_tx: oneshot::Sender<usize>,
     },
+    ForegroundGetNumChainConnections {
+        chain_id: ChainId,
+        result_tx: oneshot::Sender<usize>,
+    },
     ForegroundGetNumPeers {
         chain_id: ChainId,
         result_tx: oneshot::Sender<usize>,
@@ -314,6 +342,13 @@ struct Inner {
         fnv::FnvBuildHasher,
     >,
 
+    /// List of all state requests that have been started but not finished yet.
+    state_requests: HashMap<
+        service::SubstreamId,
+        oneshot::Sender<Result<service::EncodedStateResponse, StateRequestError>>,
+        fnv::FnvBuildHasher,
+    >,
+
     /// List of all call proof requests that have been started but not finished yet.
     call_proof_requests: HashMap<
         service::SubstreamId,
@@ -321,6 +356,19 @@ struct Inner {
         fnv::FnvBuildHasher,
     >,
 
+    /// List of call proof requests received from remotes whose answer is being generated in a
+    /// separate task, with the chain they concern.
+    incoming_call_proof_requests: HashMap<service::SubstreamId, ChainId, fnv::FnvBuildHasher>,
+
+    /// Channel where the tasks answering call proof requests send back the proof.
+    incoming_call_proofs_rx: Pin<Box<channel::Receiver<IncomingCallProof>>>,
+
+    /// Sending side of [`Inner::incoming_call_proofs_rx`].
+    incoming_call_proofs_tx: channel::Sender<IncomingCallProof>,
+
+    /// See [`Config::runtime_call_fuel_limit`].
+    runtime_call_fuel_limit: Option<u64>,
+
     /// When to start the next discovery process.
     next_discovery: smol::Timer,
 
@@ -328,6 +376,10 @@ struct Inner {
     next_discovery_period: Duration,
 }
 
+/// Call proof request received from a remote, and the entries of the proof to answer with, or
+/// `None` to deny the request.
+type IncomingCallProof = (service::SubstreamId, Option<Vec<Vec<u8>>>);
+
 /// Extra information of a chain.
 struct Chain {
     /// Name of the chain to use for logging purposes.
@@ -336,6 +388,9 @@ struct Chain {
     /// How to access data to answer requests from the remotes.
     database: Arc<database_thread::DatabaseThread>,
 
+    /// See [`ChainConfig::compiled_runtimes_cache`].
+    compiled_runtimes_cache: Arc<compiled_runtimes_cache::CompiledRuntimesCache>,
+
     /// Maximum number of peers that have slots attributed to them.
     max_slots: usize,
 
@@ -402,9 +457,11 @@ impl NetworkService {
                         },
                     ),
                     allow_inbound_block_requests: true,
+                    allow_inbound_light_requests: true,
                     user_data: Chain {
                         log_name: chain.log_name.clone(),
                         database: chain.database,
+                        compiled_runtimes_cache: chain.compiled_runtimes_cache,
                         max_in_peers: chain.max_in_peers,
                         max_slots: chain.max_slots,
                     },
@@ -423,6 +480,8 @@ impl NetworkService {
 
         let (to_background_tx, to_background_rx) = channel::bounded(16);
         let (from_connections_tx, from_connections_rx) = channel::bounded(64);
+        let (incoming_call_proofs_tx, incoming_call_proofs_rx) =
+            channel::bounded(MAX_INCOMING_CALL_PROOF_REQUESTS_PER_CHAIN);
 
         let local_peer_id =
             peer_id::PublicKey::Ed25519(*config.noise_key.libp2p_public_ed25519_key())
@@ -517,10 +576,21 @@ impl NetworkService {
                 5, // TODO: ?
                 Default::default(),
             ),
+            state_requests: hashbrown::HashMap::with_capacity_and_hasher(
+                2, // TODO: ?
+                Default::default(),
+            ),
             call_proof_requests: hashbrown::HashMap::with_capacity_and_hasher(
                 5, // TODO: ?
                 Default::default(),
             ),
+            incoming_call_proof_requests: hashbrown::HashMap::with_capacity_and_hasher(
+                MAX_INCOMING_CALL_PROOF_REQUESTS_PER_CHAIN,
+                Default::default(),
+            ),
+            incoming_call_proofs_rx: Box::pin(incoming_call_proofs_rx),
+            incoming_call_proofs_tx,
+            runtime_call_fuel_limit: config.runtime_call_fuel_limit,
             jaeger_service: config.jaeger_service.clone(),
             next_discovery: smol::Timer::after(Duration::from_secs(1)),
             next_discovery_period: Duration::from_secs(1),
@@ -573,6 +643,27 @@ impl NetworkService {
         result_rx.await.unwrap()
     }
 
+    /// Returns the number of connections, both handshaking or established, with the peers that
+    /// the given chain wants to be connected to.
+    ///
+    /// A connection with a peer that is shared between multiple chains is counted once for each
+    /// of these chains.
+    pub async fn num_chain_connections(&self, chain_id: ChainId) -> usize {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundGetNumChainConnections {
+                chain_id,
+                result_tx,
+            })
+            .await;
+
+        result_rx.await.unwrap()
+    }
+
     /// Returns the number of peers we have a substream with,.
     pub async fn num_peers(&self, chain_id: ChainId) -> usize {
         let (result_tx, result_rx) = oneshot::channel();
@@ -764,6 +855,44 @@ impl NetworkService {
         result_rx.await.unwrap()
     }
 
+    /// Sends a state request to the given peer.
+    ///
+    /// The response contains the storage entries of the given block starting after the given
+    /// key. See [`codec::StateRequest`].
+    pub async fn state_request(
+        self: Arc<Self>,
+        target: PeerId, // TODO: by value?
+        chain_id: ChainId,
+        config: codec::StateRequest<'_>,
+    ) -> Result<service::EncodedStateResponse, StateRequestError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background_tx
+            .lock()
+            .await
+            .send(ToBackground::ForegroundStateRequest {
+                target,
+                chain_id,
+                block_hash: *config.block_hash,
+                start_key: match config.start_key {
+                    codec::StateRequestStart::MainTrie(key) => {
+                        StateRequestStartOwned::MainTrie(key.to_vec())
+                    }
+                    codec::StateRequestStart::ChildTrieDefault { child_trie, key } => {
+                        StateRequestStartOwned::ChildTrieDefault {
+                            child_trie: child_trie.to_vec(),
+                            key: key.to_vec(),
+                        }
+                    }
+                },
+                result_tx,
+            })
+            .await;
+
+        result_rx.await.unwrap()
+    }
+
     /// Sends a call proof request to the given peer.
     // TODO: more docs
     // TODO: proper error type
@@ -831,6 +960,95 @@ pub enum WarpSyncRequestError {
     Request(service::GrandpaWarpSyncRequestError),
 }
 
+/// Error returned by [`NetworkService::state_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum StateRequestError {
+    /// No established connection with the target.
+    NoConnection,
+    /// Error during the request.
+    #[display(fmt = "{_0}")]
+    Request(service::StateRequestError),
+}
+
+/// Executes the given runtime function on top of the storage of the given block, and returns the
+/// entries of the proof of the storage accessed during the execution.
+async fn call_proof(
+    database: &database_thread::DatabaseThread,
+    compiled_runtimes_cache: &compiled_runtimes_cache::CompiledRuntimesCache,
+    block_hash: [u8; 32],
+    function_name: &str,
+    parameter: &[u8],
+    fuel_limit: Option<u64>,
+) -> Result<Vec<Vec<u8>>, CallProofError> {
+    let (code, heap_pages) = database
+        .with_database(move |db| {
+            let get = |key: &[u8]| {
+                db.block_storage_get(
+                    &block_hash,
+                    iter::empty::<iter::Empty<u8>>(),
+                    trie::bytes_to_nibbles(key.iter().copied()).map(u8::from),
+                )
+                .map(|value| value.map(|(value, _)| value))
+            };
+            Ok::<_, full_sqlite::StorageAccessError>((get(b":code")?, get(b":heappages")?))
+        })
+        .await
+        .map_err(CallProofError::Database)?;
+
+    let runtime = compiled_runtimes_cache
+        .build_runtime(host::Config {
+            module: code.ok_or(CallProofError::NoCode)?,
+            heap_pages: executor::storage_heap_pages_to_value(heap_pages.as_deref())
+                .map_err(|_| CallProofError::InvalidHeapPages)?,
+            exec_hint: executor::vm::ExecHint::ValidateAndCompile,
+            fuel_metering: fuel_limit.is_some(),
+            allow_unresolved_imports: false,
+        })
+        .await
+        .map_err(CallProofError::InvalidRuntime)?;
+
+    let success = consensus_service::runtime_call(
+        database,
+        &block_hash,
+        runtime,
+        function_name,
+        parameter,
+        runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
+        runtime_call::StorageChanges::empty(),
+        fuel_limit,
+        false,
+        true,
+    )
+    .await
+    .map_err(CallProofError::RuntimeCall)?;
+
+    Ok(success.storage_proof.unwrap().build_entries().collect())
+}
+
+/// Error potentially returned by [`call_proof`].
+#[derive(Debug, derive_more::Display)]
+enum CallProofError {
+    /// Error while accessing the storage of the block.
+    #[display(fmt = "{_0}")]
+    Database(full_sqlite::StorageAccessError),
+    /// The storage of the block doesn't contain any runtime code.
+    NoCode,
+    /// The storage of the block contains an invalid number of heap pages.
+    InvalidHeapPages,
+    /// Error while compiling the runtime.
+    #[display(fmt = "{_0}")]
+    InvalidRuntime(host::NewErr),
+    /// Error while executing the runtime call.
+    #[display(fmt = "{_0}")]
+    RuntimeCall(consensus_service::RuntimeCallError),
+}
+
+/// Owned version of [`codec::StateRequestStart`].
+enum StateRequestStartOwned {
+    MainTrie(Vec<u8>),
+    ChildTrieDefault { child_trie: Vec<u8>, key: Vec<u8> },
+}
+
 fn run(mut inner: Inner) {
     // This function is a small hack because I didn't find a better way to store the executor
     // within `Inner` while at the same time spawning the `Inner` using said executor.
@@ -860,6 +1078,10 @@ async fn background_task(mut inner: Inner) {
                 message: Option<service::ConnectionToCoordinator>,
             },
             EventSendersReady,
+            IncomingCallProofReady {
+                substream_id: service::SubstreamId,
+                proof: Option<Vec<Vec<u8>>>,
+            },
             CanAssignSlot(PeerId, ChainId),
             CanStartConnect(PeerId),
             CanOpenGossip(PeerId, ChainId),
@@ -972,6 +1194,13 @@ async fn background_task(mut inner: Inner) {
                 message,
             }
         })
+        .or(async {
+            let (substream_id, proof) = inner.incoming_call_proofs_rx.next().await.unwrap();
+            WakeUpReason::IncomingCallProofReady {
+                substream_id,
+                proof,
+            }
+        })
         .or(async {
             let Some((socket, socket_addr)) = inner.incoming_connections.next().await else {
                 future::pending().await
@@ -1312,6 +1541,53 @@ async fn background_task(mut inner: Inner) {
                     }
                 }
             }
+            WakeUpReason::Message(ToBackground::ForegroundStateRequest {
+                target,
+                chain_id,
+                block_hash,
+                start_key,
+                result_tx,
+            }) => {
+                inner.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "state-request-start; peer_id={}; chain={}; block-hash={}",
+                        target,
+                        inner.network[chain_id].log_name,
+                        HashDisplay(&block_hash),
+                    ),
+                );
+
+                match inner.network.start_state_request(
+                    &target,
+                    chain_id,
+                    &block_hash,
+                    match &start_key {
+                        StateRequestStartOwned::MainTrie(key) => {
+                            codec::StateRequestStart::MainTrie(key)
+                        }
+                        StateRequestStartOwned::ChildTrieDefault { child_trie, key } => {
+                            codec::StateRequestStart::ChildTrieDefault { child_trie, key }
+                        }
+                    },
+                    Duration::from_secs(20),
+                ) {
+                    Ok(request_id) => {
+                        inner.state_requests.insert(request_id, result_tx);
+                    }
+                    Err(service::StartRequestError::NoConnection) => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "state-request-ended; peer_id={}; chain={}; outcome=failure; error=no-connection",
+                                target,
+                                inner.network[chain_id].log_name,
+                            ),
+                        );
+                        let _ = result_tx.send(Err(StateRequestError::NoConnection));
+                    }
+                }
+            }
             WakeUpReason::Message(ToBackground::ForegroundCallProofRequest {
                 target,
                 chain_id,
@@ -1366,6 +1642,22 @@ async fn background_task(mut inner: Inner) {
             WakeUpReason::Message(ToBackground::ForegroundGetNumConnections { result_tx }) => {
                 let _ = result_tx.send(inner.network.num_connections());
             }
+            WakeUpReason::Message(ToBackground::ForegroundGetNumChainConnections {
+                chain_id,
+                result_tx,
+            }) => {
+                let _ = result_tx.send(
+                    inner
+                        .network
+                        .gossip_desired_iter(chain_id, service::GossipKind::ConsensusTransactions)
+                        .map(|peer_id| {
+                            inner
+                                .network
+                                .num_potential_and_established_connections(peer_id)
+                        })
+                        .sum(),
+                );
+            }
             WakeUpReason::Message(ToBackground::ForegroundGetNumPeers {
                 chain_id,
                 result_tx,
@@ -1872,6 +2164,40 @@ async fn background_task(mut inner: Inner) {
                     .unwrap()
                     .send(response.map_err(|_| ()));
             }
+            WakeUpReason::NetworkEvent(service::Event::RequestResult {
+                substream_id,
+                peer_id,
+                chain_id,
+                response: service::RequestResult::State(response),
+            }) => {
+                match &response {
+                    Ok(success) => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "state-request-ended; outcome=success; peer_id={peer_id}; chain={}; proof-size={}",
+                                inner.network[chain_id].log_name,
+                                BytesDisplay(u64::try_from(success.decode().len()).unwrap()),
+                            ),
+                        );
+                    }
+                    Err(err) => {
+                        inner.log_callback.log(
+                            LogLevel::Debug,
+                            format!(
+                                "state-request-ended; outcome=failure; peer_id={peer_id}; chain={}; error={}",
+                                inner.network[chain_id].log_name, err
+                            ),
+                        );
+                    }
+                }
+
+                let _ = inner
+                    .state_requests
+                    .remove(&substream_id)
+                    .unwrap()
+                    .send(response.map_err(StateRequestError::Request));
+            }
             WakeUpReason::NetworkEvent(service::Event::RequestResult {
                 substream_id,
                 peer_id,
@@ -1990,13 +2316,26 @@ async fn background_task(mut inner: Inner) {
                     ),
                 );
             }
-            WakeUpReason::NetworkEvent(service::Event::RequestResult { .. }) => {
-                // We never start a request of any other kind.
-                unreachable!()
+            WakeUpReason::NetworkEvent(service::Event::RequestInCancel { substream_id }) => {
+                // Call proof requests are the only requests that aren't answered immediately,
+                // and thus the only ones that can be cancelled.
+                let _was_in = inner.incoming_call_proof_requests.remove(&substream_id);
+                debug_assert!(_was_in.is_some());
             }
-            WakeUpReason::NetworkEvent(service::Event::RequestInCancel { .. }) => {
-                // Requests are answered immediately, and thus cancelling events can't happen.
-                unreachable!()
+            WakeUpReason::IncomingCallProofReady {
+                substream_id,
+                proof,
+            } => {
+                // The request might have been cancelled in the meanwhile.
+                if inner
+                    .incoming_call_proof_requests
+                    .remove(&substream_id)
+                    .is_some()
+                {
+                    inner
+                        .network
+                        .respond_call_proof(substream_id, proof.map(|p| p.into_iter()));
+                }
             }
             WakeUpReason::NetworkEvent(service::Event::IdentifyRequestIn {
                 peer_id,
@@ -2057,6 +2396,122 @@ async fn background_task(mut inner: Inner) {
                     },
                 );
             }
+            WakeUpReason::NetworkEvent(service::Event::StorageProofRequestIn {
+                peer_id,
+                chain_id,
+                block_hash,
+                keys,
+                substream_id,
+            }) => {
+                inner.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "incoming-storage-proof-request; peer_id={}; chain={}; block_hash={}; num_keys={}",
+                        peer_id,
+                        inner.network[chain_id].log_name,
+                        HashDisplay(&block_hash),
+                        keys.len()
+                    ),
+                );
+
+                // TODO: is it a good idea to await here while the lock is held and freezing the entire networking background task?
+                let response = inner.network[chain_id]
+                    .database
+                    .with_database(move |database| {
+                        database.block_storage_merkle_proof(
+                            &block_hash,
+                            keys.iter().map(|key| {
+                                trie::bytes_to_nibbles(key.iter().copied()).map(u8::from)
+                            }),
+                        )
+                    })
+                    .await;
+                inner.network.respond_storage_proof(
+                    substream_id,
+                    match response {
+                        Ok(proof) => Some(proof.into_iter()),
+                        Err(full_sqlite::StorageAccessError::Corrupted(error)) => {
+                            inner.log_callback.log(
+                                LogLevel::Warn,
+                                format!("incoming-storage-proof-request-error; error={}", error),
+                            );
+                            None
+                        }
+                        Err(full_sqlite::StorageAccessError::UnknownBlock)
+                        | Err(full_sqlite::StorageAccessError::IncompleteStorage) => None,
+                    },
+                );
+            }
+            WakeUpReason::NetworkEvent(service::Event::CallProofRequestIn {
+                peer_id,
+                chain_id,
+                block_hash,
+                function_name,
+                parameter,
+                substream_id,
+            }) => {
+                inner.log_callback.log(
+                    LogLevel::Debug,
+                    format!(
+                        "incoming-call-proof-request; peer_id={}; chain={}; block_hash={}; function={}",
+                        peer_id,
+                        inner.network[chain_id].log_name,
+                        HashDisplay(&block_hash),
+                        function_name
+                    ),
+                );
+
+                if inner
+                    .incoming_call_proof_requests
+                    .values()
+                    .filter(|c| **c == chain_id)
+                    .count()
+                    >= MAX_INCOMING_CALL_PROOF_REQUESTS_PER_CHAIN
+                {
+                    inner
+                        .network
+                        .respond_call_proof(substream_id, None::<vec::IntoIter<Vec<u8>>>);
+                    continue;
+                }
+
+                // Answering the request requires executing the runtime, which is done in a
+                // separate task in order to not block the networking.
+                inner
+                    .incoming_call_proof_requests
+                    .insert(substream_id, chain_id);
+                let database = inner.network[chain_id].database.clone();
+                let compiled_runtimes_cache =
+                    inner.network[chain_id].compiled_runtimes_cache.clone();
+                let log_callback = inner.log_callback.clone();
+                let log_name = inner.network[chain_id].log_name.clone();
+                let incoming_call_proofs_tx = inner.incoming_call_proofs_tx.clone();
+                let fuel_limit = inner.runtime_call_fuel_limit;
+                (inner.tasks_executor)(Box::pin(async move {
+                    let proof = match call_proof(
+                        &database,
+                        &compiled_runtimes_cache,
+                        block_hash,
+                        &function_name,
+                        &parameter,
+                        fuel_limit,
+                    )
+                    .await
+                    {
+                        Ok(proof) => Some(proof),
+                        Err(error) => {
+                            log_callback.log(
+                                LogLevel::Debug,
+                                format!(
+                                    "incoming-call-proof-request-error; chain={}; error={}",
+                                    log_name, error
+                                ),
+                            );
+                            None
+                        }
+                    };
+                    let _ = incoming_call_proofs_tx.send((substream_id, proof)).await;
+                }));
+            }
             WakeUpReason::NetworkEvent(service::Event::GrandpaNeighborPacket {
                 chain_id,
                 peer_id,
@@ -33,14 +33,19 @@ fn basic_block_generated() {
                 sqlite_database_path: None,
                 sqlite_cache_size: 256 * 1024 * 1024,
                 keystore_path: None,
+                compiled_runtimes_cache_path: None,
                 json_rpc_listen: None,
+                state_sync: false,
             },
             relay_chain: None,
             libp2p_key: Box::new([0; 32]),
             listen_addresses: Vec::new(),
             tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
             log_callback: Arc::new(move |_, _| {}),
+            debug_logs_enabled: false,
             jaeger_agent: None,
+            metrics_listen: None,
+            runtime_call_fuel_limit: None,
         })
         .await
         .unwrap();
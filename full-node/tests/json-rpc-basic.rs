@@ -29,14 +29,19 @@ fn send_request_errs_if_malformed() {
                 sqlite_database_path: None,
                 sqlite_cache_size: 256 * 1024 * 1024,
                 keystore_path: None,
+                compiled_runtimes_cache_path: None,
                 json_rpc_listen: None,
+                state_sync: false,
             },
             relay_chain: None,
             libp2p_key: Box::new([0; 32]),
             listen_addresses: Vec::new(),
             tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
             log_callback: Arc::new(move |_, _| {}),
+            debug_logs_enabled: false,
             jaeger_agent: None,
+            metrics_listen: None,
+            runtime_call_fuel_limit: None,
         })
         .await
         .unwrap();
@@ -61,14 +66,19 @@ fn send_request_works_if_unknown_request() {
                 sqlite_database_path: None,
                 sqlite_cache_size: 256 * 1024 * 1024,
                 keystore_path: None,
+                compiled_runtimes_cache_path: None,
                 json_rpc_listen: None,
+                state_sync: false,
             },
             relay_chain: None,
             libp2p_key: Box::new([0; 32]),
             listen_addresses: Vec::new(),
             tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
             log_callback: Arc::new(move |_, _| {}),
+            debug_logs_enabled: false,
             jaeger_agent: None,
+            metrics_listen: None,
+            runtime_call_fuel_limit: None,
         })
         .await
         .unwrap();
@@ -86,3 +96,45 @@ fn send_request_works_if_unknown_request() {
         }
     });
 }
+
+#[test]
+fn batch_request() {
+    smol::block_on(async move {
+        let client = smoldot_full_node::start(smoldot_full_node::Config {
+            chain: smoldot_full_node::ChainConfig {
+                chain_spec: (&include_bytes!("./substrate-node-template.json")[..]).into(),
+                additional_bootnodes: Vec::new(),
+                keystore_memory: vec![],
+                sqlite_database_path: None,
+                sqlite_cache_size: 256 * 1024 * 1024,
+                keystore_path: None,
+                compiled_runtimes_cache_path: None,
+                json_rpc_listen: None,
+                state_sync: false,
+            },
+            relay_chain: None,
+            libp2p_key: Box::new([0; 32]),
+            listen_addresses: Vec::new(),
+            tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
+            log_callback: Arc::new(move |_, _| {}),
+            debug_logs_enabled: false,
+            jaeger_agent: None,
+            metrics_listen: None,
+            runtime_call_fuel_limit: None,
+        })
+        .await
+        .unwrap();
+
+        client.send_json_rpc_request(
+            r#"[{"jsonrpc":"2.0","id":1,"method":"chain_getBlockHash","params":[0]},
+                {"jsonrpc":"2.0","method":"system_name","params":[]},
+                {"jsonrpc":"2.0","id":2,"method":"system_name","params":[]}]"#
+                .to_owned(),
+        );
+        let response_raw = client.next_json_rpc_response().await;
+        let responses = serde_json::from_str::<Vec<serde_json::Value>>(&response_raw).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["id"], 2);
+    });
+}
@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use smoldot::json_rpc;
+use smoldot::{json_rpc, trie};
 use std::sync::Arc;
 
 async fn start_client() -> smoldot_full_node::Client {
@@ -27,19 +27,40 @@ async fn start_client() -> smoldot_full_node::Client {
             sqlite_database_path: None,
             sqlite_cache_size: 256 * 1024 * 1024,
             keystore_path: None,
+            compiled_runtimes_cache_path: None,
             json_rpc_listen: None,
+            state_sync: false,
         },
         relay_chain: None,
         libp2p_key: Box::new([0; 32]),
         listen_addresses: Vec::new(),
         tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
         log_callback: Arc::new(move |_, _| {}),
+        debug_logs_enabled: false,
         jaeger_agent: None,
+        metrics_listen: None,
+        runtime_call_fuel_limit: None,
     })
     .await
     .unwrap()
 }
 
+fn scale_compact_usize(value: usize) -> Vec<u8> {
+    if value < 1 << 6 {
+        vec![u8::try_from(value << 2).unwrap()]
+    } else if value < 1 << 14 {
+        u16::try_from(value << 2 | 0b01)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec()
+    } else {
+        u32::try_from(value << 2 | 0b10)
+            .unwrap()
+            .to_le_bytes()
+            .to_vec()
+    }
+}
+
 #[test]
 fn chain_spec_v1_chain_name() {
     smol::block_on(async move {
@@ -457,6 +478,58 @@ fn state_get_keys_paged_unknown_block() {
     });
 }
 
+#[test]
+fn state_get_read_proof_basic() {
+    smol::block_on(async move {
+        let client = start_client().await;
+
+        client.send_json_rpc_request(
+            r#"{"jsonrpc":"2.0","id":1,"method":"chain_getHeader","params":[]}"#.to_owned(),
+        );
+        let response_raw = client.next_json_rpc_response().await;
+        let (_, result_json) = json_rpc::parse::parse_response(&response_raw)
+            .unwrap()
+            .into_success()
+            .unwrap();
+        let header = serde_json::from_str::<json_rpc::methods::Header>(result_json).unwrap();
+
+        // `0x3a636f6465` is `:code`, while the second key doesn't exist in the storage.
+        client.send_json_rpc_request(
+            r#"{"jsonrpc":"2.0","id":2,"method":"state_getReadProof","params":[["0x3a636f6465", "0x26aa394eea5630e07c48ae0c9558cef7"]]}"#
+                .to_owned(),
+        );
+        let response_raw = client.next_json_rpc_response().await;
+        let (_, result_json) = json_rpc::parse::parse_response(&response_raw)
+            .unwrap()
+            .into_success()
+            .unwrap();
+        let read_proof = serde_json::from_str::<json_rpc::methods::ReadProof>(result_json).unwrap();
+
+        // Turn the list of entries into a SCALE-encoded proof.
+        let mut proof = scale_compact_usize(read_proof.proof.len());
+        for entry in read_proof.proof {
+            proof.extend(scale_compact_usize(entry.0.len()));
+            proof.extend_from_slice(&entry.0);
+        }
+
+        let decoded = trie::proof_decode::decode_and_verify_proof(trie::proof_decode::Config {
+            proof: &proof,
+        })
+        .unwrap();
+        assert!(decoded
+            .storage_value(&header.state_root.0, b":code")
+            .unwrap()
+            .is_some());
+        assert!(decoded
+            .storage_value(
+                &header.state_root.0,
+                &[38, 170, 57, 78, 234, 86, 48, 224, 124, 72, 174, 12, 149, 88, 206, 247]
+            )
+            .unwrap()
+            .is_none());
+    });
+}
+
 #[test]
 fn system_chain() {
     smol::block_on(async move {
@@ -0,0 +1,250 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use smol::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use smoldot::json_rpc;
+use std::sync::Arc;
+
+async fn start_client() -> smoldot_full_node::Client {
+    smoldot_full_node::start(smoldot_full_node::Config {
+        chain: smoldot_full_node::ChainConfig {
+            chain_spec: (&include_bytes!("./substrate-node-template.json")[..]).into(),
+            additional_bootnodes: Vec::new(),
+            keystore_memory: vec![],
+            sqlite_database_path: None,
+            sqlite_cache_size: 256 * 1024 * 1024,
+            keystore_path: None,
+            compiled_runtimes_cache_path: None,
+            json_rpc_listen: Some(smoldot_full_node::JsonRpcListenConfig {
+                address: "127.0.0.1:0".parse().unwrap(),
+                max_json_rpc_clients: 64,
+                cors_allowed_origins: Some(vec!["http://allowed.example".to_owned()]),
+            }),
+            state_sync: false,
+        },
+        relay_chain: None,
+        libp2p_key: Box::new([0; 32]),
+        listen_addresses: Vec::new(),
+        tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
+        log_callback: Arc::new(move |_, _| {}),
+        debug_logs_enabled: false,
+        jaeger_agent: None,
+        metrics_listen: None,
+        runtime_call_fuel_limit: None,
+    })
+    .await
+    .unwrap()
+}
+
+/// Sends an HTTP request and returns the response head and body.
+async fn http_request(
+    client: &smoldot_full_node::Client,
+    method: &str,
+    path: &str,
+    extra_headers: &str,
+    body: &str,
+) -> (String, String) {
+    let mut socket = smol::net::TcpStream::connect(client.json_rpc_server_addr().unwrap())
+        .await
+        .unwrap();
+    socket
+        .write_all(
+            format!(
+                "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                Content-Length: {}\r\n{extra_headers}\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.to_owned(), body.to_owned())
+}
+
+#[test]
+fn post_single_request() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let (head, body) = http_request(
+            &client,
+            "POST",
+            "/",
+            "Content-Type: application/json\r\n",
+            r#"{"jsonrpc":"2.0","id":5,"method":"system_name","params":[]}"#,
+        )
+        .await;
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        match json_rpc::parse::parse_response(&body).unwrap() {
+            json_rpc::parse::Response::Success { id_json, .. } => assert_eq!(id_json, "5"),
+            _ => panic!(),
+        }
+    });
+}
+
+#[test]
+fn post_batch_request() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let (head, body) = http_request(
+            &client,
+            "POST",
+            "/",
+            "",
+            r#"[{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]},{"jsonrpc":"2.0","id":2,"method":"system_version","params":[]}]"#,
+        )
+        .await;
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        let responses = serde_json::from_str::<Vec<serde_json::Value>>(&body).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[1]["id"], 2);
+    });
+}
+
+#[test]
+fn subscriptions_refused() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let (_, body) = http_request(
+            &client,
+            "POST",
+            "/",
+            "",
+            r#"{"jsonrpc":"2.0","id":1,"method":"chain_subscribeNewHeads","params":[]}"#,
+        )
+        .await;
+
+        assert!(matches!(
+            json_rpc::parse::parse_response(&body).unwrap(),
+            json_rpc::parse::Response::Error { .. }
+        ));
+    });
+}
+
+#[test]
+fn health_endpoint() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let (head, body) = http_request(&client, "GET", "/health", "", "").await;
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        let health = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(health["peers"], 0);
+    });
+}
+
+#[test]
+fn cors() {
+    smol::block_on(async move {
+        let client = start_client().await;
+
+        let (head, _) = http_request(
+            &client,
+            "POST",
+            "/",
+            "Origin: http://allowed.example\r\n",
+            r#"{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]}"#,
+        )
+        .await;
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nAccess-Control-Allow-Origin: http://allowed.example\r\n"));
+
+        let (head, _) = http_request(
+            &client,
+            "OPTIONS",
+            "/",
+            "Origin: http://allowed.example\r\n",
+            "",
+        )
+        .await;
+        assert!(head.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(head.contains("\r\nAccess-Control-Allow-Methods: "));
+
+        let (head, _) = http_request(
+            &client,
+            "POST",
+            "/",
+            "Origin: http://forbidden.example\r\n",
+            r#"{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]}"#,
+        )
+        .await;
+        assert!(head.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    });
+}
+
+#[test]
+fn websocket_on_same_port() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let socket = smol::net::TcpStream::connect(client.json_rpc_server_addr().unwrap())
+            .await
+            .unwrap();
+
+        let mut ws_client = soketto::handshake::Client::new(socket, "localhost", "/");
+        match ws_client.handshake().await.unwrap() {
+            soketto::handshake::ServerResponse::Accepted { .. } => {}
+            _ => panic!(),
+        }
+        let (mut sender, mut receiver) = ws_client.into_builder().finish();
+
+        sender
+            .send_text(r#"{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]}"#)
+            .await
+            .unwrap();
+        sender.flush().await.unwrap();
+
+        let mut response = Vec::new();
+        receiver.receive_data(&mut response).await.unwrap();
+        match json_rpc::parse::parse_response(core::str::from_utf8(&response).unwrap()).unwrap() {
+            json_rpc::parse::Response::Success { id_json, .. } => assert_eq!(id_json, "1"),
+            _ => panic!(),
+        }
+    });
+}
+
+#[test]
+fn idle_connection_closed() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let mut socket = smol::net::TcpStream::connect(client.json_rpc_server_addr().unwrap())
+            .await
+            .unwrap();
+
+        // Announce a large body but never send it. The server must neither allocate the body
+        // upfront nor keep the connection open forever.
+        socket
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16000000\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = Vec::new();
+        let result = smol::future::or(
+            async { Some(socket.read_to_end(&mut response).await) },
+            async {
+                smol::Timer::after(std::time::Duration::from_secs(30)).await;
+                None
+            },
+        )
+        .await;
+        assert!(result.is_some());
+    });
+}
@@ -0,0 +1,99 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use smol::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use std::sync::Arc;
+
+async fn start_client() -> smoldot_full_node::Client {
+    smoldot_full_node::start(smoldot_full_node::Config {
+        chain: smoldot_full_node::ChainConfig {
+            chain_spec: (&include_bytes!("./substrate-node-template.json")[..]).into(),
+            additional_bootnodes: Vec::new(),
+            keystore_memory: vec![],
+            sqlite_database_path: None,
+            sqlite_cache_size: 256 * 1024 * 1024,
+            keystore_path: None,
+            compiled_runtimes_cache_path: None,
+            json_rpc_listen: None,
+            state_sync: false,
+        },
+        relay_chain: None,
+        libp2p_key: Box::new([0; 32]),
+        listen_addresses: Vec::new(),
+        tasks_executor: Arc::new(|task| smol::spawn(task).detach()),
+        log_callback: Arc::new(move |_, _| {}),
+        debug_logs_enabled: false,
+        jaeger_agent: None,
+        metrics_listen: Some("127.0.0.1:0".parse().unwrap()),
+        runtime_call_fuel_limit: None,
+    })
+    .await
+    .unwrap()
+}
+
+async fn http_get(client: &smoldot_full_node::Client, path: &str) -> String {
+    let mut socket = smol::net::TcpStream::connect(client.metrics_server_addr().unwrap())
+        .await
+        .unwrap();
+    socket
+        .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    socket.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test]
+fn metrics_are_served() {
+    smol::block_on(async move {
+        let client = start_client().await;
+
+        client.send_json_rpc_request(
+            r#"{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]}"#.to_owned(),
+        );
+        let _ = client.next_json_rpc_response().await;
+
+        let response = http_get(&client, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nsmoldot_best_block_height{chain=\"local_testnet\"} 0\n"));
+        assert!(response.contains("\nsmoldot_finalized_block_height{chain=\"local_testnet\"} 0\n"));
+        assert!(response.contains("\nsmoldot_peers{chain=\"local_testnet\"} 0\n"));
+        assert!(response.contains("\nsmoldot_network_connections{chain=\"local_testnet\"} 0\n"));
+        assert!(response.contains("\nsmoldot_database_size_bytes{chain=\"local_testnet\"} "));
+
+        // The duration of the request is reported slightly after the response has been sent.
+        loop {
+            let response = http_get(&client, "/metrics").await;
+            if response.contains(
+                "\nsmoldot_json_rpc_request_duration_seconds_count{chain=\"local_testnet\",method=\"system_name\"} 1\n",
+            ) {
+                break;
+            }
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+        }
+    });
+}
+
+#[test]
+fn unknown_path_returns_404() {
+    smol::block_on(async move {
+        let client = start_client().await;
+        let response = http_get(&client, "/foo").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    });
+}
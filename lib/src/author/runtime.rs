@@ -192,7 +192,10 @@ pub fn build_block(config: Config) -> BlockBuild {
         storage_main_trie_changes: Default::default(),
         storage_proof_size_behavior: runtime_call::StorageProofSizeBehavior::Unimplemented,
         max_log_level: config.max_log_level,
+        fuel_limit: None,
         calculate_trie_changes: config.calculate_trie_changes,
+        record_storage_proof: false,
+        record_trace: false,
     });
 
     let vm = match init_result {
@@ -345,7 +348,10 @@ impl BlockBuild {
                         storage_proof_size_behavior:
                             runtime_call::StorageProofSizeBehavior::Unimplemented,
                         max_log_level: shared.max_log_level,
+                        fuel_limit: None,
                         calculate_trie_changes: shared.calculate_trie_changes,
+                        record_storage_proof: false,
+                        record_trace: false,
                     });
 
                     inner = Inner::Runtime(match init_result {
@@ -545,7 +551,10 @@ impl InherentExtrinsics {
             storage_proof_size_behavior: runtime_call::StorageProofSizeBehavior::Unimplemented,
             storage_main_trie_changes: self.storage_changes.into_main_trie_diff(),
             max_log_level: self.shared.max_log_level,
+            fuel_limit: None,
             calculate_trie_changes: self.shared.calculate_trie_changes,
+            record_storage_proof: false,
+            record_trace: false,
         });
 
         let vm = match init_result {
@@ -577,7 +586,10 @@ impl ApplyExtrinsic {
             storage_proof_size_behavior: runtime_call::StorageProofSizeBehavior::Unimplemented,
             storage_main_trie_changes: self.storage_changes.into_main_trie_diff(),
             max_log_level: self.shared.max_log_level,
+            fuel_limit: None,
             calculate_trie_changes: self.shared.calculate_trie_changes,
+            record_storage_proof: false,
+            record_trace: false,
         });
 
         self.shared.stage = Stage::ApplyExtrinsic(extrinsic);
@@ -601,7 +613,10 @@ impl ApplyExtrinsic {
             storage_proof_size_behavior: runtime_call::StorageProofSizeBehavior::Unimplemented,
             storage_main_trie_changes: self.storage_changes.into_main_trie_diff(),
             max_log_level: self.shared.max_log_level,
+            fuel_limit: None,
             calculate_trie_changes: self.shared.calculate_trie_changes,
+            record_storage_proof: false,
+            record_trace: false,
         });
 
         let vm = match init_result {
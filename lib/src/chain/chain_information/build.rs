@@ -494,10 +494,13 @@ impl ChainInformationBuild {
                 parameter: call.parameter_vectored(),
                 virtual_machine: inner.virtual_machine.take().unwrap(),
                 max_log_level: 0,
+                fuel_limit: None,
                 storage_proof_size_behavior:
                     runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
                 storage_main_trie_changes: Default::default(),
                 calculate_trie_changes: false,
+                record_storage_proof: false,
+                record_trace: false,
             });
 
             let vm = match vm_start_result {
@@ -850,6 +853,10 @@ impl ChainInformationBuild {
                     // Generated logs are ignored.
                     call = req.resume();
                 }
+                runtime_call::RuntimeCall::StorageProofNodeValue(_) => {
+                    // Storage proofs are never recorded.
+                    unreachable!()
+                }
             }
         }
     }
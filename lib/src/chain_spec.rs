@@ -117,6 +117,7 @@ impl ChainSpec {
             module: &wasm_code,
             heap_pages,
             exec_hint: executor::vm::ExecHint::ValidateAndExecuteOnce,
+            fuel_metering: false,
             allow_unresolved_imports: true,
         })
         .map_err(FromGenesisStorageError::VmInitialization)?;
@@ -310,6 +311,21 @@ impl ChainSpec {
         }
     }
 
+    /// Returns the format of the head data of the parachain, in case this chain is a parachain.
+    ///
+    /// Defaults to [`crate::sync::para::HeadDataFormat::Header`] if the chain specification
+    /// doesn't indicate it.
+    pub fn head_data_format(&self) -> crate::sync::para::HeadDataFormat {
+        match self.client_spec.head_data_format {
+            None | Some(structs::HeadDataFormat::Header) => {
+                crate::sync::para::HeadDataFormat::Header
+            }
+            Some(structs::HeadDataFormat::HeaderWithSuffix) => {
+                crate::sync::para::HeadDataFormat::HeaderWithSuffix
+            }
+        }
+    }
+
     /// Gives access to what is known about the storage of the genesis block of the chain.
     pub fn genesis_storage(&self) -> GenesisStorage {
         match &self.client_spec.genesis {
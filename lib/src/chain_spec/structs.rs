@@ -81,6 +81,19 @@ pub(super) struct ClientSpec {
     // Same remark concerning the name as `relay_chain`
     #[serde(alias = "para_id")]
     pub(super) para_id: Option<u32>,
+    /// The `headDataFormat` field is a custom addition to the format of smoldot chain specs
+    /// compared to Substrate. It indicates how to interpret the head data of the parachain, as
+    /// the head data of some parachains contains more than just a block header. If the field is
+    /// missing, the head data is assumed to be a block header.
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub(super) head_data_format: Option<HeadDataFormat>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) enum HeadDataFormat {
+    Header,
+    HeaderWithSuffix,
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
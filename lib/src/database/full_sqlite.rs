@@ -78,7 +78,7 @@ use crate::{
 };
 
 use alloc::borrow::Cow;
-use core::{fmt, iter};
+use core::{array, fmt, iter};
 use parking_lot::Mutex;
 use rusqlite::OptionalExtension as _;
 
@@ -128,6 +128,69 @@ impl SqliteFullDatabase {
         finalized_hash(&database)
     }
 
+    /// Returns the value that was last passed to [`SqliteFullDatabase::set_state_sync_progress`],
+    /// or `None` if no state sync is in progress.
+    ///
+    /// The content of the returned value is opaque to the database.
+    pub fn state_sync_progress(&self) -> Result<Option<Vec<u8>>, CorruptedError> {
+        let connection = self.database.lock();
+        meta_get_blob(&connection, "state_sync_progress")
+    }
+
+    /// Stores the progress of a state sync, in other words the download of the entire storage
+    /// of the finalized block, so that it can be resumed after a restart.
+    ///
+    /// Passing `None` indicates that no state sync is in progress.
+    pub fn set_state_sync_progress(&self, progress: Option<&[u8]>) -> Result<(), CorruptedError> {
+        let connection = self.database.lock();
+        match progress {
+            Some(progress) => meta_set_blob(&connection, "state_sync_progress", progress),
+            None => meta_delete(&connection, "state_sync_progress"),
+        }
+    }
+
+    /// Returns statistics about the database, such as its size and the efficiency of the cache.
+    pub fn statistics(&self) -> Result<DatabaseStatistics, CorruptedError> {
+        let connection = self.database.lock();
+
+        let page_count = connection
+            .query_row("PRAGMA page_count", (), |row| row.get::<_, i64>(0))
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+        let page_size = connection
+            .query_row("PRAGMA page_size", (), |row| row.get::<_, i64>(0))
+            .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+
+        // SQLite doesn't expose the cache counters through `PRAGMA` statements, and `rusqlite`
+        // doesn't wrap `sqlite3_db_status`.
+        let mut cache_hits = 0;
+        let mut cache_misses = 0;
+        let mut highwater = 0;
+        // Safety: the raw handle is only used while the connection is locked, and
+        // `sqlite3_db_status` only reads counters.
+        unsafe {
+            rusqlite::ffi::sqlite3_db_status(
+                connection.handle(),
+                rusqlite::ffi::SQLITE_DBSTATUS_CACHE_HIT,
+                &mut cache_hits,
+                &mut highwater,
+                0,
+            );
+            rusqlite::ffi::sqlite3_db_status(
+                connection.handle(),
+                rusqlite::ffi::SQLITE_DBSTATUS_CACHE_MISS,
+                &mut cache_misses,
+                &mut highwater,
+                0,
+            );
+        }
+
+        Ok(DatabaseStatistics {
+            size_bytes: u64::try_from(page_count.saturating_mul(page_size)).unwrap_or(0),
+            cache_hits: u64::try_from(cache_hits).unwrap_or(0),
+            cache_misses: u64::try_from(cache_misses).unwrap_or(0),
+        })
+    }
+
     /// Returns the SCALE-encoded header of the given block, or `None` if the block is unknown.
     ///
     /// > **Note**: If this method is called twice times in a row with the same block hash, it
@@ -285,6 +348,7 @@ impl SqliteFullDatabase {
                         heap_pages,
                         exec_hint:
                             executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
+                        fuel_metering: false,
                         allow_unresolved_imports: true,
                     }) else {
                         todo!()
@@ -1384,6 +1448,213 @@ impl SqliteFullDatabase {
         Ok(merkle_value)
     }
 
+    /// Builds a Merkle proof of the storage of the given block.
+    ///
+    /// `keys_nibbles` must be an iterator to the **nibbles** of the keys to include in the
+    /// proof. The proof makes it possible to determine the storage value of each of these keys,
+    /// including when there is no storage value.
+    ///
+    /// Only the main trie is supported. Child tries can't be included in the proof.
+    ///
+    /// The proof is returned as a list of entries, in an unspecified order. The SCALE encoding of
+    /// this list is the format expected by [`trie::proof_decode::decode_and_verify_proof`].
+    ///
+    /// Returns an error if the block or some of the trie nodes necessary to build the proof
+    /// can't be found in the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the values yielded by `keys_nibbles` is superior or equal to 16.
+    ///
+    pub fn block_storage_merkle_proof(
+        &self,
+        block_hash: &[u8; 32],
+        keys_nibbles: impl Iterator<Item = impl Iterator<Item = u8>>,
+    ) -> Result<Vec<Vec<u8>>, StorageAccessError> {
+        Ok(self
+            .block_storage_merkle_proof_inner(block_hash, keys_nibbles, None)?
+            .unwrap())
+    }
+
+    /// Builds a Merkle proof of the storage of the given block containing all the descendants
+    /// of the given prefixes.
+    ///
+    /// `prefixes_nibbles` must be an iterator to the **nibbles** of the prefixes to include in
+    /// the proof. The proof contains all the trie nodes whose key starts with one of these
+    /// prefixes. In other words, the proof makes it possible to determine the list of keys that
+    /// start with each prefix and their storage values.
+    ///
+    /// Because the number of descendants of a prefix is unbounded, at most `max_descendants`
+    /// trie nodes descending from the prefixes are added to the proof. `Ok(None)` is returned if
+    /// the proof would need more nodes than that.
+    ///
+    /// See [`SqliteFullDatabase::block_storage_merkle_proof`] for the format of the proof and the
+    /// errors that can happen.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the values yielded by `prefixes_nibbles` is superior or equal to 16.
+    ///
+    pub fn block_storage_descendants_merkle_proof(
+        &self,
+        block_hash: &[u8; 32],
+        prefixes_nibbles: impl Iterator<Item = impl Iterator<Item = u8>>,
+        max_descendants: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>, StorageAccessError> {
+        self.block_storage_merkle_proof_inner(block_hash, prefixes_nibbles, Some(max_descendants))
+    }
+
+    /// Common implementation of [`SqliteFullDatabase::block_storage_merkle_proof`] and
+    /// [`SqliteFullDatabase::block_storage_descendants_merkle_proof`].
+    ///
+    /// If `max_descendants` is `Some`, the keys are considered as prefixes whose descendants must
+    /// be included in the proof, and `Ok(None)` is returned if there are more descendants than
+    /// the limit. Never returns `Ok(None)` if `max_descendants` is `None`.
+    fn block_storage_merkle_proof_inner(
+        &self,
+        block_hash: &[u8; 32],
+        keys_nibbles: impl Iterator<Item = impl Iterator<Item = u8>>,
+        max_descendants: Option<usize>,
+    ) -> Result<Option<Vec<Vec<u8>>>, StorageAccessError> {
+        // Process the iterators at the very beginning and before locking the database, in order
+        // to avoid a deadlock in case the `next()` function of one of the iterators accesses
+        // the database as well.
+        let keys_nibbles = keys_nibbles
+            .map(|key| {
+                key.map(|n| trie::Nibble::try_from(n).unwrap())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let connection = self.database.lock();
+
+        let state_trie_root_hash = connection
+            .prepare_cached(r#"SELECT state_trie_root_hash FROM blocks WHERE hash = ?"#)
+            .map_err(|err| {
+                StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err)))
+            })?
+            .query_row((&block_hash[..],), |row| row.get::<_, Option<Vec<u8>>>(0))
+            .optional()
+            .map_err(|err| {
+                StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err)))
+            })?
+            .ok_or(StorageAccessError::UnknownBlock)?;
+
+        // A `NULL` trie root hash indicates an empty trie.
+        let Some(state_trie_root_hash) = state_trie_root_hash else {
+            return Ok(Some(Vec::new()));
+        };
+
+        let mut proof_builder = trie::proof_encode::ProofBuilder::new();
+
+        // Keys of the nodes that have been added to the proof because they descend from one of
+        // the prefixes. Used to bound their number, and to not walk twice the same subtree when
+        // prefixes overlap.
+        let mut descendants =
+            hashbrown::HashSet::<Vec<trie::Nibble>, fnv::FnvBuildHasher>::default();
+
+        // Trie nodes are loaded from the database at most once, as the paths to the various keys
+        // typically overlap.
+        let mut loaded_nodes =
+            hashbrown::HashMap::<Vec<u8>, TrieNodeLoaded, fnv::FnvBuildHasher>::default();
+
+        for key in keys_nibbles {
+            // Nodes whose entire subtree must be added to the proof, alongside with the key of
+            // these nodes, excluding their partial key.
+            let mut subtrees = Vec::<(Vec<trie::Nibble>, Vec<u8>)>::new();
+
+            // Walk down the trie from the root towards `key`, adding every node on the path.
+            let mut node_merkle_value = state_trie_root_hash.clone();
+            let mut node_key = Vec::<trie::Nibble>::with_capacity(key.len());
+            loop {
+                let node =
+                    load_trie_node_cached(&connection, &mut loaded_nodes, &node_merkle_value)?;
+                node_key.extend_from_slice(&node.partial_key);
+                proof_builder.set_node_value(
+                    &node_key,
+                    &node.node_value,
+                    node.unhashed_storage_value.as_deref(),
+                );
+
+                if node_key.starts_with(&key) {
+                    // Either the node is at `key`, in which case the storage value is in the
+                    // proof, or the node is the closest descendant of `key`, in which case the
+                    // proof shows that there isn't any storage value at `key`.
+                    // In both cases, the node is also the first descendant of `key` when it is a
+                    // prefix.
+                    if let Some(max_descendants) = max_descendants {
+                        if !descendants.insert(node_key.clone()) {
+                            break;
+                        }
+                        if descendants.len() > max_descendants {
+                            return Ok(None);
+                        }
+                        for (child_index, child) in trie::all_nibbles().zip(node.children.iter()) {
+                            let Some(child) = child else { continue };
+                            let mut child_key = node_key.clone();
+                            child_key.push(child_index);
+                            subtrees.push((child_key, child.clone()));
+                        }
+                    }
+                    break;
+                }
+
+                if !key.starts_with(&node_key) {
+                    // The node diverges from `key`, proving that there's no storage value.
+                    break;
+                }
+
+                let child_index = key[node_key.len()];
+                let Some(child) = &node.children[usize::from(child_index)] else {
+                    break;
+                };
+                node_merkle_value = child.clone();
+                node_key.push(child_index);
+            }
+
+            // Add all the descendants of the nodes in `subtrees`.
+            while let Some((mut node_key, node_merkle_value)) = subtrees.pop() {
+                let node =
+                    load_trie_node_cached(&connection, &mut loaded_nodes, &node_merkle_value)?;
+                node_key.extend_from_slice(&node.partial_key);
+
+                if !descendants.insert(node_key.clone()) {
+                    // Subtree already walked because of an overlapping prefix.
+                    continue;
+                }
+                if max_descendants.is_some_and(|max| descendants.len() > max) {
+                    return Ok(None);
+                }
+
+                proof_builder.set_node_value(
+                    &node_key,
+                    &node.node_value,
+                    node.unhashed_storage_value.as_deref(),
+                );
+
+                for (child_index, child) in trie::all_nibbles().zip(node.children.iter()) {
+                    let Some(child) = child else { continue };
+                    let mut child_key = node_key.clone();
+                    child_key.push(child_index);
+                    subtrees.push((child_key, child.clone()));
+                }
+            }
+        }
+
+        Ok(Some(proof_builder.build_entries().collect()))
+    }
+
+    /// Returns the node value of the trie node whose Merkle value is the given one.
+    ///
+    /// The Merkle value must be a hash. Trie nodes whose node value is shorter than 32 bytes
+    /// are their own Merkle value and aren't stored in the database.
+    ///
+    /// Returns [`StorageAccessError::IncompleteStorage`] if the node is missing from the database.
+    pub fn trie_node_value(&self, merkle_value: &[u8]) -> Result<Vec<u8>, StorageAccessError> {
+        let connection = self.database.lock();
+        Ok(load_trie_node(&connection, merkle_value)?.node_value)
+    }
+
     /// Inserts a block in the database and sets it as the finalized block.
     ///
     /// The parent of the block doesn't need to be present in the database.
@@ -1563,6 +1834,17 @@ pub enum StorageAccessError {
     UnknownBlock,
 }
 
+/// See [`SqliteFullDatabase::statistics`].
+#[derive(Debug, Clone)]
+pub struct DatabaseStatistics {
+    /// Size of the database in bytes, not including the write-ahead log.
+    pub size_bytes: u64,
+    /// Number of pages that have been found in the SQLite cache since the database was opened.
+    pub cache_hits: u64,
+    /// Number of pages that were missing from the SQLite cache since the database was opened.
+    pub cache_misses: u64,
+}
+
 /// Error in the content of the database.
 // TODO: document and see if any entry is unused
 #[derive(Debug, derive_more::Display)]
@@ -1588,6 +1870,10 @@ pub enum CorruptedError {
     BlockHeaderCorrupted(header::Error),
     /// The version information about a storage entry has failed to decode.
     InvalidTrieEntryVersion,
+    /// The partial key or child index of a trie node isn't a valid nibble.
+    InvalidTrieNibble,
+    /// A trie node has neither a storage value nor children.
+    InvalidTrieNode,
     #[display(fmt = "Internal error: {_0}")]
     Internal(InternalError),
 }
@@ -1648,6 +1934,15 @@ fn meta_set_number(
     Ok(())
 }
 
+fn meta_delete(database: &rusqlite::Connection, key: &str) -> Result<(), CorruptedError> {
+    database
+        .prepare_cached(r#"DELETE FROM meta WHERE key = ?"#)
+        .map_err(|err| CorruptedError::Internal(InternalError(err)))?
+        .execute((key,))
+        .map_err(|err| CorruptedError::Internal(InternalError(err)))?;
+    Ok(())
+}
+
 fn has_block(database: &rusqlite::Connection, hash: &[u8]) -> Result<bool, CorruptedError> {
     database
         .prepare_cached(r#"SELECT COUNT(*) FROM blocks WHERE hash = ?"#)
@@ -1656,6 +1951,128 @@ fn has_block(database: &rusqlite::Connection, hash: &[u8]) -> Result<bool, Corru
         .map_err(|err| CorruptedError::Internal(InternalError(err)))
 }
 
+/// Trie node loaded from the database. See [`load_trie_node_cached`].
+struct TrieNodeLoaded {
+    /// Partial key of the node.
+    partial_key: Vec<trie::Nibble>,
+    /// Node value of the node, as found in trie proofs.
+    node_value: Vec<u8>,
+    /// If the storage value of the node is hashed in the node value, contains the storage value.
+    unhashed_storage_value: Option<Vec<u8>>,
+    /// Merkle values of the children of the node.
+    children: [Option<Vec<u8>>; 16],
+}
+
+/// Loads a trie node from the database, or from `cache` if it has already been loaded.
+///
+/// Returns [`StorageAccessError::IncompleteStorage`] if the node is missing from the database.
+fn load_trie_node_cached<'a>(
+    database: &rusqlite::Connection,
+    cache: &'a mut hashbrown::HashMap<Vec<u8>, TrieNodeLoaded, fnv::FnvBuildHasher>,
+    merkle_value: &[u8],
+) -> Result<&'a TrieNodeLoaded, StorageAccessError> {
+    if !cache.contains_key(merkle_value) {
+        let node = load_trie_node(database, merkle_value)?;
+        cache.insert(merkle_value.to_vec(), node);
+    }
+
+    Ok(cache.get(merkle_value).unwrap())
+}
+
+fn load_trie_node(
+    database: &rusqlite::Connection,
+    merkle_value: &[u8],
+) -> Result<TrieNodeLoaded, StorageAccessError> {
+    let partial_key = database
+        .prepare_cached(r#"SELECT partial_key FROM trie_node WHERE hash = ?"#)
+        .map_err(|err| StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .query_row((merkle_value,), |row| row.get::<_, Vec<u8>>(0))
+        .optional()
+        .map_err(|err| StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .ok_or(StorageAccessError::IncompleteStorage)?
+        .into_iter()
+        .map(|n| {
+            trie::Nibble::try_from(n)
+                .map_err(|_| StorageAccessError::Corrupted(CorruptedError::InvalidTrieNibble))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut children: [Option<Vec<u8>>; 16] = Default::default();
+    for child in database
+        .prepare_cached(r#"SELECT child_num, child_hash FROM trie_node_child WHERE hash = ?"#)
+        .map_err(|err| StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .query_map((merkle_value,), |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })
+        .map_err(|err| {
+            StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err)))
+        })?
+    {
+        let (child_num, child_hash) = child.map_err(|err| {
+            StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err)))
+        })?;
+        let child_num = match &child_num[..] {
+            [n] if *n < 16 => usize::from(*n),
+            _ => {
+                return Err(StorageAccessError::Corrupted(
+                    CorruptedError::InvalidTrieNibble,
+                ))
+            }
+        };
+        children[child_num] = Some(child_hash);
+    }
+
+    // The value of `trie_root_ref` is the storage value of the node when the node refers to
+    // another trie.
+    let storage_value = database
+        .prepare_cached(r#"SELECT COALESCE(value, trie_root_ref), trie_entry_version FROM trie_node_storage WHERE node_hash = ?"#)
+        .map_err(|err| StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .query_row((merkle_value,), |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?))
+        })
+        .optional()
+        .map_err(|err| StorageAccessError::Corrupted(CorruptedError::Internal(InternalError(err))))?
+        .map(|(value, version)| {
+            let version = u8::try_from(version)
+                .ok()
+                .and_then(|v| trie::TrieEntryVersion::try_from(v).ok())
+                .ok_or(StorageAccessError::Corrupted(
+                    CorruptedError::InvalidTrieEntryVersion,
+                ))?;
+            Ok::<_, StorageAccessError>((value, version))
+        })
+        .transpose()?;
+
+    let storage_value_hash = match &storage_value {
+        Some((value, trie::TrieEntryVersion::V1)) if value.len() >= 33 => Some(
+            <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], value).as_bytes()).unwrap(),
+        ),
+        _ => None,
+    };
+
+    let node_value = trie::trie_node::encode_to_vec(trie::trie_node::Decoded {
+        partial_key: partial_key.iter().copied(),
+        children: array::from_fn::<_, 16, _>(|n| children[n].as_deref()),
+        storage_value: match (&storage_value, &storage_value_hash) {
+            (_, Some(hash)) => trie::trie_node::StorageValue::Hashed(hash),
+            (Some((value, _)), None) => trie::trie_node::StorageValue::Unhashed(value),
+            (None, None) => trie::trie_node::StorageValue::None,
+        },
+    })
+    .map_err(|_| StorageAccessError::Corrupted(CorruptedError::InvalidTrieNode))?;
+
+    Ok(TrieNodeLoaded {
+        partial_key,
+        node_value,
+        unhashed_storage_value: if storage_value_hash.is_some() {
+            storage_value.map(|(value, _)| value)
+        } else {
+            None
+        },
+        children,
+    })
+}
+
 // TODO: the fact that the meta table stores blobs makes it impossible to use joins ; fix that
 fn finalized_num(database: &rusqlite::Connection) -> Result<u64, CorruptedError> {
     meta_get_number(database, "finalized")?.ok_or(CorruptedError::MissingMetaKey)
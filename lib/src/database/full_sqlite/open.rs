@@ -113,6 +113,10 @@ Keys in that table:
 
  - `finalized` (number): Height of the finalized block, as a 64bits big endian number.
 
+ - `state_sync_progress` (blob): Optional. Opaque progress of a state sync (i.e. the download of
+ the entire storage of the finalized block) that is in progress. Absent if no state sync is in
+ progress.
+
 */
 CREATE TABLE meta(
     key STRING NOT NULL PRIMARY KEY,
@@ -273,6 +273,104 @@ fn empty_database_fill_then_query() {
                 trie
             );
         }
+
+        // Ask random Merkle proofs.
+        for _ in 0..32 {
+            let keys = (0..uniform_sample(0, 4))
+                .map(|_| {
+                    (0..uniform_sample(0, 8))
+                        .map(|_| trie::Nibble::try_from(uniform_sample(0u8, 15)).unwrap())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let include_descendants = rand::random::<bool>();
+            let proof = if include_descendants {
+                open_db
+                    .block_storage_descendants_merkle_proof(
+                        &block0_hash,
+                        keys.iter().map(|k| k.iter().copied().map(u8::from)),
+                        usize::MAX,
+                    )
+                    .unwrap()
+                    .unwrap()
+            } else {
+                open_db
+                    .block_storage_merkle_proof(
+                        &block0_hash,
+                        keys.iter().map(|k| k.iter().copied().map(u8::from)),
+                    )
+                    .unwrap()
+            };
+
+            // The number of descendants is bounded.
+            if include_descendants {
+                let num_descendants = trie
+                    .iter_ordered()
+                    .map(|n| trie.node_full_key_by_index(n).unwrap().collect::<Vec<_>>())
+                    .filter(|node_key| keys.iter().any(|k| node_key.starts_with(k)))
+                    .count();
+                if num_descendants != 0 {
+                    assert!(open_db
+                        .block_storage_descendants_merkle_proof(
+                            &block0_hash,
+                            keys.iter().map(|k| k.iter().copied().map(u8::from)),
+                            num_descendants - 1,
+                        )
+                        .unwrap()
+                        .is_none());
+                }
+            }
+
+            let state_root = trie
+                .root_user_data()
+                .map(|n| *<&[u8; 32]>::try_from(n.1.as_ref().unwrap().as_ref()).unwrap())
+                .unwrap();
+            let root_node_value = open_db.trie_node_value(&state_root).unwrap();
+            assert!(keys.is_empty() || proof.contains(&root_node_value));
+            let proof = iter::once(
+                crate::util::encode_scale_compact_usize(proof.len())
+                    .as_ref()
+                    .to_vec(),
+            )
+            .chain(proof.into_iter().flat_map(|entry| {
+                [
+                    crate::util::encode_scale_compact_usize(entry.len())
+                        .as_ref()
+                        .to_vec(),
+                    entry,
+                ]
+            }))
+            .flatten()
+            .collect::<Vec<_>>();
+            let decoded = trie::proof_decode::decode_and_verify_proof(trie::proof_decode::Config {
+                proof: &proof,
+            })
+            .unwrap();
+
+            let expected_in_proof = trie
+                .iter_ordered()
+                .map(|n| trie.node_full_key_by_index(n).unwrap().collect::<Vec<_>>())
+                .filter(|node_key| {
+                    include_descendants && keys.iter().any(|k| node_key.starts_with(k))
+                })
+                .chain(keys.iter().cloned())
+                .collect::<Vec<_>>();
+            for key in &expected_in_proof {
+                let info = decoded
+                    .trie_node_info(&state_root, key.iter().copied())
+                    .unwrap();
+                let expected = trie
+                    .node_by_full_key(key.iter().copied())
+                    .and_then(|n| trie[n].0.clone());
+                match (info.storage_value, expected) {
+                    (trie::proof_decode::StorageValue::Known { value, .. }, Some(expected)) => {
+                        assert_eq!(value, expected)
+                    }
+                    (trie::proof_decode::StorageValue::None, None) => {}
+                    _ => panic!("\nkey = {key:?}\ntrie = {trie:?}"),
+                }
+            }
+        }
     }
 }
 
@@ -338,6 +436,84 @@ fn unknown_block() {
     ));
 }
 
+#[test]
+fn state_sync_progress_roundtrip() {
+    let DatabaseOpen::Empty(empty_db) = open(Config {
+        block_number_bytes: 4,
+        cache_size: 2 * 1024 * 1024,
+        ty: ConfigTy::Memory,
+    })
+    .unwrap() else {
+        panic!()
+    };
+
+    let db = empty_db
+        .initialize(
+            &header::HeaderRef {
+                number: 0,
+                extrinsics_root: &[0; 32],
+                parent_hash: &[0; 32],
+                state_root: &[1; 32],
+                digest: header::DigestRef::empty(),
+            }
+            .scale_encoding_vec(4),
+            iter::empty(),
+            None,
+        )
+        .unwrap();
+
+    assert_eq!(db.state_sync_progress().unwrap(), None);
+    db.set_state_sync_progress(Some(b"foo")).unwrap();
+    assert_eq!(
+        db.state_sync_progress().unwrap().as_deref(),
+        Some(&b"foo"[..])
+    );
+    db.set_state_sync_progress(Some(b"bar")).unwrap();
+    assert_eq!(
+        db.state_sync_progress().unwrap().as_deref(),
+        Some(&b"bar"[..])
+    );
+    db.set_state_sync_progress(None).unwrap();
+    assert_eq!(db.state_sync_progress().unwrap(), None);
+}
+
+#[test]
+fn statistics_work() {
+    let DatabaseOpen::Empty(empty_db) = open(Config {
+        block_number_bytes: 4,
+        cache_size: 2 * 1024 * 1024,
+        ty: ConfigTy::Memory,
+    })
+    .unwrap() else {
+        panic!()
+    };
+
+    let db = empty_db
+        .initialize(
+            &header::HeaderRef {
+                number: 0,
+                extrinsics_root: &[0; 32],
+                parent_hash: &[0; 32],
+                state_root: &[1; 32],
+                digest: header::DigestRef::empty(),
+            }
+            .scale_encoding_vec(4),
+            iter::empty(),
+            None,
+        )
+        .unwrap();
+
+    let before = db.statistics().unwrap();
+    assert!(before.size_bytes > 0);
+
+    for _ in 0..16 {
+        db.best_block_hash().unwrap();
+    }
+
+    let after = db.statistics().unwrap();
+    assert!(after.cache_hits > before.cache_hits);
+}
+
 #[test]
 fn storage_get_partial() {
     let DatabaseOpen::Empty(empty_db) = open(Config {
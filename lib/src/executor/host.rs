@@ -155,11 +155,13 @@
 //!         module: &wasm_binary_code,
 //!         heap_pages: HeapPages::from(2048),
 //!         exec_hint: smoldot::executor::vm::ExecHint::ValidateAndExecuteOnce,
+//!         fuel_metering: false,
 //!         allow_unresolved_imports: false
 //!     }).unwrap();
 //!     prototype.run_no_param(
 //!         "Core_version",
-//!         StorageProofSizeBehavior::proof_recording_disabled()
+//!         StorageProofSizeBehavior::proof_recording_disabled(),
+//!         None
 //!     ).unwrap().into()
 //! };
 //!
@@ -232,6 +234,14 @@ pub struct Config<TModule> {
     /// Hint used by the implementation to decide which kind of virtual machine to use.
     pub exec_hint: vm::ExecHint,
 
+    /// If `true`, the amount of fuel consumed by the runtime calls is counted, which is
+    /// necessary in order to pass a fuel limit to [`HostVmPrototype::run`] and similar
+    /// functions. See [`vm::Config::fuel_metering`].
+    ///
+    /// Fuel metering slows down the execution, and should only be enabled if calls with a fuel
+    /// limit are going to be performed.
+    pub fuel_metering: bool,
+
     /// If `true`, no [`vm::NewErr::UnresolvedFunctionImport`] error will be returned if the
     /// module trying to import functions that aren't recognized by the implementation. Instead,
     /// a [`Error::UnresolvedFunctionCalled`] error will be generated if the module tries to call
@@ -309,6 +319,79 @@ struct VmCommon {
 impl HostVmPrototype {
     /// Creates a new [`HostVmPrototype`]. Parses and potentially JITs the module.
     pub fn new(config: Config<impl AsRef<[u8]>>) -> Result<Self, NewErr> {
+        // Safety: no compiled module is passed.
+        unsafe { Self::new_inner(config, None, None) }
+    }
+
+    /// Same as [`HostVmPrototype::new`], but uses the given runtime version instead of looking
+    /// for it in the module or calling the `Core_version` runtime function.
+    ///
+    /// The runtime version must have been obtained from a module identical to the one in
+    /// [`Config::module`], for example by calling [`HostVmPrototype::runtime_version`] on a
+    /// previous instance. It is trusted and isn't verified in any way.
+    pub fn new_with_runtime_version(
+        config: Config<impl AsRef<[u8]>>,
+        runtime_version: CoreVersion,
+    ) -> Result<Self, NewErr> {
+        // Safety: no compiled module is passed.
+        unsafe { Self::new_inner(config, None, Some(runtime_version)) }
+    }
+
+    /// Creates a new [`HostVmPrototype`] from a module previously compiled and serialized with
+    /// [`HostVmPrototype::serialize_compiled_module`], skipping the compilation.
+    ///
+    /// [`Config::module`] must contain the same module as the one that has been compiled. See
+    /// [`vm::VirtualMachinePrototype::from_compiled_module`] for more information.
+    ///
+    /// # Safety
+    ///
+    /// See [`vm::VirtualMachinePrototype::from_compiled_module`]. The content of
+    /// `compiled_module` is trusted and executed without any verification.
+    pub unsafe fn from_compiled_module(
+        config: Config<impl AsRef<[u8]>>,
+        compiled_module: &[u8],
+    ) -> Result<Self, NewErr> {
+        Self::new_inner(config, Some(compiled_module), None)
+    }
+
+    /// Returns a key identifying the result of compiling the module of the given configuration,
+    /// or `None` if the module isn't compiled ahead of time.
+    ///
+    /// Modules serialized with [`HostVmPrototype::serialize_compiled_module`] can be stored
+    /// using this key, then later passed to [`HostVmPrototype::from_compiled_module`]. See
+    /// [`vm::VirtualMachinePrototype::compiled_module_cache_key`].
+    pub fn compiled_module_cache_key(config: &Config<impl AsRef<[u8]>>) -> Option<[u8; 32]> {
+        // An error while decompressing will also make `new` fail, so there's no point in
+        // returning a key.
+        let module_bytes =
+            zstd::zstd_decode_if_necessary(config.module.as_ref(), 50 * 1024 * 1024).ok()?;
+        vm::VirtualMachinePrototype::compiled_module_cache_key(&vm::Config {
+            module_bytes: &module_bytes[..],
+            exec_hint: config.exec_hint,
+            fuel_metering: config.fuel_metering,
+            symbols: &mut |_, _, _| Err(()),
+        })
+    }
+
+    /// Serializes the compiled module so that it can later be passed to
+    /// [`HostVmPrototype::from_compiled_module`].
+    ///
+    /// Returns `None` if the module isn't compiled ahead of time.
+    pub fn serialize_compiled_module(&self) -> Option<Vec<u8>> {
+        self.vm_proto.serialize_compiled_module()
+    }
+
+    /// Implementation of [`HostVmPrototype::new`], [`HostVmPrototype::new_with_runtime_version`]
+    /// and [`HostVmPrototype::from_compiled_module`].
+    ///
+    /// # Safety
+    ///
+    /// See [`HostVmPrototype::from_compiled_module`].
+    unsafe fn new_inner(
+        config: Config<impl AsRef<[u8]>>,
+        compiled_module: Option<&[u8]>,
+        known_runtime_version: Option<CoreVersion>,
+    ) -> Result<Self, NewErr> {
         // The maximum allowed size for the decompressed Wasm code needs to be the same amongst
         // all implementations.
         // See <https://github.com/paritytech/substrate/blob/f9d10fabe04d598d68f8b097cc4905adbb1ad630/primitives/maybe-compressed-blob/src/lib.rs#L37>.
@@ -324,7 +407,11 @@ impl HostVmPrototype {
         // In the case of `CustomSectionsPresenceMismatch`, indicating that one section is present
         // but not the other, we must ignore the custom sections. This is necessary due to some
         // historical accidents.
-        let runtime_version = match runtime_version::find_embedded_runtime_version(&module_bytes) {
+        // This is skipped if the runtime version is already known.
+        let runtime_version = match known_runtime_version.map_or_else(
+            || runtime_version::find_embedded_runtime_version(&module_bytes),
+            |v| Ok(Some(v)),
+        ) {
             Ok(Some(r)) => Some(r),
             Ok(None) => None,
             Err(
@@ -353,9 +440,10 @@ impl HostVmPrototype {
         // array.
         let (mut vm_proto, registered_functions) = {
             let mut registered_functions = Vec::new();
-            let vm_proto = vm::VirtualMachinePrototype::new(vm::Config {
+            let vm_config = vm::Config {
                 module_bytes: &module_bytes[..],
                 exec_hint: config.exec_hint,
+                fuel_metering: config.fuel_metering,
                 // This closure is called back for each function that the runtime imports.
                 symbols: &mut |mod_name, f_name, signature| {
                     if mod_name != "env" {
@@ -376,7 +464,13 @@ impl HostVmPrototype {
                     });
                     Ok(id)
                 },
-            })?;
+            };
+            let vm_proto = match compiled_module {
+                Some(compiled_module) => {
+                    vm::VirtualMachinePrototype::from_compiled_module(vm_config, compiled_module)?
+                }
+                None => vm::VirtualMachinePrototype::new(vm_config)?,
+            };
             (vm_proto, registered_functions.into())
         };
 
@@ -415,6 +509,7 @@ impl HostVmPrototype {
             let mut vm: HostVm = match host_vm_prototype.run_no_param(
                 "Core_version",
                 StorageProofSizeBehavior::proof_recording_disabled(),
+                None,
             ) {
                 Ok(vm) => vm.into(),
                 Err((err, _)) => return Err(NewErr::CoreVersion(CoreVersionError::Start(err))),
@@ -476,15 +571,21 @@ impl HostVmPrototype {
     ///
     /// See the documentation of [`StorageProofSizeBehavior`] for an explanation of
     /// the `storage_proof_size_behavior` parameter.
+    ///
+    /// `fuel_limit` is the maximum amount of fuel that the call can consume, or `None` for no
+    /// limit. If the limit is reached, the call ends with an [`Error::OutOfFuel`]. See
+    /// [`vm::Prepare::start`] for more information.
     pub fn run(
         self,
         function_to_call: &str,
         storage_proof_size_behavior: StorageProofSizeBehavior,
+        fuel_limit: Option<u64>,
         data: &[u8],
     ) -> Result<ReadyToRun, (StartErr, Self)> {
         self.run_vectored(
             function_to_call,
             storage_proof_size_behavior,
+            fuel_limit,
             iter::once(data),
         )
     }
@@ -497,10 +598,12 @@ impl HostVmPrototype {
         self,
         function_to_call: &str,
         storage_proof_size_behavior: StorageProofSizeBehavior,
+        fuel_limit: Option<u64>,
     ) -> Result<ReadyToRun, (StartErr, Self)> {
         self.run_vectored(
             function_to_call,
             storage_proof_size_behavior,
+            fuel_limit,
             iter::empty::<Vec<u8>>(),
         )
     }
@@ -514,6 +617,7 @@ impl HostVmPrototype {
         mut self,
         function_to_call: &str,
         storage_proof_size_behavior: StorageProofSizeBehavior,
+        fuel_limit: Option<u64>,
         data: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
     ) -> Result<ReadyToRun, (StartErr, Self)> {
         // Determine the total length of `data`.
@@ -581,6 +685,7 @@ impl HostVmPrototype {
                 vm::WasmValue::I32(i32::from_ne_bytes(data_ptr.to_ne_bytes())),
                 vm::WasmValue::I32(i32::from_ne_bytes(data_len_u32.to_ne_bytes())),
             ],
+            fuel_limit,
         ) {
             Ok(vm) => vm,
             Err((error, vm_proto)) => {
@@ -823,6 +928,13 @@ impl ReadyToRun {
                 unreachable!()
             }
 
+            Err(vm::RunErr::OutOfFuel) => {
+                return HostVm::Error {
+                    error: Error::OutOfFuel,
+                    prototype: self.inner.into_prototype(),
+                }
+            }
+
             Err(vm::RunErr::Poisoned) => {
                 // Can only happen if there's a bug somewhere.
                 unreachable!()
@@ -4006,6 +4118,10 @@ pub enum Error {
     /// Error in the Wasm code execution.
     #[display(fmt = "{_0}")]
     Trap(vm::Trap),
+    /// The runtime call has consumed all the fuel allowed when starting it. See
+    /// [`HostVmPrototype::run`].
+    #[display(fmt = "Runtime call has consumed all its fuel")]
+    OutOfFuel,
     /// Runtime has called the `ext_panic_handler_abort_on_panic_version_1` host function.
     #[display(fmt = "Runtime has aborted: {message:?}")]
     AbortOnPanic {
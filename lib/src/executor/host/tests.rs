@@ -93,6 +93,7 @@ fn basic_core_version() {
             module: &include_bytes!("./westend-runtime-v9300.wasm")[..],
             heap_pages: HeapPages::new(2048),
             exec_hint,
+            fuel_metering: false,
             allow_unresolved_imports: true,
         })
         .unwrap();
@@ -101,6 +102,7 @@ fn basic_core_version() {
             .run_no_param(
                 "Core_version",
                 StorageProofSizeBehavior::proof_recording_disabled(),
+                None,
             )
             .unwrap()
             .run();
@@ -137,6 +139,7 @@ fn min_requirements() {
 
     for exec_hint in ExecHint::available_engines() {
         HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
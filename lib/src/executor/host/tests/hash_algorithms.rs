@@ -216,6 +216,7 @@ macro_rules! gen_test {
 
             for exec_hint in ExecHint::available_engines() {
                 let proto = HostVmPrototype::new(Config {
+                    fuel_metering: false,
                     allow_unresolved_imports: false,
                     exec_hint,
                     heap_pages: HeapPages::new(1024),
@@ -228,6 +229,7 @@ macro_rules! gen_test {
                         .run(
                             "test",
                             StorageProofSizeBehavior::proof_recording_disabled(),
+                            None,
                             &$expected_out,
                         )
                         .unwrap(),
@@ -26,6 +26,7 @@ fn invalid_wasm() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -44,6 +45,7 @@ fn invalid_zstd() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -70,6 +72,7 @@ fn valid_zstd() {
 
     for exec_hint in ExecHint::available_engines() {
         HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -94,6 +97,7 @@ fn no_heap_base() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -121,6 +125,7 @@ fn memory_max_size_too_low() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -149,6 +154,7 @@ fn unresolved_host_functions_setting() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: true,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -159,6 +165,7 @@ fn unresolved_host_functions_setting() {
         }
 
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -189,6 +196,7 @@ fn host_function_bad_signature() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: true,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -199,6 +207,7 @@ fn host_function_bad_signature() {
         }
 
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -218,6 +227,7 @@ fn rococo_genesis_works() {
 
     for exec_hint in ExecHint::available_engines() {
         assert!(HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: true,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -227,4 +237,56 @@ fn rococo_genesis_works() {
     }
 }
 
+#[test]
+fn known_runtime_version_skips_core_version() {
+    let module_without_version = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory 0))
+        (global (export "__heap_base") i32 (i32.const 0))
+    )
+    "#,
+    )
+    .unwrap();
+    let module_with_version = with_core_version_custom_sections(module_without_version.clone());
+
+    for exec_hint in ExecHint::available_engines() {
+        // The module without custom sections doesn't export `Core_version`.
+        assert!(matches!(
+            HostVmPrototype::new(Config {
+                fuel_metering: false,
+                allow_unresolved_imports: false,
+                exec_hint,
+                heap_pages: HeapPages::new(1024),
+                module: &module_without_version,
+            }),
+            Err(NewErr::CoreVersion(_))
+        ));
+
+        let runtime_version = HostVmPrototype::new(Config {
+            fuel_metering: false,
+            allow_unresolved_imports: false,
+            exec_hint,
+            heap_pages: HeapPages::new(1024),
+            module: &module_with_version,
+        })
+        .unwrap()
+        .runtime_version()
+        .clone();
+
+        let prototype = HostVmPrototype::new_with_runtime_version(
+            Config {
+                fuel_metering: false,
+                allow_unresolved_imports: false,
+                exec_hint,
+                heap_pages: HeapPages::new(1024),
+                module: &module_without_version,
+            },
+            runtime_version.clone(),
+        )
+        .unwrap();
+        assert_eq!(*prototype.runtime_version(), runtime_version);
+    }
+}
+
 // TODO: add tests for the runtime version gathering after clarifying the errors in host.rs
@@ -37,6 +37,7 @@ fn function_to_run_doesnt_exist() {
 
     for exec_hint in ExecHint::available_engines() {
         let host_vm = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -47,6 +48,7 @@ fn function_to_run_doesnt_exist() {
         match host_vm.run_no_param(
             "functiondoesntexist",
             StorageProofSizeBehavior::proof_recording_disabled(),
+            None,
         ) {
             Err((StartErr::VirtualMachine(vm::StartErr::FunctionNotFound), _)) => {}
             _ => unreachable!(),
@@ -71,6 +73,7 @@ fn function_to_run_invalid_params() {
 
     for exec_hint in ExecHint::available_engines() {
         let host_vm = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -81,6 +84,7 @@ fn function_to_run_invalid_params() {
         match host_vm.run_no_param(
             "hello",
             StorageProofSizeBehavior::proof_recording_disabled(),
+            None,
         ) {
             Err((StartErr::VirtualMachine(vm::StartErr::InvalidParameters), _)) => {}
             _ => unreachable!(),
@@ -203,6 +207,7 @@ fn input_provided_correctly() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -215,6 +220,7 @@ fn input_provided_correctly() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     b"hello world",
                 )
                 .unwrap(),
@@ -233,6 +239,7 @@ fn input_provided_correctly() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -245,6 +252,7 @@ fn input_provided_correctly() {
                 .run_vectored(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     [&b"hello "[..], &b"world"[..]].into_iter(),
                 )
                 .unwrap(),
@@ -264,6 +272,7 @@ fn input_provided_correctly() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -276,6 +285,7 @@ fn input_provided_correctly() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     b"unexpected input",
                 )
                 .unwrap(),
@@ -378,6 +388,7 @@ fn large_input_provided_correctly() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -390,6 +401,7 @@ fn large_input_provided_correctly() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &input_data,
                 )
                 .unwrap(),
@@ -449,6 +461,7 @@ fn return_value_works() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -461,6 +474,7 @@ fn return_value_works() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &[],
                 )
                 .unwrap(),
@@ -511,6 +525,7 @@ fn bad_return_value() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -523,6 +538,7 @@ fn bad_return_value() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &[],
                 )
                 .unwrap(),
@@ -578,6 +594,7 @@ fn returned_ptr_out_of_range() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -590,6 +607,7 @@ fn returned_ptr_out_of_range() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &[],
                 )
                 .unwrap(),
@@ -645,6 +663,7 @@ fn returned_size_out_of_range() {
 
     for exec_hint in ExecHint::available_engines() {
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -657,6 +676,7 @@ fn returned_size_out_of_range() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &[],
                 )
                 .unwrap(),
@@ -719,6 +739,7 @@ fn unresolved_host_function_called() {
 
     for exec_hint in ExecHint::available_engines() {
         match HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: false,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -729,6 +750,7 @@ fn unresolved_host_function_called() {
         }
 
         let proto = HostVmPrototype::new(Config {
+            fuel_metering: false,
             allow_unresolved_imports: true,
             exec_hint,
             heap_pages: HeapPages::new(1024),
@@ -741,6 +763,7 @@ fn unresolved_host_function_called() {
                 .run(
                     "test",
                     StorageProofSizeBehavior::proof_recording_disabled(),
+                    None,
                     &[],
                 )
                 .unwrap(),
@@ -90,9 +90,27 @@ pub struct Config<'a, TParams> {
     /// >           and `5` for "trace".
     pub max_log_level: u32,
 
+    /// Maximum amount of fuel that the call can consume, or `None` for no limit. If the limit is
+    /// reached, the call ends with an [`host::Error::OutOfFuel`].
+    ///
+    /// Calls that are part of verifying or authoring a block should pass `None`, as the block
+    /// would otherwise be considered invalid depending on the local configuration.
+    pub fuel_limit: Option<u64>,
+
     /// If `true`, then [`StorageChanges::trie_changes_iter_ordered`] will return `Some`.
     /// Passing `None` requires fewer calculation and fewer storage accesses.
     pub calculate_trie_changes: bool,
+
+    /// If `true`, then the trie nodes of the main trie and of the child tries that the execution
+    /// accesses are recorded, and [`Success::storage_proof`] will contain `Some`.
+    ///
+    /// Once the execution is over, the trie nodes are requested from the API user through
+    /// [`RuntimeCall::StorageProofNodeValue`]. If `false`, this variant is never produced.
+    pub record_storage_proof: bool,
+
+    /// If `true`, then the storage accesses and logs performed by the runtime are recorded, and
+    /// [`Success::trace`] will contain `Some`.
+    pub record_trace: bool,
 }
 
 /// Start running the WebAssembly virtual machine.
@@ -112,6 +130,7 @@ pub fn run(
             .run_vectored(
                 config.function_to_call,
                 config.storage_proof_size_behavior,
+                config.fuel_limit,
                 config.parameter,
             )?
             .into(),
@@ -134,6 +153,16 @@ pub fn run(
         root_calculation: None,
         max_log_level: config.max_log_level,
         calculate_trie_changes: config.calculate_trie_changes,
+        storage_proof_recorder: if config.record_storage_proof {
+            Some(ProofRecorder::default())
+        } else {
+            None
+        },
+        trace: if config.record_trace {
+            Some(Vec::new())
+        } else {
+            None
+        },
     }
     .run())
 }
@@ -149,6 +178,110 @@ pub struct Success {
     /// State trie version indicated by the runtime. All the storage changes indicated by
     /// [`Success::storage_changes`] should store this version alongside with them.
     pub state_trie_version: TrieEntryVersion,
+    /// Trie nodes accessed during the execution. `Some` if and only if
+    /// [`Config::record_storage_proof`] was `true`.
+    pub storage_proof: Option<StorageProof>,
+    /// Storage accesses and logs performed by the runtime, in chronological order. `Some` if and
+    /// only if [`Config::record_trace`] was `true`.
+    pub trace: Option<Vec<TraceEvent>>,
+}
+
+/// Event that happened during the execution. See [`Success::trace`].
+///
+/// All the events are reported, including the ones that happen within a storage transaction
+/// that is later rolled back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// The runtime has read a storage value.
+    StorageGet {
+        /// Name of the child trie (without the `:child_storage:default:` prefix), or `None` for
+        /// the main trie.
+        child_trie: Option<Vec<u8>>,
+        /// Key that has been read.
+        key: Vec<u8>,
+        /// Value that has been read, or `None` if there is no storage value at this key.
+        value: Option<Vec<u8>>,
+    },
+    /// The runtime has written or removed a storage value.
+    StorageSet {
+        /// Name of the child trie, or `None` for the main trie.
+        child_trie: Option<Vec<u8>>,
+        /// Key that has been written.
+        key: Vec<u8>,
+        /// New value, or `None` if the value has been removed.
+        value: Option<Vec<u8>>,
+    },
+    /// The runtime has appended an item to a storage value.
+    StorageAppend {
+        /// Name of the child trie, or `None` for the main trie.
+        child_trie: Option<Vec<u8>>,
+        /// Key of the storage value.
+        key: Vec<u8>,
+        /// Item that has been appended.
+        value: Vec<u8>,
+    },
+    /// The runtime has removed all the storage values whose key starts with the given prefix.
+    StorageClearPrefix {
+        /// Name of the child trie, or `None` for the main trie.
+        child_trie: Option<Vec<u8>>,
+        /// Prefix of the keys to remove.
+        prefix: Vec<u8>,
+    },
+    /// The runtime has obtained the root hash of a trie.
+    StorageRoot {
+        /// Name of the child trie, or `None` for the main trie.
+        child_trie: Option<Vec<u8>>,
+        /// Hash that has been returned to the runtime.
+        root_hash: [u8; 32],
+    },
+    /// The runtime has emitted a log line.
+    Log(String),
+}
+
+/// Storage proof recorded during the execution. See [`Success::storage_proof`].
+///
+/// Contains all the trie nodes that are necessary in order to execute the same runtime call
+/// again using only the proof as the source of storage.
+pub struct StorageProof {
+    /// Nodes of the main trie.
+    pub main_trie: trie::proof_encode::ProofBuilder,
+    /// Nodes of each child trie that has been accessed, indexed by the name of the child trie
+    /// (without the `:child_storage:default:` prefix).
+    pub child_tries: BTreeMap<Vec<u8>, trie::proof_encode::ProofBuilder>,
+}
+
+impl StorageProof {
+    /// Builds the list of entries of the proof containing the nodes of all the tries.
+    pub fn build_entries(self) -> impl ExactSizeIterator<Item = Vec<u8>> {
+        // Collect the entries into a `HashSet` in order to de-duplicate them.
+        iter::once(self.main_trie)
+            .chain(self.child_tries.into_values())
+            .flat_map(|builder| builder.build_entries())
+            .collect::<hashbrown::HashSet<_, fnv::FnvBuildHasher>>()
+            .into_iter()
+    }
+
+    /// Builds the proof containing the nodes of all the tries.
+    pub fn build_to_vec(self) -> Vec<u8> {
+        let entries = self.build_entries();
+
+        let mut out = util::encode_scale_compact_usize(entries.len())
+            .as_ref()
+            .to_vec();
+        for entry in entries {
+            out.extend_from_slice(util::encode_scale_compact_usize(entry.len()).as_ref());
+            out.extend_from_slice(&entry);
+        }
+        out
+    }
+}
+
+impl fmt::Debug for StorageProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StorageProof")
+            .field("child_tries", &self.child_tries.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
 }
 
 /// See [`Success::storage_changes`].
@@ -521,6 +654,9 @@ pub enum RuntimeCall {
     OffchainStorageSet(OffchainStorageSet),
     /// Functions that can only be called within the context of an offchain worker.
     Offchain(OffchainContext),
+    /// Execution is over, and the node value of a trie node is required in order to build the
+    /// storage proof. Only happens if [`Config::record_storage_proof`] is `true`.
+    StorageProofNodeValue(StorageProofNodeValue),
 }
 
 impl RuntimeCall {
@@ -536,6 +672,7 @@ impl RuntimeCall {
             RuntimeCall::LogEmit(inner) => inner.inner.vm.into_prototype(),
             RuntimeCall::OffchainStorageSet(inner) => inner.inner.vm.into_prototype(),
             RuntimeCall::Offchain(inner) => inner.into_prototype(),
+            RuntimeCall::StorageProofNodeValue(inner) => inner.inner.vm.into_prototype(),
         }
     }
 }
@@ -657,8 +794,27 @@ impl StorageGet {
             (value, version)
         });
 
+        if let Some(mut recorder) = self.inner.storage_proof_recorder.take() {
+            let key = trie::bytes_to_nibbles(self.key().as_ref().iter().copied()).collect();
+            let child_trie = self.child_trie().map(|ct| ct.as_ref().to_vec());
+            recorder.record(
+                child_trie.as_deref(),
+                key,
+                value.as_ref().map(|(v, _)| &v[..]),
+            );
+            self.inner.storage_proof_recorder = Some(recorder);
+        }
+
         match (self.inner.vm, self.inner.root_calculation.take()) {
             (host::HostVm::ExternalStorageGet(req), None) => {
+                if let Some(trace) = &mut self.inner.trace {
+                    trace.push(TraceEvent::StorageGet {
+                        child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                        key: req.key().as_ref().to_vec(),
+                        value: value.as_ref().map(|(v, _)| v.clone()),
+                    });
+                }
+
                 // TODO: should actually report the offset and max_size in the API
                 self.inner.vm = req.resume_full_value(value.as_ref().map(|(v, _)| &v[..]));
             }
@@ -790,6 +946,20 @@ impl NextKey {
     /// Panics if the key passed as parameter doesn't start with the requested prefix.
     ///
     pub fn inject_key(mut self, key: Option<impl Iterator<Item = Nibble>>) -> RuntimeCall {
+        let key = key.map(|k| k.collect::<Vec<_>>());
+
+        if let Some(mut recorder) = self.inner.storage_proof_recorder.take() {
+            let requested_key = self.key().collect::<Vec<_>>();
+            let child_trie = self.child_trie().map(|ct| ct.as_ref().to_vec());
+            recorder.record(child_trie.as_deref(), requested_key, None);
+            if let Some(key) = &key {
+                recorder.record(child_trie.as_deref(), key.clone(), None);
+            }
+            self.inner.storage_proof_recorder = Some(recorder);
+        }
+
+        let key = key.map(|k| k.into_iter());
+
         match (self.inner.vm, self.inner.root_calculation.take()) {
             (host::HostVm::ExternalStorageNextKey(req), None) => {
                 let key =
@@ -933,6 +1103,14 @@ impl ClosestDescendantMerkleValue {
             unreachable!()
         };
 
+        if let Some(recorder) = self.inner.storage_proof_recorder.as_mut() {
+            recorder.record(
+                trie.as_deref(),
+                request.key().flat_map(util::as_ref_iter).collect(),
+                None,
+            );
+        }
+
         self.inner.root_calculation = Some((
             trie,
             match merkle_value {
@@ -948,6 +1126,59 @@ impl ClosestDescendantMerkleValue {
     }
 }
 
+/// Execution is over, and the node value of a trie node is required in order to build the
+/// storage proof.
+#[must_use]
+pub struct StorageProofNodeValue {
+    inner: Inner,
+    walk: ProofWalk,
+}
+
+impl StorageProofNodeValue {
+    /// Returns the key whose closest descendant's node value must be passed to
+    /// [`StorageProofNodeValue::inject_node_value`].
+    ///
+    /// The closest descendant of a key is the trie node whose key starts with the given key and
+    /// is the closest to it. This can be the node whose key is equal to the given key.
+    pub fn key(&'_ self) -> impl Iterator<Item = Nibble> + '_ {
+        self.walk.cursor.as_ref().unwrap().0.iter().copied()
+    }
+
+    /// If `Some`, read from the given child trie. If `None`, read from the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.walk.remaining_keys.last().unwrap().0.as_ref()
+    }
+
+    /// Returns the Merkle value of the requested node, if known.
+    ///
+    /// This is always `None` for the root node of a trie. For other nodes, this is always
+    /// `Some`, and can be used in order to load the node value from a database where node values
+    /// are indexed by their Merkle value.
+    pub fn merkle_value(&self) -> Option<&[u8]> {
+        self.walk.cursor.as_ref().unwrap().1.as_deref()
+    }
+
+    /// Injects the node value of the closest descendant of [`StorageProofNodeValue::key`].
+    ///
+    /// `None` can be passed if there is no descendant, or, in the case of a child trie, in order
+    /// to indicate that the child trie does not exist.
+    ///
+    /// The node value isn't verified against the Merkle value.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `node_value` isn't a valid trie node value.
+    ///
+    pub fn inject_node_value(mut self, node_value: Option<&[u8]>) -> RuntimeCall {
+        let (trie, _) = self.walk.remaining_keys.last().unwrap();
+        let (key, _) = self.walk.cursor.as_ref().unwrap();
+        self.walk
+            .node_values
+            .insert((trie.clone(), key.clone()), node_value.map(|v| v.to_vec()));
+        self.walk.advance(self.inner)
+    }
+}
+
 /// Verifying whether a signature is correct is required in order to continue.
 #[must_use]
 pub struct SignatureVerification {
@@ -1321,6 +1552,12 @@ struct Inner {
 
     /// See [`Config::calculate_trie_changes`].
     calculate_trie_changes: bool,
+
+    /// Keys accessed so far. `Some` if and only if [`Config::record_storage_proof`] is `true`.
+    storage_proof_recorder: Option<ProofRecorder>,
+
+    /// Events recorded so far. `Some` if and only if [`Config::record_trace`] is `true`.
+    trace: Option<Vec<TraceEvent>>,
 }
 
 /// See [`Inner::pending_storage_changes`].
@@ -1352,12 +1589,205 @@ enum PendingStorageChangesTrieNode {
     },
 }
 
+/// See [`Inner::storage_proof_recorder`].
+#[derive(Default)]
+struct ProofRecorder {
+    /// Keys that have been accessed, and the storage value that has been read at this key,
+    /// if any.
+    accessed_keys: BTreeMap<TrieNodeKey, Option<Vec<u8>>>,
+}
+
+/// Child trie (`None` for the main trie) and key of a trie node.
+type TrieNodeKey = (Option<Vec<u8>>, Vec<Nibble>);
+
+impl ProofRecorder {
+    /// Records an access to the given key of the given trie.
+    fn record(
+        &mut self,
+        child_trie: Option<&[u8]>,
+        key: Vec<Nibble>,
+        storage_value: Option<&[u8]>,
+    ) {
+        // Accessing a child trie requires proving the root hash of this child trie, which is
+        // stored in the main trie.
+        if let Some(child_trie) = child_trie {
+            let key_in_main_trie = trie::bytes_to_nibbles(
+                DEFAULT_CHILD_STORAGE_SPECIAL_PREFIX
+                    .iter()
+                    .chain(child_trie.iter())
+                    .copied(),
+            )
+            .collect();
+            self.accessed_keys
+                .entry((None, key_in_main_trie))
+                .or_insert(None);
+        }
+
+        let entry = self
+            .accessed_keys
+            .entry((child_trie.map(|ct| ct.to_vec()), key))
+            .or_insert(None);
+        if let Some(storage_value) = storage_value {
+            *entry = Some(storage_value.to_vec());
+        }
+    }
+}
+
+/// Generation of the storage proof, once the execution is over.
+///
+/// For each key that has been accessed, the trie is walked down from its root towards this key,
+/// and every node on the path is added to the proof.
+struct ProofWalk {
+    /// Keys whose path must be added to the proof. The last element is the one currently being
+    /// walked towards.
+    remaining_keys: Vec<TrieNodeKey>,
+
+    /// Storage values read during the execution. Used in order to provide the unhashed storage
+    /// value of nodes whose storage value is hashed.
+    storage_values: hashbrown::HashMap<TrieNodeKey, Vec<u8>, fnv::FnvBuildHasher>,
+
+    /// Node values provided by the API user, indexed by trie and by the key whose closest
+    /// descendant they are the node value of. Since the paths towards the various keys overlap,
+    /// this avoids requesting the same node multiple times.
+    node_values: hashbrown::HashMap<TrieNodeKey, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// Progress of the walk towards the last element of [`ProofWalk::remaining_keys`]. Contains
+    /// the key whose closest descendant is the next node to add to the proof, and the Merkle
+    /// value of this node if known.
+    cursor: Option<(Vec<Nibble>, Option<Vec<u8>>)>,
+
+    /// Proof being built.
+    storage_proof: StorageProof,
+}
+
+impl ProofWalk {
+    fn new(recorder: ProofRecorder) -> Self {
+        let mut remaining_keys = Vec::new();
+        let mut storage_values = hashbrown::HashMap::default();
+        for (trie_node_key, storage_value) in recorder.accessed_keys {
+            if let Some(storage_value) = storage_value {
+                storage_values.insert(trie_node_key.clone(), storage_value);
+            }
+            remaining_keys.push(trie_node_key);
+        }
+
+        ProofWalk {
+            remaining_keys,
+            storage_values,
+            node_values: hashbrown::HashMap::default(),
+            cursor: None,
+            storage_proof: StorageProof {
+                main_trie: trie::proof_encode::ProofBuilder::new(),
+                child_tries: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// Continues the walk, and returns either a request for a node value or the final outcome.
+    fn advance(mut self, inner: Inner) -> RuntimeCall {
+        loop {
+            let Some((trie, target_key)) = self.remaining_keys.last() else {
+                return inner.finish(Some(self.storage_proof));
+            };
+
+            let (cursor_key, cursor_merkle_value) =
+                self.cursor.get_or_insert_with(|| (Vec::new(), None));
+
+            // Merkle values shorter than 32 bytes are the node value itself.
+            let node_value = match cursor_merkle_value {
+                Some(merkle_value) if merkle_value.len() < 32 => Some(merkle_value.clone()),
+                _ => match self.node_values.get(&(trie.clone(), cursor_key.clone())) {
+                    Some(node_value) => node_value.clone(),
+                    None => {
+                        return RuntimeCall::StorageProofNodeValue(StorageProofNodeValue {
+                            inner,
+                            walk: self,
+                        })
+                    }
+                },
+            };
+
+            let Some(node_value) = node_value else {
+                // There is no node on the path towards the key.
+                self.remaining_keys.pop();
+                self.cursor = None;
+                continue;
+            };
+
+            let decoded = trie::trie_node::decode(&node_value)
+                .unwrap_or_else(|err| panic!("invalid node value: {err:?}"));
+            let mut node_key = cursor_key.clone();
+            node_key.extend(decoded.partial_key.clone());
+
+            let unhashed_storage_value = match decoded.storage_value {
+                trie::trie_node::StorageValue::Hashed(_) => self
+                    .storage_values
+                    .get(&(trie.clone(), node_key.clone()))
+                    .map(|v| &v[..]),
+                _ => None,
+            };
+
+            let builder = match trie {
+                None => &mut self.storage_proof.main_trie,
+                Some(child_trie) => self
+                    .storage_proof
+                    .child_tries
+                    .entry(child_trie.clone())
+                    .or_default(),
+            };
+            builder.set_node_value(&node_key, &node_value, unhashed_storage_value);
+
+            // Stop if the node is the closest descendant of the key, or if the node diverges
+            // from the key, as this proves the absence of a storage value.
+            let next_child =
+                if node_key.starts_with(target_key) || !target_key.starts_with(&node_key) {
+                    None
+                } else {
+                    let child_index = target_key[node_key.len()];
+                    decoded.children[usize::from(u8::from(child_index))]
+                        .map(|child| (child_index, child.to_vec()))
+                };
+
+            match next_child {
+                Some((child_index, child_merkle_value)) => {
+                    node_key.push(child_index);
+                    self.cursor = Some((node_key, Some(child_merkle_value)));
+                }
+                None => {
+                    self.remaining_keys.pop();
+                    self.cursor = None;
+                }
+            }
+        }
+    }
+}
+
 /// Writing and reading keys the main trie under this prefix obeys special rules.
 const CHILD_STORAGE_SPECIAL_PREFIX: &[u8] = b":child_storage:";
 /// Writing and reading keys the main trie under this prefix obeys special rules.
 const DEFAULT_CHILD_STORAGE_SPECIAL_PREFIX: &[u8] = b":child_storage:default:";
 
 impl Inner {
+    /// Builds the final outcome of a successful execution.
+    ///
+    /// Must only be called if the virtual machine is in the finished state.
+    fn finish(self, storage_proof: Option<StorageProof>) -> RuntimeCall {
+        let host::HostVm::Finished(finished) = self.vm else {
+            unreachable!()
+        };
+
+        RuntimeCall::Finished(Ok(Success {
+            virtual_machine: SuccessVirtualMachine(finished),
+            storage_changes: StorageChanges {
+                inner: self.pending_storage_changes,
+                calculate_trie_changes: self.calculate_trie_changes,
+            },
+            state_trie_version: self.state_trie_version,
+            storage_proof,
+            trace: self.trace,
+        }))
+    }
+
     /// Continues the execution.
     fn run(mut self) -> RuntimeCall {
         loop {
@@ -1476,6 +1906,12 @@ impl Inner {
                             _ => false,
                         };
                         if trie_match {
+                            if let Some(trace) = &mut self.trace {
+                                trace.push(TraceEvent::StorageRoot {
+                                    child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                                    root_hash: trie_root_hash,
+                                });
+                            }
                             self.vm = req.resume(&trie_root_hash);
                         } else {
                             self.vm = host::HostVm::ExternalStorageRoot(req);
@@ -1618,14 +2054,11 @@ impl Inner {
                     );
                     debug_assert!(self.offchain_storage_changes.is_empty());
 
-                    return RuntimeCall::Finished(Ok(Success {
-                        virtual_machine: SuccessVirtualMachine(finished),
-                        storage_changes: StorageChanges {
-                            inner: self.pending_storage_changes,
-                            calculate_trie_changes: self.calculate_trie_changes,
-                        },
-                        state_trie_version: self.state_trie_version,
-                    }));
+                    self.vm = host::HostVm::Finished(finished);
+                    return match self.storage_proof_recorder.take() {
+                        Some(recorder) => ProofWalk::new(recorder).advance(self),
+                        None => self.finish(None),
+                    };
                 }
 
                 host::HostVm::ExternalStorageGet(req) => {
@@ -1636,6 +2069,13 @@ impl Inner {
                         .and_then(|diff| diff.diff_get(req.key().as_ref()));
 
                     if let Some((value_in_diff, _)) = diff_search {
+                        if let Some(trace) = &mut self.trace {
+                            trace.push(TraceEvent::StorageGet {
+                                child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                                key: req.key().as_ref().to_vec(),
+                                value: value_in_diff.map(|v| v.to_vec()),
+                            });
+                        }
                         self.vm = req.resume_full_value(value_in_diff);
                     } else {
                         self.vm = req.into();
@@ -1670,6 +2110,14 @@ impl Inner {
                         trie.diff_insert_erase(req.key().as_ref(), ());
                     }
 
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(TraceEvent::StorageSet {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            key: req.key().as_ref().to_vec(),
+                            value: req.value().map(|v| v.as_ref().to_vec()),
+                        });
+                    }
+
                     self.vm = req.resume()
                 }
 
@@ -1694,6 +2142,14 @@ impl Inner {
                         .entry(req.child_trie().map(|ct| ct.as_ref().to_vec()))
                         .or_insert(storage_diff::TrieDiff::empty());
 
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(TraceEvent::StorageAppend {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            key: req.key().as_ref().to_vec(),
+                            value: req.value().as_ref().to_vec(),
+                        });
+                    }
+
                     let current_value = trie.diff_get(req.key().as_ref()).map(|(v, _)| v);
 
                     if let Some(current_value) = current_value {
@@ -1724,6 +2180,12 @@ impl Inner {
                         .insert(req.child_trie().map(|ct| ct.as_ref().to_owned()));
 
                     let prefix = req.prefix().as_ref().to_owned();
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(TraceEvent::StorageClearPrefix {
+                            child_trie: req.child_trie().map(|ct| ct.as_ref().to_vec()),
+                            prefix: prefix.clone(),
+                        });
+                    }
                     self.vm = req.into();
                     return RuntimeCall::NextKey(NextKey {
                         inner: self,
@@ -1797,6 +2259,7 @@ impl Inner {
                         module: req.wasm_code(),
                         heap_pages: executor::DEFAULT_HEAP_PAGES,
                         exec_hint: vm::ExecHint::ValidateAndExecuteOnce,
+                        fuel_metering: false,
                         allow_unresolved_imports: false, // TODO: what is a correct value here?
                     }) {
                         Ok(w) => w,
@@ -1834,6 +2297,16 @@ impl Inner {
                 }
 
                 host::HostVm::LogEmit(req) => {
+                    if let Some(trace) = &mut self.trace {
+                        trace.push(TraceEvent::Log(match req.info() {
+                            LogEmitInfo::Num(num) => num.to_string(),
+                            LogEmitInfo::Utf8(str) => str.to_string(),
+                            LogEmitInfo::Hex(data) => data.to_string(),
+                            LogEmitInfo::Log {
+                                target, message, ..
+                            } => format!("{target}: {message}"),
+                        }));
+                    }
                     self.vm = req.into();
                     return RuntimeCall::LogEmit(LogEmit { inner: self });
                 }
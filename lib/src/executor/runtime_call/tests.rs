@@ -25,168 +25,497 @@
 
 use core::{iter, ops};
 
-use super::{run, Config, RuntimeCall, StorageProofSizeBehavior};
+use super::{run, Config, RuntimeCall, StorageProofSizeBehavior, TraceEvent};
 use crate::{executor::host, trie};
 use alloc::collections::BTreeMap;
 
+const TEST_FIXTURES: [&str; 4] = [
+    // Tests ordered alphabetically.
+    include_str!("./child-trie-create-multiple.json"),
+    include_str!("./child-trie-create-one.json"),
+    include_str!("./child-trie-destroy.json"),
+    include_str!("./child-trie-read-basic.json"),
+    // TODO: more tests?
+];
+
 #[test]
 fn execute_blocks() {
-    // Tests ordered alphabetically.
-    for (test_num, test_json) in [
-        include_str!("./child-trie-create-multiple.json"),
-        include_str!("./child-trie-create-one.json"),
-        include_str!("./child-trie-destroy.json"),
-        include_str!("./child-trie-read-basic.json"),
-        // TODO: more tests?
-    ]
-    .into_iter()
-    .enumerate()
-    {
-        // Decode the test JSON.
-        let test_data = serde_json::from_str::<Test>(test_json).unwrap();
-
-        // Turn the nice-looking data into something with better access times.
-        let storage = {
-            let mut storage = test_data
-                .parent_storage
-                .main_trie
-                .iter()
-                .map(|(key, value)| ((None, key.0.clone()), value.0.clone()))
-                .collect::<BTreeMap<_, _>>();
-            for (child_trie, child_trie_data) in &test_data.parent_storage.child_tries {
-                for (key, value) in child_trie_data {
-                    storage.insert((Some(child_trie.0.clone()), key.0.clone()), value.0.clone());
-                }
-            }
-            storage
-        };
-
-        // Build the runtime.
-        let virtual_machine = {
-            let code = storage
-                .get(&(None, b":code".to_vec()))
-                .expect("no runtime code found");
-            let heap_pages = crate::executor::storage_heap_pages_to_value(
-                storage.get(&(None, b":heappages".to_vec())).map(|v| &v[..]),
-            )
-            .unwrap();
+    for (test_num, test_json) in TEST_FIXTURES.into_iter().enumerate() {
+        let outcome = execute_block(test_num, test_json, false, false);
+        assert!(outcome.storage_proof.is_none());
+        assert!(outcome.trace.is_none());
+    }
+}
 
-            host::HostVmPrototype::new(host::Config {
-                module: code,
-                heap_pages,
-                exec_hint: crate::executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
-                allow_unresolved_imports: false,
-            })
-            .unwrap()
-        };
-
-        // The runtime indicates the version of the trie items of the parent storage.
-        // While in principle each storage item could have a different version, in practice we
-        // just assume they're all the same.
-        let state_version = virtual_machine
-            .runtime_version()
-            .decode()
-            .state_version
-            .unwrap_or(host::TrieEntryVersion::V0);
-
-        // Start executing `Core_execute_block`. This runtime call will verify at the end whether
-        // the trie root hash of the block matches the one calculated by smoldot.
-        let mut execution = run(Config {
-            virtual_machine,
-            function_to_call: "Core_execute_block",
-            max_log_level: 3,
-            storage_proof_size_behavior: StorageProofSizeBehavior::Unimplemented,
-            storage_main_trie_changes: Default::default(),
-            calculate_trie_changes: false,
-            parameter: {
-                // Block header + number of extrinsics + extrinsics
-                let encoded_body_len =
-                    crate::util::encode_scale_compact_usize(test_data.block.body.len());
-                iter::once(either::Right(either::Left(&test_data.block.header.0)))
-                    .chain(iter::once(either::Right(either::Right(encoded_body_len))))
-                    .chain(test_data.block.body.iter().map(|b| either::Left(&b.0)))
-            },
+#[test]
+fn record_storage_proof() {
+    for (test_num, test_json) in TEST_FIXTURES.into_iter().enumerate() {
+        let outcome = execute_block(test_num, test_json, true, false);
+
+        // The trie root hashes are calculated from the parent storage, rather than taken from
+        // the proof, so that the proof is verified against the actual state of the parent.
+        let trie_roots = parent_trie_roots(&outcome);
+
+        let storage_proof = outcome.storage_proof.unwrap();
+        assert_eq!(
+            storage_proof.main_trie.trie_root_hash(),
+            Some(trie_roots[&None]),
+            "test #{test_num}"
+        );
+
+        let proof = trie::proof_decode::decode_and_verify_proof(trie::proof_decode::Config {
+            proof: storage_proof.build_to_vec(),
         })
         .unwrap();
 
-        loop {
-            match execution {
-                RuntimeCall::Finished(Ok(_)) => break, // Test successful!
-                RuntimeCall::Finished(Err(err)) => {
-                    panic!("Error during test #{}: {:?}", test_num, err)
+        // Every storage value that has been read during the execution must be found in the
+        // proof.
+        for (child_trie, key) in outcome.storage_reads {
+            let Some(trie_root) = trie_roots.get(&child_trie) else {
+                // Child trie doesn't exist. Its absence must be provable from the main trie.
+                let mut key_in_main_trie = b":child_storage:default:".to_vec();
+                key_in_main_trie.extend_from_slice(child_trie.as_ref().unwrap());
+                assert!(proof
+                    .storage_value(&trie_roots[&None], &key_in_main_trie)
+                    .unwrap()
+                    .is_none());
+                continue;
+            };
+
+            let expected = outcome.storage.get(&(child_trie, key.clone()));
+            let in_proof = proof.storage_value(trie_root, &key).unwrap();
+            assert_eq!(
+                in_proof.map(|(value, _)| value),
+                expected.map(|v| &v[..]),
+                "test #{test_num}"
+            );
+        }
+
+        // Every next key request must yield the same result when answered from the proof.
+        for next_key in outcome.next_key_reads {
+            let Some(trie_root) = trie_roots.get(&next_key.child_trie) else {
+                assert!(next_key.result.is_none());
+                continue;
+            };
+
+            let in_proof = proof
+                .next_key(
+                    trie_root,
+                    next_key.key_before.iter().copied(),
+                    next_key.or_equal,
+                    next_key.prefix.iter().copied(),
+                    next_key.branch_nodes,
+                )
+                .unwrap()
+                .map(|key| key.collect::<Vec<_>>());
+            assert_eq!(in_proof, next_key.result, "test #{test_num}");
+        }
+    }
+}
+
+#[test]
+fn record_trace() {
+    for (test_num, test_json) in TEST_FIXTURES.into_iter().enumerate() {
+        let outcome = execute_block(test_num, test_json, false, true);
+        let trace = outcome.trace.unwrap();
+
+        // All the test fixtures access child tries.
+        assert!(trace.iter().any(|event| matches!(
+            event,
+            TraceEvent::StorageGet {
+                child_trie: Some(_),
+                ..
+            } | TraceEvent::StorageSet {
+                child_trie: Some(_),
+                ..
+            } | TraceEvent::StorageClearPrefix {
+                child_trie: Some(_),
+                ..
+            }
+        )));
+
+        // Reading a value that hasn't been modified during the execution must yield the value
+        // found in the parent storage.
+        let mut modified = Vec::new();
+        for event in &trace {
+            match event {
+                TraceEvent::StorageGet {
+                    child_trie,
+                    key,
+                    value,
+                } => {
+                    if !modified.iter().any(|(ct, prefix): &(_, Vec<u8>)| {
+                        ct == child_trie && key.starts_with(prefix)
+                    }) {
+                        assert_eq!(
+                            value.as_ref(),
+                            outcome.storage.get(&(child_trie.clone(), key.clone())),
+                            "test #{test_num}"
+                        );
+                    }
                 }
-                RuntimeCall::SignatureVerification(sig) => execution = sig.verify_and_resume(),
-                RuntimeCall::ClosestDescendantMerkleValue(req) => execution = req.resume_unknown(),
-                RuntimeCall::StorageGet(get) => {
-                    let value = storage
-                        .get(&(
-                            get.child_trie().map(|c| c.as_ref().to_owned()),
-                            get.key().as_ref().to_owned(),
-                        ))
-                        .map(|v| (iter::once(&v[..]), state_version));
-                    execution = get.inject_value(value);
+                TraceEvent::StorageSet {
+                    child_trie, key, ..
                 }
-                RuntimeCall::NextKey(req) => {
-                    // Because `NextKey` might ask for branch nodes, and that we don't build the
-                    // trie in its entirety, we have to use an algorithm that finds the branch
-                    // nodes for us.
-                    let next_key = {
-                        let mut search = trie::branch_search::BranchSearch::NextKey(
-                            trie::branch_search::start_branch_search(trie::branch_search::Config {
-                                key_before: req.key().collect::<Vec<_>>().into_iter(),
-                                or_equal: req.or_equal(),
-                                prefix: req.prefix().collect::<Vec<_>>().into_iter(),
-                                no_branch_search: !req.branch_nodes(),
-                            }),
-                        );
+                | TraceEvent::StorageAppend {
+                    child_trie, key, ..
+                } => modified.push((child_trie.clone(), key.clone())),
+                TraceEvent::StorageClearPrefix { child_trie, prefix } => {
+                    modified.push((child_trie.clone(), prefix.clone()))
+                }
+                TraceEvent::StorageRoot { .. } | TraceEvent::Log(_) => {}
+            }
+        }
+
+        // Tracing doesn't modify the outcome of the execution.
+        assert_eq!(
+            outcome.storage_reads,
+            execute_block(test_num, test_json, false, false).storage_reads
+        );
+    }
+}
+
+/// Outcome of [`execute_block`].
+struct TestOutcome {
+    /// Storage of the parent of the block.
+    storage: BTreeMap<(Option<Vec<u8>>, Vec<u8>), Vec<u8>>,
+    /// State trie version of the parent storage.
+    state_version: host::TrieEntryVersion,
+    /// List of child tries and keys that have been read during the execution.
+    storage_reads: Vec<(Option<Vec<u8>>, Vec<u8>)>,
+    /// List of next key requests that have been answered during the execution.
+    next_key_reads: Vec<NextKeyRead>,
+    /// See [`super::Success::storage_proof`].
+    storage_proof: Option<super::StorageProof>,
+    /// See [`super::Success::trace`].
+    trace: Option<Vec<TraceEvent>>,
+}
+
+/// See [`TestOutcome::next_key_reads`].
+struct NextKeyRead {
+    child_trie: Option<Vec<u8>>,
+    key_before: Vec<trie::Nibble>,
+    or_equal: bool,
+    prefix: Vec<trie::Nibble>,
+    branch_nodes: bool,
+    result: Option<Vec<trie::Nibble>>,
+}
+
+/// Executes the block of the given test fixture on top of the storage of its parent.
+fn execute_block(
+    test_num: usize,
+    test_json: &str,
+    record_storage_proof: bool,
+    record_trace: bool,
+) -> TestOutcome {
+    // Decode the test JSON.
+    let test_data = serde_json::from_str::<Test>(test_json).unwrap();
+
+    // Turn the nice-looking data into something with better access times.
+    let storage = {
+        let mut storage = test_data
+            .parent_storage
+            .main_trie
+            .iter()
+            .map(|(key, value)| ((None, key.0.clone()), value.0.clone()))
+            .collect::<BTreeMap<_, _>>();
+        for (child_trie, child_trie_data) in &test_data.parent_storage.child_tries {
+            for (key, value) in child_trie_data {
+                storage.insert((Some(child_trie.0.clone()), key.0.clone()), value.0.clone());
+            }
+        }
+        storage
+    };
 
-                        loop {
-                            match search {
-                                trie::branch_search::BranchSearch::Found {
-                                    branch_trie_node_key,
-                                } => break branch_trie_node_key,
-                                trie::branch_search::BranchSearch::NextKey(bs_req) => {
-                                    let result = storage
-                                        .range((
-                                            if bs_req.or_equal() {
-                                                ops::Bound::Included((
-                                                    req.child_trie().map(|c| c.as_ref().to_owned()),
-                                                    bs_req.key_before().collect::<Vec<_>>(),
-                                                ))
-                                            } else {
-                                                ops::Bound::Excluded((
-                                                    req.child_trie().map(|c| c.as_ref().to_owned()),
-                                                    bs_req.key_before().collect::<Vec<_>>(),
-                                                ))
-                                            },
-                                            ops::Bound::Unbounded,
-                                        ))
-                                        .next()
-                                        .filter(|((trie, key), _)| {
-                                            *trie == req.child_trie().map(|c| c.as_ref().to_owned())
-                                                && key.starts_with(
-                                                    &bs_req.prefix().collect::<Vec<_>>(),
-                                                )
-                                        })
-                                        .map(|((_, k), _)| k);
-
-                                    search = bs_req.inject(result.map(|k| k.iter().copied()));
-                                }
+    // Build the runtime.
+    let virtual_machine = {
+        let code = storage
+            .get(&(None, b":code".to_vec()))
+            .expect("no runtime code found");
+        let heap_pages = crate::executor::storage_heap_pages_to_value(
+            storage.get(&(None, b":heappages".to_vec())).map(|v| &v[..]),
+        )
+        .unwrap();
+
+        host::HostVmPrototype::new(host::Config {
+            module: code,
+            heap_pages,
+            exec_hint: crate::executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
+            fuel_metering: false,
+            allow_unresolved_imports: false,
+        })
+        .unwrap()
+    };
+
+    // The runtime indicates the version of the trie items of the parent storage.
+    // While in principle each storage item could have a different version, in practice we
+    // just assume they're all the same.
+    let state_version = virtual_machine
+        .runtime_version()
+        .decode()
+        .state_version
+        .unwrap_or(host::TrieEntryVersion::V0);
+
+    // Start executing `Core_execute_block`. This runtime call will verify at the end whether
+    // the trie root hash of the block matches the one calculated by smoldot.
+    let node_values = if record_storage_proof {
+        trie_node_values(&storage, state_version)
+    } else {
+        BTreeMap::new()
+    };
+    let mut storage_reads = Vec::new();
+    let mut next_key_reads = Vec::new();
+
+    let mut execution = run(Config {
+        virtual_machine,
+        function_to_call: "Core_execute_block",
+        max_log_level: 3,
+        fuel_limit: None,
+        storage_proof_size_behavior: StorageProofSizeBehavior::Unimplemented,
+        storage_main_trie_changes: Default::default(),
+        calculate_trie_changes: false,
+        record_storage_proof,
+        record_trace,
+        parameter: {
+            // Block header + number of extrinsics + extrinsics
+            let encoded_body_len =
+                crate::util::encode_scale_compact_usize(test_data.block.body.len());
+            iter::once(either::Right(either::Left(&test_data.block.header.0)))
+                .chain(iter::once(either::Right(either::Right(encoded_body_len))))
+                .chain(test_data.block.body.iter().map(|b| either::Left(&b.0)))
+        },
+    })
+    .unwrap();
+
+    loop {
+        match execution {
+            RuntimeCall::Finished(Ok(success)) => {
+                // Test successful!
+                return TestOutcome {
+                    storage,
+                    state_version,
+                    storage_reads,
+                    next_key_reads,
+                    storage_proof: success.storage_proof,
+                    trace: success.trace,
+                };
+            }
+            RuntimeCall::Finished(Err(err)) => {
+                panic!("Error during test #{}: {:?}", test_num, err)
+            }
+            RuntimeCall::SignatureVerification(sig) => execution = sig.verify_and_resume(),
+            RuntimeCall::ClosestDescendantMerkleValue(req) => execution = req.resume_unknown(),
+            RuntimeCall::StorageGet(get) => {
+                let value = storage
+                    .get(&(
+                        get.child_trie().map(|c| c.as_ref().to_owned()),
+                        get.key().as_ref().to_owned(),
+                    ))
+                    .map(|v| (iter::once(&v[..]), state_version));
+                storage_reads.push((
+                    get.child_trie().map(|c| c.as_ref().to_owned()),
+                    get.key().as_ref().to_owned(),
+                ));
+                execution = get.inject_value(value);
+            }
+            RuntimeCall::NextKey(req) => {
+                // Because `NextKey` might ask for branch nodes, and that we don't build the
+                // trie in its entirety, we have to use an algorithm that finds the branch
+                // nodes for us.
+                let next_key = {
+                    let mut search = trie::branch_search::BranchSearch::NextKey(
+                        trie::branch_search::start_branch_search(trie::branch_search::Config {
+                            key_before: req.key().collect::<Vec<_>>().into_iter(),
+                            or_equal: req.or_equal(),
+                            prefix: req.prefix().collect::<Vec<_>>().into_iter(),
+                            no_branch_search: !req.branch_nodes(),
+                        }),
+                    );
+
+                    loop {
+                        match search {
+                            trie::branch_search::BranchSearch::Found {
+                                branch_trie_node_key,
+                            } => break branch_trie_node_key.map(|k| k.collect::<Vec<_>>()),
+                            trie::branch_search::BranchSearch::NextKey(bs_req) => {
+                                let result = storage
+                                    .range((
+                                        if bs_req.or_equal() {
+                                            ops::Bound::Included((
+                                                req.child_trie().map(|c| c.as_ref().to_owned()),
+                                                bs_req.key_before().collect::<Vec<_>>(),
+                                            ))
+                                        } else {
+                                            ops::Bound::Excluded((
+                                                req.child_trie().map(|c| c.as_ref().to_owned()),
+                                                bs_req.key_before().collect::<Vec<_>>(),
+                                            ))
+                                        },
+                                        ops::Bound::Unbounded,
+                                    ))
+                                    .next()
+                                    .filter(|((trie, key), _)| {
+                                        *trie == req.child_trie().map(|c| c.as_ref().to_owned())
+                                            && key.starts_with(&bs_req.prefix().collect::<Vec<_>>())
+                                    })
+                                    .map(|((_, k), _)| k);
+
+                                search = bs_req.inject(result.map(|k| k.iter().copied()));
                             }
                         }
-                    };
+                    }
+                };
 
-                    execution = req.inject_key(next_key.map(|nk| nk.into_iter()));
-                }
-                RuntimeCall::LogEmit(log) => execution = log.resume(),
-                RuntimeCall::OffchainStorageSet(_) | RuntimeCall::Offchain(_) => {
-                    unimplemented!()
-                }
+                next_key_reads.push(NextKeyRead {
+                    child_trie: req.child_trie().map(|c| c.as_ref().to_owned()),
+                    key_before: req.key().collect(),
+                    or_equal: req.or_equal(),
+                    prefix: req.prefix().collect(),
+                    branch_nodes: req.branch_nodes(),
+                    result: next_key.clone(),
+                });
+                execution = req.inject_key(next_key.map(|nk| nk.into_iter()));
+            }
+            RuntimeCall::LogEmit(log) => execution = log.resume(),
+            RuntimeCall::StorageProofNodeValue(req) => {
+                let trie = req.child_trie().map(|c| c.as_ref().to_owned());
+                let key = req.key().collect::<Vec<_>>();
+                let node_value = node_values
+                    .range((trie.clone(), key.clone())..)
+                    .next()
+                    .filter(|((t, k), _)| *t == trie && k.starts_with(&key))
+                    .map(|(_, node_value)| node_value.clone());
+                execution = req.inject_node_value(node_value.as_deref());
+            }
+            RuntimeCall::OffchainStorageSet(_) | RuntimeCall::Offchain(_) => {
+                unimplemented!()
             }
         }
     }
 }
 
+/// Calculates the root hash of the main trie and of every child trie found in the parent storage
+/// of the given outcome.
+///
+/// The returned map is indexed by child trie (`None` for the main trie).
+fn parent_trie_roots(outcome: &TestOutcome) -> BTreeMap<Option<Vec<u8>>, [u8; 32]> {
+    let mut entries = BTreeMap::<_, Vec<_>>::new();
+    entries.insert(None, Vec::new());
+    for ((child_trie, key), value) in &outcome.storage {
+        entries
+            .entry(child_trie.clone())
+            .or_default()
+            .push((&key[..], &value[..]));
+    }
+
+    let roots = entries
+        .into_iter()
+        .map(|(child_trie, entries)| {
+            let root = trie::trie_root(outcome.state_version, trie::HashFunction::Blake2, &entries);
+            (child_trie, root)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    // The root of each child trie must match the one stored in the main trie.
+    for (child_trie, root) in &roots {
+        if let Some(child_trie) = child_trie {
+            let mut key_in_main_trie = b":child_storage:default:".to_vec();
+            key_in_main_trie.extend_from_slice(child_trie);
+            assert_eq!(
+                outcome
+                    .storage
+                    .get(&(None, key_in_main_trie))
+                    .map(|v| &v[..]),
+                Some(&root[..])
+            );
+        }
+    }
+
+    roots
+}
+
+/// Builds the node value of every node of every trie found in the given storage.
+///
+/// The returned map is indexed by child trie (`None` for the main trie) and by node key.
+fn trie_node_values(
+    storage: &BTreeMap<(Option<Vec<u8>>, Vec<u8>), Vec<u8>>,
+    state_version: host::TrieEntryVersion,
+) -> BTreeMap<(Option<Vec<u8>>, Vec<trie::Nibble>), Vec<u8>> {
+    let mut tries = BTreeMap::<_, trie::trie_structure::TrieStructure<Option<&[u8]>>>::new();
+    for ((child_trie, key), value) in storage {
+        let trie = tries.entry(child_trie.clone()).or_default();
+        match trie.node(trie::bytes_to_nibbles(key.iter().copied())) {
+            trie::trie_structure::Entry::Vacant(e) => {
+                e.insert_storage_value().insert(Some(&value[..]), None);
+            }
+            trie::trie_structure::Entry::Occupied(trie::trie_structure::NodeAccess::Branch(e)) => {
+                *e.insert_storage_value().user_data() = Some(&value[..]);
+            }
+            trie::trie_structure::Entry::Occupied(trie::trie_structure::NodeAccess::Storage(_)) => {
+                unreachable!()
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    for (child_trie, mut trie) in tries {
+        // Node values are first built with dummy children, then `make_coherent` calculates the
+        // actual Merkle values of the children.
+        let mut proof_builder = trie::proof_encode::ProofBuilder::new();
+        for node_index in trie.iter_unordered().collect::<Vec<_>>() {
+            let key = trie
+                .node_full_key_by_index(node_index)
+                .unwrap()
+                .collect::<Vec<_>>();
+            let mut node = trie.node_by_index(node_index).unwrap();
+            let storage_value = *node.user_data();
+            let storage_value_hash =
+                storage_value.map(|v| blake2_rfc::blake2b::blake2b(32, &[], v));
+            let node_value = trie::trie_node::encode_to_vec(trie::trie_node::Decoded {
+                children: core::array::from_fn(|nibble| {
+                    let nibble = trie::Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+                    node.child_user_data(nibble).map(|_| &[][..])
+                }),
+                partial_key: node.partial_key().collect::<Vec<_>>().into_iter(),
+                storage_value: match (storage_value, &storage_value_hash) {
+                    (Some(v), Some(hash))
+                        if state_version == host::TrieEntryVersion::V1 && v.len() >= 33 =>
+                    {
+                        trie::trie_node::StorageValue::Hashed(
+                            <&[u8; 32]>::try_from(hash.as_bytes()).unwrap(),
+                        )
+                    }
+                    (Some(v), _) => trie::trie_node::StorageValue::Unhashed(v),
+                    (None, _) => trie::trie_node::StorageValue::None,
+                },
+            })
+            .unwrap();
+            let is_hashed = matches!(
+                trie::trie_node::decode(&node_value).unwrap().storage_value,
+                trie::trie_node::StorageValue::Hashed(_)
+            );
+            proof_builder.set_node_value(
+                &key,
+                &node_value,
+                if is_hashed { storage_value } else { None },
+            );
+        }
+        proof_builder.make_coherent();
+
+        let proof = trie::proof_decode::decode_and_verify_proof(trie::proof_decode::Config {
+            proof: proof_builder.build_to_vec(),
+        })
+        .unwrap();
+        for (entry_key, entry) in proof.iter_ordered() {
+            out.insert(
+                (child_trie.clone(), entry_key.key.collect::<Vec<_>>()),
+                entry.node_value.to_vec(),
+            );
+        }
+    }
+    out
+}
+
 // Serde structs used to decode the test fixtures.
 
 #[derive(serde::Deserialize)]
@@ -97,6 +97,13 @@ pub struct Config<'a> {
     /// Hint about how to execute the WebAssembly code.
     pub exec_hint: ExecHint,
 
+    /// If `true`, the virtual machine counts the amount of fuel consumed during the execution,
+    /// which is necessary in order to pass a fuel limit to [`Prepare::start`].
+    ///
+    /// Fuel metering slows down the execution. If `false`, passing a fuel limit to
+    /// [`Prepare::start`] returns [`StartErr::FuelMeteringDisabled`].
+    pub fuel_metering: bool,
+
     /// Called for each import that the module has. It must assign a number to each import, or
     /// return an error if the import can't be resolved. When the VM calls one of these functions,
     /// this number will be returned back in order for the user to know how to handle the call.
@@ -157,9 +164,13 @@ impl VirtualMachinePrototype {
                     ),
                     feature = "wasmtime"
                 ))]
-                ExecHint::ValidateAndCompile => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(config.module_bytes, config.symbols)?,
-                ),
+                ExecHint::ValidateAndCompile => {
+                    VirtualMachinePrototypeInner::Jit(jit::JitPrototype::new(
+                        config.module_bytes,
+                        config.fuel_metering,
+                        config.symbols,
+                    )?)
+                }
                 #[cfg(not(all(
                     any(
                         all(
@@ -179,6 +190,7 @@ impl VirtualMachinePrototype {
                     interpreter::InterpreterPrototype::new(
                         config.module_bytes,
                         interpreter::CompilationMode::Eager,
+                        config.fuel_metering,
                         config.symbols,
                     )?,
                 ),
@@ -187,6 +199,7 @@ impl VirtualMachinePrototype {
                         interpreter::InterpreterPrototype::new(
                             config.module_bytes,
                             interpreter::CompilationMode::Eager,
+                            config.fuel_metering,
                             config.symbols,
                         )?,
                     )
@@ -197,6 +210,7 @@ impl VirtualMachinePrototype {
                         interpreter::InterpreterPrototype::new(
                             config.module_bytes,
                             interpreter::CompilationMode::Lazy,
+                            config.fuel_metering,
                             config.symbols,
                         )?,
                     )
@@ -210,6 +224,7 @@ impl VirtualMachinePrototype {
                             } else {
                                 interpreter::CompilationMode::Eager
                             },
+                            config.fuel_metering,
                             config.symbols,
                         )?,
                     )
@@ -230,13 +245,128 @@ impl VirtualMachinePrototype {
                     ),
                     feature = "wasmtime"
                 ))]
-                ExecHint::ForceWasmtime => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(config.module_bytes, config.symbols)?,
-                ),
+                ExecHint::ForceWasmtime => {
+                    VirtualMachinePrototypeInner::Jit(jit::JitPrototype::new(
+                        config.module_bytes,
+                        config.fuel_metering,
+                        config.symbols,
+                    )?)
+                }
             },
         })
     }
 
+    /// Returns a key that identifies the result of compiling the module found in the
+    /// configuration, or `None` if the module would not be compiled ahead of time (for example
+    /// because it would be executed by an interpreter).
+    ///
+    /// The key covers the module bytes, the version of the compiler, the features of the current
+    /// CPU, and the configuration of the compiler. In other words, two identical keys mean that
+    /// the output of [`VirtualMachinePrototype::serialize_compiled_module`] can be passed to
+    /// [`VirtualMachinePrototype::from_compiled_module`].
+    pub fn compiled_module_cache_key(config: &Config) -> Option<[u8; 32]> {
+        match config.exec_hint {
+            #[cfg(all(
+                any(
+                    all(
+                        target_arch = "x86_64",
+                        any(
+                            target_os = "windows",
+                            all(target_os = "linux", target_env = "gnu"),
+                            target_os = "macos"
+                        )
+                    ),
+                    all(target_arch = "aarch64", all(target_os = "linux", target_env = "gnu")),
+                    all(target_arch = "s390x", all(target_os = "linux", target_env = "gnu"))
+                ),
+                feature = "wasmtime"
+            ))]
+            ExecHint::ValidateAndCompile | ExecHint::ForceWasmtime => {
+                Some(jit::JitPrototype::compiled_module_cache_key(
+                    config.module_bytes,
+                    config.fuel_metering,
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Creates a new process state machine from a module previously compiled and serialized
+    /// with [`VirtualMachinePrototype::serialize_compiled_module`].
+    ///
+    /// The [`Config::module_bytes`] are only used if the [`Config::exec_hint`] leads to the
+    /// module not being compiled ahead of time, in which case `compiled_module` is ignored and
+    /// this function is equivalent to [`VirtualMachinePrototype::new`].
+    ///
+    /// Returns [`NewErr::InvalidCompiledModule`] if `compiled_module` has been produced by a
+    /// different version of the compiler or for a different configuration.
+    ///
+    /// # Safety
+    ///
+    /// The content of `compiled_module` is trusted to contain valid machine code and is
+    /// executed without any verification. It must have been generated by
+    /// [`VirtualMachinePrototype::serialize_compiled_module`] and must not have been tampered
+    /// with, as otherwise arbitrary code could be executed.
+    pub unsafe fn from_compiled_module(
+        config: Config,
+        compiled_module: &[u8],
+    ) -> Result<Self, NewErr> {
+        match config.exec_hint {
+            #[cfg(all(
+                any(
+                    all(
+                        target_arch = "x86_64",
+                        any(
+                            target_os = "windows",
+                            all(target_os = "linux", target_env = "gnu"),
+                            target_os = "macos"
+                        )
+                    ),
+                    all(target_arch = "aarch64", all(target_os = "linux", target_env = "gnu")),
+                    all(target_arch = "s390x", all(target_os = "linux", target_env = "gnu"))
+                ),
+                feature = "wasmtime"
+            ))]
+            ExecHint::ValidateAndCompile | ExecHint::ForceWasmtime => Ok(VirtualMachinePrototype {
+                inner: VirtualMachinePrototypeInner::Jit(jit::JitPrototype::from_compiled_module(
+                    compiled_module,
+                    config.fuel_metering,
+                    config.symbols,
+                )?),
+            }),
+            _ => {
+                let _ = compiled_module;
+                Self::new(config)
+            }
+        }
+    }
+
+    /// Serializes the compiled module so that it can later be passed to
+    /// [`VirtualMachinePrototype::from_compiled_module`].
+    ///
+    /// Returns `None` if the module isn't compiled ahead of time, or if serializing failed.
+    pub fn serialize_compiled_module(&self) -> Option<Vec<u8>> {
+        match &self.inner {
+            #[cfg(all(
+                any(
+                    all(
+                        target_arch = "x86_64",
+                        any(
+                            target_os = "windows",
+                            all(target_os = "linux", target_env = "gnu"),
+                            target_os = "macos"
+                        )
+                    ),
+                    all(target_arch = "aarch64", all(target_os = "linux", target_env = "gnu")),
+                    all(target_arch = "s390x", all(target_os = "linux", target_env = "gnu"))
+                ),
+                feature = "wasmtime"
+            ))]
+            VirtualMachinePrototypeInner::Jit(inner) => inner.serialize_compiled_module(),
+            VirtualMachinePrototypeInner::Interpreter(_) => None,
+        }
+    }
+
     /// Returns the value of a global that the module exports.
     ///
     /// The global variable must be a `i32`, otherwise an error is returned. Negative values are
@@ -533,10 +663,20 @@ impl Prepare {
 
     /// Turns this prototype into an actual virtual machine. This requires choosing which function
     /// to execute.
+    ///
+    /// `fuel_limit` is the maximum amount of fuel that the execution can consume, or `None` for
+    /// no limit. If the limit is reached, [`VirtualMachine::run`] returns [`RunErr::OutOfFuel`].
+    /// Passing a limit requires [`Config::fuel_metering`] to be `true`.
+    ///
+    /// Fuel is consumed while executing WebAssembly instructions, and roughly corresponds to the
+    /// number of instructions executed. The amount of fuel consumed is deterministic for a given
+    /// backend, but differs between the interpreter and the JIT. Time spent in host functions
+    /// doesn't consume any fuel.
     pub fn start(
         self,
         function_name: &str,
         params: &[WasmValue],
+        fuel_limit: Option<u64>,
     ) -> Result<VirtualMachine, (StartErr, VirtualMachinePrototype)> {
         Ok(VirtualMachine {
             inner: match self.inner {
@@ -555,7 +695,7 @@ impl Prepare {
                     ),
                     feature = "wasmtime"
                 ))]
-                PrepareInner::Jit(inner) => match inner.start(function_name, params) {
+                PrepareInner::Jit(inner) => match inner.start(function_name, params, fuel_limit) {
                     Ok(vm) => VirtualMachineInner::Jit(vm),
                     Err((err, proto)) => {
                         return Err((
@@ -566,17 +706,19 @@ impl Prepare {
                         ));
                     }
                 },
-                PrepareInner::Interpreter(inner) => match inner.start(function_name, params) {
-                    Ok(vm) => VirtualMachineInner::Interpreter(vm),
-                    Err((err, proto)) => {
-                        return Err((
-                            err,
-                            VirtualMachinePrototype {
-                                inner: VirtualMachinePrototypeInner::Interpreter(proto),
-                            },
-                        ));
+                PrepareInner::Interpreter(inner) => {
+                    match inner.start(function_name, params, fuel_limit) {
+                        Ok(vm) => VirtualMachineInner::Interpreter(vm),
+                        Err((err, proto)) => {
+                            return Err((
+                                err,
+                                VirtualMachinePrototype {
+                                    inner: VirtualMachinePrototypeInner::Interpreter(proto),
+                                },
+                            ));
+                        }
                     }
-                },
+                }
             },
         })
     }
@@ -1374,6 +1516,12 @@ pub enum NewErr {
     /// Contains an opaque error message.
     #[display(fmt = "{_0}")]
     Instantiation(String),
+    /// Error while loading a module compiled ahead of time. See
+    /// [`VirtualMachinePrototype::from_compiled_module`].
+    ///
+    /// Contains an opaque error message.
+    #[display(fmt = "Invalid compiled module: {_0}")]
+    InvalidCompiledModule(String),
     /// Failed to resolve a function imported by the module.
     #[display(fmt = "Unresolved function `{module_name}`:`{function}`")]
     UnresolvedFunctionImport {
@@ -1422,6 +1570,9 @@ pub enum StartErr {
     /// The types of the provided parameters don't match the signature.
     #[display(fmt = "The types of the provided parameters don't match the signature.")]
     InvalidParameters,
+    /// A fuel limit has been passed, but [`Config::fuel_metering`] was `false`.
+    #[display(fmt = "Fuel metering is disabled.")]
+    FuelMeteringDisabled,
 }
 
 /// Error while reading memory.
@@ -1443,6 +1594,10 @@ pub enum RunErr {
         /// Type of the value that was actually passed.
         obtained: Option<ValueType>,
     },
+    /// The execution has consumed all the fuel allowed when calling [`Prepare::start`]. The state
+    /// machine is now poisoned.
+    #[display(fmt = "All the fuel has been consumed")]
+    OutOfFuel,
 }
 
 /// Error that can happen when calling [`VirtualMachinePrototype::global_value`].
@@ -54,6 +54,7 @@ impl InterpreterPrototype {
     pub fn new(
         module_bytes: &[u8],
         compilation_mode: CompilationMode,
+        fuel_metering: bool,
         symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
         let engine = {
@@ -69,6 +70,7 @@ impl InterpreterPrototype {
             config.wasm_saturating_float_to_int(false);
             config.wasm_tail_call(false);
             config.compilation_mode(compilation_mode);
+            config.consume_fuel(fuel_metering);
 
             wasmi::Engine::new(&config)
         };
@@ -133,6 +135,9 @@ impl InterpreterPrototype {
 
     fn from_base_components(base_components: BaseComponents) -> Result<Self, NewErr> {
         let mut store = wasmi::Store::new(base_components.module.engine(), ());
+        // The instantiation of the module isn't subject to any fuel limit.
+        // Fails if fuel metering is disabled in the engine, in which case there's nothing to do.
+        let _ = store.set_fuel(u64::MAX);
 
         let mut linker = base_components
             .linker_builder
@@ -337,9 +342,10 @@ impl Prepare {
 
     /// See [`super::Prepare::start`].
     pub fn start(
-        self,
+        mut self,
         function_name: &str,
         params: &[WasmValue],
+        fuel_limit: Option<u64>,
     ) -> Result<Interpreter, (StartErr, InterpreterPrototype)> {
         let func_to_call = match self
             .inner
@@ -386,6 +392,18 @@ impl Prepare {
             })
         };
 
+        // `set_fuel` fails if and only if fuel metering is disabled.
+        match fuel_limit {
+            Some(fuel_limit) => {
+                if self.inner.store.set_fuel(fuel_limit).is_err() {
+                    return Err((StartErr::FuelMeteringDisabled, self.inner));
+                }
+            }
+            None => {
+                let _ = self.inner.store.set_fuel(u64::MAX);
+            }
+        }
+
         Ok(Interpreter {
             base_components: self.inner.base_components,
             store: self.inner.store,
@@ -523,6 +541,9 @@ impl Interpreter {
                 self.execution = Some(Execution::Started(next));
                 Ok(outcome)
             }
+            Err(err) if err.as_trap_code() == Some(wasmi::core::TrapCode::OutOfFuel) => {
+                Err(RunErr::OutOfFuel)
+            }
             Err(err) => Ok(ExecOutcome::Finished {
                 return_value: Err(Trap(err.to_string())),
             }),
@@ -59,8 +59,54 @@ impl JitPrototype {
     /// See [`super::VirtualMachinePrototype::new`].
     pub fn new(
         module_bytes: &[u8],
+        fuel_metering: bool,
         symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
+        let engine = Self::engine(fuel_metering)?;
+        let module = wasmtime::Module::from_binary(&engine, module_bytes)
+            .map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
+        Self::from_module(module, symbols)
+    }
+
+    /// See [`super::VirtualMachinePrototype::from_compiled_module`].
+    ///
+    /// # Safety
+    ///
+    /// See [`super::VirtualMachinePrototype::from_compiled_module`].
+    pub unsafe fn from_compiled_module(
+        compiled_module: &[u8],
+        fuel_metering: bool,
+        symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
+        let engine = Self::engine(fuel_metering)?;
+        // Wasmtime verifies that the module has been compiled with the same version and
+        // configuration as `engine`, and returns an error otherwise.
+        let module = wasmtime::Module::deserialize(&engine, compiled_module)
+            .map_err(|err| NewErr::InvalidCompiledModule(err.to_string()))?;
+        Self::from_module(module, symbols)
+    }
+
+    /// See [`super::VirtualMachinePrototype::compiled_module_cache_key`].
+    pub fn compiled_module_cache_key(module_bytes: &[u8], fuel_metering: bool) -> [u8; 32] {
+        let mut hasher = Blake2Hasher(blake2_rfc::blake2b::Blake2b::new(32));
+        hasher.0.update(module_bytes);
+        // Building an engine can only fail if the configuration is invalid, which would also
+        // make `new` always fail.
+        if let Ok(engine) = Self::engine(fuel_metering) {
+            // The compatibility hash covers the version of wasmtime, the target and its
+            // features, and the configuration of the engine.
+            core::hash::Hash::hash(&engine.precompile_compatibility_hash(), &mut hasher);
+        }
+        <[u8; 32]>::try_from(hasher.0.finalize().as_bytes()).unwrap()
+    }
+
+    /// See [`super::VirtualMachinePrototype::serialize_compiled_module`].
+    pub fn serialize_compiled_module(&self) -> Option<Vec<u8>> {
+        self.base_components.module.serialize().ok()
+    }
+
+    /// Builds the engine used to compile and run modules.
+    fn engine(fuel_metering: bool) -> Result<wasmtime::Engine, NewErr> {
         let mut config = wasmtime::Config::new();
         config.cranelift_nan_canonicalization(true);
         config.cranelift_opt_level(wasmtime::OptLevel::Speed);
@@ -71,6 +117,7 @@ impl JitPrototype {
         // environment variables whatsoever. Whether to use `Enable` or `Disable` below isn't
         // very important, so long as it is not `Environment`.
         config.wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
+        config.consume_fuel(fuel_metering);
 
         // Disable all post-MVP wasm features.
         // Some of these configuration options are `true` by default while some others are `false`
@@ -87,12 +134,13 @@ impl JitPrototype {
         config.wasm_tail_call(false);
         config.wasm_component_model(false);
 
-        let engine =
-            wasmtime::Engine::new(&config).map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
-
-        let module = wasmtime::Module::from_binary(&engine, module_bytes)
-            .map_err(|err| NewErr::InvalidWasm(err.to_string()))?;
+        wasmtime::Engine::new(&config).map_err(|err| NewErr::InvalidWasm(err.to_string()))
+    }
 
+    fn from_module(
+        module: wasmtime::Module,
+        symbols: &mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>,
+    ) -> Result<Self, NewErr> {
         // Building the list of imports that the Wasm VM is able to use.
         let resolved_imports = {
             let mut imports = Vec::with_capacity(module.imports().len());
@@ -138,6 +186,9 @@ impl JitPrototype {
 
     fn from_base_components(base_components: BaseComponents) -> Result<Self, NewErr> {
         let mut store = wasmtime::Store::new(base_components.module.engine(), ());
+        // The instantiation of the module isn't subject to any fuel limit.
+        // Fails if fuel metering is disabled in the engine, in which case there's nothing to do.
+        let _ = store.set_fuel(u64::MAX);
 
         let mut imported_memory = None;
         let shared = Arc::new(Mutex::new(Shared::ExecutingStart));
@@ -380,6 +431,22 @@ impl Clone for JitPrototype {
     }
 }
 
+/// Adapter that makes it possible to feed a type implementing [`core::hash::Hash`] to a
+/// BLAKE2 hasher. Contrary to the hashers of the standard library, the output is guaranteed to
+/// be stable.
+struct Blake2Hasher(blake2_rfc::blake2b::Blake2b);
+
+impl core::hash::Hasher for Blake2Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let hash = self.0.clone().finalize();
+        u64::from_le_bytes(<[u8; 8]>::try_from(&hash.as_bytes()[..8]).unwrap())
+    }
+}
+
 impl fmt::Debug for JitPrototype {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_tuple("JitPrototype").finish()
@@ -457,6 +524,7 @@ impl Prepare {
         mut self,
         function_name: &str,
         params: &[WasmValue],
+        fuel_limit: Option<u64>,
     ) -> Result<Jit, (StartErr, JitPrototype)> {
         let function_to_call = match self
             .inner
@@ -486,6 +554,18 @@ impl Prepare {
             }
         }
 
+        // `set_fuel` fails if and only if fuel metering is disabled.
+        match fuel_limit {
+            Some(fuel_limit) => {
+                if self.inner.store.set_fuel(fuel_limit).is_err() {
+                    return Err((StartErr::FuelMeteringDisabled, self.inner));
+                }
+            }
+            None => {
+                let _ = self.inner.store.set_fuel(u64::MAX);
+            }
+        }
+
         // This function only performs all the verifications and preparations, but the call isn't
         // actually started here because we might still need to potentially access `store`
         // before being in the context of a function handler.
@@ -744,6 +824,12 @@ impl Jit {
                     return_value: Ok(val),
                 })
             }
+            task::Poll::Ready((store, Err(err)))
+                if err.downcast_ref::<wasmtime::Trap>() == Some(&wasmtime::Trap::OutOfFuel) =>
+            {
+                self.inner = JitInner::Done(store);
+                Err(RunErr::OutOfFuel)
+            }
             task::Poll::Ready((store, Err(err))) => {
                 self.inner = JitInner::Done(store);
                 Ok(ExecOutcome::Finished {
@@ -34,6 +34,7 @@ fn basic_seems_to_work() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &include_bytes!("./test-polkadot-runtime-v9160.wasm")[..],
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -46,6 +47,7 @@ fn basic_seems_to_work() {
             .start(
                 "Core_version",
                 &[super::WasmValue::I32(0), super::WasmValue::I32(0)],
+                None,
             )
             .unwrap();
 
@@ -70,6 +72,7 @@ fn wat_not_accepted() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: b"(module)",
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::InvalidWasm(_))
@@ -88,6 +91,7 @@ fn out_of_memory_access() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -105,6 +109,7 @@ fn has_start_function() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -123,6 +128,7 @@ fn unsupported_type() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes[..],
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0)
         })
         .is_err());
@@ -150,11 +156,12 @@ fn basic_host_function_return_value_works() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
 
-        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
 
         let mut resume_value = None;
         loop {
@@ -198,6 +205,7 @@ fn no_memory() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::NoMemory)
@@ -225,6 +233,7 @@ fn memory_misnamed() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::MemoryNotNamedMemory)
@@ -248,6 +257,7 @@ fn memory_isnt_memory() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::MemoryIsntMemory)
@@ -279,6 +289,7 @@ fn two_memories() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::InvalidWasm(_) | super::NewErr::TwoMemories)
@@ -305,6 +316,7 @@ fn exported_memory_works() {
         super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -331,6 +343,7 @@ fn unresolved_function() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Err(())
             }),
             Err(super::NewErr::UnresolvedFunctionImport { .. })
@@ -356,6 +369,7 @@ fn unsupported_signature() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::UnresolvedFunctionImport { .. })
@@ -381,6 +395,7 @@ fn unsupported_import_type() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::ImportTypeNotSupported)
@@ -407,6 +422,7 @@ fn start_function_forbidden() {
             super::VirtualMachinePrototype::new(super::Config {
                 module_bytes: &module_bytes,
                 exec_hint,
+                fuel_metering: false,
                 symbols: &mut |_, _, _| Ok(0)
             }),
             Err(super::NewErr::StartFunctionNotSupported) | Ok(_)
@@ -430,6 +446,7 @@ fn max_memory_pages() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -456,6 +473,7 @@ fn get_global() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -478,6 +496,7 @@ fn global_doesntexist() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -504,6 +523,7 @@ fn global_wrong_type() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -530,6 +550,7 @@ fn global_isnt_global() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -556,6 +577,7 @@ fn global_negative_value_works() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -579,11 +601,12 @@ fn call_non_existing_function() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
         assert!(matches!(
-            prototype.prepare().start("doesntexist", &[]),
+            prototype.prepare().start("doesntexist", &[], None),
             Err((super::StartErr::FunctionNotFound, _))
         ));
     }
@@ -605,11 +628,12 @@ fn call_signature_not_supported() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
         assert!(matches!(
-            prototype.prepare().start("hello", &[]),
+            prototype.prepare().start("hello", &[], None),
             Err((super::StartErr::SignatureNotSupported, _))
         ));
     }
@@ -631,11 +655,12 @@ fn bad_params_types() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
         assert!(matches!(
-            prototype.prepare().start("hello", &[]),
+            prototype.prepare().start("hello", &[], None),
             Err((super::StartErr::InvalidParameters, _))
         ));
     }
@@ -657,11 +682,12 @@ fn try_to_call_global() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
         assert!(matches!(
-            prototype.prepare().start("hello", &[]),
+            prototype.prepare().start("hello", &[], None),
             Err((super::StartErr::NotAFunction, _))
         ));
     }
@@ -686,11 +712,12 @@ fn wrong_type_provided_initially() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
 
-        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
 
         assert!(matches!(
             vm.run(Some(super::WasmValue::I32(3))),
@@ -718,11 +745,12 @@ fn wrong_type_returned_by_host_function_call() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
 
-        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
 
         let Ok(super::ExecOutcome::Interrupted { id: 0, .. }) = vm.run(None) else {
             panic!()
@@ -750,10 +778,11 @@ fn memory_min_specified_in_wasm() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
-        let interpreter = prototype.prepare().start("hello", &[]).unwrap();
+        let interpreter = prototype.prepare().start("hello", &[], None).unwrap();
         assert_eq!(interpreter.memory_size(), super::HeapPages::new(16));
     }
 }
@@ -774,10 +803,11 @@ fn memory_grow_works() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
-        let mut interpreter = prototype.prepare().start("hello", &[]).unwrap();
+        let mut interpreter = prototype.prepare().start("hello", &[], None).unwrap();
         assert_eq!(interpreter.memory_size(), super::HeapPages::new(16));
         interpreter.grow_memory(super::HeapPages::new(3)).unwrap();
         assert_eq!(interpreter.memory_size(), super::HeapPages::new(19));
@@ -800,10 +830,11 @@ fn memory_grow_detects_limit() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
-        let mut interpreter = prototype.prepare().start("hello", &[]).unwrap();
+        let mut interpreter = prototype.prepare().start("hello", &[], None).unwrap();
         assert_eq!(interpreter.memory_size(), super::HeapPages::new(16));
         assert!(interpreter.grow_memory(super::HeapPages::new(10)).is_err());
     }
@@ -828,11 +859,12 @@ fn memory_grow_detects_limit_within_host_function() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
 
-        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
 
         let mut resume_value = None;
         loop {
@@ -880,12 +912,13 @@ fn globals_reinitialized_after_reset() {
         let mut prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
         assert_eq!(prototype.global_value("myglob").unwrap(), 5);
 
-        let mut vm = prototype.prepare().start("hello", &[]).unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
         assert!(matches!(
             vm.run(None),
             Ok(super::ExecOutcome::Finished {
@@ -914,6 +947,7 @@ fn memory_zeroed_after_reset() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -921,7 +955,7 @@ fn memory_zeroed_after_reset() {
         let mut vm = prototype.prepare();
         vm.write_memory(11, &[5, 6]).unwrap();
 
-        let mut vm = vm.start("hello", &[]).unwrap();
+        let mut vm = vm.start("hello", &[], None).unwrap();
         assert_eq!(vm.read_memory(12, 1).unwrap().as_ref()[0], 6);
         vm.write_memory(12, &[7]).unwrap();
         assert_eq!(vm.read_memory(12, 1).unwrap().as_ref()[0], 7);
@@ -940,7 +974,7 @@ fn memory_zeroed_after_reset() {
         assert_eq!(vm.read_memory(11, 2).unwrap().as_ref(), &[0, 0]);
         assert_eq!(vm.read_memory(12, 1).unwrap().as_ref(), &[0]);
 
-        let vm = vm.start("hello", &[]).unwrap();
+        let vm = vm.start("hello", &[], None).unwrap();
         assert_eq!(vm.read_memory(11, 2).unwrap().as_ref(), &[0, 0]);
         assert_eq!(vm.read_memory(12, 1).unwrap().as_ref(), &[0]);
     }
@@ -962,6 +996,7 @@ fn memory_zeroed_after_prepare() {
         let prototype = super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .unwrap();
@@ -1000,6 +1035,7 @@ fn feature_disabled_signext() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1031,6 +1067,7 @@ fn feature_disabled_saturated_float_to_int() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1056,6 +1093,7 @@ fn feature_disabled_threads() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1078,6 +1116,7 @@ fn feature_disabled_reference_type() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1103,6 +1142,7 @@ fn feature_disabled_bulk_memory() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1127,6 +1167,7 @@ fn feature_disabled_multi_value() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1148,6 +1189,7 @@ fn feature_disabled_memory64() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1176,6 +1218,7 @@ fn feature_disabled_mutable_globals() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1211,6 +1254,7 @@ fn feature_disabled_tail_call() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
@@ -1270,12 +1314,270 @@ fn feature_disabled_simd128() {
         assert!(super::VirtualMachinePrototype::new(super::Config {
             module_bytes: &module_bytes,
             exec_hint,
+            fuel_metering: false,
             symbols: &mut |_, _, _| Ok(0),
         })
         .is_err());
     }
 }
 
+#[test]
+fn infinite_loop_runs_out_of_fuel() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello")
+            (loop $continue
+                br $continue
+            )
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            fuel_metering: true,
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+
+        let mut vm = prototype
+            .prepare()
+            .start("hello", &[], Some(100_000))
+            .unwrap();
+        assert!(matches!(vm.run(None), Err(super::RunErr::OutOfFuel)));
+        assert!(matches!(vm.run(None), Err(super::RunErr::Poisoned)));
+
+        // The fuel is refilled when starting a new execution.
+        let mut vm = vm
+            .into_prototype()
+            .prepare()
+            .start("hello", &[], Some(100_000))
+            .unwrap();
+        assert!(matches!(vm.run(None), Err(super::RunErr::OutOfFuel)));
+
+        // The limit is chosen for each call.
+        let mut vm = vm
+            .into_prototype()
+            .prepare()
+            .start("hello", &[], Some(1_000))
+            .unwrap();
+        assert!(matches!(vm.run(None), Err(super::RunErr::OutOfFuel)));
+    }
+}
+
+#[test]
+fn fuel_limit_doesnt_affect_host_functions() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "host" "hello" (func $host_hello (param i32) (result i32)))
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello") (result i32)
+            (call $host_hello (i32.const 3))
+            (call $host_hello (i32.const 3))
+            i32.add
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            fuel_metering: true,
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+
+        let mut vm = prototype
+            .prepare()
+            .start("hello", &[], Some(1_000))
+            .unwrap();
+
+        let mut resume_value = None;
+        loop {
+            match vm.run(resume_value) {
+                Ok(super::ExecOutcome::Finished {
+                    return_value: Ok(value),
+                }) => {
+                    assert_eq!(value, Some(super::WasmValue::I32(10)));
+                    break;
+                }
+                Ok(super::ExecOutcome::Interrupted { id: 0, .. }) => {
+                    resume_value = Some(super::WasmValue::I32(5));
+                }
+                _ => panic!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn compiled_module_round_trip() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello") (result i32)
+            (i32.const 5)
+        )
+    )
+    "#,
+    )
+    .unwrap();
+    let other_module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello") (result i32)
+            (i32.const 6)
+        )
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let mut symbols = |_: &str, _: &str, _: &super::Signature| Ok(0);
+
+        let cache_key = super::VirtualMachinePrototype::compiled_module_cache_key(&super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            fuel_metering: false,
+            symbols: &mut symbols,
+        });
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            fuel_metering: false,
+            symbols: &mut symbols,
+        })
+        .unwrap();
+        let compiled = prototype.serialize_compiled_module();
+
+        // Only the JIT compiles modules ahead of time.
+        if Some(exec_hint) != super::ExecHint::force_wasmtime_if_available() {
+            assert!(cache_key.is_none());
+            assert!(compiled.is_none());
+            continue;
+        }
+
+        // The key depends on the module.
+        assert!(cache_key.is_some());
+        assert_eq!(
+            cache_key,
+            super::VirtualMachinePrototype::compiled_module_cache_key(&super::Config {
+                module_bytes: &module_bytes,
+                exec_hint,
+                fuel_metering: false,
+                symbols: &mut symbols,
+            })
+        );
+        assert_ne!(
+            cache_key,
+            super::VirtualMachinePrototype::compiled_module_cache_key(&super::Config {
+                module_bytes: &other_module_bytes,
+                exec_hint,
+                fuel_metering: false,
+                symbols: &mut symbols,
+            })
+        );
+
+        // Modules compiled with fuel metering are incompatible with the ones compiled without.
+        assert_ne!(
+            cache_key,
+            super::VirtualMachinePrototype::compiled_module_cache_key(&super::Config {
+                module_bytes: &module_bytes,
+                exec_hint,
+                fuel_metering: true,
+                symbols: &mut symbols,
+            })
+        );
+
+        let compiled = compiled.unwrap();
+        let prototype = unsafe {
+            super::VirtualMachinePrototype::from_compiled_module(
+                super::Config {
+                    module_bytes: &module_bytes,
+                    exec_hint,
+                    fuel_metering: false,
+                    symbols: &mut symbols,
+                },
+                &compiled,
+            )
+        }
+        .unwrap();
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
+        assert!(matches!(
+            vm.run(None),
+            Ok(super::ExecOutcome::Finished {
+                return_value: Ok(Some(super::WasmValue::I32(5)))
+            })
+        ));
+
+        // Something that isn't a compiled module is rejected.
+        assert!(matches!(
+            unsafe {
+                super::VirtualMachinePrototype::from_compiled_module(
+                    super::Config {
+                        module_bytes: &module_bytes,
+                        exec_hint,
+                        fuel_metering: false,
+                        symbols: &mut symbols,
+                    },
+                    &[],
+                )
+            },
+            Err(super::NewErr::InvalidCompiledModule(_))
+        ));
+    }
+}
+
 // TODO: check that the extended-const feature is disabled: https://github.com/WebAssembly/extended-const/blob/master/proposals/extended-const/Overview.md
 
 // TODO: test for memory reads and writes, including within host functions
+
+#[test]
+fn fuel_limit_requires_fuel_metering() {
+    let module_bytes = wat::parse_str(
+        r#"
+    (module
+        (import "env" "memory" (memory $mem 0 4096))
+        (func (export "hello"))
+    )
+    "#,
+    )
+    .unwrap();
+
+    for exec_hint in super::ExecHint::available_engines() {
+        let prototype = super::VirtualMachinePrototype::new(super::Config {
+            module_bytes: &module_bytes,
+            exec_hint,
+            fuel_metering: false,
+            symbols: &mut |_, _, _| Ok(0),
+        })
+        .unwrap();
+
+        let Err((super::StartErr::FuelMeteringDisabled, prototype)) =
+            prototype.prepare().start("hello", &[], Some(1_000))
+        else {
+            panic!()
+        };
+
+        let mut vm = prototype.prepare().start("hello", &[], None).unwrap();
+        assert!(matches!(
+            vm.run(None),
+            Ok(super::ExecOutcome::Finished {
+                return_value: Ok(None)
+            })
+        ));
+    }
+}
@@ -71,25 +71,52 @@ pub fn decode_ed25519_private_key(phrase: &str) -> Result<Box<[u8; 32]>, ParsePr
     let mut secret_key = parsed.seed;
     for junction in parsed.path {
         secret_key = match junction {
-            DeriveJunction::Soft(_) => todo!(), // TODO: return error
-            DeriveJunction::Hard(cc) => {
-                let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
-                hash.update(crate::util::encode_scale_compact_usize(11).as_ref()); // Length of `"Ed25519HDKD"`
-                hash.update(b"Ed25519HDKD");
-                hash.update(&*secret_key);
-                hash.update(&cc);
-
-                let mut out = Box::new([0; 32]);
-                out.copy_from_slice(hash.finalize().as_ref());
-                // TODO: `hash` should be zero'ed on drop :-/
-                out
-            }
+            DeriveJunction::Soft(_) => return Err(ParsePrivateKeyError::SoftDerivationUnsupported),
+            DeriveJunction::Hard(cc) => hard_derive_blake2(b"Ed25519HDKD", &secret_key, &cc),
+        };
+    }
+
+    Ok(secret_key)
+}
+
+/// Decodes a human-readable private key (a.k.a. a seed phrase) using the ECDSA algorithm on the
+/// secp256k1 curve.
+///
+/// > **Note**: The key is returned within a `Box` in order to guarantee that no trace of the
+/// >           secret key is accidentally left in memory due to automatic copies of stack data.
+pub fn decode_ecdsa_private_key(phrase: &str) -> Result<Box<[u8; 32]>, ParsePrivateKeyError> {
+    let parsed = parse_private_key(phrase)?;
+
+    let mut secret_key = parsed.seed;
+    for junction in parsed.path {
+        secret_key = match junction {
+            DeriveJunction::Soft(_) => return Err(ParsePrivateKeyError::SoftDerivationUnsupported),
+            DeriveJunction::Hard(cc) => hard_derive_blake2(b"Secp256k1HDKD", &secret_key, &cc),
         };
     }
 
     Ok(secret_key)
 }
 
+/// Performs a hard derivation by hashing the SCALE encoding of `(domain, secret_key, chain_code)`
+/// with blake2. This is how both Ed25519 and ECDSA keys are derived.
+fn hard_derive_blake2(
+    domain: &[u8],
+    secret_key: &[u8; 32],
+    chain_code: &[u8; 32],
+) -> Box<[u8; 32]> {
+    let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
+    hash.update(crate::util::encode_scale_compact_usize(domain.len()).as_ref());
+    hash.update(domain);
+    hash.update(secret_key);
+    hash.update(chain_code);
+
+    let mut out = Box::new([0; 32]);
+    out.copy_from_slice(hash.finalize().as_ref());
+    // TODO: `hash` should be zero'ed on drop :-/
+    out
+}
+
 /// Turns a human-readable private key (a.k.a. a seed phrase) into a seed and a derivation path.
 pub fn parse_private_key(phrase: &str) -> Result<ParsedPrivateKey, ParsePrivateKeyError> {
     let parse_result: Result<_, nom::Err<nom::error::Error<&str>>> =
@@ -188,6 +215,8 @@ pub enum ParsePrivateKeyError {
     InvalidFormat,
     /// Failed to decode the provided BIP39 seed phrase.
     Bip39Decode(Bip39ToSeedError),
+    /// The derivation path contains a soft junction, which the requested curve doesn't support.
+    SoftDerivationUnsupported,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -372,6 +401,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn alice_matches_ecdsa() {
+        let secret_key = super::decode_ecdsa_private_key("//Alice").unwrap();
+        let public_key = libsecp256k1::PublicKey::from_secret_key(
+            &libsecp256k1::SecretKey::parse(&secret_key).unwrap(),
+        );
+        assert_eq!(
+            hex::encode(public_key.serialize_compressed()),
+            "020a1091341fe5664bfa1782d5e04779689068c916b04cb365ec3153755684d9a1"
+        );
+    }
+
     #[test]
     fn hex_seed_matches_sr25519() {
         assert_eq!(
@@ -417,4 +458,16 @@ mod tests {
             [95, 205, 122, 218, 56, 195, 127, 158, 30, 205, 82, 84, 159, 120, 105, 63, 210, 155, 217, 74, 40, 142, 70, 179, 11, 75, 82, 143, 219, 208, 86, 245]
         );
     }
+
+    #[test]
+    fn soft_derivation_unsupported() {
+        assert!(matches!(
+            super::decode_ed25519_private_key("//Alice/foo"),
+            Err(super::ParsePrivateKeyError::SoftDerivationUnsupported)
+        ));
+        assert!(matches!(
+            super::decode_ecdsa_private_key("//Alice/foo"),
+            Err(super::ParsePrivateKeyError::SoftDerivationUnsupported)
+        ));
+    }
 }
@@ -421,7 +421,7 @@ define_methods! {
     state_getKeysPaged(prefix: Option<HexString>, count: u32, start_key: Option<HexString>, hash: Option<HashHexString>) -> Vec<HexString> [state_getKeysPagedAt],
     state_getMetadata(hash: Option<HashHexString>) -> HexString,
     state_getPairs() -> (), // TODO:
-    state_getReadProof() -> (), // TODO:
+    state_getReadProof(keys: Vec<HexString>, at: Option<HashHexString>) -> ReadProof,
     state_getRuntimeVersion(at: Option<HashHexString>) -> RuntimeVersion<'a> [chain_getRuntimeVersion],
     state_getStorage(key: HexString, hash: Option<HashHexString>) -> HexString [state_getStorageAt],
     state_getStorageHash() -> () [state_getStorageHashAt], // TODO:
@@ -430,6 +430,11 @@ define_methods! {
     state_queryStorageAt(keys: Vec<HexString>, at: Option<HashHexString>) -> Vec<StorageChangeSet>, // TODO:
     state_subscribeRuntimeVersion() -> Cow<'a, str> [chain_subscribeRuntimeVersion],
     state_subscribeStorage(list: Vec<HexString>) -> Cow<'a, str>,
+    /// Re-executes the given block and returns the storage accesses and logs of each runtime
+    /// call. `storage_keys` is an optional comma-separated list of hexadecimal key prefixes used
+    /// to filter the storage accesses. `targets` and `methods` are accepted for compatibility
+    /// reasons and ignored.
+    state_traceBlock(block: HashHexString, targets: Option<Cow<'a, str>>, storage_keys: Option<Cow<'a, str>>, methods: Option<Cow<'a, str>>) -> BlockTrace,
     state_unsubscribeRuntimeVersion(subscription: Cow<'a, str>) -> bool [chain_unsubscribeRuntimeVersion],
     state_unsubscribeStorage(subscription: Cow<'a, str>) -> bool,
     system_accountNextIndex(account: AccountId) -> u64,
@@ -510,6 +515,12 @@ define_methods! {
     sudo_network_unstable_watch() -> Cow<'a, str>,
     sudo_network_unstable_unwatch(subscription: Cow<'a, str>) -> (),
     chainHead_unstable_finalizedDatabase(#[rename = "maxSizeBytes"] max_size_bytes: Option<u64>) -> Cow<'a, str>,
+
+    // These functions are a custom addition in smoldot. They behave like
+    // `transactionWatch_v1_submitAndWatch` and `transactionWatch_v1_unwatch`, but the
+    // subscription also generates the events of `TransactionWatchEventDetail`.
+    smoldot_transactionWatch_submitAndWatch(transaction: HexString) -> Cow<'a, str>,
+    smoldot_transactionWatch_unwatch(subscription: Cow<'a, str>) -> (),
 }
 
 define_methods! {
@@ -529,6 +540,10 @@ define_methods! {
     // This function is a custom addition in smoldot. As of the writing of this comment, there is
     // no plan to standardize it. See https://github.com/paritytech/smoldot/issues/2245.
     sudo_networkState_event(subscription: Cow<'a, str>, result: NetworkEvent) -> (),
+
+    // This function is a custom addition in smoldot. It is the counterpart of
+    // `transactionWatch_v1_watchEvent` for `smoldot_transactionWatch_submitAndWatch`.
+    smoldot_transactionWatch_watchEvent(subscription: Cow<'a, str>, result: SmoldotTransactionWatchEvent<'a>) -> (),
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -842,6 +857,36 @@ pub enum TransactionWatchEvent<'a> {
     },
 }
 
+/// Event generated by `smoldot_transactionWatch_submitAndWatch` subscriptions.
+///
+/// This is a custom addition in smoldot.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum SmoldotTransactionWatchEvent<'a> {
+    Standard(TransactionWatchEvent<'a>),
+    Detail(TransactionWatchEventDetail<'a>),
+}
+
+/// Events of `smoldot_transactionWatch_submitAndWatch` that `transactionWatch_v1_submitAndWatch`
+/// doesn't generate.
+///
+/// This is a custom addition in smoldot.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event")]
+pub enum TransactionWatchEventDetail<'a> {
+    #[serde(rename = "validatedAgainstBlock")]
+    ValidatedAgainstBlock { block: HashHexString },
+    #[serde(rename = "validationFailed")]
+    ValidationFailed {
+        block: HashHexString,
+        error: Cow<'a, str>,
+    },
+    #[serde(rename = "broadcastedToPeers")]
+    BroadcastedToPeers { peers: Vec<String> },
+    #[serde(rename = "broadcastFailed")]
+    BroadcastFailed { attempts: u32 },
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TransactionWatchEventBlock {
     pub hash: HashHexString,
@@ -877,6 +922,33 @@ pub enum NetworkEvent {
         direction: NetworkEventDirection,
         when: u64,
     },
+    #[serde(rename = "finalityDivergence")]
+    FinalityDivergence {
+        #[serde(rename = "blockNumber")]
+        block_number: u64,
+        #[serde(rename = "peerId")]
+        peer_id: String,
+        #[serde(rename = "blockHash")]
+        block_hash: HashHexString,
+        #[serde(rename = "conflictingPeerId", skip_serializing_if = "Option::is_none")]
+        conflicting_peer_id: Option<String>,
+        #[serde(rename = "conflictingBlockHash")]
+        conflicting_block_hash: HashHexString,
+        when: u64,
+    },
+    #[serde(rename = "finalityWithheld")]
+    FinalityWithheld {
+        #[serde(rename = "peerId")]
+        peer_id: String,
+        #[serde(
+            rename = "peerFinalizedBlockNumber",
+            skip_serializing_if = "Option::is_none"
+        )]
+        peer_finalized_block_number: Option<u64>,
+        #[serde(rename = "localFinalizedBlockNumber")]
+        local_finalized_block_number: u64,
+        when: u64,
+    },
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -1025,6 +1097,72 @@ pub enum DispatchClass {
     Mandatory,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ReadProof {
+    pub at: HashHexString,
+    pub proof: Vec<HexString>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlockTrace {
+    #[serde(rename = "blockHash")]
+    pub block_hash: HashHexString,
+    #[serde(rename = "parentHash")]
+    pub parent_hash: HashHexString,
+    #[serde(rename = "storageKeys")]
+    pub storage_keys: String,
+    pub segments: Vec<BlockTraceSegment>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlockTraceSegment {
+    pub function: String,
+    #[serde(rename = "extrinsicIndex", skip_serializing_if = "Option::is_none")]
+    pub extrinsic_index: Option<u32>,
+    pub events: Vec<BlockTraceEvent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type")]
+pub enum BlockTraceEvent {
+    #[serde(rename = "storageGet")]
+    StorageGet {
+        #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+        child_trie: Option<HexString>,
+        key: HexString,
+        value: Option<HexString>,
+    },
+    #[serde(rename = "storageSet")]
+    StorageSet {
+        #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+        child_trie: Option<HexString>,
+        key: HexString,
+        value: Option<HexString>,
+    },
+    #[serde(rename = "storageAppend")]
+    StorageAppend {
+        #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+        child_trie: Option<HexString>,
+        key: HexString,
+        value: HexString,
+    },
+    #[serde(rename = "storageClearPrefix")]
+    StorageClearPrefix {
+        #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+        child_trie: Option<HexString>,
+        prefix: HexString,
+    },
+    #[serde(rename = "storageRoot")]
+    StorageRoot {
+        #[serde(rename = "childTrie", skip_serializing_if = "Option::is_none")]
+        child_trie: Option<HexString>,
+        #[serde(rename = "rootHash")]
+        root_hash: HashHexString,
+    },
+    #[serde(rename = "log")]
+    Log { message: String },
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct StorageChangeSet {
     pub block: HashHexString,
@@ -1278,4 +1416,33 @@ mod tests {
             })
         ));
     }
+
+    #[test]
+    fn smoldot_transaction_watch_events() {
+        // Standard events keep the format of `transactionWatch_v1_watchEvent`, while the detail
+        // events are only generated through `smoldot_transactionWatch_watchEvent`.
+        let standard = super::ServerToClient::smoldot_transactionWatch_watchEvent {
+            subscription: "foo".into(),
+            result: super::SmoldotTransactionWatchEvent::Standard(
+                super::TransactionWatchEvent::Validated {},
+            ),
+        }
+        .to_json_request_object_parameters(None);
+        assert_eq!(
+            standard,
+            r#"{"jsonrpc":"2.0","method":"smoldot_transactionWatch_watchEvent","params":{"subscription":"foo","result":{"event":"validated"}}}"#
+        );
+
+        let detail = super::ServerToClient::smoldot_transactionWatch_watchEvent {
+            subscription: "foo".into(),
+            result: super::SmoldotTransactionWatchEvent::Detail(
+                super::TransactionWatchEventDetail::BroadcastFailed { attempts: 3 },
+            ),
+        }
+        .to_json_request_object_parameters(None);
+        assert_eq!(
+            detail,
+            r#"{"jsonrpc":"2.0","method":"smoldot_transactionWatch_watchEvent","params":{"subscription":"foo","result":{"event":"broadcastFailed","attempts":3}}}"#
+        );
+    }
 }
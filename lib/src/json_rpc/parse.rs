@@ -17,7 +17,7 @@
 
 //! Parse JSON-RPC method calls and notifications, and build responses messages.
 
-use alloc::{borrow::Cow, string::String};
+use alloc::{borrow::Cow, string::String, vec::Vec};
 
 /// Parses a JSON-encoded RPC method call or notification.
 pub fn parse_request(request_json: &str) -> Result<Request, ParseError> {
@@ -46,6 +46,55 @@ pub fn parse_request(request_json: &str) -> Result<Request, ParseError> {
     })
 }
 
+/// Returns `true` if the given JSON-encoded message is a batch of requests, in other words a JSON
+/// array.
+///
+/// This function only looks at the first non-whitespace character of the message and doesn't
+/// check whether the message is valid JSON.
+pub fn is_batch(message_json: &str) -> bool {
+    message_json.trim_start().starts_with('[')
+}
+
+/// Splits a JSON-encoded batch of RPC method calls and notifications into the JSON-encoded
+/// requests it contains, in order.
+///
+/// The requests themselves aren't parsed, and must individually be passed to [`parse_request`].
+///
+/// > **Note**: The JSON-RPC specification forbids empty batches, but this function doesn't
+/// >           return an error if the batch is empty.
+pub fn parse_batch(batch_json: &str) -> Result<Vec<&str>, ParseError> {
+    let requests: Vec<&serde_json::value::RawValue> =
+        serde_json::from_str(batch_json).map_err(ParseError)?;
+    Ok(requests.into_iter().map(|r| r.get()).collect())
+}
+
+/// Returns the number of requests that a JSON-encoded batch of RPC method calls and notifications
+/// contains.
+///
+/// Contrary to [`parse_batch`], this function doesn't allocate a list of the requests.
+pub fn batch_len(batch_json: &str) -> Result<usize, ParseError> {
+    struct Visitor;
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = usize;
+        fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+            formatter.write_str("a JSON array")
+        }
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+            let mut num = 0;
+            while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                num += 1;
+            }
+            Ok(num)
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_str(batch_json);
+    let num =
+        serde::Deserializer::deserialize_seq(&mut deserializer, Visitor).map_err(ParseError)?;
+    deserializer.end().map_err(ParseError)?;
+    Ok(num)
+}
+
 /// Parses a JSON-encoded RPC response.
 pub fn parse_response(response_json: &str) -> Result<Response, ParseError> {
     let error = match serde_json::from_str::<SerdeSuccess>(response_json) {
@@ -338,6 +387,46 @@ pub enum ErrorResponse<'a> {
     ApplicationDefined(i64, &'a str),
 }
 
+/// Builds the JSON response to a batch of requests from the JSON-encoded responses to the
+/// individual requests of the batch.
+///
+/// The responses must be in the same order as the requests they correspond to. Notifications
+/// don't have any response and must simply be omitted.
+///
+/// Returns `None` if the list of responses is empty, in which case, according to the JSON-RPC
+/// specification, nothing must be sent back.
+///
+/// # Example
+///
+/// ```
+/// # use smoldot::json_rpc::parse;
+/// let response_json = parse::build_batch_response([
+///     parse::build_success_response("1", "true"),
+///     parse::build_success_response("2", "null"),
+/// ]);
+///
+/// assert_eq!(
+///     response_json.unwrap(),
+///     r#"[{"jsonrpc":"2.0","id":1,"result":true},{"jsonrpc":"2.0","id":2,"result":null}]"#
+/// );
+/// ```
+pub fn build_batch_response(
+    responses_json: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Option<String> {
+    let mut out = String::new();
+    for response_json in responses_json {
+        out.push(if out.is_empty() { '[' } else { ',' });
+        out.push_str(response_json.as_ref());
+    }
+
+    if out.is_empty() {
+        return None;
+    }
+
+    out.push(']');
+    Some(out)
+}
+
 /// Builds a JSON error response when a request couldn't be decoded.
 ///
 /// # Example
@@ -502,6 +591,43 @@ mod tests {
         assert_eq!(request.params_json, Some("[5,true, \"hello\"]"));
     }
 
+    #[test]
+    fn parse_batch_basic_works() {
+        let batch =
+            r#" [{"jsonrpc":"2.0","id":1,"method":"foo"}, {"jsonrpc":"2.0","method":"bar"},5]"#;
+        assert!(super::is_batch(batch));
+        let requests = super::parse_batch(batch).unwrap();
+        assert_eq!(
+            requests,
+            [
+                r#"{"jsonrpc":"2.0","id":1,"method":"foo"}"#,
+                r#"{"jsonrpc":"2.0","method":"bar"}"#,
+                "5"
+            ]
+        );
+        assert_eq!(super::batch_len(batch).unwrap(), 3);
+        assert_eq!(super::parse_request(requests[0]).unwrap().method, "foo");
+        assert!(super::parse_request(requests[2]).is_err());
+    }
+
+    #[test]
+    fn parse_batch_empty() {
+        assert!(super::parse_batch("[]").unwrap().is_empty());
+        assert_eq!(super::batch_len("[]").unwrap(), 0);
+        assert!(super::build_batch_response(Vec::<&str>::new()).is_none());
+    }
+
+    #[test]
+    fn parse_batch_not_array() {
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"foo"}"#;
+        assert!(!super::is_batch(request));
+        assert!(super::parse_batch(request).is_err());
+        assert!(super::parse_batch("[{}").is_err());
+        assert!(super::batch_len(request).is_err());
+        assert!(super::batch_len("[{}").is_err());
+        assert!(super::batch_len("[{}] 5").is_err());
+    }
+
     #[test]
     fn parse_response_basic_works() {
         let (id, result) = super::parse_response(r#"{"jsonrpc":"2.0","id":5,"result":true}"#)
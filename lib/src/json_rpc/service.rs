@@ -18,6 +18,8 @@
 pub mod client_main_task;
 pub mod deliver_channel;
 
+mod tests;
+
 // TODO: import paths?
 pub use self::client_main_task::*;
 pub use self::deliver_channel::*;
@@ -19,11 +19,12 @@
 
 use crate::json_rpc::{methods, parse};
 use alloc::{
-    borrow::Cow,
+    borrow::{Cow, ToOwned as _},
     boxed::Box,
     collections::VecDeque,
     string::{String, ToString as _},
     sync::{Arc, Weak},
+    vec::Vec,
 };
 use async_lock::Mutex;
 use core::{
@@ -69,13 +70,39 @@ struct Inner {
 
     /// Event notified after the [`SerializedRequestsIo`] is destroyed.
     on_serialized_requests_io_destroyed: event_listener::EventListener,
+
+    /// List of batches of requests whose responses are being collected. A batch is answered
+    /// with a single response once all the requests it contains have been answered.
+    batches: Slab<Batch>,
+
+    /// Requests belonging to an entry of [`Inner::batches`] that haven't been processed yet.
+    pending_batch_requests: VecDeque<(String, BatchEntry)>,
+}
+
+/// See [`Inner::batches`].
+struct Batch {
+    /// Responses to the requests of the batch, in the same order as the requests. `None` for
+    /// requests that haven't been answered yet and for notifications.
+    responses: Vec<Option<String>>,
+    /// Number of requests of the batch that haven't been answered yet.
+    num_unanswered: usize,
+}
+
+/// Identifies a request within an entry of [`Inner::batches`].
+#[derive(Debug, Copy, Clone)]
+struct BatchEntry {
+    /// Index within [`Inner::batches`].
+    batch_index: usize,
+    /// Index of the request within [`Batch::responses`].
+    request_index: usize,
 }
 
 struct InnerSubscription {
     /// Shared with the subscription. Used to notify the subscription that it should be killed.
     kill_channel: Arc<SubscriptionKillChannel>,
-    /// Response to an unsubscribe request that must be sent out once the subscription is killed.
-    unsubscribe_response: Option<String>,
+    /// Response to an unsubscribe request that must be sent out once the subscription is killed,
+    /// and the batch the unsubscribe request belongs to.
+    unsubscribe_response: Option<(String, Option<BatchEntry>)>,
 }
 
 struct SerializedIo {
@@ -91,6 +118,9 @@ struct SerializedIo {
 
     /// Number of requests that have have been received from the client but whose answer hasn't
     /// been pulled out from [`SerializedIo::requests_queue`] yet.
+    ///
+    /// A batch of requests counts as the number of requests it contains. See
+    /// [`num_requests_in_message`].
     num_requests_in_fly: AtomicU32,
 
     /// Maximum value that [`SerializedIo::num_requests_in_fly`] is allowed to reach.
@@ -108,9 +138,10 @@ struct SerializedIo {
 struct SerializedIoResponses {
     /// Unordered list of responses and notifications to send back to the client.
     ///
-    /// Each entry contains the response/notification, and a boolean equal to `true` if this is
-    /// a request response or `false` if this is a notification.
-    pending_serialized_responses: Slab<(String, bool)>,
+    /// Each entry contains the response/notification, and the number of requests it answers.
+    /// This number is `0` for notifications, `1` for responses to individual requests, and the
+    /// number of requests in the batch for responses to batches.
+    pending_serialized_responses: Slab<(String, u32)>,
 
     /// Ordered list of responses and notifications to send back to the client, as indices within
     /// [`SerializedIoResponses::pending_serialized_responses`].
@@ -137,7 +168,7 @@ struct ResponsesNotificationsQueue {
 
 // TODO: weird enum
 enum ToMainTask {
-    RequestResponse(String),
+    RequestResponse(String, Option<BatchEntry>),
     Notification(String),
     SubscriptionDestroyed { subscription_id: String },
 }
@@ -149,6 +180,9 @@ pub struct Config {
     ///
     /// If this limit is reached, it is not possible to send further requests without pulling
     /// responses first.
+    ///
+    /// Each request of a batch counts individually towards this limit. Batches containing more
+    /// requests than this limit are refused.
     pub max_pending_requests: NonZeroU32,
 
     /// Maximum number of simultaneous subscriptions allowed. Trying to create a subscription will
@@ -200,6 +234,8 @@ pub fn client_main_task(config: Config) -> (ClientMainTask, SerializedRequestsIo
                 on_popped: event_listener::Event::new(),
             }),
             on_serialized_requests_io_destroyed: on_serialized_requests_io_destroyed.listen(),
+            batches: Slab::new(),
+            pending_batch_requests: VecDeque::new(),
         }),
     };
 
@@ -217,10 +253,17 @@ impl ClientMainTask {
         loop {
             enum WakeUpReason {
                 NewRequest(String),
+                BatchRequest(String, BatchEntry),
                 Message(ToMainTask),
             }
 
-            let wake_up_reason = {
+            // Requests of batches that have been received earlier are processed before anything
+            // else.
+            let wake_up_reason = if let Some((request, batch_entry)) =
+                self.inner.pending_batch_requests.pop_front()
+            {
+                WakeUpReason::BatchRequest(request, batch_entry)
+            } else {
                 let serialized_requests_io_destroyed = async {
                     (&mut self.inner.on_serialized_requests_io_destroyed).await;
                     Err(())
@@ -269,9 +312,14 @@ impl ClientMainTask {
                 }
             };
 
-            // Immediately handle every event apart from `NewRequest`.
-            let new_request = match wake_up_reason {
-                WakeUpReason::NewRequest(request) => request,
+            // Immediately handle every event apart from `NewRequest` and `BatchRequest`.
+            let (new_request, batch_entry) = match wake_up_reason {
+                WakeUpReason::NewRequest(request) if parse::is_batch(&request) => {
+                    self.inner.start_batch(request).await;
+                    continue;
+                }
+                WakeUpReason::NewRequest(request) => (request, None),
+                WakeUpReason::BatchRequest(request, batch_entry) => (request, Some(batch_entry)),
                 WakeUpReason::Message(ToMainTask::SubscriptionDestroyed { subscription_id }) => {
                     let InnerSubscription {
                         unsubscribe_response,
@@ -282,19 +330,10 @@ impl ClientMainTask {
                         .remove(&subscription_id)
                         .unwrap();
                     // TODO: post a `stop`/`error` event for chainhead subscriptions
-                    if let Some(unsubscribe_response) = unsubscribe_response {
-                        let mut responses_queue =
-                            self.inner.serialized_io.responses_queue.lock().await;
-                        let pos = responses_queue
-                            .pending_serialized_responses
-                            .insert((unsubscribe_response, true));
-                        responses_queue
-                            .pending_serialized_responses_queue
-                            .push_back(pos);
+                    if let Some((unsubscribe_response, batch_entry)) = unsubscribe_response {
                         self.inner
-                            .serialized_io
-                            .on_response_pushed_or_task_destroyed
-                            .notify(usize::MAX);
+                            .answer_request(Some(unsubscribe_response), batch_entry)
+                            .await;
                     }
 
                     // Shrink the list of active subscriptions if necessary.
@@ -309,33 +348,13 @@ impl ClientMainTask {
                         subscription_id,
                     };
                 }
-                WakeUpReason::Message(ToMainTask::RequestResponse(response)) => {
-                    let mut responses_queue = self.inner.serialized_io.responses_queue.lock().await;
-                    let pos = responses_queue
-                        .pending_serialized_responses
-                        .insert((response, true));
-                    responses_queue
-                        .pending_serialized_responses_queue
-                        .push_back(pos);
-                    self.inner
-                        .serialized_io
-                        .on_response_pushed_or_task_destroyed
-                        .notify(usize::MAX);
+                WakeUpReason::Message(ToMainTask::RequestResponse(response, batch_entry)) => {
+                    self.inner.answer_request(Some(response), batch_entry).await;
                     continue;
                 }
                 WakeUpReason::Message(ToMainTask::Notification(notification)) => {
                     // TODO: filter out redundant notifications, as it's the entire point of this module
-                    let mut responses_queue = self.inner.serialized_io.responses_queue.lock().await;
-                    let pos = responses_queue
-                        .pending_serialized_responses
-                        .insert((notification, false));
-                    responses_queue
-                        .pending_serialized_responses_queue
-                        .push_back(pos);
-                    self.inner
-                        .serialized_io
-                        .on_response_pushed_or_task_destroyed
-                        .notify(usize::MAX);
+                    self.inner.push_to_responses_queue(notification, 0).await;
                     continue;
                 }
             };
@@ -345,35 +364,17 @@ impl ClientMainTask {
                     Ok((request_id, method)) => (request_id, method),
                     Err(methods::ParseClientToServerError::Method { request_id, error }) => {
                         let response = error.to_json_error(request_id);
-                        let mut responses_queue =
-                            self.inner.serialized_io.responses_queue.lock().await;
-                        let pos = responses_queue
-                            .pending_serialized_responses
-                            .insert((response, true));
-                        responses_queue
-                            .pending_serialized_responses_queue
-                            .push_back(pos);
-                        self.inner
-                            .serialized_io
-                            .on_response_pushed_or_task_destroyed
-                            .notify(usize::MAX);
+                        self.inner.answer_request(Some(response), batch_entry).await;
+                        continue;
+                    }
+                    Err(methods::ParseClientToServerError::UnknownNotification(_)) => {
+                        // Notifications don't have any response.
+                        self.inner.answer_request(None, batch_entry).await;
                         continue;
                     }
-                    Err(methods::ParseClientToServerError::UnknownNotification(_)) => continue,
                     Err(methods::ParseClientToServerError::JsonRpcParse(_)) => {
                         let response = parse::build_parse_error_response();
-                        let mut responses_queue =
-                            self.inner.serialized_io.responses_queue.lock().await;
-                        let pos = responses_queue
-                            .pending_serialized_responses
-                            .insert((response, true));
-                        responses_queue
-                            .pending_serialized_responses_queue
-                            .push_back(pos);
-                        self.inner
-                            .serialized_io
-                            .on_response_pushed_or_task_destroyed
-                            .notify(usize::MAX);
+                        self.inner.answer_request(Some(response), batch_entry).await;
                         continue;
                     }
                 };
@@ -418,6 +419,7 @@ impl ClientMainTask {
                 | methods::MethodCall::state_getStorageSize { .. }
                 | methods::MethodCall::state_queryStorage { .. }
                 | methods::MethodCall::state_queryStorageAt { .. }
+                | methods::MethodCall::state_traceBlock { .. }
                 | methods::MethodCall::system_accountNextIndex { .. }
                 | methods::MethodCall::system_addReservedPeer { .. }
                 | methods::MethodCall::system_chain { .. }
@@ -455,6 +457,7 @@ impl ClientMainTask {
                                 .responses_notifications_queue
                                 .clone(),
                             request: new_request,
+                            batch_entry,
                             has_sent_response: false,
                         },
                         task: self,
@@ -469,10 +472,28 @@ impl ClientMainTask {
                 | methods::MethodCall::state_subscribeStorage { .. }
                 | methods::MethodCall::transaction_v1_broadcast { .. }
                 | methods::MethodCall::transactionWatch_v1_submitAndWatch { .. }
+                | methods::MethodCall::smoldot_transactionWatch_submitAndWatch { .. }
                 | methods::MethodCall::sudo_network_unstable_watch { .. }
                 | methods::MethodCall::chainHead_v1_follow { .. } => {
                     // Subscription starting requests.
 
+                    // The response to a request of a batch is only sent out once all the
+                    // requests of the batch have been answered, while notifications are sent out
+                    // immediately. Subscriptions started from within a batch would thus generate
+                    // notifications about a subscription that the client doesn't know about yet.
+                    if batch_entry.is_some() {
+                        let response = parse::build_error_response(
+                            request_id,
+                            ErrorResponse::ServerError(
+                                -32000,
+                                "Subscriptions can't be started from within a batch",
+                            ),
+                            None,
+                        );
+                        self.inner.answer_request(Some(response), batch_entry).await;
+                        continue;
+                    }
+
                     // We must check the maximum number of subscriptions.
                     let max_subscriptions =
                         usize::try_from(self.inner.max_active_subscriptions).unwrap_or(usize::MAX);
@@ -483,18 +504,7 @@ impl ClientMainTask {
                             ErrorResponse::ServerError(-32000, "Too many active subscriptions"),
                             None,
                         );
-                        let mut responses_queue =
-                            self.inner.serialized_io.responses_queue.lock().await;
-                        let pos = responses_queue
-                            .pending_serialized_responses
-                            .insert((response, true));
-                        responses_queue
-                            .pending_serialized_responses_queue
-                            .push_back(pos);
-                        self.inner
-                            .serialized_io
-                            .on_response_pushed_or_task_destroyed
-                            .notify(usize::MAX);
+                        self.inner.answer_request(Some(response), batch_entry).await;
                         continue;
                     }
 
@@ -527,6 +537,7 @@ impl ClientMainTask {
                                 .responses_notifications_queue
                                 .clone(),
                             request: new_request,
+                            batch_entry,
                             kill_channel,
                             subscription_id,
                             has_sent_response: false,
@@ -542,6 +553,7 @@ impl ClientMainTask {
                     operation_id: subscription,
                 }
                 | methods::MethodCall::transactionWatch_v1_unwatch { subscription, .. }
+                | methods::MethodCall::smoldot_transactionWatch_unwatch { subscription, .. }
                 | methods::MethodCall::sudo_network_unstable_unwatch { subscription, .. }
                 | methods::MethodCall::chainHead_v1_unfollow {
                     follow_subscription: subscription,
@@ -553,7 +565,7 @@ impl ClientMainTask {
                             kill_channel,
                             unsubscribe_response,
                         }) if unsubscribe_response.is_none() => {
-                            *unsubscribe_response = Some(
+                            *unsubscribe_response = Some((
                                 match parsed_request {
                                     methods::MethodCall::author_unwatchExtrinsic { .. } => {
                                         methods::Response::author_unwatchExtrinsic(true)
@@ -570,6 +582,9 @@ impl ClientMainTask {
                                     methods::MethodCall::transactionWatch_v1_unwatch { .. } => {
                                         methods::Response::transactionWatch_v1_unwatch(())
                                     }
+                                    methods::MethodCall::smoldot_transactionWatch_unwatch {
+                                        ..
+                                    } => methods::Response::smoldot_transactionWatch_unwatch(()),
                                     methods::MethodCall::sudo_network_unstable_unwatch {
                                         ..
                                     } => methods::Response::sudo_network_unstable_unwatch(()),
@@ -579,7 +594,8 @@ impl ClientMainTask {
                                     _ => unreachable!(),
                                 }
                                 .to_json_response(request_id),
-                            );
+                                batch_entry,
+                            ));
 
                             kill_channel.dead.store(true, Ordering::Release);
                             kill_channel.on_dead_changed.notify(usize::MAX);
@@ -605,18 +621,7 @@ impl ClientMainTask {
                                 ),
                             };
 
-                            let mut responses_queue =
-                                self.inner.serialized_io.responses_queue.lock().await;
-                            let pos = responses_queue
-                                .pending_serialized_responses
-                                .insert((response, true));
-                            responses_queue
-                                .pending_serialized_responses_queue
-                                .push_back(pos);
-                            self.inner
-                                .serialized_io
-                                .on_response_pushed_or_task_destroyed
-                                .notify(usize::MAX);
+                            self.inner.answer_request(Some(response), batch_entry).await;
                         }
                     }
                 }
@@ -630,21 +635,24 @@ impl ClientMainTask {
                             unsubscribe_response,
                             kill_channel,
                         }) if unsubscribe_response.is_none() => {
-                            *unsubscribe_response = Some(match parsed_request {
-                                methods::MethodCall::chain_unsubscribeAllHeads { .. } => {
-                                    methods::Response::chain_unsubscribeAllHeads(true)
-                                        .to_json_response(request_id)
-                                }
-                                methods::MethodCall::chain_unsubscribeFinalizedHeads { .. } => {
-                                    methods::Response::chain_unsubscribeFinalizedHeads(true)
-                                        .to_json_response(request_id)
-                                }
-                                methods::MethodCall::chain_unsubscribeNewHeads { .. } => {
-                                    methods::Response::chain_unsubscribeNewHeads(true)
-                                        .to_json_response(request_id)
-                                }
-                                _ => unreachable!(),
-                            });
+                            *unsubscribe_response = Some((
+                                match parsed_request {
+                                    methods::MethodCall::chain_unsubscribeAllHeads { .. } => {
+                                        methods::Response::chain_unsubscribeAllHeads(true)
+                                            .to_json_response(request_id)
+                                    }
+                                    methods::MethodCall::chain_unsubscribeFinalizedHeads {
+                                        ..
+                                    } => methods::Response::chain_unsubscribeFinalizedHeads(true)
+                                        .to_json_response(request_id),
+                                    methods::MethodCall::chain_unsubscribeNewHeads { .. } => {
+                                        methods::Response::chain_unsubscribeNewHeads(true)
+                                            .to_json_response(request_id)
+                                    }
+                                    _ => unreachable!(),
+                                },
+                                batch_entry,
+                            ));
 
                             kill_channel.dead.store(true, Ordering::Release);
                             kill_channel.on_dead_changed.notify(usize::MAX);
@@ -666,18 +674,7 @@ impl ClientMainTask {
                                 _ => unreachable!(),
                             };
 
-                            let mut responses_queue =
-                                self.inner.serialized_io.responses_queue.lock().await;
-                            let pos = responses_queue
-                                .pending_serialized_responses
-                                .insert((response, true));
-                            responses_queue
-                                .pending_serialized_responses_queue
-                                .push_back(pos);
-                            self.inner
-                                .serialized_io
-                                .on_response_pushed_or_task_destroyed
-                                .notify(usize::MAX);
+                            self.inner.answer_request(Some(response), batch_entry).await;
                         }
                     }
                 }
@@ -692,6 +689,145 @@ impl ClientMainTask {
     }
 }
 
+impl Inner {
+    /// Splits the given batch of requests and queues its requests to
+    /// [`Inner::pending_batch_requests`], or immediately answers the batch with an error if it
+    /// is malformed.
+    async fn start_batch(&mut self, batch: String) {
+        let requests = match parse::parse_batch(&batch) {
+            Ok(requests) => requests,
+            Err(_) => {
+                let response = parse::build_parse_error_response();
+                self.answer_request(Some(response), None).await;
+                return;
+            }
+        };
+
+        // Must be consistent with `num_requests_in_message`.
+        if requests.is_empty() {
+            let response = parse::build_error_response("null", ErrorResponse::InvalidRequest, None);
+            self.answer_request(Some(response), None).await;
+            return;
+        }
+        if u32::try_from(requests.len())
+            .map_or(true, |n| n > self.serialized_io.max_requests_in_fly.get())
+        {
+            let response = parse::build_error_response(
+                "null",
+                ErrorResponse::ServerError(-32000, "Too many requests in batch"),
+                None,
+            );
+            self.answer_request(Some(response), None).await;
+            return;
+        }
+
+        let batch_index = self.batches.insert(Batch {
+            responses: (0..requests.len()).map(|_| None).collect(),
+            num_unanswered: requests.len(),
+        });
+        self.pending_batch_requests
+            .extend(
+                requests
+                    .into_iter()
+                    .enumerate()
+                    .map(|(request_index, request)| {
+                        (
+                            request.to_owned(),
+                            BatchEntry {
+                                batch_index,
+                                request_index,
+                            },
+                        )
+                    }),
+            );
+    }
+
+    /// Reports the response to a request, or `None` if the request doesn't have any response
+    /// (i.e. is a notification).
+    ///
+    /// If the request belongs to a batch, the response is stored in [`Inner::batches`] until
+    /// every request of the batch has been answered.
+    async fn answer_request(&mut self, response: Option<String>, batch_entry: Option<BatchEntry>) {
+        let Some(BatchEntry {
+            batch_index,
+            request_index,
+        }) = batch_entry
+        else {
+            match response {
+                Some(response) => self.push_to_responses_queue(response, 1).await,
+                None => self.release_requests_in_fly(1),
+            }
+            return;
+        };
+
+        let batch = &mut self.batches[batch_index];
+        debug_assert!(batch.responses[request_index].is_none());
+        batch.responses[request_index] = response;
+        batch.num_unanswered -= 1;
+        if batch.num_unanswered != 0 {
+            return;
+        }
+
+        let batch = self.batches.remove(batch_index);
+        if self.batches.is_empty() && self.batches.capacity() > 16 {
+            self.batches.shrink_to_fit();
+        }
+
+        // The number of requests in a batch is always inferior to the maximum number of requests
+        // in fly, and thus fits in a `u32`.
+        let num_requests = u32::try_from(batch.responses.len()).unwrap();
+        match parse::build_batch_response(batch.responses.into_iter().flatten()) {
+            Some(response) => self.push_to_responses_queue(response, num_requests).await,
+            None => self.release_requests_in_fly(num_requests),
+        }
+    }
+
+    /// Pushes a response or notification to [`SerializedIo::responses_queue`]. See
+    /// [`SerializedIoResponses::pending_serialized_responses`] for the meaning of
+    /// `num_requests`.
+    async fn push_to_responses_queue(&self, response_or_notif: String, num_requests: u32) {
+        let mut responses_queue = self.serialized_io.responses_queue.lock().await;
+        let pos = responses_queue
+            .pending_serialized_responses
+            .insert((response_or_notif, num_requests));
+        responses_queue
+            .pending_serialized_responses_queue
+            .push_back(pos);
+        self.serialized_io
+            .on_response_pushed_or_task_destroyed
+            .notify(usize::MAX);
+    }
+
+    /// Decreases [`SerializedIo::num_requests_in_fly`] for requests that don't have any response.
+    fn release_requests_in_fly(&self, num_requests: u32) {
+        let _prev_val = self
+            .serialized_io
+            .num_requests_in_fly
+            .fetch_sub(num_requests, Ordering::Release);
+        debug_assert!(_prev_val >= num_requests); // Check underflows.
+        self.serialized_io
+            .on_request_pulled_or_task_destroyed
+            .notify(usize::MAX);
+    }
+}
+
+/// Returns the number of requests that the given JSON-RPC message counts as in
+/// [`SerializedIo::num_requests_in_fly`].
+///
+/// A batch counts as the number of requests it contains. Malformed and empty batches, and batches
+/// that contain more than `max_requests_in_fly` requests, are answered with a single error
+/// response and thus count as one request.
+fn num_requests_in_message(message: &str, max_requests_in_fly: NonZeroU32) -> u32 {
+    if !parse::is_batch(message) {
+        return 1;
+    }
+
+    match parse::batch_len(message).map(u32::try_from) {
+        Ok(Ok(num)) if num != 0 && num <= max_requests_in_fly.get() => num,
+        _ => 1,
+    }
+}
+
 impl fmt::Debug for ClientMainTask {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("ClientMainTask").finish()
@@ -788,13 +924,15 @@ impl SerializedRequestsIo {
                     .pending_serialized_responses_queue
                     .pop_front()
                 {
-                    let (response_or_notif, is_response) = responses_queue
+                    let (response_or_notif, num_requests) = responses_queue
                         .pending_serialized_responses
                         .remove(response_index);
 
-                    if is_response {
-                        let _prev_val = queue.num_requests_in_fly.fetch_sub(1, Ordering::Release);
-                        debug_assert_ne!(_prev_val, u32::MAX); // Check underflows.
+                    if num_requests != 0 {
+                        let _prev_val = queue
+                            .num_requests_in_fly
+                            .fetch_sub(num_requests, Ordering::Release);
+                        debug_assert!(_prev_val >= num_requests); // Check underflows.
                     }
 
                     // Shrink containers if necessary in order to reduce memory usage after a
@@ -832,14 +970,18 @@ impl SerializedRequestsIo {
         }
     }
 
-    /// Adds a JSON-RPC request to the queue of requests of the [`ClientMainTask`]. Waits if the
-    /// queue is full.
+    /// Adds a JSON-RPC request or batch of requests to the queue of requests of the
+    /// [`ClientMainTask`]. Waits if the queue is full.
+    ///
+    /// A batch is answered with a single response once all the requests it contains have been
+    /// answered.
     ///
     /// This might cause a call to [`ClientMainTask::run_until_event`] to return
     /// [`Event::HandleRequest`] or [`Event::HandleSubscriptionStart`].
     pub async fn send_request(&self, request: String) -> Result<(), SendRequestError> {
         // Wait until it is possible to increment `num_requests_in_fly`.
         let mut wait = None;
+        let mut num_requests = None;
         let queue = loop {
             let Some(queue) = self.serialized_io.upgrade() else {
                 return Err(SendRequestError {
@@ -848,14 +990,18 @@ impl SerializedRequestsIo {
                 });
             };
 
+            let num_requests = *num_requests.get_or_insert_with(|| {
+                num_requests_in_message(&request, queue.max_requests_in_fly)
+            });
+
             if queue
                 .num_requests_in_fly
                 .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |old_value| {
-                    if old_value < queue.max_requests_in_fly.get() {
-                        // Considering that `old_value < max`, and `max` fits in a `u32` by
-                        // definition, then `old_value + 1` also always fits in a `u32`. QED.
-                        // There's no risk of overflow.
-                        Some(old_value + 1)
+                    if old_value <= queue.max_requests_in_fly.get() - num_requests {
+                        // Considering that `old_value + num_requests <= max`, and `max` fits in
+                        // a `u32` by definition, then `old_value + num_requests` also always
+                        // fits in a `u32`. QED. There's no risk of overflow.
+                        Some(old_value + num_requests)
                     } else {
                         None
                     }
@@ -878,7 +1024,11 @@ impl SerializedRequestsIo {
         Ok(())
     }
 
-    /// Tries to add a JSON-RPC request to the queue of requests of the [`ClientMainTask`].
+    /// Tries to add a JSON-RPC request or batch of requests to the queue of requests of the
+    /// [`ClientMainTask`].
+    ///
+    /// A batch is answered with a single response once all the requests it contains have been
+    /// answered.
     ///
     /// This might cause a call to [`ClientMainTask::run_until_event`] to return
     /// [`Event::HandleRequest`] or [`Event::HandleSubscriptionStart`].
@@ -891,14 +1041,15 @@ impl SerializedRequestsIo {
         };
 
         // Try to increment `num_requests_in_fly`. Return an error if it is past the maximum.
+        let num_requests = num_requests_in_message(&request, queue.max_requests_in_fly);
         if queue
             .num_requests_in_fly
             .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |old_value| {
-                if old_value < queue.max_requests_in_fly.get() {
-                    // Considering that `old_value < max`, and `max` fits in a `u32` by
-                    // definition, then `old_value + 1` also always fits in a `u32`. QED.
-                    // There's no risk of overflow.
-                    Some(old_value + 1)
+                if old_value <= queue.max_requests_in_fly.get() - num_requests {
+                    // Considering that `old_value + num_requests <= max`, and `max` fits in a
+                    // `u32` by definition, then `old_value + num_requests` also always fits in a
+                    // `u32`. QED. There's no risk of overflow.
+                    Some(old_value + num_requests)
                 } else {
                     None
                 }
@@ -984,6 +1135,8 @@ pub struct RequestProcess {
     responses_notifications_queue: Arc<ResponsesNotificationsQueue>,
     /// Request in JSON form. Guaranteed to decode successfully.
     request: String,
+    /// Batch the request belongs to, if any.
+    batch_entry: Option<BatchEntry>,
     /// `true` if a response has already been sent.
     has_sent_response: bool,
 }
@@ -1009,7 +1162,7 @@ impl RequestProcess {
         let serialized = response.to_json_response(request_id);
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized));
+            .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
         self.responses_notifications_queue
             .on_pushed
             .notify(usize::MAX);
@@ -1027,7 +1180,7 @@ impl RequestProcess {
         let serialized = parse::build_success_response(request_id, "null");
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized));
+            .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
         self.responses_notifications_queue
             .on_pushed
             .notify(usize::MAX);
@@ -1044,7 +1197,7 @@ impl RequestProcess {
         let serialized = parse::build_error_response(request_id, error, None);
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized));
+            .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
         self.responses_notifications_queue
             .on_pushed
             .notify(usize::MAX);
@@ -1064,7 +1217,7 @@ impl RequestProcess {
         let serialized = parse::build_error_response(request_id, error, Some(json));
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized));
+            .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
         self.responses_notifications_queue
             .on_pushed
             .notify(usize::MAX);
@@ -1088,7 +1241,7 @@ impl Drop for RequestProcess {
                 parse::build_error_response(request_id, ErrorResponse::InternalError, None);
             self.responses_notifications_queue
                 .queue
-                .push(ToMainTask::RequestResponse(serialized));
+                .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
             self.responses_notifications_queue
                 .on_pushed
                 .notify(usize::MAX);
@@ -1109,6 +1262,8 @@ pub struct SubscriptionStartProcess {
     kill_channel: Arc<SubscriptionKillChannel>,
     /// Request in JSON form. Guaranteed to decode successfully.
     request: String,
+    /// Batch the request belongs to, if any.
+    batch_entry: Option<BatchEntry>,
     /// Identifier of the subscription. Assigned by the client task.
     subscription_id: String,
     /// `true` if a response has already been sent.
@@ -1165,6 +1320,11 @@ impl SubscriptionStartProcess {
                     &self.subscription_id,
                 ))
             }
+            methods::MethodCall::smoldot_transactionWatch_submitAndWatch { .. } => {
+                methods::Response::smoldot_transactionWatch_submitAndWatch(Cow::Borrowed(
+                    &self.subscription_id,
+                ))
+            }
             methods::MethodCall::sudo_network_unstable_watch { .. } => {
                 methods::Response::sudo_network_unstable_watch(Cow::Borrowed(&self.subscription_id))
             }
@@ -1177,7 +1337,10 @@ impl SubscriptionStartProcess {
 
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized_response));
+            .push(ToMainTask::RequestResponse(
+                serialized_response,
+                self.batch_entry,
+            ));
         self.responses_notifications_queue
             .on_pushed
             .notify(usize::MAX);
@@ -1201,7 +1364,7 @@ impl SubscriptionStartProcess {
         let serialized = parse::build_error_response(request_id, error, None);
         self.responses_notifications_queue
             .queue
-            .push(ToMainTask::RequestResponse(serialized));
+            .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
         self.responses_notifications_queue
             .queue
             .push(ToMainTask::SubscriptionDestroyed {
@@ -1230,7 +1393,7 @@ impl Drop for SubscriptionStartProcess {
                 parse::build_error_response(request_id, ErrorResponse::InternalError, None);
             self.responses_notifications_queue
                 .queue
-                .push(ToMainTask::RequestResponse(serialized));
+                .push(ToMainTask::RequestResponse(serialized, self.batch_entry));
             self.responses_notifications_queue
                 .queue
                 .push(ToMainTask::SubscriptionDestroyed {
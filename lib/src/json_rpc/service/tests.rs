@@ -0,0 +1,174 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{client_main_task, Config, Event, TrySendRequestErrorCause};
+use crate::json_rpc::methods;
+
+use core::num::NonZeroU32;
+use futures_lite::future;
+
+#[test]
+fn batch_responses_in_order() {
+    futures_executor::block_on(async move {
+        let (mut task, io) = client_main_task(Config {
+            max_pending_requests: NonZeroU32::new(8).unwrap(),
+            max_active_subscriptions: 0,
+        });
+
+        io.try_send_request(
+            r#"[{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]},
+                {"jsonrpc":"2.0","method":"foo","params":[]},
+                {"jsonrpc":"2.0","id":2,"method":"system_version","params":[]}]"#
+                .to_owned(),
+        )
+        .unwrap();
+
+        let mut requests = Vec::new();
+        for _ in 0..2 {
+            match task.run_until_event().await {
+                Event::HandleRequest {
+                    task: t,
+                    request_process,
+                } => {
+                    task = t;
+                    requests.push(request_process);
+                }
+                _ => panic!(),
+            }
+        }
+
+        // Answer the requests in the reverse order.
+        requests
+            .pop()
+            .unwrap()
+            .respond(methods::Response::system_version("2.0".into()));
+        requests
+            .pop()
+            .unwrap()
+            .respond(methods::Response::system_name("foo".into()));
+
+        let response = future::or(io.wait_next_response(), async {
+            let _ = task.run_until_event().await;
+            unreachable!()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            r#"[{"jsonrpc":"2.0","id":1,"result":"foo"},{"jsonrpc":"2.0","id":2,"result":"2.0"}]"#
+        );
+    });
+}
+
+#[test]
+fn batch_counts_towards_pending_requests() {
+    let (_task, io) = client_main_task(Config {
+        max_pending_requests: NonZeroU32::new(3).unwrap(),
+        max_active_subscriptions: 0,
+    });
+
+    io.try_send_request(
+        r#"[{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]},
+            {"jsonrpc":"2.0","id":2,"method":"system_name","params":[]}]"#
+            .to_owned(),
+    )
+    .unwrap();
+    io.try_send_request(
+        r#"{"jsonrpc":"2.0","id":3,"method":"system_name","params":[]}"#.to_owned(),
+    )
+    .unwrap();
+
+    let error = io
+        .try_send_request(
+            r#"{"jsonrpc":"2.0","id":4,"method":"system_name","params":[]}"#.to_owned(),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        error.cause,
+        TrySendRequestErrorCause::TooManyPendingRequests
+    ));
+}
+
+#[test]
+fn invalid_batches_answered_with_error() {
+    futures_executor::block_on(async move {
+        let (task, io) = client_main_task(Config {
+            max_pending_requests: NonZeroU32::new(1).unwrap(),
+            max_active_subscriptions: 0,
+        });
+
+        let _task = async {
+            let _ = task.run_until_event().await;
+            unreachable!()
+        };
+        futures_lite::pin!(_task);
+
+        for (request, expected_code) in [("[]", -32600), ("[{}, {}]", -32000), ("[{}", -32700)] {
+            io.try_send_request(request.to_owned()).unwrap();
+            let response = future::or(io.wait_next_response(), _task.as_mut())
+                .await
+                .unwrap();
+            match crate::json_rpc::parse::parse_response(&response).unwrap() {
+                crate::json_rpc::parse::Response::ParseError { error_code, .. } => {
+                    assert_eq!(error_code, expected_code)
+                }
+                _ => panic!(),
+            }
+        }
+    });
+}
+
+#[test]
+fn no_subscription_in_batch() {
+    futures_executor::block_on(async move {
+        let (task, io) = client_main_task(Config {
+            max_pending_requests: NonZeroU32::new(8).unwrap(),
+            max_active_subscriptions: 8,
+        });
+
+        io.try_send_request(
+            r#"[{"jsonrpc":"2.0","id":1,"method":"chain_subscribeNewHeads","params":[]},
+                {"jsonrpc":"2.0","id":2,"method":"system_name","params":[]}]"#
+                .to_owned(),
+        )
+        .unwrap();
+
+        let (task, request_process) = match task.run_until_event().await {
+            Event::HandleRequest {
+                task,
+                request_process,
+            } => (task, request_process),
+            _ => panic!(),
+        };
+        request_process.respond(methods::Response::system_name("foo".into()));
+
+        let response = future::or(io.wait_next_response(), async {
+            let _ = task.run_until_event().await;
+            unreachable!()
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            response,
+            r#"[{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Subscriptions can't be started from within a batch"}},{"jsonrpc":"2.0","id":2,"result":"foo"}]"#
+        );
+    });
+}
@@ -0,0 +1,382 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Building and decoding the storage keys of FRAME-based runtimes.
+//!
+//! In FRAME-based runtimes, each storage item belongs to a pallet. The key of a plain storage
+//! value is the concatenation of `twox_128(pallet_name)` and `twox_128(storage_item_name)`.
+//!
+//! Storage maps (and double maps, and n-maps) append to these 32 bytes the hashes of each of the
+//! keys of the map, each key being hashed with the [`StorageHasher`] declared in the runtime.
+//! Some hashers, such as [`StorageHasher::Blake2_128Concat`], are said to be *reversible*: the
+//! unhashed key is appended after its hash, making it possible to retrieve the key of a map
+//! entry from its storage key.
+//!
+//! Use [`prefix_scan`] in order to iterate over all the entries of a map by means of storage
+//! proofs, then [`decode_storage_map_key`] in order to retrieve the keys of each entry.
+
+use crate::trie::prefix_proof;
+
+use alloc::vec::Vec;
+use core::hash::Hasher as _;
+
+/// Hashing algorithm applied on a key of a storage map.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
+pub enum StorageHasher {
+    /// 16 bytes blake2 hash of the key.
+    Blake2_128,
+    /// 32 bytes blake2 hash of the key.
+    Blake2_256,
+    /// 16 bytes blake2 hash of the key, followed with the key itself.
+    Blake2_128Concat,
+    /// 16 bytes xxhash of the key.
+    Twox128,
+    /// 32 bytes xxhash of the key.
+    Twox256,
+    /// 8 bytes xxhash of the key, followed with the key itself.
+    Twox64Concat,
+    /// The key itself, unhashed.
+    Identity,
+}
+
+impl StorageHasher {
+    /// Returns `true` if the unhashed key can be retrieved from the output of the hasher.
+    pub fn is_reversible(&self) -> bool {
+        matches!(
+            self,
+            StorageHasher::Blake2_128Concat | StorageHasher::Twox64Concat | StorageHasher::Identity
+        )
+    }
+
+    /// Returns the number of bytes of the hash that precedes the unhashed key, or the length of
+    /// the hash if the hasher isn't reversible.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            StorageHasher::Blake2_128 | StorageHasher::Blake2_128Concat => 16,
+            StorageHasher::Blake2_256 | StorageHasher::Twox256 => 32,
+            StorageHasher::Twox128 => 16,
+            StorageHasher::Twox64Concat => 8,
+            StorageHasher::Identity => 0,
+        }
+    }
+
+    /// Hashes the given key. The output contains the unhashed key as well if the hasher is
+    /// reversible.
+    pub fn hash(&self, key: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.hash_len() + key.len());
+
+        match self {
+            StorageHasher::Blake2_128 | StorageHasher::Blake2_128Concat => {
+                out.extend_from_slice(blake2_rfc::blake2b::blake2b(16, &[], key).as_bytes());
+            }
+            StorageHasher::Blake2_256 => {
+                out.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], key).as_bytes());
+            }
+            StorageHasher::Twox128 => out.extend_from_slice(&twox(key, 2)),
+            StorageHasher::Twox256 => out.extend_from_slice(&twox(key, 4)),
+            StorageHasher::Twox64Concat => out.extend_from_slice(&twox(key, 1)),
+            StorageHasher::Identity => {}
+        }
+
+        if self.is_reversible() {
+            out.extend_from_slice(key);
+        }
+
+        out
+    }
+}
+
+/// Returns the storage key of a plain storage value. This is also the prefix that all the
+/// entries of a storage map share.
+pub fn storage_value_key(pallet_name: &str, storage_item_name: &str) -> [u8; 32] {
+    let mut out = [0; 32];
+    out[..16].copy_from_slice(&twox(pallet_name.as_bytes(), 2));
+    out[16..].copy_from_slice(&twox(storage_item_name.as_bytes(), 2));
+    out
+}
+
+/// Returns the storage key of an entry of a storage map.
+///
+/// `keys` must contain the SCALE-encoded keys of the map, alongside with their hasher. Storage
+/// maps have one key, double maps two keys, and n-maps any number of keys.
+///
+/// If `keys` contains fewer keys than the storage map, the returned value is a prefix that all
+/// the entries that share these first keys have in common.
+pub fn storage_map_key<'a>(
+    pallet_name: &str,
+    storage_item_name: &str,
+    keys: impl IntoIterator<Item = (StorageHasher, &'a [u8])>,
+) -> Vec<u8> {
+    let mut out = storage_value_key(pallet_name, storage_item_name).to_vec();
+    for (hasher, key) in keys {
+        out.extend_from_slice(&hasher.hash(key));
+    }
+    out
+}
+
+/// Key of a storage map decoded by [`decode_storage_map_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedKey<'a> {
+    /// Hash of the key, as found in the storage key. Empty if the hasher is
+    /// [`StorageHasher::Identity`].
+    pub hash: &'a [u8],
+    /// Unhashed SCALE-encoded key. `None` if the hasher isn't reversible.
+    pub key: Option<&'a [u8]>,
+}
+
+/// Decodes a storage key of an entry of a storage map.
+///
+/// `key_hashers` must contain the hasher of each key of the map, alongside with the length of
+/// the SCALE-encoded unhashed key if the hasher is reversible. The length can be `None` for the
+/// last key, in which case the rest of the storage key is assumed to be the unhashed key. The
+/// length is ignored for hashers that aren't reversible.
+///
+/// Returns an error if the storage key doesn't belong to the given storage item, or doesn't have
+/// the expected length.
+pub fn decode_storage_map_key<'a>(
+    pallet_name: &str,
+    storage_item_name: &str,
+    key_hashers: &[(StorageHasher, Option<usize>)],
+    storage_key: &'a [u8],
+) -> Result<Vec<DecodedKey<'a>>, DecodeError> {
+    let Some(mut remaining) =
+        storage_key.strip_prefix(&storage_value_key(pallet_name, storage_item_name)[..])
+    else {
+        return Err(DecodeError::PrefixMismatch);
+    };
+
+    let mut out = Vec::with_capacity(key_hashers.len());
+
+    for (index, (hasher, key_len)) in key_hashers.iter().enumerate() {
+        if remaining.len() < hasher.hash_len() {
+            return Err(DecodeError::TooShort);
+        }
+        let (hash, rest) = remaining.split_at(hasher.hash_len());
+
+        if !hasher.is_reversible() {
+            out.push(DecodedKey { hash, key: None });
+            remaining = rest;
+            continue;
+        }
+
+        let key_len = match key_len {
+            Some(len) => *len,
+            None if index == key_hashers.len() - 1 => rest.len(),
+            None => return Err(DecodeError::UnknownKeyLength),
+        };
+
+        if rest.len() < key_len {
+            return Err(DecodeError::TooShort);
+        }
+        let (key, rest) = rest.split_at(key_len);
+
+        // Make sure that the hash matches the key, in order to detect invalid lengths.
+        if hasher.hash(key)[..hasher.hash_len()] != *hash {
+            return Err(DecodeError::HashMismatch);
+        }
+
+        out.push(DecodedKey {
+            hash,
+            key: Some(key),
+        });
+        remaining = rest;
+    }
+
+    if !remaining.is_empty() {
+        return Err(DecodeError::TooLong);
+    }
+
+    Ok(out)
+}
+
+/// Error potentially returned by [`decode_storage_map_key`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeError {
+    /// Storage key doesn't start with the prefix of the storage item.
+    PrefixMismatch,
+    /// Storage key is shorter than expected.
+    TooShort,
+    /// Storage key is longer than expected.
+    TooLong,
+    /// The length of a key that isn't the last one is unknown.
+    UnknownKeyLength,
+    /// Unhashed key doesn't match its hash.
+    HashMismatch,
+}
+
+/// Starts a scan of all the entries of a storage map whose first keys are the ones passed as
+/// parameter. Pass an empty list of keys in order to scan the entire map.
+///
+/// See [`prefix_proof::prefix_scan`].
+pub fn prefix_scan<'a>(
+    pallet_name: &str,
+    storage_item_name: &str,
+    first_keys: impl IntoIterator<Item = (StorageHasher, &'a [u8])>,
+    trie_root_hash: [u8; 32],
+    full_storage_values_required: bool,
+) -> prefix_proof::PrefixScan {
+    let prefix = storage_map_key(pallet_name, storage_item_name, first_keys);
+    prefix_proof::prefix_scan(prefix_proof::Config {
+        prefix: &prefix,
+        trie_root_hash,
+        full_storage_values_required,
+    })
+}
+
+/// Calculates the xxhash of the given data with `num_seeds` seeds, in other words returns
+/// `8 * num_seeds` bytes.
+fn twox(data: &[u8], num_seeds: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(usize::try_from(num_seeds).unwrap() * 8);
+    for seed in 0..num_seeds {
+        let mut hasher = twox_hash::XxHash::with_seed(seed);
+        hasher.write(data);
+        out.extend_from_slice(&hasher.finish().to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageHasher;
+
+    #[test]
+    fn system_account_key() {
+        let alice = hex::decode("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d")
+            .unwrap();
+
+        let key = super::storage_map_key(
+            "System",
+            "Account",
+            [(StorageHasher::Blake2_128Concat, &alice[..])],
+        );
+
+        assert_eq!(
+            hex::encode(&key),
+            "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9\
+             de1e86a9a8c739864cf3cc5ec2bea59fd43593c715fdd31c61141abd04a99fd6\
+             822c8558854ccde39a5684e7a56da27d"
+        );
+
+        let decoded = super::decode_storage_map_key(
+            "System",
+            "Account",
+            &[(StorageHasher::Blake2_128Concat, None)],
+            &key,
+        )
+        .unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].key, Some(&alice[..]));
+    }
+
+    #[test]
+    fn storage_value_key() {
+        assert_eq!(
+            hex::encode(super::storage_value_key("System", "Number")),
+            "26aa394eea5630e07c48ae0c9558cef702a5c1b19ab7a04f536c519aca4983ac"
+        );
+    }
+
+    #[test]
+    fn double_map_round_trip() {
+        let hashers = [
+            StorageHasher::Blake2_128,
+            StorageHasher::Blake2_256,
+            StorageHasher::Blake2_128Concat,
+            StorageHasher::Twox128,
+            StorageHasher::Twox256,
+            StorageHasher::Twox64Concat,
+            StorageHasher::Identity,
+        ];
+
+        for hasher1 in hashers {
+            for hasher2 in hashers {
+                let key = super::storage_map_key(
+                    "Pallet",
+                    "Item",
+                    [(hasher1, &[1, 2, 3][..]), (hasher2, &[4, 5, 6, 7][..])],
+                );
+
+                let decoded = super::decode_storage_map_key(
+                    "Pallet",
+                    "Item",
+                    &[(hasher1, Some(3)), (hasher2, None)],
+                    &key,
+                )
+                .unwrap();
+
+                assert_eq!(decoded.len(), 2);
+                assert_eq!(decoded[0].hash.len(), hasher1.hash_len());
+                assert_eq!(decoded[1].hash.len(), hasher2.hash_len());
+                assert_eq!(
+                    decoded[0].key,
+                    hasher1.is_reversible().then_some(&[1, 2, 3][..])
+                );
+                assert_eq!(
+                    decoded[1].key,
+                    hasher2.is_reversible().then_some(&[4, 5, 6, 7][..])
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        let key = super::storage_map_key(
+            "Pallet",
+            "Item",
+            [(StorageHasher::Twox64Concat, &[1, 2, 3][..])],
+        );
+
+        assert!(matches!(
+            super::decode_storage_map_key(
+                "Pallet",
+                "Other",
+                &[(StorageHasher::Twox64Concat, None)],
+                &key
+            ),
+            Err(super::DecodeError::PrefixMismatch)
+        ));
+        assert!(matches!(
+            super::decode_storage_map_key(
+                "Pallet",
+                "Item",
+                &[(StorageHasher::Twox64Concat, Some(2))],
+                &key
+            ),
+            Err(super::DecodeError::HashMismatch)
+        ));
+        assert!(matches!(
+            super::decode_storage_map_key(
+                "Pallet",
+                "Item",
+                &[(StorageHasher::Twox64Concat, Some(4))],
+                &key
+            ),
+            Err(super::DecodeError::TooShort)
+        ));
+        assert!(matches!(
+            super::decode_storage_map_key(
+                "Pallet",
+                "Item",
+                &[(StorageHasher::Twox128, None)],
+                &key
+            ),
+            Err(super::DecodeError::TooShort)
+        ));
+    }
+}
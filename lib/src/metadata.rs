@@ -0,0 +1,162 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runtime metadata retrieval and decoding.
+//!
+//! The runtime of a FRAME-based chain can provide, through a runtime call, a description of
+//! itself called *the metadata*. The metadata notably contains the list of pallets, the list of
+//! storage items of each pallet, the list of calls that can be performed, the list of events
+//! that can be generated, and the definitions of all the types that are used by the runtime.
+//!
+//! # Retrieving the metadata
+//!
+//! The metadata can be obtained by calling the `Metadata_metadata` runtime function, which
+//! returns the metadata in version 14, or by calling the `Metadata_metadata_at_version` runtime
+//! function, which returns the metadata in the requested version if the runtime supports it.
+//! The list of versions supported by the runtime can be obtained by calling the
+//! `Metadata_metadata_versions` runtime function.
+//!
+//! Use [`metadata_versions`] and [`metadata_at_version`] in order to start these runtime calls,
+//! then [`decode_metadata_versions_output`] and [`decode_metadata_at_version_output`] in order
+//! to decode their output.
+//!
+//! # Decoding the metadata
+//!
+//! Once retrieved, the metadata can be decoded with [`decode`]. Versions 14 and 15 of the
+//! metadata format are supported.
+//!
+//! The decoded metadata can then be used in order to decode extrinsics, with
+//! [`extrinsic::decode_extrinsic`], and the events generated by a block, with
+//! [`events::decode_events`].
+
+use crate::executor::{host, runtime_call};
+
+use alloc::vec::Vec;
+use core::iter;
+
+pub mod decode;
+pub mod events;
+pub mod extrinsic;
+pub mod value;
+
+pub use decode::{decode, DecodeError, MetadataRef};
+pub use events::{decode_events, DecodedEvent, EventPhase};
+pub use extrinsic::{decode_extrinsic, DecodedExtrinsic};
+pub use value::{decode_value, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// Name of the runtime function that returns the metadata in a specific version.
+pub const METADATA_AT_VERSION_FUNCTION_NAME: &str = "Metadata_metadata_at_version";
+
+/// Name of the runtime function that returns the list of versions of the metadata that the
+/// runtime supports.
+pub const METADATA_VERSIONS_FUNCTION_NAME: &str = "Metadata_metadata_versions";
+
+/// Version of the metadata that the runtime can use to indicate that its metadata is unstable.
+pub const UNSTABLE_METADATA_VERSION: u32 = u32::MAX;
+
+/// Produces the input to pass to the `Metadata_metadata_at_version` runtime call.
+pub fn metadata_at_version_parameters(version: u32) -> impl Iterator<Item = [u8; 4]> + Clone {
+    iter::once(version.to_le_bytes())
+}
+
+/// Starts the `Metadata_metadata_at_version` runtime call with the given virtual machine.
+///
+/// Once [`runtime_call::RuntimeCall::Finished`] is reached, pass the output of the call to
+/// [`decode_metadata_at_version_output`].
+pub fn metadata_at_version(
+    virtual_machine: host::HostVmPrototype,
+    version: u32,
+) -> Result<runtime_call::RuntimeCall, (host::StartErr, host::HostVmPrototype)> {
+    runtime_call::run(runtime_call::Config {
+        virtual_machine,
+        function_to_call: METADATA_AT_VERSION_FUNCTION_NAME,
+        parameter: metadata_at_version_parameters(version),
+        storage_proof_size_behavior:
+            runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
+        storage_main_trie_changes: Default::default(),
+        max_log_level: 0,
+        fuel_limit: None,
+        calculate_trie_changes: false,
+        record_storage_proof: false,
+        record_trace: false,
+    })
+}
+
+/// Starts the `Metadata_metadata_versions` runtime call with the given virtual machine.
+///
+/// Once [`runtime_call::RuntimeCall::Finished`] is reached, pass the output of the call to
+/// [`decode_metadata_versions_output`].
+pub fn metadata_versions(
+    virtual_machine: host::HostVmPrototype,
+) -> Result<runtime_call::RuntimeCall, (host::StartErr, host::HostVmPrototype)> {
+    runtime_call::run(runtime_call::Config {
+        virtual_machine,
+        function_to_call: METADATA_VERSIONS_FUNCTION_NAME,
+        parameter: iter::empty::<&'static [u8]>(),
+        storage_proof_size_behavior:
+            runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
+        storage_main_trie_changes: Default::default(),
+        max_log_level: 0,
+        fuel_limit: None,
+        calculate_trie_changes: false,
+        record_storage_proof: false,
+        record_trace: false,
+    })
+}
+
+/// Decodes the output of the `Metadata_metadata_at_version` runtime call.
+///
+/// Returns `None` if the runtime doesn't support the requested version. Otherwise, returns the
+/// metadata, which can then be passed to [`decode`].
+pub fn decode_metadata_at_version_output(
+    scale_encoded: &[u8],
+) -> Result<Option<&[u8]>, DecodeRuntimeCallOutputError> {
+    match scale_encoded.split_first() {
+        Some((0, [])) => Ok(None),
+        Some((1, rest)) => crate::json_rpc::methods::remove_metadata_length_prefix(rest)
+            .map(Some)
+            .map_err(|_| DecodeRuntimeCallOutputError),
+        _ => Err(DecodeRuntimeCallOutputError),
+    }
+}
+
+/// Decodes the output of the `Metadata_metadata_versions` runtime call.
+pub fn decode_metadata_versions_output(
+    scale_encoded: &[u8],
+) -> Result<Vec<u32>, DecodeRuntimeCallOutputError> {
+    nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| {
+            nom::multi::many_m_n(
+                num_elems,
+                num_elems,
+                nom::number::complete::le_u32::<_, nom::error::Error<&[u8]>>,
+            )
+        },
+    ))(scale_encoded)
+    .map(|(_, versions)| versions)
+    .map_err(|_| DecodeRuntimeCallOutputError)
+}
+
+/// Error potentially returned by [`decode_metadata_at_version_output`] or
+/// [`decode_metadata_versions_output`].
+#[derive(Debug, Clone, derive_more::Display)]
+#[display(fmt = "Failed to decode the output of the runtime call")]
+pub struct DecodeRuntimeCallOutputError;
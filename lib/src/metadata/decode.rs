@@ -0,0 +1,775 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the runtime metadata.
+//!
+//! The metadata starts with the four bytes `meta`, followed with a byte indicating the version
+//! of the format of the metadata, followed with the metadata itself.
+//!
+//! Versions 14 and 15 share the same format for the type registry. All the types that the
+//! runtime uses are found in [`MetadataRef::types`], and the rest of the metadata refers to
+//! these types through their identifier.
+
+use crate::json_rpc::storage_keys::StorageHasher;
+
+use alloc::vec::Vec;
+
+/// Decodes the given metadata.
+///
+/// The metadata must not be prefixed with its length. See
+/// [`crate::json_rpc::methods::remove_metadata_length_prefix`].
+pub fn decode(metadata: &[u8]) -> Result<MetadataRef<'_>, DecodeError> {
+    let Some(after_magic) = metadata.strip_prefix(b"meta") else {
+        return Err(DecodeError::InvalidMagicNumber);
+    };
+
+    let (version, after_version) = match after_magic.split_first() {
+        Some((14, rest)) => (MetadataVersion::V14, rest),
+        Some((15, rest)) => (MetadataVersion::V15, rest),
+        Some((v, _)) => return Err(DecodeError::UnsupportedVersion(*v)),
+        None => return Err(DecodeError::InvalidFormat),
+    };
+
+    match nom::combinator::all_consuming(move |bytes| metadata_content(bytes, version))(
+        after_version,
+    ) {
+        Ok((_, metadata)) => Ok(metadata),
+        Err(_) => Err(DecodeError::InvalidFormat),
+    }
+}
+
+/// Error potentially returned by [`decode`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeError {
+    /// Metadata doesn't start with the expected magic number.
+    InvalidMagicNumber,
+    /// Version of the metadata isn't supported.
+    #[display(fmt = "Unsupported metadata version: {_0}")]
+    UnsupportedVersion(u8),
+    /// Failed to decode the metadata.
+    InvalidFormat,
+}
+
+/// Version of the format of the metadata.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetadataVersion {
+    V14,
+    V15,
+}
+
+/// Decoded metadata.
+#[derive(Debug, Clone)]
+pub struct MetadataRef<'a> {
+    /// Version of the format of the metadata.
+    pub version: MetadataVersion,
+    /// Registry of all the types used by the runtime.
+    pub types: Vec<PortableType<'a>>,
+    /// List of pallets of the runtime.
+    pub pallets: Vec<PalletMetadata<'a>>,
+    /// Information about the format of the extrinsics.
+    pub extrinsic: ExtrinsicMetadata<'a>,
+    /// Type of the `Runtime` struct.
+    pub runtime_ty: u32,
+    /// List of runtime APIs the runtime provides. Always empty in version 14.
+    pub apis: Vec<RuntimeApiMetadata<'a>>,
+    /// Types of the enums that aggregate the calls, events, and errors of all the pallets.
+    /// Always `None` in version 14.
+    pub outer_enums: Option<OuterEnums>,
+    /// Custom values. Always empty in version 14.
+    pub custom: Vec<CustomValueMetadata<'a>>,
+}
+
+impl<'a> MetadataRef<'a> {
+    /// Returns the type with the given identifier, or `None` if it can't be found.
+    pub fn type_by_id(&self, id: u32) -> Option<&PortableType<'a>> {
+        // Identifiers are in practice always equal to the position of the type in the list,
+        // but this isn't guaranteed.
+        match self.types.get(usize::try_from(id).ok()?) {
+            Some(ty) if ty.id == id => Some(ty),
+            _ => self.types.iter().find(|ty| ty.id == id),
+        }
+    }
+
+    /// Returns the pallet with the given name, or `None` if it can't be found.
+    pub fn pallet_by_name(&self, name: &str) -> Option<&PalletMetadata<'a>> {
+        self.pallets.iter().find(|p| p.name == name)
+    }
+
+    /// Returns the pallet with the given index, or `None` if it can't be found.
+    pub fn pallet_by_index(&self, index: u8) -> Option<&PalletMetadata<'a>> {
+        self.pallets.iter().find(|p| p.index == index)
+    }
+}
+
+/// Type found in the registry. See [`MetadataRef::types`].
+#[derive(Debug, Clone)]
+pub struct PortableType<'a> {
+    /// Identifier of the type, used to refer to it.
+    pub id: u32,
+    /// Path of the type, for example `["sp_core", "crypto", "AccountId32"]`. Empty for types
+    /// that don't have a path, such as primitives.
+    pub path: Vec<&'a str>,
+    /// Generic parameters of the type.
+    pub params: Vec<TypeParameter<'a>>,
+    /// Definition of the type.
+    pub type_def: TypeDef<'a>,
+    /// Documentation of the type.
+    pub docs: Vec<&'a str>,
+}
+
+/// Generic parameter of a type. See [`PortableType::params`].
+#[derive(Debug, Clone)]
+pub struct TypeParameter<'a> {
+    /// Name of the parameter.
+    pub name: &'a str,
+    /// Type of the parameter, or `None` if it isn't known.
+    pub ty: Option<u32>,
+}
+
+/// Definition of a type. See [`PortableType::type_def`].
+#[derive(Debug, Clone)]
+pub enum TypeDef<'a> {
+    /// Struct.
+    Composite(Vec<Field<'a>>),
+    /// Enum.
+    Variant(Vec<Variant<'a>>),
+    /// List of elements of the given type, prefixed with its length.
+    Sequence(u32),
+    /// Fixed-size list of elements.
+    Array {
+        /// Number of elements.
+        len: u32,
+        /// Type of the elements.
+        ty: u32,
+    },
+    /// Tuple of the given types.
+    Tuple(Vec<u32>),
+    /// Primitive type.
+    Primitive(Primitive),
+    /// Integer of the given type, encoded in a compact way.
+    Compact(u32),
+    /// Sequence of bits.
+    BitSequence {
+        /// Type used to store the bits.
+        bit_store_ty: u32,
+        /// Type indicating the order of the bits.
+        bit_order_ty: u32,
+    },
+}
+
+/// Primitive type. See [`TypeDef::Primitive`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Primitive {
+    Bool,
+    Char,
+    Str,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    U256,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    I256,
+}
+
+/// Field of a struct or of an enum variant.
+#[derive(Debug, Clone)]
+pub struct Field<'a> {
+    /// Name of the field, or `None` for tuple-like structs and variants.
+    pub name: Option<&'a str>,
+    /// Type of the field.
+    pub ty: u32,
+    /// Name of the type of the field as it appears in the source code of the runtime.
+    pub type_name: Option<&'a str>,
+    /// Documentation of the field.
+    pub docs: Vec<&'a str>,
+}
+
+/// Variant of an enum. See [`TypeDef::Variant`].
+#[derive(Debug, Clone)]
+pub struct Variant<'a> {
+    /// Name of the variant.
+    pub name: &'a str,
+    /// Fields of the variant.
+    pub fields: Vec<Field<'a>>,
+    /// Index of the variant, used when encoding the enum.
+    pub index: u8,
+    /// Documentation of the variant.
+    pub docs: Vec<&'a str>,
+}
+
+/// Pallet of the runtime. See [`MetadataRef::pallets`].
+#[derive(Debug, Clone)]
+pub struct PalletMetadata<'a> {
+    /// Name of the pallet.
+    pub name: &'a str,
+    /// Storage items of the pallet, if any.
+    pub storage: Option<PalletStorageMetadata<'a>>,
+    /// Type of the enum containing the calls of the pallet, if any.
+    pub calls_ty: Option<u32>,
+    /// Type of the enum containing the events of the pallet, if any.
+    pub event_ty: Option<u32>,
+    /// Constants of the pallet.
+    pub constants: Vec<PalletConstantMetadata<'a>>,
+    /// Type of the enum containing the errors of the pallet, if any.
+    pub error_ty: Option<u32>,
+    /// Index of the pallet, used when encoding calls and events.
+    pub index: u8,
+    /// Documentation of the pallet. Always empty in version 14.
+    pub docs: Vec<&'a str>,
+}
+
+/// Storage items of a pallet. See [`PalletMetadata::storage`].
+#[derive(Debug, Clone)]
+pub struct PalletStorageMetadata<'a> {
+    /// Prefix of the storage items, used to build their keys. Generally equal to the name of
+    /// the pallet.
+    pub prefix: &'a str,
+    /// List of storage items.
+    pub entries: Vec<StorageEntryMetadata<'a>>,
+}
+
+/// Storage item. See [`PalletStorageMetadata::entries`].
+#[derive(Debug, Clone)]
+pub struct StorageEntryMetadata<'a> {
+    /// Name of the storage item.
+    pub name: &'a str,
+    /// Behavior when the storage item isn't present in the storage.
+    pub modifier: StorageEntryModifier,
+    /// Type of the storage item.
+    pub ty: StorageEntryType,
+    /// SCALE-encoded value of the storage item when it isn't present in the storage.
+    pub default: &'a [u8],
+    /// Documentation of the storage item.
+    pub docs: Vec<&'a str>,
+}
+
+/// See [`StorageEntryMetadata::modifier`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StorageEntryModifier {
+    /// The storage item is absent from the storage if it has no value.
+    Optional,
+    /// The storage item is absent from the storage if it is equal to
+    /// [`StorageEntryMetadata::default`].
+    Default,
+}
+
+/// See [`StorageEntryMetadata::ty`].
+#[derive(Debug, Clone)]
+pub enum StorageEntryType {
+    /// Single value of the given type.
+    Plain(u32),
+    /// Map of keys to values.
+    Map {
+        /// Hashers of each of the keys of the map. Contains more than one element for double
+        /// maps and n-maps.
+        hashers: Vec<StorageHasher>,
+        /// Type of the key. If there are multiple hashers, this is a tuple.
+        key_ty: u32,
+        /// Type of the values.
+        value_ty: u32,
+    },
+}
+
+/// Constant of a pallet. See [`PalletMetadata::constants`].
+#[derive(Debug, Clone)]
+pub struct PalletConstantMetadata<'a> {
+    /// Name of the constant.
+    pub name: &'a str,
+    /// Type of the constant.
+    pub ty: u32,
+    /// SCALE-encoded value of the constant.
+    pub value: &'a [u8],
+    /// Documentation of the constant.
+    pub docs: Vec<&'a str>,
+}
+
+/// Information about the format of the extrinsics. See [`MetadataRef::extrinsic`].
+#[derive(Debug, Clone)]
+pub struct ExtrinsicMetadata<'a> {
+    /// Version of the format of the extrinsics.
+    pub version: u8,
+    /// Type of the extrinsics. Always `None` in version 15.
+    pub ty: Option<u32>,
+    /// Type of the address of the signer of extrinsics. Always `None` in version 14.
+    pub address_ty: Option<u32>,
+    /// Type of the call of extrinsics. Always `None` in version 14.
+    pub call_ty: Option<u32>,
+    /// Type of the signature of extrinsics. Always `None` in version 14.
+    pub signature_ty: Option<u32>,
+    /// Type of the signed extensions of extrinsics. Always `None` in version 14.
+    pub extra_ty: Option<u32>,
+    /// List of signed extensions, in the order in which they are encoded.
+    pub signed_extensions: Vec<SignedExtensionMetadata<'a>>,
+}
+
+/// Signed extension. See [`ExtrinsicMetadata::signed_extensions`].
+#[derive(Debug, Clone)]
+pub struct SignedExtensionMetadata<'a> {
+    /// Name of the signed extension.
+    pub identifier: &'a str,
+    /// Type of the data included in the extrinsic.
+    pub ty: u32,
+    /// Type of the data not included in the extrinsic but included in the signed payload.
+    pub additional_signed_ty: u32,
+}
+
+/// Runtime API. See [`MetadataRef::apis`].
+#[derive(Debug, Clone)]
+pub struct RuntimeApiMetadata<'a> {
+    /// Name of the runtime API.
+    pub name: &'a str,
+    /// List of functions of the runtime API.
+    pub methods: Vec<RuntimeApiMethodMetadata<'a>>,
+    /// Documentation of the runtime API.
+    pub docs: Vec<&'a str>,
+}
+
+/// Function of a runtime API. See [`RuntimeApiMetadata::methods`].
+#[derive(Debug, Clone)]
+pub struct RuntimeApiMethodMetadata<'a> {
+    /// Name of the function. The name of the runtime function to call is the name of the
+    /// runtime API and the name of the function separated with `_`.
+    pub name: &'a str,
+    /// Parameters of the function.
+    pub inputs: Vec<RuntimeApiMethodParamMetadata<'a>>,
+    /// Type of the output of the function.
+    pub output_ty: u32,
+    /// Documentation of the function.
+    pub docs: Vec<&'a str>,
+}
+
+/// Parameter of a function of a runtime API. See [`RuntimeApiMethodMetadata::inputs`].
+#[derive(Debug, Clone)]
+pub struct RuntimeApiMethodParamMetadata<'a> {
+    /// Name of the parameter.
+    pub name: &'a str,
+    /// Type of the parameter.
+    pub ty: u32,
+}
+
+/// See [`MetadataRef::outer_enums`].
+#[derive(Debug, Clone)]
+pub struct OuterEnums {
+    /// Type of the enum of all the calls of all the pallets.
+    pub call_enum_ty: u32,
+    /// Type of the enum of all the events of all the pallets.
+    pub event_enum_ty: u32,
+    /// Type of the enum of all the errors of all the pallets.
+    pub error_enum_ty: u32,
+}
+
+/// Custom value. See [`MetadataRef::custom`].
+#[derive(Debug, Clone)]
+pub struct CustomValueMetadata<'a> {
+    /// Name of the value.
+    pub name: &'a str,
+    /// Type of the value.
+    pub ty: u32,
+    /// SCALE-encoded value.
+    pub value: &'a [u8],
+}
+
+type ParseResult<'a, T> = nom::IResult<&'a [u8], T>;
+
+fn metadata_content(bytes: &[u8], version: MetadataVersion) -> ParseResult<'_, MetadataRef<'_>> {
+    let (bytes, types) = vec(bytes, portable_type)?;
+    let (bytes, pallets) = vec(bytes, |b| pallet(b, version))?;
+    let (bytes, extrinsic) = match version {
+        MetadataVersion::V14 => extrinsic_v14(bytes)?,
+        MetadataVersion::V15 => extrinsic_v15(bytes)?,
+    };
+    let (bytes, runtime_ty) = ty(bytes)?;
+
+    let (bytes, apis, outer_enums, custom) = match version {
+        MetadataVersion::V14 => (bytes, Vec::new(), None, Vec::new()),
+        MetadataVersion::V15 => {
+            let (bytes, apis) = vec(bytes, runtime_api)?;
+            let (bytes, call_enum_ty) = ty(bytes)?;
+            let (bytes, event_enum_ty) = ty(bytes)?;
+            let (bytes, error_enum_ty) = ty(bytes)?;
+            let (bytes, custom) = vec(bytes, custom_value)?;
+            (
+                bytes,
+                apis,
+                Some(OuterEnums {
+                    call_enum_ty,
+                    event_enum_ty,
+                    error_enum_ty,
+                }),
+                custom,
+            )
+        }
+    };
+
+    Ok((
+        bytes,
+        MetadataRef {
+            version,
+            types,
+            pallets,
+            extrinsic,
+            runtime_ty,
+            apis,
+            outer_enums,
+            custom,
+        },
+    ))
+}
+
+fn portable_type(bytes: &[u8]) -> ParseResult<'_, PortableType<'_>> {
+    let (bytes, id) = ty(bytes)?;
+    let (bytes, path) = vec(bytes, string)?;
+    let (bytes, params) = vec(bytes, |bytes| {
+        let (bytes, name) = string(bytes)?;
+        let (bytes, ty) = option(bytes, ty)?;
+        Ok((bytes, TypeParameter { name, ty }))
+    })?;
+    let (bytes, type_def) = type_def(bytes)?;
+    let (bytes, docs) = vec(bytes, string)?;
+    Ok((
+        bytes,
+        PortableType {
+            id,
+            path,
+            params,
+            type_def,
+            docs,
+        },
+    ))
+}
+
+fn type_def(bytes: &[u8]) -> ParseResult<'_, TypeDef<'_>> {
+    let (bytes, variant) = nom::number::complete::u8(bytes)?;
+    match variant {
+        0 => nom::combinator::map(|b| vec(b, field), TypeDef::Composite)(bytes),
+        1 => nom::combinator::map(|b| vec(b, variant_def), TypeDef::Variant)(bytes),
+        2 => nom::combinator::map(ty, TypeDef::Sequence)(bytes),
+        3 => nom::combinator::map(
+            nom::sequence::tuple((nom::number::complete::le_u32, ty)),
+            |(len, ty)| TypeDef::Array { len, ty },
+        )(bytes),
+        4 => nom::combinator::map(|b| vec(b, ty), TypeDef::Tuple)(bytes),
+        5 => {
+            let (bytes, primitive) = nom::number::complete::u8(bytes)?;
+            let primitive = match primitive {
+                0 => Primitive::Bool,
+                1 => Primitive::Char,
+                2 => Primitive::Str,
+                3 => Primitive::U8,
+                4 => Primitive::U16,
+                5 => Primitive::U32,
+                6 => Primitive::U64,
+                7 => Primitive::U128,
+                8 => Primitive::U256,
+                9 => Primitive::I8,
+                10 => Primitive::I16,
+                11 => Primitive::I32,
+                12 => Primitive::I64,
+                13 => Primitive::I128,
+                14 => Primitive::I256,
+                _ => return Err(error(bytes)),
+            };
+            Ok((bytes, TypeDef::Primitive(primitive)))
+        }
+        6 => nom::combinator::map(ty, TypeDef::Compact)(bytes),
+        7 => nom::combinator::map(
+            nom::sequence::tuple((ty, ty)),
+            |(bit_store_ty, bit_order_ty)| TypeDef::BitSequence {
+                bit_store_ty,
+                bit_order_ty,
+            },
+        )(bytes),
+        _ => Err(error(bytes)),
+    }
+}
+
+fn field(bytes: &[u8]) -> ParseResult<'_, Field<'_>> {
+    let (bytes, name) = option(bytes, string)?;
+    let (bytes, ty) = ty(bytes)?;
+    let (bytes, type_name) = option(bytes, string)?;
+    let (bytes, docs) = vec(bytes, string)?;
+    Ok((
+        bytes,
+        Field {
+            name,
+            ty,
+            type_name,
+            docs,
+        },
+    ))
+}
+
+fn variant_def(bytes: &[u8]) -> ParseResult<'_, Variant<'_>> {
+    let (bytes, name) = string(bytes)?;
+    let (bytes, fields) = vec(bytes, field)?;
+    let (bytes, index) = nom::number::complete::u8(bytes)?;
+    let (bytes, docs) = vec(bytes, string)?;
+    Ok((
+        bytes,
+        Variant {
+            name,
+            fields,
+            index,
+            docs,
+        },
+    ))
+}
+
+fn pallet(bytes: &[u8], version: MetadataVersion) -> ParseResult<'_, PalletMetadata<'_>> {
+    let (bytes, name) = string(bytes)?;
+    let (bytes, storage) = option(bytes, |bytes| {
+        let (bytes, prefix) = string(bytes)?;
+        let (bytes, entries) = vec(bytes, storage_entry)?;
+        Ok((bytes, PalletStorageMetadata { prefix, entries }))
+    })?;
+    let (bytes, calls_ty) = option(bytes, ty)?;
+    let (bytes, event_ty) = option(bytes, ty)?;
+    let (bytes, constants) = vec(bytes, |bytes| {
+        let (bytes, name) = string(bytes)?;
+        let (bytes, ty) = ty(bytes)?;
+        let (bytes, value) = bytes_vec(bytes)?;
+        let (bytes, docs) = vec(bytes, string)?;
+        Ok((
+            bytes,
+            PalletConstantMetadata {
+                name,
+                ty,
+                value,
+                docs,
+            },
+        ))
+    })?;
+    let (bytes, error_ty) = option(bytes, ty)?;
+    let (bytes, index) = nom::number::complete::u8(bytes)?;
+    let (bytes, docs) = match version {
+        MetadataVersion::V14 => (bytes, Vec::new()),
+        MetadataVersion::V15 => vec(bytes, string)?,
+    };
+    Ok((
+        bytes,
+        PalletMetadata {
+            name,
+            storage,
+            calls_ty,
+            event_ty,
+            constants,
+            error_ty,
+            index,
+            docs,
+        },
+    ))
+}
+
+fn storage_entry(bytes: &[u8]) -> ParseResult<'_, StorageEntryMetadata<'_>> {
+    let (bytes, name) = string(bytes)?;
+    let (bytes, modifier) = match nom::number::complete::u8(bytes)? {
+        (bytes, 0) => (bytes, StorageEntryModifier::Optional),
+        (bytes, 1) => (bytes, StorageEntryModifier::Default),
+        (bytes, _) => return Err(error(bytes)),
+    };
+    let (bytes, ty) = match nom::number::complete::u8(bytes)? {
+        (bytes, 0) => nom::combinator::map(ty, StorageEntryType::Plain)(bytes)?,
+        (bytes, 1) => {
+            let (bytes, hashers) = vec(bytes, storage_hasher)?;
+            let (bytes, key_ty) = ty(bytes)?;
+            let (bytes, value_ty) = ty(bytes)?;
+            (
+                bytes,
+                StorageEntryType::Map {
+                    hashers,
+                    key_ty,
+                    value_ty,
+                },
+            )
+        }
+        (bytes, _) => return Err(error(bytes)),
+    };
+    let (bytes, default) = bytes_vec(bytes)?;
+    let (bytes, docs) = vec(bytes, string)?;
+    Ok((
+        bytes,
+        StorageEntryMetadata {
+            name,
+            modifier,
+            ty,
+            default,
+            docs,
+        },
+    ))
+}
+
+fn storage_hasher(bytes: &[u8]) -> ParseResult<'_, StorageHasher> {
+    let (bytes, hasher) = nom::number::complete::u8(bytes)?;
+    let hasher = match hasher {
+        0 => StorageHasher::Blake2_128,
+        1 => StorageHasher::Blake2_256,
+        2 => StorageHasher::Blake2_128Concat,
+        3 => StorageHasher::Twox128,
+        4 => StorageHasher::Twox256,
+        5 => StorageHasher::Twox64Concat,
+        6 => StorageHasher::Identity,
+        _ => return Err(error(bytes)),
+    };
+    Ok((bytes, hasher))
+}
+
+fn extrinsic_v14(bytes: &[u8]) -> ParseResult<'_, ExtrinsicMetadata<'_>> {
+    let (bytes, extrinsic_ty) = ty(bytes)?;
+    let (bytes, version) = nom::number::complete::u8(bytes)?;
+    let (bytes, signed_extensions) = vec(bytes, signed_extension)?;
+    Ok((
+        bytes,
+        ExtrinsicMetadata {
+            version,
+            ty: Some(extrinsic_ty),
+            address_ty: None,
+            call_ty: None,
+            signature_ty: None,
+            extra_ty: None,
+            signed_extensions,
+        },
+    ))
+}
+
+fn extrinsic_v15(bytes: &[u8]) -> ParseResult<'_, ExtrinsicMetadata<'_>> {
+    let (bytes, version) = nom::number::complete::u8(bytes)?;
+    let (bytes, address_ty) = ty(bytes)?;
+    let (bytes, call_ty) = ty(bytes)?;
+    let (bytes, signature_ty) = ty(bytes)?;
+    let (bytes, extra_ty) = ty(bytes)?;
+    let (bytes, signed_extensions) = vec(bytes, signed_extension)?;
+    Ok((
+        bytes,
+        ExtrinsicMetadata {
+            version,
+            ty: None,
+            address_ty: Some(address_ty),
+            call_ty: Some(call_ty),
+            signature_ty: Some(signature_ty),
+            extra_ty: Some(extra_ty),
+            signed_extensions,
+        },
+    ))
+}
+
+fn signed_extension(bytes: &[u8]) -> ParseResult<'_, SignedExtensionMetadata<'_>> {
+    let (bytes, identifier) = string(bytes)?;
+    let (bytes, extension_ty) = ty(bytes)?;
+    let (bytes, additional_signed_ty) = ty(bytes)?;
+    Ok((
+        bytes,
+        SignedExtensionMetadata {
+            identifier,
+            ty: extension_ty,
+            additional_signed_ty,
+        },
+    ))
+}
+
+fn runtime_api(bytes: &[u8]) -> ParseResult<'_, RuntimeApiMetadata<'_>> {
+    let (bytes, name) = string(bytes)?;
+    let (bytes, methods) = vec(bytes, |bytes| {
+        let (bytes, name) = string(bytes)?;
+        let (bytes, inputs) = vec(bytes, |bytes| {
+            let (bytes, name) = string(bytes)?;
+            let (bytes, ty) = ty(bytes)?;
+            Ok((bytes, RuntimeApiMethodParamMetadata { name, ty }))
+        })?;
+        let (bytes, output_ty) = ty(bytes)?;
+        let (bytes, docs) = vec(bytes, string)?;
+        Ok((
+            bytes,
+            RuntimeApiMethodMetadata {
+                name,
+                inputs,
+                output_ty,
+                docs,
+            },
+        ))
+    })?;
+    let (bytes, docs) = vec(bytes, string)?;
+    Ok((
+        bytes,
+        RuntimeApiMetadata {
+            name,
+            methods,
+            docs,
+        },
+    ))
+}
+
+fn custom_value(bytes: &[u8]) -> ParseResult<'_, CustomValueMetadata<'_>> {
+    let (bytes, name) = string(bytes)?;
+    let (bytes, ty) = ty(bytes)?;
+    let (bytes, value) = bytes_vec(bytes)?;
+    Ok((bytes, CustomValueMetadata { name, ty, value }))
+}
+
+/// Decodes a reference to a type of the registry.
+fn ty(bytes: &[u8]) -> ParseResult<'_, u32> {
+    nom::combinator::map_res(crate::util::nom_scale_compact_usize, u32::try_from)(bytes)
+}
+
+fn string(bytes: &[u8]) -> ParseResult<'_, &str> {
+    nom::combinator::map_res(bytes_vec, core::str::from_utf8)(bytes)
+}
+
+fn bytes_vec(bytes: &[u8]) -> ParseResult<'_, &[u8]> {
+    nom::multi::length_data(crate::util::nom_scale_compact_usize)(bytes)
+}
+
+fn option<'a, T>(
+    bytes: &'a [u8],
+    inner: impl FnMut(&'a [u8]) -> ParseResult<'a, T>,
+) -> ParseResult<'a, Option<T>> {
+    match nom::number::complete::u8(bytes)? {
+        (bytes, 0) => Ok((bytes, None)),
+        (bytes, 1) => nom::combinator::map(inner, Some)(bytes),
+        (bytes, _) => Err(error(bytes)),
+    }
+}
+
+fn vec<'a, T>(
+    bytes: &'a [u8],
+    mut inner: impl FnMut(&'a [u8]) -> ParseResult<'a, T>,
+) -> ParseResult<'a, Vec<T>> {
+    let (mut bytes, num_elems) = crate::util::nom_scale_compact_usize(bytes)?;
+
+    // Each element is at least one byte long. This check avoids allocating a very large
+    // vector if the length is invalid.
+    if num_elems > bytes.len() {
+        return Err(error(bytes));
+    }
+
+    let mut out = Vec::with_capacity(num_elems);
+    for _ in 0..num_elems {
+        let (rest, elem) = inner(bytes)?;
+        out.push(elem);
+        bytes = rest;
+    }
+    Ok((bytes, out))
+}
+
+fn error(bytes: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+    nom::Err::Error(nom::error::make_error(bytes, nom::error::ErrorKind::Verify))
+}
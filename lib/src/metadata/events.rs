@@ -0,0 +1,195 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the events generated by a block based on the metadata.
+//!
+//! The events generated during the execution of a block are stored in the `Events` storage
+//! item of the `System` pallet, whose key can be obtained with [`system_events_storage_key`].
+//! Use [`decode_events`] to decode the value of this storage item.
+
+use super::{
+    decode::{MetadataRef, StorageEntryType, TypeDef},
+    extrinsic::split_outer_enum,
+    value::{decode_value, DecodeValueError, Value},
+};
+use crate::json_rpc::storage_keys;
+
+use alloc::vec::Vec;
+
+/// Returns the key of the storage item containing the events of the block.
+pub fn system_events_storage_key() -> [u8; 32] {
+    storage_keys::storage_value_key("System", "Events")
+}
+
+/// Decoded event. See [`decode_events`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedEvent<'a> {
+    /// Moment during the execution of the block when the event has been generated.
+    pub phase: EventPhase,
+    /// Name of the pallet that has generated the event.
+    pub pallet_name: &'a str,
+    /// Name of the event.
+    pub event_name: &'a str,
+    /// Fields of the event.
+    pub fields: Vec<(Option<&'a str>, Value<'a>)>,
+    /// List of topics of the event.
+    pub topics: Vec<Value<'a>>,
+}
+
+/// See [`DecodedEvent::phase`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventPhase {
+    /// Event has been generated during the execution of the extrinsic with the given index.
+    ApplyExtrinsic(u32),
+    /// Event has been generated after all the extrinsics have been applied.
+    Finalization,
+    /// Event has been generated before any extrinsic has been applied.
+    Initialization,
+}
+
+/// Decodes the value of the `System::Events` storage item.
+pub fn decode_events<'a>(
+    metadata: &MetadataRef<'a>,
+    scale_encoded_events: &'a [u8],
+) -> Result<Vec<DecodedEvent<'a>>, DecodeEventsError> {
+    let record_ty = event_record_type(metadata).ok_or(DecodeEventsError::MissingEventsType)?;
+
+    let (mut bytes, num_events) =
+        crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(scale_encoded_events)
+            .map_err(|_| DecodeEventsError::InvalidLengthPrefix)?;
+
+    // Each event is at least one byte long. This check avoids allocating a very large vector
+    // if the length is invalid.
+    let mut out = Vec::with_capacity(num_events.min(bytes.len()));
+
+    for _ in 0..num_events {
+        let (phase, rest) =
+            decode_value(metadata, record_ty.phase_ty, bytes).map_err(DecodeEventsError::Phase)?;
+        let (event, rest) =
+            decode_value(metadata, record_ty.event_ty, rest).map_err(DecodeEventsError::Event)?;
+        let (topics, rest) =
+            decode_value(metadata, record_ty.topics_ty, rest).map_err(DecodeEventsError::Topics)?;
+        bytes = rest;
+
+        let phase = match phase {
+            Value::Variant {
+                name: "ApplyExtrinsic",
+                fields,
+            } => match fields.first() {
+                Some((_, Value::Unsigned(index))) => EventPhase::ApplyExtrinsic(
+                    u32::try_from(*index).map_err(|_| DecodeEventsError::InvalidPhase)?,
+                ),
+                _ => return Err(DecodeEventsError::InvalidPhase),
+            },
+            Value::Variant {
+                name: "Finalization",
+                ..
+            } => EventPhase::Finalization,
+            Value::Variant {
+                name: "Initialization",
+                ..
+            } => EventPhase::Initialization,
+            _ => return Err(DecodeEventsError::InvalidPhase),
+        };
+
+        let (pallet_name, event_name, fields) =
+            split_outer_enum(event).ok_or(DecodeEventsError::InvalidEventType)?;
+
+        let topics = match topics {
+            Value::Sequence(topics) => topics,
+            Value::Bytes(&[]) => Vec::new(),
+            _ => return Err(DecodeEventsError::InvalidTopicsType),
+        };
+
+        out.push(DecodedEvent {
+            phase,
+            pallet_name,
+            event_name,
+            fields,
+            topics,
+        });
+    }
+
+    if !bytes.is_empty() {
+        return Err(DecodeEventsError::TrailingData);
+    }
+
+    Ok(out)
+}
+
+/// Error potentially returned by [`decode_events`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeEventsError {
+    /// The metadata doesn't contain the type of the `System::Events` storage item, or this type
+    /// isn't a list of event records.
+    MissingEventsType,
+    /// Failed to decode the number of events.
+    InvalidLengthPrefix,
+    /// Failed to decode the phase of an event.
+    #[display(fmt = "Failed to decode event phase: {_0}")]
+    Phase(DecodeValueError),
+    /// The phase of an event has an unknown format.
+    InvalidPhase,
+    /// Failed to decode an event.
+    #[display(fmt = "Failed to decode event: {_0}")]
+    Event(DecodeValueError),
+    /// The type of the events isn't an enum of pallets containing an enum of events.
+    InvalidEventType,
+    /// Failed to decode the topics of an event.
+    #[display(fmt = "Failed to decode event topics: {_0}")]
+    Topics(DecodeValueError),
+    /// The topics of an event aren't a list.
+    InvalidTopicsType,
+    /// The storage value contains data after the last event.
+    TrailingData,
+}
+
+/// Types of the fields of an event record.
+struct EventRecordType {
+    phase_ty: u32,
+    event_ty: u32,
+    topics_ty: u32,
+}
+
+/// Returns the types of the fields of the records found in the `System::Events` storage item.
+fn event_record_type(metadata: &MetadataRef) -> Option<EventRecordType> {
+    let events_ty = metadata
+        .pallet_by_name("System")?
+        .storage
+        .as_ref()?
+        .entries
+        .iter()
+        .find(|entry| entry.name == "Events")
+        .and_then(|entry| match entry.ty {
+            StorageEntryType::Plain(ty) => Some(ty),
+            StorageEntryType::Map { .. } => None,
+        })?;
+
+    let TypeDef::Sequence(record_ty) = metadata.type_by_id(events_ty)?.type_def else {
+        return None;
+    };
+    let TypeDef::Composite(fields) = &metadata.type_by_id(record_ty)?.type_def else {
+        return None;
+    };
+
+    let field = |name: &str| fields.iter().find(|f| f.name == Some(name)).map(|f| f.ty);
+    Some(EventRecordType {
+        phase_ty: field("phase")?,
+        event_ty: field("event")?,
+        topics_ty: field("topics")?,
+    })
+}
@@ -0,0 +1,229 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of extrinsics based on the metadata.
+//!
+//! An extrinsic starts with its length, followed with a byte containing the version of the
+//! format of the extrinsic and whether it is signed. Signed extrinsics then contain the address
+//! of the signer, the signature, and the data of each signed extension. Finally, all extrinsics
+//! contain the call, which consists in the index of the pallet, the index of the call within
+//! the pallet, and the arguments of the call.
+
+use super::{
+    decode::MetadataRef,
+    value::{decode_value, DecodeValueError, Value},
+};
+
+use alloc::vec::Vec;
+
+/// Version of the extrinsics format supported by [`decode_extrinsic`].
+const EXTRINSIC_FORMAT_VERSION: u8 = 4;
+
+/// Decoded extrinsic. See [`decode_extrinsic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedExtrinsic<'a> {
+    /// Signer and signature of the extrinsic, or `None` if the extrinsic isn't signed.
+    pub signature: Option<ExtrinsicSignature<'a>>,
+    /// Name of the pallet the call belongs to.
+    pub pallet_name: &'a str,
+    /// Name of the call.
+    pub call_name: &'a str,
+    /// Arguments of the call.
+    pub arguments: Vec<(Option<&'a str>, Value<'a>)>,
+}
+
+/// See [`DecodedExtrinsic::signature`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtrinsicSignature<'a> {
+    /// Address of the signer.
+    pub address: Value<'a>,
+    /// Signature of the extrinsic.
+    pub signature: Value<'a>,
+    /// Identifier and value of each signed extension, in order.
+    pub signed_extensions: Vec<(&'a str, Value<'a>)>,
+}
+
+/// Decodes a SCALE-encoded extrinsic, as found in block bodies.
+///
+/// The extrinsic must be prefixed with its length.
+pub fn decode_extrinsic<'a>(
+    metadata: &MetadataRef<'a>,
+    scale_encoded_extrinsic: &'a [u8],
+) -> Result<DecodedExtrinsic<'a>, DecodeExtrinsicError> {
+    let (bytes, len) =
+        crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(scale_encoded_extrinsic)
+            .map_err(|_| DecodeExtrinsicError::InvalidLengthPrefix)?;
+    if bytes.len() != len {
+        return Err(DecodeExtrinsicError::InvalidLengthPrefix);
+    }
+
+    let (version, bytes) = bytes
+        .split_first()
+        .ok_or(DecodeExtrinsicError::InvalidLengthPrefix)?;
+    let is_signed = (version & 0x80) != 0;
+    if (version & 0x7f) != EXTRINSIC_FORMAT_VERSION {
+        return Err(DecodeExtrinsicError::UnsupportedVersion(version & 0x7f));
+    }
+
+    let types = extrinsic_types(metadata).ok_or(DecodeExtrinsicError::MissingExtrinsicTypes)?;
+
+    let (signature, bytes) = if is_signed {
+        let (address, bytes) = decode_value(metadata, types.address_ty, bytes)
+            .map_err(DecodeExtrinsicError::Address)?;
+        let (signature, mut bytes) = decode_value(metadata, types.signature_ty, bytes)
+            .map_err(DecodeExtrinsicError::Signature)?;
+
+        let mut signed_extensions = Vec::with_capacity(metadata.extrinsic.signed_extensions.len());
+        for extension in &metadata.extrinsic.signed_extensions {
+            let (value, rest) = decode_value(metadata, extension.ty, bytes).map_err(|error| {
+                DecodeExtrinsicError::SignedExtension {
+                    identifier: extension.identifier.into(),
+                    error,
+                }
+            })?;
+            signed_extensions.push((extension.identifier, value));
+            bytes = rest;
+        }
+
+        (
+            Some(ExtrinsicSignature {
+                address,
+                signature,
+                signed_extensions,
+            }),
+            bytes,
+        )
+    } else {
+        (None, bytes)
+    };
+
+    let (call, bytes) =
+        decode_value(metadata, types.call_ty, bytes).map_err(DecodeExtrinsicError::Call)?;
+    if !bytes.is_empty() {
+        return Err(DecodeExtrinsicError::TrailingData);
+    }
+
+    let (pallet_name, call_name, arguments) =
+        split_outer_enum(call).ok_or(DecodeExtrinsicError::InvalidCallType)?;
+
+    Ok(DecodedExtrinsic {
+        signature,
+        pallet_name,
+        call_name,
+        arguments,
+    })
+}
+
+/// Error potentially returned by [`decode_extrinsic`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeExtrinsicError {
+    /// The length prefix of the extrinsic is invalid or doesn't match its length.
+    InvalidLengthPrefix,
+    /// The version of the extrinsic format isn't supported.
+    #[display(fmt = "Unsupported extrinsic version: {_0}")]
+    UnsupportedVersion(u8),
+    /// The metadata doesn't indicate the types of the extrinsic.
+    MissingExtrinsicTypes,
+    /// Failed to decode the address of the signer.
+    #[display(fmt = "Failed to decode address: {_0}")]
+    Address(DecodeValueError),
+    /// Failed to decode the signature.
+    #[display(fmt = "Failed to decode signature: {_0}")]
+    Signature(DecodeValueError),
+    /// Failed to decode a signed extension.
+    #[display(fmt = "Failed to decode signed extension {identifier}: {error}")]
+    SignedExtension {
+        /// Identifier of the signed extension.
+        identifier: alloc::string::String,
+        /// Error that happened.
+        error: DecodeValueError,
+    },
+    /// Failed to decode the call.
+    #[display(fmt = "Failed to decode call: {_0}")]
+    Call(DecodeValueError),
+    /// The type of the call isn't an enum of pallets containing an enum of calls.
+    InvalidCallType,
+    /// The extrinsic contains data after the call.
+    TrailingData,
+}
+
+/// Types found in extrinsics.
+struct ExtrinsicTypes {
+    address_ty: u32,
+    call_ty: u32,
+    signature_ty: u32,
+}
+
+/// Returns the types found in extrinsics, or `None` if they can't be found in the metadata.
+fn extrinsic_types(metadata: &MetadataRef) -> Option<ExtrinsicTypes> {
+    let extrinsic = &metadata.extrinsic;
+
+    if let (Some(address_ty), Some(call_ty), Some(signature_ty)) = (
+        extrinsic.address_ty,
+        extrinsic.call_ty,
+        extrinsic.signature_ty,
+    ) {
+        return Some(ExtrinsicTypes {
+            address_ty,
+            call_ty,
+            signature_ty,
+        });
+    }
+
+    // In version 14 of the metadata, the types are found in the generic parameters of the
+    // type of the extrinsic.
+    let extrinsic_ty = metadata.type_by_id(extrinsic.ty?)?;
+    let param = |name: &str| {
+        extrinsic_ty
+            .params
+            .iter()
+            .find(|p| p.name == name)
+            .and_then(|p| p.ty)
+    };
+
+    Some(ExtrinsicTypes {
+        address_ty: param("Address")?,
+        call_ty: param("Call")?,
+        signature_ty: param("Signature")?,
+    })
+}
+
+/// Splits a value of the enum that aggregates all the pallets (such as the enum of all the calls
+/// or the enum of all the events) into the name of the pallet, the name of the variant within
+/// that pallet, and its fields.
+#[allow(clippy::type_complexity)]
+pub(super) fn split_outer_enum(
+    value: Value<'_>,
+) -> Option<(&'_ str, &'_ str, Vec<(Option<&'_ str>, Value<'_>)>)> {
+    let Value::Variant {
+        name: pallet_name,
+        mut fields,
+    } = value
+    else {
+        return None;
+    };
+
+    if fields.len() != 1 {
+        return None;
+    }
+
+    let Value::Variant { name, fields } = fields.pop().unwrap().1 else {
+        return None;
+    };
+
+    Some((pallet_name, name, fields))
+}
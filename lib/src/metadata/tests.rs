@@ -0,0 +1,481 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::decode;
+use crate::{
+    executor::{self, runtime_call},
+    json_rpc::storage_keys::StorageHasher,
+};
+use core::iter;
+
+/// Returns the runtime found in the transaction validation test fixture.
+fn test_runtime() -> executor::host::HostVmPrototype {
+    #[derive(serde::Deserialize)]
+    struct Test {
+        #[serde(rename = "runtimeCode")]
+        runtime_code: String,
+    }
+
+    let test: Test =
+        serde_json::from_str(include_str!("../transactions/validate/test-fixture.json")).unwrap();
+
+    executor::host::HostVmPrototype::new(executor::host::Config {
+        module: hex::decode(test.runtime_code).unwrap(),
+        heap_pages: executor::DEFAULT_HEAP_PAGES,
+        fuel_metering: false,
+        allow_unresolved_imports: true,
+        exec_hint: executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
+    })
+    .unwrap()
+}
+
+/// Runs the given call to completion against an empty storage and returns its output.
+fn run_to_completion(mut call: runtime_call::RuntimeCall) -> Vec<u8> {
+    loop {
+        match call {
+            runtime_call::RuntimeCall::Finished(Ok(success)) => {
+                return success.virtual_machine.value().as_ref().to_vec()
+            }
+            runtime_call::RuntimeCall::Finished(Err(err)) => panic!("{err:?}"),
+            runtime_call::RuntimeCall::StorageGet(get) => {
+                call = get.inject_value(None::<(iter::Empty<Vec<u8>>, _)>)
+            }
+            runtime_call::RuntimeCall::NextKey(nk) => call = nk.inject_key(None::<iter::Empty<_>>),
+            runtime_call::RuntimeCall::ClosestDescendantMerkleValue(mv) => {
+                call = mv.inject_merkle_value(None)
+            }
+            runtime_call::RuntimeCall::SignatureVerification(r) => call = r.verify_and_resume(),
+            runtime_call::RuntimeCall::LogEmit(r) => call = r.resume(),
+            runtime_call::RuntimeCall::OffchainStorageSet(r) => call = r.resume(),
+            runtime_call::RuntimeCall::Offchain(_)
+            | runtime_call::RuntimeCall::StorageProofNodeValue(_) => panic!(),
+        }
+    }
+}
+
+#[test]
+fn fetch_and_decode_v14() {
+    let versions = run_to_completion(super::metadata_versions(test_runtime()).unwrap());
+    let versions = super::decode_metadata_versions_output(&versions).unwrap();
+    assert_eq!(versions, vec![14, super::UNSTABLE_METADATA_VERSION]);
+
+    let output = run_to_completion(super::metadata_at_version(test_runtime(), 15).unwrap());
+    assert!(super::decode_metadata_at_version_output(&output)
+        .unwrap()
+        .is_none());
+
+    let output = run_to_completion(super::metadata_at_version(test_runtime(), 14).unwrap());
+    let metadata = super::decode_metadata_at_version_output(&output)
+        .unwrap()
+        .unwrap();
+    let metadata = decode(metadata).unwrap();
+
+    assert_eq!(metadata.version, decode::MetadataVersion::V14);
+    assert!(metadata.apis.is_empty());
+    assert!(metadata.outer_enums.is_none());
+    assert_eq!(metadata.extrinsic.version, 4);
+    assert!(metadata.extrinsic.ty.is_some());
+    assert!(metadata
+        .extrinsic
+        .signed_extensions
+        .iter()
+        .any(|ext| ext.identifier == "CheckNonce"));
+
+    let system = metadata.pallet_by_name("System").unwrap();
+    assert_eq!(system.index, 0);
+    assert!(metadata.pallet_by_index(0).is_some());
+    let account = system
+        .storage
+        .as_ref()
+        .unwrap()
+        .entries
+        .iter()
+        .find(|e| e.name == "Account")
+        .unwrap();
+    match &account.ty {
+        decode::StorageEntryType::Map { hashers, .. } => {
+            assert_eq!(hashers, &[StorageHasher::Blake2_128Concat]);
+        }
+        _ => panic!(),
+    }
+
+    let calls_ty = metadata.type_by_id(system.calls_ty.unwrap()).unwrap();
+    match &calls_ty.type_def {
+        decode::TypeDef::Variant(variants) => {
+            assert!(variants.iter().any(|v| v.name == "remark"));
+        }
+        _ => panic!(),
+    }
+
+    // All the types referred to must be found in the registry.
+    for pallet in &metadata.pallets {
+        for ty in [pallet.calls_ty, pallet.event_ty, pallet.error_ty]
+            .into_iter()
+            .flatten()
+        {
+            assert!(metadata.type_by_id(ty).is_some());
+        }
+    }
+}
+
+fn compact(value: usize) -> Vec<u8> {
+    crate::util::encode_scale_compact_usize(value)
+        .as_ref()
+        .to_vec()
+}
+
+fn string(value: &str) -> Vec<u8> {
+    let mut out = compact(value.len());
+    out.extend_from_slice(value.as_bytes());
+    out
+}
+
+/// Builds a small metadata in version 15.
+fn synthetic_v15() -> Vec<u8> {
+    let mut out = b"meta".to_vec();
+    out.push(15);
+
+    // Types.
+    out.extend(compact(2));
+    // Type 0: `u32`.
+    out.extend(compact(0));
+    out.extend(compact(0)); // Path.
+    out.extend(compact(0)); // Params.
+    out.extend([5, 5]); // Primitive `u32`.
+    out.extend(compact(0)); // Docs.
+
+    // Type 1: `pallet_test::Call<T>`.
+    out.extend(compact(1));
+    out.extend(compact(2));
+    out.extend(string("pallet_test"));
+    out.extend(string("Call"));
+    out.extend(compact(1));
+    out.extend(string("T"));
+    out.push(0); // No type.
+    out.push(1); // Variant.
+    out.extend(compact(1));
+    out.extend(string("foo"));
+    out.extend(compact(1));
+    out.push(1);
+    out.extend(string("a"));
+    out.extend(compact(0));
+    out.push(1);
+    out.extend(string("u32"));
+    out.extend(compact(0));
+    out.push(0); // Variant index.
+    out.extend(compact(1));
+    out.extend(string("Does foo."));
+    out.extend(compact(0)); // Docs.
+
+    // Pallets.
+    out.extend(compact(1));
+    out.extend(string("Test"));
+    out.push(1); // Storage.
+    out.extend(string("Test"));
+    out.extend(compact(1));
+    out.extend(string("Value"));
+    out.push(1); // Default modifier.
+    out.push(0); // Plain.
+    out.extend(compact(0));
+    out.extend(compact(4));
+    out.extend([0, 0, 0, 0]);
+    out.extend(compact(0));
+    out.push(1); // Calls.
+    out.extend(compact(1));
+    out.push(0); // Events.
+    out.extend(compact(1)); // Constants.
+    out.extend(string("C"));
+    out.extend(compact(0));
+    out.extend(compact(4));
+    out.extend([1, 0, 0, 0]);
+    out.extend(compact(0));
+    out.push(0); // Errors.
+    out.push(7); // Index.
+    out.extend(compact(1));
+    out.extend(string("Test pallet."));
+
+    // Extrinsic.
+    out.push(4);
+    out.extend(compact(0));
+    out.extend(compact(1));
+    out.extend(compact(0));
+    out.extend(compact(0));
+    out.extend(compact(1));
+    out.extend(string("CheckNonce"));
+    out.extend(compact(0));
+    out.extend(compact(0));
+
+    // Runtime type.
+    out.extend(compact(0));
+
+    // Runtime APIs.
+    out.extend(compact(1));
+    out.extend(string("Core"));
+    out.extend(compact(1));
+    out.extend(string("version"));
+    out.extend(compact(1));
+    out.extend(string("x"));
+    out.extend(compact(0));
+    out.extend(compact(0));
+    out.extend(compact(0));
+    out.extend(compact(0));
+
+    // Outer enums.
+    out.extend(compact(1));
+    out.extend(compact(1));
+    out.extend(compact(1));
+
+    // Custom values.
+    out.extend(compact(1));
+    out.extend(string("foo"));
+    out.extend(compact(0));
+    out.extend(compact(4));
+    out.extend([1, 2, 3, 4]);
+
+    out
+}
+
+#[test]
+fn decode_v15() {
+    let metadata = synthetic_v15();
+    let metadata = decode(&metadata).unwrap();
+
+    assert_eq!(metadata.version, decode::MetadataVersion::V15);
+    assert_eq!(metadata.types.len(), 2);
+    assert_eq!(metadata.types[1].path, ["pallet_test", "Call"]);
+
+    let pallet = metadata.pallet_by_index(7).unwrap();
+    assert_eq!(pallet.name, "Test");
+    assert_eq!(pallet.docs, ["Test pallet."]);
+    assert_eq!(pallet.calls_ty, Some(1));
+    assert_eq!(pallet.constants[0].value, &[1, 0, 0, 0]);
+    let storage = pallet.storage.as_ref().unwrap();
+    assert_eq!(storage.entries[0].name, "Value");
+    assert_eq!(
+        storage.entries[0].modifier,
+        decode::StorageEntryModifier::Default
+    );
+
+    assert_eq!(metadata.extrinsic.call_ty, Some(1));
+    assert_eq!(
+        metadata.extrinsic.signed_extensions[0].identifier,
+        "CheckNonce"
+    );
+
+    assert_eq!(metadata.apis[0].name, "Core");
+    assert_eq!(metadata.apis[0].methods[0].inputs[0].name, "x");
+    assert_eq!(metadata.outer_enums.as_ref().unwrap().call_enum_ty, 1);
+    assert_eq!(metadata.custom[0].name, "foo");
+    assert_eq!(metadata.custom[0].value, &[1, 2, 3, 4]);
+}
+
+#[test]
+fn decode_errors() {
+    assert!(matches!(
+        decode(b"foo"),
+        Err(super::DecodeError::InvalidMagicNumber)
+    ));
+    assert!(matches!(
+        decode(b"meta\x0d"),
+        Err(super::DecodeError::UnsupportedVersion(13))
+    ));
+
+    // Truncating the metadata at any point must lead to an error.
+    let metadata = synthetic_v15();
+    for len in 0..metadata.len() {
+        assert!(decode(&metadata[..len]).is_err());
+    }
+
+    // Trailing data isn't allowed.
+    let mut metadata = synthetic_v15();
+    metadata.push(0);
+    assert!(decode(&metadata).is_err());
+}
+
+#[test]
+fn decode_list_length_bounded() {
+    let metadata = synthetic_v15();
+    let mut metadata = decode(&metadata).unwrap();
+
+    // Type 2: `Vec<u32>`. Type 3: `()`. Type 4: `Vec<()>`.
+    for (id, type_def) in [
+        (2, decode::TypeDef::Sequence(0)),
+        (3, decode::TypeDef::Tuple(Vec::new())),
+        (4, decode::TypeDef::Sequence(3)),
+    ] {
+        metadata.types.push(decode::PortableType {
+            id,
+            path: Vec::new(),
+            params: Vec::new(),
+            type_def,
+            docs: Vec::new(),
+        });
+    }
+
+    // A length superior to the number of bytes is rejected before decoding the elements.
+    let mut value = compact(1 << 29);
+    value.extend([0; 8]);
+    assert!(matches!(
+        super::value::decode_value(&metadata, 2, &value),
+        Err(super::value::DecodeValueError::Truncated)
+    ));
+
+    let mut value = compact(2);
+    value.extend([1, 0, 0, 0, 2, 0, 0, 0]);
+    let (decoded, rest) = super::value::decode_value(&metadata, 2, &value).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(
+        decoded,
+        super::value::Value::Sequence(vec![
+            super::value::Value::Unsigned(1),
+            super::value::Value::Unsigned(2)
+        ])
+    );
+
+    // Lists of zero-sized elements are capped.
+    let value = compact(3);
+    let (decoded, rest) = super::value::decode_value(&metadata, 4, &value).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(
+        decoded,
+        super::value::Value::Sequence(vec![super::value::Value::Composite(Vec::new()); 3])
+    );
+    assert!(matches!(
+        super::value::decode_value(&metadata, 4, &compact(1 << 29)),
+        Err(super::value::DecodeValueError::TooManyZeroSizedElements)
+    ));
+}
+
+/// Returns the metadata of the runtime found in the transaction validation test fixture.
+fn test_metadata() -> Vec<u8> {
+    let output = run_to_completion(super::metadata_at_version(test_runtime(), 14).unwrap());
+    super::decode_metadata_at_version_output(&output)
+        .unwrap()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn decode_signed_extrinsic() {
+    #[derive(serde::Deserialize)]
+    struct Test {
+        #[serde(rename = "transactionBytes")]
+        transaction_bytes: String,
+    }
+
+    let test: Test =
+        serde_json::from_str(include_str!("../transactions/validate/test-fixture.json")).unwrap();
+    let extrinsic = hex::decode(test.transaction_bytes).unwrap();
+
+    let metadata = test_metadata();
+    let metadata = decode(&metadata).unwrap();
+
+    let decoded = super::decode_extrinsic(&metadata, &extrinsic).unwrap();
+    assert_eq!(decoded.pallet_name, "Balances");
+    assert_eq!(decoded.call_name, "transfer_keep_alive");
+    assert_eq!(decoded.arguments.len(), 2);
+    assert_eq!(decoded.arguments[0].0, Some("dest"));
+    assert_eq!(
+        decoded.arguments[1],
+        (Some("value"), super::Value::Unsigned(100_000_000_000))
+    );
+
+    let signature = decoded.signature.unwrap();
+    assert!(matches!(
+        signature.address,
+        super::Value::Variant { name: "Id", .. }
+    ));
+    assert!(matches!(
+        signature.signature,
+        super::Value::Variant {
+            name: "Sr25519",
+            ..
+        }
+    ));
+    assert!(signature.signed_extensions.contains(&(
+        "CheckNonce",
+        super::Value::Composite(vec![(None, super::Value::Unsigned(48))])
+    )));
+
+    // Truncated or extended extrinsics must be rejected.
+    for len in 0..extrinsic.len() {
+        assert!(super::decode_extrinsic(&metadata, &extrinsic[..len]).is_err());
+    }
+    let mut extended = extrinsic.clone();
+    extended.push(0);
+    assert!(super::decode_extrinsic(&metadata, &extended).is_err());
+}
+
+#[test]
+fn decode_system_events() {
+    let metadata = test_metadata();
+    let metadata = decode(&metadata).unwrap();
+
+    let system = metadata.pallet_by_name("System").unwrap();
+    let event_index = |name: &str| match &metadata
+        .type_by_id(system.event_ty.unwrap())
+        .unwrap()
+        .type_def
+    {
+        decode::TypeDef::Variant(variants) => {
+            variants.iter().find(|v| v.name == name).unwrap().index
+        }
+        _ => panic!(),
+    };
+
+    let mut events = compact(2);
+    // Phase `ApplyExtrinsic(3)`.
+    events.extend([0, 3, 0, 0, 0]);
+    events.extend([system.index, event_index("Remarked")]);
+    events.extend([1; 32]);
+    events.extend([2; 32]);
+    events.extend(compact(0));
+    // Phase `Finalization`.
+    events.push(1);
+    events.extend([system.index, event_index("CodeUpdated")]);
+    events.extend(compact(1));
+    events.extend([3; 32]);
+
+    let decoded = super::decode_events(&metadata, &events).unwrap();
+    assert_eq!(decoded.len(), 2);
+
+    assert_eq!(decoded[0].phase, super::EventPhase::ApplyExtrinsic(3));
+    assert_eq!(decoded[0].pallet_name, "System");
+    assert_eq!(decoded[0].event_name, "Remarked");
+    assert_eq!(decoded[0].fields[0].0, Some("sender"));
+    assert_eq!(decoded[0].fields[1].0, Some("hash"));
+    assert!(decoded[0].topics.is_empty());
+
+    assert_eq!(decoded[1].phase, super::EventPhase::Finalization);
+    assert_eq!(decoded[1].event_name, "CodeUpdated");
+    assert!(decoded[1].fields.is_empty());
+    assert_eq!(decoded[1].topics.len(), 1);
+
+    // Truncated or extended values must be rejected.
+    for len in 0..events.len() {
+        assert!(super::decode_events(&metadata, &events[..len]).is_err());
+    }
+    events.push(0);
+    assert!(super::decode_events(&metadata, &events).is_err());
+
+    assert_eq!(
+        super::events::system_events_storage_key(),
+        crate::json_rpc::storage_keys::storage_value_key("System", "Events")
+    );
+}
@@ -0,0 +1,350 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of SCALE-encoded values whose type is found in the type registry of the metadata.
+
+use super::decode::{MetadataRef, Primitive, TypeDef};
+
+use alloc::vec::Vec;
+
+/// Maximum number of nested types that [`decode_value`] accepts. Protects against types that
+/// recursively contain themselves.
+const MAX_DEPTH: u32 = 128;
+
+/// Maximum number of elements of a list whose elements are zero-sized. Since such elements
+/// don't occupy any byte, the length of the list can't be checked against the size of the
+/// input.
+const MAX_ZERO_SIZED_ELEMENTS: usize = 1024;
+
+/// Decoded SCALE-encoded value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// Boolean.
+    Bool(bool),
+    /// Unicode character.
+    Char(char),
+    /// String.
+    Str(&'a str),
+    /// Unsigned integer of 128 bits or less, including compact-encoded integers.
+    Unsigned(u128),
+    /// Signed integer of 128 bits or less.
+    Signed(i128),
+    /// Unsigned 256 bits integer, in little endian.
+    U256(&'a [u8; 32]),
+    /// Signed 256 bits integer, in little endian.
+    I256(&'a [u8; 32]),
+    /// Sequence or array of `u8`.
+    Bytes(&'a [u8]),
+    /// Sequence or array of elements other than `u8`.
+    Sequence(Vec<Value<'a>>),
+    /// Struct or tuple.
+    Composite(Vec<(Option<&'a str>, Value<'a>)>),
+    /// Variant of an enum.
+    Variant {
+        /// Name of the variant.
+        name: &'a str,
+        /// Fields of the variant.
+        fields: Vec<(Option<&'a str>, Value<'a>)>,
+    },
+    /// Sequence of bits.
+    BitSequence {
+        /// Number of bits in the sequence.
+        num_bits: usize,
+        /// Storage of the bits, whose layout depends on the type.
+        data: &'a [u8],
+    },
+}
+
+/// Decodes a value of the given type found at the start of `bytes`.
+///
+/// Returns the decoded value and the bytes that follow it.
+pub fn decode_value<'a>(
+    metadata: &MetadataRef<'a>,
+    ty: u32,
+    bytes: &'a [u8],
+) -> Result<(Value<'a>, &'a [u8]), DecodeValueError> {
+    decode_inner(metadata, ty, bytes, 0)
+}
+
+/// Error potentially returned by [`decode_value`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum DecodeValueError {
+    /// A type couldn't be found in the registry.
+    #[display(fmt = "Unknown type: {_0}")]
+    UnknownType(u32),
+    /// The value ends before the end of its type.
+    Truncated,
+    /// The value doesn't match its type.
+    InvalidValue,
+    /// The index of an enum variant doesn't correspond to any known variant.
+    #[display(fmt = "Unknown variant index {index} for type {ty}")]
+    UnknownVariant { ty: u32, index: u8 },
+    /// Types are too deeply nested.
+    RecursionLimitReached,
+    /// A list of zero-sized elements is longer than what is accepted.
+    TooManyZeroSizedElements,
+}
+
+fn decode_inner<'a>(
+    metadata: &MetadataRef<'a>,
+    ty: u32,
+    bytes: &'a [u8],
+    depth: u32,
+) -> Result<(Value<'a>, &'a [u8]), DecodeValueError> {
+    if depth >= MAX_DEPTH {
+        return Err(DecodeValueError::RecursionLimitReached);
+    }
+
+    let type_def = &metadata
+        .type_by_id(ty)
+        .ok_or(DecodeValueError::UnknownType(ty))?
+        .type_def;
+
+    match type_def {
+        TypeDef::Composite(fields) => {
+            let (fields, rest) = decode_fields(
+                metadata,
+                fields.iter().map(|f| (f.name, f.ty)),
+                bytes,
+                depth,
+            )?;
+            Ok((Value::Composite(fields), rest))
+        }
+        TypeDef::Variant(variants) => {
+            let (index, rest) = bytes.split_first().ok_or(DecodeValueError::Truncated)?;
+            let variant = variants
+                .iter()
+                .find(|v| v.index == *index)
+                .ok_or(DecodeValueError::UnknownVariant { ty, index: *index })?;
+            let (fields, rest) = decode_fields(
+                metadata,
+                variant.fields.iter().map(|f| (f.name, f.ty)),
+                rest,
+                depth,
+            )?;
+            Ok((
+                Value::Variant {
+                    name: variant.name,
+                    fields,
+                },
+                rest,
+            ))
+        }
+        TypeDef::Sequence(elem_ty) => {
+            let (rest, len) =
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(bytes)
+                    .map_err(|_| DecodeValueError::InvalidValue)?;
+            decode_list(metadata, *elem_ty, len, rest, depth)
+        }
+        TypeDef::Array { len, ty: elem_ty } => decode_list(
+            metadata,
+            *elem_ty,
+            usize::try_from(*len).map_err(|_| DecodeValueError::InvalidValue)?,
+            bytes,
+            depth,
+        ),
+        TypeDef::Tuple(types) => {
+            let (fields, rest) =
+                decode_fields(metadata, types.iter().map(|ty| (None, *ty)), bytes, depth)?;
+            Ok((Value::Composite(fields), rest))
+        }
+        TypeDef::Primitive(primitive) => decode_primitive(*primitive, bytes),
+        TypeDef::Compact(_) => {
+            let (rest, value) =
+                crate::util::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(bytes)
+                    .map_err(|_| DecodeValueError::InvalidValue)?;
+            Ok((Value::Unsigned(value), rest))
+        }
+        TypeDef::BitSequence { bit_store_ty, .. } => {
+            let store_bytes = match metadata.type_by_id(*bit_store_ty).map(|ty| &ty.type_def) {
+                Some(TypeDef::Primitive(Primitive::U8)) => 1,
+                Some(TypeDef::Primitive(Primitive::U16)) => 2,
+                Some(TypeDef::Primitive(Primitive::U32)) => 4,
+                Some(TypeDef::Primitive(Primitive::U64)) => 8,
+                Some(_) => return Err(DecodeValueError::InvalidValue),
+                None => return Err(DecodeValueError::UnknownType(*bit_store_ty)),
+            };
+            let (rest, num_bits) =
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(bytes)
+                    .map_err(|_| DecodeValueError::InvalidValue)?;
+            let num_bytes = num_bits.div_ceil(store_bytes * 8) * store_bytes;
+            if rest.len() < num_bytes {
+                return Err(DecodeValueError::Truncated);
+            }
+            let (data, rest) = rest.split_at(num_bytes);
+            Ok((Value::BitSequence { num_bits, data }, rest))
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn decode_fields<'a>(
+    metadata: &MetadataRef<'a>,
+    fields: impl ExactSizeIterator<Item = (Option<&'a str>, u32)>,
+    mut bytes: &'a [u8],
+    depth: u32,
+) -> Result<(Vec<(Option<&'a str>, Value<'a>)>, &'a [u8]), DecodeValueError> {
+    let mut out = Vec::with_capacity(fields.len());
+    for (name, ty) in fields {
+        let (value, rest) = decode_inner(metadata, ty, bytes, depth + 1)?;
+        out.push((name, value));
+        bytes = rest;
+    }
+    Ok((out, bytes))
+}
+
+fn decode_list<'a>(
+    metadata: &MetadataRef<'a>,
+    elem_ty: u32,
+    len: usize,
+    bytes: &'a [u8],
+    depth: u32,
+) -> Result<(Value<'a>, &'a [u8]), DecodeValueError> {
+    if matches!(
+        metadata.type_by_id(elem_ty).map(|ty| &ty.type_def),
+        Some(TypeDef::Primitive(Primitive::U8))
+    ) {
+        if bytes.len() < len {
+            return Err(DecodeValueError::Truncated);
+        }
+        let (data, rest) = bytes.split_at(len);
+        return Ok((Value::Bytes(data), rest));
+    }
+
+    if len == 0 {
+        return Ok((Value::Sequence(Vec::new()), bytes));
+    }
+
+    // Whether a type is zero-sized doesn't depend on the value, so decoding the first element
+    // is enough to know whether the elements occupy at least one byte.
+    let (first, rest) = decode_inner(metadata, elem_ty, bytes, depth + 1)?;
+    if rest.len() == bytes.len() {
+        if len > MAX_ZERO_SIZED_ELEMENTS {
+            return Err(DecodeValueError::TooManyZeroSizedElements);
+        }
+        return Ok((Value::Sequence(alloc::vec![first; len]), rest));
+    }
+
+    // Each element is at least one byte long, so the length can't be superior to the number of
+    // bytes. This check avoids decoding or allocating a very large number of elements.
+    if len > bytes.len() {
+        return Err(DecodeValueError::Truncated);
+    }
+
+    let mut out = Vec::with_capacity(len);
+    out.push(first);
+    let mut bytes = rest;
+    for _ in 1..len {
+        let (value, rest) = decode_inner(metadata, elem_ty, bytes, depth + 1)?;
+        out.push(value);
+        bytes = rest;
+    }
+    Ok((Value::Sequence(out), bytes))
+}
+
+fn decode_primitive(
+    primitive: Primitive,
+    bytes: &[u8],
+) -> Result<(Value<'_>, &[u8]), DecodeValueError> {
+    fn take<const N: usize>(bytes: &[u8]) -> Result<(&[u8; N], &[u8]), DecodeValueError> {
+        if bytes.len() < N {
+            return Err(DecodeValueError::Truncated);
+        }
+        let (value, rest) = bytes.split_at(N);
+        Ok((<&[u8; N]>::try_from(value).unwrap(), rest))
+    }
+
+    Ok(match primitive {
+        Primitive::Bool => match bytes.split_first() {
+            Some((0, rest)) => (Value::Bool(false), rest),
+            Some((1, rest)) => (Value::Bool(true), rest),
+            Some(_) => return Err(DecodeValueError::InvalidValue),
+            None => return Err(DecodeValueError::Truncated),
+        },
+        Primitive::Char => {
+            let (value, rest) = take::<4>(bytes)?;
+            let value =
+                char::from_u32(u32::from_le_bytes(*value)).ok_or(DecodeValueError::InvalidValue)?;
+            (Value::Char(value), rest)
+        }
+        Primitive::Str => {
+            let (rest, value) = nom::multi::length_data(
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>,
+            )(bytes)
+            .map_err(|_| DecodeValueError::InvalidValue)?;
+            let value = core::str::from_utf8(value).map_err(|_| DecodeValueError::InvalidValue)?;
+            (Value::Str(value), rest)
+        }
+        Primitive::U8 => {
+            let (value, rest) = take::<1>(bytes)?;
+            (Value::Unsigned(u128::from(value[0])), rest)
+        }
+        Primitive::U16 => {
+            let (value, rest) = take::<2>(bytes)?;
+            (
+                Value::Unsigned(u128::from(u16::from_le_bytes(*value))),
+                rest,
+            )
+        }
+        Primitive::U32 => {
+            let (value, rest) = take::<4>(bytes)?;
+            (
+                Value::Unsigned(u128::from(u32::from_le_bytes(*value))),
+                rest,
+            )
+        }
+        Primitive::U64 => {
+            let (value, rest) = take::<8>(bytes)?;
+            (
+                Value::Unsigned(u128::from(u64::from_le_bytes(*value))),
+                rest,
+            )
+        }
+        Primitive::U128 => {
+            let (value, rest) = take::<16>(bytes)?;
+            (Value::Unsigned(u128::from_le_bytes(*value)), rest)
+        }
+        Primitive::U256 => {
+            let (value, rest) = take::<32>(bytes)?;
+            (Value::U256(value), rest)
+        }
+        Primitive::I8 => {
+            let (value, rest) = take::<1>(bytes)?;
+            (Value::Signed(i128::from(i8::from_le_bytes(*value))), rest)
+        }
+        Primitive::I16 => {
+            let (value, rest) = take::<2>(bytes)?;
+            (Value::Signed(i128::from(i16::from_le_bytes(*value))), rest)
+        }
+        Primitive::I32 => {
+            let (value, rest) = take::<4>(bytes)?;
+            (Value::Signed(i128::from(i32::from_le_bytes(*value))), rest)
+        }
+        Primitive::I64 => {
+            let (value, rest) = take::<8>(bytes)?;
+            (Value::Signed(i128::from(i64::from_le_bytes(*value))), rest)
+        }
+        Primitive::I128 => {
+            let (value, rest) = take::<16>(bytes)?;
+            (Value::Signed(i128::from_le_bytes(*value)), rest)
+        }
+        Primitive::I256 => {
+            let (value, rest) = take::<32>(bytes)?;
+            (Value::I256(value), rest)
+        }
+    })
+}
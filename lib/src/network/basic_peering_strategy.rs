@@ -54,7 +54,7 @@ use alloc::{
     vec::Vec,
 };
 use core::{hash::Hash, iter, ops};
-use rand::seq::IteratorRandom as _;
+use rand::{seq::IteratorRandom as _, Rng as _};
 use rand_chacha::{
     rand_core::{RngCore as _, SeedableRng as _},
     ChaCha20Rng,
@@ -511,6 +511,56 @@ where
         }
     }
 
+    /// Same as [`BasicPeeringStrategy::pick_assignable_peer`], except that the peer is chosen
+    /// randomly amongst the assignable peers for which `score` returns the highest value.
+    pub fn pick_assignable_peer_by_score(
+        &'_ mut self,
+        chain: &TChainId,
+        now: &TInstant,
+        mut score: impl FnMut(&PeerId) -> i32,
+    ) -> AssignablePeer<'_, TInstant> {
+        let Some(&chain_index) = self.chains_indices.get(chain) else {
+            return AssignablePeer::NoPeer;
+        };
+
+        // Best score so far, and peer chosen amongst the ones that have this score.
+        let mut best: Option<(i32, usize)> = None;
+        let mut num_best = 0u32;
+
+        for (_, _, peer_id_index) in self.peers_chains_by_state.range(
+            (chain_index, PeerChainState::Assignable, usize::MIN)
+                ..=(
+                    chain_index,
+                    PeerChainState::Banned {
+                        expires: now.clone(),
+                    },
+                    usize::MAX,
+                ),
+        ) {
+            let peer_score = score(&self.peer_ids[*peer_id_index]);
+            match best {
+                Some((best_score, _)) if peer_score < best_score => continue,
+                Some((best_score, _)) if peer_score == best_score => {
+                    // Reservoir sampling, in order for all the peers with the best score to have
+                    // the same probability of being chosen.
+                    num_best += 1;
+                    if !self.randomness.gen_ratio(1, num_best) {
+                        continue;
+                    }
+                }
+                _ => num_best = 1,
+            }
+            best = Some((peer_score, *peer_id_index));
+        }
+
+        if let Some((_, peer_id_index)) = best {
+            return AssignablePeer::Assignable(&self.peer_ids[peer_id_index]);
+        }
+
+        // No peer is assignable. The non-scored version reports the bans.
+        self.pick_assignable_peer(chain, now)
+    }
+
     /// Assigns a slot to the given peer on the given chain.
     ///
     /// Acts as an implicit call to [`BasicPeeringStrategy::insert_chain_peer`].
@@ -1015,8 +1065,8 @@ where
 #[cfg(test)]
 mod tests {
     use super::{
-        BasicPeeringStrategy, Config, InsertAddressConnectionsResult, InsertAddressResult,
-        InsertChainPeerResult,
+        AssignablePeer, BasicPeeringStrategy, Config, InsertAddressConnectionsResult,
+        InsertAddressResult, InsertChainPeerResult,
     };
     use crate::network::service::{peer_id::PublicKey, PeerId};
     use core::time::Duration;
@@ -1130,5 +1180,47 @@ mod tests {
         assert_eq!(bps.peer_addresses(&peer_id).count(), 1);
     }
 
+    #[test]
+    fn pick_assignable_peer_prefers_highest_score() {
+        let mut bps = BasicPeeringStrategy::<u32, Duration>::new(Config {
+            randomness_seed: [0; 32],
+            peers_capacity: 0,
+            chains_capacity: 0,
+        });
+
+        let peer_ids = (0..8u8)
+            .map(|n| PeerId::from_public_key(&PublicKey::Ed25519([n; 32])))
+            .collect::<Vec<_>>();
+        for peer_id in &peer_ids {
+            bps.insert_chain_peer(0, peer_id.clone(), usize::MAX);
+        }
+
+        let best = peer_ids[5].clone();
+        for _ in 0..16 {
+            match bps.pick_assignable_peer_by_score(&0, &Duration::new(0, 0), |peer_id| {
+                if *peer_id == best {
+                    10
+                } else {
+                    0
+                }
+            }) {
+                AssignablePeer::Assignable(peer_id) => assert_eq!(*peer_id, best),
+                _ => panic!(),
+            }
+        }
+
+        // Once the best peer has a slot, another one is picked.
+        bps.assign_slot(&0, &best);
+        match bps.pick_assignable_peer_by_score(&0, &Duration::new(0, 0), |_| 0) {
+            AssignablePeer::Assignable(peer_id) => assert_ne!(*peer_id, best),
+            _ => panic!(),
+        }
+
+        assert!(matches!(
+            bps.pick_assignable_peer_by_score(&1, &Duration::new(0, 0), |_| 0),
+            AssignablePeer::NoPeer
+        ));
+    }
+
     // TODO: more tests
 }
@@ -17,7 +17,11 @@
 
 use crate::util::protobuf;
 
-use alloc::{borrow::Cow, vec::Vec};
+use alloc::{
+    borrow::Cow,
+    vec::{self, Vec},
+};
+use core::iter;
 
 /// Description of a storage proof request that can be sent to a peer.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -31,6 +35,9 @@ pub struct StorageProofRequestConfig<TKeysIter> {
 // See https://github.com/paritytech/substrate/blob/c8653447fc8ef8d95a92fe164c96dffb37919e85/client/network/sync/src/schema/api.v1.proto
 // for protocol definition.
 
+/// Maximum number of keys that a storage proof request received from a peer can contain.
+const MAX_STORAGE_PROOF_REQUEST_KEYS: usize = 1024;
+
 /// Builds the bytes corresponding to a storage proof request.
 pub fn build_storage_proof_request<'a>(
     config: StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]> + Clone + 'a> + 'a>,
@@ -48,6 +55,61 @@ pub fn build_storage_proof_request<'a>(
     )
 }
 
+/// Decodes a storage proof request received from a peer.
+///
+/// Requests of the light protocol that aren't storage proof requests, such as call proof requests
+/// or child trie storage proof requests, lead to [`DecodeStorageProofRequestError::NotStorageProof`].
+///
+/// Requests containing more than 1024 keys are considered invalid, as answering them would be
+/// too expensive.
+pub fn decode_storage_proof_request(
+    request_bytes: &[u8],
+) -> Result<StorageProofRequestConfig<vec::IntoIter<&[u8]>>, DecodeStorageProofRequestError> {
+    let mut parser = nom::combinator::all_consuming::<_, _, nom::error::Error<&[u8]>, _>(
+        nom::combinator::complete(protobuf::message_decode! {
+            #[optional] read = 2 => protobuf::message_tag_decode(protobuf::message_decode!{
+                #[required] block = 2 => protobuf::bytes_tag_decode,
+                #[repeated(max = MAX_STORAGE_PROOF_REQUEST_KEYS)] keys = 3 => protobuf::bytes_tag_decode,
+            }),
+        }),
+    );
+
+    let read = match nom::Finish::finish(parser(request_bytes)) {
+        Ok((_, rq)) => rq.read,
+        Err(_) => return Err(DecodeStorageProofRequestError::ProtobufDecode),
+    };
+
+    let Some(read) = read else {
+        return Err(DecodeStorageProofRequestError::NotStorageProof);
+    };
+
+    Ok(StorageProofRequestConfig {
+        block_hash: <[u8; 32]>::try_from(read.block)
+            .map_err(|_| DecodeStorageProofRequestError::InvalidBlockHashLength)?,
+        keys: read.keys.into_iter(),
+    })
+}
+
+/// Error potentially returned by [`decode_storage_proof_request`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeStorageProofRequestError {
+    /// Error while decoding the Protobuf encoding.
+    ProtobufDecode,
+    /// Block hash doesn't have the correct length.
+    InvalidBlockHashLength,
+    /// Request is a valid request of the light protocol but isn't a storage proof request.
+    NotStorageProof,
+}
+
+/// Builds the bytes corresponding to a response to a storage proof request.
+///
+/// `proof` must be the SCALE-encoded Merkle proof.
+pub fn build_storage_proof_response(
+    proof: &[u8],
+) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+    protobuf::message_tag_encode(2, protobuf::bytes_tag_encode(2, proof))
+}
+
 /// Description of a call proof request that can be sent to a peer.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CallProofRequestConfig<'a, I> {
@@ -92,6 +154,58 @@ pub fn build_call_proof_request<'a>(
     )
 }
 
+/// Decodes a call proof request received from a peer.
+///
+/// Requests of the light protocol that aren't call proof requests, such as storage proof requests
+/// or child trie storage proof requests, lead to [`DecodeCallProofRequestError::NotCallProof`].
+pub fn decode_call_proof_request(
+    request_bytes: &[u8],
+) -> Result<CallProofRequestConfig<'_, iter::Once<&[u8]>>, DecodeCallProofRequestError> {
+    let mut parser = nom::combinator::all_consuming::<_, _, nom::error::Error<&[u8]>, _>(
+        nom::combinator::complete(protobuf::message_decode! {
+            #[optional] call = 1 => protobuf::message_tag_decode(protobuf::message_decode!{
+                #[required] block = 2 => protobuf::bytes_tag_decode,
+                #[required] method = 3 => protobuf::string_tag_decode,
+                #[required] data = 4 => protobuf::bytes_tag_decode,
+            }),
+        }),
+    );
+
+    let call = match nom::Finish::finish(parser(request_bytes)) {
+        Ok((_, rq)) => rq.call,
+        Err(_) => return Err(DecodeCallProofRequestError::ProtobufDecode),
+    };
+
+    let Some(call) = call else {
+        return Err(DecodeCallProofRequestError::NotCallProof);
+    };
+
+    Ok(CallProofRequestConfig {
+        block_hash: <[u8; 32]>::try_from(call.block)
+            .map_err(|_| DecodeCallProofRequestError::InvalidBlockHashLength)?,
+        method: Cow::Borrowed(call.method),
+        parameter_vectored: iter::once(call.data),
+    })
+}
+
+/// Error potentially returned by [`decode_call_proof_request`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeCallProofRequestError {
+    /// Error while decoding the Protobuf encoding.
+    ProtobufDecode,
+    /// Block hash doesn't have the correct length.
+    InvalidBlockHashLength,
+    /// Request is a valid request of the light protocol but isn't a call proof request.
+    NotCallProof,
+}
+
+/// Builds the bytes corresponding to a response to a call proof request.
+///
+/// `proof` must be the SCALE-encoded Merkle proof.
+pub fn build_call_proof_response(proof: &[u8]) -> impl Iterator<Item = impl AsRef<[u8]> + '_> + '_ {
+    protobuf::message_tag_encode(1, protobuf::bytes_tag_encode(2, proof))
+}
+
 /// Decodes a response to a storage proof request or a call proof request.
 ///
 /// On success, returns a SCALE-encoded Merkle proof, or `None` if the remote couldn't answer
@@ -141,3 +255,130 @@ pub enum StorageOrCallProof {
     StorageProof,
     CallProof,
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn storage_proof_request_encode_decode() {
+        let encoded = super::build_storage_proof_request(super::StorageProofRequestConfig {
+            block_hash: [0xaa; 32],
+            keys: [&b"foo"[..], &b""[..], &b"bar"[..]].into_iter(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_storage_proof_request(&encoded).unwrap();
+        assert_eq!(decoded.block_hash, [0xaa; 32]);
+        assert_eq!(
+            decoded.keys.collect::<Vec<_>>(),
+            vec![&b"foo"[..], &b""[..], &b"bar"[..]]
+        );
+    }
+
+    #[test]
+    fn storage_proof_request_too_many_keys() {
+        let encoded = super::build_storage_proof_request(super::StorageProofRequestConfig {
+            block_hash: [0xaa; 32],
+            keys: core::iter::repeat_n(&b"foo"[..], super::MAX_STORAGE_PROOF_REQUEST_KEYS + 1),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert!(matches!(
+            super::decode_storage_proof_request(&encoded),
+            Err(super::DecodeStorageProofRequestError::ProtobufDecode)
+        ));
+    }
+
+    #[test]
+    fn call_proof_request_not_storage_proof() {
+        let encoded = super::build_call_proof_request(super::CallProofRequestConfig {
+            block_hash: [0xaa; 32],
+            method: "Core_version".into(),
+            parameter_vectored: core::iter::empty::<Vec<u8>>(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert!(matches!(
+            super::decode_storage_proof_request(&encoded),
+            Err(super::DecodeStorageProofRequestError::NotStorageProof)
+        ));
+    }
+
+    #[test]
+    fn call_proof_request_encode_decode() {
+        let encoded = super::build_call_proof_request(super::CallProofRequestConfig {
+            block_hash: [0xaa; 32],
+            method: "Core_version".into(),
+            parameter_vectored: [&b"foo"[..], &b"bar"[..]].into_iter(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_call_proof_request(&encoded).unwrap();
+        assert_eq!(decoded.block_hash, [0xaa; 32]);
+        assert_eq!(decoded.method, "Core_version");
+        assert_eq!(
+            decoded.parameter_vectored.collect::<Vec<_>>(),
+            vec![&b"foobar"[..]]
+        );
+    }
+
+    #[test]
+    fn storage_proof_request_not_call_proof() {
+        let encoded = super::build_storage_proof_request(super::StorageProofRequestConfig {
+            block_hash: [0xaa; 32],
+            keys: [&b"foo"[..]].into_iter(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        assert!(matches!(
+            super::decode_call_proof_request(&encoded),
+            Err(super::DecodeCallProofRequestError::NotCallProof)
+        ));
+    }
+
+    #[test]
+    fn call_proof_response_encode_decode() {
+        let encoded =
+            super::build_call_proof_response(&[1, 2, 3, 4]).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        let decoded = super::decode_storage_or_call_proof_response(
+            super::StorageOrCallProof::CallProof,
+            &encoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, Some(&[1, 2, 3, 4][..]));
+    }
+
+    #[test]
+    fn storage_proof_response_encode_decode() {
+        let encoded =
+            super::build_storage_proof_response(&[1, 2, 3, 4]).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+
+        let decoded = super::decode_storage_or_call_proof_response(
+            super::StorageOrCallProof::StorageProof,
+            &encoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, Some(&[1, 2, 3, 4][..]));
+    }
+}
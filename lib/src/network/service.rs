@@ -151,6 +151,9 @@ pub struct ChainConfig<TChain> {
     /// `true` if incoming block requests are allowed.
     pub allow_inbound_block_requests: bool,
 
+    /// `true` if incoming storage proof and call proof requests are allowed.
+    pub allow_inbound_light_requests: bool,
+
     /// Hash of the best block according to the local node.
     pub best_hash: [u8; 32],
     /// Height of the best block according to the local node.
@@ -261,6 +264,9 @@ struct Chain<TChain> {
     /// See [`ChainConfig::allow_inbound_block_requests`].
     allow_inbound_block_requests: bool,
 
+    /// See [`ChainConfig::allow_inbound_light_requests`].
+    allow_inbound_light_requests: bool,
+
     /// See [`ChainConfig::user_data`].
     user_data: TChain,
 }
@@ -431,6 +437,7 @@ where
             best_hash: config.best_hash,
             best_number: config.best_number,
             allow_inbound_block_requests: config.allow_inbound_block_requests,
+            allow_inbound_light_requests: config.allow_inbound_light_requests,
             grandpa_protocol_config: config.grandpa_protocol_config,
             user_data: config.user_data,
         });
@@ -1430,6 +1437,13 @@ where
                             self.inner.reject_inbound(substream_id);
                             continue;
                         }
+                        Protocol::LightUnknown { chain_index }
+                            if self.chains[chain_index].allow_inbound_light_requests =>
+                        {
+                            collection::InboundTy::Request {
+                                request_max_size: Some(1024 * 1024),
+                            }
+                        }
 
                         // TODO: the protocols below are not supported yet
                         Protocol::LightUnknown { .. }
@@ -1665,6 +1679,58 @@ where
                                 }
                             }
                         }
+                        Some(Protocol::LightUnknown { chain_index }) => {
+                            match codec::decode_storage_proof_request(&request_payload) {
+                                Ok(config) => {
+                                    return Some(Event::StorageProofRequestIn {
+                                        peer_id,
+                                        chain_id: ChainId(chain_index),
+                                        block_hash: config.block_hash,
+                                        keys: config.keys.map(|k| k.to_vec()).collect(),
+                                        substream_id,
+                                    })
+                                }
+                                Err(codec::DecodeStorageProofRequestError::NotStorageProof) => {
+                                    match codec::decode_call_proof_request(&request_payload) {
+                                        Ok(config) => {
+                                            return Some(Event::CallProofRequestIn {
+                                                peer_id,
+                                                chain_id: ChainId(chain_index),
+                                                block_hash: config.block_hash,
+                                                function_name: config.method.into_owned(),
+                                                parameter: config
+                                                    .parameter_vectored
+                                                    .flat_map(|p| p.iter().copied())
+                                                    .collect(),
+                                                substream_id,
+                                            })
+                                        }
+                                        Err(codec::DecodeCallProofRequestError::NotCallProof) => {
+                                            // Other kinds of light protocol requests are
+                                            // legitimate but not supported.
+                                            let _ = self.substreams.remove(&substream_id);
+                                            self.inner.respond_in_request(substream_id, Err(()));
+                                        }
+                                        Err(error) => {
+                                            let _ = self.substreams.remove(&substream_id);
+                                            self.inner.respond_in_request(substream_id, Err(()));
+                                            return Some(Event::ProtocolError {
+                                                peer_id,
+                                                error: ProtocolError::BadCallProofRequest(error),
+                                            });
+                                        }
+                                    }
+                                }
+                                Err(error) => {
+                                    let _ = self.substreams.remove(&substream_id);
+                                    self.inner.respond_in_request(substream_id, Err(()));
+                                    return Some(Event::ProtocolError {
+                                        peer_id,
+                                        error: ProtocolError::BadStorageProofRequest(error),
+                                    });
+                                }
+                            }
+                        }
                         // Any other protocol is declined when the protocol is negotiated.
                         _ => unreachable!(),
                     }
@@ -3207,6 +3273,15 @@ where
                             })
                             .into_iter(),
                     )
+                    .chain(
+                        chain
+                            .allow_inbound_light_requests
+                            .then_some(codec::ProtocolName::Light {
+                                genesis_hash: chain.genesis_hash,
+                                fork_id: chain.fork_id.as_deref(),
+                            })
+                            .into_iter(),
+                    )
                 }));
 
             let supported_protocols_names = supported_protocols
@@ -3269,6 +3344,87 @@ where
         self.inner.respond_in_request(substream_id, response);
     }
 
+    /// Responds to a storage proof request. Call this function in response to
+    /// a [`Event::StorageProofRequestIn`].
+    ///
+    /// `response` must be the list of entries of the Merkle proof. Pass `None` in order to deny
+    /// the request. Do this if the storage of the requested block isn't available locally.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] is invalid or doesn't correspond to a storage proof request
+    /// or if the request has been cancelled with a [`Event::RequestInCancel`].
+    ///
+    pub fn respond_storage_proof(
+        &mut self,
+        substream_id: SubstreamId,
+        response: Option<impl ExactSizeIterator<Item = impl AsRef<[u8]>>>,
+    ) {
+        let substream_info = self.substreams.remove(&substream_id).unwrap();
+        assert!(matches!(
+            substream_info.protocol,
+            Some(Protocol::LightUnknown { .. })
+        ));
+
+        let response = if let Some(response) = response {
+            let proof = encode_proof_entries(response);
+            Ok(
+                codec::build_storage_proof_response(&proof).fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                }),
+            )
+        } else {
+            Err(())
+        };
+
+        self.inner.respond_in_request(substream_id, response);
+    }
+
+    /// Responds to a call proof request. Call this function in response to
+    /// a [`Event::CallProofRequestIn`].
+    ///
+    /// `response` must be the list of entries of the Merkle proof of the storage accessed by the
+    /// call. Pass `None` in order to deny the request. Do this if the storage of the requested
+    /// block isn't available locally or if the call has failed.
+    ///
+    /// This function might generate a message destined a connection. Use
+    /// [`ChainNetwork::pull_message_to_connection`] to process messages after it has returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] is invalid or doesn't correspond to a call proof request
+    /// or if the request has been cancelled with a [`Event::RequestInCancel`].
+    ///
+    pub fn respond_call_proof(
+        &mut self,
+        substream_id: SubstreamId,
+        response: Option<impl ExactSizeIterator<Item = impl AsRef<[u8]>>>,
+    ) {
+        let substream_info = self.substreams.remove(&substream_id).unwrap();
+        assert!(matches!(
+            substream_info.protocol,
+            Some(Protocol::LightUnknown { .. })
+        ));
+
+        let response = if let Some(response) = response {
+            let proof = encode_proof_entries(response);
+            Ok(
+                codec::build_call_proof_response(&proof).fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                }),
+            )
+        } else {
+            Err(())
+        };
+
+        self.inner.respond_in_request(substream_id, response);
+    }
+
     /// Returns the list of all peers for a [`Event::GossipConnected`] event of the given kind has
     /// been emitted.
     /// It is possible to send gossip notifications to these peers.
@@ -4137,6 +4293,20 @@ impl<TChain, TConn, TNow> ops::IndexMut<ConnectionId> for ChainNetwork<TChain, T
     }
 }
 
+/// Turns a list of Merkle proof entries into a SCALE-encoded Merkle proof.
+fn encode_proof_entries(entries: impl ExactSizeIterator<Item = impl AsRef<[u8]>>) -> Vec<u8> {
+    // The proof is a SCALE-encoded list of entries.
+    let mut proof = crate::util::encode_scale_compact_usize(entries.len())
+        .as_ref()
+        .to_vec();
+    for entry in entries {
+        let entry = entry.as_ref();
+        proof.extend_from_slice(crate::util::encode_scale_compact_usize(entry.len()).as_ref());
+        proof.extend_from_slice(entry);
+    }
+    proof
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum GossipKind {
     ConsensusTransactions,
@@ -4367,6 +4537,46 @@ pub enum Event<TConn> {
         substream_id: SubstreamId,
     },
 
+    /// A remote has sent a request for a proof of some storage entries.
+    ///
+    /// Can only happen for chains where [`ChainConfig::allow_inbound_light_requests`] is
+    /// `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_storage_proof`].
+    StorageProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_id: ChainId,
+        /// Hash of the block whose storage is requested.
+        block_hash: [u8; 32],
+        /// List of keys whose storage value must be proven.
+        keys: Vec<Vec<u8>>,
+        /// Identifier of the request. Necessary to send back the answer.
+        substream_id: SubstreamId,
+    },
+
+    /// A remote has sent a request for a proof of the storage accessed by a runtime call.
+    ///
+    /// Can only happen for chains where [`ChainConfig::allow_inbound_light_requests`] is
+    /// `true`.
+    ///
+    /// You are strongly encouraged to call [`ChainNetwork::respond_call_proof`].
+    CallProofRequestIn {
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Index of the chain concerned by the request.
+        chain_id: ChainId,
+        /// Hash of the block on top of which the call must be performed.
+        block_hash: [u8; 32],
+        /// Name of the runtime function to call.
+        function_name: String,
+        /// Parameter to pass to the runtime function.
+        parameter: Vec<u8>,
+        /// Identifier of the request. Necessary to send back the answer.
+        substream_id: SubstreamId,
+    },
+
     /// A remote is no longer interested in the response to a request.
     ///
     /// Calling [`ChainNetwork::respond_identify`], [`ChainNetwork::respond_blocks`], or similar
@@ -4404,6 +4614,12 @@ pub enum ProtocolError {
     /// Error while decoding a received blocks request.
     #[display(fmt = "Error while decoding a received blocks request: {_0}")]
     BadBlocksRequest(codec::DecodeBlockRequestError),
+    /// Error while decoding a received storage proof request.
+    #[display(fmt = "Error while decoding a received storage proof request: {_0}")]
+    BadStorageProofRequest(codec::DecodeStorageProofRequestError),
+    /// Error while decoding a received call proof request.
+    #[display(fmt = "Error while decoding a received call proof request: {_0}")]
+    BadCallProofRequest(codec::DecodeCallProofRequestError),
 }
 
 /// Error potentially returned by [`ChainNetwork::gossip_open`].
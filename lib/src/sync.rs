@@ -68,4 +68,5 @@
 pub mod all;
 pub mod all_forks;
 pub mod para;
+pub mod state_sync;
 pub mod warp_sync;
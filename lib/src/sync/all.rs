@@ -2364,12 +2364,15 @@ impl<TRq, TSrc, TBl> WarpSyncBuildRuntime<TRq, TSrc, TBl> {
     pub fn build(
         self,
         exec_hint: ExecHint,
+        fuel_metering: bool,
         allow_unresolved_imports: bool,
     ) -> (
         AllSync<TRq, TSrc, TBl>,
         Result<(), WarpSyncBuildRuntimeError>,
     ) {
-        let (warp_sync, outcome) = self.inner.build(exec_hint, allow_unresolved_imports);
+        let (warp_sync, outcome) =
+            self.inner
+                .build(exec_hint, fuel_metering, allow_unresolved_imports);
 
         (
             AllSync {
@@ -32,6 +32,44 @@
 //! See the [`persisted_validation_data_parameters`] to obtain the input to pass to the runtime
 //! function. The first parameter is a `para_id` found in the chain specification of the
 //! parachain of parathread.
+//!
+//! # Head data
+//!
+//! For chains built on top of Cumulus, the head data is a SCALE-encoded block header, and the
+//! hash of the parachain block is the hash of this header. Some parachains, however, append
+//! additional information after the header. See [`HeadDataFormat`] and [`decode_head_data`].
+//!
+//! # Asynchronous backing
+//!
+//! The head data returned by `ParachainHost_persisted_validation_data` (with the
+//! [`OccupiedCoreAssumption::TimedOut`] assumption) corresponds to the latest parachain block
+//! that has been included in the relay chain. With asynchronous backing, however, one or more
+//! parachain blocks, called candidates, can be backed by the relay chain but still be pending
+//! availability. These candidates are descendants of the included block and are expected to
+//! become included in a later relay chain block.
+//!
+//! The candidates pending availability can be obtained by calling the
+//! `ParachainHost_candidates_pending_availability` runtime function (see
+//! [`CANDIDATES_PENDING_AVAILABILITY_FUNCTION_NAME`]), or the older
+//! `ParachainHost_candidate_pending_availability` runtime function for relay chains that don't
+//! support it (see [`CANDIDATE_PENDING_AVAILABILITY_FUNCTION_NAME`]). Both functions accept the
+//! `para_id` as parameter.
+//!
+//! # Reading the relay chain storage
+//!
+//! Calling runtime functions requires downloading a proof of all the storage items that the
+//! runtime function accesses, and executing the relay chain runtime. As an alternative, the
+//! head data and the candidates pending availability can be read directly from the storage of
+//! the relay chain, by downloading a storage proof of the keys returned by
+//! [`paras_heads_storage_key`] and [`pending_availability_storage_key`].
+//!
+//! Contrary to runtime functions, the layout of the storage is an implementation detail of the
+//! relay chain runtime. The functions of this module assume the storage layout of the Polkadot
+//! and Kusama relay chains. If a storage item is missing, it can either mean that the relay
+//! chain uses a different layout or that the parachain isn't registered, and callers should
+//! fall back to calling the runtime functions.
+
+use alloc::vec::Vec;
 
 /// Produces the input to pass to the `ParachainHost_persisted_validation_data` runtime call.
 pub fn persisted_validation_data_parameters(
@@ -48,6 +86,32 @@ pub fn persisted_validation_data_parameters(
 /// Name of the runtime function to call in order to obtain the parachain heads.
 pub const PERSISTED_VALIDATION_FUNCTION_NAME: &str = "ParachainHost_persisted_validation_data";
 
+/// Name of the runtime function to call in order to obtain the list of candidates pending
+/// availability of a parachain.
+///
+/// The parameter of this function is the SCALE-encoded `para_id`. The return value can be
+/// decoded with [`decode_candidates_pending_availability_return_value`].
+pub const CANDIDATES_PENDING_AVAILABILITY_FUNCTION_NAME: &str =
+    "ParachainHost_candidates_pending_availability";
+
+/// Name of the runtime function to call in order to obtain the candidate pending availability of
+/// a parachain. Older equivalent of [`CANDIDATES_PENDING_AVAILABILITY_FUNCTION_NAME`] that
+/// returns at most one candidate.
+///
+/// The parameter of this function is the SCALE-encoded `para_id`. The return value can be
+/// decoded with [`decode_candidate_pending_availability_return_value`].
+pub const CANDIDATE_PENDING_AVAILABILITY_FUNCTION_NAME: &str =
+    "ParachainHost_candidate_pending_availability";
+
+/// Consensus engine id of the justifications that prove the finality of a parachain block.
+///
+/// The finality of a parachain block derives from the finality of the relay chain block in
+/// which it has been included. The justification consists in the 32 bytes hash of this relay
+/// chain block.
+///
+/// > **Note**: This is a smoldot-specific concept. Parachain nodes don't store justifications.
+pub const INCLUSION_JUSTIFICATION_ENGINE_ID: [u8; 4] = *b"incl";
+
 /// An assumption being made about the state of an occupied core.
 // TODO: what does that mean?
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -87,11 +151,163 @@ pub fn decode_persisted_validation_data_return_value(
     }
 }
 
+/// Attempt to decode the return value of the `ParachainHost_candidates_pending_availability`
+/// runtime call.
+///
+/// The candidates are returned in the order in which they have been backed, in other words each
+/// candidate is the parent of the next one.
+pub fn decode_candidates_pending_availability_return_value(
+    scale_encoded: &[u8],
+    block_number_bytes: usize,
+) -> Result<Vec<CandidateReceiptRef<'_>>, Error> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> =
+        nom::combinator::complete(nom::combinator::all_consuming(nom::combinator::flat_map(
+            crate::util::nom_scale_compact_usize,
+            move |num_elems| {
+                nom::multi::many_m_n(
+                    num_elems,
+                    num_elems,
+                    committed_candidate_receipt(block_number_bytes),
+                )
+            },
+        )))(scale_encoded);
+    match res {
+        Ok((_, data)) => Ok(data),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(Error(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Attempt to decode the return value of the `ParachainHost_candidate_pending_availability`
+/// runtime call.
+pub fn decode_candidate_pending_availability_return_value(
+    scale_encoded: &[u8],
+    block_number_bytes: usize,
+) -> Result<Option<CandidateReceiptRef<'_>>, Error> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> =
+        nom::combinator::complete(nom::combinator::all_consuming(
+            crate::util::nom_option_decode(committed_candidate_receipt(block_number_bytes)),
+        ))(scale_encoded);
+    match res {
+        Ok((_, data)) => Ok(data),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(Error(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
 /// Error that can happen during the decoding.
 #[derive(Debug, derive_more::Display)]
 #[display(fmt = "Error decoding persisted validation data")]
 pub struct Error(nom::error::ErrorKind);
 
+/// Returns the key in the relay chain storage of the head data of the given parachain.
+///
+/// This is the entry of the `Heads` storage map of the `Paras` pallet. The head data found at
+/// this key is the same as the one returned by the `ParachainHost_persisted_validation_data`
+/// runtime call with the [`OccupiedCoreAssumption::TimedOut`] assumption. It can be decoded with
+/// [`decode_paras_heads_storage_value`].
+pub fn paras_heads_storage_key(para_id: u32) -> Vec<u8> {
+    crate::json_rpc::storage_keys::storage_map_key(
+        "Paras",
+        "Heads",
+        [(
+            crate::json_rpc::storage_keys::StorageHasher::Twox64Concat,
+            &para_id.to_le_bytes()[..],
+        )],
+    )
+}
+
+/// Attempt to decode the storage value found at the key returned by [`paras_heads_storage_key`].
+///
+/// Returns the head data of the parachain.
+pub fn decode_paras_heads_storage_value(scale_encoded: &[u8]) -> Result<&[u8], Error> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> = nom::combinator::complete(
+        nom::combinator::all_consuming(crate::util::nom_bytes_decode),
+    )(scale_encoded);
+    match res {
+        Ok((_, head_data)) => Ok(head_data),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(Error(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Returns the key in the relay chain storage of the list of candidates pending availability of
+/// the given parachain.
+///
+/// This is the entry of the `V1` storage map of the `ParaInclusion` pallet. Contrary to
+/// [`paras_heads_storage_key`], the absence of a storage value at this key is normal and means
+/// that no candidate is pending availability. The storage value can be decoded with
+/// [`decode_pending_availability_storage_value`].
+pub fn pending_availability_storage_key(para_id: u32) -> Vec<u8> {
+    crate::json_rpc::storage_keys::storage_map_key(
+        "ParaInclusion",
+        "V1",
+        [(
+            crate::json_rpc::storage_keys::StorageHasher::Twox64Concat,
+            &para_id.to_le_bytes()[..],
+        )],
+    )
+}
+
+/// Attempt to decode the storage value found at the key returned by
+/// [`pending_availability_storage_key`].
+///
+/// Just like [`decode_candidates_pending_availability_return_value`], the candidates are
+/// returned in the order in which they have been backed.
+pub fn decode_pending_availability_storage_value(
+    scale_encoded: &[u8],
+    block_number_bytes: usize,
+) -> Result<Vec<CandidateReceiptRef<'_>>, Error> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> =
+        nom::combinator::complete(nom::combinator::all_consuming(nom::combinator::flat_map(
+            crate::util::nom_scale_compact_usize,
+            move |num_elems| {
+                nom::multi::many_m_n(
+                    num_elems,
+                    num_elems,
+                    candidate_pending_availability(block_number_bytes),
+                )
+            },
+        )))(scale_encoded);
+    match res {
+        Ok((_, data)) => Ok(data),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(Error(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Format of the head data of a parachain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum HeadDataFormat {
+    /// The head data is a SCALE-encoded block header. This is the case for chains built on top
+    /// of Cumulus.
+    #[default]
+    Header,
+    /// The head data starts with a SCALE-encoded block header, followed with additional data
+    /// specific to the parachain. The additional data is ignored.
+    HeaderWithSuffix,
+}
+
+/// Extracts the SCALE-encoded block header from the given head data.
+///
+/// The hash of the parachain block can then be obtained by hashing the returned header.
+pub fn decode_head_data(
+    head_data: &[u8],
+    format: HeadDataFormat,
+    block_number_bytes: usize,
+) -> Result<&[u8], crate::header::Error> {
+    match format {
+        HeadDataFormat::Header => {
+            crate::header::decode(head_data, block_number_bytes)?;
+            Ok(head_data)
+        }
+        HeadDataFormat::HeaderWithSuffix => {
+            let (_, remainder) = crate::header::decode_partial(head_data, block_number_bytes)?;
+            Ok(&head_data[..head_data.len() - remainder.len()])
+        }
+    }
+}
+
 /// Decoded persisted validation data.
 // TODO: document and explain
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -108,6 +324,114 @@ pub struct PersistedValidationDataRef<'a> {
     pub max_pov_size: u32,
 }
 
+/// Decoded candidate receipt of a parachain block that has been backed by the relay chain.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CandidateReceiptRef<'a> {
+    /// Identifier of the parachain the candidate belongs to.
+    pub para_id: u32,
+    /// Hash of the relay chain block the candidate has been built against.
+    pub relay_parent: &'a [u8; 32],
+    /// Hash of [`CandidateReceiptRef::head_data`].
+    pub para_head_hash: &'a [u8; 32],
+    /// Head data of the parachain block produced by the candidate. See
+    /// [`PersistedValidationDataRef::parent_head`].
+    pub head_data: &'a [u8],
+    /// Relay chain block number up to which the horizontal messages have been processed.
+    pub hrmp_watermark: u64,
+}
+
+/// `Nom` combinator that parses a `CommittedCandidateReceipt` into a [`CandidateReceiptRef`].
+fn committed_candidate_receipt<'a, E: nom::error::ParseError<&'a [u8]>>(
+    block_number_bytes: usize,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&[u8], CandidateReceiptRef, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            // Descriptor.
+            nom::number::streaming::le_u32,
+            nom::bytes::streaming::take(32u32),
+            // Collator public key, persisted validation data hash, PoV hash, erasure root,
+            // collator signature. The `V2` version of the descriptor replaces the collator
+            // public key and signature with fields of the same total size.
+            nom::bytes::streaming::take(32u32 * 4 + 64),
+            nom::bytes::streaming::take(32u32),
+            // Validation code hash.
+            nom::bytes::streaming::take(32u32),
+            // Commitments.
+            // Upward messages.
+            nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+                nom::multi::fold_many_m_n(
+                    num_elems,
+                    num_elems,
+                    crate::util::nom_bytes_decode,
+                    || (),
+                    |(), _| (),
+                )
+            }),
+            // Horizontal messages.
+            nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+                nom::multi::fold_many_m_n(
+                    num_elems,
+                    num_elems,
+                    nom::sequence::tuple((
+                        nom::number::streaming::le_u32,
+                        crate::util::nom_bytes_decode,
+                    )),
+                    || (),
+                    |(), _| (),
+                )
+            }),
+            // New validation code.
+            crate::util::nom_option_decode(crate::util::nom_bytes_decode),
+            crate::util::nom_bytes_decode,
+            // Processed downward messages.
+            nom::number::streaming::le_u32,
+            crate::util::nom_varsize_number_decode_u64(block_number_bytes),
+        )),
+        |(para_id, relay_parent, _, para_head_hash, _, _, _, _, head_data, _, hrmp_watermark)| {
+            CandidateReceiptRef {
+                para_id,
+                relay_parent: <&[u8; 32]>::try_from(relay_parent).unwrap(),
+                para_head_hash: <&[u8; 32]>::try_from(para_head_hash).unwrap(),
+                head_data,
+                hrmp_watermark,
+            }
+        },
+    )
+}
+
+/// `Nom` combinator that parses a `CandidatePendingAvailability` found in the storage of the
+/// relay chain into a [`CandidateReceiptRef`].
+fn candidate_pending_availability<'a, E: nom::error::ParseError<&'a [u8]>>(
+    block_number_bytes: usize,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&[u8], CandidateReceiptRef, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            // Core index and candidate hash.
+            nom::bytes::streaming::take(4u32 + 32),
+            // Descriptor and commitments, laid out the same way as a committed candidate receipt.
+            committed_candidate_receipt(block_number_bytes),
+            // Availability votes and backers.
+            bit_vec,
+            bit_vec,
+            // Relay parent number and backed-in number.
+            crate::util::nom_varsize_number_decode_u64(block_number_bytes),
+            crate::util::nom_varsize_number_decode_u64(block_number_bytes),
+            // Backing group.
+            nom::number::streaming::le_u32,
+        )),
+        |(_, receipt, _, _, _, _, _)| receipt,
+    )
+}
+
+/// `Nom` combinator that parses a SCALE-encoded bit vector and returns its underlying bytes.
+fn bit_vec<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], &'a [u8], E> {
+    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_bits| {
+        nom::bytes::streaming::take(num_bits.div_ceil(8))
+    })(bytes)
+}
+
 /// `Nom` combinator that parses a [`PersistedValidationDataRef`].
 fn persisted_validation_data<'a, E: nom::error::ParseError<&'a [u8]>>(
     block_number_bytes: usize,
@@ -133,6 +457,65 @@ fn persisted_validation_data<'a, E: nom::error::ParseError<&'a [u8]>>(
 
 #[cfg(test)]
 mod tests {
+    /// Header of a parachain block built with Cumulus.
+    const PARACHAIN_HEADER: &[u8] = &[
+        22, 207, 102, 211, 103, 223, 185, 141, 222, 102, 202, 83, 47, 68, 220, 233, 136, 252, 151,
+        156, 190, 127, 178, 126, 69, 0, 176, 226, 54, 174, 2, 100, 26, 176, 149, 0, 51, 178, 156,
+        151, 115, 34, 137, 183, 125, 44, 36, 155, 24, 27, 225, 97, 213, 118, 100, 6, 42, 26, 94,
+        247, 4, 39, 46, 159, 44, 211, 102, 99, 155, 215, 16, 175, 12, 245, 124, 60, 237, 51, 48,
+        68, 207, 77, 12, 7, 167, 106, 166, 110, 29, 77, 220, 206, 138, 24, 168, 136, 247, 153, 227,
+        237, 8, 6, 97, 117, 114, 97, 32, 43, 60, 58, 8, 0, 0, 0, 0, 5, 97, 117, 114, 97, 1, 1, 172,
+        118, 69, 16, 88, 154, 84, 2, 85, 206, 148, 69, 115, 132, 204, 88, 113, 101, 26, 4, 1, 7,
+        28, 130, 119, 6, 112, 220, 22, 242, 128, 29, 5, 151, 192, 57, 31, 159, 23, 159, 84, 155,
+        91, 207, 147, 191, 222, 231, 28, 75, 157, 111, 200, 192, 160, 235, 111, 59, 238, 81, 72,
+        169, 102, 136,
+    ];
+
+    /// SCALE-encodes a `CommittedCandidateReceipt` of parachain 2000 whose head data is
+    /// `head_data`.
+    ///
+    /// The fields follow the definition of the type found in the metadata of the Polkadot
+    /// runtime.
+    fn encode_committed_candidate_receipt(relay_parent: &[u8; 32], head_data: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+
+        // Descriptor.
+        // Para id.
+        encoded.extend_from_slice(&2000u32.to_le_bytes());
+        encoded.extend_from_slice(relay_parent);
+        // Collator.
+        encoded.extend_from_slice(&[0xc0; 32]);
+        // Persisted validation data hash.
+        encoded.extend_from_slice(&[0xd0; 32]);
+        // PoV hash.
+        encoded.extend_from_slice(&[0xe0; 32]);
+        // Erasure root.
+        encoded.extend_from_slice(&[0xf0; 32]);
+        // Signature.
+        encoded.extend_from_slice(&[0x51; 64]);
+        // Para head.
+        encoded.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], head_data).as_bytes());
+        // Validation code hash.
+        encoded.extend_from_slice(&[0xab; 32]);
+
+        // Commitments.
+        // One upward message.
+        encoded.extend_from_slice(&[4, 8, 0xaa, 0xbb]);
+        // One horizontal message.
+        encoded.extend_from_slice(&[4, 1, 0, 0, 0, 4, 0xcc]);
+        // No new validation code.
+        encoded.push(0);
+        encoded
+            .extend_from_slice(crate::util::encode_scale_compact_usize(head_data.len()).as_ref());
+        encoded.extend_from_slice(head_data);
+        // Processed downward messages.
+        encoded.extend_from_slice(&5u32.to_le_bytes());
+        // HRMP watermark.
+        encoded.extend_from_slice(&1234u32.to_le_bytes());
+
+        encoded
+    }
+
     #[test]
     fn basic_decode() {
         let encoded = [
@@ -151,19 +534,7 @@ mod tests {
         ];
 
         let expected = Some(super::PersistedValidationDataRef {
-            parent_head: &[
-                22, 207, 102, 211, 103, 223, 185, 141, 222, 102, 202, 83, 47, 68, 220, 233, 136,
-                252, 151, 156, 190, 127, 178, 126, 69, 0, 176, 226, 54, 174, 2, 100, 26, 176, 149,
-                0, 51, 178, 156, 151, 115, 34, 137, 183, 125, 44, 36, 155, 24, 27, 225, 97, 213,
-                118, 100, 6, 42, 26, 94, 247, 4, 39, 46, 159, 44, 211, 102, 99, 155, 215, 16, 175,
-                12, 245, 124, 60, 237, 51, 48, 68, 207, 77, 12, 7, 167, 106, 166, 110, 29, 77, 220,
-                206, 138, 24, 168, 136, 247, 153, 227, 237, 8, 6, 97, 117, 114, 97, 32, 43, 60, 58,
-                8, 0, 0, 0, 0, 5, 97, 117, 114, 97, 1, 1, 172, 118, 69, 16, 88, 154, 84, 2, 85,
-                206, 148, 69, 115, 132, 204, 88, 113, 101, 26, 4, 1, 7, 28, 130, 119, 6, 112, 220,
-                22, 242, 128, 29, 5, 151, 192, 57, 31, 159, 23, 159, 84, 155, 91, 207, 147, 191,
-                222, 231, 28, 75, 157, 111, 200, 192, 160, 235, 111, 59, 238, 81, 72, 169, 102,
-                136,
-            ],
+            parent_head: PARACHAIN_HEADER,
             relay_parent_number: 11484832,
             relay_parent_storage_root: &[
                 118, 200, 227, 89, 55, 136, 85, 177, 69, 145, 143, 221, 59, 214, 173, 221, 247,
@@ -177,4 +548,135 @@ mod tests {
             expected
         );
     }
+
+    #[test]
+    fn candidates_pending_availability_decode() {
+        // The head data of the second candidate is a suffix of the real header, in order to
+        // have two candidates with different head data lengths.
+        let head_datas = [PARACHAIN_HEADER, &PARACHAIN_HEADER[100..]];
+
+        let mut encoded = vec![8];
+        for (n, head_data) in head_datas.iter().enumerate() {
+            let n = u8::try_from(n).unwrap();
+            encoded.extend_from_slice(&encode_committed_candidate_receipt(&[n + 1; 32], head_data));
+        }
+
+        let decoded =
+            super::decode_candidates_pending_availability_return_value(&encoded, 4).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (n, candidate) in decoded.iter().enumerate() {
+            assert_eq!(candidate.para_id, 2000);
+            assert_eq!(*candidate.relay_parent, [u8::try_from(n).unwrap() + 1; 32]);
+            assert_eq!(candidate.head_data, head_datas[n]);
+            assert_eq!(
+                &candidate.para_head_hash[..],
+                blake2_rfc::blake2b::blake2b(32, &[], head_datas[n]).as_bytes()
+            );
+            assert_eq!(candidate.hrmp_watermark, 1234);
+        }
+
+        // The older function returns an `Option` of the same type.
+        let mut single = vec![1];
+        single.extend_from_slice(&encode_committed_candidate_receipt(&[1; 32], head_datas[0]));
+        assert_eq!(
+            super::decode_candidate_pending_availability_return_value(&single, 4).unwrap(),
+            Some(decoded[0])
+        );
+        assert_eq!(
+            super::decode_candidate_pending_availability_return_value(&[0], 4).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn paras_heads_storage() {
+        let key = super::paras_heads_storage_key(1000);
+        assert_eq!(
+            key[..32],
+            [
+                0xcd, 0x71, 0x0b, 0x30, 0xbd, 0x2e, 0xab, 0x03, 0x52, 0xdd, 0xcc, 0x26, 0x41, 0x7a,
+                0xa1, 0x94, 0x1b, 0x3c, 0x25, 0x2f, 0xcb, 0x29, 0xd8, 0x8e, 0xff, 0x4f, 0x3d, 0xe5,
+                0xde, 0x44, 0x76, 0xc3
+            ]
+        );
+        assert_eq!(key.len(), 32 + 8 + 4);
+        assert_eq!(key[40..], 1000u32.to_le_bytes());
+
+        assert_eq!(
+            super::decode_paras_heads_storage_value(&[12, 1, 2, 3]).unwrap(),
+            &[1, 2, 3]
+        );
+        assert!(super::decode_paras_heads_storage_value(&[12, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn pending_availability_storage_decode() {
+        // Value of the `ParaInclusion::V1` storage map, in other words a list of
+        // `CandidatePendingAvailability`. The fields follow the definition of the type in the
+        // Polkadot runtime, where the commitments are found right after the descriptor.
+        let head_datas = [PARACHAIN_HEADER, &PARACHAIN_HEADER[100..]];
+        let mut encoded = vec![8];
+        for (n, head_data) in head_datas.iter().enumerate() {
+            let n = u8::try_from(n).unwrap();
+            // Core index.
+            encoded.extend_from_slice(&3u32.to_le_bytes());
+            // Candidate hash.
+            encoded.extend_from_slice(&[0xff; 32]);
+            // Descriptor and commitments.
+            encoded.extend_from_slice(&encode_committed_candidate_receipt(&[n + 1; 32], head_data));
+            // Availability votes: 300 validators, all of them but the first having voted.
+            encoded.extend_from_slice(crate::util::encode_scale_compact_usize(300).as_ref());
+            encoded.push(0xfe);
+            encoded.extend_from_slice(&[0xff; 36]);
+            encoded.push(0x0f);
+            // Backers: 5 validators in the group, the first 3 having backed the candidate.
+            encoded.extend_from_slice(&[20, 0x07]);
+            // Relay parent number and backed-in number.
+            encoded.extend_from_slice(&23_000_000u32.to_le_bytes());
+            encoded.extend_from_slice(&23_000_002u32.to_le_bytes());
+            // Backing group.
+            encoded.extend_from_slice(&7u32.to_le_bytes());
+        }
+
+        let decoded = super::decode_pending_availability_storage_value(&encoded, 4).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (n, candidate) in decoded.iter().enumerate() {
+            assert_eq!(candidate.para_id, 2000);
+            assert_eq!(*candidate.relay_parent, [u8::try_from(n).unwrap() + 1; 32]);
+            assert_eq!(candidate.head_data, head_datas[n]);
+            assert_eq!(
+                &candidate.para_head_hash[..],
+                blake2_rfc::blake2b::blake2b(32, &[], head_datas[n]).as_bytes()
+            );
+            assert_eq!(candidate.hrmp_watermark, 1234);
+        }
+
+        assert!(super::decode_pending_availability_storage_value(&encoded[1..], 4).is_err());
+    }
+
+    #[test]
+    fn head_data_with_suffix() {
+        let header = crate::header::HeaderRef {
+            parent_hash: &[1; 32],
+            number: 12,
+            state_root: &[2; 32],
+            extrinsics_root: &[3; 32],
+            digest: crate::header::DigestRef::empty(),
+        }
+        .scale_encoding_vec(4);
+
+        let mut head_data = header.clone();
+        head_data.extend_from_slice(b"suffix");
+
+        assert_eq!(
+            super::decode_head_data(&header, super::HeadDataFormat::Header, 4).unwrap(),
+            &header[..]
+        );
+        assert!(super::decode_head_data(&head_data, super::HeadDataFormat::Header, 4).is_err());
+        assert_eq!(
+            super::decode_head_data(&head_data, super::HeadDataFormat::HeaderWithSuffix, 4)
+                .unwrap(),
+            &header[..]
+        );
+    }
 }
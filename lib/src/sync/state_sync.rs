@@ -0,0 +1,513 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Downloading the entire storage of a block.
+//!
+//! After a warp sync, only the storage items that are necessary to verify blocks are known.
+//! State syncing consists in downloading all the other storage items of a specific block by
+//! sending state requests (see [`crate::network::codec::StateRequest`]) to peers.
+//!
+//! Each response to a state request contains as many storage entries as possible, starting at a
+//! specific key, in the form of a compact Merkle proof. The [`StateSync`] state machine verifies
+//! these responses against the state trie root of the block, keeps track of the last key that
+//! has been downloaded, and indicates which request should be sent next.
+//!
+//! Child tries are downloaded as part of the main trie: when an entry of the main trie that
+//! points to a child trie is reached, the content of this child trie is downloaded before
+//! continuing with the rest of the main trie.
+//!
+//! # Usage
+//!
+//! Create a [`StateSync`] with [`StateSync::new`], then call [`StateSync::desired_request`] to
+//! obtain the request to send to a peer, and [`StateSync::inject_response`] once the response
+//! has been received. Repeat until [`StateSync::is_finished`] returns `true`.
+//!
+//! The [`StateSync`] can be serialized at any point using [`StateSync::encode_progress`] and
+//! restored using [`StateSync::decode_progress`], making it possible to resume a state sync
+//! after a restart.
+
+use crate::{
+    network::codec,
+    trie::{self, compact_proof, proof_decode},
+};
+
+use alloc::vec::Vec;
+use core::iter;
+
+/// Prefix of the keys of the main trie that point to a default child trie.
+const CHILD_TRIE_DEFAULT_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Prefix of the keys of the main trie whose value is the Merkle value of the root of a
+/// child trie.
+const CHILD_TRIE_PREFIX: &[u8] = b":child_storage:";
+
+/// Configuration for [`StateSync::new`].
+#[derive(Debug)]
+pub struct Config {
+    /// Hash of the block whose storage to download.
+    pub block_hash: [u8; 32],
+
+    /// State trie root of the block whose storage to download.
+    pub state_root: [u8; 32],
+}
+
+/// State machine that downloads the storage of a block.
+#[derive(Debug, Clone)]
+pub struct StateSync {
+    /// See [`Config::block_hash`].
+    block_hash: [u8; 32],
+
+    /// See [`Config::state_root`].
+    state_root: [u8; 32],
+
+    /// Last key of the main trie that has been downloaded. `None` if nothing has been downloaded
+    /// yet.
+    main_trie_cursor: Option<Vec<u8>>,
+
+    /// If `Some`, the child trie currently being downloaded.
+    child_trie: Option<ChildTrieCursor>,
+
+    /// `true` if the entire storage has been downloaded.
+    finished: bool,
+
+    /// Number of storage entries that have been downloaded so far.
+    num_entries_downloaded: u64,
+}
+
+#[derive(Debug, Clone)]
+struct ChildTrieCursor {
+    /// Key of the child trie, without the [`CHILD_TRIE_DEFAULT_PREFIX`].
+    child_trie: Vec<u8>,
+    /// Merkle value of the root node of the child trie.
+    root_merkle_value: [u8; 32],
+    /// Last key of the child trie that has been downloaded. `None` if nothing has been
+    /// downloaded yet.
+    cursor: Option<Vec<u8>>,
+}
+
+impl StateSync {
+    /// Initializes a new state machine that starts downloading from scratch.
+    pub fn new(config: Config) -> Self {
+        StateSync {
+            block_hash: config.block_hash,
+            state_root: config.state_root,
+            main_trie_cursor: None,
+            child_trie: None,
+            finished: false,
+            num_entries_downloaded: 0,
+        }
+    }
+
+    /// Restores a state machine from the output of [`StateSync::encode_progress`].
+    pub fn decode_progress(encoded: &[u8]) -> Result<Self, DecodeProgressError> {
+        let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+            nom::sequence::tuple((
+                nom::bytes::streaming::take(32u32),
+                nom::bytes::streaming::take(32u32),
+                nom::number::streaming::u8,
+                nom::number::streaming::le_u64,
+                crate::util::nom_option_decode(crate::util::nom_bytes_decode),
+                crate::util::nom_option_decode(nom::sequence::tuple((
+                    crate::util::nom_bytes_decode,
+                    nom::bytes::streaming::take(32u32),
+                    crate::util::nom_option_decode(crate::util::nom_bytes_decode),
+                ))),
+            )),
+            |(
+                block_hash,
+                state_root,
+                finished,
+                num_entries_downloaded,
+                main_trie_cursor,
+                child_trie,
+            ): (&[u8], &[u8], _, _, Option<&[u8]>, _)| {
+                StateSync {
+                    block_hash: <[u8; 32]>::try_from(block_hash).unwrap(),
+                    state_root: <[u8; 32]>::try_from(state_root).unwrap(),
+                    main_trie_cursor: main_trie_cursor.map(|k| k.to_vec()),
+                    child_trie: child_trie.map(
+                        |(child_trie, root, cursor): (&[u8], &[u8], Option<&[u8]>)| {
+                            ChildTrieCursor {
+                                child_trie: child_trie.to_vec(),
+                                root_merkle_value: <[u8; 32]>::try_from(root).unwrap(),
+                                cursor: cursor.map(|k| k.to_vec()),
+                            }
+                        },
+                    ),
+                    finished: finished != 0,
+                    num_entries_downloaded,
+                }
+            },
+        ))(encoded);
+
+        match result {
+            Ok((_, state_sync)) => Ok(state_sync),
+            Err(_) => Err(DecodeProgressError()),
+        }
+    }
+
+    /// Serializes the progress of the state machine, so that it can later be restored using
+    /// [`StateSync::decode_progress`].
+    pub fn encode_progress(&self) -> Vec<u8> {
+        fn encode_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+            out.extend_from_slice(crate::util::encode_scale_compact_usize(bytes.len()).as_ref());
+            out.extend_from_slice(bytes);
+        }
+
+        let mut out = Vec::with_capacity(128);
+        out.extend_from_slice(&self.block_hash);
+        out.extend_from_slice(&self.state_root);
+        out.push(u8::from(self.finished));
+        out.extend_from_slice(&self.num_entries_downloaded.to_le_bytes());
+        match &self.main_trie_cursor {
+            Some(key) => {
+                out.push(1);
+                encode_bytes(&mut out, key);
+            }
+            None => out.push(0),
+        }
+        match &self.child_trie {
+            Some(child_trie) => {
+                out.push(1);
+                encode_bytes(&mut out, &child_trie.child_trie);
+                out.extend_from_slice(&child_trie.root_merkle_value);
+                match &child_trie.cursor {
+                    Some(key) => {
+                        out.push(1);
+                        encode_bytes(&mut out, key);
+                    }
+                    None => out.push(0),
+                }
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Returns the value that was passed as [`Config::block_hash`].
+    pub fn block_hash(&self) -> &[u8; 32] {
+        &self.block_hash
+    }
+
+    /// Returns the value that was passed as [`Config::state_root`].
+    pub fn state_root(&self) -> &[u8; 32] {
+        &self.state_root
+    }
+
+    /// Returns `true` if the entire storage of the block has been downloaded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Returns the number of storage entries, including the entries of child tries, that have
+    /// been downloaded so far.
+    pub fn num_entries_downloaded(&self) -> u64 {
+        self.num_entries_downloaded
+    }
+
+    /// Returns the request that should be sent next. Returns `None` if the state sync is
+    /// finished.
+    ///
+    /// The same request is returned as long as no successful response has been injected with
+    /// [`StateSync::inject_response`].
+    pub fn desired_request(&self) -> Option<codec::StateRequest<'_>> {
+        if self.finished {
+            return None;
+        }
+
+        let start_key = match &self.child_trie {
+            Some(child_trie) => codec::StateRequestStart::ChildTrieDefault {
+                child_trie: &child_trie.child_trie,
+                key: child_trie.cursor.as_deref().unwrap_or(&[]),
+            },
+            None => {
+                codec::StateRequestStart::MainTrie(self.main_trie_cursor.as_deref().unwrap_or(&[]))
+            }
+        };
+
+        Some(codec::StateRequest {
+            block_hash: &self.block_hash,
+            start_key,
+        })
+    }
+
+    /// Injects a response to the request returned by [`StateSync::desired_request`].
+    ///
+    /// `compact_proof` must be the proof found in the response. See
+    /// [`crate::network::service::EncodedStateResponse::decode`].
+    ///
+    /// On success, returns the list of trie nodes that the response contains and that have been
+    /// verified against the state trie root. These nodes should be stored by the API user.
+    ///
+    /// In case of error, the state machine is left untouched and the same request should be
+    /// sent again, preferably to a different peer.
+    pub fn inject_response(&mut self, compact_proof: &[u8]) -> Result<Vec<TrieNode>, Error> {
+        let proof =
+            compact_proof::decode_to_proof(compact_proof).map_err(Error::InvalidCompactProof)?;
+        let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config { proof })
+            .map_err(Error::InvalidProof)?;
+
+        // Trie roots whose nodes can be trusted, as they are either the state trie root or
+        // child trie roots found in the state trie.
+        let mut trusted_roots = Vec::with_capacity(4);
+        trusted_roots.push(self.state_root);
+        if let Some(child_trie) = &self.child_trie {
+            trusted_roots.push(child_trie.root_merkle_value);
+        }
+
+        // Advance the cursors as much as the proof allows. Modifications are made on a copy, in
+        // order to leave `self` untouched in case of error.
+        let mut updated = self.clone();
+        loop {
+            if let Some(child_trie) = &mut updated.child_trie {
+                let next = decoded.next_key(
+                    &child_trie.root_merkle_value,
+                    trie::bytes_to_nibbles(
+                        child_trie.cursor.iter().flat_map(|k| k.iter().copied()),
+                    ),
+                    child_trie.cursor.is_none(),
+                    iter::empty(),
+                    false,
+                );
+
+                match next {
+                    Err(proof_decode::IncompleteProofError()) => break,
+                    Ok(None) => {
+                        // Child trie is finished. Continue with the main trie, after the key
+                        // of the child trie.
+                        updated.child_trie = None;
+                    }
+                    Ok(Some(key)) => {
+                        let key = nibbles_to_bytes(key)?;
+                        if decoded
+                            .storage_value(&child_trie.root_merkle_value, &key)
+                            .map_or(true, |v| v.is_none())
+                        {
+                            break;
+                        }
+                        child_trie.cursor = Some(key);
+                        updated.num_entries_downloaded += 1;
+                    }
+                }
+            } else {
+                let next = decoded.next_key(
+                    &updated.state_root,
+                    trie::bytes_to_nibbles(
+                        updated
+                            .main_trie_cursor
+                            .iter()
+                            .flat_map(|k| k.iter().copied()),
+                    ),
+                    updated.main_trie_cursor.is_none(),
+                    iter::empty(),
+                    false,
+                );
+
+                match next {
+                    Err(proof_decode::IncompleteProofError()) => break,
+                    Ok(None) => {
+                        updated.finished = true;
+                        break;
+                    }
+                    Ok(Some(key)) => {
+                        let key = nibbles_to_bytes(key)?;
+                        let value = match decoded.storage_value(&updated.state_root, &key) {
+                            Ok(Some((value, _))) => value,
+                            Ok(None) | Err(_) => break,
+                        };
+
+                        if let Some(child_trie) = key.strip_prefix(CHILD_TRIE_DEFAULT_PREFIX) {
+                            if let Ok(root_merkle_value) = <[u8; 32]>::try_from(value) {
+                                trusted_roots.push(root_merkle_value);
+                                updated.child_trie = Some(ChildTrieCursor {
+                                    child_trie: child_trie.to_vec(),
+                                    root_merkle_value,
+                                    cursor: None,
+                                });
+                            }
+                        }
+
+                        updated.main_trie_cursor = Some(key);
+                        updated.num_entries_downloaded += 1;
+                    }
+                }
+            }
+        }
+
+        if updated.finished == self.finished
+            && updated.main_trie_cursor == self.main_trie_cursor
+            && updated
+                .child_trie
+                .as_ref()
+                .map(|c| (&c.child_trie, &c.cursor))
+                == self.child_trie.as_ref().map(|c| (&c.child_trie, &c.cursor))
+        {
+            return Err(Error::NoProgress);
+        }
+
+        let mut trie_nodes = Vec::with_capacity(decoded.iter_ordered().size_hint().0);
+        for (key, entry) in decoded.iter_ordered() {
+            if !trusted_roots.iter().any(|r| r == key.trie_root_hash) {
+                continue;
+            }
+
+            let (storage_value, trie_entry_version) = match entry.trie_node_info.storage_value {
+                proof_decode::StorageValue::HashKnownValueMissing(_) => continue,
+                proof_decode::StorageValue::None => (None, trie::TrieEntryVersion::V0),
+                proof_decode::StorageValue::Known { value, inline } => (
+                    Some(value.to_vec()),
+                    if inline {
+                        trie::TrieEntryVersion::V0
+                    } else {
+                        trie::TrieEntryVersion::V1
+                    },
+                ),
+            };
+
+            let references_merkle_value = *key.trie_root_hash == self.state_root
+                && storage_value.is_some()
+                && key
+                    .key
+                    .clone()
+                    .take(CHILD_TRIE_PREFIX.len() * 2)
+                    .eq(trie::bytes_to_nibbles(CHILD_TRIE_PREFIX.iter().copied()));
+
+            trie_nodes.push(TrieNode {
+                merkle_value: entry.merkle_value.to_vec(),
+                partial_key_nibbles: entry.partial_key_nibbles.collect(),
+                children_merkle_values: core::array::from_fn(|n| {
+                    entry
+                        .trie_node_info
+                        .children
+                        .child(trie::Nibble::try_from(u8::try_from(n).unwrap()).unwrap())
+                        .merkle_value()
+                        .map(|mv| mv.to_vec())
+                }),
+                storage_value,
+                references_merkle_value,
+                trie_entry_version,
+            });
+        }
+
+        *self = updated;
+        Ok(trie_nodes)
+    }
+}
+
+/// Converts a key found in the proof to bytes.
+fn nibbles_to_bytes(key: impl Iterator<Item = trie::Nibble> + Clone) -> Result<Vec<u8>, Error> {
+    if key.clone().count() % 2 != 0 {
+        return Err(Error::UnevenKeyLength);
+    }
+    Ok(trie::nibbles_to_bytes_suffix_extend(key).collect())
+}
+
+/// Trie node verified by [`StateSync::inject_response`].
+#[derive(Debug, Clone)]
+pub struct TrieNode {
+    /// Merkle value of the node.
+    pub merkle_value: Vec<u8>,
+    /// Partial key of the node.
+    pub partial_key_nibbles: Vec<trie::Nibble>,
+    /// Merkle values of the children of the node.
+    pub children_merkle_values: [Option<Vec<u8>>; 16],
+    /// Storage value of the node, if any.
+    pub storage_value: Option<Vec<u8>>,
+    /// `true` if the storage value is the Merkle value of the root of a child trie.
+    pub references_merkle_value: bool,
+    /// Version of the trie entry. Irrelevant if the node doesn't have a storage value.
+    pub trie_entry_version: trie::TrieEntryVersion,
+}
+
+/// Error potentially returned by [`StateSync::inject_response`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum Error {
+    /// Failed to decode the compact proof.
+    #[display(fmt = "Failed to decode the compact proof: {_0}")]
+    InvalidCompactProof(compact_proof::Error),
+    /// Failed to verify the proof.
+    #[display(fmt = "Failed to verify the proof: {_0}")]
+    InvalidProof(proof_decode::Error),
+    /// The proof contains a storage entry whose key has an uneven number of nibbles.
+    UnevenKeyLength,
+    /// The response doesn't contain any storage entry that hasn't been downloaded yet.
+    NoProgress,
+}
+
+/// Error potentially returned by [`StateSync::decode_progress`].
+#[derive(Debug, derive_more::Display, Clone)]
+#[display(fmt = "Failed to decode state sync progress")]
+pub struct DecodeProgressError();
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::{self, trie_node};
+    use core::iter;
+
+    #[test]
+    fn single_response() {
+        let leaf = |partial_key: &'static [u8], value: &'static [u8]| {
+            trie_node::encode_to_vec(trie_node::Decoded {
+                partial_key: trie::bytes_to_nibbles(partial_key.iter().copied()).skip(1),
+                children: [None::<&[u8]>; 16],
+                storage_value: trie_node::StorageValue::Unhashed(value),
+            })
+            .unwrap()
+        };
+
+        // Trie containing the keys `0x10` and `0x20`, whose nodes are short enough to be
+        // inlined in the root node.
+        let leaf1 = leaf(&[0x10], b"foo");
+        let leaf2 = leaf(&[0x20], b"bar");
+        let root = trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: iter::empty(),
+            children: core::array::from_fn(|n| match n {
+                1 => Some(&leaf1[..]),
+                2 => Some(&leaf2[..]),
+                _ => None,
+            }),
+            storage_value: trie_node::StorageValue::None,
+        })
+        .unwrap();
+        let state_root: [u8; 32] = blake2_rfc::blake2b::blake2b(32, &[], &root)
+            .as_bytes()
+            .try_into()
+            .unwrap();
+
+        let mut compact_proof = vec![4];
+        compact_proof
+            .extend_from_slice(crate::util::encode_scale_compact_usize(root.len()).as_ref());
+        compact_proof.extend_from_slice(&root);
+
+        let mut state_sync = super::StateSync::new(super::Config {
+            block_hash: [1; 32],
+            state_root,
+        });
+        assert!(state_sync.desired_request().is_some());
+
+        let nodes = state_sync.inject_response(&compact_proof).unwrap();
+        assert!(state_sync.is_finished());
+        assert!(state_sync.desired_request().is_none());
+        assert_eq!(state_sync.num_entries_downloaded(), 2);
+        assert_eq!(nodes.len(), 3);
+
+        let restored = super::StateSync::decode_progress(&state_sync.encode_progress()).unwrap();
+        assert!(restored.is_finished());
+        assert_eq!(restored.num_entries_downloaded(), 2);
+        assert_eq!(restored.state_root(), &state_root);
+    }
+}
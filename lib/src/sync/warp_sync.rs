@@ -181,6 +181,11 @@ pub struct ConfigCodeTrieNodeHint {
 
     /// Closest ancestor of the `:code` key except for `:code` itself.
     pub closest_ancestor_excluding: Vec<Nibble>,
+
+    /// Runtime version of the runtime found in [`ConfigCodeTrieNodeHint::storage_value`], if
+    /// known. If the hint matches, it is used rather than calling `Core_version`. See
+    /// [`HostVmPrototype::new_with_runtime_version`].
+    pub runtime_version: Option<executor::CoreVersion>,
 }
 
 /// Initializes the warp sync state machine.
@@ -1869,11 +1874,12 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
     /// Build the runtime of the chain.
     ///
     /// Must be passed parameters used for the construction of the runtime: a hint as to whether
-    /// the runtime is trusted and/or will be executed again, and whether unresolved function
-    /// imports are allowed.
+    /// the runtime is trusted and/or will be executed again, whether fuel metering is enabled,
+    /// and whether unresolved function imports are allowed.
     pub fn build(
         mut self,
         exec_hint: ExecHint,
+        fuel_metering: bool,
         allow_unresolved_imports: bool,
     ) -> (WarpSync<TSrc, TRq>, Result<(), BuildRuntimeError>) {
         let RuntimeDownload::NotVerified {
@@ -1971,11 +1977,11 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
             }
         };
 
-        let finalized_storage_code = if let (false, Some(hint)) =
+        let (finalized_storage_code, known_runtime_version) = if let (false, Some(hint)) =
             (*hint_doesnt_match, self.inner.code_trie_node_hint.as_ref())
         {
             if hint.merkle_value == finalized_storage_code_merkle_value {
-                &hint.storage_value
+                (&hint.storage_value[..], hint.runtime_version.clone())
             } else {
                 self.inner.runtime_download = RuntimeDownload::NotStarted {
                     hint_doesnt_match: true,
@@ -1986,7 +1992,7 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
             match decoded_downloaded_runtime
                 .storage_value(&self.inner.warped_header_state_root, b":code")
             {
-                Ok(Some((code, _))) => code,
+                Ok(Some((code, _))) => (code, None),
                 Ok(None) => {
                     self.inner.warped_block_ty = WarpedBlockTy::KnownBad;
                     self.inner.runtime_download = RuntimeDownload::NotStarted {
@@ -2041,12 +2047,20 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
                 }
             };
 
-        let runtime = match HostVmPrototype::new(host::Config {
+        let runtime_config = host::Config {
             module: &finalized_storage_code,
             heap_pages: decoded_heap_pages,
             exec_hint,
+            fuel_metering,
             allow_unresolved_imports,
-        }) {
+        };
+        let runtime = match known_runtime_version {
+            Some(runtime_version) => {
+                HostVmPrototype::new_with_runtime_version(runtime_config, runtime_version)
+            }
+            None => HostVmPrototype::new(runtime_config),
+        };
+        let runtime = match runtime {
             Ok(runtime) => runtime,
             Err(err) => {
                 self.inner.warped_block_ty = WarpedBlockTy::KnownBad;
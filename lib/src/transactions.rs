@@ -25,7 +25,8 @@
 //! follows:
 //!
 //! - The transaction gets built, in other words the bytes that encode the transaction are
-//! generated. This can be done for example through a UI, through an off-chain worker, or other. A
+//! generated. This can be done for example through a UI, through an off-chain worker, through
+//! the [`build`] module, or other. A
 //! transaction can be either signed (i.e. have a signature attached to it) or unsigned, depending
 //! on the action to be performed. A balance transfer, for example, generally always requires a
 //! signature.
@@ -85,6 +86,7 @@
 //! certain block B, it will forever remain considered as invalid on any descendant of B, but a
 //! client also attempts to not cache that information for *too long* through heuristics.
 
+pub mod build;
 pub mod light_pool;
 pub mod pool;
 pub mod validate;
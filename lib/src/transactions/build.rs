@@ -0,0 +1,442 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline construction and signing of extrinsics.
+//!
+//! A signed extrinsic (in version 4 of the extrinsics format) consists of the address of the
+//! signer, a signature, the so-called *extra* data of each signed extension, and the call. The
+//! list of signed extensions of a chain is found in its metadata (see [`crate::metadata`]).
+//!
+//! The signature covers the call, the *extra* data of each signed extension, and the so-called
+//! *additional signed* data of each signed extension. The additional signed data isn't part of
+//! the extrinsic, but is known by the runtime, such as the genesis hash or the version of the
+//! runtime.
+//!
+//! # Usage
+//!
+//! - Call [`signing_payload`] with the metadata of the chain and a [`Config`]. The nonce of the
+//!   account can be obtained with the `system_accountNextIndex` JSON-RPC function.
+//! - Call [`SigningPayload::sign`] with a [`SigningKey`], which can be obtained from a seed
+//!   phrase thanks to the [`crate::identity::seed_phrase`] module. This returns the
+//!   SCALE-encoded extrinsic, ready to be submitted with for example `transaction_v1_broadcast`.
+//!
+//! Alternatively, the bytes returned by [`SigningPayload::bytes_to_sign`] can be signed
+//! externally, and the extrinsic built with [`SigningPayload::into_signed_extrinsic`].
+//!
+//! Signed extensions that aren't known by this module are supported only if they contain no data.
+
+use crate::{
+    metadata::decode::{MetadataRef, TypeDef},
+    util,
+};
+
+use alloc::{borrow::ToOwned as _, boxed::Box, string::String, vec::Vec};
+use rand_chacha::rand_core::SeedableRng as _;
+
+mod tests;
+
+/// Version of the extrinsics format that is generated.
+const EXTRINSIC_FORMAT_VERSION: u8 = 4;
+
+/// Signing payloads longer than this value are hashed before being signed.
+const MAX_UNHASHED_PAYLOAD_LEN: usize = 256;
+
+/// Configuration for building an extrinsic.
+#[derive(Debug, Clone)]
+pub struct Config<'a> {
+    /// SCALE-encoded call, in other words the index of the pallet, followed with the index of
+    /// the call within the pallet, followed with the arguments of the call.
+    pub call: &'a [u8],
+
+    /// Nonce of the signing account. Must be equal to the number of extrinsics that this account
+    /// has previously submitted.
+    pub nonce: u64,
+
+    /// Period during which the extrinsic is valid.
+    pub era: Era,
+
+    /// Hash of the block whose number is [`Era::birth_block_number`]. Ignored if the era is
+    /// [`Era::Immortal`], in which case the genesis hash is used instead.
+    pub mortality_checkpoint: [u8; 32],
+
+    /// Tip to give to the block author, in addition to the fees.
+    pub tip: u128,
+
+    /// SCALE-encoded identifier of the asset used to pay the fees, or `None` to pay with the
+    /// native token. Only relevant for chains that use the `ChargeAssetTxPayment` signed
+    /// extension.
+    pub asset_id: Option<&'a [u8]>,
+
+    /// Specification version of the runtime. See
+    /// [`crate::executor::CoreVersionRef::spec_version`].
+    pub spec_version: u32,
+
+    /// Transaction version of the runtime. See
+    /// [`crate::executor::CoreVersionRef::transaction_version`].
+    pub transaction_version: u32,
+
+    /// Hash of the genesis block of the chain.
+    pub genesis_hash: [u8; 32],
+
+    /// Hash of the metadata, for chains that use the `CheckMetadataHash` signed extension. If
+    /// `None`, the verification of the metadata hash is disabled.
+    pub metadata_hash: Option<[u8; 32]>,
+}
+
+/// Period during which an extrinsic is valid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Era {
+    /// Extrinsic is valid forever.
+    Immortal,
+    /// Extrinsic is valid only during a certain number of blocks.
+    Mortal {
+        /// Number of blocks during which the extrinsic is valid. Rounded up to a power of two
+        /// between 4 and 65536.
+        period: u64,
+        /// Number of the current best block.
+        current_block: u64,
+    },
+}
+
+impl Era {
+    /// Returns the number of the first block from which the extrinsic is valid, or `None` if
+    /// the era is [`Era::Immortal`].
+    ///
+    /// The hash of this block must be passed as [`Config::mortality_checkpoint`].
+    pub fn birth_block_number(&self) -> Option<u64> {
+        let (period, phase) = self.period_and_phase()?;
+        Some((self.current_block().max(phase) - phase) / period * period + phase)
+    }
+
+    /// Returns the SCALE encoding of the era.
+    pub fn scale_encoding(&self) -> Vec<u8> {
+        let Some((period, phase)) = self.period_and_phase() else {
+            return alloc::vec![0];
+        };
+
+        let quantize_factor = (period >> 12).max(1);
+        let encoded = (period.trailing_zeros() - 1).clamp(1, 15) as u16
+            | (((phase / quantize_factor) as u16) << 4);
+        encoded.to_le_bytes().to_vec()
+    }
+
+    fn current_block(&self) -> u64 {
+        match self {
+            Era::Immortal => 0,
+            Era::Mortal { current_block, .. } => *current_block,
+        }
+    }
+
+    /// Returns the period and the (quantized) phase of the era.
+    fn period_and_phase(&self) -> Option<(u64, u64)> {
+        let Era::Mortal {
+            period,
+            current_block,
+        } = *self
+        else {
+            return None;
+        };
+
+        let period = period.checked_next_power_of_two().unwrap_or(1 << 16);
+        let period = period.clamp(4, 1 << 16);
+        let quantize_factor = (period >> 12).max(1);
+        let phase = current_block % period / quantize_factor * quantize_factor;
+        Some((period, phase))
+    }
+}
+
+/// Builds the payload that must be signed in order to build an extrinsic.
+pub fn signing_payload(
+    metadata: &MetadataRef,
+    config: &Config,
+) -> Result<SigningPayload, BuildError> {
+    let mut extra = Vec::new();
+    let mut additional_signed = Vec::new();
+
+    for extension in &metadata.extrinsic.signed_extensions {
+        match extension.identifier {
+            "CheckNonZeroSender" | "CheckWeight" => {}
+            "CheckSpecVersion" => {
+                additional_signed.extend_from_slice(&config.spec_version.to_le_bytes());
+            }
+            "CheckTxVersion" => {
+                additional_signed.extend_from_slice(&config.transaction_version.to_le_bytes());
+            }
+            "CheckGenesis" => additional_signed.extend_from_slice(&config.genesis_hash),
+            "CheckMortality" | "CheckEra" => {
+                extra.extend_from_slice(&config.era.scale_encoding());
+                match config.era {
+                    Era::Immortal => additional_signed.extend_from_slice(&config.genesis_hash),
+                    Era::Mortal { .. } => {
+                        additional_signed.extend_from_slice(&config.mortality_checkpoint)
+                    }
+                }
+            }
+            "CheckNonce" => {
+                extra.extend_from_slice(util::encode_scale_compact_u64(config.nonce).as_ref());
+            }
+            "ChargeTransactionPayment" => {
+                extra.extend_from_slice(util::encode_scale_compact_u128(config.tip).as_ref());
+            }
+            "ChargeAssetTxPayment" => {
+                extra.extend_from_slice(util::encode_scale_compact_u128(config.tip).as_ref());
+                match config.asset_id {
+                    Some(asset_id) => {
+                        extra.push(1);
+                        extra.extend_from_slice(asset_id);
+                    }
+                    None => extra.push(0),
+                }
+            }
+            "CheckMetadataHash" => match config.metadata_hash {
+                Some(hash) => {
+                    extra.push(1);
+                    additional_signed.push(1);
+                    additional_signed.extend_from_slice(&hash);
+                }
+                None => {
+                    extra.push(0);
+                    additional_signed.push(0);
+                }
+            },
+            identifier => {
+                if !is_empty_type(metadata, extension.ty, 0)
+                    || !is_empty_type(metadata, extension.additional_signed_ty, 0)
+                {
+                    return Err(BuildError::UnsupportedSignedExtension(
+                        identifier.to_owned(),
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(SigningPayload {
+        call: config.call.to_vec(),
+        extra,
+        additional_signed,
+    })
+}
+
+/// Error potentially returned by [`signing_payload`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum BuildError {
+    /// The chain uses a signed extension that isn't supported and that contains data.
+    #[display(fmt = "Unsupported signed extension: {_0}")]
+    UnsupportedSignedExtension(String),
+}
+
+/// Payload to sign in order to build an extrinsic. See [`signing_payload`].
+#[derive(Debug, Clone)]
+pub struct SigningPayload {
+    call: Vec<u8>,
+    extra: Vec<u8>,
+    additional_signed: Vec<u8>,
+}
+
+impl SigningPayload {
+    /// Returns the bytes that must be signed.
+    ///
+    /// This consists in the call, the extra data and the additional signed data of the signed
+    /// extensions, hashed with blake2 if this is longer than 256 bytes.
+    pub fn bytes_to_sign(&self) -> Vec<u8> {
+        let mut payload =
+            Vec::with_capacity(self.call.len() + self.extra.len() + self.additional_signed.len());
+        payload.extend_from_slice(&self.call);
+        payload.extend_from_slice(&self.extra);
+        payload.extend_from_slice(&self.additional_signed);
+
+        if payload.len() > MAX_UNHASHED_PAYLOAD_LEN {
+            blake2_rfc::blake2b::blake2b(32, &[], &payload)
+                .as_bytes()
+                .to_vec()
+        } else {
+            payload
+        }
+    }
+
+    /// Signs the payload with the given key and returns the SCALE-encoded extrinsic, including
+    /// its length prefix.
+    ///
+    /// The address of the signer is assumed to be a `MultiAddress::Id` and the signature a
+    /// `MultiSignature`, which is the case for the vast majority of chains.
+    ///
+    /// `randomness_seed` is used only for Sr25519 signatures, which aren't deterministic.
+    pub fn sign(
+        self,
+        key: &SigningKey,
+        randomness_seed: [u8; 32],
+    ) -> Result<Vec<u8>, InvalidSigningKeyError> {
+        let signature = key.sign(&self.bytes_to_sign(), randomness_seed)?;
+
+        // `MultiAddress::Id`.
+        let mut address = Vec::with_capacity(33);
+        address.push(0);
+        address.extend_from_slice(&key.account_id()?);
+
+        Ok(self.into_signed_extrinsic(&address, &signature))
+    }
+
+    /// Builds the SCALE-encoded extrinsic, including its length prefix, from a SCALE-encoded
+    /// address and a SCALE-encoded signature of [`SigningPayload::bytes_to_sign`].
+    pub fn into_signed_extrinsic(self, address: &[u8], signature: &[u8]) -> Vec<u8> {
+        let len = 1 + address.len() + signature.len() + self.extra.len() + self.call.len();
+        let len_prefix = util::encode_scale_compact_usize(len);
+
+        let mut out = Vec::with_capacity(len_prefix.as_ref().len() + len);
+        out.extend_from_slice(len_prefix.as_ref());
+        out.push(0x80 | EXTRINSIC_FORMAT_VERSION);
+        out.extend_from_slice(address);
+        out.extend_from_slice(signature);
+        out.extend_from_slice(&self.extra);
+        out.extend_from_slice(&self.call);
+        out
+    }
+}
+
+/// Private key used to sign an extrinsic.
+///
+/// The keys can be obtained from a seed phrase thanks to the functions of the
+/// [`crate::identity::seed_phrase`] module.
+pub enum SigningKey {
+    /// Key returned by [`crate::identity::seed_phrase::decode_sr25519_private_key`].
+    Sr25519(Box<[u8; 64]>),
+    /// Key returned by [`crate::identity::seed_phrase::decode_ed25519_private_key`].
+    Ed25519(Box<[u8; 32]>),
+    /// Key returned by [`crate::identity::seed_phrase::decode_ecdsa_private_key`].
+    Ecdsa(Box<[u8; 32]>),
+}
+
+impl SigningKey {
+    /// Returns the account id corresponding to this key.
+    ///
+    /// For Sr25519 and Ed25519 keys, this is the public key. For ECDSA keys, this is the
+    /// blake2 hash of the compressed public key.
+    pub fn account_id(&self) -> Result<[u8; 32], InvalidSigningKeyError> {
+        Ok(match self {
+            SigningKey::Sr25519(key) => {
+                // `from_bytes` can only fail if the key is of the wrong length.
+                schnorrkel::SecretKey::from_bytes(&key[..])
+                    .unwrap()
+                    .to_public()
+                    .to_bytes()
+            }
+            SigningKey::Ed25519(key) => {
+                ed25519_zebra::VerificationKey::from(&ed25519_zebra::SigningKey::from(**key)).into()
+            }
+            SigningKey::Ecdsa(key) => {
+                let public_key = ecdsa_public_key(key)?;
+                <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &public_key).as_bytes())
+                    .unwrap()
+            }
+        })
+    }
+
+    /// Signs the given payload and returns the SCALE-encoded `MultiSignature`.
+    ///
+    /// `randomness_seed` is used only for Sr25519 signatures, which aren't deterministic.
+    pub fn sign(
+        &self,
+        payload: &[u8],
+        randomness_seed: [u8; 32],
+    ) -> Result<Vec<u8>, InvalidSigningKeyError> {
+        Ok(match self {
+            SigningKey::Ed25519(key) => {
+                let signature: [u8; 64] =
+                    ed25519_zebra::SigningKey::from(**key).sign(payload).into();
+                let mut out = Vec::with_capacity(65);
+                out.push(0);
+                out.extend_from_slice(&signature);
+                out
+            }
+            SigningKey::Sr25519(key) => {
+                // `from_bytes` can only fail if the key is of the wrong length.
+                let keypair = schnorrkel::SecretKey::from_bytes(&key[..])
+                    .unwrap()
+                    .to_keypair();
+                let signature = keypair.sign(schnorrkel::context::attach_rng(
+                    schnorrkel::signing_context(b"substrate").bytes(payload),
+                    rand_chacha::ChaCha20Rng::from_seed(randomness_seed),
+                ));
+                let mut out = Vec::with_capacity(65);
+                out.push(1);
+                out.extend_from_slice(&signature.to_bytes());
+                out
+            }
+            SigningKey::Ecdsa(key) => {
+                let secret_key = libsecp256k1::SecretKey::parse(key)
+                    .map_err(|_| InvalidSigningKeyError::InvalidEcdsaKey)?;
+                let message = libsecp256k1::Message::parse(
+                    &<[u8; 32]>::try_from(
+                        blake2_rfc::blake2b::blake2b(32, &[], payload).as_bytes(),
+                    )
+                    .unwrap(),
+                );
+                let (signature, recovery_id) = libsecp256k1::sign(&message, &secret_key);
+                let mut out = Vec::with_capacity(66);
+                out.push(2);
+                out.extend_from_slice(&signature.serialize());
+                out.push(recovery_id.serialize());
+                out
+            }
+        })
+    }
+}
+
+/// Error potentially returned by [`SigningKey::sign`] and [`SigningKey::account_id`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum InvalidSigningKeyError {
+    /// The ECDSA private key is zero or isn't inferior to the order of the curve.
+    InvalidEcdsaKey,
+}
+
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        match self {
+            SigningKey::Sr25519(key) => zeroize::Zeroize::zeroize(&mut **key),
+            SigningKey::Ed25519(key) | SigningKey::Ecdsa(key) => {
+                zeroize::Zeroize::zeroize(&mut **key)
+            }
+        }
+    }
+}
+
+/// Returns the compressed public key of the given ECDSA secret key.
+fn ecdsa_public_key(secret_key: &[u8; 32]) -> Result<[u8; 33], InvalidSigningKeyError> {
+    let secret_key = libsecp256k1::SecretKey::parse(secret_key)
+        .map_err(|_| InvalidSigningKeyError::InvalidEcdsaKey)?;
+    Ok(libsecp256k1::PublicKey::from_secret_key(&secret_key).serialize_compressed())
+}
+
+/// Returns `true` if the given type is always encoded as zero bytes.
+fn is_empty_type(metadata: &MetadataRef, ty: u32, depth: u32) -> bool {
+    // Protects against types that recursively contain themselves.
+    if depth >= 32 {
+        return false;
+    }
+
+    match metadata.type_by_id(ty).map(|ty| &ty.type_def) {
+        Some(TypeDef::Composite(fields)) => fields
+            .iter()
+            .all(|f| is_empty_type(metadata, f.ty, depth + 1)),
+        Some(TypeDef::Tuple(types)) => types
+            .iter()
+            .all(|ty| is_empty_type(metadata, *ty, depth + 1)),
+        Some(TypeDef::Array { len, ty }) => *len == 0 || is_empty_type(metadata, *ty, depth + 1),
+        _ => false,
+    }
+}
@@ -0,0 +1,287 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, Era, SigningKey};
+use crate::{
+    executor::{self, runtime_call},
+    identity::seed_phrase,
+    metadata::{self, Value},
+};
+use core::iter;
+
+/// Returns the metadata of the runtime found in the transaction validation test fixture.
+fn test_metadata() -> Vec<u8> {
+    #[derive(serde::Deserialize)]
+    struct Test {
+        #[serde(rename = "runtimeCode")]
+        runtime_code: String,
+    }
+
+    let test: Test = serde_json::from_str(include_str!("../validate/test-fixture.json")).unwrap();
+    let vm = executor::host::HostVmPrototype::new(executor::host::Config {
+        module: hex::decode(test.runtime_code).unwrap(),
+        heap_pages: executor::DEFAULT_HEAP_PAGES,
+        fuel_metering: false,
+        allow_unresolved_imports: true,
+        exec_hint: executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
+    })
+    .unwrap();
+
+    let mut call = metadata::metadata_at_version(vm, 14).unwrap();
+    loop {
+        match call {
+            runtime_call::RuntimeCall::Finished(Ok(success)) => {
+                let output = success.virtual_machine.value().as_ref().to_vec();
+                return metadata::decode_metadata_at_version_output(&output)
+                    .unwrap()
+                    .unwrap()
+                    .to_vec();
+            }
+            runtime_call::RuntimeCall::Finished(Err(err)) => panic!("{err:?}"),
+            runtime_call::RuntimeCall::StorageGet(get) => {
+                call = get.inject_value(None::<(iter::Empty<Vec<u8>>, _)>)
+            }
+            runtime_call::RuntimeCall::NextKey(nk) => call = nk.inject_key(None::<iter::Empty<_>>),
+            runtime_call::RuntimeCall::ClosestDescendantMerkleValue(mv) => {
+                call = mv.inject_merkle_value(None)
+            }
+            runtime_call::RuntimeCall::SignatureVerification(r) => call = r.verify_and_resume(),
+            runtime_call::RuntimeCall::LogEmit(r) => call = r.resume(),
+            runtime_call::RuntimeCall::OffchainStorageSet(r) => call = r.resume(),
+            runtime_call::RuntimeCall::Offchain(_)
+            | runtime_call::RuntimeCall::StorageProofNodeValue(_) => panic!(),
+        }
+    }
+}
+
+/// Builds a `System::remark` call with the given remark.
+fn remark_call(metadata: &metadata::MetadataRef, remark: &[u8]) -> Vec<u8> {
+    let system = metadata.pallet_by_name("System").unwrap();
+    let call_index = match &metadata
+        .type_by_id(system.calls_ty.unwrap())
+        .unwrap()
+        .type_def
+    {
+        metadata::decode::TypeDef::Variant(variants) => {
+            variants.iter().find(|v| v.name == "remark").unwrap().index
+        }
+        _ => panic!(),
+    };
+
+    let mut call = vec![system.index, call_index];
+    call.extend_from_slice(crate::util::encode_scale_compact_usize(remark.len()).as_ref());
+    call.extend_from_slice(remark);
+    call
+}
+
+fn config(call: &[u8]) -> Config {
+    Config {
+        call,
+        nonce: 5,
+        era: Era::Mortal {
+            period: 64,
+            current_block: 1000,
+        },
+        mortality_checkpoint: [1; 32],
+        tip: 1 << 70,
+        asset_id: None,
+        spec_version: 9160,
+        transaction_version: 9,
+        genesis_hash: [2; 32],
+        metadata_hash: None,
+    }
+}
+
+#[test]
+fn era_encoding() {
+    assert_eq!(Era::Immortal.scale_encoding(), [0]);
+    assert_eq!(Era::Immortal.birth_block_number(), None);
+
+    // Test vectors from Substrate.
+    let era = Era::Mortal {
+        period: 64,
+        current_block: 42,
+    };
+    assert_eq!(era.scale_encoding(), [0xa5, 0x02]);
+    assert_eq!(era.birth_block_number(), Some(42));
+
+    let era = Era::Mortal {
+        period: 32768,
+        current_block: 20000,
+    };
+    assert_eq!(era.scale_encoding(), [0x4e, 0x9c]);
+    assert_eq!(era.birth_block_number(), Some(20000));
+
+    // The phase is quantized for long periods.
+    let era = Era::Mortal {
+        period: 32768,
+        current_block: 20003,
+    };
+    assert_eq!(era.scale_encoding(), [0x4e, 0x9c]);
+    assert_eq!(era.birth_block_number(), Some(20000));
+
+    // The period is rounded up to a power of two.
+    let era = Era::Mortal {
+        period: 100,
+        current_block: 1000,
+    };
+    assert_eq!(era.birth_block_number(), Some(1000));
+    assert_eq!(era.scale_encoding()[0] & 0xf, 6);
+}
+
+#[test]
+fn build_and_decode() {
+    let metadata = test_metadata();
+    let metadata = metadata::decode(&metadata).unwrap();
+
+    for (key, signature_variant) in [
+        (
+            SigningKey::Sr25519(seed_phrase::decode_sr25519_private_key("//Alice").unwrap()),
+            "Sr25519",
+        ),
+        (
+            SigningKey::Ed25519(seed_phrase::decode_ed25519_private_key("//Alice").unwrap()),
+            "Ed25519",
+        ),
+        (
+            SigningKey::Ecdsa(seed_phrase::decode_ecdsa_private_key("//Alice").unwrap()),
+            "Ecdsa",
+        ),
+    ] {
+        // Both short and long (and thus hashed) payloads are tested.
+        for remark_len in [4, 300] {
+            let call = remark_call(&metadata, &vec![0xaa; remark_len]);
+            let payload = super::signing_payload(&metadata, &config(&call)).unwrap();
+            let to_sign = payload.bytes_to_sign();
+            if remark_len > 256 {
+                assert_eq!(to_sign.len(), 32);
+            } else {
+                assert!(to_sign.starts_with(&call));
+            }
+
+            let extrinsic = payload.sign(&key, [0; 32]).unwrap();
+            let decoded = metadata::decode_extrinsic(&metadata, &extrinsic).unwrap();
+            assert_eq!(decoded.pallet_name, "System");
+            assert_eq!(decoded.call_name, "remark");
+
+            let signature = decoded.signature.unwrap();
+            let Value::Variant { name, fields } = &signature.address else {
+                panic!()
+            };
+            assert_eq!(*name, "Id");
+            assert_eq!(
+                fields[0].1,
+                Value::Composite(vec![(None, Value::Bytes(&key.account_id().unwrap()))])
+            );
+
+            let Value::Variant { name, fields } = &signature.signature else {
+                panic!()
+            };
+            assert_eq!(*name, signature_variant);
+            let Value::Composite(fields) = &fields[0].1 else {
+                panic!()
+            };
+            let Value::Bytes(signature_bytes) = fields[0].1 else {
+                panic!()
+            };
+
+            match &key {
+                SigningKey::Sr25519(_) => {
+                    let public_key =
+                        schnorrkel::PublicKey::from_bytes(&key.account_id().unwrap()).unwrap();
+                    public_key
+                        .verify_simple(
+                            b"substrate",
+                            &to_sign,
+                            &schnorrkel::Signature::from_bytes(signature_bytes).unwrap(),
+                        )
+                        .unwrap();
+                }
+                SigningKey::Ed25519(_) => {
+                    ed25519_zebra::VerificationKey::try_from(key.account_id().unwrap())
+                        .unwrap()
+                        .verify(
+                            &ed25519_zebra::Signature::from(
+                                <[u8; 64]>::try_from(signature_bytes).unwrap(),
+                            ),
+                            &to_sign,
+                        )
+                        .unwrap();
+                }
+                SigningKey::Ecdsa(secret_key) => {
+                    let message = libsecp256k1::Message::parse(
+                        &<[u8; 32]>::try_from(
+                            blake2_rfc::blake2b::blake2b(32, &[], &to_sign).as_bytes(),
+                        )
+                        .unwrap(),
+                    );
+                    let recovered = libsecp256k1::recover(
+                        &message,
+                        &libsecp256k1::Signature::parse_standard_slice(&signature_bytes[..64])
+                            .unwrap(),
+                        &libsecp256k1::RecoveryId::parse(signature_bytes[64]).unwrap(),
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        recovered.serialize_compressed(),
+                        super::ecdsa_public_key(secret_key).unwrap()
+                    );
+                }
+            }
+
+            let extensions = signature.signed_extensions;
+            let extension = |name: &str| &extensions.iter().find(|(n, _)| *n == name).unwrap().1;
+            assert_eq!(
+                *extension("CheckNonce"),
+                Value::Composite(vec![(None, Value::Unsigned(5))])
+            );
+            assert_eq!(
+                *extension("ChargeTransactionPayment"),
+                Value::Composite(vec![(None, Value::Unsigned(1 << 70))])
+            );
+            assert!(matches!(
+                extension("CheckMortality"),
+                Value::Composite(fields) if matches!(fields[0].1, Value::Variant { .. })
+            ));
+        }
+    }
+}
+
+#[test]
+fn invalid_ecdsa_key() {
+    let metadata = test_metadata();
+    let metadata = metadata::decode(&metadata).unwrap();
+
+    // Zero and the order of the curve are both invalid secret keys.
+    for secret_key in [
+        [0; 32],
+        [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xfe, 0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c,
+            0xd0, 0x36, 0x41, 0x41,
+        ],
+    ] {
+        let key = SigningKey::Ecdsa(Box::new(secret_key));
+        assert!(key.account_id().is_err());
+
+        let call = remark_call(&metadata, &[0xaa; 4]);
+        let payload = super::signing_payload(&metadata, &config(&call)).unwrap();
+        assert!(payload.sign(&key, [0; 32]).is_err());
+    }
+}
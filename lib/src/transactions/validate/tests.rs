@@ -33,6 +33,7 @@ fn validate_from_proof() {
     let runtime = executor::host::HostVmPrototype::new(executor::host::Config {
         module: hex::decode(&test.runtime_code).unwrap(),
         heap_pages: executor::DEFAULT_HEAP_PAGES,
+        fuel_metering: false,
         allow_unresolved_imports: true,
         exec_hint: executor::vm::ExecHint::ExecuteOnceWithNonDeterministicValidation,
     })
@@ -59,7 +60,10 @@ fn validate_from_proof() {
             runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
         storage_main_trie_changes: Default::default(),
         max_log_level: 0,
+        fuel_limit: None,
         calculate_trie_changes: false,
+        record_storage_proof: false,
+        record_trace: false,
     })
     .unwrap();
 
@@ -97,7 +101,8 @@ fn validate_from_proof() {
             }
             runtime_call::RuntimeCall::LogEmit(r) => validation_in_progress = r.resume(),
             runtime_call::RuntimeCall::OffchainStorageSet(r) => validation_in_progress = r.resume(),
-            runtime_call::RuntimeCall::Offchain(_) => panic!(),
+            runtime_call::RuntimeCall::Offchain(_)
+            | runtime_call::RuntimeCall::StorageProofNodeValue(_) => panic!(),
         }
     }
 }
@@ -109,8 +109,10 @@ mod nibble;
 
 pub mod branch_search;
 pub mod calculate_root;
+pub mod compact_proof;
 pub mod prefix_proof;
 pub mod proof_decode;
+pub mod proof_decode_stream;
 pub mod proof_encode;
 pub mod trie_node;
 pub mod trie_structure;
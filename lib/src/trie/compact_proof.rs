@@ -0,0 +1,744 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact trie proofs.
+//!
+//! A compact proof is a variant of a trie proof (see the [`super::proof_decode`] module) where
+//! redundant information has been removed.
+//!
+//! # Details
+//!
+//! A compact proof consists in a list of node values ordered in a depth-first pre-order manner.
+//! In other words, a node is always immediately followed with the nodes of its first child,
+//! then the nodes of its second child, and so on.
+//!
+//! When a child of a node is also present in the proof, the Merkle value of this child is
+//! removed from the node value of its parent and replaced with an empty child. This Merkle value
+//! can then be recalculated by the decoder from the node value of the child.
+//!
+//! When a node uses a hashed storage value (which can only happen with version 1 of the trie),
+//! and that this storage value is present in the proof, the node value is prefixed with a byte
+//! whose value is `1` and the hash of the storage value is replaced with the storage value
+//! itself.
+//!
+//! A compact proof can contain multiple tries one behind the other. This is for example the case
+//! for proofs that contain both entries of the main trie and of child tries. When the root node
+//! of a trie is complete, the next node of the proof is the root node of the next trie.
+//!
+//! # Usage
+//!
+//! Use [`encode_proof`] to turn a regular proof into a compact proof, and [`decode_to_proof`]
+//! to perform the opposite conversion.
+//!
+//! Use [`decode_and_verify_proof`] in order to verify that a compact proof matches a list of
+//! trusted trie root hashes and examine its content.
+
+use super::{proof_decode, trie_node};
+
+use alloc::vec::Vec;
+
+/// Decodes a compact proof and turns it into a regular proof that can then be passed to
+/// [`super::proof_decode::decode_and_verify_proof`].
+///
+/// The input is the SCALE encoding of the list of node values. The output is similarly the
+/// SCALE encoding of the list of node values and storage values of the regular proof. Node
+/// values that are too short to be hashed are inlined within the node value of their parent and
+/// are thus not part of the output.
+///
+/// This function doesn't verify whether the proof matches a certain trie root hash. This is done
+/// when decoding the regular proof.
+pub fn decode_to_proof(compact_proof: &[u8]) -> Result<Vec<u8>, Error> {
+    decode(compact_proof).map(|(proof, _)| proof)
+}
+
+/// Decodes a compact proof, verifies that it contains exactly the tries whose root hashes are
+/// passed as parameter, and returns an object that allows examining its content.
+///
+/// The tries must be found in the compact proof in the same order as in `trie_root_hashes`.
+pub fn decode_and_verify_proof(
+    compact_proof: &[u8],
+    trie_root_hashes: &[[u8; 32]],
+) -> Result<proof_decode::DecodedTrieProof<Vec<u8>>, VerifyError> {
+    let (proof, trie_roots) = decode(compact_proof).map_err(VerifyError::Decode)?;
+
+    if trie_roots.len() != trie_root_hashes.len()
+        || trie_roots
+            .iter()
+            .zip(trie_root_hashes)
+            .any(|(obtained, expected)| obtained[..] != expected[..])
+    {
+        return Err(VerifyError::TrieRootMismatch);
+    }
+
+    proof_decode::decode_and_verify_proof(proof_decode::Config { proof })
+        .map_err(VerifyError::InvalidProof)
+}
+
+/// Encodes a regular proof into a compact proof.
+///
+/// The tries whose root hash is in `trie_root_hashes` are encoded one behind the other, in the
+/// order in which they are provided. The entries of the proof that don't belong to any of these
+/// tries are discarded.
+///
+/// Storage values that are hashed in their node value are inlined in the compact proof if the
+/// unhashed storage value is present in `proof`.
+pub fn encode_proof<T: AsRef<[u8]>>(
+    proof: &proof_decode::DecodedTrieProof<T>,
+    trie_root_hashes: &[[u8; 32]],
+) -> Result<Vec<u8>, EncodeError> {
+    let mut node_values = Vec::new();
+
+    for trie_root_hash in trie_root_hashes {
+        let mut trie_found = false;
+
+        for (key, entry) in proof.iter_ordered() {
+            if key.trie_root_hash != trie_root_hash {
+                continue;
+            }
+
+            // Nodes whose Merkle value isn't a hash are inlined within their parent, and are
+            // thus not part of the compact proof.
+            let is_root = !trie_found;
+            trie_found = true;
+            if !is_root && entry.merkle_value.len() < 32 {
+                continue;
+            }
+
+            let Ok(decoded) = trie_node::decode(entry.node_value) else {
+                // The proof has been successfully decoded, and thus all the node values are
+                // valid.
+                unreachable!()
+            };
+
+            // Hashed storage values are inlined in the compact proof, if known.
+            let unhashed_storage_value = match decoded.storage_value {
+                trie_node::StorageValue::Hashed(_) => entry.unhashed_storage_value,
+                _ => None,
+            };
+
+            let mut node_value = Vec::with_capacity(entry.node_value.len() + 1);
+            if unhashed_storage_value.is_some() {
+                node_value.push(ESCAPE_HEADER);
+            }
+
+            node_value.extend(
+                trie_node::encode_to_vec(trie_node::Decoded {
+                    partial_key: decoded.partial_key,
+                    children: core::array::from_fn::<_, 16, _>(|n| {
+                        match entry
+                            .trie_node_info
+                            .children
+                            .child(super::Nibble::try_from(u8::try_from(n).unwrap()).unwrap())
+                        {
+                            // Children that are present in the proof can be recalculated.
+                            proof_decode::Child::InProof { merkle_value, .. }
+                                if merkle_value.len() >= 32 =>
+                            {
+                                Some(&[][..])
+                            }
+                            child => child.merkle_value(),
+                        }
+                    }),
+                    storage_value: match unhashed_storage_value {
+                        Some(value) => trie_node::StorageValue::Unhashed(value),
+                        None => decoded.storage_value,
+                    },
+                })
+                // The node value was valid, and thus encoding can't fail.
+                .unwrap_or_else(|_| unreachable!()),
+            );
+
+            node_values.push(node_value);
+        }
+
+        if !trie_found {
+            return Err(EncodeError::TrieRootNotFound);
+        }
+    }
+
+    let mut out = crate::util::encode_scale_compact_usize(node_values.len())
+        .as_ref()
+        .to_vec();
+    for node_value in node_values {
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(node_value.len()).as_ref());
+        out.extend_from_slice(&node_value);
+    }
+    Ok(out)
+}
+
+/// Decodes a compact proof and returns the regular proof and the hashes of the roots of the
+/// tries found in the proof.
+fn decode(compact_proof: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>), Error> {
+    let node_values = decode_node_values(compact_proof)?;
+
+    let mut output = Output::default();
+    let mut trie_roots = Vec::new();
+    for trie_root in decode_tries(&node_values)? {
+        trie_roots.push(trie_root.merkle_value.clone());
+        output.push_node(trie_root.node_value, trie_root.merkle_value);
+        for entry in trie_root.sub_entries {
+            match entry {
+                DecodedEntry::Node {
+                    node_value,
+                    merkle_value,
+                } => output.push_node(node_value, merkle_value),
+                DecodedEntry::Value { value, hash } => output.push_node(value, hash),
+            }
+        }
+    }
+
+    Ok((output.finish(), trie_roots))
+}
+
+/// Error potentially returned by [`decode_to_proof`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum Error {
+    /// Failed to decode the list of node values.
+    InvalidFormat,
+    /// Failed to decode a node value found in the proof.
+    #[display(fmt = "Invalid node value: {_0}")]
+    InvalidNodeValue(trie_node::Error),
+    /// A node value is prefixed with the escape byte despite not containing a storage value.
+    EscapedNodeWithoutStorageValue,
+    /// The proof ends while some node values are still expected.
+    Truncated,
+}
+
+/// Error potentially returned by [`decode_and_verify_proof`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum VerifyError {
+    /// Failed to decode the compact proof.
+    #[display(fmt = "{_0}")]
+    Decode(Error),
+    /// The tries found in the compact proof don't match the expected trie root hashes.
+    TrieRootMismatch,
+    /// The regular proof obtained from the compact proof is invalid.
+    #[display(fmt = "{_0}")]
+    InvalidProof(proof_decode::Error),
+}
+
+/// Error potentially returned by [`encode_proof`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum EncodeError {
+    /// One of the trie root hashes couldn't be found in the proof.
+    TrieRootNotFound,
+}
+
+/// Decodes the SCALE-encoded list of node values.
+fn decode_node_values(compact_proof: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| nom::multi::many_m_n(num_elems, num_elems, crate::util::nom_bytes_decode),
+    ))(compact_proof);
+
+    match result {
+        Ok((_, node_values)) => Ok(node_values),
+        Err(_) => Err(Error::InvalidFormat),
+    }
+}
+
+/// Value of the first byte of a node value that indicates that the hashed storage value has
+/// been replaced with the storage value itself.
+///
+/// This value can't be confused with the first byte of a regular node value, as it doesn't
+/// correspond to any valid node header.
+const ESCAPE_HEADER: u8 = 0x01;
+
+/// Trie found in a compact proof.
+struct DecodedTrie {
+    /// Node value of the root node of the trie, after the compact proof modifications have been
+    /// reverted.
+    node_value: Vec<u8>,
+    /// Merkle value of the root node. Always a hash.
+    merkle_value: Vec<u8>,
+    /// Non-inline nodes and storage values of the trie, excluding the root node.
+    sub_entries: Vec<DecodedEntry>,
+}
+
+enum DecodedEntry {
+    Node {
+        node_value: Vec<u8>,
+        merkle_value: Vec<u8>,
+    },
+    Value {
+        value: Vec<u8>,
+        hash: Vec<u8>,
+    },
+}
+
+/// Entry in the stack of nodes that are being decoded.
+struct StackEntry<'a> {
+    decoded: trie_node::Decoded<'a, trie_node::DecodedPartialKey<'a>, &'a [u8]>,
+    /// Merkle values of the children that were omitted in the node value.
+    recovered_children: [Option<Vec<u8>>; 16],
+    /// Index of the next child to look at in order to find omitted children.
+    next_child: usize,
+    /// `true` if the node value was prefixed with [`ESCAPE_HEADER`].
+    escaped_value: bool,
+}
+
+/// Rebuilds the regular node values of all the nodes in the compact proof, grouped by trie.
+fn decode_tries(node_values: &[&[u8]]) -> Result<Vec<DecodedTrie>, Error> {
+    let mut tries = Vec::new();
+    let mut current_trie_entries = Vec::new();
+    let mut stack: Vec<StackEntry> = Vec::with_capacity(16);
+
+    for node_value in node_values {
+        let (node_value, escaped_value) = match node_value.split_first() {
+            Some((&ESCAPE_HEADER, rest)) => (rest, true),
+            _ => (*node_value, false),
+        };
+
+        let decoded = trie_node::decode(node_value).map_err(Error::InvalidNodeValue)?;
+        if escaped_value && !matches!(decoded.storage_value, trie_node::StorageValue::Unhashed(_)) {
+            return Err(Error::EscapedNodeWithoutStorageValue);
+        }
+
+        stack.push(StackEntry {
+            decoded,
+            recovered_children: Default::default(),
+            next_child: 0,
+            escaped_value,
+        });
+
+        // Now pop all the nodes at the top of the stack that don't have any omitted child
+        // left.
+        loop {
+            let top = stack.last_mut().unwrap();
+            if let Some(omitted_child) = (top.next_child..16).find(|n| {
+                top.decoded.children[*n].map_or(false, |c| c.is_empty())
+                    && top.recovered_children[*n].is_none()
+            }) {
+                // The next node in the proof is the omitted child.
+                top.next_child = omitted_child;
+                break;
+            }
+
+            let entry = stack.pop().unwrap();
+            let is_root = stack.is_empty();
+
+            let value_hash = if entry.escaped_value {
+                let trie_node::StorageValue::Unhashed(value) = entry.decoded.storage_value else {
+                    unreachable!()
+                };
+                Some((
+                    value,
+                    <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], value).as_bytes())
+                        .unwrap(),
+                ))
+            } else {
+                None
+            };
+
+            let children = {
+                let mut recovered = entry.recovered_children;
+                let mut out: [Option<Vec<u8>>; 16] = Default::default();
+                for (n, child) in entry.decoded.children.iter().enumerate() {
+                    out[n] = match (child, recovered[n].take()) {
+                        (Some(_), Some(recovered)) => Some(recovered),
+                        (Some(child), None) => Some(child.to_vec()),
+                        (None, _) => None,
+                    };
+                }
+                out
+            };
+
+            let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                partial_key: entry.decoded.partial_key,
+                children,
+                storage_value: match &value_hash {
+                    Some((_, hash)) => trie_node::StorageValue::Hashed(hash),
+                    None => entry.decoded.storage_value,
+                },
+            })
+            // Encoding can only fail if the node has no child and no storage value, in which
+            // case decoding would have failed as well.
+            .unwrap_or_else(|_| unreachable!());
+
+            if let Some((value, hash)) = value_hash {
+                current_trie_entries.push(DecodedEntry::Value {
+                    value: value.to_vec(),
+                    hash: hash.to_vec(),
+                });
+            }
+
+            let merkle_value = if is_root || node_value.len() >= 32 {
+                blake2_rfc::blake2b::blake2b(32, &[], &node_value)
+                    .as_bytes()
+                    .to_vec()
+            } else {
+                node_value.clone()
+            };
+
+            if is_root {
+                tries.push(DecodedTrie {
+                    node_value,
+                    merkle_value,
+                    sub_entries: core::mem::take(&mut current_trie_entries),
+                });
+                break;
+            }
+
+            if node_value.len() >= 32 {
+                current_trie_entries.push(DecodedEntry::Node {
+                    node_value,
+                    merkle_value: merkle_value.clone(),
+                });
+            }
+
+            let parent = stack.last_mut().unwrap();
+            parent.recovered_children[parent.next_child] = Some(merkle_value);
+            parent.next_child += 1;
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(Error::Truncated);
+    }
+
+    Ok(tries)
+}
+
+/// Builds the SCALE-encoded regular proof while removing duplicate entries.
+#[derive(Default)]
+struct Output {
+    entries: Vec<Vec<u8>>,
+    known_hashes: hashbrown::HashSet<Vec<u8>, fnv::FnvBuildHasher>,
+}
+
+impl Output {
+    fn push_node(&mut self, node_value: Vec<u8>, merkle_value: Vec<u8>) {
+        if self.known_hashes.insert(merkle_value) {
+            self.entries.push(node_value);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut out = crate::util::encode_scale_compact_usize(self.entries.len())
+            .as_ref()
+            .to_vec();
+        for entry in self.entries {
+            out.extend_from_slice(crate::util::encode_scale_compact_usize(entry.len()).as_ref());
+            out.extend_from_slice(&entry);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{nibble, proof_decode, proof_encode, trie_node, trie_structure};
+    use core::array;
+    use rand::distributions::{Distribution as _, Uniform};
+
+    /// Builds a random trie and returns a regular proof containing all of its nodes, plus its
+    /// root hash.
+    fn random_full_proof() -> (Vec<u8>, [u8; 32]) {
+        let mut trie = trie_structure::TrieStructure::new();
+        for _ in 0..Uniform::new_inclusive(1, 32).sample(&mut rand::thread_rng()) {
+            let mut key = Vec::new();
+            for _ in 0..Uniform::new_inclusive(0, 12).sample(&mut rand::thread_rng()) {
+                key.push(
+                    nibble::Nibble::try_from(
+                        Uniform::new_inclusive(0, 15).sample(&mut rand::thread_rng()),
+                    )
+                    .unwrap(),
+                );
+            }
+
+            match trie.node(key.into_iter()) {
+                trie_structure::Entry::Vacant(e) => {
+                    e.insert_storage_value().insert((), ());
+                }
+                trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(e)) => {
+                    e.insert_storage_value();
+                }
+                trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(_)) => {}
+            }
+        }
+
+        let mut proof_builder = proof_encode::ProofBuilder::new();
+        for node_index in trie.iter_unordered().collect::<Vec<_>>() {
+            let key = trie
+                .node_full_key_by_index(node_index)
+                .unwrap()
+                .collect::<Vec<_>>();
+
+            // Storage values of 33 bytes or more are hashed, as done in version 1 of the trie.
+            let mut storage_value = Vec::new();
+            for _ in 0..Uniform::new_inclusive(0, 64).sample(&mut rand::thread_rng()) {
+                storage_value.push(Uniform::new_inclusive(0, 255).sample(&mut rand::thread_rng()));
+            }
+            let storage_value_hash = blake2_rfc::blake2b::blake2b(32, &[], &storage_value);
+            let has_storage_value = trie.node_by_index(node_index).unwrap().has_storage_value();
+            let is_hashed = has_storage_value && storage_value.len() >= 33;
+
+            let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                children: array::from_fn(|nibble| {
+                    let nibble = nibble::Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+                    if trie
+                        .node_by_index(node_index)
+                        .unwrap()
+                        .child_user_data(nibble)
+                        .is_some()
+                    {
+                        Some(&[][..])
+                    } else {
+                        None
+                    }
+                }),
+                partial_key: trie
+                    .node_by_index(node_index)
+                    .unwrap()
+                    .partial_key()
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+                storage_value: if is_hashed {
+                    trie_node::StorageValue::Hashed(
+                        <&[u8; 32]>::try_from(storage_value_hash.as_bytes()).unwrap(),
+                    )
+                } else if has_storage_value {
+                    trie_node::StorageValue::Unhashed(&storage_value)
+                } else {
+                    trie_node::StorageValue::None
+                },
+            })
+            .unwrap();
+
+            proof_builder.set_node_value(
+                &key,
+                &node_value,
+                if is_hashed {
+                    Some(&storage_value[..])
+                } else {
+                    None
+                },
+            );
+        }
+
+        assert!(proof_builder.missing_node_values().next().is_none());
+        proof_builder.make_coherent();
+        let trie_root_hash = proof_builder.trie_root_hash().unwrap();
+        (proof_builder.build_to_vec(), trie_root_hash)
+    }
+
+    /// Returns a regular proof containing a random subset of the nodes of the given proof. The
+    /// ancestors of all the nodes in the subset are included as well. Hashed storage values are
+    /// randomly included or not.
+    fn random_sub_proof(full_proof: &[u8], trie_root_hash: &[u8; 32]) -> Vec<u8> {
+        let decoded =
+            proof_decode::decode_and_verify_proof(proof_decode::Config { proof: full_proof })
+                .unwrap();
+
+        let mut included = hashbrown::HashSet::<Vec<nibble::Nibble>>::new();
+        let mut proof_builder = proof_encode::ProofBuilder::new();
+
+        for (key, entry) in decoded.iter_ordered() {
+            // Storage values found in the proof might accidentally be valid trie nodes.
+            if key.trie_root_hash != trie_root_hash {
+                continue;
+            }
+
+            let key = key.key.collect::<Vec<_>>();
+            let partial_key_len = entry.partial_key_nibbles.clone().count();
+
+            let include = if key.len() == partial_key_len {
+                true
+            } else {
+                let parent_key = &key[..key.len() - partial_key_len - 1];
+                // Inline nodes must always be included alongside with their parent.
+                included.contains(parent_key)
+                    && (entry.merkle_value.len() < 32
+                        || Uniform::new_inclusive(0, 3).sample(&mut rand::thread_rng()) != 0)
+            };
+
+            if include {
+                proof_builder.set_node_value(
+                    &key,
+                    entry.node_value,
+                    entry.unhashed_storage_value.filter(|_| {
+                        Uniform::new_inclusive(0, 1).sample(&mut rand::thread_rng()) == 0
+                    }),
+                );
+                included.insert(key);
+            }
+        }
+
+        proof_builder.build_to_vec()
+    }
+
+    /// Returns the list of entries of the proof in a format that can be compared.
+    fn proof_entries(
+        proof: &proof_decode::DecodedTrieProof<Vec<u8>>,
+        trie_root_hash: &[u8; 32],
+    ) -> Vec<(Vec<nibble::Nibble>, Vec<u8>, Vec<u8>, Option<Vec<u8>>)> {
+        proof
+            .iter_ordered()
+            .filter(|(key, _)| key.trie_root_hash == trie_root_hash)
+            .map(|(key, entry)| {
+                (
+                    key.key.collect(),
+                    entry.merkle_value.to_vec(),
+                    entry.node_value.to_vec(),
+                    entry.unhashed_storage_value.map(|v| v.to_vec()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_proof() {
+        assert_eq!(super::decode_to_proof(&[0]).unwrap(), &[0]);
+        assert!(super::decode_and_verify_proof(&[0], &[]).is_ok());
+        assert!(matches!(
+            super::decode_and_verify_proof(&[0], &[[0; 32]]),
+            Err(super::VerifyError::TrieRootMismatch)
+        ));
+    }
+
+    #[test]
+    fn encode_decode_random() {
+        for _ in 0..500 {
+            let (full_proof, trie_root_hash) = random_full_proof();
+            let proof = random_sub_proof(&full_proof, &trie_root_hash);
+            let proof =
+                proof_decode::decode_and_verify_proof(proof_decode::Config { proof }).unwrap();
+
+            let compact_proof = super::encode_proof(&proof, &[trie_root_hash]).unwrap();
+
+            // Converting the compact proof back to a regular proof must give back the same
+            // content.
+            let regular = super::decode_to_proof(&compact_proof).unwrap();
+            let regular =
+                proof_decode::decode_and_verify_proof(proof_decode::Config { proof: regular })
+                    .unwrap();
+            assert_eq!(
+                proof_entries(&regular, &trie_root_hash),
+                proof_entries(&proof, &trie_root_hash)
+            );
+
+            // Same through direct verification.
+            let verified =
+                super::decode_and_verify_proof(&compact_proof, &[trie_root_hash]).unwrap();
+            assert_eq!(
+                proof_entries(&verified, &trie_root_hash),
+                proof_entries(&proof, &trie_root_hash)
+            );
+
+            // Verification against the wrong trie root must fail.
+            let mut wrong_root = trie_root_hash;
+            wrong_root[0] ^= 0xff;
+            assert!(matches!(
+                super::decode_and_verify_proof(&compact_proof, &[wrong_root]),
+                Err(super::VerifyError::TrieRootMismatch)
+            ));
+            assert!(matches!(
+                super::encode_proof(&proof, &[wrong_root]),
+                Err(super::EncodeError::TrieRootNotFound)
+            ));
+        }
+    }
+
+    #[test]
+    fn multiple_tries() {
+        for _ in 0..100 {
+            let (proof1, trie_root_hash1) = random_full_proof();
+            let (proof2, trie_root_hash2) = random_full_proof();
+            let proof1 =
+                proof_decode::decode_and_verify_proof(proof_decode::Config { proof: proof1 })
+                    .unwrap();
+            let proof2 =
+                proof_decode::decode_and_verify_proof(proof_decode::Config { proof: proof2 })
+                    .unwrap();
+
+            let compact1 = super::encode_proof(&proof1, &[trie_root_hash1]).unwrap();
+            let compact2 = super::encode_proof(&proof2, &[trie_root_hash2]).unwrap();
+
+            // Concatenate the two compact proofs.
+            let mut compact = crate::util::encode_scale_compact_usize(
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&compact1)
+                    .unwrap()
+                    .1
+                    + crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&compact2)
+                        .unwrap()
+                        .1,
+            )
+            .as_ref()
+            .to_vec();
+            compact.extend_from_slice(
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&compact1)
+                    .unwrap()
+                    .0,
+            );
+            compact.extend_from_slice(
+                crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&compact2)
+                    .unwrap()
+                    .0,
+            );
+
+            let verified =
+                super::decode_and_verify_proof(&compact, &[trie_root_hash1, trie_root_hash2])
+                    .unwrap();
+            assert_eq!(
+                proof_entries(&verified, &trie_root_hash1),
+                proof_entries(&proof1, &trie_root_hash1)
+            );
+            assert_eq!(
+                proof_entries(&verified, &trie_root_hash2),
+                proof_entries(&proof2, &trie_root_hash2)
+            );
+
+            assert!(
+                super::decode_and_verify_proof(&compact, &[trie_root_hash2, trie_root_hash1])
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn fuzz_random_data() {
+        for _ in 0..5000 {
+            let mut data = Vec::new();
+            for _ in 0..Uniform::new_inclusive(0, 256).sample(&mut rand::thread_rng()) {
+                data.push(Uniform::new_inclusive(0, 255).sample(&mut rand::thread_rng()));
+            }
+
+            // Must not panic.
+            if let Ok(proof) = super::decode_to_proof(&data) {
+                let _ = proof_decode::decode_and_verify_proof(proof_decode::Config { proof });
+            }
+            let _ = super::decode_and_verify_proof(&data, &[[0; 32]]);
+        }
+    }
+
+    #[test]
+    fn fuzz_mutated_proof() {
+        for _ in 0..500 {
+            let (proof, trie_root_hash) = random_full_proof();
+            let proof =
+                proof_decode::decode_and_verify_proof(proof_decode::Config { proof }).unwrap();
+            let mut compact_proof = super::encode_proof(&proof, &[trie_root_hash]).unwrap();
+
+            let index = Uniform::new(0, compact_proof.len()).sample(&mut rand::thread_rng());
+            compact_proof[index] = Uniform::new_inclusive(0, 255).sample(&mut rand::thread_rng());
+
+            // Must not panic.
+            let _ = super::decode_to_proof(&compact_proof);
+            let _ = super::decode_and_verify_proof(&compact_proof, &[trie_root_hash]);
+        }
+    }
+}
@@ -120,11 +120,23 @@ impl PrefixScan {
         self.resume_inner(true, proof)
     }
 
+    /// Identical to [`PrefixScan::resume_partial`], but accepts a proof that has already been
+    /// decoded and verified.
+    ///
+    /// This makes it possible to inject the same proof into multiple scans without decoding it
+    /// multiple times.
+    pub fn resume_partial_decoded<T: AsRef<[u8]>>(
+        self,
+        decoded_proof: &proof_decode::DecodedTrieProof<T>,
+    ) -> Result<ResumeOutcome, (Self, Error)> {
+        self.resume_decoded(true, decoded_proof)
+    }
+
     /// Injects the proof presumably containing the keys returned by [`PrefixScan::requested_keys`].
     ///
     /// Returns an error if the proof is invalid. In that case, `self` isn't modified.
     fn resume_inner(
-        mut self,
+        self,
         allow_incomplete_proof: bool,
         proof: &[u8],
     ) -> Result<ResumeOutcome, (Self, Error)> {
@@ -134,6 +146,16 @@ impl PrefixScan {
                 Err(err) => return Err((self, Error::InvalidProof(err))),
             };
 
+        self.resume_decoded(allow_incomplete_proof, &decoded_proof)
+    }
+
+    /// Injects the decoded proof presumably containing the keys returned by
+    /// [`PrefixScan::requested_keys`].
+    fn resume_decoded<T: AsRef<[u8]>>(
+        mut self,
+        allow_incomplete_proof: bool,
+        decoded_proof: &proof_decode::DecodedTrieProof<T>,
+    ) -> Result<ResumeOutcome, (Self, Error)> {
         // The code below contains an infinite loop.
         // At each iteration, we update the content of `non_terminal_queries` (by extracting its
         // value then putting a new value back before the next iteration).
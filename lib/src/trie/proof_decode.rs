@@ -41,7 +41,7 @@
 use super::{nibble, trie_node, TrieEntryVersion};
 
 use alloc::vec::Vec;
-use core::{fmt, iter, ops};
+use core::{cmp, fmt, iter, ops};
 
 /// Configuration to pass to [`decode_and_verify_proof`].
 pub struct Config<I> {
@@ -1007,253 +1007,116 @@ impl<T: AsRef<[u8]>> DecodedTrieProof<T> {
         &'_ self,
         trie_root_merkle_value: &[u8; 32],
         key_before: impl Iterator<Item = nibble::Nibble>,
-        mut or_equal: bool,
+        or_equal: bool,
         prefix: impl Iterator<Item = nibble::Nibble>,
         branch_nodes: bool,
     ) -> Result<Option<EntryKeyIter<'_, T>>, IncompleteProofError> {
-        let proof = self.proof.as_ref();
-
-        // The implementation below might continue iterating `prefix` even after it has returned
-        // `None`, thus we have to fuse it.
-        let mut prefix = prefix.fuse();
-
-        // The implementation below might continue iterating `key_before` even after it has
-        // returned `None`, thus we have to fuse it.
-        // Furthermore, `key_before` might be modified in the implementation below. When that
-        // happens, de do this by setting it to `either::Right`.
-        let mut key_before = either::Left(key_before.fuse());
+        let key_before = key_before.collect::<Vec<_>>();
+        let prefix = prefix.collect::<Vec<_>>();
 
         // Find the starting point of the requested trie.
-        let Some(&(mut iter_entry)) = self.trie_roots.get(trie_root_merkle_value) else {
+        let Some(&root_entry) = self.trie_roots.get(trie_root_merkle_value) else {
             return Err(IncompleteProofError());
         };
 
-        // If `true`, then it was determined that `key_before` was after the last child of
-        // `iter_entry`. The next iteration must find `iter_entry`'s next sibling.
-        let mut iterating_up: bool = false;
-
-        // Indicates the depth of ancestors of `iter_entry` that match `prefix`.
-        // For example, if the value is 2, then `iter_entry`'s parent and grandparent match
-        //`prefix`, but `iter_entry`'s grandparent parent does not.
-        let mut prefix_match_iter_entry_ancestor_depth = 0;
-
-        loop {
-            let Ok(iter_entry_decoded) =
-                trie_node::decode(&proof[self.entries[iter_entry].range_in_proof.clone()])
-            else {
-                // Proof has been checked to be entirely decodable.
-                unreachable!()
-            };
-
-            if iterating_up {
-                debug_assert!(matches!(key_before, either::Right(_)));
-                debug_assert!(or_equal);
+        // Returns `true` if no descendant of `key` (including `key` itself) can match the
+        // criteria, either because it doesn't match `prefix` or because it is strictly inferior
+        // to `key_before`.
+        let subtree_excluded = |key: &[nibble::Nibble]| {
+            let prefix_len = cmp::min(key.len(), prefix.len());
+            let key_before_len = cmp::min(key.len(), key_before.len());
+            key[..prefix_len] != prefix[..prefix_len]
+                || key[..key_before_len] < key_before[..key_before_len]
+        };
 
-                // It was determined that `key_before` was after the last child of `iter_entry`.
-                // The next iteration must find `iter_entry`'s next sibling.
+        // Since the entries of the proof are in lexicographic order, we perform a depth-first
+        // search that stops at the first node that matches the criteria. Subtrees that can't
+        // contain any matching node are skipped.
+        struct StackEntry {
+            /// Length of the key of the entry, including its partial key.
+            key_len: usize,
+            /// Bitmap of the children of the entry.
+            children_bitmap: u16,
+            /// Bitmap of the children of the entry that are present in the proof.
+            children_in_proof_bitmap: u16,
+            /// Next child nibble to look at.
+            next_child: u8,
+            /// Index within [`DecodedTrieProof::entries`] of the next child that is present in
+            /// the proof.
+            next_child_entry_index: usize,
+        }
 
-                if prefix_match_iter_entry_ancestor_depth == 0 {
-                    // `prefix` only matches `iter_entry` and not its parent and
-                    // thus wouldn't match `iter_entry`'s sibling or uncle.
-                    // Therefore, the next node is `None`.
-                    return Ok(None);
-                }
+        let mut key = Vec::<nibble::Nibble>::with_capacity(64);
+        let mut stack = Vec::<StackEntry>::with_capacity(16);
+        let mut entry_to_visit = Some(root_entry);
 
-                // Go to `iter_entry`'s next sibling, if any.
-                let Some((parent_entry, parent_to_child_nibble)) =
-                    self.entries[iter_entry].parent_entry_index
-                else {
-                    // `iter_entry` is the root of the trie. `key_before` is therefore
-                    // after the last entry of the trie.
-                    return Ok(None);
-                };
-
-                let Some(child_num) = iter_entry_decoded
-                    .children
-                    .iter()
-                    .skip(usize::from(parent_to_child_nibble) + 1)
-                    .position(|c| c.is_some())
-                    .map(|n| n + usize::from(parent_to_child_nibble) + 1)
-                else {
-                    // No child found. Continue iterating up.
-                    iterating_up = true;
-                    iter_entry = parent_entry;
-                    prefix_match_iter_entry_ancestor_depth -= 1;
-                    continue;
+        loop {
+            if let Some(entry_index) = entry_to_visit.take() {
+                let Ok(entry_decoded) = trie_node::decode(
+                    &self.proof.as_ref()[self.entries[entry_index].range_in_proof.clone()],
+                ) else {
+                    // Proof has been checked to be entirely decodable.
+                    unreachable!()
                 };
 
-                // Found a next sibling.
-
-                let children_present_in_proof_bitmap =
-                    self.entries[parent_entry].children_present_in_proof_bitmap;
-
-                // If the child isn't present in the proof, then the proof is
-                // incomplete. While in some situations we could prove that the child
-                // is necessarily the next key, there is no way to know its full key.
-                if children_present_in_proof_bitmap & (1 << child_num) == 0 {
-                    return Err(IncompleteProofError());
-                }
+                key.extend(entry_decoded.partial_key.clone());
 
-                // `iter_entry` is still pointing to the child at index `parent_to_child_nibble`.
-                // Since we're jumping to its next sibling, all we have to do is skip over it
-                // and its descedants.
-                iter_entry += 1;
-                iter_entry += self.entries[iter_entry].child_entries_follow_up;
-                iterating_up = false;
-            }
-
-            let mut iter_entry_partial_key_iter = iter_entry_decoded.partial_key;
-            loop {
-                match (
-                    key_before.next(),
-                    prefix.next(),
-                    iter_entry_partial_key_iter.next(),
-                ) {
-                    (Some(k), Some(p), Some(pk)) if k == p && p == pk => {
-                        // Continue descending down the tree.
-                    }
-                    (None, Some(p), Some(pk)) if p == pk => {
-                        // Continue descending down the tree.
-                    }
-                    (Some(k), None, Some(pk)) if k == pk => {
-                        // Continue descending down the tree.
-                    }
-                    (None, None, Some(_)) => {
-                        // Exact match. Due to the `branch_nodes` setting, we can't just
-                        // return `iter_entry` and instead continue iterating.
-                        or_equal = true;
-                    }
-                    (Some(k), None, Some(pk)) if k < pk => {
-                        // `key_before` points to somewhere between `iter_entry`'s parent
-                        // and `iter_entry`. Due to the `branch_nodes` setting, we can't just
-                        // return `iter_entry` and instead continue iterating.
-                        key_before = either::Right(iter::empty().fuse());
-                        or_equal = true;
-                    }
-                    (Some(k), Some(p), _) if k > p => {
-                        // `key_before` is strictly superior to `prefix`. The next key is
-                        // thus necessarily `None`.
-                        // Note that this is not a situation that is expected to be common, as
-                        // doing such a call is pointless.
-                        return Ok(None);
-                    }
-                    (Some(k), Some(p), Some(pk)) if k < p && p == pk => {
-                        // The key and prefix diverge.
-                        // We know that there isn't any key in the trie between `key_before`
-                        // and `prefix`.
-                        // Starting next iteration, the next node matching the prefix
-                        // will be the result.
-                        key_before = either::Right(iter::empty().fuse());
-                        or_equal = true;
-                    }
-                    (_, Some(p), Some(pk)) => {
-                        debug_assert!(p != pk); // Other situations covered by other match blocks.
-
-                        // Mismatch between prefix and partial key. No matter the value of
-                        // `key_before` There is no node in the trie that starts with `prefix`.
-                        return Ok(None);
-                    }
-                    (Some(k), None, Some(pk)) if k < pk => {
-                        // `key_before` points to somewhere between `iter_entry`'s parent
-                        // and `iter_entry`. We know that `iter_entry` necessarily matches
-                        // the prefix. The next key is necessarily `iter_entry`.
-                        return Ok(Some(EntryKeyIter::new(self, iter_entry)));
-                    }
-                    (Some(k), None, Some(pk)) => {
-                        debug_assert!(k > pk); // Checked above.
-
-                        // `key_before` points to somewhere between the last child of `iter_entry`
-                        // and its next sibling.
-                        // The next key is thus the next sibling of `iter_entry`.
-                        iterating_up = true;
-                        key_before = either::Right(iter::empty().fuse());
-                        or_equal = true;
-                        break;
-                    }
-                    (None, None, None)
-                        if or_equal
-                            && (branch_nodes
-                                || !matches!(
-                                    iter_entry_decoded.storage_value,
-                                    trie_node::StorageValue::None,
-                                )) =>
+                if !subtree_excluded(&key) {
+                    // Check whether the entry itself matches.
+                    if key.starts_with(&prefix)
+                        && match key.cmp(&key_before) {
+                            cmp::Ordering::Greater => true,
+                            cmp::Ordering::Equal => or_equal,
+                            cmp::Ordering::Less => false,
+                        }
+                        && (branch_nodes
+                            || !matches!(
+                                entry_decoded.storage_value,
+                                trie_node::StorageValue::None
+                            ))
                     {
-                        // Exact match. Next key is `iter_entry`.
-                        return Ok(Some(EntryKeyIter::new(self, iter_entry)));
+                        return Ok(Some(EntryKeyIter::new(self, entry_index)));
                     }
-                    (key_before_nibble, prefix_nibble, None) => {
-                        // The moment when we have finished traversing a node and go to the
-                        // next one is the most complicated situation.
-
-                        // We know for sure that `iter_entry` can't be returned, as it is covered
-                        // by the other match variants above. Therefore, `key_before_nibble` equal
-                        // to `None` is the same as if it is equal to `0`.
-                        let key_before_nibble = match key_before_nibble {
-                            Some(n) => u8::from(n),
-                            None => {
-                                or_equal = true;
-                                0
-                            }
-                        };
 
-                        // Try to find the first child that is after `key_before`.
-                        if let Some(child_num) = iter_entry_decoded
-                            .children
-                            .iter()
-                            .skip(usize::from(key_before_nibble))
-                            .position(|c| c.is_some())
-                            .map(|n| n + usize::from(key_before_nibble))
-                        {
-                            // Found a child. Make sure that it matches the prefix nibble.
-                            if prefix_nibble.map_or(false, |p| child_num != usize::from(p)) {
-                                // Child doesn't match prefix. No next key.
-                                return Ok(None);
-                            }
-
-                            // Continue iterating down through the child that has been found.
+                    stack.push(StackEntry {
+                        key_len: key.len(),
+                        children_bitmap: entry_decoded.children_bitmap(),
+                        children_in_proof_bitmap: self.entries[entry_index]
+                            .children_present_in_proof_bitmap,
+                        next_child: 0,
+                        next_child_entry_index: entry_index + 1,
+                    });
+                }
+            }
 
-                            let children_present_in_proof_bitmap =
-                                self.entries[iter_entry].children_present_in_proof_bitmap;
+            // Find the next child of the entry at the top of the stack.
+            let Some(top) = stack.last_mut() else {
+                return Ok(None);
+            };
+            key.truncate(top.key_len);
 
-                            // If the child isn't present in the proof, then the proof is
-                            // incomplete. While in some situations we could prove that the child
-                            // is necessarily the next key, there is no way to know its full key.
-                            if children_present_in_proof_bitmap & (1 << key_before_nibble) == 0 {
-                                return Err(IncompleteProofError());
-                            }
+            let Some(child_num) =
+                (top.next_child..16).find(|n| top.children_bitmap & (1 << n) != 0)
+            else {
+                stack.pop();
+                continue;
+            };
 
-                            for c in 0..key_before_nibble {
-                                if children_present_in_proof_bitmap & (1 << c) != 0 {
-                                    iter_entry += 1;
-                                    iter_entry += self.entries[iter_entry].child_entries_follow_up;
-                                }
-                            }
+            top.next_child = child_num + 1;
+            key.push(nibble::Nibble::try_from(child_num).unwrap());
 
-                            iter_entry += 1;
-                            prefix_match_iter_entry_ancestor_depth += 1;
-                            if usize::from(key_before_nibble) != child_num {
-                                key_before = either::Right(iter::empty().fuse());
-                                or_equal = true;
-                            }
-                            break;
-                        } else {
-                            // Childless branch nodes are forbidden. This is checked when
-                            // decoding the proof.
-                            debug_assert!(!matches!(
-                                iter_entry_decoded.storage_value,
-                                trie_node::StorageValue::None,
-                            ));
-
-                            // `key_before` is after the last child of `iter_entry`. The next
-                            // node is thus a sibling or uncle of `iter_entry`.
-                            iterating_up = true;
-                            key_before = either::Right(iter::empty().fuse());
-                            or_equal = true;
-                            break;
-                        }
-                    }
+            if top.children_in_proof_bitmap & (1 << child_num) == 0 {
+                // If the child isn't present in the proof, then the proof is incomplete unless
+                // the child can't possibly contain the next key.
+                if subtree_excluded(&key) {
+                    continue;
                 }
+                return Err(IncompleteProofError());
             }
+
+            entry_to_visit = Some(top.next_child_entry_index);
+            top.next_child_entry_index +=
+                1 + self.entries[top.next_child_entry_index].child_entries_follow_up;
         }
     }
 
@@ -4138,4 +4001,117 @@ mod tests {
             )
             .is_ok());
     }
+
+    #[test]
+    fn next_key_random() {
+        use crate::trie::{proof_encode, trie_node, trie_structure};
+        use rand::distributions::{Distribution as _, Uniform};
+
+        fn random_nibbles(max_len: usize) -> Vec<Nibble> {
+            (0..Uniform::new_inclusive(0, max_len).sample(&mut rand::thread_rng()))
+                .map(|_| {
+                    Nibble::try_from(Uniform::new_inclusive(0, 15).sample(&mut rand::thread_rng()))
+                        .unwrap()
+                })
+                .collect()
+        }
+
+        for _ in 0..500 {
+            let mut trie = trie_structure::TrieStructure::new();
+            for _ in 0..Uniform::new_inclusive(1, 32).sample(&mut rand::thread_rng()) {
+                match trie.node(random_nibbles(6).into_iter()) {
+                    trie_structure::Entry::Vacant(e) => {
+                        e.insert_storage_value().insert((), ());
+                    }
+                    trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(e)) => {
+                        e.insert_storage_value();
+                    }
+                    trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(_)) => {}
+                }
+            }
+
+            // Build a proof containing all the nodes of the trie.
+            let mut proof_builder = proof_encode::ProofBuilder::new();
+            let mut all_keys = Vec::new();
+            for node_index in trie.iter_unordered().collect::<Vec<_>>() {
+                let key = trie
+                    .node_full_key_by_index(node_index)
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                let node = trie.node_by_index(node_index).unwrap();
+                all_keys.push((key.clone(), node.has_storage_value()));
+
+                let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                    children: core::array::from_fn(|nibble| {
+                        let nibble = Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+                        if trie
+                            .node_by_index(node_index)
+                            .unwrap()
+                            .child_user_data(nibble)
+                            .is_some()
+                        {
+                            Some(&[][..])
+                        } else {
+                            None
+                        }
+                    }),
+                    partial_key: trie
+                        .node_by_index(node_index)
+                        .unwrap()
+                        .partial_key()
+                        .collect::<Vec<_>>()
+                        .into_iter(),
+                    storage_value: if trie.node_by_index(node_index).unwrap().has_storage_value() {
+                        trie_node::StorageValue::Unhashed(b"value")
+                    } else {
+                        trie_node::StorageValue::None
+                    },
+                })
+                .unwrap();
+
+                proof_builder.set_node_value(&key, &node_value, None);
+            }
+            all_keys.sort();
+
+            proof_builder.make_coherent();
+            let trie_root_hash = proof_builder.trie_root_hash().unwrap();
+            let decoded = super::decode_and_verify_proof(super::Config {
+                proof: proof_builder.build_to_vec(),
+            })
+            .unwrap();
+
+            for _ in 0..32 {
+                let key_before = random_nibbles(7);
+                let prefix = random_nibbles(2);
+                let or_equal = rand::random::<bool>();
+                let branch_nodes = rand::random::<bool>();
+
+                let expected = all_keys
+                    .iter()
+                    .filter(|(_, has_value)| branch_nodes || *has_value)
+                    .map(|(key, _)| key)
+                    .filter(|key| key.starts_with(&prefix))
+                    .find(|key| {
+                        if or_equal {
+                            **key >= key_before
+                        } else {
+                            **key > key_before
+                        }
+                    });
+
+                let obtained = decoded
+                    .next_key(
+                        &trie_root_hash,
+                        key_before.iter().copied(),
+                        or_equal,
+                        prefix.iter().copied(),
+                        branch_nodes,
+                    )
+                    .unwrap()
+                    .map(|k| k.collect::<Vec<_>>());
+
+                assert_eq!(obtained.as_ref(), expected);
+            }
+        }
+    }
 }
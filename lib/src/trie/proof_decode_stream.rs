@@ -0,0 +1,433 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decodes and verifies a trie proof incrementally, while it is being received.
+//!
+//! Contrary to [`super::proof_decode`], which requires the entire proof to be in memory before
+//! it can be examined, this module accepts the proof in chunks of arbitrary size, for example
+//! as it arrives from a networking substream, and yields the entries of the trie as soon as
+//! they have been verified.
+//!
+//! # Details
+//!
+//! The format of the proof is the same as the one accepted by [`super::proof_decode`]: a
+//! SCALE-encoded list of node values.
+//!
+//! Every time a node value has been received, it is hashed and compared with the list of
+//! Merkle values that are known to be part of the trie (initially, only the Merkle value of the
+//! root node). If the node value matches one of these Merkle values, it is immediately decoded
+//! and reported, and the Merkle values of its children are added to the list. Otherwise, the
+//! node value is kept in memory until the node value of its parent has been received.
+//!
+//! Entries of a proof are typically ordered from the root of the trie towards its leaves, in
+//! which case very few node values need to be kept in memory. In the worst case, however, the
+//! node values are received in the opposite order and all of them need to be kept in memory.
+//! In order to bound the memory usage, [`Config::max_memory_usage`] contains the maximum number
+//! of bytes that the decoder is allowed to keep in memory. An error is returned if this limit
+//! is exceeded.
+//!
+//! The memory usage includes the items that have been verified but not yet pulled with
+//! [`ProofDecoder::next_item`]. Items should therefore be pulled after each call to
+//! [`ProofDecoder::feed`].
+//!
+//! > **Note**: If the same node value is found at multiple different locations in the trie,
+//! >           it is only reported at the locations whose Merkle value was known at the time
+//! >           when the node value was received.
+//!
+//! # Usage
+//!
+//! Create a [`ProofDecoder`] with [`ProofDecoder::new`], then call [`ProofDecoder::feed`] every
+//! time some data is received and [`ProofDecoder::next_item`] in order to obtain the entries of
+//! the trie that have been verified. Once all the data has been received, call
+//! [`ProofDecoder::finish`].
+//!
+//! > **Note**: This module is, at the moment, only a building block. The networking code still
+//! >           reports request responses once they have been entirely received, and the storage
+//! >           and call proofs found in these responses are decoded with
+//! >           [`super::proof_decode`]. Feeding the proof to a [`ProofDecoder`] as it arrives
+//! >           from the substream requires the request-response substreams to report partial
+//! >           responses, which they don't do yet.
+
+use super::{nibble, trie_node};
+
+use alloc::{collections::VecDeque, vec, vec::Vec};
+
+/// Configuration to pass to [`ProofDecoder::new`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Merkle value (or node value) of the root node of the trie.
+    pub trie_root_hash: [u8; 32],
+
+    /// Maximum number of bytes that the decoder is allowed to keep in memory. This includes the
+    /// entry being received, the node values whose parent hasn't been received yet, the Merkle
+    /// values of the nodes that are expected, and the items that haven't been pulled yet.
+    pub max_memory_usage: usize,
+}
+
+/// Item yielded by [`ProofDecoder::next_item`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// A node of the trie has been verified.
+    Node {
+        /// Key of the node.
+        key: Vec<nibble::Nibble>,
+        /// Merkle value of the node.
+        merkle_value: Vec<u8>,
+        /// Storage value of the node.
+        storage_value: StorageValue,
+    },
+
+    /// The storage value of a node that has been reported earlier with
+    /// [`StorageValue::Hashed`] has been found in the proof.
+    HashedStorageValue {
+        /// Key of the node.
+        key: Vec<nibble::Nibble>,
+        /// Unhashed storage value.
+        value: Vec<u8>,
+    },
+}
+
+/// Storage value of a node. See [`Item::Node`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageValue {
+    /// The node has no storage value.
+    None,
+    /// The storage value is known.
+    Known(Vec<u8>),
+    /// The node value only contains the hash of the storage value. If the storage value is
+    /// present in the proof, it is later reported as an [`Item::HashedStorageValue`].
+    Hashed([u8; 32]),
+}
+
+/// Decoder in progress.
+pub struct ProofDecoder {
+    /// See [`Config::max_memory_usage`].
+    max_memory_usage: usize,
+
+    /// Current number of bytes accounted for in memory. Compared with
+    /// [`ProofDecoder::max_memory_usage`].
+    memory_usage: usize,
+
+    /// Data received but not decoded yet.
+    input: Vec<u8>,
+
+    /// What [`ProofDecoder::input`] is expected to contain.
+    state: State,
+
+    /// List of Merkle values of trie nodes or storage values that are known to be part of the
+    /// trie but whose value hasn't been received yet.
+    expected: hashbrown::HashMap<[u8; 32], Vec<Expected>, fnv::FnvBuildHasher>,
+
+    /// Entries of the proof that have been received, indexed by their hash, but that don't
+    /// match any entry of [`ProofDecoder::expected`] yet.
+    unverified: hashbrown::HashMap<[u8; 32], Vec<u8>, fnv::FnvBuildHasher>,
+
+    /// Items verified but not yet pulled.
+    output: VecDeque<Item>,
+}
+
+/// See [`ProofDecoder::state`].
+enum State {
+    /// Waiting for the number of entries in the proof.
+    EntriesCount,
+    /// Waiting for the length of the next entry.
+    EntryLength { remaining_entries: usize },
+    /// Waiting for the data of the next entry.
+    EntryData {
+        remaining_entries: usize,
+        entry_len: usize,
+    },
+    /// All the entries of the proof have been received.
+    Finished,
+}
+
+/// See [`ProofDecoder::expected`].
+enum Expected {
+    /// The value is the node value of a trie node whose key up to its partial key is the one
+    /// indicated.
+    Node(Vec<nibble::Nibble>),
+    /// The value is the storage value of the trie node whose full key is the one indicated.
+    StorageValue(Vec<nibble::Nibble>),
+}
+
+impl Expected {
+    fn memory_usage(&self) -> usize {
+        match self {
+            Expected::Node(key) | Expected::StorageValue(key) => 32 + key.len(),
+        }
+    }
+}
+
+impl ProofDecoder {
+    /// Initializes a new decoder.
+    pub fn new(config: Config) -> Self {
+        let mut expected = hashbrown::HashMap::with_capacity_and_hasher(16, Default::default());
+        expected.insert(config.trie_root_hash, vec![Expected::Node(Vec::new())]);
+
+        ProofDecoder {
+            max_memory_usage: config.max_memory_usage,
+            memory_usage: 32,
+            input: Vec::new(),
+            state: State::EntriesCount,
+            expected,
+            unverified: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+            output: VecDeque::new(),
+        }
+    }
+
+    /// Adds data to the decoder.
+    ///
+    /// The data doesn't need to be aligned in any way with the entries of the proof.
+    ///
+    /// After this function returns an error, the decoder is in an unspecified state and should
+    /// be discarded.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        if matches!(self.state, State::Finished) && !data.is_empty() {
+            return Err(Error::InvalidFormat);
+        }
+
+        self.input.extend_from_slice(data);
+        self.memory_usage += data.len();
+
+        loop {
+            match self.state {
+                State::EntriesCount | State::EntryLength { .. } => {
+                    let (num_bytes, value) = match crate::util::nom_scale_compact_usize::<
+                        nom::error::Error<&[u8]>,
+                    >(&self.input)
+                    {
+                        Ok((rest, value)) => (self.input.len() - rest.len(), value),
+                        Err(nom::Err::Incomplete(_)) => break,
+                        Err(_) => return Err(Error::InvalidFormat),
+                    };
+                    self.input.drain(..num_bytes);
+                    self.memory_usage -= num_bytes;
+
+                    self.state = match self.state {
+                        State::EntriesCount if value == 0 => State::Finished,
+                        State::EntriesCount => State::EntryLength {
+                            remaining_entries: value,
+                        },
+                        State::EntryLength { remaining_entries } => {
+                            // Entries that can't possibly fit in memory are rejected early.
+                            if value > self.max_memory_usage {
+                                return Err(Error::MemoryLimitExceeded);
+                            }
+
+                            State::EntryData {
+                                remaining_entries,
+                                entry_len: value,
+                            }
+                        }
+                        _ => unreachable!(),
+                    };
+                }
+                State::EntryData {
+                    remaining_entries,
+                    entry_len,
+                } => {
+                    if self.input.len() < entry_len {
+                        break;
+                    }
+
+                    let entry = self.input.drain(..entry_len).collect::<Vec<_>>();
+                    self.memory_usage -= entry_len;
+                    self.on_entry(entry)?;
+
+                    self.state = if remaining_entries == 1 {
+                        State::Finished
+                    } else {
+                        State::EntryLength {
+                            remaining_entries: remaining_entries - 1,
+                        }
+                    };
+                }
+                State::Finished => {
+                    if !self.input.is_empty() {
+                        return Err(Error::InvalidFormat);
+                    }
+                    break;
+                }
+            }
+        }
+
+        if self.memory_usage > self.max_memory_usage {
+            return Err(Error::MemoryLimitExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Pulls the next item that has been verified.
+    pub fn next_item(&mut self) -> Option<Item> {
+        let item = self.output.pop_front()?;
+        self.memory_usage -= item_memory_usage(&item);
+        Some(item)
+    }
+
+    /// Indicates that the entire proof has been provided. Returns the items that haven't been
+    /// pulled yet.
+    ///
+    /// Returns an error if the proof is truncated or contains entries that are disconnected
+    /// from the root node of the trie.
+    pub fn finish(self) -> Result<impl ExactSizeIterator<Item = Item>, Error> {
+        if !matches!(self.state, State::Finished) {
+            return Err(Error::Truncated);
+        }
+
+        if !self.unverified.is_empty() {
+            return Err(Error::UnusedProofEntry);
+        }
+
+        Ok(self.output.into_iter())
+    }
+
+    /// Called when an entry of the proof has been fully received.
+    fn on_entry(&mut self, entry: Vec<u8>) -> Result<(), Error> {
+        let hash = *<&[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], &entry).as_bytes())
+            .unwrap();
+
+        let Some(expected) = self.expected.remove(&hash) else {
+            self.memory_usage += entry.len();
+            if self.unverified.insert(hash, entry).is_some() {
+                return Err(Error::DuplicateProofEntry);
+            }
+            return Ok(());
+        };
+
+        let mut to_process = Vec::with_capacity(expected.len());
+        for expected in expected {
+            self.memory_usage -= expected.memory_usage();
+            to_process.push((expected, hash.to_vec(), entry.clone()));
+        }
+
+        while let Some((expected, merkle_value, value)) = to_process.pop() {
+            let node_key = match expected {
+                Expected::StorageValue(key) => {
+                    self.push_output(Item::HashedStorageValue { key, value });
+                    continue;
+                }
+                Expected::Node(key) => key,
+            };
+
+            let decoded = trie_node::decode(&value).map_err(Error::InvalidNodeValue)?;
+
+            let mut full_key = node_key;
+            full_key.extend(decoded.partial_key);
+
+            for (child_num, child) in decoded.children.iter().enumerate() {
+                let Some(child) = child else { continue };
+
+                let mut child_key = Vec::with_capacity(full_key.len() + 1);
+                child_key.extend_from_slice(&full_key);
+                child_key.push(nibble::Nibble::try_from(u8::try_from(child_num).unwrap()).unwrap());
+
+                // Children whose Merkle value is shorter than 32 bytes are inlined within their
+                // parent, and can thus be decoded immediately.
+                let Ok(child_hash) = <[u8; 32]>::try_from(*child) else {
+                    to_process.push((Expected::Node(child_key), child.to_vec(), child.to_vec()));
+                    continue;
+                };
+
+                self.expect(child_hash, Expected::Node(child_key), &mut to_process);
+            }
+
+            let storage_value = match decoded.storage_value {
+                trie_node::StorageValue::None => StorageValue::None,
+                trie_node::StorageValue::Unhashed(value) => StorageValue::Known(value.to_vec()),
+                trie_node::StorageValue::Hashed(hash) => {
+                    self.expect(
+                        *hash,
+                        Expected::StorageValue(full_key.clone()),
+                        &mut to_process,
+                    );
+                    StorageValue::Hashed(*hash)
+                }
+            };
+
+            self.push_output(Item::Node {
+                key: full_key,
+                merkle_value,
+                storage_value,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Registers the fact that an entry whose hash is `hash` is part of the trie. If this entry
+    /// has already been received, it is instead pushed to `to_process`.
+    fn expect(
+        &mut self,
+        hash: [u8; 32],
+        expected: Expected,
+        to_process: &mut Vec<(Expected, Vec<u8>, Vec<u8>)>,
+    ) {
+        if let Some(value) = self.unverified.remove(&hash) {
+            self.memory_usage -= value.len();
+            to_process.push((expected, hash.to_vec(), value));
+        } else {
+            self.memory_usage += expected.memory_usage();
+            self.expected.entry(hash).or_default().push(expected);
+        }
+    }
+
+    fn push_output(&mut self, item: Item) {
+        self.memory_usage += item_memory_usage(&item);
+        self.output.push_back(item);
+    }
+}
+
+fn item_memory_usage(item: &Item) -> usize {
+    match item {
+        Item::Node {
+            key,
+            merkle_value,
+            storage_value,
+        } => {
+            key.len()
+                + merkle_value.len()
+                + match storage_value {
+                    StorageValue::None => 0,
+                    StorageValue::Known(value) => value.len(),
+                    StorageValue::Hashed(_) => 32,
+                }
+        }
+        Item::HashedStorageValue { key, value } => key.len() + value.len(),
+    }
+}
+
+/// Possible error returned by [`ProofDecoder::feed`] or [`ProofDecoder::finish`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum Error {
+    /// Proof is in an invalid format.
+    InvalidFormat,
+    /// Proof has ended while more entries were expected.
+    Truncated,
+    /// The maximum memory usage indicated in [`Config::max_memory_usage`] has been exceeded.
+    MemoryLimitExceeded,
+    /// Failed to decode a node value whose hash matches a node of the trie.
+    #[display(fmt = "Failed to decode node value: {_0}")]
+    InvalidNodeValue(trie_node::Error),
+    /// One of the entries of the proof is disconnected from the root node.
+    UnusedProofEntry,
+    /// The same entry has been found multiple times in the proof.
+    DuplicateProofEntry,
+}
+
+#[cfg(test)]
+mod tests;
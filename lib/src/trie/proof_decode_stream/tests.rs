@@ -0,0 +1,277 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{Config, Error, Item, ProofDecoder, StorageValue};
+use crate::trie::{nibble, proof_decode, proof_encode, trie_node, trie_structure};
+
+use core::array;
+use rand::{
+    distributions::{Distribution as _, Uniform},
+    seq::SliceRandom as _,
+};
+use std::collections::BTreeMap;
+
+/// Builds a random trie and returns the list of entries of a proof containing all of its nodes,
+/// plus its root hash.
+fn random_proof_entries() -> (Vec<Vec<u8>>, [u8; 32]) {
+    let mut trie = trie_structure::TrieStructure::new();
+    for _ in 0..Uniform::new_inclusive(1, 32).sample(&mut rand::thread_rng()) {
+        let mut key = Vec::new();
+        for _ in 0..Uniform::new_inclusive(0, 12).sample(&mut rand::thread_rng()) {
+            key.push(
+                nibble::Nibble::try_from(
+                    Uniform::new_inclusive(0, 15).sample(&mut rand::thread_rng()),
+                )
+                .unwrap(),
+            );
+        }
+
+        match trie.node(key.into_iter()) {
+            trie_structure::Entry::Vacant(e) => {
+                e.insert_storage_value().insert((), ());
+            }
+            trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(e)) => {
+                e.insert_storage_value();
+            }
+            trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(_)) => {}
+        }
+    }
+
+    let mut proof_builder = proof_encode::ProofBuilder::new();
+    for node_index in trie.iter_unordered().collect::<Vec<_>>() {
+        let node = trie.node_by_index(node_index).unwrap();
+
+        let mut storage_value = Vec::new();
+        for _ in 0..Uniform::new_inclusive(0, 64).sample(&mut rand::thread_rng()) {
+            storage_value.push(Uniform::new_inclusive(0, 255).sample(&mut rand::thread_rng()));
+        }
+        let storage_value_hash = blake2_rfc::blake2b::blake2b(32, &[], &storage_value);
+        let is_hashed = node.has_storage_value() && storage_value.len() >= 33;
+
+        let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+            children: array::from_fn(|nibble| {
+                let nibble = nibble::Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+                trie.node_by_index(node_index)
+                    .unwrap()
+                    .child_user_data(nibble)
+                    .map(|_| &[][..])
+            }),
+            partial_key: trie
+                .node_by_index(node_index)
+                .unwrap()
+                .partial_key()
+                .collect::<Vec<_>>()
+                .into_iter(),
+            storage_value: if is_hashed {
+                trie_node::StorageValue::Hashed(
+                    <&[u8; 32]>::try_from(storage_value_hash.as_bytes()).unwrap(),
+                )
+            } else if trie.node_by_index(node_index).unwrap().has_storage_value() {
+                trie_node::StorageValue::Unhashed(&storage_value)
+            } else {
+                trie_node::StorageValue::None
+            },
+        })
+        .unwrap();
+
+        let key = trie
+            .node_full_key_by_index(node_index)
+            .unwrap()
+            .collect::<Vec<_>>();
+        proof_builder.set_node_value(
+            &key,
+            &node_value,
+            if is_hashed {
+                Some(&storage_value[..])
+            } else {
+                None
+            },
+        );
+    }
+
+    proof_builder.make_coherent();
+    let trie_root_hash = proof_builder.trie_root_hash().unwrap();
+    (proof_builder.build_entries().collect(), trie_root_hash)
+}
+
+fn encode_proof(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut proof = crate::util::encode_scale_compact_usize(entries.len())
+        .as_ref()
+        .to_vec();
+    for entry in entries {
+        proof.extend_from_slice(crate::util::encode_scale_compact_usize(entry.len()).as_ref());
+        proof.extend_from_slice(entry);
+    }
+    proof
+}
+
+/// Feeds the given proof to a decoder in chunks of random sizes, and returns the storage values
+/// that have been found.
+fn decode_in_chunks(
+    proof: &[u8],
+    trie_root_hash: [u8; 32],
+    max_memory_usage: usize,
+) -> Result<BTreeMap<Vec<nibble::Nibble>, StorageValue>, Error> {
+    let mut decoder = ProofDecoder::new(Config {
+        trie_root_hash,
+        max_memory_usage,
+    });
+
+    let mut out = BTreeMap::new();
+    let mut on_item = |item| match item {
+        Item::Node {
+            key, storage_value, ..
+        } => {
+            assert!(out.insert(key, storage_value).is_none());
+        }
+        Item::HashedStorageValue { key, value } => {
+            let entry = out.get_mut(&key).unwrap();
+            assert!(matches!(entry, StorageValue::Hashed(_)));
+            *entry = StorageValue::Known(value);
+        }
+    };
+
+    let mut remaining = proof;
+    while !remaining.is_empty() {
+        let chunk_len = Uniform::new_inclusive(1, remaining.len()).sample(&mut rand::thread_rng());
+        decoder.feed(&remaining[..chunk_len])?;
+        remaining = &remaining[chunk_len..];
+        while let Some(item) = decoder.next_item() {
+            on_item(item);
+        }
+    }
+
+    for item in decoder.finish()? {
+        on_item(item);
+    }
+
+    Ok(out)
+}
+
+#[test]
+fn matches_proof_decode() {
+    for _ in 0..500 {
+        let (mut entries, trie_root_hash) = random_proof_entries();
+        entries.shuffle(&mut rand::thread_rng());
+        let proof = encode_proof(&entries);
+
+        let obtained = decode_in_chunks(&proof, trie_root_hash, usize::MAX).unwrap();
+
+        let decoded =
+            proof_decode::decode_and_verify_proof(proof_decode::Config { proof }).unwrap();
+        let expected = decoded
+            .iter_ordered()
+            .filter(|(key, _)| *key.trie_root_hash == trie_root_hash)
+            .map(|(key, entry)| {
+                let storage_value = match entry.trie_node_info.storage_value {
+                    proof_decode::StorageValue::None => StorageValue::None,
+                    proof_decode::StorageValue::Known { value, .. } => {
+                        StorageValue::Known(value.to_vec())
+                    }
+                    proof_decode::StorageValue::HashKnownValueMissing(hash) => {
+                        StorageValue::Hashed(*hash)
+                    }
+                };
+                (key.key.collect::<Vec<_>>(), storage_value)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(obtained, expected);
+    }
+}
+
+#[test]
+fn memory_limit_exceeded() {
+    for _ in 0..100 {
+        let (mut entries, trie_root_hash) = random_proof_entries();
+        if entries.len() < 2 {
+            continue;
+        }
+
+        // Put the root node last, so that all the other entries must be kept in memory.
+        let root_index = entries
+            .iter()
+            .position(|e| blake2_rfc::blake2b::blake2b(32, &[], e).as_bytes() == trie_root_hash)
+            .unwrap();
+        let root = entries.remove(root_index);
+        let non_root_len = entries.iter().map(|e| e.len()).sum::<usize>();
+        entries.push(root);
+        let proof = encode_proof(&entries);
+
+        assert!(matches!(
+            decode_in_chunks(&proof, trie_root_hash, non_root_len - 1),
+            Err(Error::MemoryLimitExceeded)
+        ));
+    }
+}
+
+#[test]
+fn empty_proof() {
+    let out = decode_in_chunks(&[0], [0; 32], 1024).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn truncated_proof() {
+    let (entries, trie_root_hash) = random_proof_entries();
+    let proof = encode_proof(&entries);
+
+    let mut decoder = ProofDecoder::new(Config {
+        trie_root_hash,
+        max_memory_usage: usize::MAX,
+    });
+    decoder.feed(&proof[..proof.len() - 1]).unwrap();
+    assert!(matches!(decoder.finish(), Err(Error::Truncated)));
+}
+
+#[test]
+fn unused_proof_entry() {
+    let (mut entries, trie_root_hash) = random_proof_entries();
+    entries.push(vec![0xff; 40]);
+    let proof = encode_proof(&entries);
+
+    assert!(matches!(
+        decode_in_chunks(&proof, trie_root_hash, usize::MAX),
+        Err(Error::UnusedProofEntry)
+    ));
+}
+
+#[test]
+fn wrong_trie_root() {
+    let (entries, mut trie_root_hash) = random_proof_entries();
+    let proof = encode_proof(&entries);
+    trie_root_hash[0] ^= 0xff;
+
+    assert!(matches!(
+        decode_in_chunks(&proof, trie_root_hash, usize::MAX),
+        Err(Error::UnusedProofEntry)
+    ));
+}
+
+#[test]
+fn random_data_doesnt_panic() {
+    for _ in 0..5000 {
+        let mut data = Vec::new();
+        for _ in 0..Uniform::new_inclusive(0, 256).sample(&mut rand::thread_rng()) {
+            data.push(Uniform::new_inclusive(0, 255).sample(&mut rand::thread_rng()));
+        }
+
+        let _ = decode_in_chunks(&data, [0; 32], 1024);
+    }
+}
@@ -372,7 +372,26 @@ impl ProofBuilder {
     ///
     /// This function will succeed even if [`ProofBuilder::missing_node_values`] returns a
     /// non-zero number of elements. However, the proof produced will then be invalid.
-    pub fn build(mut self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> {
+    pub fn build(self) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> {
+        let entries = self.build_entries();
+
+        // The first bytes of the proof contain the number of entries in the proof.
+        let num_entries_encoded = crate::util::encode_scale_compact_usize(entries.len());
+
+        // Add the size of each entry before each entry.
+        let entries = entries.flat_map(|entry| {
+            let len = crate::util::encode_scale_compact_usize(entry.len());
+            [either::Left(len), either::Right(entry)].into_iter()
+        });
+
+        iter::once(either::Left(num_entries_encoded)).chain(entries.into_iter().map(either::Right))
+    }
+
+    /// Similar to [`ProofBuilder::build`], but returns the list of entries of the proof rather
+    /// than their SCALE encoding.
+    ///
+    /// The entries are yielded in an unspecified order.
+    pub fn build_entries(mut self) -> impl ExactSizeIterator<Item = Vec<u8>> {
         // Index of the root node in the trie, if any.
         let root_node_index = self.trie_structure.root_node().map(|n| n.node_index());
 
@@ -412,16 +431,7 @@ impl ProofBuilder {
             })
             .collect::<hashbrown::HashSet<_, fnv::FnvBuildHasher>>();
 
-        // The first bytes of the proof contain the number of entries in the proof.
-        let num_entries_encoded = crate::util::encode_scale_compact_usize(entries.len());
-
-        // Add the size of each entry before each entry.
-        let entries = entries.into_iter().flat_map(|entry| {
-            let len = crate::util::encode_scale_compact_usize(entry.len());
-            [either::Left(len), either::Right(entry)].into_iter()
-        });
-
-        iter::once(either::Left(num_entries_encoded)).chain(entries.into_iter().map(either::Right))
+        entries.into_iter()
     }
 
     /// Similar to [`ProofBuilder::build`], but returns a `Vec`.
@@ -271,6 +271,7 @@ macro_rules! decode_scale_compact {
 
 decode_scale_compact!(nom_scale_compact_usize, usize);
 decode_scale_compact!(nom_scale_compact_u64, u64);
+decode_scale_compact!(nom_scale_compact_u128, u128);
 
 macro_rules! encode_scale_compact {
     ($fn_name:ident, $num_ty:ty) => {
@@ -305,3 +306,4 @@ macro_rules! encode_scale_compact {
 
 encode_scale_compact!(encode_scale_compact_u64, u64);
 encode_scale_compact!(encode_scale_compact_usize, usize);
+encode_scale_compact!(encode_scale_compact_u128, u128);
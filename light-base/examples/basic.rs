@@ -62,11 +62,16 @@ fn main() {
                 // start a lot of subscriptions, and a value such as 1024 is recommended.
                 // Similarly, if you don't want any limit, feel free to pass `u32::MAX`.
                 max_subscriptions: 1024,
+                runtime_call_fuel_limit: None,
             },
 
             // This field is necessary only if adding a parachain.
             potential_relay_chains: iter::empty(),
 
+            // Cross-checking the finality claims of multiple peers is optional and disabled here.
+            finality_consistency_checks: false,
+            network_budget: Default::default(),
+
             // After a chain has been added, it is possible to extract a "database" (in the form of a
             // simple string). This database can later be passed back the next time the same chain is
             // added again.
@@ -99,9 +104,12 @@ fn main() {
             json_rpc: smoldot_light::AddChainConfigJsonRpc::Enabled {
                 max_pending_requests: NonZeroU32::new(128).unwrap(),
                 max_subscriptions: 1024,
+                runtime_call_fuel_limit: None,
             },
             database_content: "",
             user_data: (),
+            finality_consistency_checks: false,
+            network_budget: Default::default(),
 
             // The chain specification of the asset hub parachain mentions that the identifier
             // of its relay chain is `polkadot`. Because the `Client` might contain multiple different
@@ -38,6 +38,7 @@ use core::cmp;
 use smoldot::{
     chain,
     database::finalized_serialize,
+    executor, header,
     libp2p::{multiaddr, PeerId},
 };
 
@@ -57,6 +58,19 @@ pub struct DatabaseContent {
     /// was encoded.
     pub known_nodes: Vec<(PeerId, Vec<multiaddr::Multiaddr>)>,
 
+    /// Reputation of the nodes of [`DatabaseContent::known_nodes`] at the time when the database
+    /// was encoded. Nodes that aren't in this list have a reputation of 0.
+    ///
+    /// See [`network_service::NetworkServiceChain::peers_reputation`].
+    pub peers_reputation: Vec<(PeerId, i32)>,
+
+    /// SCALE-encoded headers of some of the non-finalized blocks that were known when the
+    /// database was encoded, ordered so that parents are always found before their children.
+    ///
+    /// Contains at most [`MAX_NON_FINALIZED_HEADERS`] elements. Headers are guaranteed to be
+    /// decodable, but haven't been verified in any way.
+    pub non_finalized_headers: Vec<Vec<u8>>,
+
     /// Known valid Merkle value and storage value combination for the `:code` key.
     ///
     /// Does **not** necessarily match the finalized block found in
@@ -75,8 +89,18 @@ pub struct DatabaseContentRuntimeCodeHint {
     /// Closest ancestor of the `:code` key except for `:code` itself.
     // TODO: this punches a bit through abstraction layers, but it's temporary
     pub closest_ancestor_excluding: Vec<Nibble>,
+    /// Output of the `Core_version` runtime function of the runtime compiled from
+    /// [`DatabaseContentRuntimeCodeHint::code`]. Identifies the runtime without having to
+    /// compile it.
+    ///
+    /// `None` if the database was encoded by an older version, or if the runtime had failed to
+    /// compile.
+    pub runtime_version: Option<executor::CoreVersion>,
 }
 
+/// Maximum number of entries in [`DatabaseContent::non_finalized_headers`].
+pub const MAX_NON_FINALIZED_HEADERS: usize = 32;
+
 /// Serializes the finalized state of the chain, using the given services.
 ///
 /// The returned string is guaranteed to not exceed `max_size` bytes. A truncated or invalid
@@ -92,6 +116,37 @@ pub async fn encode_database<TPlat: platform::PlatformRef>(
         .finalized_runtime_storage_merkle_values()
         .await
         .unwrap_or((None, None, None));
+    // The finalized block might have changed in between the two calls. The runtime version is
+    // only kept if it corresponds to the same `:code` as above.
+    let runtime_version = runtime_service
+        .finalized_runtime_version()
+        .await
+        .filter(|(merkle_value, _)| code_merkle_value.as_ref() == Some(merkle_value))
+        .map(|(_, runtime_version)| runtime_version);
+
+    let nodes = network_service
+        .discovered_nodes()
+        .await
+        .map(|(peer_id, addrs)| {
+            (
+                peer_id.to_base58(),
+                addrs.map(|a| a.to_string()).collect::<Vec<_>>(),
+            )
+        })
+        .collect::<hashbrown::HashMap<_, _, _>>();
+
+    let non_finalized_headers = {
+        let subscription = sync_service
+            .subscribe_all(MAX_NON_FINALIZED_HEADERS, false)
+            .await;
+        let blocks = subscription.non_finalized_blocks_ancestry_order;
+        // Keep the most recent blocks, as they are the most likely to still be useful.
+        blocks
+            .iter()
+            .skip(blocks.len().saturating_sub(MAX_NON_FINALIZED_HEADERS))
+            .map(|block| hex::encode(&block.scale_encoded_header))
+            .collect::<Vec<_>>()
+    };
 
     // Craft the structure containing all the data that we would like to include.
     let mut database_draft = SerdeDatabase {
@@ -100,16 +155,14 @@ pub async fn encode_database<TPlat: platform::PlatformRef>(
             let encoded = finalized_serialize::encode_chain(&ci, sync_service.block_number_bytes());
             serde_json::from_str(&encoded).unwrap()
         }),
-        nodes: network_service
-            .discovered_nodes()
+        reputations: network_service
+            .peers_reputation()
             .await
-            .map(|(peer_id, addrs)| {
-                (
-                    peer_id.to_base58(),
-                    addrs.map(|a| a.to_string()).collect::<Vec<_>>(),
-                )
-            })
+            .map(|(peer_id, reputation)| (peer_id.to_base58(), reputation))
+            .filter(|(peer_id, _)| nodes.contains_key(peer_id))
             .collect(),
+        nodes,
+        non_finalized_headers,
         code_merkle_value: code_merkle_value.map(hex::encode),
         // While it might seem like a good idea to compress the runtime code, in practice it is
         // normally already zstd-compressed, and additional compressing shouldn't improve the size.
@@ -121,6 +174,7 @@ pub async fn encode_database<TPlat: platform::PlatformRef>(
                 .map(|nibble| format!("{:x}", nibble))
                 .collect::<String>()
         }),
+        runtime_version: runtime_version.map(hex::encode),
     };
 
     // Cap the database length to the maximum size.
@@ -136,6 +190,14 @@ pub async fn encode_database<TPlat: platform::PlatformRef>(
         {
             database_draft.code_merkle_value = None;
             database_draft.code_storage_value = None;
+            database_draft.runtime_version = None;
+            continue;
+        }
+
+        // Then remove the oldest half of the non-finalized headers.
+        if !database_draft.non_finalized_headers.is_empty() {
+            let num_to_remove = cmp::max(1, database_draft.non_finalized_headers.len() / 2);
+            database_draft.non_finalized_headers.drain(..num_to_remove);
             continue;
         }
 
@@ -152,17 +214,27 @@ pub async fn encode_database<TPlat: platform::PlatformRef>(
 
         // Try to reduce the size of the database.
 
-        // Remove half of the nodes.
-        // Which nodes are removed doesn't really matter.
-        let mut nodes_to_remove = cmp::max(1, database_draft.nodes.len() / 2);
-        database_draft.nodes.retain(|_, _| {
-            if nodes_to_remove >= 1 {
-                nodes_to_remove -= 1;
-                false
-            } else {
-                true
-            }
-        });
+        // Remove half of the nodes, starting with the ones with the lowest reputation.
+        let mut nodes_by_reputation = database_draft
+            .nodes
+            .keys()
+            .map(|peer_id| {
+                let reputation = database_draft
+                    .reputations
+                    .get(peer_id)
+                    .copied()
+                    .unwrap_or(0);
+                (reputation, peer_id.clone())
+            })
+            .collect::<Vec<_>>();
+        nodes_by_reputation.sort_unstable();
+        for (_, peer_id) in nodes_by_reputation
+            .into_iter()
+            .take(cmp::max(1, database_draft.nodes.len() / 2))
+        {
+            database_draft.nodes.remove(&peer_id);
+            database_draft.reputations.remove(&peer_id);
+        }
     }
 }
 
@@ -207,6 +279,28 @@ pub fn decode_database(encoded: &str, block_number_bytes: usize) -> Result<Datab
         })
         .collect::<Vec<_>>();
 
+    // Same as for nodes, invalid entries are simply ignored.
+    let peers_reputation = decoded
+        .reputations
+        .iter()
+        .filter_map(|(peer_id, reputation)| Some((peer_id.parse::<PeerId>().ok()?, *reputation)))
+        .collect::<Vec<_>>();
+
+    let non_finalized_headers = decoded
+        .non_finalized_headers
+        .iter()
+        .filter_map(|h| hex::decode(h).ok())
+        .filter(|h| header::decode(h, block_number_bytes).is_ok())
+        .take(MAX_NON_FINALIZED_HEADERS)
+        .collect::<Vec<_>>();
+
+    // The runtime version is only an optimization. If it fails to decode, we simply discard it
+    // rather than the entire hint.
+    let runtime_version = decoded
+        .runtime_version
+        .and_then(|v| hex::decode(v).ok())
+        .and_then(|v| executor::CoreVersion::from_slice(v).ok());
+
     let runtime_code_hint = match (
         decoded.code_merkle_value,
         decoded.code_storage_value,
@@ -221,6 +315,7 @@ pub fn decode_database(encoded: &str, block_number_bytes: usize) -> Result<Datab
                 .iter()
                 .map(|char| Nibble::from_ascii_hex_digit(*char).ok_or(()))
                 .collect::<Result<Vec<Nibble>, ()>>()?,
+            runtime_version,
         }),
         // A combination of `Some` and `None` is technically invalid, but we simply ignore this
         // situation.
@@ -231,6 +326,8 @@ pub fn decode_database(encoded: &str, block_number_bytes: usize) -> Result<Datab
         genesis_block_hash,
         chain_information,
         known_nodes,
+        peers_reputation,
+        non_finalized_headers,
         runtime_code_hint,
     })
 }
@@ -243,6 +340,19 @@ struct SerdeDatabase {
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
     chain: Option<Box<serde_json::value::RawValue>>,
     nodes: hashbrown::HashMap<String, Vec<String>, fnv::FnvBuildHasher>,
+    /// Reputation of some of the nodes of [`SerdeDatabase::nodes`].
+    #[serde(
+        default = "Default::default",
+        skip_serializing_if = "hashbrown::HashMap::is_empty"
+    )]
+    reputations: hashbrown::HashMap<String, i32, fnv::FnvBuildHasher>,
+    /// Hexadecimal-encoded headers, without `0x` prefix.
+    #[serde(
+        rename = "nonFinalizedHeaders",
+        default = "Default::default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    non_finalized_headers: Vec<String>,
     #[serde(
         rename = "runtimeCode",
         default = "Default::default",
@@ -261,4 +371,59 @@ struct SerdeDatabase {
         skip_serializing_if = "Option::is_none"
     )]
     code_closest_ancestor_excluding: Option<String>,
+    #[serde(
+        rename = "runtimeVersion",
+        default = "Default::default",
+        skip_serializing_if = "Option::is_none"
+    )]
+    runtime_version: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use smoldot::header;
+
+    #[test]
+    fn decode_old_format() {
+        let decoded = super::decode_database(
+            &format!(r#"{{"genesisHash":"{}","nodes":{{}}}}"#, "00".repeat(32)),
+            4,
+        )
+        .unwrap();
+        assert!(decoded.chain_information.is_none());
+        assert!(decoded.known_nodes.is_empty());
+        assert!(decoded.peers_reputation.is_empty());
+        assert!(decoded.non_finalized_headers.is_empty());
+        assert!(decoded.runtime_code_hint.is_none());
+    }
+
+    #[test]
+    fn decode_reputations_and_headers() {
+        let peer_id = smoldot::libp2p::PeerId::from_public_key(
+            &smoldot::libp2p::peer_id::PublicKey::Ed25519([0; 32]),
+        );
+        let valid_header = header::Header {
+            parent_hash: [1; 32],
+            number: 5,
+            state_root: [2; 32],
+            extrinsics_root: [3; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+        .scale_encoding_vec(4);
+
+        let decoded = super::decode_database(
+            &format!(
+                r#"{{"genesisHash":"{}","nodes":{{"{}":[]}},"reputations":{{"{}":-20,"invalid":5}},"nonFinalizedHeaders":["{}","zz","0102"]}}"#,
+                "00".repeat(32),
+                peer_id.to_base58(),
+                peer_id.to_base58(),
+                hex::encode(&valid_header)
+            ),
+            4,
+        )
+        .unwrap();
+
+        assert_eq!(decoded.peers_reputation, vec![(peer_id, -20)]);
+        assert_eq!(decoded.non_finalized_headers, vec![valid_header]);
+    }
 }
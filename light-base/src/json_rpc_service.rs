@@ -51,7 +51,11 @@ use alloc::{
     string::{String, ToString as _},
     sync::Arc,
 };
-use core::{num::NonZeroU32, pin::Pin};
+use core::{
+    num::NonZeroU32,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use futures_lite::StreamExt as _;
 
 /// Configuration for [`service()`].
@@ -68,9 +72,10 @@ pub struct Config<TPlat: PlatformRef> {
     /// Maximum number of JSON-RPC requests that can be added to a queue if it is not ready to be
     /// processed immediately. Any additional request will be immediately rejected.
     ///
+    /// Each request of a batch of requests counts individually towards this limit.
+    ///
     /// This parameter is necessary in order to prevent users from using up too much memory within
     /// the client.
-    // TODO: unused at the moment
     pub max_pending_requests: NonZeroU32,
 
     /// Maximum number of active subscriptions. Any additional subscription will be immediately
@@ -113,6 +118,10 @@ pub struct Config<TPlat: PlatformRef> {
 
     /// Hash of the genesis block of the chain.
     pub genesis_block_hash: [u8; 32],
+
+    /// Maximum amount of fuel that the runtime calls performed on behalf of the JSON-RPC client
+    /// can consume, or `None` for no limit.
+    pub runtime_call_fuel_limit: Option<u64>,
 }
 
 /// Creates a new JSON-RPC service with the given configuration.
@@ -123,14 +132,19 @@ pub struct Config<TPlat: PlatformRef> {
 pub fn service<TPlat: PlatformRef>(config: Config<TPlat>) -> Frontend<TPlat> {
     let log_target = format!("json-rpc-{}", config.log_name);
 
-    let (requests_tx, requests_rx) = async_channel::unbounded(); // TODO: capacity?
+    // The number of pending requests is enforced through `num_pending_requests` rather than
+    // through the capacity of the channel, as batches of requests count as multiple requests.
+    let (requests_tx, requests_rx) = async_channel::unbounded();
     let (responses_tx, responses_rx) = async_channel::bounded(16); // TODO: capacity?
+    let num_pending_requests = Arc::new(AtomicU32::new(0));
 
     let frontend = Frontend {
         platform: config.platform.clone(),
         log_target: log_target.clone(),
         responses_rx: Arc::new(async_lock::Mutex::new(Box::pin(responses_rx))),
         requests_tx,
+        num_pending_requests: num_pending_requests.clone(),
+        max_pending_requests: config.max_pending_requests,
     };
 
     let platform = config.platform.clone();
@@ -151,6 +165,8 @@ pub fn service<TPlat: PlatformRef>(config: Config<TPlat>) -> Frontend<TPlat> {
                 system_name: config.system_name,
                 system_version: config.system_version,
                 genesis_block_hash: config.genesis_block_hash,
+                runtime_call_fuel_limit: config.runtime_call_fuel_limit,
+                num_pending_requests,
             },
             requests_rx,
             responses_tx,
@@ -174,25 +190,58 @@ pub struct Frontend<TPlat> {
     /// How to send requests to the background task.
     requests_tx: async_channel::Sender<String>,
 
+    /// Number of requests sent through [`Frontend::requests_tx`] and that the background task
+    /// hasn't pulled yet.
+    ///
+    /// The requests of a batch count individually, and until the response to the batch has been
+    /// pulled from [`Frontend::responses_rx`], as the background task holds back the responses
+    /// to the requests of a batch until all of them have been answered.
+    num_pending_requests: Arc<AtomicU32>,
+
+    /// See [`Config::max_pending_requests`].
+    max_pending_requests: NonZeroU32,
+
     /// How to receive responses coming from the background task.
+    ///
+    /// Each message is accompanied with the number of requests of
+    /// [`Frontend::num_pending_requests`] that it answers.
     // TODO: we use an Arc so that it's clonable, but that's questionnable
-    responses_rx: Arc<async_lock::Mutex<Pin<Box<async_channel::Receiver<String>>>>>,
+    #[allow(clippy::type_complexity)]
+    responses_rx: Arc<async_lock::Mutex<Pin<Box<async_channel::Receiver<(String, u32)>>>>>,
 
     /// Target to use when emitting logs.
     log_target: String,
 }
 
 impl<TPlat: PlatformRef> Frontend<TPlat> {
-    /// Queues the given JSON-RPC request to be processed in the background.
+    /// Queues the given JSON-RPC request or batch of requests to be processed in the background.
+    ///
+    /// A batch of requests is answered with a single response, once all the requests it contains
+    /// have been answered.
     ///
     /// An error is returned if [`Config::max_pending_requests`] is exceeded, which can happen
     /// if the requests take a long time to process or if [`Frontend::next_json_rpc_response`]
-    /// isn't called often enough.
+    /// isn't called often enough. Each request of a batch counts individually towards this
+    /// limit.
     pub fn queue_rpc_request(&self, json_rpc_request: String) -> Result<(), HandleRpcError> {
         let log_friendly_request =
             crate::util::truncated_str(json_rpc_request.chars().filter(|c| !c.is_control()), 250)
                 .to_string();
 
+        // Reserve the slots of the request, or of all the requests of the batch.
+        let num_requests = background::batch::num_requests_in_message(&json_rpc_request);
+        if self
+            .num_pending_requests
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |num_pending| {
+                num_pending
+                    .checked_add(num_requests)
+                    .filter(|n| *n <= self.max_pending_requests.get())
+            })
+            .is_err()
+        {
+            return Err(HandleRpcError::TooManyPendingRequests { json_rpc_request });
+        }
+
         match self.requests_tx.try_send(json_rpc_request) {
             Ok(()) => {
                 log!(
@@ -204,9 +253,14 @@ impl<TPlat: PlatformRef> Frontend<TPlat> {
                 );
                 Ok(())
             }
-            Err(err) => Err(HandleRpcError::TooManyPendingRequests {
-                json_rpc_request: err.into_inner(),
-            }),
+            Err(err) => {
+                // The background task has stopped.
+                self.num_pending_requests
+                    .fetch_sub(num_requests, Ordering::Release);
+                Err(HandleRpcError::TooManyPendingRequests {
+                    json_rpc_request: err.into_inner(),
+                })
+            }
         }
     }
 
@@ -215,10 +269,14 @@ impl<TPlat: PlatformRef> Frontend<TPlat> {
     /// If this function is called multiple times in parallel, the order in which the calls are
     /// responded to is unspecified.
     pub async fn next_json_rpc_response(&self) -> String {
-        let message = match self.responses_rx.lock().await.next().await {
+        let (message, num_answered_requests) = match self.responses_rx.lock().await.next().await {
             Some(m) => m,
             None => unreachable!(),
         };
+        if num_answered_requests != 0 {
+            self.num_pending_requests
+                .fetch_sub(num_answered_requests, Ordering::Release);
+        }
 
         log!(
             &self.platform,
@@ -15,6 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub(super) mod batch;
+mod merkle_values;
+
 use crate::{
     log, network_service,
     platform::PlatformRef,
@@ -36,6 +39,7 @@ use core::{
     iter, mem,
     num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
     time::Duration,
 };
 use futures_lite::{FutureExt as _, StreamExt as _};
@@ -50,6 +54,7 @@ use smoldot::{
     json_rpc::{self, methods, parse},
     libp2p::{multiaddr, PeerId},
     network::codec,
+    sync::para,
 };
 
 /// Configuration for a JSON-RPC service.
@@ -90,6 +95,14 @@ pub(super) struct Config<TPlat: PlatformRef> {
 
     /// Hash of the genesis block of the chain.
     pub genesis_block_hash: [u8; 32],
+
+    /// Maximum amount of fuel that runtime calls can consume, or `None` for no limit.
+    pub runtime_call_fuel_limit: Option<u64>,
+
+    /// Number of requests that have been queued by the frontend and haven't been pulled yet.
+    /// Decreased as requests are pulled, except for batches of requests, whose requests count
+    /// individually until the frontend has pulled the response to the batch.
+    pub num_pending_requests: Arc<AtomicU32>,
 }
 
 /// Fields used to process JSON-RPC requests in the background.
@@ -128,6 +141,8 @@ struct Background<TPlat: PlatformRef> {
     runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
     /// See [`Config::transactions_service`].
     transactions_service: Arc<transactions_service::TransactionsService<TPlat>>,
+    /// See [`Config::runtime_call_fuel_limit`].
+    runtime_call_fuel_limit: Option<u64>,
 
     /// Tasks that are spawned by the service and running in the background.
     background_tasks:
@@ -136,7 +151,13 @@ struct Background<TPlat: PlatformRef> {
     /// Channel where serialized JSON-RPC requests are pulled from.
     requests_rx: Pin<Box<async_channel::Receiver<String>>>,
     /// Channel to send serialized JSON-RPC responses and notifications to the foreground.
-    responses_tx: async_channel::Sender<String>,
+    responses_tx: batch::ResponsesSender,
+
+    /// Requests extracted from batches of requests and that haven't been processed yet. Their
+    /// identifier has been modified by [`batch::ResponsesSender::start_batch`].
+    batch_requests_to_process: VecDeque<String>,
+    /// See [`Config::num_pending_requests`].
+    num_pending_requests: Arc<AtomicU32>,
 
     /// State of each `chainHead_follow` subscription indexed by its ID.
     chain_head_follow_subscriptions:
@@ -161,6 +182,9 @@ struct Background<TPlat: PlatformRef> {
     /// List of all active `state_subscribeRuntimeVersion` subscriptions, indexed by the
     /// subscription ID.
     runtime_version_subscriptions: hashbrown::HashSet<String, fnv::FnvBuildHasher>,
+    /// List of all active `sudo_network_unstable_watch` subscriptions, indexed by the
+    /// subscription ID.
+    network_watch_subscriptions: hashbrown::HashSet<String, fnv::FnvBuildHasher>,
     /// List of all active `author_submitAndWatchExtrinsic`, `transaction_v1_broadcast`, and
     /// `transactionWatch_v1_submitAndWatch` subscriptions, indexed by the subscription ID.
     /// When it comes to `author_submitAndWatchExtrinsic` and
@@ -178,9 +202,17 @@ struct Background<TPlat: PlatformRef> {
     /// List of storage subscriptions whose latest sent notification isn't about the current
     /// best block.
     legacy_api_stale_storage_subscriptions: hashbrown::HashSet<Arc<str>, fnv::FnvBuildHasher>,
+    /// List of storage subscriptions that haven't received any notification yet. The first
+    /// notification of a subscription contains all of its keys, while the next ones only
+    /// contain the keys whose value has changed.
+    legacy_api_new_storage_subscriptions: hashbrown::HashSet<Arc<str>, fnv::FnvBuildHasher>,
     /// `true` if there exists a background task in [`Background::background_tasks`] currently
     /// fetching storage items for storage subscriptions.
     legacy_api_storage_query_in_progress: bool,
+    /// Closest descendant Merkle values of storage keys, used to avoid downloading storage
+    /// values that haven't changed for storage subscriptions, and to answer `chainHead_v1_storage`
+    /// requests.
+    merkle_values_cache: merkle_values::MerkleValuesCache,
 
     /// List of multi-stage requests (i.e. JSON-RPC requests that require multiple asynchronous
     /// operations) that are ready to make progress.
@@ -415,6 +447,7 @@ enum Event<TPlat: PlatformRef> {
     ChainHeadStorageOperationProgress {
         subscription_id: String,
         operation_id: String,
+        state_trie_root: [u8; 32],
         progress: sync_service::StorageQueryProgress<TPlat>,
     },
     ChainHeadCallOperationDone {
@@ -449,13 +482,23 @@ enum Event<TPlat: PlatformRef> {
     },
     LegacyApiStorageSubscriptionsUpdate {
         block_hash: [u8; 32],
-        result: Result<Vec<sync_service::StorageResultItem>, sync_service::StorageQueryError>,
+        state_trie_root: [u8; 32],
+        subscriptions: Vec<Arc<str>>,
+        result: Result<Vec<merkle_values::WatchedKeyUpdate>, sync_service::StorageQueryError>,
+    },
+    NetworkWatchFinalityConsistency {
+        subscription_id: String,
+        event: sync_service::FinalityConsistencyEvent,
+        stream: Pin<Box<async_channel::Receiver<sync_service::FinalityConsistencyEvent>>>,
     },
+    NetworkWatchDeadSubscription,
 }
 
 struct TransactionWatch {
     included_block: Option<[u8; 32]>,
     num_broadcasted_peers: usize,
+    /// `true` if a `validated` event has already been generated.
+    validated: bool,
     ty: TransactionWatchTy,
 }
 
@@ -468,8 +511,12 @@ enum TransactionWatchTy {
         /// it in the transactions service later, for example if it reports having crashed.
         transaction_bytes: Vec<u8>,
     },
-    /// `transactionWatch_v1_submitAndWatch`.
-    NewApiWatch,
+    /// `transactionWatch_v1_submitAndWatch` or `smoldot_transactionWatch_submitAndWatch`.
+    NewApiWatch {
+        /// `true` for `smoldot_transactionWatch_submitAndWatch`, in which case the events of
+        /// [`methods::TransactionWatchEventDetail`] are generated as well.
+        detailed: bool,
+    },
 }
 
 /// See [`Background::state_get_keys_paged_cache`].
@@ -485,7 +532,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
     log_target: String,
     config: Config<TPlat>,
     requests_rx: async_channel::Receiver<String>,
-    responses_tx: async_channel::Sender<String>,
+    responses_tx: async_channel::Sender<(String, u32)>,
 ) {
     let mut me = Background {
         log_target,
@@ -505,6 +552,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
         sync_service: config.sync_service.clone(),
         runtime_service: config.runtime_service.clone(),
         transactions_service: config.transactions_service.clone(),
+        runtime_call_fuel_limit: config.runtime_call_fuel_limit,
         background_tasks: stream::FuturesUnordered::new(),
         runtime_service_subscription: RuntimeServiceSubscription::NotCreated,
         all_heads_subscriptions: hashbrown::HashSet::with_capacity_and_hasher(
@@ -523,6 +571,10 @@ pub(super) async fn run<TPlat: PlatformRef>(
             2,
             Default::default(),
         ),
+        network_watch_subscriptions: hashbrown::HashSet::with_capacity_and_hasher(
+            0,
+            Default::default(),
+        ),
         transactions_subscriptions: hashbrown::HashMap::with_capacity_and_hasher(
             2,
             Default::default(),
@@ -534,9 +586,21 @@ pub(super) async fn run<TPlat: PlatformRef>(
             0,
             Default::default(),
         ),
+        legacy_api_new_storage_subscriptions: hashbrown::HashSet::with_capacity_and_hasher(
+            0,
+            Default::default(),
+        ),
         legacy_api_storage_query_in_progress: false,
+        merkle_values_cache: merkle_values::MerkleValuesCache::new(
+            NonZeroUsize::new(1024).unwrap(),
+        ),
         requests_rx: Box::pin(requests_rx),
-        responses_tx,
+        responses_tx: batch::ResponsesSender::new(
+            responses_tx,
+            config.num_pending_requests.clone(),
+        ),
+        batch_requests_to_process: VecDeque::new(),
+        num_pending_requests: config.num_pending_requests.clone(),
         multistage_requests_to_advance: VecDeque::new(),
         block_headers_cache: lru::LruCache::with_hasher(
             NonZeroUsize::new(32).unwrap_or_else(|| unreachable!()),
@@ -570,7 +634,11 @@ pub(super) async fn run<TPlat: PlatformRef>(
         enum WakeUpReason<'a, TPlat: PlatformRef> {
             ForegroundDead,
             GarbageCollection,
-            IncomingJsonRpcRequest(String),
+            IncomingJsonRpcRequest {
+                request_json: String,
+                /// `true` if the request has been extracted from a batch of requests.
+                from_batch: bool,
+            },
             AdvanceMultiStageRequest {
                 request_id_json: String,
                 stage: MultiStageRequestStage,
@@ -664,6 +732,16 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     future::pending().await
                 }
             })
+            .or(async {
+                if let Some(request_json) = me.batch_requests_to_process.pop_front() {
+                    WakeUpReason::IncomingJsonRpcRequest {
+                        request_json,
+                        from_batch: true,
+                    }
+                } else {
+                    future::pending().await
+                }
+            })
             .or(async {
                 if let Some(event) = me.background_tasks.next().await {
                     WakeUpReason::Event(event)
@@ -674,10 +752,15 @@ pub(super) async fn run<TPlat: PlatformRef>(
             .or(async {
                 // Pulling new requests is one of the lowest priority tasks, in order to avoid
                 // doing so if the task is overloaded.
-                me.requests_rx.next().await.map_or(
-                    WakeUpReason::ForegroundDead,
-                    WakeUpReason::IncomingJsonRpcRequest,
-                )
+                me.requests_rx
+                    .next()
+                    .await
+                    .map_or(WakeUpReason::ForegroundDead, |request_json| {
+                        WakeUpReason::IncomingJsonRpcRequest {
+                            request_json,
+                            from_batch: false,
+                        }
+                    })
             })
             .or(async {
                 (&mut me.next_garbage_collection).await;
@@ -701,15 +784,51 @@ pub(super) async fn run<TPlat: PlatformRef>(
                 me.new_heads_subscriptions.shrink_to_fit();
                 me.finalized_heads_subscriptions.shrink_to_fit();
                 me.runtime_version_subscriptions.shrink_to_fit();
+                me.network_watch_subscriptions.shrink_to_fit();
                 me.transactions_subscriptions.shrink_to_fit();
                 me.legacy_api_stale_storage_subscriptions.shrink_to_fit();
+                me.legacy_api_new_storage_subscriptions.shrink_to_fit();
+                me.merkle_values_cache.shrink_to_fit();
                 me.multistage_requests_to_advance.shrink_to_fit();
+                me.batch_requests_to_process.shrink_to_fit();
                 me.block_headers_pending.shrink_to_fit();
                 me.block_runtimes_pending.shrink_to_fit();
             }
 
-            WakeUpReason::IncomingJsonRpcRequest(request_json) => {
-                // New JSON-RPC request pulled from the channel.
+            WakeUpReason::IncomingJsonRpcRequest {
+                request_json,
+                from_batch: false,
+            } if parse::is_batch(&request_json) => {
+                // Batch of requests pulled from the channel. Its requests are processed
+                // individually. They continue to count towards the maximum number of pending
+                // requests until the response to the batch has been pulled by the frontend,
+                // as this response is held back by `responses_tx` in the meanwhile.
+                let batch = me
+                    .responses_tx
+                    .start_batch(&request_json, &mut me.randomness)
+                    .await;
+                for method in batch.ignored_notifications {
+                    log!(
+                        &me.platform,
+                        Warn,
+                        &me.log_target,
+                        format!(
+                            "Ignoring notification of JSON-RPC method {method:?} found in a \
+                            batch of requests, as no notification is supported"
+                        )
+                    );
+                }
+                me.batch_requests_to_process.extend(batch.requests);
+            }
+
+            WakeUpReason::IncomingJsonRpcRequest {
+                request_json,
+                from_batch,
+            } => {
+                // New JSON-RPC request pulled from the channel or from a batch.
+                if !from_batch {
+                    me.num_pending_requests.fetch_sub(1, Ordering::Release);
+                }
                 let Ok((request_id_json, request_parsed)) =
                     methods::parse_jsonrpc_client_to_server(&request_json)
                 else {
@@ -767,6 +886,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     | methods::MethodCall::state_queryStorageAt { .. }
                     | methods::MethodCall::state_subscribeRuntimeVersion { .. }
                     | methods::MethodCall::state_subscribeStorage { .. }
+                    | methods::MethodCall::state_traceBlock { .. }
                     | methods::MethodCall::state_unsubscribeRuntimeVersion { .. }
                     | methods::MethodCall::state_unsubscribeStorage { .. }
                     | methods::MethodCall::system_accountNextIndex { .. }
@@ -824,6 +944,8 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     | methods::MethodCall::transaction_v1_stop { .. }
                     | methods::MethodCall::transactionWatch_v1_submitAndWatch { .. }
                     | methods::MethodCall::transactionWatch_v1_unwatch { .. }
+                    | methods::MethodCall::smoldot_transactionWatch_submitAndWatch { .. }
+                    | methods::MethodCall::smoldot_transactionWatch_unwatch { .. }
                     | methods::MethodCall::sudo_network_unstable_watch { .. }
                     | methods::MethodCall::sudo_network_unstable_unwatch { .. }
                     | methods::MethodCall::chainHead_unstable_finalizedDatabase { .. } => {}
@@ -897,6 +1019,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             TransactionWatch {
                                 included_block: None,
                                 num_broadcasted_peers: 0,
+                                validated: false,
                                 ty: TransactionWatchTy::Legacy,
                             },
                         );
@@ -1482,6 +1605,10 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             .legacy_api_stale_storage_subscriptions
                             .insert(subscription_id.clone());
                         debug_assert!(_was_inserted);
+                        let _was_inserted = me
+                            .legacy_api_new_storage_subscriptions
+                            .insert(subscription_id.clone());
+                        debug_assert!(_was_inserted);
 
                         let _ = me
                             .responses_tx
@@ -1527,13 +1654,28 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         for (_, key) in subscribed_keys {
                             let _was_removed = me
                                 .legacy_api_storage_subscriptions_by_key
-                                .remove(&(key, subscription.clone()));
+                                .remove(&(key.clone(), subscription.clone()));
                             debug_assert!(_was_removed);
+
+                            // Stop tracking the state of the key if no other subscription is
+                            // interested in it.
+                            if me
+                                .legacy_api_storage_subscriptions_by_key
+                                .range((key.clone(), Arc::from(String::new()))..)
+                                .take_while(|(k, _)| *k == key)
+                                .next()
+                                .is_none()
+                            {
+                                me.merkle_values_cache.unwatch(&key);
+                            }
                         }
 
                         let _ = me
                             .legacy_api_stale_storage_subscriptions
                             .remove(&subscription);
+                        let _ = me
+                            .legacy_api_new_storage_subscriptions
+                            .remove(&subscription);
 
                         let _ = me
                             .responses_tx
@@ -1922,6 +2064,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         let runtime_call_future = {
                             let runtime_service = me.runtime_service.clone();
                             let function = function.into_owned();
+                            let fuel_limit = me.runtime_call_fuel_limit;
                             async move {
                                 runtime_service
                                     .clone()
@@ -1933,6 +2076,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                                         function,
                                         None,
                                         call_parameters,
+                                        fuel_limit,
                                         3,
                                         Duration::from_secs(20),
                                         NonZeroU32::new(2).unwrap(),
@@ -2076,7 +2220,10 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         // Build the list of storage operations that are effectively started.
                         // This reads from the list that the API user requests, and stops if there
                         // is no available operation slot.
+                        // Closest descendant Merkle values that are already in the cache are
+                        // directly reported instead of being requested from the network.
                         let mut storage_operations = Vec::with_capacity(items.len());
+                        let mut cached_items = Vec::new();
                         let mut items = items.into_iter();
                         loop {
                             if subscription.available_operation_slots == 0 {
@@ -2084,6 +2231,26 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             }
                             let Some(item) = items.next() else { break };
                             subscription.available_operation_slots -= 1;
+                            if let methods::ChainHeadStorageType::ClosestDescendantMerkleValue =
+                                item.ty
+                            {
+                                if let Some(merkle_value) = me
+                                    .merkle_values_cache
+                                    .get(&block_state_trie_root, &item.key.0)
+                                {
+                                    cached_items.extend(merkle_value.map(|merkle_value| {
+                                        methods::ChainHeadStorageResponseItem {
+                                            key: item.key.clone(),
+                                            value: None,
+                                            hash: None,
+                                            closest_descendant_merkle_value: Some(
+                                                methods::HexString(merkle_value.to_vec()),
+                                            ),
+                                        }
+                                    }));
+                                    continue;
+                                }
+                            }
                             storage_operations.push(sync_service::StorageRequestItem {
                                     key: item.key.0,
                                     ty: match item.ty {
@@ -2107,7 +2274,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         }
 
                         // Abort immediately if nothing was started.
-                        if storage_operations.is_empty() {
+                        if storage_operations.is_empty() && cached_items.is_empty() {
                             let _ = me
                                 .responses_tx
                                 .send(
@@ -2161,6 +2328,22 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             )
                             .await;
 
+                        if !cached_items.is_empty() {
+                            let _ = me
+                                .responses_tx
+                                .send(
+                                    methods::ServerToClient::chainHead_v1_followEvent {
+                                        subscription: Cow::Borrowed(&follow_subscription),
+                                        result: methods::FollowEvent::OperationStorageItems {
+                                            operation_id: Cow::Borrowed(&operation_id),
+                                            items: cached_items,
+                                        },
+                                    }
+                                    .to_json_request_object_parameters(None),
+                                )
+                                .await;
+                        }
+
                         let subscription_id = follow_subscription.into_owned();
                         me.background_tasks.push(Box::pin(async move {
                             async {
@@ -2173,6 +2356,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                                 Event::ChainHeadStorageOperationProgress {
                                     subscription_id,
                                     operation_id,
+                                    state_trie_root: block_state_trie_root,
                                     progress: fetch_operation.advance().await,
                                 }
                             })
@@ -2587,6 +2771,58 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         }
                     }
 
+                    methods::MethodCall::sudo_network_unstable_watch {} => {
+                        let subscription_id = {
+                            let mut subscription_id = [0u8; 32];
+                            me.randomness.fill_bytes(&mut subscription_id);
+                            bs58::encode(subscription_id).into_string()
+                        };
+
+                        let _ = me
+                            .responses_tx
+                            .send(
+                                methods::Response::sudo_network_unstable_watch(Cow::Borrowed(
+                                    &subscription_id,
+                                ))
+                                .to_json_response(request_id_json),
+                            )
+                            .await;
+
+                        // Only the events of the finality consistency checks are currently
+                        // reported. If the checks are disabled, the subscription stays silent.
+                        me.background_tasks.push(Box::pin({
+                            let sync_service = me.sync_service.clone();
+                            let subscription_id = subscription_id.clone();
+                            async move {
+                                let mut stream =
+                                    Box::pin(sync_service.subscribe_finality_consistency(16).await);
+                                if let Some(event) = stream.next().await {
+                                    Event::NetworkWatchFinalityConsistency {
+                                        subscription_id,
+                                        event,
+                                        stream,
+                                    }
+                                } else {
+                                    Event::NetworkWatchDeadSubscription
+                                }
+                            }
+                        }));
+
+                        let _was_inserted = me.network_watch_subscriptions.insert(subscription_id);
+                        debug_assert!(_was_inserted);
+                    }
+
+                    methods::MethodCall::sudo_network_unstable_unwatch { subscription } => {
+                        me.network_watch_subscriptions.remove(&*subscription);
+                        let _ = me
+                            .responses_tx
+                            .send(
+                                methods::Response::sudo_network_unstable_unwatch(())
+                                    .to_json_response(request_id_json),
+                            )
+                            .await;
+                    }
+
                     methods::MethodCall::sudo_unstable_version {} => {
                         let _ = me
                             .responses_tx
@@ -2600,16 +2836,20 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     }
 
                     request_parsed @ (methods::MethodCall::transaction_v1_broadcast { .. }
-                    | methods::MethodCall::transactionWatch_v1_submitAndWatch {
+                    | methods::MethodCall::transactionWatch_v1_submitAndWatch { .. }
+                    | methods::MethodCall::smoldot_transactionWatch_submitAndWatch {
                         ..
                     }) => {
-                        let (transaction, watched) = match request_parsed {
+                        let (transaction, watched, detailed) = match request_parsed {
                             methods::MethodCall::transaction_v1_broadcast {
                                 transaction: methods::HexString(transaction),
-                            } => (transaction, false),
+                            } => (transaction, false, false),
                             methods::MethodCall::transactionWatch_v1_submitAndWatch {
                                 transaction: methods::HexString(transaction),
-                            } => (transaction, true),
+                            } => (transaction, true, false),
+                            methods::MethodCall::smoldot_transactionWatch_submitAndWatch {
+                                transaction: methods::HexString(transaction),
+                            } => (transaction, true, true),
                             _ => unreachable!(),
                         };
 
@@ -2624,8 +2864,9 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             TransactionWatch {
                                 included_block: None,
                                 num_broadcasted_peers: 0,
+                                validated: false,
                                 ty: if watched {
-                                    TransactionWatchTy::NewApiWatch
+                                    TransactionWatchTy::NewApiWatch { detailed }
                                 } else {
                                     TransactionWatchTy::NewApi {
                                         transaction_bytes: transaction.clone(),
@@ -2644,7 +2885,11 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         let _ = me
                             .responses_tx
                             .send(
-                                if watched {
+                                if detailed {
+                                    methods::Response::smoldot_transactionWatch_submitAndWatch(
+                                        Cow::Borrowed(&subscription_id),
+                                    )
+                                } else if watched {
                                     methods::Response::transactionWatch_v1_submitAndWatch(
                                         Cow::Borrowed(&subscription_id),
                                     )
@@ -2707,7 +2952,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             .transactions_subscriptions
                             .get(&*subscription)
                             .map_or(false, |sub| {
-                                matches!(sub.ty, TransactionWatchTy::NewApiWatch)
+                                matches!(sub.ty, TransactionWatchTy::NewApiWatch { detailed: false })
                             });
                         if exists {
                             me.transactions_subscriptions.remove(&*subscription);
@@ -2721,6 +2966,25 @@ pub(super) async fn run<TPlat: PlatformRef>(
                             .await;
                     }
 
+                    methods::MethodCall::smoldot_transactionWatch_unwatch { subscription } => {
+                        let exists = me
+                            .transactions_subscriptions
+                            .get(&*subscription)
+                            .is_some_and(|sub| {
+                                matches!(sub.ty, TransactionWatchTy::NewApiWatch { detailed: true })
+                            });
+                        if exists {
+                            me.transactions_subscriptions.remove(&*subscription);
+                        }
+                        let _ = me
+                            .responses_tx
+                            .send(
+                                methods::Response::smoldot_transactionWatch_unwatch(())
+                                    .to_json_response(request_id_json),
+                            )
+                            .await;
+                    }
+
                     _method @ (methods::MethodCall::account_nextIndex { .. }
                     | methods::MethodCall::author_hasKey { .. }
                     | methods::MethodCall::author_hasSessionKeys { .. }
@@ -2740,13 +3004,12 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     | methods::MethodCall::state_getStorageHash { .. }
                     | methods::MethodCall::state_getStorageSize { .. }
                     | methods::MethodCall::state_queryStorage { .. }
+                    | methods::MethodCall::state_traceBlock { .. }
                     | methods::MethodCall::system_addReservedPeer { .. }
                     | methods::MethodCall::system_dryRun { .. }
                     | methods::MethodCall::system_localPeerId { .. }
                     | methods::MethodCall::system_networkState { .. }
-                    | methods::MethodCall::system_removeReservedPeer { .. }
-                    | methods::MethodCall::sudo_network_unstable_watch { .. }
-                    | methods::MethodCall::sudo_network_unstable_unwatch { .. }) => {
+                    | methods::MethodCall::system_removeReservedPeer { .. }) => {
                         // TODO: implement the ones that make sense to implement ^
                         log!(
                             &me.platform,
@@ -3147,6 +3410,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     // Start the runtime call in the background.
                     let runtime_service = me.runtime_service.clone();
                     let in_cache = in_cache.clone();
+                    let fuel_limit = me.runtime_call_fuel_limit;
                     me.background_tasks.push(Box::pin(async move {
                         Event::LegacyApiFunctionRuntimeCallResult {
                             request_id_json,
@@ -3160,6 +3424,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                                     function_name,
                                     required_api_version,
                                     parameters_vectored,
+                                    fuel_limit,
                                     3,
                                     Duration::from_secs(5),
                                     NonZeroU32::new(1).unwrap_or_else(|| unreachable!()),
@@ -4183,6 +4448,75 @@ pub(super) async fn run<TPlat: PlatformRef>(
                 }))
             }
 
+            WakeUpReason::Event(Event::NetworkWatchFinalityConsistency {
+                subscription_id,
+                event,
+                mut stream,
+            }) => {
+                // It might be that the JSON-RPC client has unsubscribed.
+                if !me.network_watch_subscriptions.contains(&subscription_id) {
+                    continue;
+                }
+
+                let when = u64::try_from(me.platform.now_from_unix_epoch().as_millis())
+                    .unwrap_or(u64::MAX);
+                let result = match event {
+                    sync_service::FinalityConsistencyEvent::Divergence {
+                        block_number,
+                        peer_id,
+                        block_hash,
+                        conflicting_peer_id,
+                        conflicting_block_hash,
+                    } => methods::NetworkEvent::FinalityDivergence {
+                        block_number,
+                        peer_id: peer_id.to_base58(),
+                        block_hash: methods::HashHexString(block_hash),
+                        conflicting_peer_id: conflicting_peer_id.map(|p| p.to_base58()),
+                        conflicting_block_hash: methods::HashHexString(conflicting_block_hash),
+                        when,
+                    },
+                    sync_service::FinalityConsistencyEvent::Withheld {
+                        peer_id,
+                        peer_finalized_block_number,
+                        local_finalized_block_number,
+                    } => methods::NetworkEvent::FinalityWithheld {
+                        peer_id: peer_id.to_base58(),
+                        peer_finalized_block_number,
+                        local_finalized_block_number,
+                        when,
+                    },
+                };
+
+                let _ = me
+                    .responses_tx
+                    .send(
+                        methods::ServerToClient::sudo_networkState_event {
+                            subscription: Cow::Borrowed(&subscription_id),
+                            result,
+                        }
+                        .to_json_request_object_parameters(None),
+                    )
+                    .await;
+
+                me.background_tasks.push(Box::pin(async move {
+                    if let Some(event) = stream.next().await {
+                        Event::NetworkWatchFinalityConsistency {
+                            subscription_id,
+                            event,
+                            stream,
+                        }
+                    } else {
+                        Event::NetworkWatchDeadSubscription
+                    }
+                }));
+            }
+
+            WakeUpReason::Event(Event::NetworkWatchDeadSubscription) => {
+                // The sync service doesn't generate any event for this subscription anymore.
+                // The subscription is kept alive but silent, as there is no way to notify the
+                // JSON-RPC client.
+            }
+
             WakeUpReason::Event(Event::ChainHeadCallOperationDone {
                 subscription_id,
                 operation_id,
@@ -4339,6 +4673,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
             WakeUpReason::Event(Event::ChainHeadStorageOperationProgress {
                 subscription_id,
                 operation_id,
+                state_trie_root,
                 progress:
                     sync_service::StorageQueryProgress::Progress {
                         request_index,
@@ -4352,6 +4687,19 @@ pub(super) async fn run<TPlat: PlatformRef>(
                 for (_, item) in
                     iter::once((request_index, item)).chain(iter::from_fn(|| query.try_advance()))
                 {
+                    if let sync_service::StorageResultItem::ClosestDescendantMerkleValue {
+                        requested_key,
+                        closest_descendant_merkle_value,
+                        ..
+                    } = &item
+                    {
+                        me.merkle_values_cache.insert(
+                            state_trie_root,
+                            requested_key.clone(),
+                            closest_descendant_merkle_value.clone(),
+                        );
+                    }
+
                     // Perform some API conversion.
                     let item = match item {
                         sync_service::StorageResultItem::Value {
@@ -4451,6 +4799,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         Event::ChainHeadStorageOperationProgress {
                             subscription_id,
                             operation_id,
+                            state_trie_root,
                             progress: query.advance().await,
                         }
                     })
@@ -4462,6 +4811,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                 subscription_id,
                 operation_id,
                 progress: sync_service::StorageQueryProgress::Finished,
+                ..
             }) => {
                 // A `chainHead_storage` operation has finished successfully.
 
@@ -4499,6 +4849,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                 subscription_id,
                 operation_id,
                 progress: sync_service::StorageQueryProgress::Error(_),
+                ..
             }) => {
                 // A `chainHead_storage` operation has finished failed.
 
@@ -4973,7 +5324,9 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         transactions_service::DropReason::Finalized { .. }
                         | transactions_service::DropReason::Invalid(_)
                         | transactions_service::DropReason::MaxPendingTransactionsReached
-                        | transactions_service::DropReason::ValidateError(_),
+                        | transactions_service::DropReason::ValidateError(_)
+                        | transactions_service::DropReason::Replaced { .. }
+                        | transactions_service::DropReason::PriorityTooLow { .. },
                         TransactionWatchTy::NewApi { .. },
                     ) => {
                         // In case of `transaction_v1_broadcast`, the transaction is re-inserted
@@ -4996,7 +5349,11 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         transactions_service::DropReason::ValidateError(_),
                         TransactionWatchTy::Legacy,
                     )
-                    | (transactions_service::DropReason::Crashed, TransactionWatchTy::Legacy) => {
+                    | (transactions_service::DropReason::Crashed, TransactionWatchTy::Legacy)
+                    | (
+                        transactions_service::DropReason::PriorityTooLow { .. },
+                        TransactionWatchTy::Legacy,
+                    ) => {
                         let _ = me
                             .responses_tx
                             .send(
@@ -5010,89 +5367,133 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     }
                     (
                         transactions_service::DropReason::GapInChain,
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Dropped {
-                                        error: "gap in chain of blocks".into(),
-                                        broadcasted: transaction_watch.num_broadcasted_peers != 0,
-                                    },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Dropped {
+                                    error: "gap in chain of blocks".into(),
+                                    broadcasted: transaction_watch.num_broadcasted_peers != 0,
+                                },
+                            ))
                             .await;
                     }
                     (
                         transactions_service::DropReason::MaxPendingTransactionsReached,
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Dropped {
-                                        error: "transactions pool full".into(),
-                                        broadcasted: transaction_watch.num_broadcasted_peers != 0,
-                                    },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Dropped {
+                                    error: "transactions pool full".into(),
+                                    broadcasted: transaction_watch.num_broadcasted_peers != 0,
+                                },
+                            ))
                             .await;
                     }
                     (
                         transactions_service::DropReason::Invalid(error),
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Invalid {
-                                        error: error.to_string().into(),
-                                    },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Invalid {
+                                    error: error.to_string().into(),
+                                },
+                            ))
                             .await;
                     }
                     (
                         transactions_service::DropReason::ValidateError(error),
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
+                    ) => {
+                        let _ = me
+                            .responses_tx
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Error {
+                                    error: error.to_string().into(),
+                                },
+                            ))
+                            .await;
+                    }
+                    (
+                        transactions_service::DropReason::Replaced { by },
+                        TransactionWatchTy::Legacy,
                     ) => {
                         let _ = me
                             .responses_tx
                             .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
+                                methods::ServerToClient::author_extrinsicUpdate {
                                     subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Error {
-                                        error: error.to_string().into(),
-                                    },
+                                    result: methods::TransactionStatus::Usurped(
+                                        methods::HashHexString(by),
+                                    ),
                                 }
                                 .to_json_request_object_parameters(None),
                             )
                             .await;
                     }
+                    (
+                        transactions_service::DropReason::Replaced { by },
+                        TransactionWatchTy::NewApiWatch { detailed },
+                    ) => {
+                        let _ = me
+                            .responses_tx
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Dropped {
+                                    error: format!("replaced by transaction 0x{}", hex::encode(by))
+                                        .into(),
+                                    broadcasted: transaction_watch.num_broadcasted_peers != 0,
+                                },
+                            ))
+                            .await;
+                    }
+                    (
+                        transactions_service::DropReason::PriorityTooLow { existing },
+                        TransactionWatchTy::NewApiWatch { detailed },
+                    ) => {
+                        let _ = me
+                            .responses_tx
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Dropped {
+                                    error: format!(
+                                        "priority too low to replace transaction 0x{}",
+                                        hex::encode(existing)
+                                    )
+                                    .into(),
+                                    broadcasted: transaction_watch.num_broadcasted_peers != 0,
+                                },
+                            ))
+                            .await;
+                    }
                     (
                         transactions_service::DropReason::Crashed,
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Error {
-                                        error: "transactions service has crashed".into(),
-                                    },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Error {
+                                    error: "transactions service has crashed".into(),
+                                },
+                            ))
                             .await;
                     }
 
@@ -5115,22 +5516,20 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     }
                     (
                         transactions_service::DropReason::Finalized { block_hash, index },
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Finalized {
-                                        block: methods::TransactionWatchEventBlock {
-                                            hash: methods::HashHexString(block_hash),
-                                            index,
-                                        },
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Finalized {
+                                    block: methods::TransactionWatchEventBlock {
+                                        hash: methods::HashHexString(block_hash),
+                                        index,
                                     },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                                },
+                            ))
                             .await;
                     }
                 }
@@ -5175,42 +5574,127 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     }
                     (
                         transactions_service::TransactionStatus::Broadcast(peers),
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         transaction_watch.num_broadcasted_peers += peers.len();
+                        let _ = me
+                            .responses_tx
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::Broadcasted {
+                                    num_peers: u32::try_from(
+                                        transaction_watch.num_broadcasted_peers,
+                                    )
+                                    .unwrap_or(u32::MAX),
+                                },
+                            ))
+                            .await;
+                        if *detailed {
+                            let _ = me
+                                .responses_tx
+                                .send(
+                                    methods::ServerToClient::smoldot_transactionWatch_watchEvent {
+                                        subscription: Cow::Borrowed(&subscription_id),
+                                        result: methods::SmoldotTransactionWatchEvent::Detail(
+                                            methods::TransactionWatchEventDetail::BroadcastedToPeers {
+                                                peers: peers
+                                                    .into_iter()
+                                                    .map(|peer| peer.to_base58())
+                                                    .collect(),
+                                            },
+                                        ),
+                                    }
+                                    .to_json_request_object_parameters(None),
+                                )
+                                .await;
+                        }
+                    }
+
+                    (
+                        transactions_service::TransactionStatus::BroadcastFailed { .. }
+                        | transactions_service::TransactionStatus::Validated { .. }
+                        | transactions_service::TransactionStatus::ValidationFailed { .. },
+                        TransactionWatchTy::Legacy,
+                    )
+                    | (
+                        transactions_service::TransactionStatus::BroadcastFailed { .. }
+                        | transactions_service::TransactionStatus::ValidationFailed { .. },
+                        TransactionWatchTy::NewApiWatch { detailed: false },
+                    ) => {
+                        // Nothing to do.
+                    }
+                    (
+                        transactions_service::TransactionStatus::BroadcastFailed { attempts },
+                        TransactionWatchTy::NewApiWatch { detailed: true },
+                    ) => {
                         let _ = me
                             .responses_tx
                             .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
+                                methods::ServerToClient::smoldot_transactionWatch_watchEvent {
                                     subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Broadcasted {
-                                        num_peers: u32::try_from(
-                                            transaction_watch.num_broadcasted_peers,
-                                        )
-                                        .unwrap_or(u32::MAX),
-                                    },
+                                    result: methods::SmoldotTransactionWatchEvent::Detail(
+                                        methods::TransactionWatchEventDetail::BroadcastFailed {
+                                            attempts,
+                                        },
+                                    ),
                                 }
                                 .to_json_request_object_parameters(None),
                             )
                             .await;
                     }
-
                     (
-                        transactions_service::TransactionStatus::Validated,
-                        TransactionWatchTy::Legacy,
+                        transactions_service::TransactionStatus::Validated { block_hash, .. },
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
-                        // Nothing to do.
+                        // The transactions service validates the transaction again against
+                        // new blocks, but the `validated` event is generated only once.
+                        if !transaction_watch.validated {
+                            transaction_watch.validated = true;
+                            let _ = me
+                                .responses_tx
+                                .send(transaction_watch_event(
+                                    &subscription_id,
+                                    *detailed,
+                                    methods::TransactionWatchEvent::Validated {},
+                                ))
+                                .await;
+                        }
+                        if *detailed {
+                            let _ = me
+                                .responses_tx
+                                .send(
+                                    methods::ServerToClient::smoldot_transactionWatch_watchEvent {
+                                        subscription: Cow::Borrowed(&subscription_id),
+                                        result: methods::SmoldotTransactionWatchEvent::Detail(
+                                            methods::TransactionWatchEventDetail::ValidatedAgainstBlock {
+                                                block: methods::HashHexString(block_hash),
+                                            },
+                                        ),
+                                    }
+                                    .to_json_request_object_parameters(None),
+                                )
+                                .await;
+                        }
                     }
                     (
-                        transactions_service::TransactionStatus::Validated,
-                        TransactionWatchTy::NewApiWatch,
+                        transactions_service::TransactionStatus::ValidationFailed {
+                            block_hash,
+                            error,
+                        },
+                        TransactionWatchTy::NewApiWatch { detailed: true },
                     ) => {
                         let _ = me
                             .responses_tx
                             .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
+                                methods::ServerToClient::smoldot_transactionWatch_watchEvent {
                                     subscription: Cow::Borrowed(&subscription_id),
-                                    result: methods::TransactionWatchEvent::Validated {},
+                                    result: methods::SmoldotTransactionWatchEvent::Detail(
+                                        methods::TransactionWatchEventDetail::ValidationFailed {
+                                            block: methods::HashHexString(block_hash),
+                                            error: error.to_string().into(),
+                                        },
+                                    ),
                                 }
                                 .to_json_request_object_parameters(None),
                             )
@@ -5262,44 +5746,38 @@ pub(super) async fn run<TPlat: PlatformRef>(
                         transactions_service::TransactionStatus::IncludedBlockUpdate {
                             block_hash: Some((block_hash, index)),
                         },
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         transaction_watch.included_block = Some(block_hash);
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result:
-                                        methods::TransactionWatchEvent::BestChainBlockIncluded {
-                                            block: Some(methods::TransactionWatchEventBlock {
-                                                hash: methods::HashHexString(block_hash),
-                                                index,
-                                            }),
-                                        },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::BestChainBlockIncluded {
+                                    block: Some(methods::TransactionWatchEventBlock {
+                                        hash: methods::HashHexString(block_hash),
+                                        index,
+                                    }),
+                                },
+                            ))
                             .await;
                     }
                     (
                         transactions_service::TransactionStatus::IncludedBlockUpdate {
                             block_hash: None,
                         },
-                        TransactionWatchTy::NewApiWatch,
+                        TransactionWatchTy::NewApiWatch { detailed },
                     ) => {
                         let _ = me
                             .responses_tx
-                            .send(
-                                methods::ServerToClient::transactionWatch_v1_watchEvent {
-                                    subscription: Cow::Borrowed(&subscription_id),
-                                    result:
-                                        methods::TransactionWatchEvent::BestChainBlockIncluded {
-                                            block: None,
-                                        },
-                                }
-                                .to_json_request_object_parameters(None),
-                            )
+                            .send(transaction_watch_event(
+                                &subscription_id,
+                                *detailed,
+                                methods::TransactionWatchEvent::BestChainBlockIncluded {
+                                    block: None,
+                                },
+                            ))
                             .await;
                     }
 
@@ -5354,6 +5832,20 @@ pub(super) async fn run<TPlat: PlatformRef>(
 
                 // Send the response.
                 if let Ok(block) = result {
+                    // There's no way to verify the correctness of the justifications downloaded
+                    // from the network, consequently we never return them. The finality of
+                    // parachain blocks, however, is known from the relay chain.
+                    let justifications = me
+                        .sync_service
+                        .parachain_block_inclusion(expected_block_hash)
+                        .await
+                        .map(|relay_block_hash| {
+                            vec![(
+                                para::INCLUSION_JUSTIFICATION_ENGINE_ID,
+                                relay_block_hash.to_vec(),
+                            )]
+                        });
+
                     let _ = me
                         .responses_tx
                         .send(
@@ -5369,9 +5861,7 @@ pub(super) async fn run<TPlat: PlatformRef>(
                                     me.sync_service.block_number_bytes(),
                                 )
                                 .unwrap(),
-                                // There's no way to verify the correctness of the justifications, consequently
-                                // we always return an empty list.
-                                justifications: None,
+                                justifications,
                             })
                             .to_json_response(&request_id_json),
                         )
@@ -5418,13 +5908,18 @@ pub(super) async fn run<TPlat: PlatformRef>(
 
                 // Build the list of keys that must be requested by aggregating the keys requested
                 // by all stale storage subscriptions.
+                let subscriptions = me
+                    .legacy_api_stale_storage_subscriptions
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
                 let mut keys = hashbrown::HashSet::with_hasher(SipHasherBuild::new({
                     let mut seed = [0; 16];
                     me.platform.fill_random_bytes(&mut seed);
                     seed
                 }));
                 keys.extend(
-                    me.legacy_api_stale_storage_subscriptions
+                    subscriptions
                         .iter()
                         .map(|s_id| {
                             me.legacy_api_storage_subscriptions
@@ -5443,6 +5938,11 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     continue;
                 }
 
+                // Keys whose state is already known for this state trie root, for example because
+                // the state trie root hasn't changed since the previous best block, don't need to
+                // be queried. For the other keys, we pass along their latest known Merkle value.
+                let keys = me.merkle_values_cache.keys_to_query(&state_trie_root, keys);
+
                 // Start the task in the background.
                 // The task will generate a
                 // `Event::LegacyApiStorageSubscriptionsUpdate` once it is done.
@@ -5451,53 +5951,138 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     let block_hash = *current_best_block;
                     let sync_service = me.sync_service.clone();
                     async move {
-                        let mut out = Vec::with_capacity(keys.len());
-                        let mut query = sync_service
-                            .storage_query(
+                        // Start by querying the closest descendant Merkle values of all the keys.
+                        // The storage values of the keys that were unknown are queried at the
+                        // same time.
+                        let first_query = sync_service.clone().storage_query(
+                            block_number,
+                            block_hash,
+                            state_trie_root,
+                            keys.iter().flat_map(|(key, known_merkle_value)| {
+                                iter::once(sync_service::StorageRequestItem {
+                                    key: key.clone(),
+                                    ty: sync_service::StorageRequestItemTy::ClosestDescendantMerkleValue,
+                                })
+                                .chain(known_merkle_value.is_none().then(|| {
+                                    sync_service::StorageRequestItem {
+                                        key: key.clone(),
+                                        ty: sync_service::StorageRequestItemTy::Value,
+                                    }
+                                }))
+                            }),
+                            4,
+                            Duration::from_secs(12),
+                            NonZeroU32::new(2).unwrap(),
+                        );
+                        let first_query_result = match first_query.collect_all().await {
+                            Ok(r) => r,
+                            Err(error) => {
+                                return Event::LegacyApiStorageSubscriptionsUpdate {
+                                    block_hash,
+                                    state_trie_root,
+                                    subscriptions,
+                                    result: Err(error),
+                                };
+                            }
+                        };
+
+                        let mut updates = hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+                            keys.len(),
+                            Default::default(),
+                        );
+                        for item in first_query_result {
+                            match item {
+                                sync_service::StorageResultItem::ClosestDescendantMerkleValue {
+                                    requested_key,
+                                    closest_descendant_merkle_value,
+                                    ..
+                                } => {
+                                    updates
+                                        .entry(requested_key.clone())
+                                        .or_insert_with(|| merkle_values::WatchedKeyUpdate {
+                                            key: requested_key,
+                                            merkle_value: None,
+                                            value: None,
+                                        })
+                                        .merkle_value = closest_descendant_merkle_value;
+                                }
+                                sync_service::StorageResultItem::Value { key, value } => {
+                                    updates
+                                        .entry(key.clone())
+                                        .or_insert_with(|| merkle_values::WatchedKeyUpdate {
+                                            key,
+                                            merkle_value: None,
+                                            value: None,
+                                        })
+                                        .value = Some(value);
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+
+                        // Then, download the storage values of the keys whose Merkle value has
+                        // changed. The subtrees whose Merkle value is unchanged are skipped.
+                        let changed_keys = keys
+                            .into_iter()
+                            .filter_map(|(key, known_merkle_value)| {
+                                let known_merkle_value = known_merkle_value?;
+                                let update = updates.get(&key)?;
+                                (update.merkle_value != known_merkle_value).then_some(key)
+                            })
+                            .collect::<Vec<_>>();
+                        if !changed_keys.is_empty() {
+                            let second_query = sync_service.clone().storage_query(
                                 block_number,
                                 block_hash,
                                 state_trie_root,
-                                keys.into_iter()
-                                    .map(|key| sync_service::StorageRequestItem {
+                                changed_keys.into_iter().map(|key| {
+                                    sync_service::StorageRequestItem {
                                         key,
                                         ty: sync_service::StorageRequestItemTy::Value,
-                                    }),
+                                    }
+                                }),
                                 4,
                                 Duration::from_secs(12),
                                 NonZeroU32::new(2).unwrap(),
-                            )
-                            .advance()
-                            .await;
-                        loop {
-                            match query {
-                                sync_service::StorageQueryProgress::Progress {
-                                    item,
-                                    query: next,
-                                    ..
-                                } => {
-                                    out.push(item);
-                                    query = next.advance().await;
-                                }
-                                sync_service::StorageQueryProgress::Finished => {
-                                    break Event::LegacyApiStorageSubscriptionsUpdate {
-                                        block_hash,
-                                        result: Ok(out),
-                                    };
+                            );
+                            match second_query.collect_all().await {
+                                Ok(result) => {
+                                    for item in result {
+                                        let sync_service::StorageResultItem::Value { key, value } =
+                                            item
+                                        else {
+                                            unreachable!()
+                                        };
+                                        if let Some(update) = updates.get_mut(&key) {
+                                            update.value = Some(value);
+                                        }
+                                    }
                                 }
-                                sync_service::StorageQueryProgress::Error(error) => {
-                                    break Event::LegacyApiStorageSubscriptionsUpdate {
+                                Err(error) => {
+                                    return Event::LegacyApiStorageSubscriptionsUpdate {
                                         block_hash,
+                                        state_trie_root,
+                                        subscriptions,
                                         result: Err(error),
                                     };
                                 }
                             }
                         }
+
+                        Event::LegacyApiStorageSubscriptionsUpdate {
+                            block_hash,
+                            state_trie_root,
+                            subscriptions,
+                            result: Ok(updates.into_values().collect()),
+                        }
                     }
                 }));
             }
 
             WakeUpReason::Event(Event::LegacyApiStorageSubscriptionsUpdate {
                 block_hash,
+                state_trie_root,
+                subscriptions,
                 result: Ok(result),
             }) => {
                 // Background task dedicated to performing a storage query for the storage
@@ -5516,45 +6101,56 @@ pub(super) async fn run<TPlat: PlatformRef>(
                     | RuntimeServiceSubscription::Pending(_) => true,
                 };
 
-                // Because all the keys of all the subscriptions are merged into one network
-                // request, we must now attribute each item in the result back to its subscription.
-                // While this solution is a bit CPU-heavy, it is a more elegant solution than
-                // keeping track of subscription in the background task.
-                let mut notifications_to_send = hashbrown::HashMap::<
-                    Arc<str>,
-                    Vec<(methods::HexString, Option<methods::HexString>)>,
-                    _,
-                >::with_capacity_and_hasher(
-                    me.legacy_api_storage_subscriptions.len(),
-                    fnv::FnvBuildHasher::default(),
-                );
-                for item in result {
-                    let sync_service::StorageResultItem::Value { key, value } = item else {
-                        unreachable!()
-                    };
-                    for subscription_id in me
-                        .legacy_api_storage_subscriptions_by_key
-                        .range((key.clone(), Arc::from(String::new()))..)
-                        .take_while(|(k, _)| *k == key)
-                        .map(|(_, s_id)| s_id.clone())
-                    {
-                        notifications_to_send
-                            .entry(subscription_id)
-                            .or_insert_with(Vec::new)
-                            .push((
-                                methods::HexString(key.clone()),
-                                value.clone().map(methods::HexString),
-                            ));
-                    }
-                }
+                // Update the state of the keys, and determine which ones have changed.
+                // The JSON-RPC client might have unsubscribed in the meanwhile.
+                let changed_keys = {
+                    let subscriptions_by_key = &me.legacy_api_storage_subscriptions_by_key;
+                    me.merkle_values_cache
+                        .update_watched_keys(state_trie_root, result, |key| {
+                            subscriptions_by_key
+                                .range((key.to_vec(), Arc::from(String::new()))..)
+                                .take_while(|(k, _)| *k == key)
+                                .next()
+                                .is_some()
+                        })
+                };
 
                 // Send the notifications and mark the subscriptions as no longer stale if
-                // relevant.
-                for (subscription_id, changes) in notifications_to_send {
-                    if is_up_to_date {
+                // relevant. Subscriptions that haven't received any notification yet receive
+                // all of their keys, while the other ones only receive the keys that have
+                // changed.
+                for subscription_id in subscriptions {
+                    let is_new = me
+                        .legacy_api_new_storage_subscriptions
+                        .remove(&subscription_id);
+                    let changes = me.merkle_values_cache.subscription_changes(
+                        me.legacy_api_storage_subscriptions
+                            .range((subscription_id.clone(), Vec::new())..)
+                            .take_while(|(s, _)| *s == subscription_id)
+                            .map(|(_, key)| &key[..]),
+                        is_new,
+                        &changed_keys,
+                    );
+
+                    // Subscriptions with keys whose state isn't known stay stale, so that
+                    // these keys are queried again.
+                    if is_up_to_date && !changes.has_unknown_keys {
                         me.legacy_api_stale_storage_subscriptions
                             .remove(&subscription_id);
                     }
+
+                    if changes.changes.is_empty() {
+                        continue;
+                    }
+
+                    let changes = changes
+                        .changes
+                        .into_iter()
+                        .map(|(key, value)| {
+                            (methods::HexString(key), value.map(methods::HexString))
+                        })
+                        .collect();
+
                     let _ = me
                         .responses_tx
                         .send(
@@ -5731,6 +6327,28 @@ pub(super) async fn run<TPlat: PlatformRef>(
     }
 }
 
+/// Builds the notification of an event of a `transactionWatch_v1_submitAndWatch` subscription,
+/// or of a `smoldot_transactionWatch_submitAndWatch` subscription if `detailed` is `true`.
+fn transaction_watch_event(
+    subscription_id: &str,
+    detailed: bool,
+    event: methods::TransactionWatchEvent,
+) -> String {
+    if detailed {
+        methods::ServerToClient::smoldot_transactionWatch_watchEvent {
+            subscription: Cow::Borrowed(subscription_id),
+            result: methods::SmoldotTransactionWatchEvent::Standard(event),
+        }
+        .to_json_request_object_parameters(None)
+    } else {
+        methods::ServerToClient::transactionWatch_v1_watchEvent {
+            subscription: Cow::Borrowed(subscription_id),
+            result: event,
+        }
+        .to_json_request_object_parameters(None)
+    }
+}
+
 fn convert_runtime_version_legacy(
     runtime_spec: &smoldot::executor::CoreVersion,
 ) -> methods::RuntimeVersion {
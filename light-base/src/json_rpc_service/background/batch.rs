@@ -0,0 +1,521 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Support for batches of JSON-RPC requests.
+//!
+//! The requests of a batch are processed individually by the background task, after their
+//! identifier has been replaced with a randomly-generated identifier. The [`ResponsesSender`]
+//! keeps track of which batch and position within the batch each of these identifiers
+//! corresponds to. The responses to these requests are held back by the [`ResponsesSender`] and
+//! sent to the foreground all together, with their original identifier, once all the requests of
+//! the batch have been answered.
+//!
+//! Because the identifiers are random and never sent back to the JSON-RPC client, the client
+//! can't craft a request whose response would be mistaken for the response of a request of a
+//! batch.
+//!
+//! Requests that start a subscription are refused when they are part of a batch. Because the
+//! response to the batch is held back, the notifications of the subscription would otherwise
+//! be sent to the client before the client knows about the subscription.
+//!
+//! The requests of a batch count towards the maximum number of pending requests until the
+//! response to the batch has been pulled by the foreground.
+
+use alloc::{borrow::ToOwned as _, format, string::String, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+use rand_chacha::rand_core::RngCore;
+use smoldot::json_rpc::{methods, parse};
+
+/// Sending side of the channel of responses and notifications to the foreground.
+pub(super) struct ResponsesSender {
+    /// The actual channel. Each message is accompanied with the number of requests of
+    /// [`ResponsesSender::num_pending_requests`] that it answers.
+    channel: async_channel::Sender<(String, u32)>,
+    /// Number of requests that count towards the maximum number of pending requests. Shared with
+    /// the foreground.
+    num_pending_requests: Arc<AtomicU32>,
+    /// Batches of requests whose responses are being collected.
+    batches: slab::Slab<Batch>,
+    /// For each JSON-formatted identifier given to a request of an entry of
+    /// [`ResponsesSender::batches`] and that hasn't been answered yet, the index of the batch
+    /// and the index of the request within the batch.
+    batch_requests: hashbrown::HashMap<String, (usize, usize), fnv::FnvBuildHasher>,
+}
+
+struct Batch {
+    /// For each request of the batch, in order, the JSON-formatted identifier chosen by the
+    /// JSON-RPC client and the response. The identifier is `None` if no response is expected.
+    requests: Vec<(Option<String>, Option<String>)>,
+    /// Number of requests of the batch that haven't been answered yet.
+    num_unanswered: usize,
+}
+
+/// Outcome of [`ResponsesSender::start_batch`].
+pub(super) struct StartedBatch {
+    /// Requests of the batch that must be processed, with their identifier modified.
+    pub requests: Vec<String>,
+    /// Name of the methods of the notifications (i.e. requests without an identifier) that
+    /// the batch contains. No notification is supported, and they are ignored.
+    pub ignored_notifications: Vec<String>,
+}
+
+impl ResponsesSender {
+    /// Wraps around the given channel.
+    pub(super) fn new(
+        channel: async_channel::Sender<(String, u32)>,
+        num_pending_requests: Arc<AtomicU32>,
+    ) -> Self {
+        ResponsesSender {
+            channel,
+            num_pending_requests,
+            batches: slab::Slab::new(),
+            batch_requests: hashbrown::HashMap::with_hasher(Default::default()),
+        }
+    }
+
+    /// Sends a response or a notification to the foreground.
+    ///
+    /// Responses to requests returned by [`ResponsesSender::start_batch`] are instead held back
+    /// until all the requests of their batch have been answered.
+    pub(super) async fn send(
+        &mut self,
+        response_or_notif: String,
+    ) -> Result<(), async_channel::SendError<String>> {
+        // Fast path, in order to avoid parsing every single response.
+        if self.batch_requests.is_empty() {
+            return self.send_unbatched(response_or_notif).await;
+        }
+
+        let id_json = match parse::parse_response(&response_or_notif) {
+            Ok(
+                parse::Response::Success { id_json, .. } | parse::Response::Error { id_json, .. },
+            ) => id_json,
+            Ok(parse::Response::ParseError { .. }) | Err(_) => {
+                return self.send_unbatched(response_or_notif).await;
+            }
+        };
+
+        let Some((batch_index, request_index)) = self.batch_requests.remove(id_json) else {
+            return self.send_unbatched(response_or_notif).await;
+        };
+
+        // Put back the identifier that the JSON-RPC client has chosen.
+        let id_json_range = subslice_range(&response_or_notif, id_json);
+        let batch = &mut self.batches[batch_index];
+        let (Some(original_id_json), response @ None) = &mut batch.requests[request_index] else {
+            unreachable!()
+        };
+        *response = Some(format!(
+            "{}{}{}",
+            &response_or_notif[..id_json_range.start],
+            original_id_json,
+            &response_or_notif[id_json_range.end..]
+        ));
+
+        batch.num_unanswered -= 1;
+        if batch.num_unanswered != 0 {
+            return Ok(());
+        }
+
+        let batch = self.batches.remove(batch_index);
+        self.send_batch_response(batch).await
+    }
+
+    /// Splits the given batch of requests and returns the requests that must be processed.
+    ///
+    /// The identifiers of the returned requests have been replaced with identifiers generated
+    /// using `randomness`, so that their responses can be recognized by
+    /// [`ResponsesSender::send`]. Requests that can be answered immediately, such as malformed
+    /// requests or requests that start a subscription, are not returned.
+    ///
+    /// The batch must count as [`num_requests_in_message`] requests towards the maximum number
+    /// of pending requests.
+    pub(super) async fn start_batch(
+        &mut self,
+        batch_json: &str,
+        randomness: &mut impl RngCore,
+    ) -> StartedBatch {
+        let requests = match parse::parse_batch(batch_json) {
+            Ok(requests) if !requests.is_empty() => requests,
+            Ok(_) => {
+                let _ = self
+                    .channel
+                    .send((
+                        parse::build_error_response(
+                            "null",
+                            parse::ErrorResponse::InvalidRequest,
+                            None,
+                        ),
+                        1,
+                    ))
+                    .await;
+                return StartedBatch {
+                    requests: Vec::new(),
+                    ignored_notifications: Vec::new(),
+                };
+            }
+            Err(_) => {
+                let _ = self
+                    .channel
+                    .send((parse::build_parse_error_response(), 1))
+                    .await;
+                return StartedBatch {
+                    requests: Vec::new(),
+                    ignored_notifications: Vec::new(),
+                };
+            }
+        };
+
+        let batch_index = self.batches.vacant_key();
+        let mut batch = Batch {
+            requests: Vec::with_capacity(requests.len()),
+            num_unanswered: 0,
+        };
+        let mut outcome = StartedBatch {
+            requests: Vec::with_capacity(requests.len()),
+            ignored_notifications: Vec::new(),
+        };
+
+        for (request_index, request_json) in requests.into_iter().enumerate() {
+            match parse::parse_request(request_json) {
+                Ok(parse::Request {
+                    id_json: Some(id_json),
+                    ..
+                }) if methods::parse_jsonrpc_client_to_server(request_json)
+                    .is_ok_and(|(_, method)| starts_subscription(&method)) =>
+                {
+                    batch.requests.push((
+                        None,
+                        Some(parse::build_error_response(
+                            id_json,
+                            parse::ErrorResponse::ServerError(
+                                -32000,
+                                "Subscriptions can't be started from within a batch",
+                            ),
+                            None,
+                        )),
+                    ));
+                }
+                Ok(parse::Request {
+                    id_json: Some(id_json),
+                    ..
+                }) => {
+                    let new_id_json = loop {
+                        let mut id = [0u8; 16];
+                        randomness.fill_bytes(&mut id);
+                        let id = format!("\"{}\"", hex::encode(id));
+                        if !self.batch_requests.contains_key(&id) {
+                            break id;
+                        }
+                    };
+
+                    let id_json_range = subslice_range(request_json, id_json);
+                    outcome.requests.push(format!(
+                        "{}{}{}",
+                        &request_json[..id_json_range.start],
+                        new_id_json,
+                        &request_json[id_json_range.end..]
+                    ));
+                    self.batch_requests
+                        .insert(new_id_json, (batch_index, request_index));
+                    batch.requests.push((Some(id_json.to_owned()), None));
+                    batch.num_unanswered += 1;
+                }
+                Ok(parse::Request {
+                    id_json: None,
+                    method,
+                    ..
+                }) => {
+                    // Notifications don't have any response.
+                    outcome.ignored_notifications.push(method.to_owned());
+                    batch.requests.push((None, None));
+                }
+                Err(_) => {
+                    batch.requests.push((
+                        None,
+                        Some(parse::build_error_response(
+                            "null",
+                            parse::ErrorResponse::InvalidRequest,
+                            None,
+                        )),
+                    ));
+                }
+            }
+        }
+
+        if batch.num_unanswered == 0 {
+            let _ = self.send_batch_response(batch).await;
+        } else {
+            let _inserted_index = self.batches.insert(batch);
+            debug_assert_eq!(_inserted_index, batch_index);
+        }
+
+        outcome
+    }
+
+    /// Sends to the foreground the response to a batch whose requests have all been answered.
+    async fn send_batch_response(
+        &mut self,
+        batch: Batch,
+    ) -> Result<(), async_channel::SendError<String>> {
+        debug_assert_eq!(batch.num_unanswered, 0);
+
+        if self.batches.is_empty() && self.batches.capacity() > 16 {
+            self.batches.shrink_to_fit();
+            self.batch_requests.shrink_to_fit();
+        }
+
+        // The number of requests of the batch has been checked against the maximum number of
+        // pending requests, and thus fits in a `u32`.
+        let num_requests = u32::try_from(batch.requests.len()).unwrap();
+
+        // According to the JSON-RPC specification, nothing is sent back if the batch only
+        // contains notifications.
+        match parse::build_batch_response(
+            batch
+                .requests
+                .into_iter()
+                .filter_map(|(_, response)| response),
+        ) {
+            Some(response) => self
+                .channel
+                .send((response, num_requests))
+                .await
+                .map_err(|err| async_channel::SendError(err.into_inner().0)),
+            None => {
+                self.num_pending_requests
+                    .fetch_sub(num_requests, Ordering::Release);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends to the foreground a message that isn't the response to a batch.
+    async fn send_unbatched(
+        &self,
+        response_or_notif: String,
+    ) -> Result<(), async_channel::SendError<String>> {
+        self.channel
+            .send((response_or_notif, 0))
+            .await
+            .map_err(|err| async_channel::SendError(err.into_inner().0))
+    }
+}
+
+/// Returns the number of requests that the given JSON-RPC message counts as towards the maximum
+/// number of pending requests.
+///
+/// A batch counts as the number of requests it contains. Malformed and empty batches are
+/// answered with a single error response and thus count as one request.
+pub(in super::super) fn num_requests_in_message(message: &str) -> u32 {
+    if !parse::is_batch(message) {
+        return 1;
+    }
+
+    match parse::batch_len(message) {
+        Ok(0) | Err(_) => 1,
+        Ok(num) => u32::try_from(num).unwrap_or(u32::MAX),
+    }
+}
+
+/// Returns `true` if the given request starts a subscription.
+fn starts_subscription(method: &methods::MethodCall) -> bool {
+    matches!(
+        method,
+        methods::MethodCall::author_submitAndWatchExtrinsic { .. }
+            | methods::MethodCall::chain_subscribeAllHeads { .. }
+            | methods::MethodCall::chain_subscribeFinalizedHeads { .. }
+            | methods::MethodCall::chain_subscribeNewHeads { .. }
+            | methods::MethodCall::state_subscribeRuntimeVersion { .. }
+            | methods::MethodCall::state_subscribeStorage { .. }
+            | methods::MethodCall::transaction_v1_broadcast { .. }
+            | methods::MethodCall::transactionWatch_v1_submitAndWatch { .. }
+            | methods::MethodCall::smoldot_transactionWatch_submitAndWatch { .. }
+            | methods::MethodCall::sudo_network_unstable_watch { .. }
+            | methods::MethodCall::chainHead_v1_follow { .. }
+    )
+}
+
+/// Returns the range of `subslice` within `string`.
+///
+/// # Panic
+///
+/// Panics if `subslice` isn't a sub-slice of `string`.
+///
+fn subslice_range(string: &str, subslice: &str) -> core::ops::Range<usize> {
+    let start = (subslice.as_ptr() as usize)
+        .checked_sub(string.as_ptr() as usize)
+        .unwrap();
+    assert!(start + subslice.len() <= string.len());
+    start..(start + subslice.len())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::ResponsesSender;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use rand_chacha::{rand_core::SeedableRng as _, ChaCha20Rng};
+    use smoldot::json_rpc::parse;
+
+    #[test]
+    fn responses_grouped_in_order() {
+        smol::block_on(async move {
+            let (tx, rx) = async_channel::unbounded();
+            let mut sender = ResponsesSender::new(tx, Arc::new(AtomicU32::new(0)));
+
+            let batch = sender
+                .start_batch(
+                    r#"[{"jsonrpc":"2.0","id":"a","method":"foo"},5,
+                        {"jsonrpc":"2.0","method":"bar"},{"jsonrpc":"2.0","id":2,"method":"baz"}]"#,
+                    &mut ChaCha20Rng::seed_from_u64(0),
+                )
+                .await;
+            assert_eq!(batch.requests.len(), 2);
+            assert_eq!(batch.ignored_notifications, ["bar"]);
+            let ids = batch
+                .requests
+                .iter()
+                .map(|rq| parse::parse_request(rq).unwrap().id_json.unwrap())
+                .collect::<Vec<_>>();
+
+            // Messages unrelated to the batch go through.
+            sender.send("unrelated".to_owned()).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), ("unrelated".to_owned(), 0));
+
+            sender
+                .send(parse::build_success_response(ids[1], "true"))
+                .await
+                .unwrap();
+            assert!(rx.is_empty());
+            sender
+                .send(parse::build_success_response(ids[0], "null"))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                (r#"[{"jsonrpc":"2.0","id":"a","result":null},{"jsonrpc":"2.0","id":null,"error":{"code":-32600,"message":"The JSON sent is not a valid Request object."}},{"jsonrpc":"2.0","id":2,"result":true}]"#.to_owned(), 4)
+            );
+        });
+    }
+
+    #[test]
+    fn empty_batch() {
+        smol::block_on(async move {
+            let (tx, rx) = async_channel::unbounded();
+            let mut sender = ResponsesSender::new(tx, Arc::new(AtomicU32::new(0)));
+            assert!(sender
+                .start_batch("[]", &mut ChaCha20Rng::seed_from_u64(0))
+                .await
+                .requests
+                .is_empty());
+            assert!(matches!(
+                parse::parse_response(&rx.recv().await.unwrap().0).unwrap(),
+                parse::Response::ParseError {
+                    error_code: -32600,
+                    ..
+                }
+            ));
+        });
+    }
+
+    #[test]
+    fn client_cant_spoof_batch_responses() {
+        smol::block_on(async move {
+            let (tx, rx) = async_channel::unbounded();
+            let mut sender = ResponsesSender::new(tx, Arc::new(AtomicU32::new(0)));
+
+            let batch = sender
+                .start_batch(
+                    r#"[{"jsonrpc":"2.0","id":1,"method":"foo"}]"#,
+                    &mut ChaCha20Rng::seed_from_u64(0),
+                )
+                .await;
+            let id = parse::parse_request(&batch.requests[0])
+                .unwrap()
+                .id_json
+                .unwrap();
+
+            // Responses to requests whose identifier has been chosen by the JSON-RPC client are
+            // never captured, even if they look like the identifiers of batch requests.
+            for spoofed_id in [r#""smoldot-batch:0:0""#, "1", "0"] {
+                let response = parse::build_success_response(spoofed_id, "null");
+                sender.send(response.clone()).await.unwrap();
+                assert_eq!(rx.recv().await.unwrap(), (response, 0));
+            }
+
+            sender
+                .send(parse::build_success_response(id, "null"))
+                .await
+                .unwrap();
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                (r#"[{"jsonrpc":"2.0","id":1,"result":null}]"#.to_owned(), 1)
+            );
+        });
+    }
+
+    #[test]
+    fn subscriptions_refused() {
+        smol::block_on(async move {
+            let (tx, rx) = async_channel::unbounded();
+            let mut sender = ResponsesSender::new(tx, Arc::new(AtomicU32::new(0)));
+
+            let batch = sender
+                .start_batch(
+                    r#"[{"jsonrpc":"2.0","id":1,"method":"chain_subscribeNewHeads","params":[]},
+                        {"jsonrpc":"2.0","id":2,"method":"system_name","params":[]}]"#,
+                    &mut ChaCha20Rng::seed_from_u64(0),
+                )
+                .await;
+            assert_eq!(batch.requests.len(), 1);
+            let id = parse::parse_request(&batch.requests[0])
+                .unwrap()
+                .id_json
+                .unwrap();
+
+            sender
+                .send(parse::build_success_response(id, r#""foo""#))
+                .await
+                .unwrap();
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                (r#"[{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"Subscriptions can't be started from within a batch"}},{"jsonrpc":"2.0","id":2,"result":"foo"}]"#.to_owned(), 2)
+            );
+        });
+    }
+
+    #[test]
+    fn notifications_only_batch_releases_pending_requests() {
+        smol::block_on(async move {
+            let (tx, rx) = async_channel::unbounded();
+            let num_pending_requests = Arc::new(AtomicU32::new(2));
+            let mut sender = ResponsesSender::new(tx, num_pending_requests.clone());
+
+            let batch_json =
+                r#"[{"jsonrpc":"2.0","method":"foo"},{"jsonrpc":"2.0","method":"bar"}]"#;
+            assert_eq!(super::num_requests_in_message(batch_json), 2);
+            let batch = sender
+                .start_batch(batch_json, &mut ChaCha20Rng::seed_from_u64(0))
+                .await;
+            assert!(batch.requests.is_empty());
+            assert!(rx.is_empty());
+            assert_eq!(num_pending_requests.load(Ordering::Acquire), 0);
+        });
+    }
+}
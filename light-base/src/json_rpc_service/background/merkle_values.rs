@@ -0,0 +1,479 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Closest descendant Merkle values of storage keys.
+//!
+//! The closest descendant Merkle value of a key commits to the storage value of this key and of
+//! all of its descendants. If the Merkle value of a key is identical in two blocks, then its
+//! storage value is identical as well, and there is no need to download it again.
+//!
+//! This module contains a cache of Merkle values indexed by state trie root and key, used to
+//! answer `chainHead_v1_storage` requests, and the latest known state of the keys watched by
+//! `state_subscribeStorage` subscriptions, used to determine which storage values have changed
+//! between two blocks.
+
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+
+pub(super) struct MerkleValuesCache {
+    /// Closest descendant Merkle values of keys, indexed by state trie root hash and key.
+    /// `None` if the key has no descendant.
+    entries: lru::LruCache<EntryKey, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// Latest known state of the keys watched by storage subscriptions.
+    watched: hashbrown::HashMap<Vec<u8>, WatchedKey, fnv::FnvBuildHasher>,
+}
+
+/// State trie root hash and key.
+type EntryKey = ([u8; 32], Vec<u8>);
+
+struct WatchedKey {
+    /// Hash of the state trie root the other fields correspond to.
+    state_trie_root: [u8; 32],
+    /// Closest descendant Merkle value of the key. `None` if the key has no descendant.
+    merkle_value: Option<Vec<u8>>,
+    /// Storage value of the key. `None` if the key has no storage value.
+    value: Option<Vec<u8>>,
+}
+
+/// See [`MerkleValuesCache::watched_key_status`].
+pub(super) enum WatchedKeyStatus {
+    /// The state of the key is known for the requested state trie root.
+    UpToDate,
+    /// The state of the key is known for a different state trie root.
+    Outdated {
+        /// Merkle value of the key in that different state trie.
+        merkle_value: Option<Vec<u8>>,
+    },
+    /// The state of the key isn't known.
+    Unknown,
+}
+
+/// Key to query and its latest known Merkle value. See [`MerkleValuesCache::keys_to_query`].
+pub(super) type KeyToQuery = (Vec<u8>, Option<Option<Vec<u8>>>);
+
+/// Changes to report to a storage subscription. See [`MerkleValuesCache::subscription_changes`].
+pub(super) struct SubscriptionChanges {
+    /// List of keys and their new storage value. `None` if the key has no storage value.
+    pub changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// `true` if some keys have been skipped because their state isn't known.
+    pub has_unknown_keys: bool,
+}
+
+/// New state of a watched key. See [`MerkleValuesCache::update_watched_key`].
+pub(super) struct WatchedKeyUpdate {
+    /// Key that has been queried.
+    pub key: Vec<u8>,
+    /// Closest descendant Merkle value of the key. `None` if the key has no descendant.
+    pub merkle_value: Option<Vec<u8>>,
+    /// Storage value of the key, or `None` if it hasn't been downloaded because the Merkle value
+    /// of the key hasn't changed.
+    pub value: Option<Option<Vec<u8>>>,
+}
+
+impl MerkleValuesCache {
+    /// Initializes a new empty cache that can hold up to `capacity` Merkle values, in addition
+    /// to the watched keys.
+    pub(super) fn new(capacity: NonZeroUsize) -> Self {
+        MerkleValuesCache {
+            entries: lru::LruCache::with_hasher(capacity, Default::default()),
+            watched: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+        }
+    }
+
+    /// Returns the closest descendant Merkle value of the given key in the given state trie, if
+    /// it is in the cache.
+    ///
+    /// Returns `Some(None)` if it is known that the key has no descendant.
+    pub(super) fn get(&mut self, state_trie_root: &[u8; 32], key: &[u8]) -> Option<Option<&[u8]>> {
+        self.entries
+            .get(&(*state_trie_root, key.to_vec()))
+            .map(|merkle_value| merkle_value.as_deref())
+    }
+
+    /// Inserts in the cache the closest descendant Merkle value of the given key in the given
+    /// state trie.
+    pub(super) fn insert(
+        &mut self,
+        state_trie_root: [u8; 32],
+        key: Vec<u8>,
+        merkle_value: Option<Vec<u8>>,
+    ) {
+        self.entries.put((state_trie_root, key), merkle_value);
+    }
+
+    /// Returns what is known about the given watched key in the given state trie.
+    pub(super) fn watched_key_status(
+        &self,
+        key: &[u8],
+        state_trie_root: &[u8; 32],
+    ) -> WatchedKeyStatus {
+        match self.watched.get(key) {
+            Some(watched) if watched.state_trie_root == *state_trie_root => {
+                WatchedKeyStatus::UpToDate
+            }
+            Some(watched) => WatchedKeyStatus::Outdated {
+                merkle_value: watched.merkle_value.clone(),
+            },
+            None => WatchedKeyStatus::Unknown,
+        }
+    }
+
+    /// Returns the latest known storage value of the given watched key.
+    ///
+    /// Returns `None` if the key isn't watched, and `Some(None)` if the key has no storage value.
+    pub(super) fn watched_value(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.watched
+            .get(key)
+            .map(|watched| watched.value.as_deref())
+    }
+
+    /// Returns the list of keys among `keys` that must be queried in order to know their state in
+    /// the given state trie, alongside with their latest known Merkle value.
+    ///
+    /// Keys whose state is already known for this state trie, for example because the state trie
+    /// root hasn't changed since the previous query, are skipped. The latest known Merkle value is
+    /// `None` if the state of the key isn't known at all, in which case its storage value must
+    /// be queried as well.
+    pub(super) fn keys_to_query(
+        &self,
+        state_trie_root: &[u8; 32],
+        keys: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Vec<KeyToQuery> {
+        keys.into_iter()
+            .filter_map(|key| match self.watched_key_status(&key, state_trie_root) {
+                WatchedKeyStatus::UpToDate => None,
+                WatchedKeyStatus::Outdated { merkle_value } => Some((key, Some(merkle_value))),
+                WatchedKeyStatus::Unknown => Some((key, None)),
+            })
+            .collect()
+    }
+
+    /// Updates the state of the watched keys with the result of a query. See
+    /// [`MerkleValuesCache::update_watched_key`].
+    ///
+    /// `is_watched` must return `false` for keys that are no longer watched by any subscription,
+    /// for example because the JSON-RPC client has unsubscribed in the meanwhile. The updates of
+    /// these keys are ignored.
+    ///
+    /// Returns the list of keys whose storage value has changed or wasn't known.
+    pub(super) fn update_watched_keys(
+        &mut self,
+        state_trie_root: [u8; 32],
+        updates: impl IntoIterator<Item = WatchedKeyUpdate>,
+        mut is_watched: impl FnMut(&[u8]) -> bool,
+    ) -> hashbrown::HashSet<Vec<u8>, fnv::FnvBuildHasher> {
+        let mut changed_keys = hashbrown::HashSet::with_hasher(Default::default());
+        for update in updates {
+            if !is_watched(&update.key) {
+                continue;
+            }
+
+            let key = update.key.clone();
+            if self.update_watched_key(state_trie_root, update) {
+                changed_keys.insert(key);
+            }
+        }
+        changed_keys
+    }
+
+    /// Builds the changes to report to a storage subscription watching the given keys.
+    ///
+    /// If `is_new` is `true`, the subscription hasn't received any notification yet and all of
+    /// its keys are reported. Otherwise, only the keys in `changed_keys` are reported.
+    ///
+    /// Keys whose state isn't known, for example because their update has been discarded while
+    /// the query was in progress, are skipped rather than reported as having no storage value.
+    pub(super) fn subscription_changes<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a [u8]>,
+        is_new: bool,
+        changed_keys: &hashbrown::HashSet<Vec<u8>, fnv::FnvBuildHasher>,
+    ) -> SubscriptionChanges {
+        let mut changes = Vec::new();
+        let mut has_unknown_keys = false;
+
+        for key in keys {
+            if !is_new && !changed_keys.contains(key) {
+                continue;
+            }
+
+            match self.watched_value(key) {
+                Some(value) => changes.push((key.to_vec(), value.map(|v| v.to_vec()))),
+                None => has_unknown_keys = true,
+            }
+        }
+
+        SubscriptionChanges {
+            changes,
+            has_unknown_keys,
+        }
+    }
+
+    /// Updates the state of a watched key, and inserts its Merkle value in the cache.
+    ///
+    /// If [`WatchedKeyUpdate::value`] is `None` and the key isn't watched, for example because it
+    /// has been unwatched in the meanwhile, then only the Merkle value is inserted in the cache.
+    ///
+    /// Returns `true` if the storage value of the key has changed or wasn't known.
+    pub(super) fn update_watched_key(
+        &mut self,
+        state_trie_root: [u8; 32],
+        update: WatchedKeyUpdate,
+    ) -> bool {
+        self.insert(
+            state_trie_root,
+            update.key.clone(),
+            update.merkle_value.clone(),
+        );
+
+        match (self.watched.get_mut(&update.key), update.value) {
+            (Some(watched), Some(value)) => {
+                watched.state_trie_root = state_trie_root;
+                watched.merkle_value = update.merkle_value;
+                if watched.value != value {
+                    watched.value = value;
+                    true
+                } else {
+                    false
+                }
+            }
+            (Some(watched), None) if watched.merkle_value == update.merkle_value => {
+                watched.state_trie_root = state_trie_root;
+                false
+            }
+            (Some(_), None) => {
+                // The key has been unwatched then watched again in the meanwhile. Its value
+                // is unknown.
+                self.watched.remove(&update.key);
+                false
+            }
+            (None, Some(value)) => {
+                self.watched.insert(
+                    update.key,
+                    WatchedKey {
+                        state_trie_root,
+                        merkle_value: update.merkle_value,
+                        value,
+                    },
+                );
+                true
+            }
+            (None, None) => false,
+        }
+    }
+
+    /// Stops tracking the state of the given key.
+    pub(super) fn unwatch(&mut self, key: &[u8]) {
+        self.watched.remove(key);
+    }
+
+    /// Shrinks the memory used by the list of watched keys as much as possible.
+    pub(super) fn shrink_to_fit(&mut self) {
+        self.watched.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MerkleValuesCache, WatchedKeyStatus, WatchedKeyUpdate};
+    use core::num::NonZeroUsize;
+
+    /// Key, Merkle value and storage value.
+    type StorageEntry<'a> = (&'a [u8], Option<Vec<u8>>, Option<Vec<u8>>);
+
+    /// Simulates the storage query of a storage subscriptions update, where `storage` contains
+    /// the Merkle value and storage value of each key in the queried block.
+    fn query(
+        cache: &MerkleValuesCache,
+        state_trie_root: [u8; 32],
+        keys: &[&[u8]],
+        storage: &[StorageEntry],
+    ) -> Vec<WatchedKeyUpdate> {
+        cache
+            .keys_to_query(&state_trie_root, keys.iter().map(|k| k.to_vec()))
+            .into_iter()
+            .map(|(key, known_merkle_value)| {
+                let (_, merkle_value, value) =
+                    storage.iter().find(|(k, ..)| *k == &key[..]).unwrap();
+                let download_value = known_merkle_value.as_ref() != Some(merkle_value);
+                WatchedKeyUpdate {
+                    key,
+                    merkle_value: merkle_value.clone(),
+                    value: download_value.then(|| value.clone()),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn subscription_diffing() {
+        let mut cache = MerkleValuesCache::new(NonZeroUsize::new(16).unwrap());
+        let keys: &[&[u8]] = &[b"a", b"b"];
+
+        // First notification contains all the keys.
+        let updates = query(
+            &cache,
+            [1; 32],
+            keys,
+            &[
+                (b"a", Some(vec![1]), Some(b"foo".to_vec())),
+                (b"b", None, None),
+            ],
+        );
+        assert_eq!(updates.len(), 2);
+        let changed = cache.update_watched_keys([1; 32], updates, |_| true);
+        let changes = cache.subscription_changes(keys.iter().copied(), true, &changed);
+        assert!(!changes.has_unknown_keys);
+        assert_eq!(
+            changes.changes,
+            vec![
+                (b"a".to_vec(), Some(b"foo".to_vec())),
+                (b"b".to_vec(), None)
+            ]
+        );
+
+        // Same state trie root, so nothing is queried and nothing is reported.
+        assert!(query(&cache, [1; 32], keys, &[]).is_empty());
+        let changed = cache.update_watched_keys([1; 32], Vec::new(), |_| true);
+        let changes = cache.subscription_changes(keys.iter().copied(), false, &changed);
+        assert!(changes.changes.is_empty());
+
+        // Only `b` has changed. The value of `a` isn't downloaded again.
+        let updates = query(
+            &cache,
+            [2; 32],
+            keys,
+            &[
+                (b"a", Some(vec![1]), Some(b"foo".to_vec())),
+                (b"b", Some(vec![2]), Some(b"bar".to_vec())),
+            ],
+        );
+        assert!(updates.iter().any(|u| u.key == b"a" && u.value.is_none()));
+        let changed = cache.update_watched_keys([2; 32], updates, |_| true);
+        let changes = cache.subscription_changes(keys.iter().copied(), false, &changed);
+        assert!(!changes.has_unknown_keys);
+        assert_eq!(
+            changes.changes,
+            vec![(b"b".to_vec(), Some(b"bar".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn subscription_unknown_key_skipped() {
+        let mut cache = MerkleValuesCache::new(NonZeroUsize::new(16).unwrap());
+        let keys: &[&[u8]] = &[b"a", b"b"];
+
+        // The update of `b` is discarded, for example because `b` was unwatched while the query
+        // was in progress. A new subscription must not report `b` as having no storage value.
+        let updates = query(
+            &cache,
+            [1; 32],
+            keys,
+            &[
+                (b"a", Some(vec![1]), Some(b"foo".to_vec())),
+                (b"b", Some(vec![2]), Some(b"bar".to_vec())),
+            ],
+        );
+        let changed = cache.update_watched_keys([1; 32], updates, |key| key == b"a");
+        let changes = cache.subscription_changes(keys.iter().copied(), true, &changed);
+        assert!(changes.has_unknown_keys);
+        assert_eq!(
+            changes.changes,
+            vec![(b"a".to_vec(), Some(b"foo".to_vec()))]
+        );
+
+        // `b` is queried again and reported once its state is known.
+        let updates = query(
+            &cache,
+            [1; 32],
+            keys,
+            &[(b"b", Some(vec![2]), Some(b"bar".to_vec()))],
+        );
+        assert_eq!(updates.len(), 1);
+        let changed = cache.update_watched_keys([1; 32], updates, |_| true);
+        let changes = cache.subscription_changes(keys.iter().copied(), false, &changed);
+        assert!(!changes.has_unknown_keys);
+        assert_eq!(
+            changes.changes,
+            vec![(b"b".to_vec(), Some(b"bar".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn watched_key_changes() {
+        let mut cache = MerkleValuesCache::new(NonZeroUsize::new(16).unwrap());
+        assert!(matches!(
+            cache.watched_key_status(b"foo", &[1; 32]),
+            WatchedKeyStatus::Unknown
+        ));
+
+        assert!(cache.update_watched_key(
+            [1; 32],
+            WatchedKeyUpdate {
+                key: b"foo".to_vec(),
+                merkle_value: Some(vec![1]),
+                value: Some(Some(b"bar".to_vec())),
+            }
+        ));
+        assert!(matches!(
+            cache.watched_key_status(b"foo", &[1; 32]),
+            WatchedKeyStatus::UpToDate
+        ));
+        assert!(matches!(
+            cache.watched_key_status(b"foo", &[2; 32]),
+            WatchedKeyStatus::Outdated { merkle_value: Some(ref v) } if *v == [1]
+        ));
+
+        // Merkle value unchanged, so the value isn't downloaded again.
+        assert!(!cache.update_watched_key(
+            [2; 32],
+            WatchedKeyUpdate {
+                key: b"foo".to_vec(),
+                merkle_value: Some(vec![1]),
+                value: None,
+            }
+        ));
+        assert_eq!(cache.watched_value(b"foo"), Some(Some(&b"bar"[..])));
+
+        assert!(cache.update_watched_key(
+            [3; 32],
+            WatchedKeyUpdate {
+                key: b"foo".to_vec(),
+                merkle_value: None,
+                value: Some(None),
+            }
+        ));
+        assert_eq!(cache.watched_value(b"foo"), Some(None));
+        assert_eq!(cache.get(&[2; 32], b"foo"), Some(Some(&[1][..])));
+        assert_eq!(cache.get(&[3; 32], b"foo"), Some(None));
+
+        cache.unwatch(b"foo");
+        assert!(cache.watched_value(b"foo").is_none());
+    }
+
+    #[test]
+    fn cache_capacity() {
+        let mut cache = MerkleValuesCache::new(NonZeroUsize::new(2).unwrap());
+        cache.insert([1; 32], b"a".to_vec(), Some(vec![1]));
+        cache.insert([1; 32], b"b".to_vec(), None);
+        cache.insert([2; 32], b"a".to_vec(), Some(vec![2]));
+        assert!(cache.get(&[1; 32], b"a").is_none());
+        assert_eq!(cache.get(&[1; 32], b"b"), Some(None));
+        assert_eq!(cache.get(&[2; 32], b"a"), Some(Some(&[2][..])));
+    }
+}
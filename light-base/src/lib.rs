@@ -86,7 +86,7 @@
 extern crate alloc;
 
 use alloc::{borrow::ToOwned as _, boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
-use core::{num::NonZeroU32, ops, time::Duration};
+use core::{future::Future, mem, num::NonZeroU32, ops, time::Duration};
 use hashbrown::{hash_map::Entry, HashMap};
 use itertools::Itertools as _;
 use platform::PlatformRef;
@@ -142,6 +142,22 @@ pub struct AddChainConfig<'a, TChain, TRelays> {
 
     /// Configuration for the JSON-RPC endpoint.
     pub json_rpc: AddChainConfigJsonRpc,
+
+    /// If `true`, the finalized blocks announced by several distinct peers, preferably
+    /// discovered through different bootnodes, are cross-checked with each other. A warning is
+    /// printed and an event is generated on `sudo_network_unstable_watch` JSON-RPC subscriptions
+    /// when peers diverge or when a peer appears to be withholding finality.
+    ///
+    /// Ignored for parachains, and if an identical chain is already running, in which case the
+    /// value that was passed for this already-running chain is used.
+    pub finality_consistency_checks: bool,
+
+    /// Limits on the bandwidth and number of peers that the chain is allowed to use. Use
+    /// [`Client::network_usage`] to retrieve the current usage.
+    ///
+    /// Ignored if an identical chain is already running, in which case the budget that was
+    /// passed for this already-running chain is used.
+    pub network_budget: network_service::ChainNetworkBudget,
 }
 
 /// See [`AddChainConfig::json_rpc`].
@@ -177,6 +193,16 @@ pub enum AddChainConfigJsonRpc {
         /// While a typical reasonable value would be for example 64, existing UIs tend to start
         /// a lot of subscriptions, and a value such as 1024 is recommended.
         max_subscriptions: u32,
+
+        /// Maximum amount of fuel that a runtime call performed on behalf of the JSON-RPC client
+        /// can consume, or `None` for no limit. The amount of fuel is roughly the number of
+        /// WebAssembly instructions executed. Calls that go beyond this limit fail.
+        ///
+        /// This parameter is necessary in order to prevent JSON-RPC clients from monopolizing
+        /// the CPU with runtime calls that never end.
+        /// If the JSON-RPC client is entirely trusted, then passing `None` is completely
+        /// reasonable.
+        runtime_call_fuel_limit: Option<u64>,
     },
 }
 
@@ -428,7 +454,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
 
         // Decode the database and make sure that it matches the chain by comparing the finalized
         // block header in it with the actual one.
-        let (database, database_was_wrong_chain) = {
+        let (mut database, database_was_wrong_chain) = {
             let mut maybe_database = database::decode_database(
                 config.database_content,
                 chain_spec.block_number_bytes().into(),
@@ -445,6 +471,16 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
             (maybe_database, database_was_wrong)
         };
 
+        // Extract from the database the fields that are used no matter which chain information
+        // is chosen below.
+        let (peers_reputation, database_non_finalized_headers) = match &mut database {
+            Some(database) => (
+                mem::take(&mut database.peers_reputation),
+                mem::take(&mut database.non_finalized_headers),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+
         // Load the information about the chain. If a light sync state (also known as a checkpoint)
         // is present in the chain spec, it is possible to start syncing at the finalized block
         // it describes.
@@ -530,6 +566,14 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
             }
         };
 
+        // The non-finalized headers found in the database are only relevant if they descend from
+        // the finalized block that the chain is going to start from.
+        let non_finalized_headers_hint = if used_database_chain_information {
+            database_non_finalized_headers
+        } else {
+            Vec::new()
+        };
+
         // If the chain specification specifies a parachain, find the corresponding relay chain
         // in the list of potential relay chains passed by the user.
         // If no relay chain can be found, the chain creation fails. Exactly one matching relay
@@ -685,6 +729,8 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                 }
 
                 // Start the services of the new chain.
+                let finality_consistency_checks = config.finality_consistency_checks;
+                let network_budget = config.network_budget.clone();
                 let services = {
                     // Version of the client when requested through the networking.
                     let network_identify_agent_version = format!(
@@ -704,6 +750,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                                         chain_spec.block_number_bytes(),
                                     )),
                                 para_id: *para_id,
+                                head_data_format: chain_spec.head_data_format(),
                             }
                         }
                         (Some((relay_chain, para_id, _)), None) => {
@@ -711,6 +758,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                                 relay_chain,
                                 finalized_block_header: genesis_block_header.clone(),
                                 para_id: *para_id,
+                                head_data_format: chain_spec.head_data_format(),
                             }
                         }
                         (None, Some(chain_information)) => {
@@ -727,6 +775,9 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                         &self.platform,
                         &mut self.network_service,
                         runtime_code_hint,
+                        non_finalized_headers_hint,
+                        finality_consistency_checks,
+                        network_budget,
                         genesis_block_header,
                         usize::from(chain_spec.block_number_bytes()),
                         chain_spec.fork_id().map(|f| f.to_owned()),
@@ -917,6 +968,19 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
             .spawn_task("network-service-add-initial-topology".into(), {
                 let network_service = services.network_service.clone();
                 async move {
+                    // The reputations are restored before any node is added, so that peers
+                    // with a higher reputation are the first ones to be connected to.
+                    network_service
+                        .set_peers_reputation(peers_reputation.iter().cloned())
+                        .await;
+
+                    // Nodes that had a negative reputation are not added back, as there is
+                    // little point in connecting to them again.
+                    let known_nodes = known_nodes.into_iter().filter(|(peer_id, _)| {
+                        !peers_reputation
+                            .iter()
+                            .any(|(p, reputation)| p == peer_id && *reputation < 0)
+                    });
                     network_service.discover(known_nodes, false).await;
                     network_service.discover(bootstrap_nodes, true).await;
                 }
@@ -927,6 +991,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
         let json_rpc_frontend = if let AddChainConfigJsonRpc::Enabled {
             max_pending_requests,
             max_subscriptions,
+            runtime_call_fuel_limit,
         } = config.json_rpc
         {
             let frontend = json_rpc_service::service(json_rpc_service::Config {
@@ -945,6 +1010,7 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
                 system_name: self.platform.client_name().into_owned(),
                 system_version: self.platform.client_version().into_owned(),
                 genesis_block_hash,
+                runtime_call_fuel_limit,
             });
 
             Some(frontend)
@@ -1049,6 +1115,37 @@ impl<TPlat: platform::PlatformRef, TChain> Client<TPlat, TChain> {
         self.json_rpc_request_inner(json_rpc_request.into(), chain_id)
     }
 
+    /// Returns the networking resources currently used by the given chain, to be compared with
+    /// the limits passed through [`AddChainConfig::network_budget`].
+    ///
+    /// Chains that are identical share the same networking resources, and thus the same usage.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn network_usage(
+        &self,
+        chain_id: ChainId,
+    ) -> impl Future<Output = network_service::ChainNetworkUsage> + Send + 'static {
+        let key = &self.public_api_chains.get(chain_id.0).unwrap().key;
+
+        // `chains_by_key` is created lazily when `add_chain` is called.
+        // Since the chain has been added with `add_chain`, it is guaranteed that `chains_by_key`
+        // is set.
+        let network_service = self
+            .chains_by_key
+            .as_ref()
+            .unwrap_or_else(|| unreachable!())
+            .get(key)
+            .unwrap()
+            .services
+            .network_service
+            .clone();
+
+        async move { network_service.usage().await }
+    }
+
     fn json_rpc_request_inner(
         &mut self,
         json_rpc_request: String,
@@ -1117,6 +1214,7 @@ enum StartServicesChainTy<'a, TPlat: platform::PlatformRef> {
         relay_chain: &'a ChainServices<TPlat>,
         finalized_block_header: Vec<u8>,
         para_id: u32,
+        head_data_format: smoldot::sync::para::HeadDataFormat,
     },
 }
 
@@ -1129,6 +1227,9 @@ fn start_services<TPlat: platform::PlatformRef>(
     platform: &TPlat,
     network_service: &mut Option<Arc<network_service::NetworkService<TPlat>>>,
     runtime_code_hint: Option<database::DatabaseContentRuntimeCodeHint>,
+    non_finalized_headers_hint: Vec<Vec<u8>>,
+    finality_consistency_checks: bool,
+    network_budget: network_service::ChainNetworkBudget,
     genesis_block_scale_encoded_header: Vec<u8>,
     block_number_bytes: usize,
     fork_id: Option<String>,
@@ -1194,6 +1295,7 @@ fn start_services<TPlat: platform::PlatformRef>(
         },
         fork_id,
         block_number_bytes,
+        budget: network_budget,
     });
 
     let (sync_service, runtime_service) = match config {
@@ -1201,7 +1303,7 @@ fn start_services<TPlat: platform::PlatformRef>(
             relay_chain,
             finalized_block_header,
             para_id,
-            ..
+            head_data_format,
         } => {
             // Chain is a parachain.
 
@@ -1213,11 +1315,14 @@ fn start_services<TPlat: platform::PlatformRef>(
                 log_name: log_name.clone(),
                 block_number_bytes,
                 network_service: network_service_chain.clone(),
+                storage_cache_memory_budget: 4 * 1024 * 1024,
                 chain_type: sync_service::ConfigChainType::Parachain(
                     sync_service::ConfigParachain {
                         finalized_block_header,
                         para_id,
+                        head_data_format,
                         relay_chain_sync: relay_chain.runtime_service.clone(),
+                        relay_chain_sync_service: relay_chain.sync_service.clone(),
                     },
                 ),
             }));
@@ -1247,6 +1352,7 @@ fn start_services<TPlat: platform::PlatformRef>(
                 block_number_bytes,
                 platform: platform.clone(),
                 network_service: network_service_chain.clone(),
+                storage_cache_memory_budget: 4 * 1024 * 1024,
                 chain_type: sync_service::ConfigChainType::RelayChain(
                     sync_service::ConfigRelayChain {
                         chain_information: chain_information.clone(),
@@ -1255,8 +1361,11 @@ fn start_services<TPlat: platform::PlatformRef>(
                                 storage_value: hint.code,
                                 merkle_value: hint.code_merkle_value,
                                 closest_ancestor_excluding: hint.closest_ancestor_excluding,
+                                runtime_version: hint.runtime_version,
                             }
                         }),
+                        non_finalized_headers_hint,
+                        finality_consistency_checks,
                     },
                 ),
             }));
@@ -48,17 +48,23 @@ use crate::{
 use alloc::{
     borrow::ToOwned as _,
     boxed::Box,
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
     format,
     string::{String, ToString as _},
     sync::Arc,
     vec::{self, Vec},
 };
-use core::{cmp, mem, num::NonZeroUsize, pin::Pin, time::Duration};
+use core::{
+    cmp, mem,
+    num::{NonZeroU32, NonZeroUsize},
+    pin::Pin,
+    time::Duration,
+};
 use futures_channel::oneshot;
 use futures_lite::FutureExt as _;
 use futures_util::{future, stream, StreamExt as _};
 use hashbrown::{HashMap, HashSet};
+use itertools::Itertools as _;
 use rand::seq::IteratorRandom as _;
 use rand_chacha::rand_core::SeedableRng as _;
 use smoldot::{
@@ -75,6 +81,7 @@ use smoldot::{
 pub use codec::{CallProofRequestConfig, Role};
 pub use service::{ChainId, EncodedMerkleProof, PeerId, QueueNotificationError};
 
+mod budget;
 mod tasks;
 
 /// Configuration for a [`NetworkService`].
@@ -134,6 +141,58 @@ pub struct ConfigChain {
     /// Must be `Some` if and only if the chain uses the GrandPa networking protocol. Contains the
     /// number of the finalized block at the time of the initialization.
     pub grandpa_protocol_finalized_block_height: Option<u64>,
+
+    /// Limits on the networking resources that this chain is allowed to use.
+    ///
+    /// Ignored if an identical chain has already been added, in which case the budget of the
+    /// existing chain is used.
+    pub budget: ChainNetworkBudget,
+}
+
+/// Limits on the networking resources that a chain is allowed to use. See [`ConfigChain::budget`].
+///
+/// The bandwidth limits apply to the requests and notifications of the chain. A connection that
+/// is shared between multiple chains is never restricted by the budget of one of these chains.
+/// Requests that would exceed a rate limit are delayed until the limit allows them, and requests
+/// started after [`ChainNetworkBudget::max_total_bytes`] has been reached fail immediately.
+///
+/// The overhead of the connections themselves, such as the handshakes, pings and discovery
+/// requests, isn't counted.
+#[derive(Debug, Clone, Default)]
+pub struct ChainNetworkBudget {
+    /// Maximum number of bytes per second received from the network. `None` for no limit.
+    pub max_download_bytes_per_sec: Option<NonZeroU32>,
+
+    /// Maximum number of bytes per second sent to the network. `None` for no limit.
+    pub max_upload_bytes_per_sec: Option<NonZeroU32>,
+
+    /// Maximum total number of bytes, sent and received, after which the chain no longer starts
+    /// requests and no longer connects to new peers. `None` for no limit.
+    pub max_total_bytes: Option<u64>,
+
+    /// Maximum number of peers that the chain maintains gossip links with, including both the
+    /// peers that the chain has connected to and the peers that have connected to the chain.
+    /// `None` for no limit.
+    pub max_peer_slots: Option<u32>,
+}
+
+/// Networking resources currently used by a chain. See [`NetworkServiceChain::usage`].
+#[derive(Debug, Clone)]
+pub struct ChainNetworkUsage {
+    /// Total number of bytes received on the connections of the chain.
+    pub downloaded_bytes: u64,
+
+    /// Total number of bytes sent on the connections of the chain.
+    pub uploaded_bytes: u64,
+
+    /// Number of peers that the chain currently maintains gossip links with.
+    pub peer_slots: usize,
+
+    /// Value that was passed through [`ChainNetworkBudget::max_total_bytes`].
+    pub max_total_bytes: Option<u64>,
+
+    /// Maximum number of peers that the chain can maintain gossip links with.
+    pub max_peer_slots: usize,
 }
 
 pub struct NetworkService<TPlat: PlatformRef> {
@@ -200,6 +259,8 @@ impl<TPlat: PlatformRef> NetworkService<TPlat> {
             storage_proof_requests: HashMap::with_capacity_and_hasher(8, Default::default()),
             call_proof_requests: HashMap::with_capacity_and_hasher(8, Default::default()),
             chains_by_next_discovery: BTreeMap::new(),
+            chains_by_budget_refill: BTreeMap::new(),
+            requests_ready: VecDeque::new(),
         }));
 
         config.platform.spawn_task("network-service".into(), {
@@ -241,12 +302,32 @@ impl<TPlat: PlatformRef> NetworkService<TPlat> {
                 genesis_hash: config.genesis_block_hash,
                 role: Role::Light,
                 allow_inbound_block_requests: false,
+                allow_inbound_light_requests: false,
                 user_data: Chain {
                     log_name: config.log_name,
                     block_number_bytes: config.block_number_bytes,
-                    num_out_slots: config.num_out_slots,
+                    num_out_slots: cmp::min(
+                        config.num_out_slots,
+                        config
+                            .budget
+                            .max_peer_slots
+                            .map_or(usize::MAX, |n| usize::try_from(n).unwrap_or(usize::MAX)),
+                    ),
+                    max_peer_slots: config
+                        .budget
+                        .max_peer_slots
+                        .map_or(usize::MAX, |n| usize::try_from(n).unwrap_or(usize::MAX)),
+                    max_total_bytes: config.budget.max_total_bytes,
+                    bandwidth: budget::ChainBandwidth::new(&config.budget, self.platform.now()),
+                    requests_awaiting_budget: VecDeque::new(),
+                    budget_refill_when: None,
                     num_references: NonZeroUsize::new(1).unwrap(),
                     next_discovery_period: Duration::from_secs(2),
+                    peers_reputation: HashMap::with_capacity_and_hasher(0, Default::default()),
+                    peers_discovery_origin: HashMap::with_capacity_and_hasher(
+                        0,
+                        Default::default(),
+                    ),
                     next_discovery_when: self.platform.now(),
                 },
             };
@@ -265,7 +346,7 @@ impl<TPlat: PlatformRef> NetworkService<TPlat> {
         Arc::new(NetworkServiceChain {
             _keep_alive_messages_tx: self.messages_tx.clone(),
             messages_tx,
-            marker: core::marker::PhantomData,
+            platform: self.platform.clone(),
         })
     }
 }
@@ -278,8 +359,8 @@ pub struct NetworkServiceChain<TPlat: PlatformRef> {
     /// Channel to send messages to the background task.
     messages_tx: async_channel::Sender<ToBackgroundChain>,
 
-    /// Dummy to hold the `TPlat` type.
-    marker: core::marker::PhantomData<TPlat>,
+    /// See [`Config::platform`].
+    platform: TPlat,
 }
 
 /// Severity of a ban. See [`NetworkServiceChain::ban_and_disconnect`].
@@ -289,6 +370,16 @@ pub enum BanSeverity {
     High,
 }
 
+/// Minimum value of the reputation of a peer. See [`NetworkServiceChain::peers_reputation`].
+pub const MIN_REPUTATION: i32 = -100;
+
+/// Maximum value of the reputation of a peer. See [`NetworkServiceChain::peers_reputation`].
+pub const MAX_REPUTATION: i32 = 100;
+
+/// Maximum number of peers per chain whose reputation is tracked. Peers past this limit keep a
+/// reputation of 0.
+const MAX_TRACKED_REPUTATIONS: usize = 1024;
+
 impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
     /// Subscribes to the networking events that happen on the given chain.
     ///
@@ -368,7 +459,11 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .await
             .unwrap();
 
-        rx.await.unwrap()
+        let result = rx.await.unwrap();
+        if matches!(result, Err(BlocksRequestError::BudgetExhausted)) {
+            self.platform.sleep(timeout).await;
+        }
+        result
     }
 
     /// Sends a grandpa warp sync request to the given peer.
@@ -391,7 +486,11 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .await
             .unwrap();
 
-        rx.await.unwrap()
+        let result = rx.await.unwrap();
+        if matches!(result, Err(WarpSyncRequestError::BudgetExhausted)) {
+            self.platform.sleep(timeout).await;
+        }
+        result
     }
 
     pub async fn set_local_best_block(&self, best_hash: [u8; 32], best_number: u64) {
@@ -438,7 +537,11 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .await
             .unwrap();
 
-        rx.await.unwrap()
+        let result = rx.await.unwrap();
+        if matches!(result, Err(StorageProofRequestError::BudgetExhausted)) {
+            self.platform.sleep(timeout).await;
+        }
+        result
     }
 
     /// Sends a call proof request to the given peer.
@@ -471,24 +574,35 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .await
             .unwrap();
 
-        rx.await.unwrap()
+        let result = rx.await.unwrap();
+        if matches!(result, Err(CallProofRequestError::BudgetExhausted)) {
+            self.platform.sleep(timeout).await;
+        }
+        result
     }
 
-    /// Announces transaction to the peers we are connected to.
+    /// Announces transaction to the peers we are connected to, with the exception of the peers
+    /// in `excluded_peers`.
     ///
     /// Returns a list of peers that we have sent the transaction to. Can return an empty `Vec`
-    /// if we didn't send the transaction to any peer.
+    /// if we didn't send the transaction to any peer. Peers whose queue of outgoing
+    /// notifications is full aren't included in the list.
     ///
     /// Note that the remote doesn't confirm that it has received the transaction. Because
     /// networking is inherently unreliable, successfully sending a transaction to a peer doesn't
     /// necessarily mean that the remote has received it. In practice, however, the likelihood of
     /// a transaction not being received are extremely low. This can be considered as known flaw.
-    pub async fn announce_transaction(self: Arc<Self>, transaction: &[u8]) -> Vec<PeerId> {
+    pub async fn announce_transaction(
+        self: Arc<Self>,
+        transaction: &[u8],
+        excluded_peers: &[PeerId],
+    ) -> Vec<PeerId> {
         let (tx, rx) = oneshot::channel();
 
         self.messages_tx
             .send(ToBackgroundChain::AnnounceTransaction {
                 transaction: transaction.to_vec(), // TODO: ovheread
+                excluded_peers: excluded_peers.to_vec(),
                 result: tx,
             })
             .await
@@ -567,6 +681,35 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .map(|(peer_id, addrs)| (peer_id, addrs.into_iter()))
     }
 
+    /// Returns the reputation of the peers of this chain whose reputation isn't 0.
+    ///
+    /// The reputation of a peer increases every time a gossip link is successfully opened with
+    /// it, and decreases every time it is banned through
+    /// [`NetworkServiceChain::ban_and_disconnect`] or fails to open a gossip link. It is clamped
+    /// between [`MIN_REPUTATION`] and [`MAX_REPUTATION`]. Peers with a higher reputation are
+    /// preferred when choosing which peers to open gossip links with.
+    pub async fn peers_reputation(&self) -> impl Iterator<Item = (PeerId, i32)> {
+        let (tx, rx) = oneshot::channel();
+        self.messages_tx
+            .send(ToBackgroundChain::PeersReputation { result: tx })
+            .await
+            .unwrap();
+        rx.await.unwrap().into_iter()
+    }
+
+    /// Overwrites the reputation of the given peers. Typically used in order to restore the
+    /// values previously returned by [`NetworkServiceChain::peers_reputation`].
+    ///
+    /// Values are clamped between [`MIN_REPUTATION`] and [`MAX_REPUTATION`].
+    pub async fn set_peers_reputation(&self, list: impl IntoIterator<Item = (PeerId, i32)>) {
+        let _ = self
+            .messages_tx
+            .send(ToBackgroundChain::SetPeersReputation {
+                list: list.into_iter().collect(),
+            })
+            .await;
+    }
+
     /// Returns an iterator to the list of [`PeerId`]s that we have an established connection
     /// with.
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
@@ -577,6 +720,17 @@ impl<TPlat: PlatformRef> NetworkServiceChain<TPlat> {
             .unwrap();
         rx.await.unwrap().into_iter()
     }
+
+    /// Returns the networking resources currently used by this chain, to be compared with the
+    /// limits passed through [`ConfigChain::budget`].
+    pub async fn usage(&self) -> ChainNetworkUsage {
+        let (tx, rx) = oneshot::channel();
+        self.messages_tx
+            .send(ToBackgroundChain::Usage { result: tx })
+            .await
+            .unwrap();
+        rx.await.unwrap()
+    }
 }
 
 /// Event that can happen on the network service.
@@ -587,6 +741,11 @@ pub enum Event {
         role: Role,
         best_block_number: u64,
         best_block_hash: [u8; 32],
+        /// Bootstrap node through which this peer has been discovered, either directly or
+        /// indirectly. Equal to `peer_id` if the peer is itself a bootstrap node. `None` if
+        /// unknown, for example if the peer has been added through
+        /// [`NetworkServiceChain::discover`] without being a bootstrap node.
+        discovery_origin: Option<PeerId>,
     },
     Disconnected {
         peer_id: PeerId,
@@ -611,6 +770,10 @@ pub enum Event {
 pub enum BlocksRequestError {
     /// No established connection with the target.
     NoConnection,
+    /// The chain has reached [`ChainNetworkBudget::max_total_bytes`]. This error is returned
+    /// only after the timeout of the request has elapsed, so that retrying the request straight
+    /// away doesn't lead to a busy loop.
+    BudgetExhausted,
     /// Error during the request.
     #[display(fmt = "{_0}")]
     Request(service::BlocksRequestError),
@@ -621,6 +784,10 @@ pub enum BlocksRequestError {
 pub enum WarpSyncRequestError {
     /// No established connection with the target.
     NoConnection,
+    /// The chain has reached [`ChainNetworkBudget::max_total_bytes`]. This error is returned
+    /// only after the timeout of the request has elapsed, so that retrying the request straight
+    /// away doesn't lead to a busy loop.
+    BudgetExhausted,
     /// Error during the request.
     #[display(fmt = "{_0}")]
     Request(service::GrandpaWarpSyncRequestError),
@@ -631,6 +798,10 @@ pub enum WarpSyncRequestError {
 pub enum StorageProofRequestError {
     /// No established connection with the target.
     NoConnection,
+    /// The chain has reached [`ChainNetworkBudget::max_total_bytes`]. This error is returned
+    /// only after the timeout of the request has elapsed, so that retrying the request straight
+    /// away doesn't lead to a busy loop.
+    BudgetExhausted,
     /// Storage proof request is too large and can't be sent.
     RequestTooLarge,
     /// Error during the request.
@@ -643,6 +814,10 @@ pub enum StorageProofRequestError {
 pub enum CallProofRequestError {
     /// No established connection with the target.
     NoConnection,
+    /// The chain has reached [`ChainNetworkBudget::max_total_bytes`]. This error is returned
+    /// only after the timeout of the request has elapsed, so that retrying the request straight
+    /// away doesn't lead to a busy loop.
+    BudgetExhausted,
     /// Call proof request is too large and can't be sent.
     RequestTooLarge,
     /// Error during the request.
@@ -658,6 +833,7 @@ impl CallProofRequestError {
             CallProofRequestError::Request(err) => err.is_network_problem(),
             CallProofRequestError::RequestTooLarge => false,
             CallProofRequestError::NoConnection => true,
+            CallProofRequestError::BudgetExhausted => true,
         }
     }
 }
@@ -717,6 +893,7 @@ enum ToBackgroundChain {
     },
     AnnounceTransaction {
         transaction: Vec<u8>,
+        excluded_peers: Vec<PeerId>,
         result: oneshot::Sender<Vec<PeerId>>,
     },
     SendBlockAnnounce {
@@ -735,6 +912,15 @@ enum ToBackgroundChain {
     PeersList {
         result: oneshot::Sender<Vec<PeerId>>,
     },
+    Usage {
+        result: oneshot::Sender<ChainNetworkUsage>,
+    },
+    PeersReputation {
+        result: oneshot::Sender<Vec<(PeerId, i32)>>,
+    },
+    SetPeersReputation {
+        list: Vec<(PeerId, i32)>,
+    },
 }
 
 struct BackgroundTask<TPlat: PlatformRef> {
@@ -836,6 +1022,13 @@ struct BackgroundTask<TPlat: PlatformRef> {
 
     /// All chains, indexed by the value of [`Chain::next_discovery_when`].
     chains_by_next_discovery: BTreeMap<(TPlat::Instant, ChainId), Pin<Box<TPlat::Delay>>>,
+
+    /// All chains whose [`Chain::budget_refill_when`] is `Some`, indexed by this value.
+    chains_by_budget_refill: BTreeMap<(TPlat::Instant, ChainId), Pin<Box<TPlat::Delay>>>,
+
+    /// Requests whose chain's rate limits might now allow them to start. Processed before the
+    /// other messages.
+    requests_ready: VecDeque<(ChainId, ToBackgroundChain)>,
 }
 
 struct Chain<TPlat: PlatformRef> {
@@ -848,20 +1041,47 @@ struct Chain<TPlat: PlatformRef> {
     // TODO: redundant with ChainNetwork? since we might not need to know this in the future i'm reluctant to add a getter to ChainNetwork
     block_number_bytes: usize,
 
-    /// See [`ConfigChain::num_out_slots`].
+    /// See [`ConfigChain::num_out_slots`]. Capped to [`ChainNetworkBudget::max_peer_slots`].
     num_out_slots: usize,
 
+    /// See [`ChainNetworkBudget::max_peer_slots`]. `usize::MAX` if no limit.
+    max_peer_slots: usize,
+
+    /// See [`ChainNetworkBudget::max_total_bytes`].
+    max_total_bytes: Option<u64>,
+
+    /// Bandwidth used by the requests and notifications of the chain.
+    bandwidth: budget::ChainBandwidth<TPlat::Instant>,
+
+    /// Requests that can't start yet because of the rate limits of the chain, in the order in
+    /// which they have been received.
+    requests_awaiting_budget: VecDeque<ToBackgroundChain>,
+
+    /// When the rate limits of the chain allow the first request of
+    /// [`Chain::requests_awaiting_budget`] to start. `Some` if and only if the chain is in
+    /// [`BackgroundTask::chains_by_budget_refill`].
+    budget_refill_when: Option<TPlat::Instant>,
+
     /// When the next discovery should be started for this chain.
     next_discovery_when: TPlat::Instant,
 
     /// After [`Chain::next_discovery_when`] is reached, the following discovery happens after
     /// the given duration.
     next_discovery_period: Duration,
+
+    /// Reputation of the peers of this chain. Peers that aren't in this list have a reputation
+    /// of 0. See [`NetworkServiceChain::peers_reputation`].
+    peers_reputation: HashMap<PeerId, i32, fnv::FnvBuildHasher>,
+
+    /// For each peer of the address book of this chain, the bootstrap node through which it has
+    /// been discovered. See [`Event::Connected::discovery_origin`].
+    peers_discovery_origin: HashMap<PeerId, PeerId, fnv::FnvBuildHasher>,
 }
 
 #[derive(Clone)]
 struct OpenGossipLinkState {
     role: Role,
+    discovery_origin: Option<PeerId>,
     best_block_number: u64,
     best_block_hash: [u8; 32],
     /// `None` if unknown.
@@ -892,6 +1112,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
             },
             EventSendersReady,
             StartDiscovery(ChainId),
+            BudgetRefilled(ChainId),
         }
 
         let wake_up_reason = {
@@ -902,6 +1123,10 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     .map_or(WakeUpReason::ForegroundClosed, WakeUpReason::Message)
             };
             let message_for_chain_received = async {
+                if let Some((chain_id, message)) = task.requests_ready.pop_front() {
+                    return WakeUpReason::MessageForChain(chain_id, message);
+                }
+
                 // Note that when the last entry of `messages_rx` yields `None`, `messages_rx`
                 // itself will yield `None`. For this reason, we can't use
                 // `task.messages_rx.is_empty()` to determine whether `messages_rx` will
@@ -966,10 +1191,19 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                                 continue;
                             }
 
-                            match task
-                                .peering_strategy
-                                .pick_assignable_peer(&chain_id, &task.platform.now())
-                            {
+                            // Chains that have used up their bandwidth budget don't connect to
+                            // new peers.
+                            if task.network[chain_id].bandwidth.is_exhausted() {
+                                continue;
+                            }
+
+                            // Peers with a higher reputation are preferred.
+                            let peers_reputation = &task.network[chain_id].peers_reputation;
+                            match task.peering_strategy.pick_assignable_peer_by_score(
+                                &chain_id,
+                                &task.platform.now(),
+                                |peer_id| peers_reputation.get(peer_id).copied().unwrap_or(0),
+                            ) {
                                 basic_peering_strategy::AssignablePeer::Assignable(peer_id) => {
                                     break 'search WakeUpReason::CanAssignSlot(
                                         peer_id.clone(),
@@ -1033,6 +1267,14 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 let ((_, chain_id), _) = next_discovery.remove_entry();
                 WakeUpReason::StartDiscovery(chain_id)
             };
+            let budget_refilled = async {
+                let Some(mut next_refill) = task.chains_by_budget_refill.first_entry() else {
+                    future::pending().await
+                };
+                next_refill.get_mut().await;
+                let ((_, chain_id), _) = next_refill.remove_entry();
+                WakeUpReason::BudgetRefilled(chain_id)
+            };
 
             message_for_chain_received
                 .or(message_received)
@@ -1041,6 +1283,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 .or(next_recent_connection_restore)
                 .or(finished_sending_event)
                 .or(start_discovery)
+                .or(budget_refilled)
                 .await
         };
 
@@ -1138,6 +1381,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                                         role: state.role,
                                         best_block_number: state.best_block_number,
                                         best_block_hash: state.best_block_hash,
+                                        discovery_origin: state.discovery_origin.clone(),
                                     })
                                     .await;
 
@@ -1196,6 +1440,13 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     .remove(&(task.network[chain_id].next_discovery_when.clone(), chain_id));
                 debug_assert!(_was_in.is_some());
 
+                if let Some(when) = task.network[chain_id].budget_refill_when.take() {
+                    let _was_in = task.chains_by_budget_refill.remove(&(when, chain_id));
+                    debug_assert!(_was_in.is_some());
+                }
+                task.requests_ready
+                    .retain(|(request_chain_id, _)| *request_chain_id != chain_id);
+
                 log!(
                     &task.platform,
                     Debug,
@@ -1222,6 +1473,15 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     BanSeverity::High => 40,
                 });
 
+                adjust_reputation(
+                    &mut task.network[chain_id],
+                    &peer_id,
+                    match severity {
+                        BanSeverity::Low => -10,
+                        BanSeverity::High => -40,
+                    },
+                );
+
                 let had_slot = matches!(
                     task.peering_strategy.unassign_slot_and_ban(
                         &chain_id,
@@ -1278,6 +1538,89 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     task.event_pending_send = Some((chain_id, Event::Disconnected { peer_id }));
                 }
             }
+            WakeUpReason::MessageForChain(
+                chain_id,
+                message @ (ToBackgroundChain::StartBlocksRequest { .. }
+                | ToBackgroundChain::StartWarpSyncRequest { .. }
+                | ToBackgroundChain::StartStorageProofRequest { .. }
+                | ToBackgroundChain::StartCallProofRequest { .. }),
+            ) if task.network[chain_id].bandwidth.is_exhausted() => {
+                log!(
+                    &task.platform,
+                    Debug,
+                    "network",
+                    "request-error",
+                    chain = task.network[chain_id].log_name,
+                    error = "BudgetExhausted"
+                );
+
+                match message {
+                    ToBackgroundChain::StartBlocksRequest { result, .. } => {
+                        let _ = result.send(Err(BlocksRequestError::BudgetExhausted));
+                    }
+                    ToBackgroundChain::StartWarpSyncRequest { result, .. } => {
+                        let _ = result.send(Err(WarpSyncRequestError::BudgetExhausted));
+                    }
+                    ToBackgroundChain::StartStorageProofRequest { result, .. } => {
+                        let _ = result.send(Err(StorageProofRequestError::BudgetExhausted));
+                    }
+                    ToBackgroundChain::StartCallProofRequest { result, .. } => {
+                        let _ = result.send(Err(CallProofRequestError::BudgetExhausted));
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            WakeUpReason::MessageForChain(
+                chain_id,
+                message @ (ToBackgroundChain::StartBlocksRequest { .. }
+                | ToBackgroundChain::StartWarpSyncRequest { .. }
+                | ToBackgroundChain::StartStorageProofRequest { .. }
+                | ToBackgroundChain::StartCallProofRequest { .. }),
+            ) if task.network[chain_id].budget_refill_when.is_some()
+                || task.network[chain_id]
+                    .bandwidth
+                    .next_request_allowed(&task.platform.now())
+                    .is_some() =>
+            {
+                // The rate limits of the chain don't allow starting the request now. It is queued
+                // until the chain's token buckets have been refilled.
+                let now = task.platform.now();
+                let chain = &mut task.network[chain_id];
+
+                if chain.budget_refill_when.is_some() {
+                    chain.requests_awaiting_budget.push_back(message);
+                } else if let Some(when) = chain.bandwidth.next_request_allowed(&now) {
+                    chain.requests_awaiting_budget.push_back(message);
+                    log!(
+                        &task.platform,
+                        Debug,
+                        "network",
+                        "requests-delayed-by-budget",
+                        chain = chain.log_name,
+                        delay = ?(when.clone() - now)
+                    );
+                    chain.budget_refill_when = Some(when.clone());
+                    task.chains_by_budget_refill.insert(
+                        (when.clone(), chain_id),
+                        Box::pin(task.platform.sleep_until(when)),
+                    );
+                } else {
+                    // The debt has been paid back between the moment when the rate limits have
+                    // been checked above and now. Try again to start the request.
+                    task.requests_ready.push_front((chain_id, message));
+                }
+            }
+            WakeUpReason::BudgetRefilled(chain_id) => {
+                // Try again to start the requests that were waiting. Requests that still can't
+                // start are queued again.
+                let chain = &mut task.network[chain_id];
+                chain.budget_refill_when = None;
+                task.requests_ready.extend(
+                    mem::take(&mut chain.requests_awaiting_budget)
+                        .into_iter()
+                        .map(|message| (chain_id, message)),
+                );
+            }
             WakeUpReason::MessageForChain(
                 chain_id,
                 ToBackgroundChain::StartBlocksRequest {
@@ -1316,11 +1659,18 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     }
                 }
 
+                let request_size =
+                    codec::build_block_request(task.network[chain_id].block_number_bytes, &config)
+                        .fold(0, |sum, buf| sum + buf.as_ref().len());
+
                 match task
                     .network
                     .start_blocks_request(&target, chain_id, config.clone(), timeout)
                 {
                     Ok(substream_id) => {
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_upload(request_size, &task.platform.now());
                         task.blocks_requests.insert(substream_id, result);
                     }
                     Err(service::StartRequestError::NoConnection) => {
@@ -1361,6 +1711,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     .start_grandpa_warp_sync_request(&target, chain_id, begin_hash, timeout)
                 {
                     Ok(substream_id) => {
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_upload(begin_hash.len(), &task.platform.now());
                         task.grandpa_warp_sync_requests.insert(substream_id, result);
                     }
                     Err(service::StartRequestError::NoConnection) => {
@@ -1396,6 +1749,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     block_hash = HashDisplay(&config.block_hash)
                 );
 
+                let request_size = codec::build_storage_proof_request(config.clone())
+                    .fold(0, |sum, buf| sum + buf.as_ref().len());
+
                 match task.network.start_storage_proof_request(
                     &target,
                     chain_id,
@@ -1403,6 +1759,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     timeout,
                 ) {
                     Ok(substream_id) => {
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_upload(request_size, &task.platform.now());
                         task.storage_proof_requests.insert(substream_id, result);
                     }
                     Err(service::StartRequestMaybeTooLargeError::NoConnection) => {
@@ -1452,6 +1811,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 );
                 // TODO: log parameter
 
+                let request_size = codec::build_call_proof_request(config.clone())
+                    .fold(0, |sum, buf| sum + buf.as_ref().len());
+
                 match task.network.start_call_proof_request(
                     &target,
                     chain_id,
@@ -1459,6 +1821,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     timeout,
                 ) {
                     Ok(substream_id) => {
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_upload(request_size, &task.platform.now());
                         task.call_proof_requests.insert(substream_id, result);
                     }
                     Err(service::StartRequestMaybeTooLargeError::NoConnection) => {
@@ -1520,14 +1885,16 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 chain_id,
                 ToBackgroundChain::AnnounceTransaction {
                     transaction,
+                    excluded_peers,
                     result,
                 },
             ) => {
-                // TODO: keep track of which peer knows about which transaction, and don't send it again
-
+                // Keeping track of which peer knows about which transaction is the
+                // responsibility of the caller.
                 let peers_to_send = task
                     .network
                     .gossip_connected_peers(chain_id, service::GossipKind::ConsensusTransactions)
+                    .filter(|peer| !excluded_peers.contains(peer))
                     .cloned()
                     .collect::<Vec<_>>();
 
@@ -1538,7 +1905,12 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                         .network
                         .gossip_send_transaction(peer, chain_id, &transaction)
                     {
-                        Ok(()) => peers_sent.push(peer.to_base58()),
+                        Ok(()) => {
+                            task.network[chain_id]
+                                .bandwidth
+                                .record_upload(transaction.len(), &task.platform.now());
+                            peers_sent.push(peer.clone())
+                        }
                         Err(QueueNotificationError::QueueFull) => {
                             peers_queue_full.push(peer.to_base58())
                         }
@@ -1555,11 +1927,11 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     transaction =
                         hex::encode(blake2_rfc::blake2b::blake2b(32, &[], &transaction).as_bytes()),
                     size = transaction.len(),
-                    peers_sent = peers_sent.join(", "),
+                    peers_sent = peers_sent.iter().join(", "),
                     peers_queue_full = peers_queue_full.join(", "),
                 );
 
-                let _ = result.send(peers_to_send);
+                let _ = result.send(peers_sent);
             }
             WakeUpReason::MessageForChain(
                 chain_id,
@@ -1571,12 +1943,18 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 },
             ) => {
                 // TODO: log who the announce was sent to
-                let _ = result.send(task.network.gossip_send_block_announce(
+                let outcome = task.network.gossip_send_block_announce(
                     &target,
                     chain_id,
                     &scale_encoded_header,
                     is_best,
-                ));
+                );
+                if outcome.is_ok() {
+                    task.network[chain_id]
+                        .bandwidth
+                        .record_upload(scale_encoded_header.len(), &task.platform.now());
+                }
+                let _ = result.send(outcome);
             }
             WakeUpReason::MessageForChain(
                 chain_id,
@@ -1588,6 +1966,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 for (peer_id, addrs) in list {
                     if important_nodes {
                         task.important_nodes.insert(peer_id.clone());
+                        task.network[chain_id]
+                            .peers_discovery_origin
+                            .insert(peer_id.clone(), peer_id.clone());
                     }
 
                     // Note that we must call this function before `insert_address`, as documented
@@ -1622,6 +2003,28 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                         .collect::<Vec<_>>(),
                 );
             }
+            WakeUpReason::MessageForChain(
+                chain_id,
+                ToBackgroundChain::PeersReputation { result },
+            ) => {
+                let _ = result.send(
+                    task.network[chain_id]
+                        .peers_reputation
+                        .iter()
+                        .map(|(peer_id, reputation)| (peer_id.clone(), *reputation))
+                        .collect(),
+                );
+            }
+            WakeUpReason::MessageForChain(
+                chain_id,
+                ToBackgroundChain::SetPeersReputation { list },
+            ) => {
+                let chain = &mut task.network[chain_id];
+                for (peer_id, reputation) in list {
+                    chain.peers_reputation.remove(&peer_id);
+                    adjust_reputation(chain, &peer_id, reputation);
+                }
+            }
             WakeUpReason::MessageForChain(chain_id, ToBackgroundChain::PeersList { result }) => {
                 let _ = result.send(
                     task.network
@@ -1633,6 +2036,22 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                         .collect(),
                 );
             }
+            WakeUpReason::MessageForChain(chain_id, ToBackgroundChain::Usage { result }) => {
+                let chain = &task.network[chain_id];
+                let _ = result.send(ChainNetworkUsage {
+                    downloaded_bytes: chain.bandwidth.downloaded_bytes(),
+                    uploaded_bytes: chain.bandwidth.uploaded_bytes(),
+                    peer_slots: task
+                        .network
+                        .gossip_connected_peers(
+                            chain_id,
+                            service::GossipKind::ConsensusTransactions,
+                        )
+                        .count(),
+                    max_total_bytes: chain.max_total_bytes,
+                    max_peer_slots: chain.max_peer_slots,
+                });
+            }
             WakeUpReason::StartDiscovery(chain_id) => {
                 // Re-insert the chain in `chains_by_next_discovery`.
                 let chain = &mut task.network[chain_id];
@@ -1842,6 +2261,10 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 );
 
                 let decoded_announce = announce.decode();
+                task.network[chain_id].bandwidth.record_download(
+                    decoded_announce.scale_encoded_header.len(),
+                    &task.platform.now(),
+                );
                 if decoded_announce.is_best {
                     let link = task
                         .open_gossip_links
@@ -1881,12 +2304,20 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     best_hash = HashDisplay(&best_hash)
                 );
 
+                adjust_reputation(&mut task.network[chain_id], &peer_id, 1);
+
+                let discovery_origin = task.network[chain_id]
+                    .peers_discovery_origin
+                    .get(&peer_id)
+                    .cloned();
+
                 let _prev_value = task.open_gossip_links.insert(
                     (chain_id, peer_id.clone()),
                     OpenGossipLinkState {
                         best_block_number: best_number,
                         best_block_hash: best_hash,
                         role,
+                        discovery_origin: discovery_origin.clone(),
                         finalized_block_height: None,
                     },
                 );
@@ -1900,6 +2331,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                         role,
                         best_block_number: best_number,
                         best_block_hash: best_hash,
+                        discovery_origin,
                     },
                 ));
             }
@@ -1919,6 +2351,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     ?error,
                 );
                 let ban_duration = Duration::from_secs(15);
+                adjust_reputation(&mut task.network[chain_id], &peer_id, -5);
 
                 // Note that peer doesn't necessarily have an out slot, as this event might happen
                 // as a result of an inbound gossip connection.
@@ -2013,6 +2446,22 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
             }) => {
                 match &response {
                     Ok(blocks) => {
+                        let block_data_total_size = blocks.iter().fold(0, |sum, block| {
+                            sum + block.header.as_ref().map_or(0, |h| h.len())
+                                + block
+                                    .body
+                                    .as_ref()
+                                    .map_or(0, |b| b.iter().fold(0, |s, e| s + e.len()))
+                                + block
+                                    .justifications
+                                    .as_ref()
+                                    .into_iter()
+                                    .flat_map(|l| l.iter())
+                                    .fold(0, |s, j| s + j.justification.len())
+                        });
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_download(block_data_total_size, &task.platform.now());
                         log!(
                             &task.platform,
                             Debug,
@@ -2022,20 +2471,7 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                             target = peer_id,
                             num_blocks = blocks.len(),
                             block_data_total_size =
-                                BytesDisplay(blocks.iter().fold(0, |sum, block| {
-                                    let block_size = block.header.as_ref().map_or(0, |h| h.len())
-                                        + block
-                                            .body
-                                            .as_ref()
-                                            .map_or(0, |b| b.iter().fold(0, |s, e| s + e.len()))
-                                        + block
-                                            .justifications
-                                            .as_ref()
-                                            .into_iter()
-                                            .flat_map(|l| l.iter())
-                                            .fold(0, |s, j| s + j.justification.len());
-                                    sum + u64::try_from(block_size).unwrap()
-                                }))
+                                BytesDisplay(u64::try_from(block_data_total_size).unwrap())
                         );
                     }
                     Err(error) => {
@@ -2083,6 +2519,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 match &response {
                     Ok(response) => {
                         // TODO: print total bytes size
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_download(response.as_encoded().len(), &task.platform.now());
                         let decoded = response.decode();
                         log!(
                             &task.platform,
@@ -2123,6 +2562,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 match &response {
                     Ok(items) => {
                         let decoded = items.decode();
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_download(decoded.len(), &task.platform.now());
                         log!(
                             &task.platform,
                             Debug,
@@ -2161,6 +2603,9 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                 match &response {
                     Ok(items) => {
                         let decoded = items.decode();
+                        task.network[chain_id]
+                            .bandwidth
+                            .record_download(decoded.len(), &task.platform.now());
                         log!(
                             &task.platform,
                             Debug,
@@ -2254,7 +2699,22 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                             peer_removed,
                         } = insert_outcome
                         {
+                            // The newly-discovered peer inherits the discovery origin of the
+                            // peer that has told us about it.
+                            let chain = &mut task.network[chain_id];
+                            if let Some(origin) = chain
+                                .peers_discovery_origin
+                                .get(&requestee_peer_id)
+                                .cloned()
+                            {
+                                chain
+                                    .peers_discovery_origin
+                                    .entry(peer_id.clone())
+                                    .or_insert(origin);
+                            }
+
                             if let Some(peer_removed) = peer_removed {
+                                chain.peers_discovery_origin.remove(&peer_removed);
                                 log!(
                                     &task.platform,
                                     Debug,
@@ -2361,6 +2821,15 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     .opened_gossip_undesired_by_chain(chain_id)
                     .count()
                     < 4
+                    && task
+                        .network
+                        .gossip_connected_peers(
+                            chain_id,
+                            service::GossipKind::ConsensusTransactions,
+                        )
+                        .count()
+                        < task.network[chain_id].max_peer_slots
+                    && !task.network[chain_id].bandwidth.is_exhausted()
                 {
                     log!(
                         &task.platform,
@@ -2416,6 +2885,10 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     .respond_identify(substream_id, &task.identify_agent_version);
             }
             WakeUpReason::NetworkEvent(service::Event::BlocksRequestIn { .. }) => unreachable!(),
+            WakeUpReason::NetworkEvent(service::Event::StorageProofRequestIn { .. })
+            | WakeUpReason::NetworkEvent(service::Event::CallProofRequestIn { .. }) => {
+                unreachable!()
+            }
             WakeUpReason::NetworkEvent(service::Event::RequestInCancel { .. }) => {
                 // All incoming requests are immediately answered.
                 unreachable!()
@@ -2466,6 +2939,10 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
                     target_block_hash = HashDisplay(message.decode().target_hash),
                 );
 
+                task.network[chain_id]
+                    .bandwidth
+                    .record_download(message.as_encoded().len(), &task.platform.now());
+
                 debug_assert!(task.event_pending_send.is_none());
                 task.event_pending_send =
                     Some((chain_id, Event::GrandpaCommitMessage { peer_id, message }));
@@ -2731,3 +3208,20 @@ async fn background_task<TPlat: PlatformRef>(mut task: BackgroundTask<TPlat>) {
         }
     }
 }
+
+/// Adds `delta` to the reputation of the given peer, clamping the result between
+/// [`MIN_REPUTATION`] and [`MAX_REPUTATION`].
+fn adjust_reputation<TPlat: PlatformRef>(chain: &mut Chain<TPlat>, peer_id: &PeerId, delta: i32) {
+    let current = chain.peers_reputation.get(peer_id).copied().unwrap_or(0);
+    let new_value = current
+        .saturating_add(delta)
+        .clamp(MIN_REPUTATION, MAX_REPUTATION);
+
+    if new_value == 0 {
+        chain.peers_reputation.remove(peer_id);
+    } else if let Some(value) = chain.peers_reputation.get_mut(peer_id) {
+        *value = new_value;
+    } else if chain.peers_reputation.len() < MAX_TRACKED_REPUTATIONS {
+        chain.peers_reputation.insert(peer_id.clone(), new_value);
+    }
+}
@@ -0,0 +1,293 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bandwidth budget of a single chain.
+//!
+//! The bytes of the requests and notifications of a chain are reported to its [`ChainBandwidth`]
+//! by the background task of the network service. Because accounting happens at the level of
+//! requests and notifications, a connection shared between multiple chains is never slowed
+//! down by the budget of one of these chains.
+//!
+//! The rate limits are enforced with token buckets. A bucket is allowed to go into debt, as the
+//! size of a response isn't known before it is received. New requests are started only once the
+//! debt has been paid back, and [`ChainBandwidth::next_request_allowed`] returns the moment when
+//! this happens.
+
+use super::ChainNetworkBudget;
+
+use core::{num::NonZeroU32, ops, time::Duration};
+
+pub(super) struct ChainBandwidth<TInstant> {
+    /// See [`ChainNetworkBudget::max_download_bytes_per_sec`].
+    max_download_bytes_per_sec: Option<NonZeroU32>,
+    /// See [`ChainNetworkBudget::max_upload_bytes_per_sec`].
+    max_upload_bytes_per_sec: Option<NonZeroU32>,
+    /// See [`ChainNetworkBudget::max_total_bytes`].
+    max_total_bytes: Option<u64>,
+    /// Total number of bytes received by this chain.
+    downloaded_bytes: u64,
+    /// Total number of bytes sent by this chain.
+    uploaded_bytes: u64,
+    /// Number of bytes that can still be downloaded before the rate limit kicks in. Negative if
+    /// more bytes than allowed have been downloaded.
+    download_tokens: i64,
+    /// Number of bytes that can still be uploaded before the rate limit kicks in. Negative if
+    /// more bytes than allowed have been uploaded.
+    upload_tokens: i64,
+    /// Millionths of a token that have been refilled into [`ChainBandwidth::download_tokens`]
+    /// but not credited yet, as tokens are credited as a whole.
+    download_tokens_remainder: u64,
+    /// Millionths of a token that have been refilled into [`ChainBandwidth::upload_tokens`]
+    /// but not credited yet, as tokens are credited as a whole.
+    upload_tokens_remainder: u64,
+    /// Last time [`ChainBandwidth::download_tokens`] and [`ChainBandwidth::upload_tokens`] have
+    /// been refilled.
+    last_refill: TInstant,
+}
+
+impl<TInstant> ChainBandwidth<TInstant>
+where
+    TInstant:
+        Clone + Ord + ops::Add<Duration, Output = TInstant> + ops::Sub<TInstant, Output = Duration>,
+{
+    /// Creates a new budget with all the rate limit tokens available.
+    pub(super) fn new(budget: &ChainNetworkBudget, now: TInstant) -> Self {
+        ChainBandwidth {
+            max_download_bytes_per_sec: budget.max_download_bytes_per_sec,
+            max_upload_bytes_per_sec: budget.max_upload_bytes_per_sec,
+            max_total_bytes: budget.max_total_bytes,
+            downloaded_bytes: 0,
+            uploaded_bytes: 0,
+            download_tokens: bucket_capacity(budget.max_download_bytes_per_sec),
+            upload_tokens: bucket_capacity(budget.max_upload_bytes_per_sec),
+            download_tokens_remainder: 0,
+            upload_tokens_remainder: 0,
+            last_refill: now,
+        }
+    }
+
+    /// Returns the total number of bytes received by this chain.
+    pub(super) fn downloaded_bytes(&self) -> u64 {
+        self.downloaded_bytes
+    }
+
+    /// Returns the total number of bytes sent by this chain.
+    pub(super) fn uploaded_bytes(&self) -> u64 {
+        self.uploaded_bytes
+    }
+
+    /// Returns `true` if [`ChainNetworkBudget::max_total_bytes`] has been reached.
+    pub(super) fn is_exhausted(&self) -> bool {
+        self.max_total_bytes
+            .is_some_and(|max| self.downloaded_bytes.saturating_add(self.uploaded_bytes) >= max)
+    }
+
+    /// Reports that the given number of bytes have been received.
+    pub(super) fn record_download(&mut self, bytes: usize, now: &TInstant) {
+        self.refill(now);
+        let bytes = u64::try_from(bytes).unwrap_or(u64::MAX);
+        self.downloaded_bytes = self.downloaded_bytes.saturating_add(bytes);
+        if self.max_download_bytes_per_sec.is_some() {
+            self.download_tokens = self
+                .download_tokens
+                .saturating_sub(i64::try_from(bytes).unwrap_or(i64::MAX));
+        }
+    }
+
+    /// Reports that the given number of bytes have been sent.
+    pub(super) fn record_upload(&mut self, bytes: usize, now: &TInstant) {
+        self.refill(now);
+        let bytes = u64::try_from(bytes).unwrap_or(u64::MAX);
+        self.uploaded_bytes = self.uploaded_bytes.saturating_add(bytes);
+        if self.max_upload_bytes_per_sec.is_some() {
+            self.upload_tokens = self
+                .upload_tokens
+                .saturating_sub(i64::try_from(bytes).unwrap_or(i64::MAX));
+        }
+    }
+
+    /// Returns `None` if the rate limits allow starting a new request, or the moment when they
+    /// will allow it.
+    ///
+    /// Doesn't take [`ChainNetworkBudget::max_total_bytes`] into account. Use
+    /// [`ChainBandwidth::is_exhausted`] for that.
+    pub(super) fn next_request_allowed(&mut self, now: &TInstant) -> Option<TInstant> {
+        self.refill(now);
+
+        let wait = [
+            (self.download_tokens, self.max_download_bytes_per_sec),
+            (self.upload_tokens, self.max_upload_bytes_per_sec),
+        ]
+        .into_iter()
+        .filter_map(|(tokens, rate)| {
+            let rate = rate?;
+            if tokens >= 0 {
+                return None;
+            }
+            let debt = tokens.unsigned_abs();
+            let micros = debt
+                .saturating_mul(1_000_000)
+                .div_ceil(u64::from(rate.get()));
+            Some(Duration::from_micros(micros))
+        })
+        .max()?;
+
+        Some(now.clone() + wait)
+    }
+
+    fn refill(&mut self, now: &TInstant) {
+        if *now <= self.last_refill {
+            return;
+        }
+
+        let elapsed_micros =
+            u64::try_from((now.clone() - self.last_refill.clone()).as_micros()).unwrap_or(u64::MAX);
+        self.last_refill = now.clone();
+
+        for (tokens, remainder, rate) in [
+            (
+                &mut self.download_tokens,
+                &mut self.download_tokens_remainder,
+                self.max_download_bytes_per_sec,
+            ),
+            (
+                &mut self.upload_tokens,
+                &mut self.upload_tokens_remainder,
+                self.max_upload_bytes_per_sec,
+            ),
+        ] {
+            let Some(rate) = rate else { continue };
+            // The fraction of token that can't be credited is carried over to the next refill,
+            // as frequent refills would otherwise never credit anything at low rates.
+            let refill_millionths = elapsed_micros
+                .saturating_mul(u64::from(rate.get()))
+                .saturating_add(*remainder);
+            *remainder = refill_millionths % 1_000_000;
+            *tokens = tokens
+                .saturating_add(i64::try_from(refill_millionths / 1_000_000).unwrap_or(i64::MAX));
+            if *tokens >= bucket_capacity(Some(rate)) {
+                *tokens = bucket_capacity(Some(rate));
+                *remainder = 0;
+            }
+        }
+    }
+}
+
+/// Number of tokens in a full token bucket.
+fn bucket_capacity(bytes_per_sec: Option<NonZeroU32>) -> i64 {
+    bytes_per_sec.map_or(i64::MAX, |rate| i64::from(rate.get()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChainBandwidth;
+    use crate::network_service::ChainNetworkBudget;
+    use core::{num::NonZeroU32, time::Duration};
+
+    #[test]
+    fn rate_limit_waits_for_refill() {
+        let mut budget = ChainBandwidth::new(
+            &ChainNetworkBudget {
+                max_download_bytes_per_sec: NonZeroU32::new(1000),
+                ..Default::default()
+            },
+            Duration::from_secs(0),
+        );
+
+        assert!(budget
+            .next_request_allowed(&Duration::from_secs(0))
+            .is_none());
+
+        // Going 500 bytes into debt requires waiting half a second.
+        budget.record_download(1500, &Duration::from_secs(0));
+        assert_eq!(
+            budget.next_request_allowed(&Duration::from_secs(0)),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(
+            budget.next_request_allowed(&Duration::from_millis(200)),
+            Some(Duration::from_millis(500))
+        );
+        assert!(budget
+            .next_request_allowed(&Duration::from_millis(500))
+            .is_none());
+
+        // Uploads aren't limited.
+        budget.record_upload(1_000_000, &Duration::from_millis(500));
+        assert!(budget
+            .next_request_allowed(&Duration::from_millis(500))
+            .is_none());
+        assert_eq!(budget.downloaded_bytes(), 1500);
+        assert_eq!(budget.uploaded_bytes(), 1_000_000);
+    }
+
+    #[test]
+    fn sub_token_refills_accumulate() {
+        let mut budget = ChainBandwidth::new(
+            &ChainNetworkBudget {
+                max_download_bytes_per_sec: NonZeroU32::new(10),
+                ..Default::default()
+            },
+            Duration::from_secs(0),
+        );
+
+        // Going 5 bytes into debt requires waiting half a second, even if the budget is checked
+        // more often than the time it takes to refill a single token.
+        budget.record_download(15, &Duration::from_secs(0));
+        let mut now = Duration::from_secs(0);
+        while budget.next_request_allowed(&now).is_some() {
+            now += Duration::from_millis(30);
+            assert!(now <= Duration::from_millis(510));
+        }
+        assert_eq!(now, Duration::from_millis(510));
+    }
+
+    #[test]
+    fn bucket_doesnt_exceed_capacity() {
+        let mut budget = ChainBandwidth::new(
+            &ChainNetworkBudget {
+                max_upload_bytes_per_sec: NonZeroU32::new(100),
+                ..Default::default()
+            },
+            Duration::from_secs(0),
+        );
+
+        // After a long idle period, only one second worth of bytes is available.
+        budget.record_upload(150, &Duration::from_secs(3600));
+        assert_eq!(
+            budget.next_request_allowed(&Duration::from_secs(3600)),
+            Some(Duration::from_millis(3_600_500))
+        );
+    }
+
+    #[test]
+    fn total_bytes_exhausted() {
+        let mut budget = ChainBandwidth::new(
+            &ChainNetworkBudget {
+                max_total_bytes: Some(10),
+                ..Default::default()
+            },
+            Duration::from_secs(0),
+        );
+        assert!(!budget.is_exhausted());
+
+        budget.record_download(6, &Duration::from_secs(0));
+        assert!(!budget.is_exhausted());
+        budget.record_upload(4, &Duration::from_secs(0));
+        assert!(budget.is_exhausted());
+    }
+}
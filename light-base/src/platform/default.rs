@@ -26,6 +26,12 @@
 //! messages, you should register as "logger" as documented by the `log` crate.
 //! See <https://docs.rs/log>.
 //!
+//! Secure WebSocket connections are encrypted using `rustls`. By default, the certificates of
+//! the remotes are verified against the Mozilla root certificates. Use
+//! [`DefaultPlatform::with_tls_root_certificates`] in order to use different root certificates.
+//!
+//! WebRTC-direct connections are implemented using the `str0m` library.
+//!
 //! # Example
 //!
 //! ```rust
@@ -49,6 +55,7 @@ use core::{
     str,
     time::Duration,
 };
+use futures_rustls::rustls;
 use futures_util::{future, FutureExt as _};
 use smoldot::libp2p::websocket;
 use std::{
@@ -58,12 +65,19 @@ use std::{
     time::{Instant, UNIX_EPOCH},
 };
 
+mod webrtc;
+
 /// Implementation of the [`PlatformRef`] trait that leverages the operating system.
 pub struct DefaultPlatform {
     client_name: String,
     client_version: String,
     tasks_executor: Arc<smol::Executor<'static>>,
     shutdown_notify: event_listener::Event,
+    /// Configuration used when opening secure WebSocket connections.
+    ///
+    /// The `PlatformRef` trait requires unwind safety. The configuration is never modified after
+    /// having been built, and thus can't be observed in an inconsistent state after a panic.
+    tls_client_config: panic::AssertUnwindSafe<Arc<rustls::ClientConfig>>,
 }
 
 impl DefaultPlatform {
@@ -81,6 +95,53 @@ impl DefaultPlatform {
     /// Panics if it wasn't possible to spawn background threads.
     ///
     pub fn new(client_name: String, client_version: String) -> Arc<Self> {
+        let root_certificates = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::new_inner(client_name, client_version, root_certificates)
+    }
+
+    /// Creates a new [`DefaultPlatform`] that verifies the certificates of secure WebSocket
+    /// remotes against the given list of DER-encoded root certificates rather than against the
+    /// Mozilla root certificates.
+    ///
+    /// Certificates that fail to parse are ignored.
+    ///
+    /// See also [`DefaultPlatform::new`].
+    ///
+    /// # Panic
+    ///
+    /// Panics if it wasn't possible to spawn background threads.
+    ///
+    pub fn with_tls_root_certificates(
+        client_name: String,
+        client_version: String,
+        root_certificates: impl IntoIterator<Item = Vec<u8>>,
+    ) -> Arc<Self> {
+        let mut root_certificates_store = rustls::RootCertStore::empty();
+        root_certificates_store.add_parsable_certificates(
+            root_certificates
+                .into_iter()
+                .map(rustls::pki_types::CertificateDer::from),
+        );
+        Self::new_inner(client_name, client_version, root_certificates_store)
+    }
+
+    fn new_inner(
+        client_name: String,
+        client_version: String,
+        root_certificates: rustls::RootCertStore,
+    ) -> Arc<Self> {
+        let tls_client_config = Arc::new(
+            rustls::ClientConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap_or_else(|_| unreachable!())
+            .with_root_certificates(root_certificates)
+            .with_no_client_auth(),
+        );
+
         let tasks_executor = Arc::new(smol::Executor::new());
         let shutdown_notify = event_listener::Event::new();
 
@@ -107,6 +168,7 @@ impl DefaultPlatform {
             client_version,
             tasks_executor,
             shutdown_notify,
+            tls_client_config: panic::AssertUnwindSafe(tls_client_config),
         })
     }
 }
@@ -114,14 +176,15 @@ impl DefaultPlatform {
 impl PlatformRef for Arc<DefaultPlatform> {
     type Delay = futures_util::future::Map<smol::Timer, fn(Instant) -> ()>;
     type Instant = Instant;
-    type MultiStream = std::convert::Infallible; // TODO: replace with `!` once stable: https://github.com/rust-lang/rust/issues/35121
+    type MultiStream = webrtc::Connection;
     type Stream = Stream;
     type StreamConnectFuture = future::Ready<Self::Stream>;
-    type MultiStreamConnectFuture = future::Pending<MultiStreamWebRtcConnection<Self::MultiStream>>;
+    type MultiStreamConnectFuture = future::Ready<MultiStreamWebRtcConnection<Self::MultiStream>>;
     type ReadWriteAccess<'a> = with_buffers::ReadWriteAccess<'a, Instant>;
     type StreamUpdateFuture<'a> = future::BoxFuture<'a, ()>;
     type StreamErrorRef<'a> = &'a io::Error;
-    type NextSubstreamFuture<'a> = future::Pending<Option<(Self::Stream, SubstreamDirection)>>;
+    type NextSubstreamFuture<'a> =
+        future::BoxFuture<'a, Option<(Self::Stream, SubstreamDirection)>>;
 
     fn now_from_unix_epoch(&self) -> Duration {
         // Intentionally panic if the time is configured earlier than the UNIX EPOCH.
@@ -212,7 +275,6 @@ impl PlatformRef for Arc<DefaultPlatform> {
     }
 
     fn supports_connection_type(&self, connection_type: ConnectionType) -> bool {
-        // TODO: support WebSocket secure
         matches!(
             connection_type,
             ConnectionType::TcpIpv4
@@ -220,11 +282,23 @@ impl PlatformRef for Arc<DefaultPlatform> {
                 | ConnectionType::TcpDns
                 | ConnectionType::WebSocketIpv4 { .. }
                 | ConnectionType::WebSocketIpv6 { .. }
-                | ConnectionType::WebSocketDns { secure: false, .. }
+                | ConnectionType::WebSocketDns { .. }
+                | ConnectionType::WebRtcIpv4
+                | ConnectionType::WebRtcIpv6
         )
     }
 
     fn connect_stream(&self, multiaddr: Address) -> Self::StreamConnectFuture {
+        // `Some` if and only if the connection is a secure WebSocket connection.
+        let tls_server_name = match &multiaddr {
+            Address::WebSocketDns {
+                hostname,
+                secure: true,
+                ..
+            } => Some(hostname.to_string()),
+            _ => None,
+        };
+
         let (tcp_socket_addr, host_if_websocket): (
             either::Either<SocketAddr, (String, u16)>,
             Option<String>,
@@ -243,7 +317,7 @@ impl PlatformRef for Arc<DefaultPlatform> {
             Address::WebSocketDns {
                 hostname,
                 port,
-                secure: false,
+                secure: _,
             } => (
                 either::Right((hostname.to_string(), port)),
                 Some(format!("{}:{}", hostname, port)),
@@ -262,12 +336,10 @@ impl PlatformRef for Arc<DefaultPlatform> {
                 let addr = SocketAddr::from((ip, port));
                 (either::Left(addr), Some(addr.to_string()))
             }
-
-            // The API user of the `PlatformRef` trait is never supposed to open connections of
-            // a type that isn't supported.
-            _ => unreachable!(),
         };
 
+        let tls_client_config = self.tls_client_config.0.clone();
+
         let socket_future = async {
             let tcp_socket = match tcp_socket_addr {
                 either::Left(socket_addr) => smol::net::TcpStream::connect(socket_addr).await,
@@ -278,41 +350,77 @@ impl PlatformRef for Arc<DefaultPlatform> {
                 let _ = tcp_socket.set_nodelay(true);
             }
 
-            match (tcp_socket, host_if_websocket) {
-                (Ok(tcp_socket), Some(host)) => {
+            match (tcp_socket, host_if_websocket, tls_server_name) {
+                (Ok(tcp_socket), Some(host), None) => {
                     websocket::websocket_client_handshake(websocket::Config {
                         tcp_socket,
                         host: &host,
                         url: "/",
                     })
                     .await
-                    .map(TcpOrWs::Right)
+                    .map(|ws| TcpOrWs::Right(future::Either::Left(ws)))
                 }
 
-                (Ok(tcp_socket), None) => Ok(TcpOrWs::Left(tcp_socket)),
-                (Err(err), _) => Err(err),
+                (Ok(tcp_socket), Some(host), Some(tls_server_name)) => {
+                    let tls_server_name = rustls::pki_types::ServerName::try_from(tls_server_name)
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                    let tls_socket = futures_rustls::TlsConnector::from(tls_client_config)
+                        .connect(tls_server_name, tcp_socket)
+                        .await?;
+
+                    websocket::websocket_client_handshake(websocket::Config {
+                        tcp_socket: tls_socket,
+                        host: &host,
+                        url: "/",
+                    })
+                    .await
+                    .map(|ws| TcpOrWs::Right(future::Either::Right(ws)))
+                }
+
+                (Ok(tcp_socket), None, _) => Ok(TcpOrWs::Left(tcp_socket)),
+                (Err(err), _, _) => Err(err),
             }
         };
 
         future::ready(Stream(with_buffers::WithBuffers::new(Box::pin(
-            socket_future,
+            socket_future.map(|socket| socket.map(future::Either::Left)),
         ))))
     }
 
-    fn connect_multistream(&self, _address: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
-        panic!()
+    fn connect_multistream(&self, address: MultiStreamAddress) -> Self::MultiStreamConnectFuture {
+        let MultiStreamAddress::WebRtc {
+            ip,
+            port,
+            remote_certificate_sha256,
+        } = address;
+
+        let (connection, local_tls_certificate_sha256, background_task) =
+            webrtc::Connection::connect(SocketAddr::new(ip, port), remote_certificate_sha256);
+        self.spawn_task("webrtc-connection".into(), background_task);
+
+        future::ready(MultiStreamWebRtcConnection {
+            connection,
+            local_tls_certificate_sha256,
+        })
     }
 
-    fn open_out_substream(&self, c: &mut Self::MultiStream) {
-        // This function can only be called with so-called "multi-stream" connections. We never
-        // open such connection.
-        match *c {}
+    fn open_out_substream(&self, connection: &mut Self::MultiStream) {
+        connection.open_out_substream()
     }
 
-    fn next_substream(&self, c: &'_ mut Self::MultiStream) -> Self::NextSubstreamFuture<'_> {
-        // This function can only be called with so-called "multi-stream" connections. We never
-        // open such connection.
-        match *c {}
+    fn next_substream<'a>(
+        &self,
+        connection: &'a mut Self::MultiStream,
+    ) -> Self::NextSubstreamFuture<'a> {
+        Box::pin(async move {
+            let (data_channel, direction) = connection.next_substream().await?;
+            let data_channel: future::BoxFuture<_> =
+                Box::pin(future::ready(Ok(future::Either::Right(data_channel))));
+            Some((
+                Stream(with_buffers::WithBuffers::new(data_channel)),
+                direction,
+            ))
+        })
     }
 
     fn read_write_access<'a>(
@@ -345,17 +453,31 @@ impl Drop for DefaultPlatform {
 pub struct Stream(
     #[pin]
     with_buffers::WithBuffers<
-        future::BoxFuture<'static, Result<TcpOrWs, io::Error>>,
-        TcpOrWs,
+        future::BoxFuture<'static, Result<StreamSocket, io::Error>>,
+        StreamSocket,
         Instant,
     >,
 );
 
-type TcpOrWs = future::Either<smol::net::TcpStream, websocket::Connection<smol::net::TcpStream>>;
+/// Either a TCP connection, a WebSocket connection, or a WebRTC data channel.
+type StreamSocket = future::Either<TcpOrWs, webrtc::DataChannel>;
+
+type TcpOrWs = future::Either<
+    smol::net::TcpStream,
+    future::Either<
+        websocket::Connection<smol::net::TcpStream>,
+        websocket::Connection<futures_rustls::client::TlsStream<smol::net::TcpStream>>,
+    >,
+>;
 
 #[cfg(test)]
 mod tests {
-    use super::{DefaultPlatform, PlatformRef as _};
+    use super::{
+        rustls, Address, DefaultPlatform, MultiStreamAddress, PlatformRef as _, SubstreamDirection,
+    };
+    use core::{pin::Pin, str};
+    use smol::future::FutureExt as _;
+    use std::{net::IpAddr, sync::Arc, time::Instant};
 
     #[test]
     fn tasks_run_indefinitely() {
@@ -376,4 +498,316 @@ mod tests {
         platform_destroyed.notify(usize::MAX);
         assert!(matches!(smol::block_on(rx), Ok(())));
     }
+
+    #[test]
+    fn secure_websocket_connection() {
+        // Generate a certificate authority and a certificate for `localhost` signed by it.
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_certificate = ca_params.self_signed(&ca_key).unwrap();
+        let server_key = rcgen::KeyPair::generate().unwrap();
+        let server_certificate = rcgen::CertificateParams::new(vec!["localhost".to_owned()])
+            .unwrap()
+            .signed_by(&server_key, &ca_certificate, &ca_key)
+            .unwrap();
+
+        let listener = smol::block_on(smol::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // TLS WebSocket server that sends `hello` to the first client.
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![server_certificate.der().clone()],
+            rustls::pki_types::PrivateKeyDer::Pkcs8(server_key.serialize_der().into()),
+        )
+        .unwrap();
+        let server = smol::spawn(async move {
+            let (tcp_socket, _) = listener.accept().await.unwrap();
+            let tls_socket = futures_rustls::TlsAcceptor::from(Arc::new(server_config))
+                .accept(tcp_socket)
+                .await
+                .unwrap();
+            let mut server = soketto::handshake::Server::new(tls_socket);
+            let key = server.receive_request().await.unwrap().key();
+            server
+                .send_response(&soketto::handshake::server::Response::Accept {
+                    key,
+                    protocol: None,
+                })
+                .await
+                .unwrap();
+            let (mut sender, mut receiver) = server.into_builder().finish();
+            sender.send_binary(b"hello").await.unwrap();
+            sender.flush().await.unwrap();
+            // Keep the connection alive until the client closes it.
+            let _ = receiver.receive_data(&mut Vec::new()).await;
+        });
+
+        let platform = DefaultPlatform::with_tls_root_certificates(
+            "".to_string(),
+            "".to_string(),
+            [ca_certificate.der().to_vec()],
+        );
+        let mut stream = smol::block_on(platform.connect_stream(Address::WebSocketDns {
+            hostname: "localhost",
+            port,
+            secure: true,
+        }));
+
+        smol::block_on(async {
+            loop {
+                {
+                    let mut read_write = platform.read_write_access(Pin::new(&mut stream)).unwrap();
+                    if read_write.incoming_buffer.len() >= 5 {
+                        assert_eq!(&read_write.incoming_buffer[..5], b"hello");
+                        break;
+                    }
+                    read_write.expected_incoming_bytes = Some(5);
+                }
+                platform.wait_read_write_again(Pin::new(&mut stream)).await;
+            }
+        });
+
+        drop(stream);
+        let _ = smol::block_on(server.cancel());
+    }
+
+    #[test]
+    fn secure_websocket_unknown_certificate_authority() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = rcgen::CertificateParams::new(vec!["localhost".to_owned()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+
+        let listener = smol::block_on(smol::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![certificate.der().clone()],
+            rustls::pki_types::PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+        )
+        .unwrap();
+        let server = smol::spawn(async move {
+            let (tcp_socket, _) = listener.accept().await.unwrap();
+            let _ = futures_rustls::TlsAcceptor::from(Arc::new(server_config))
+                .accept(tcp_socket)
+                .await;
+        });
+
+        // The self-signed certificate isn't among the default root certificates.
+        let platform = DefaultPlatform::new("".to_string(), "".to_string());
+        let mut stream = smol::block_on(platform.connect_stream(Address::WebSocketDns {
+            hostname: "localhost",
+            port,
+            secure: true,
+        }));
+
+        smol::block_on(async {
+            while platform.read_write_access(Pin::new(&mut stream)).is_ok() {
+                platform.wait_read_write_again(Pin::new(&mut stream)).await;
+            }
+        });
+
+        smol::block_on(server);
+    }
+
+    /// Spawns a WebRTC-direct server listening on localhost that sends back everything it
+    /// receives on its data channels.
+    ///
+    /// Returns the UDP port of the server, the SHA-256 hash of its certificate, and a sender
+    /// where the SHA-256 hash of the certificate of the client must be sent.
+    fn spawn_webrtc_echo_server() -> (u16, [u8; 32], futures_channel::oneshot::Sender<[u8; 32]>) {
+        let socket = smol::block_on(smol::net::UdpSocket::bind("127.0.0.1:0")).unwrap();
+        let local_addr = socket.local_addr().unwrap();
+
+        let mut rtc = str0m::RtcConfig::new()
+            .set_ice_lite(true)
+            .build(Instant::now());
+        let certificate_sha256 =
+            <[u8; 32]>::try_from(&rtc.direct_api().local_dtls_fingerprint().bytes[..]).unwrap();
+
+        let (client_certificate_tx, client_certificate_rx) =
+            futures_channel::oneshot::channel::<[u8; 32]>();
+
+        smol::spawn(async move {
+            let client_certificate_sha256 = client_certificate_rx.await.unwrap();
+            let mut buffer = vec![0; 2048];
+
+            // The ICE credentials of the client are found in the `USERNAME` attribute of the
+            // first STUN message, which has the format `<ufrag>:<ufrag>`.
+            let (num_read, source) = socket.recv_from(&mut buffer).await.unwrap();
+            let ice_ufrag = {
+                let mut attributes = &buffer[20..num_read];
+                loop {
+                    let ty = u16::from_be_bytes([attributes[0], attributes[1]]);
+                    let len = usize::from(u16::from_be_bytes([attributes[2], attributes[3]]));
+                    if ty == 0x6 {
+                        let username = str::from_utf8(&attributes[4..][..len]).unwrap();
+                        break username.split(':').next().unwrap().to_owned();
+                    }
+                    attributes = &attributes[4 + len.next_multiple_of(4)..];
+                }
+            };
+
+            rtc.add_local_candidate(str0m::Candidate::host(local_addr, "udp").unwrap());
+            let mut direct_api = rtc.direct_api();
+            direct_api.set_ice_controlling(false);
+            let ice_credentials = str0m::IceCreds {
+                ufrag: ice_ufrag.clone(),
+                pass: ice_ufrag,
+            };
+            direct_api.set_local_ice_credentials(ice_credentials.clone());
+            direct_api.set_remote_ice_credentials(ice_credentials);
+            direct_api.set_remote_fingerprint(str0m::config::Fingerprint {
+                hash_func: "sha-256".to_owned(),
+                bytes: client_certificate_sha256.to_vec(),
+            });
+            direct_api.start_dtls(false).unwrap();
+            direct_api.start_sctp(false);
+            direct_api.create_data_channel(str0m::channel::ChannelConfig {
+                negotiated: Some(0),
+                ..Default::default()
+            });
+
+            // The server stops as soon as an error happens, for example if the DTLS handshake
+            // fails.
+            let mut datagram = Some((num_read, source));
+            loop {
+                let result = match datagram {
+                    Some((num_read, source)) => rtc.handle_input(str0m::Input::Receive(
+                        Instant::now(),
+                        str0m::net::Receive::new(
+                            str0m::net::Protocol::Udp,
+                            source,
+                            local_addr,
+                            &buffer[..num_read],
+                        )
+                        .unwrap(),
+                    )),
+                    None => rtc.handle_input(str0m::Input::Timeout(Instant::now())),
+                };
+                if result.is_err() {
+                    break;
+                }
+
+                let timeout = loop {
+                    let Ok(output) = rtc.poll_output() else {
+                        return;
+                    };
+                    match output {
+                        str0m::Output::Timeout(timeout) => break timeout,
+                        str0m::Output::Transmit(transmit) => {
+                            socket
+                                .send_to(&transmit.contents, transmit.destination)
+                                .await
+                                .unwrap();
+                        }
+                        str0m::Output::Event(str0m::Event::ChannelData(data)) => {
+                            // Each message is expected to contain exactly one frame, with a
+                            // single-byte length prefix.
+                            let mut channel = rtc.channel(data.id).unwrap();
+                            if usize::from(data.data[0]) + 1 == data.data.len() {
+                                assert!(channel.write(true, &data.data).unwrap());
+                            } else {
+                                assert!(channel.write(true, b"invalid").unwrap());
+                            }
+                        }
+                        str0m::Output::Event(_) => {}
+                    }
+                };
+
+                datagram = async { Some(socket.recv_from(&mut buffer).await.unwrap()) }
+                    .or(async {
+                        smol::Timer::at(timeout).await;
+                        None
+                    })
+                    .await;
+            }
+        })
+        .detach();
+
+        (local_addr.port(), certificate_sha256, client_certificate_tx)
+    }
+
+    #[test]
+    fn webrtc_direct_data_channel() {
+        let (port, server_certificate_sha256, client_certificate_tx) = spawn_webrtc_echo_server();
+
+        let platform = DefaultPlatform::new("".to_string(), "".to_string());
+        let mut connection =
+            smol::block_on(platform.connect_multistream(MultiStreamAddress::WebRtc {
+                ip: IpAddr::from([127, 0, 0, 1]),
+                port,
+                remote_certificate_sha256: &server_certificate_sha256,
+            }));
+        client_certificate_tx
+            .send(connection.local_tls_certificate_sha256)
+            .unwrap();
+
+        platform.open_out_substream(&mut connection.connection);
+        let (mut stream, direction) =
+            smol::block_on(platform.next_substream(&mut connection.connection)).unwrap();
+        assert!(matches!(direction, SubstreamDirection::Outbound));
+
+        // Two frames are written at once, and must be sent as two separate messages.
+        let frames = b"\x04ping\x05hello";
+        smol::block_on(async {
+            let mut written = false;
+            loop {
+                {
+                    let mut read_write = platform.read_write_access(Pin::new(&mut stream)).unwrap();
+                    if !written && read_write.write_bytes_queueable.unwrap_or(0) >= frames.len() {
+                        read_write.write_out(frames.to_vec());
+                        written = true;
+                    }
+                    if read_write.incoming_buffer.len() >= frames.len() {
+                        assert_eq!(&read_write.incoming_buffer[..frames.len()], frames);
+                        break;
+                    }
+                    read_write.expected_incoming_bytes = Some(frames.len());
+                }
+                platform.wait_read_write_again(Pin::new(&mut stream)).await;
+            }
+        });
+    }
+
+    #[test]
+    fn webrtc_direct_wrong_certificate() {
+        let (port, _, client_certificate_tx) = spawn_webrtc_echo_server();
+
+        let platform = DefaultPlatform::new("".to_string(), "".to_string());
+        let mut connection =
+            smol::block_on(platform.connect_multistream(MultiStreamAddress::WebRtc {
+                ip: IpAddr::from([127, 0, 0, 1]),
+                port,
+                remote_certificate_sha256: &[0; 32],
+            }));
+        client_certificate_tx
+            .send(connection.local_tls_certificate_sha256)
+            .unwrap();
+
+        // The DTLS handshake fails, and the connection is shut down.
+        platform.open_out_substream(&mut connection.connection);
+        let (mut stream, _) =
+            smol::block_on(platform.next_substream(&mut connection.connection)).unwrap();
+        smol::block_on(async {
+            while platform.read_write_access(Pin::new(&mut stream)).is_ok() {
+                platform.wait_read_write_again(Pin::new(&mut stream)).await;
+            }
+        });
+        assert!(smol::block_on(platform.next_substream(&mut connection.connection)).is_none());
+    }
 }
@@ -0,0 +1,660 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! WebRTC-direct connections of the [`DefaultPlatform`](super::DefaultPlatform).
+//!
+//! See <https://github.com/libp2p/specs/blob/master/webrtc/webrtc-direct.md>.
+//!
+//! Each connection is driven by a background task that owns the UDP socket and the `str0m`
+//! state machine, which implements ICE, DTLS and SCTP. The [`Connection`] and the
+//! [`DataChannel`]s communicate with this background task through a state shared behind a
+//! mutex.
+//!
+//! The data channels are exposed as plain byte streams. The framing of the messages (see the
+//! `webrtc_framing` module of the `smoldot` crate) and the Noise handshake are performed by the
+//! user of the [`PlatformRef`](super::PlatformRef) trait. Since the libp2p specification
+//! requires each SCTP message to contain exactly one frame, the bytes written to a
+//! [`DataChannel`] are split at the boundaries of the frames before being sent.
+
+use super::SubstreamDirection;
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::{self, Future},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use futures_util::{AsyncRead, AsyncWrite};
+use rand::distributions::{Alphanumeric, DistString as _};
+use smol::future::FutureExt as _;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+use str0m::{
+    channel::{ChannelConfig, ChannelId},
+    config::Fingerprint,
+    net::{Protocol, Receive},
+    Candidate, Event, IceConnectionState, IceCreds, Input, Output, Rtc, RtcConfig,
+};
+
+/// Maximum size of a message sent on a data channel, as defined by the libp2p specification.
+const MAX_MESSAGE_SIZE: usize = 16384;
+
+/// Maximum number of bytes that can be queued for sending on a data channel before writing
+/// blocks.
+const MAX_QUEUED_OUTGOING_BYTES: usize = 4 * MAX_MESSAGE_SIZE;
+
+/// Maximum number of bytes received on a data channel and not read yet. WebRTC data channels
+/// don't provide any way to apply back-pressure on the remote, and the data channel is instead
+/// reset if the remote sends more than this.
+const MAX_QUEUED_INCOMING_BYTES: usize = 64 * MAX_MESSAGE_SIZE;
+
+/// WebRTC connection to a remote.
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+/// Data channel of a [`Connection`]. Implements `AsyncRead` and `AsyncWrite`.
+pub struct DataChannel {
+    shared: Arc<Shared>,
+    id: ChannelId,
+}
+
+struct Shared {
+    state: parking_lot::Mutex<State>,
+    /// Notified whenever the background task should process [`Shared::state`] again.
+    background_wake_up: event_listener::Event,
+}
+
+struct State {
+    /// If `true`, the background task has stopped and the connection is dead.
+    dead: bool,
+    /// If `true`, the [`Connection`] has been destroyed and the background task should stop.
+    connection_dropped: bool,
+    /// Number of calls to [`Connection::open_out_substream`] that the background task hasn't
+    /// processed yet.
+    out_substreams_to_open: usize,
+    /// Data channels that have been opened and not yet yielded by
+    /// [`Connection::next_substream`].
+    new_substreams: VecDeque<(ChannelId, SubstreamDirection)>,
+    /// Waker to wake up when [`State::new_substreams`] is modified or [`State::dead`] is set.
+    new_substreams_waker: Option<Waker>,
+    /// List of all data channels whose [`DataChannel`] hasn't been processed as dropped yet.
+    channels: hashbrown::HashMap<ChannelId, ChannelState, fnv::FnvBuildHasher>,
+}
+
+struct ChannelState {
+    /// `true` if the channel has been closed by the remote or if the connection is dead.
+    reset: bool,
+    /// `true` if the [`DataChannel`] has been destroyed.
+    dropped: bool,
+    /// Data received on the channel and not read yet.
+    incoming: VecDeque<u8>,
+    /// Messages waiting to be sent by the background task.
+    outgoing: VecDeque<Vec<u8>>,
+    /// Sum of the lengths of all the messages in [`ChannelState::outgoing`].
+    outgoing_bytes: usize,
+    /// Beginning of a frame that has been written but isn't complete yet. Moved to
+    /// [`ChannelState::outgoing`] once complete.
+    partial_outgoing: Vec<u8>,
+    /// Waker to wake up when data is received or if the channel is reset.
+    read_waker: Option<Waker>,
+    /// Waker to wake up when messages are removed from [`ChannelState::outgoing`] or if the
+    /// channel is reset.
+    write_waker: Option<Waker>,
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        ChannelState {
+            reset: false,
+            dropped: false,
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            outgoing_bytes: 0,
+            partial_outgoing: Vec::new(),
+            read_waker: None,
+            write_waker: None,
+        }
+    }
+
+    fn wake_all(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Connection {
+    /// Starts connecting to the given remote.
+    ///
+    /// Returns the connection, the SHA-256 hash of the local DTLS certificate, and the future
+    /// of the background task that drives the connection. This future must be spawned.
+    ///
+    /// The connection is considered open immediately. If the remote turns out to be
+    /// unreachable, the connection is later shut down.
+    pub fn connect(
+        remote_addr: SocketAddr,
+        remote_certificate_sha256: &[u8; 32],
+    ) -> (Self, [u8; 32], impl Future<Output = ()> + Send + 'static) {
+        let now = Instant::now();
+        let mut rtc = RtcConfig::new().build(now);
+
+        let local_tls_certificate_sha256 =
+            <[u8; 32]>::try_from(&rtc.direct_api().local_dtls_fingerprint().bytes[..])
+                .unwrap_or_else(|_| unreachable!());
+
+        // According to the libp2p specification, the ICE username fragment and password are
+        // identical on both sides of the connection. The remote learns about them through the
+        // STUN messages that we send.
+        let ice_credentials = {
+            let random = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+            IceCreds {
+                ufrag: format!("libp2p+webrtc+v1/{random}"),
+                pass: format!("libp2p+webrtc+v1/{random}"),
+            }
+        };
+
+        let remote_fingerprint = Fingerprint {
+            hash_func: "sha-256".to_owned(),
+            bytes: remote_certificate_sha256.to_vec(),
+        };
+
+        let socket = UdpSocket::bind(match remote_addr.ip() {
+            IpAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            IpAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        })
+        .and_then(|socket| {
+            // Connecting the socket makes the operating system choose the interface to use,
+            // which is necessary in order to report an actual local address to the ICE agent.
+            socket.connect(remote_addr)?;
+            let local_addr = socket.local_addr()?;
+            Ok((smol::net::UdpSocket::try_from(socket)?, local_addr))
+        });
+
+        let candidates = socket.as_ref().ok().and_then(|(_, local_addr)| {
+            Some((
+                Candidate::host(*local_addr, "udp").ok()?,
+                Candidate::host(remote_addr, "udp").ok()?,
+            ))
+        });
+
+        let shared = Arc::new(Shared {
+            state: parking_lot::Mutex::new(State {
+                dead: false,
+                connection_dropped: false,
+                out_substreams_to_open: 0,
+                new_substreams: VecDeque::new(),
+                new_substreams_waker: None,
+                channels: hashbrown::HashMap::with_capacity_and_hasher(8, Default::default()),
+            }),
+            background_wake_up: event_listener::Event::new(),
+        });
+
+        let background_task = {
+            let shared = shared.clone();
+            async move {
+                if let (Ok((socket, local_addr)), Some((local_candidate, remote_candidate))) =
+                    (socket, candidates)
+                {
+                    rtc.add_local_candidate(local_candidate);
+                    rtc.add_remote_candidate(remote_candidate);
+
+                    let mut direct_api = rtc.direct_api();
+                    direct_api.set_ice_controlling(true);
+                    direct_api.set_local_ice_credentials(ice_credentials.clone());
+                    direct_api.set_remote_ice_credentials(ice_credentials);
+                    direct_api.set_remote_fingerprint(remote_fingerprint);
+
+                    // The remote is always the DTLS server and SCTP server.
+                    if direct_api.start_dtls(true).is_ok() {
+                        direct_api.start_sctp(true);
+                        run_background(&shared, rtc, socket, local_addr, remote_addr).await;
+                    }
+                }
+
+                // Mark the connection as dead and wake up everything that is waiting.
+                let mut state = shared.state.lock();
+                state.dead = true;
+                if let Some(waker) = state.new_substreams_waker.take() {
+                    waker.wake();
+                }
+                for channel in state.channels.values_mut() {
+                    channel.reset = true;
+                    channel.wake_all();
+                }
+            }
+        };
+
+        (
+            Connection { shared },
+            local_tls_certificate_sha256,
+            background_task,
+        )
+    }
+
+    /// Queues the opening of a new outbound data channel. The data channel is later yielded
+    /// by [`Connection::next_substream`].
+    ///
+    /// The first data channel is negotiated out-of-band with an identifier of 0, as required
+    /// by the libp2p specification for the Noise handshake.
+    pub fn open_out_substream(&mut self) {
+        self.shared.state.lock().out_substreams_to_open += 1;
+        self.shared.background_wake_up.notify(1);
+    }
+
+    /// Waits for a new data channel to be opened, either by the remote or following a call to
+    /// [`Connection::open_out_substream`].
+    ///
+    /// Returns `None` if the connection is dead.
+    pub async fn next_substream(&mut self) -> Option<(DataChannel, SubstreamDirection)> {
+        let (id, direction) = future::poll_fn(|cx| {
+            let mut state = self.shared.state.lock();
+            if let Some(substream) = state.new_substreams.pop_front() {
+                return Poll::Ready(Some(substream));
+            }
+            if state.dead {
+                return Poll::Ready(None);
+            }
+            state.new_substreams_waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await?;
+
+        Some((
+            DataChannel {
+                shared: self.shared.clone(),
+                id,
+            },
+            direction,
+        ))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.shared.state.lock().connection_dropped = true;
+        self.shared.background_wake_up.notify(1);
+    }
+}
+
+impl AsyncRead for DataChannel {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock();
+        let channel = state.channels.get_mut(&self.id).unwrap();
+
+        if channel.incoming.is_empty() {
+            if channel.reset {
+                return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            channel.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let num_read = buf.len().min(channel.incoming.len());
+        for (out, byte) in buf.iter_mut().zip(channel.incoming.drain(..num_read)) {
+            *out = byte;
+        }
+        Poll::Ready(Ok(num_read))
+    }
+}
+
+impl AsyncWrite for DataChannel {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_write_vectored(cx, &[io::IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let mut state = self.shared.state.lock();
+        let channel = state.channels.get_mut(&self.id).unwrap();
+
+        if channel.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        if channel.outgoing_bytes >= MAX_QUEUED_OUTGOING_BYTES {
+            channel.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // The bytes are accumulated in `partial_outgoing` until a frame is complete, at which
+        // point the frame is queued as a message of its own.
+        let mut num_written = 0;
+        let mut num_messages_queued = 0;
+        'bufs: for buf in bufs {
+            let mut buf = &buf[..];
+            while !buf.is_empty() {
+                let missing = match frame_missing_bytes(&channel.partial_outgoing) {
+                    Some(missing) => missing,
+                    None if num_written == 0 => {
+                        return Poll::Ready(Err(io::ErrorKind::InvalidData.into()));
+                    }
+                    None => break 'bufs,
+                };
+
+                let len = missing.min(buf.len());
+                channel.partial_outgoing.extend_from_slice(&buf[..len]);
+                buf = &buf[len..];
+                num_written += len;
+
+                if frame_missing_bytes(&channel.partial_outgoing) == Some(0) {
+                    let message = core::mem::take(&mut channel.partial_outgoing);
+                    channel.outgoing_bytes += message.len();
+                    channel.outgoing.push_back(message);
+                    num_messages_queued += 1;
+                    if channel.outgoing_bytes >= MAX_QUEUED_OUTGOING_BYTES {
+                        break 'bufs;
+                    }
+                }
+            }
+        }
+        drop(state);
+
+        if num_messages_queued != 0 {
+            self.shared.background_wake_up.notify(1);
+        }
+        Poll::Ready(Ok(num_written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = self.shared.state.lock();
+        let channel = state.channels.get_mut(&self.id).unwrap();
+
+        if channel.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        if channel.outgoing.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        channel.write_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // WebRTC data channels can't be half-closed. Closing the writing side is instead
+        // indicated through the framing of the messages.
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for DataChannel {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        if state.dead {
+            state.channels.remove(&self.id);
+            return;
+        }
+
+        state.channels.get_mut(&self.id).unwrap().dropped = true;
+        drop(state);
+        self.shared.background_wake_up.notify(1);
+    }
+}
+
+/// Drives the given [`Rtc`] until the connection is dead or the [`Connection`] has been
+/// dropped.
+async fn run_background(
+    shared: &Shared,
+    mut rtc: Rtc,
+    socket: smol::net::UdpSocket,
+    local_addr: SocketAddr,
+    remote_addr: SocketAddr,
+) {
+    let mut first_out_substream = true;
+    let mut receive_buffer = vec![0; 2048];
+
+    loop {
+        // Listen before processing the shared state, in order to not miss any notification.
+        let background_wake_up = shared.background_wake_up.listen();
+
+        // Process what the `Connection` and `DataChannel`s have requested.
+        {
+            let mut state = shared.state.lock();
+            let state = &mut *state;
+
+            if state.connection_dropped {
+                rtc.disconnect();
+            }
+
+            for _ in 0..state.out_substreams_to_open {
+                let id = rtc.direct_api().create_data_channel(ChannelConfig {
+                    negotiated: if first_out_substream { Some(0) } else { None },
+                    ..Default::default()
+                });
+                first_out_substream = false;
+                state.channels.insert(id, ChannelState::new());
+                state
+                    .new_substreams
+                    .push_back((id, SubstreamDirection::Outbound));
+                if let Some(waker) = state.new_substreams_waker.take() {
+                    waker.wake();
+                }
+            }
+            state.out_substreams_to_open = 0;
+
+            state.channels.retain(|id, channel| {
+                if channel.dropped {
+                    if !channel.reset {
+                        rtc.direct_api().close_data_channel(*id);
+                    }
+                    return false;
+                }
+
+                if channel.reset || channel.outgoing.is_empty() {
+                    return true;
+                }
+
+                // `None` if the channel isn't open yet.
+                let Some(mut rtc_channel) = rtc.channel(*id) else {
+                    return true;
+                };
+
+                while let Some(message) = channel.outgoing.front() {
+                    match rtc_channel.write(true, message) {
+                        Ok(true) => {
+                            channel.outgoing_bytes -= message.len();
+                            channel.outgoing.pop_front();
+                            if let Some(waker) = channel.write_waker.take() {
+                                waker.wake();
+                            }
+                        }
+                        Ok(false) => break,
+                        Err(_) => {
+                            channel.reset = true;
+                            channel.wake_all();
+                            break;
+                        }
+                    }
+                }
+
+                true
+            });
+        }
+
+        // Send out the packets generated by the state machine and process its events.
+        // Some events require processing the shared state again, in which case
+        // `process_state_again` is set to `true`.
+        let mut process_state_again = false;
+        let timeout = loop {
+            match rtc.poll_output() {
+                Ok(Output::Timeout(timeout)) => break timeout,
+                Ok(Output::Transmit(transmit)) => {
+                    // The socket is connected to the remote, which is the only destination
+                    // that the state machine knows about.
+                    debug_assert_eq!(transmit.destination, remote_addr);
+                    // Errors are ignored, as UDP is unreliable anyway.
+                    let _ = socket.send(&transmit.contents).await;
+                }
+                Ok(Output::Event(event)) => {
+                    let mut state = shared.state.lock();
+                    match event {
+                        Event::IceConnectionStateChange(IceConnectionState::Disconnected) => {
+                            rtc.disconnect();
+                        }
+                        Event::ChannelOpen(id, _) => {
+                            if state.channels.contains_key(&id) {
+                                // Messages might have been queued before the channel was open.
+                                process_state_again = true;
+                            } else {
+                                state.channels.insert(id, ChannelState::new());
+                                state
+                                    .new_substreams
+                                    .push_back((id, SubstreamDirection::Inbound));
+                                if let Some(waker) = state.new_substreams_waker.take() {
+                                    waker.wake();
+                                }
+                            }
+                        }
+                        Event::ChannelData(data) => {
+                            if let Some(channel) = state.channels.get_mut(&data.id) {
+                                if channel.reset {
+                                    // Data received after the channel has been reset because of
+                                    // an overflow is discarded.
+                                } else if channel.incoming.len() + data.data.len()
+                                    > MAX_QUEUED_INCOMING_BYTES
+                                {
+                                    channel.reset = true;
+                                    channel.wake_all();
+                                    rtc.direct_api().close_data_channel(data.id);
+                                } else {
+                                    channel.incoming.extend(data.data);
+                                    if let Some(waker) = channel.read_waker.take() {
+                                        waker.wake();
+                                    }
+                                }
+                            }
+                        }
+                        Event::ChannelClose(id) => {
+                            if let Some(channel) = state.channels.get_mut(&id) {
+                                channel.reset = true;
+                                channel.wake_all();
+                            }
+                        }
+                        Event::ChannelBufferedAmountLow(_) => {
+                            // Messages might be waiting to be sent.
+                            process_state_again = true;
+                        }
+                        _ => {}
+                    }
+                }
+                Err(_) => {
+                    rtc.disconnect();
+                }
+            }
+        };
+
+        if !rtc.is_alive() {
+            return;
+        }
+
+        if process_state_again {
+            continue;
+        }
+
+        enum WakeUpReason {
+            Datagram(io::Result<(usize, SocketAddr)>),
+            Timeout,
+            Notified,
+        }
+
+        let wake_up_reason =
+            async { WakeUpReason::Datagram(socket.recv_from(&mut receive_buffer).await) }
+                .or(async {
+                    smol::Timer::at(timeout).await;
+                    WakeUpReason::Timeout
+                })
+                .or(async {
+                    background_wake_up.await;
+                    WakeUpReason::Notified
+                })
+                .await;
+
+        match wake_up_reason {
+            WakeUpReason::Datagram(Ok((num_read, source))) => {
+                // Datagrams that can't be parsed are silently ignored.
+                if let Ok(receive) = Receive::new(
+                    Protocol::Udp,
+                    source,
+                    local_addr,
+                    &receive_buffer[..num_read],
+                ) {
+                    if rtc
+                        .handle_input(Input::Receive(Instant::now(), receive))
+                        .is_err()
+                    {
+                        rtc.disconnect();
+                    }
+                }
+            }
+            WakeUpReason::Datagram(Err(_)) => {
+                rtc.disconnect();
+            }
+            WakeUpReason::Timeout => {
+                if rtc.handle_input(Input::Timeout(Instant::now())).is_err() {
+                    rtc.disconnect();
+                }
+            }
+            WakeUpReason::Notified => {}
+        }
+    }
+}
+
+/// Returns the number of bytes missing from `partial` for it to be a complete frame, or `None`
+/// if `partial` doesn't start with a valid frame.
+///
+/// Frames consist of a LEB128-encoded length followed with the given number of bytes. If the
+/// length prefix itself is incomplete, the returned value is `1`.
+fn frame_missing_bytes(partial: &[u8]) -> Option<usize> {
+    let mut length = 0usize;
+    for (index, byte) in partial.iter().enumerate() {
+        // Frames can never be larger than `MAX_MESSAGE_SIZE`, which makes it possible to
+        // refuse long prefixes without worrying about overflows.
+        if index >= 3 {
+            return None;
+        }
+
+        length |= usize::from(byte & 0x7f) << (7 * index);
+        if byte & 0x80 == 0 {
+            let total = index + 1 + length;
+            if total > MAX_MESSAGE_SIZE {
+                return None;
+            }
+            return Some(total.saturating_sub(partial.len()));
+        }
+    }
+
+    Some(1)
+}
@@ -257,6 +257,24 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
         result_rx.await.unwrap_or(None)
     }
 
+    /// Returns the SCALE-encoded output of the `Core_version` runtime function of the runtime of
+    /// the finalized block, alongside with the Merkle value of the `:code` key it was built from.
+    ///
+    /// Returns `None` if the runtime of the current finalized block is not known yet or has
+    /// failed to compile.
+    pub async fn finalized_runtime_version(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let _ = self
+            .to_background
+            .lock()
+            .await
+            .send(ToBackground::FinalizedRuntimeVersion { result_tx })
+            .await;
+
+        result_rx.await.unwrap_or(None)
+    }
+
     /// Pins the runtime of a pinned block.
     ///
     /// The hash of the block passed as parameter corresponds to the block whose runtime is to
@@ -300,6 +318,9 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
     /// to make the call. The block must be currently pinned in the context of the provided
     /// [`SubscriptionId`].
     ///
+    /// `fuel_limit` is the maximum amount of fuel that each attempt at executing the call can
+    /// consume, or `None` for no limit. See [`executor::runtime_call::Config::fuel_limit`].
+    ///
     /// Returns an error if the subscription is stale, meaning that it has been reset by the
     /// runtime service.
     pub async fn runtime_call(
@@ -311,6 +332,7 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
         function_name: String,
         required_api_version: Option<(String, ops::RangeInclusive<u32>)>,
         parameters_vectored: Vec<u8>,
+        fuel_limit: Option<u64>,
         total_attempts: u32,
         timeout_per_request: Duration,
         max_parallel: NonZeroU32,
@@ -326,6 +348,7 @@ impl<TPlat: PlatformRef> RuntimeService<TPlat> {
             function_name,
             required_api_version,
             parameters_vectored,
+            fuel_limit,
             total_attempts,
             timeout_per_request,
             _max_parallel: max_parallel,
@@ -696,6 +719,9 @@ enum ToBackground<TPlat: PlatformRef> {
         // TODO: overcomplicated
         result_tx: oneshot::Sender<Option<(Option<Vec<u8>>, Option<Vec<u8>>, Option<Vec<Nibble>>)>>,
     },
+    FinalizedRuntimeVersion {
+        result_tx: oneshot::Sender<Option<(Vec<u8>, Vec<u8>)>>,
+    },
     IsNearHeadOfChainHeuristic {
         result_tx: oneshot::Sender<bool>,
     },
@@ -719,6 +745,7 @@ enum ToBackground<TPlat: PlatformRef> {
         function_name: String,
         required_api_version: Option<(String, ops::RangeInclusive<u32>)>,
         parameters_vectored: Vec<u8>,
+        fuel_limit: Option<u64>,
         total_attempts: u32,
         timeout_per_request: Duration,
         _max_parallel: NonZeroU32,
@@ -1811,6 +1838,32 @@ async fn run_background<TPlat: PlatformRef>(
                 );
             }
 
+            WakeUpReason::ToBackground(ToBackground::FinalizedRuntimeVersion { result_tx }) => {
+                // Foreground wants the version of the finalized runtime.
+
+                log!(
+                    &background.platform,
+                    Trace,
+                    &background.log_target,
+                    "foreground-finalized-runtime-version"
+                );
+
+                let _ = result_tx.send(
+                    if let Tree::FinalizedBlockRuntimeKnown { tree, .. } = &background.tree {
+                        let runtime = &tree.output_finalized_async_user_data();
+                        match (&runtime.code_merkle_value, &runtime.runtime) {
+                            (Some(code_merkle_value), Ok(vm)) => Some((
+                                code_merkle_value.clone(),
+                                vm.runtime_version().as_ref().to_vec(),
+                            )),
+                            _ => None,
+                        }
+                    } else {
+                        None
+                    },
+                );
+            }
+
             WakeUpReason::ToBackground(ToBackground::IsNearHeadOfChainHeuristic { result_tx }) => {
                 // Foreground wants to query whether we are at the head of the chain.
 
@@ -1986,6 +2039,7 @@ async fn run_background<TPlat: PlatformRef>(
                 function_name,
                 required_api_version,
                 parameters_vectored,
+                fuel_limit,
                 total_attempts,
                 timeout_per_request,
                 _max_parallel: _, // TODO: unused /!\
@@ -2066,6 +2120,7 @@ async fn run_background<TPlat: PlatformRef>(
                             api_version,
                             parameters_vectored,
                             runtime,
+                            fuel_limit,
                             total_attempts,
                             timeout_per_request,
                             inaccessible_errors: Vec::with_capacity(cmp::min(
@@ -2078,13 +2133,27 @@ async fn run_background<TPlat: PlatformRef>(
             }
 
             WakeUpReason::ProgressRuntimeCallRequest(progress) => {
+                // The sender of the call proof is `None` if the proof comes from the cache.
                 let (mut operation, call_proof_and_sender) = match progress {
-                    ProgressRuntimeCallRequest::Initialize(operation) => (operation, None),
+                    ProgressRuntimeCallRequest::Initialize(operation) => {
+                        let cached_proof = background
+                            .sync_service
+                            .call_proof_from_cache(
+                                &operation.block_state_trie_root_hash,
+                                &operation.function_name,
+                                &operation.parameters_vectored,
+                            )
+                            .await;
+                        (operation, cached_proof.map(|proof| (proof, None)))
+                    }
                     ProgressRuntimeCallRequest::CallProofRequestDone {
                         result: Ok(proof),
                         call_proof_sender,
                         operation,
-                    } => (operation, Some((proof, call_proof_sender))),
+                    } => (
+                        operation,
+                        Some((Arc::new(proof.decode().to_vec()), Some(call_proof_sender))),
+                    ),
                     ProgressRuntimeCallRequest::CallProofRequestDone {
                         result: Err(error),
                         mut operation,
@@ -2103,17 +2172,23 @@ async fn run_background<TPlat: PlatformRef>(
                                 - 1,
                             ?error
                         );
+                        // The peer isn't at fault if the chain has used up its bandwidth budget.
+                        if !matches!(
+                            error,
+                            network_service::CallProofRequestError::BudgetExhausted
+                        ) {
+                            background
+                                .network_service
+                                .ban_and_disconnect(
+                                    call_proof_sender,
+                                    network_service::BanSeverity::Low,
+                                    "call-proof-request-failed",
+                                )
+                                .await;
+                        }
                         operation
                             .inaccessible_errors
                             .push(RuntimeCallInaccessibleError::Network(error));
-                        background
-                            .network_service
-                            .ban_and_disconnect(
-                                call_proof_sender,
-                                network_service::BanSeverity::Low,
-                                "call-proof-request-failed",
-                            )
-                            .await;
                         (operation, None)
                     }
                 };
@@ -2131,14 +2206,26 @@ async fn run_background<TPlat: PlatformRef>(
                         operation.runtime.clone(),
                         &operation.function_name,
                         &operation.parameters_vectored,
+                        operation.fuel_limit,
                         &operation.block_state_trie_root_hash,
-                        call_proof.decode(),
+                        &call_proof,
                     )
                     .await
                     {
                         (timing, Ok(output)) => {
                             // Execution finished successfully.
                             // This is the happy path.
+                            if call_proof_sender.is_some() {
+                                background
+                                    .sync_service
+                                    .insert_call_proof_in_cache(
+                                        operation.block_state_trie_root_hash,
+                                        operation.function_name.clone(),
+                                        operation.parameters_vectored.clone(),
+                                        call_proof,
+                                    )
+                                    .await;
+                            }
                             log!(
                                 &background.platform,
                                 Debug,
@@ -2194,14 +2281,16 @@ async fn run_background<TPlat: PlatformRef>(
                                 proof_access_duration = ?timing.proof_access_duration,
                             );
                             operation.inaccessible_errors.push(error);
-                            background
-                                .network_service
-                                .ban_and_disconnect(
-                                    call_proof_sender,
-                                    network_service::BanSeverity::High,
-                                    "invalid-call-proof",
-                                )
-                                .await;
+                            if let Some(call_proof_sender) = call_proof_sender {
+                                background
+                                    .network_service
+                                    .ban_and_disconnect(
+                                        call_proof_sender,
+                                        network_service::BanSeverity::High,
+                                        "invalid-call-proof",
+                                    )
+                                    .await;
+                            }
                         }
                     }
                 }
@@ -2832,6 +2921,7 @@ struct RuntimeCallRequest {
     api_version: Option<u32>,
     parameters_vectored: Vec<u8>,
     runtime: executor::host::HostVmPrototype,
+    fuel_limit: Option<u64>,
     total_attempts: u32,
     timeout_per_request: Duration,
     inaccessible_errors: Vec<RuntimeCallInaccessibleError>,
@@ -2880,6 +2970,9 @@ fn compile_runtime<TPlat: PlatformRef>(
     let heap_pages = executor::storage_heap_pages_to_value(heap_pages.as_deref())
         .map_err(RuntimeError::InvalidHeapPages)?;
     let exec_hint = executor::vm::ExecHint::CompileWithNonDeterministicValidation;
+    // The runtime is shared between all the JSON-RPC services of the chain, and each of them can
+    // be configured with a fuel limit for its runtime calls.
+    let fuel_metering = true;
 
     // We try once with `allow_unresolved_imports: false`. If this fails due to unresolved
     // import, we try again but with `allowed_unresolved_imports: true`.
@@ -2890,6 +2983,7 @@ fn compile_runtime<TPlat: PlatformRef>(
         module,
         heap_pages,
         exec_hint,
+        fuel_metering,
         allow_unresolved_imports: false,
     }) {
         Ok(vm) => Ok(vm),
@@ -2903,6 +2997,7 @@ fn compile_runtime<TPlat: PlatformRef>(
                 module,
                 heap_pages,
                 exec_hint,
+                fuel_metering,
                 allow_unresolved_imports: true,
             }) {
                 Ok(vm) => {
@@ -3062,6 +3157,7 @@ async fn runtime_call_single_attempt<TPlat: PlatformRef>(
     runtime: executor::host::HostVmPrototype,
     function_name: &str,
     parameters_vectored: &[u8],
+    fuel_limit: Option<u64>,
     block_state_trie_root_hash: &[u8; 32],
     call_proof: &[u8],
 ) -> (
@@ -3092,7 +3188,10 @@ async fn runtime_call_single_attempt<TPlat: PlatformRef>(
             executor::runtime_call::StorageProofSizeBehavior::proof_recording_disabled(),
         storage_main_trie_changes: Default::default(),
         max_log_level: 0,
+        fuel_limit,
         calculate_trie_changes: false,
+        record_storage_proof: false,
+        record_trace: false,
     }) {
         Ok(call) => call,
         Err((error, _)) => {
@@ -3166,6 +3265,10 @@ async fn runtime_call_single_attempt<TPlat: PlatformRef>(
                     platform.now() - runtime_call_duration_before;
                 continue;
             }
+            executor::runtime_call::RuntimeCall::StorageProofNodeValue(_) => {
+                // Storage proofs are never recorded.
+                unreachable!()
+            }
             executor::runtime_call::RuntimeCall::Offchain(_) => {
                 // Forbidden host function called.
                 return (
@@ -41,11 +41,14 @@ use smoldot::{
     executor::host,
     libp2p::PeerId,
     network::{codec, service},
+    sync::para,
     trie::{self, prefix_proof, proof_decode, Nibble},
 };
 
+mod finality_consistency;
 mod parachain;
 mod standalone;
+mod storage_cache;
 
 pub use network_service::Role;
 
@@ -66,6 +69,11 @@ pub struct Config<TPlat: PlatformRef> {
     /// Access to the network, and index of the chain to sync from the point of view of the
     /// network service.
     pub network_service: Arc<network_service::NetworkServiceChain<TPlat>>,
+
+    /// Maximum number of bytes of storage values and storage proofs that are kept in memory in
+    /// order to avoid downloading them again. See [`SyncService::storage_query`].
+    pub storage_cache_memory_budget: usize,
+
     /// Extra fields depending on whether the chain is a relay chain or a parachain.
     pub chain_type: ConfigChainType<TPlat>,
 }
@@ -90,6 +98,20 @@ pub struct ConfigRelayChain {
     /// instead of downloading it. If the hint doesn't match, an extra round-trip will be needed,
     /// but if the hint matches it saves a big download.
     pub runtime_code_hint: Option<ConfigRelayChainRuntimeCodeHint>,
+
+    /// SCALE-encoded headers of blocks that are likely to be descendants of the finalized block
+    /// of [`ConfigRelayChain::chain_information`], for example because they were saved in a
+    /// database.
+    ///
+    /// Whenever the syncing algorithm wants to download from the network blocks whose headers
+    /// are found in this list, the headers in the list are used instead. The headers are verified
+    /// in the same way as if they had been downloaded.
+    pub non_finalized_headers_hint: Vec<Vec<u8>>,
+
+    /// If `true`, the finalized blocks announced by several distinct peers are cross-checked
+    /// with each other and with the local finalized block. Peers discovered through different
+    /// bootstrap nodes are preferred. See [`SyncService::subscribe_finality_consistency`].
+    pub finality_consistency_checks: bool,
 }
 
 /// See [`ConfigRelayChain::runtime_code_hint`].
@@ -101,6 +123,10 @@ pub struct ConfigRelayChainRuntimeCodeHint {
     pub merkle_value: Vec<u8>,
     /// Closest ancestor of the `:code` key except for `:code` itself.
     pub closest_ancestor_excluding: Vec<Nibble>,
+    /// Runtime version of the runtime found in
+    /// [`ConfigRelayChainRuntimeCodeHint::storage_value`], if known. Saves calling the
+    /// `Core_version` runtime function when the hint matches.
+    pub runtime_version: Option<host::CoreVersion>,
 }
 
 /// See [`ConfigChainType::Parachain`].
@@ -108,6 +134,10 @@ pub struct ConfigParachain<TPlat: PlatformRef> {
     /// Runtime service that synchronizes the relay chain of this parachain.
     pub relay_chain_sync: Arc<runtime_service::RuntimeService<TPlat>>,
 
+    /// Sync service of the relay chain of this parachain. Used in order to read the parachain
+    /// heads from the storage of the relay chain.
+    pub relay_chain_sync_service: Arc<SyncService<TPlat>>,
+
     /// SCALE-encoded header of a known finalized block of the parachain. Used in the situation
     /// where the API user subscribes using [`SyncService::subscribe_all`] before any parachain
     /// block can be gathered.
@@ -121,6 +151,12 @@ pub struct ConfigParachain<TPlat: PlatformRef> {
     /// > **Note**: This information is normally found in the chain specification of the
     /// >           parachain.
     pub para_id: u32,
+
+    /// Format of the head data of the parachain found in the relay chain.
+    ///
+    /// > **Note**: This information is normally found in the chain specification of the
+    /// >           parachain.
+    pub head_data_format: para::HeadDataFormat,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -138,6 +174,12 @@ pub struct SyncService<TPlat: PlatformRef> {
     network_service: Arc<network_service::NetworkServiceChain<TPlat>>,
     /// See [`Config::block_number_bytes`].
     block_number_bytes: usize,
+
+    /// Target to use for the logs.
+    log_target: String,
+
+    /// Storage values and proofs that have been verified, shared between all storage queries.
+    storage_cache: async_lock::Mutex<storage_cache::StorageCache>,
 }
 
 impl<TPlat: PlatformRef> SyncService<TPlat> {
@@ -154,7 +196,9 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
                 config_parachain.finalized_block_header,
                 config.block_number_bytes,
                 config_parachain.relay_chain_sync.clone(),
+                config_parachain.relay_chain_sync_service.clone(),
                 config_parachain.para_id,
+                config_parachain.head_data_format,
                 from_foreground,
                 config.network_service.clone(),
             )),
@@ -165,6 +209,8 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
                     config_relay_chain.chain_information,
                     config.block_number_bytes,
                     config_relay_chain.runtime_code_hint,
+                    config_relay_chain.non_finalized_headers_hint,
+                    config_relay_chain.finality_consistency_checks,
                     from_foreground,
                     config.network_service.clone(),
                 ))
@@ -173,6 +219,7 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
 
         config.platform.spawn_task(log_target.clone().into(), {
             let platform = config.platform.clone();
+            let log_target = log_target.clone();
             async move {
                 task.await;
                 log!(&platform, Debug, &log_target, "shutdown");
@@ -184,6 +231,10 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
             platform: config.platform,
             network_service: config.network_service,
             block_number_bytes: config.block_number_bytes,
+            log_target,
+            storage_cache: async_lock::Mutex::new(storage_cache::StorageCache::new(
+                config.storage_cache_memory_budget,
+            )),
         }
     }
 
@@ -242,6 +293,53 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
         rx.await.unwrap()
     }
 
+    /// Subscribes to the events generated by the finality consistency checks.
+    ///
+    /// The returned channel is closed immediately if the checks are disabled, which is always
+    /// the case for parachains. See [`ConfigRelayChain::finality_consistency_checks`].
+    ///
+    /// Events are dropped if the channel is full.
+    pub async fn subscribe_finality_consistency(
+        &self,
+        buffer_size: usize,
+    ) -> async_channel::Receiver<FinalityConsistencyEvent> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .send(ToBackground::SubscribeFinalityConsistency {
+                send_back,
+                buffer_size,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the hash of a finalized relay chain block in which the given finalized parachain
+    /// block is included.
+    ///
+    /// The finality of a parachain block is entirely determined by the relay chain. The returned
+    /// relay chain block, combined with the finality proof of this relay chain block, can serve
+    /// as a justification of the finality of the parachain block.
+    ///
+    /// Returns `None` if the chain isn't a parachain, or if the given block isn't a finalized
+    /// block known to the service. Only a limited number of recently-finalized parachain
+    /// blocks are known.
+    pub async fn parachain_block_inclusion(&self, block_hash: [u8; 32]) -> Option<[u8; 32]> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .send(ToBackground::ParachainBlockInclusion {
+                block_hash,
+                send_back,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
     /// Returns true if it is believed that we are near the head of the chain.
     ///
     /// The way this method is implemented is opaque and cannot be relied on. The return value
@@ -308,6 +406,39 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
         rx.await.unwrap().into_iter()
     }
 
+    /// Returns the SCALE-encoded call proof of a runtime call against the storage trie whose root
+    /// hash is `main_trie_root_hash`, if it has previously been passed to
+    /// [`SyncService::insert_call_proof_in_cache`].
+    pub async fn call_proof_from_cache(
+        &self,
+        main_trie_root_hash: &[u8; 32],
+        function_name: &str,
+        parameters_vectored: &[u8],
+    ) -> Option<Arc<Vec<u8>>> {
+        let mut cache = self.storage_cache.lock().await;
+        let proof = cache.call_proof(main_trie_root_hash, function_name, parameters_vectored);
+        cache.record_lookups(usize::from(proof.is_some()), usize::from(proof.is_none()));
+        proof
+    }
+
+    /// Inserts in the cache shared with the storage queries a SCALE-encoded call proof that has
+    /// successfully been used to perform the given runtime call against the storage trie whose
+    /// root hash is `main_trie_root_hash`.
+    pub async fn insert_call_proof_in_cache(
+        &self,
+        main_trie_root_hash: [u8; 32],
+        function_name: String,
+        parameters_vectored: Vec<u8>,
+        proof: Arc<Vec<u8>>,
+    ) {
+        self.storage_cache.lock().await.insert_call_proof(
+            main_trie_root_hash,
+            function_name,
+            parameters_vectored,
+            proof,
+        );
+    }
+
     // TODO: doc; explain the guarantees
     pub async fn block_query(
         self: Arc<Self>,
@@ -414,6 +545,10 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
     ///
     /// The result will contain items corresponding to the requests, but in no particular order.
     ///
+    /// The storage values and proofs that have been downloaded and verified are kept in a cache
+    /// shared between all the queries, and are re-used by later queries targeting a block with
+    /// the same `main_trie_root_hash`.
+    ///
     /// See the documentation of [`StorageRequestItem`] and [`StorageResultItem`] for more
     /// information.
     pub fn storage_query(
@@ -467,6 +602,7 @@ impl<TPlat: PlatformRef> SyncService<TPlat> {
             outcome_errors: Vec::with_capacity(total_attempts),
             available_results: VecDeque::with_capacity(requests.len() * 4),
             requests_remaining: requests,
+            cache_queried: false,
             response_nodes_cap: (16 * 1024 * 1024) / 164,
             randomness: rand_chacha::ChaCha20Rng::from_seed({
                 let mut seed = [0; 32];
@@ -596,6 +732,8 @@ pub struct StorageQuery<TPlat: PlatformRef> {
     /// The `usize` is the index of the request in the original list of requests that the API user
     /// provided.
     available_results: VecDeque<(usize, StorageResultItem)>,
+    /// `true` if the storage cache has already been used to fulfill the requests.
+    cache_queried: bool,
     /// Number of nodes that are possible in a response before exceeding the response size
     /// limit. Because the size of a trie node is unknown, this can only ever be a gross
     /// estimate.
@@ -627,9 +765,32 @@ impl<TPlat: PlatformRef> StorageQuery<TPlat> {
         self.available_results.pop_front()
     }
 
+    /// Advances the query until it has finished, and returns all the items it has yielded.
+    pub async fn collect_all(self) -> Result<Vec<StorageResultItem>, StorageQueryError> {
+        let mut out = Vec::new();
+        let mut query = self.advance().await;
+        loop {
+            match query {
+                StorageQueryProgress::Progress {
+                    item, query: next, ..
+                } => {
+                    out.push(item);
+                    query = next.advance().await;
+                }
+                StorageQueryProgress::Finished => return Ok(out),
+                StorageQueryProgress::Error(error) => return Err(error),
+            }
+        }
+    }
+
     /// Wait until some progress is made.
     pub async fn advance(mut self) -> StorageQueryProgress<TPlat> {
         loop {
+            if !self.cache_queried {
+                self.cache_queried = true;
+                self.query_cache().await;
+            }
+
             // TODO: instead of buffering everything here, progressively decode the proof
             if let Some((request_index, item)) = self.available_results.pop_front() {
                 return StorageQueryProgress::Progress {
@@ -748,14 +909,20 @@ impl<TPlat: PlatformRef> StorageQuery<TPlat> {
                         network_service::StorageProofRequestError::RequestTooLarge
                     ) || self.response_nodes_cap == 1
                     {
-                        self.sync_service
-                            .network_service
-                            .ban_and_disconnect(
-                                target,
-                                network_service::BanSeverity::Low,
-                                "storage-request-failed",
-                            )
-                            .await;
+                        // The peer isn't at fault if the chain has used up its bandwidth budget.
+                        if !matches!(
+                            err,
+                            network_service::StorageProofRequestError::BudgetExhausted
+                        ) {
+                            self.sync_service
+                                .network_service
+                                .ban_and_disconnect(
+                                    target,
+                                    network_service::BanSeverity::Low,
+                                    "storage-request-failed",
+                                )
+                                .await;
+                        }
                         self.outcome_errors
                             .push(StorageQueryErrorDetail::Network(err));
                     }
@@ -768,8 +935,9 @@ impl<TPlat: PlatformRef> StorageQuery<TPlat> {
                 }
             };
 
+            let proof_len = proof.decode().len();
             let decoded_proof = match proof_decode::decode_and_verify_proof(proof_decode::Config {
-                proof: proof.decode(),
+                proof: proof.decode().to_vec(),
             }) {
                 Ok(d) => d,
                 Err(err) => {
@@ -787,214 +955,305 @@ impl<TPlat: PlatformRef> StorageQuery<TPlat> {
                 }
             };
 
-            let mut proof_has_advanced_verification = false;
+            let proof_has_advanced_verification = self.inject_proof(&decoded_proof);
+
+            if proof_has_advanced_verification {
+                self.insert_in_cache(Arc::new(decoded_proof), proof_len)
+                    .await;
+            }
+
+            // If the proof doesn't contain any item that reduces the number of things to request,
+            // then we push an error.
+            if !proof_has_advanced_verification {
+                self.outcome_errors
+                    .push(StorageQueryErrorDetail::MissingProofEntry);
+            }
+        }
+    }
+
+    /// Fulfills as many requests as possible using the content of the storage cache.
+    async fn query_cache(&mut self) {
+        let num_requests = self.requests_remaining.len();
+
+        let cached_proofs = {
+            let mut cache = self.sync_service.storage_cache.lock().await;
 
             for (request_index, request) in mem::take(&mut self.requests_remaining) {
-                match request {
-                    RequestImpl::PrefixScan {
-                        scan,
-                        requested_key,
-                    } => {
-                        // TODO: how "partial" do we accept that the proof is? it should be considered malicious if the full node might return the minimum amount of information
-                        match scan.resume_partial(proof.decode()) {
-                            Ok(prefix_proof::ResumeOutcome::InProgress(scan)) => {
-                                proof_has_advanced_verification = true;
-                                self.requests_remaining.push((
-                                    request_index,
-                                    RequestImpl::PrefixScan {
-                                        scan,
-                                        requested_key,
-                                    },
-                                ));
-                            }
-                            Ok(prefix_proof::ResumeOutcome::Success {
-                                entries,
-                                full_storage_values_required,
-                            }) => {
-                                proof_has_advanced_verification = true;
-                                // The value of `full_storage_values_required` determines whether
-                                // we wanted full values (`true`) or hashes (`false`).
-                                for (key, value) in entries {
-                                    match value {
-                                        prefix_proof::StorageValue::Hash(hash) => {
-                                            debug_assert!(!full_storage_values_required);
-                                            self.available_results.push_back((
-                                                request_index,
-                                                StorageResultItem::DescendantHash {
-                                                    key,
-                                                    hash,
-                                                    requested_key: requested_key.clone(),
-                                                },
-                                            ));
-                                        }
-                                        prefix_proof::StorageValue::Value(value)
-                                            if full_storage_values_required =>
-                                        {
-                                            self.available_results.push_back((
-                                                request_index,
-                                                StorageResultItem::DescendantValue {
-                                                    requested_key: requested_key.clone(),
-                                                    key,
-                                                    value,
-                                                },
-                                            ));
-                                        }
-                                        prefix_proof::StorageValue::Value(value) => {
-                                            let hashed_value =
-                                                blake2_rfc::blake2b::blake2b(32, &[], &value);
-                                            self.available_results.push_back((
-                                                request_index,
-                                                StorageResultItem::DescendantHash {
-                                                    key,
-                                                    hash: *<&[u8; 32]>::try_from(
-                                                        hashed_value.as_bytes(),
-                                                    )
-                                                    .unwrap(),
-                                                    requested_key: requested_key.clone(),
-                                                },
-                                            ));
-                                        }
-                                    }
-                                }
+                if let RequestImpl::ValueOrHash { key, hash } = &request {
+                    if let Some(value) = cache.storage_value(&self.main_trie_root_hash, key) {
+                        let item = if *hash {
+                            StorageResultItem::Hash {
+                                key: key.clone(),
+                                hash: value.map(|value| {
+                                    *<&[u8; 32]>::try_from(
+                                        blake2_rfc::blake2b::blake2b(32, &[], value).as_bytes(),
+                                    )
+                                    .unwrap()
+                                }),
                             }
-                            Err((_, prefix_proof::Error::InvalidProof(_))) => {
-                                // Since we decode the proof above, this is never supposed to
-                                // be reachable.
-                                unreachable!()
+                        } else {
+                            StorageResultItem::Value {
+                                key: key.clone(),
+                                value: value.map(|value| value.to_vec()),
                             }
-                            Err((scan, prefix_proof::Error::MissingProofEntry)) => {
-                                self.requests_remaining.push((
-                                    request_index,
-                                    RequestImpl::PrefixScan {
-                                        requested_key,
-                                        scan,
-                                    },
-                                ));
-                            }
-                        }
+                        };
+                        self.available_results.push_back((request_index, item));
+                        continue;
                     }
-                    RequestImpl::ValueOrHash { key, hash } => {
-                        match decoded_proof.trie_node_info(
-                            &self.main_trie_root_hash,
-                            trie::bytes_to_nibbles(key.iter().copied()),
-                        ) {
-                            Ok(node_info) => match node_info.storage_value {
-                                proof_decode::StorageValue::HashKnownValueMissing(h) if hash => {
-                                    proof_has_advanced_verification = true;
-                                    self.available_results.push_back((
-                                        request_index,
-                                        StorageResultItem::Hash {
-                                            key,
-                                            hash: Some(*h),
-                                        },
-                                    ));
-                                }
-                                proof_decode::StorageValue::HashKnownValueMissing(_) => {
-                                    self.requests_remaining.push((
-                                        request_index,
-                                        RequestImpl::ValueOrHash { key, hash },
-                                    ));
-                                }
-                                proof_decode::StorageValue::Known { value, .. } => {
-                                    proof_has_advanced_verification = true;
-                                    if hash {
-                                        let hashed_value =
-                                            blake2_rfc::blake2b::blake2b(32, &[], value);
+                }
+
+                self.requests_remaining.push((request_index, request));
+            }
+
+            cache.proofs(&self.main_trie_root_hash)
+        };
+
+        // The proofs in the cache have already been decoded and verified in the past.
+        for decoded_proof in cached_proofs {
+            if self.requests_remaining.is_empty() {
+                break;
+            }
+
+            self.inject_proof(&decoded_proof);
+        }
+
+        let mut cache = self.sync_service.storage_cache.lock().await;
+        let misses = self.requests_remaining.len();
+        cache.record_lookups(num_requests - misses, misses);
+        log!(
+            &self.sync_service.platform,
+            Debug,
+            &self.sync_service.log_target,
+            "storage-cache",
+            query_hits = num_requests - misses,
+            query_misses = misses,
+            total_hits = cache.hits(),
+            total_misses = cache.misses(),
+            memory_used = cache.memory_used()
+        );
+    }
+
+    /// Inserts in the storage cache the given decoded proof, and the storage values that are
+    /// available to yield.
+    async fn insert_in_cache(
+        &self,
+        decoded_proof: Arc<proof_decode::DecodedTrieProof<Vec<u8>>>,
+        encoded_proof_len: usize,
+    ) {
+        let mut cache = self.sync_service.storage_cache.lock().await;
+
+        for (_, item) in &self.available_results {
+            if let StorageResultItem::Value { key, value } = item {
+                cache.insert_value(self.main_trie_root_hash, key.clone(), value.clone());
+            }
+        }
+
+        cache.insert_proof(self.main_trie_root_hash, decoded_proof, encoded_proof_len);
+    }
+
+    /// Updates the state of the query with the content of the given decoded proof.
+    ///
+    /// Returns `true` if the proof contained at least one of the items that were missing.
+    fn inject_proof(&mut self, decoded_proof: &proof_decode::DecodedTrieProof<Vec<u8>>) -> bool {
+        let mut proof_has_advanced_verification = false;
+
+        for (request_index, request) in mem::take(&mut self.requests_remaining) {
+            match request {
+                RequestImpl::PrefixScan {
+                    scan,
+                    requested_key,
+                } => {
+                    // TODO: how "partial" do we accept that the proof is? it should be considered malicious if the full node might return the minimum amount of information
+                    match scan.resume_partial_decoded(decoded_proof) {
+                        Ok(prefix_proof::ResumeOutcome::InProgress(scan)) => {
+                            proof_has_advanced_verification = true;
+                            self.requests_remaining.push((
+                                request_index,
+                                RequestImpl::PrefixScan {
+                                    scan,
+                                    requested_key,
+                                },
+                            ));
+                        }
+                        Ok(prefix_proof::ResumeOutcome::Success {
+                            entries,
+                            full_storage_values_required,
+                        }) => {
+                            proof_has_advanced_verification = true;
+                            // The value of `full_storage_values_required` determines whether
+                            // we wanted full values (`true`) or hashes (`false`).
+                            for (key, value) in entries {
+                                match value {
+                                    prefix_proof::StorageValue::Hash(hash) => {
+                                        debug_assert!(!full_storage_values_required);
                                         self.available_results.push_back((
                                             request_index,
-                                            StorageResultItem::Hash {
+                                            StorageResultItem::DescendantHash {
                                                 key,
-                                                hash: Some(
-                                                    *<&[u8; 32]>::try_from(hashed_value.as_bytes())
-                                                        .unwrap(),
-                                                ),
+                                                hash,
+                                                requested_key: requested_key.clone(),
                                             },
                                         ));
-                                    } else {
+                                    }
+                                    prefix_proof::StorageValue::Value(value)
+                                        if full_storage_values_required =>
+                                    {
                                         self.available_results.push_back((
                                             request_index,
-                                            StorageResultItem::Value {
+                                            StorageResultItem::DescendantValue {
+                                                requested_key: requested_key.clone(),
                                                 key,
-                                                value: Some(value.to_vec()),
+                                                value,
                                             },
                                         ));
                                     }
-                                }
-                                proof_decode::StorageValue::None => {
-                                    proof_has_advanced_verification = true;
-                                    if hash {
-                                        self.available_results.push_back((
-                                            request_index,
-                                            StorageResultItem::Hash { key, hash: None },
-                                        ));
-                                    } else {
+                                    prefix_proof::StorageValue::Value(value) => {
+                                        let hashed_value =
+                                            blake2_rfc::blake2b::blake2b(32, &[], &value);
                                         self.available_results.push_back((
                                             request_index,
-                                            StorageResultItem::Value { key, value: None },
+                                            StorageResultItem::DescendantHash {
+                                                key,
+                                                hash: *<&[u8; 32]>::try_from(
+                                                    hashed_value.as_bytes(),
+                                                )
+                                                .unwrap(),
+                                                requested_key: requested_key.clone(),
+                                            },
                                         ));
                                     }
                                 }
-                            },
-                            Err(proof_decode::IncompleteProofError { .. }) => {
-                                self.requests_remaining
-                                    .push((request_index, RequestImpl::ValueOrHash { key, hash }));
                             }
                         }
+                        Err((_, prefix_proof::Error::InvalidProof(_))) => {
+                            // Since the proof is already decoded, this is never supposed to
+                            // be reachable.
+                            unreachable!()
+                        }
+                        Err((scan, prefix_proof::Error::MissingProofEntry)) => {
+                            self.requests_remaining.push((
+                                request_index,
+                                RequestImpl::PrefixScan {
+                                    requested_key,
+                                    scan,
+                                },
+                            ));
+                        }
                     }
-                    RequestImpl::ClosestDescendantMerkleValue { key } => {
-                        let key_nibbles = trie::bytes_to_nibbles(key.iter().copied());
-
-                        let closest_descendant_merkle_value = match decoded_proof
-                            .closest_descendant_merkle_value(
-                                &self.main_trie_root_hash,
-                                key_nibbles.clone(),
-                            ) {
-                            Ok(Some(merkle_value)) => Some(merkle_value.to_vec()),
-                            Ok(None) => None,
-                            Err(proof_decode::IncompleteProofError { .. }) => {
-                                self.requests_remaining.push((
+                }
+                RequestImpl::ValueOrHash { key, hash } => {
+                    match decoded_proof.trie_node_info(
+                        &self.main_trie_root_hash,
+                        trie::bytes_to_nibbles(key.iter().copied()),
+                    ) {
+                        Ok(node_info) => match node_info.storage_value {
+                            proof_decode::StorageValue::HashKnownValueMissing(h) if hash => {
+                                proof_has_advanced_verification = true;
+                                self.available_results.push_back((
                                     request_index,
-                                    RequestImpl::ClosestDescendantMerkleValue { key },
+                                    StorageResultItem::Hash {
+                                        key,
+                                        hash: Some(*h),
+                                    },
                                 ));
-                                continue;
                             }
-                        };
-
-                        let found_closest_ancestor_excluding = match decoded_proof
-                            .closest_ancestor_in_proof(&self.main_trie_root_hash, key_nibbles)
-                        {
-                            Ok(Some(ancestor)) => Some(ancestor.collect::<Vec<_>>()),
-                            Ok(None) => None,
-                            Err(proof_decode::IncompleteProofError { .. }) => {
-                                self.requests_remaining.push((
-                                    request_index,
-                                    RequestImpl::ClosestDescendantMerkleValue { key },
-                                ));
-                                continue;
+                            proof_decode::StorageValue::HashKnownValueMissing(_) => {
+                                self.requests_remaining
+                                    .push((request_index, RequestImpl::ValueOrHash { key, hash }));
                             }
-                        };
-
-                        proof_has_advanced_verification = true;
-
-                        self.available_results.push_back((
-                            request_index,
-                            StorageResultItem::ClosestDescendantMerkleValue {
-                                requested_key: key,
-                                closest_descendant_merkle_value,
-                                found_closest_ancestor_excluding,
-                            },
-                        ))
+                            proof_decode::StorageValue::Known { value, .. } => {
+                                proof_has_advanced_verification = true;
+                                if hash {
+                                    let hashed_value = blake2_rfc::blake2b::blake2b(32, &[], value);
+                                    self.available_results.push_back((
+                                        request_index,
+                                        StorageResultItem::Hash {
+                                            key,
+                                            hash: Some(
+                                                *<&[u8; 32]>::try_from(hashed_value.as_bytes())
+                                                    .unwrap(),
+                                            ),
+                                        },
+                                    ));
+                                } else {
+                                    self.available_results.push_back((
+                                        request_index,
+                                        StorageResultItem::Value {
+                                            key,
+                                            value: Some(value.to_vec()),
+                                        },
+                                    ));
+                                }
+                            }
+                            proof_decode::StorageValue::None => {
+                                proof_has_advanced_verification = true;
+                                if hash {
+                                    self.available_results.push_back((
+                                        request_index,
+                                        StorageResultItem::Hash { key, hash: None },
+                                    ));
+                                } else {
+                                    self.available_results.push_back((
+                                        request_index,
+                                        StorageResultItem::Value { key, value: None },
+                                    ));
+                                }
+                            }
+                        },
+                        Err(proof_decode::IncompleteProofError { .. }) => {
+                            self.requests_remaining
+                                .push((request_index, RequestImpl::ValueOrHash { key, hash }));
+                        }
                     }
                 }
-            }
+                RequestImpl::ClosestDescendantMerkleValue { key } => {
+                    let key_nibbles = trie::bytes_to_nibbles(key.iter().copied());
 
-            // If the proof doesn't contain any item that reduces the number of things to request,
-            // then we push an error.
-            if !proof_has_advanced_verification {
-                self.outcome_errors
-                    .push(StorageQueryErrorDetail::MissingProofEntry);
+                    let closest_descendant_merkle_value = match decoded_proof
+                        .closest_descendant_merkle_value(
+                            &self.main_trie_root_hash,
+                            key_nibbles.clone(),
+                        ) {
+                        Ok(Some(merkle_value)) => Some(merkle_value.to_vec()),
+                        Ok(None) => None,
+                        Err(proof_decode::IncompleteProofError { .. }) => {
+                            self.requests_remaining.push((
+                                request_index,
+                                RequestImpl::ClosestDescendantMerkleValue { key },
+                            ));
+                            continue;
+                        }
+                    };
+
+                    let found_closest_ancestor_excluding = match decoded_proof
+                        .closest_ancestor_in_proof(&self.main_trie_root_hash, key_nibbles)
+                    {
+                        Ok(Some(ancestor)) => Some(ancestor.collect::<Vec<_>>()),
+                        Ok(None) => None,
+                        Err(proof_decode::IncompleteProofError { .. }) => {
+                            self.requests_remaining.push((
+                                request_index,
+                                RequestImpl::ClosestDescendantMerkleValue { key },
+                            ));
+                            continue;
+                        }
+                    };
+
+                    proof_has_advanced_verification = true;
+
+                    self.available_results.push_back((
+                        request_index,
+                        StorageResultItem::ClosestDescendantMerkleValue {
+                            requested_key: key,
+                            closest_descendant_merkle_value,
+                            found_closest_ancestor_excluding,
+                        },
+                    ))
+                }
             }
         }
+
+        proof_has_advanced_verification
     }
 }
 
@@ -1038,7 +1297,8 @@ impl StorageQueryError {
                 ),
             )
             | StorageQueryErrorDetail::Network(
-                network_service::StorageProofRequestError::NoConnection,
+                network_service::StorageProofRequestError::NoConnection
+                | network_service::StorageProofRequestError::BudgetExhausted,
             ) => true,
             StorageQueryErrorDetail::Network(
                 network_service::StorageProofRequestError::Request(
@@ -1106,6 +1366,40 @@ pub struct SubscribeAll {
     pub new_blocks: async_channel::Receiver<Notification>,
 }
 
+/// Event generated by the finality consistency checks.
+/// See [`SyncService::subscribe_finality_consistency`].
+#[derive(Debug, Clone)]
+pub enum FinalityConsistencyEvent {
+    /// A peer claims that a block is finalized while another peer, or the local node, considers
+    /// a different block at the same height as finalized.
+    Divergence {
+        /// Height of the conflicting blocks.
+        block_number: u64,
+        /// Peer whose claim conflicts.
+        peer_id: PeerId,
+        /// Hash of the block that [`FinalityConsistencyEvent::Divergence::peer_id`] claims is
+        /// finalized.
+        block_hash: [u8; 32],
+        /// Peer that has made the other claim, or `None` if the other block is the local
+        /// finalized block.
+        conflicting_peer_id: Option<PeerId>,
+        /// Hash of the other block.
+        conflicting_block_hash: [u8; 32],
+    },
+    /// A peer reports a finalized block height that lags far behind the local finalized block,
+    /// or hasn't reported any finalized block height while the local finalized block has
+    /// advanced, which might indicate that it is withholding finality.
+    Withheld {
+        /// Peer that lags behind.
+        peer_id: PeerId,
+        /// Finalized block height reported by the peer, or `None` if the peer hasn't reported
+        /// any.
+        peer_finalized_block_number: Option<u64>,
+        /// Height of the local finalized block.
+        local_finalized_block_number: u64,
+    },
+}
+
 /// See [`SubscribeAll::finalized_block_runtime`].
 pub struct FinalizedBlockRuntime {
     /// Compiled virtual machine.
@@ -1228,4 +1522,14 @@ enum ToBackground {
     SerializeChainInformation {
         send_back: oneshot::Sender<Option<chain::chain_information::ValidChainInformation>>,
     },
+    /// See [`SyncService::subscribe_finality_consistency`].
+    SubscribeFinalityConsistency {
+        send_back: oneshot::Sender<async_channel::Receiver<FinalityConsistencyEvent>>,
+        buffer_size: usize,
+    },
+    /// See [`SyncService::parachain_block_inclusion`].
+    ParachainBlockInclusion {
+        block_hash: [u8; 32],
+        send_back: oneshot::Sender<Option<[u8; 32]>>,
+    },
 }
@@ -0,0 +1,478 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cross-checking of what peers claim about the finality of the chain.
+//!
+//! A subset of the connected peers, called "witnesses", is selected. Peers discovered through
+//! bootstrap nodes that no other witness has been discovered through are preferred, but peers
+//! sharing a discovery origin fill the remaining slots, so that peers can be cross-checked even
+//! when they have all been discovered through the same bootstrap node. The finalized blocks
+//! announced by these witnesses through GrandPa commit messages are compared with each other
+//! and with the local finalized block, and the finalized block heights they announce through
+//! GrandPa neighbor packets are compared with the local finalized block height.
+//!
+//! A witness that doesn't send any neighbor packet is considered as withholding finality once
+//! the local finalized block height has advanced by more than [`WITHHOLDING_THRESHOLD`] blocks
+//! since it has been selected.
+//!
+//! This module is a pure state machine. It doesn't perform any I/O.
+
+use super::FinalityConsistencyEvent;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use hashbrown::HashMap;
+use smoldot::libp2p::PeerId;
+
+/// Maximum number of witnesses.
+const MAX_WITNESSES: usize = 8;
+
+/// Maximum number of block heights for which the claims of witnesses and the local finalized
+/// blocks are kept in memory.
+const MAX_TRACKED_HEIGHTS: usize = 64;
+
+/// Number of blocks the finalized block height reported by a witness can lag behind the local
+/// finalized block height before it is considered as withholding finality.
+const WITHHOLDING_THRESHOLD: u64 = 32;
+
+/// Maximum number of blocks between the local finalized block height and the height of a block
+/// claimed as finalized by a witness. Claims further ahead are ignored, as they would otherwise
+/// make all the claims about lower heights be considered as too old.
+const MAX_CLAIM_DISTANCE: u64 = 512;
+
+pub(super) struct FinalityConsistency {
+    /// Peers whose claims are cross-checked.
+    witnesses: HashMap<PeerId, Witness, fnv::FnvBuildHasher>,
+
+    /// Connected peers that aren't witnesses, with their discovery origin. Used to pick a
+    /// replacement when a witness disconnects or when a witness is replaced with a peer whose
+    /// discovery origin isn't witnessed yet.
+    candidates: HashMap<PeerId, Option<PeerId>, fnv::FnvBuildHasher>,
+
+    /// For each block height, the first block claimed as finalized at this height by a witness.
+    claims: BTreeMap<u64, Claim>,
+
+    /// Recent local finalized blocks, indexed by height.
+    local_finalized: BTreeMap<u64, [u8; 32]>,
+
+    /// Height of the local finalized block.
+    local_finalized_block_number: u64,
+}
+
+struct Witness {
+    /// See [`crate::network_service::Event::Connected::discovery_origin`].
+    discovery_origin: Option<PeerId>,
+    /// Latest finalized block height reported by the peer, if any.
+    finalized_block_height: Option<u64>,
+    /// Height of the local finalized block when the peer has been selected as a witness.
+    selected_at_local_finalized: u64,
+    /// `true` if a [`FinalityConsistencyEvent::Withheld`] has been generated for this peer and
+    /// the peer hasn't caught up since.
+    withholding_reported: bool,
+}
+
+struct Claim {
+    block_hash: [u8; 32],
+    peer_id: PeerId,
+    /// `true` if a [`FinalityConsistencyEvent::Divergence`] has been generated for this height.
+    divergence_reported: bool,
+}
+
+impl FinalityConsistency {
+    /// Initializes a new state machine. `local_finalized` is the current local finalized block.
+    pub(super) fn new(local_finalized: (u64, [u8; 32])) -> Self {
+        let mut me = FinalityConsistency {
+            witnesses: HashMap::with_capacity_and_hasher(MAX_WITNESSES, Default::default()),
+            candidates: HashMap::with_capacity_and_hasher(0, Default::default()),
+            claims: BTreeMap::new(),
+            local_finalized: BTreeMap::new(),
+            local_finalized_block_number: local_finalized.0,
+        };
+        me.local_finalized
+            .insert(local_finalized.0, local_finalized.1);
+        me
+    }
+
+    /// Returns the list of peers currently used as witnesses.
+    #[cfg(test)]
+    pub(super) fn witnesses(&self) -> impl Iterator<Item = &PeerId> {
+        self.witnesses.keys()
+    }
+
+    /// Notifies the state machine of a new gossip link with a peer.
+    pub(super) fn peer_connected(&mut self, peer_id: PeerId, discovery_origin: Option<PeerId>) {
+        if self.witnesses.len() < MAX_WITNESSES {
+            self.add_witness(peer_id, discovery_origin);
+            return;
+        }
+
+        // All the slots are taken. If the origin of the peer isn't witnessed yet, it replaces a
+        // witness whose origin is shared with another witness.
+        let demoted = if !self.origin_is_witnessed(&peer_id, discovery_origin.as_ref()) {
+            self.witnesses
+                .iter()
+                .find(|(w, witness)| self.origin_is_witnessed(w, witness.discovery_origin.as_ref()))
+                .map(|(w, _)| w.clone())
+        } else {
+            None
+        };
+
+        if let Some(demoted) = demoted {
+            let witness = self.witnesses.remove(&demoted).unwrap();
+            self.candidates.insert(demoted, witness.discovery_origin);
+            self.add_witness(peer_id, discovery_origin);
+        } else {
+            self.candidates.insert(peer_id, discovery_origin);
+        }
+    }
+
+    /// Notifies the state machine that the gossip link with a peer has been closed.
+    pub(super) fn peer_disconnected(&mut self, peer_id: &PeerId) {
+        if self.candidates.remove(peer_id).is_some() || self.witnesses.remove(peer_id).is_none() {
+            return;
+        }
+
+        // A witness has left. Promote a candidate, preferably one whose origin isn't witnessed
+        // yet.
+        let replacement = self
+            .candidates
+            .iter()
+            .find(|(candidate, origin)| !self.origin_is_witnessed(candidate, origin.as_ref()))
+            .or_else(|| self.candidates.iter().next())
+            .map(|(candidate, _)| candidate.clone());
+        if let Some(replacement) = replacement {
+            let discovery_origin = self.candidates.remove(&replacement).unwrap();
+            self.add_witness(replacement, discovery_origin);
+        }
+    }
+
+    /// Notifies the state machine of a GrandPa neighbor packet sent by a peer.
+    pub(super) fn neighbor_packet(
+        &mut self,
+        peer_id: &PeerId,
+        finalized_block_height: u64,
+    ) -> Option<FinalityConsistencyEvent> {
+        let witness = self.witnesses.get_mut(peer_id)?;
+        witness.finalized_block_height = Some(finalized_block_height);
+        Self::check_withholding(self.local_finalized_block_number, peer_id, witness)
+    }
+
+    /// Notifies the state machine of a GrandPa commit message sent by a peer.
+    ///
+    /// The signatures of the commit message must have been verified beforehand, as otherwise any
+    /// witness could report fake divergences.
+    pub(super) fn commit_message(
+        &mut self,
+        peer_id: &PeerId,
+        target_number: u64,
+        target_hash: [u8; 32],
+    ) -> Option<FinalityConsistencyEvent> {
+        if !self.witnesses.contains_key(peer_id) {
+            return None;
+        }
+
+        if target_number
+            > self
+                .local_finalized_block_number
+                .saturating_add(MAX_CLAIM_DISTANCE)
+        {
+            return None;
+        }
+
+        if let Some(local_hash) = self.local_finalized.get(&target_number) {
+            if *local_hash != target_hash {
+                let claim = self.claims.entry(target_number).or_insert(Claim {
+                    block_hash: target_hash,
+                    peer_id: peer_id.clone(),
+                    divergence_reported: false,
+                });
+                if claim.divergence_reported {
+                    return None;
+                }
+                claim.divergence_reported = true;
+                return Some(FinalityConsistencyEvent::Divergence {
+                    block_number: target_number,
+                    peer_id: peer_id.clone(),
+                    block_hash: target_hash,
+                    conflicting_peer_id: None,
+                    conflicting_block_hash: *local_hash,
+                });
+            }
+        }
+
+        if let Some(claim) = self.claims.get_mut(&target_number) {
+            if claim.block_hash != target_hash
+                && claim.peer_id != *peer_id
+                && !claim.divergence_reported
+            {
+                claim.divergence_reported = true;
+                return Some(FinalityConsistencyEvent::Divergence {
+                    block_number: target_number,
+                    peer_id: peer_id.clone(),
+                    block_hash: target_hash,
+                    conflicting_peer_id: Some(claim.peer_id.clone()),
+                    conflicting_block_hash: claim.block_hash,
+                });
+            }
+            return None;
+        }
+
+        // Don't track claims about blocks that are too old.
+        if self
+            .claims
+            .last_key_value()
+            .is_some_and(|(n, _)| target_number < n.saturating_sub(MAX_TRACKED_HEIGHTS as u64))
+        {
+            return None;
+        }
+
+        self.claims.insert(
+            target_number,
+            Claim {
+                block_hash: target_hash,
+                peer_id: peer_id.clone(),
+                divergence_reported: false,
+            },
+        );
+        while self.claims.len() > MAX_TRACKED_HEIGHTS {
+            self.claims.pop_first();
+        }
+        None
+    }
+
+    /// Notifies the state machine that the local finalized block has changed.
+    pub(super) fn local_finalized(
+        &mut self,
+        block_number: u64,
+        block_hash: [u8; 32],
+    ) -> Vec<FinalityConsistencyEvent> {
+        let mut events = Vec::new();
+
+        self.local_finalized_block_number = block_number;
+        self.local_finalized.insert(block_number, block_hash);
+        while self.local_finalized.len() > MAX_TRACKED_HEIGHTS {
+            self.local_finalized.pop_first();
+        }
+
+        if let Some(claim) = self.claims.get_mut(&block_number) {
+            if claim.block_hash != block_hash && !claim.divergence_reported {
+                claim.divergence_reported = true;
+                events.push(FinalityConsistencyEvent::Divergence {
+                    block_number,
+                    peer_id: claim.peer_id.clone(),
+                    block_hash: claim.block_hash,
+                    conflicting_peer_id: None,
+                    conflicting_block_hash: block_hash,
+                });
+            }
+        }
+
+        for (peer_id, witness) in &mut self.witnesses {
+            if let Some(event) = Self::check_withholding(block_number, peer_id, witness) {
+                events.push(event);
+            }
+        }
+
+        events
+    }
+
+    fn add_witness(&mut self, peer_id: PeerId, discovery_origin: Option<PeerId>) {
+        debug_assert!(self.witnesses.len() < MAX_WITNESSES);
+        self.witnesses.insert(
+            peer_id,
+            Witness {
+                discovery_origin,
+                finalized_block_height: None,
+                selected_at_local_finalized: self.local_finalized_block_number,
+                withholding_reported: false,
+            },
+        );
+    }
+
+    fn check_withholding(
+        local_finalized_block_number: u64,
+        peer_id: &PeerId,
+        witness: &mut Witness,
+    ) -> Option<FinalityConsistencyEvent> {
+        // A witness that hasn't reported anything yet is compared as if it had reported the
+        // local finalized block height at the time when it has been selected.
+        let peer_finalized_block_number = witness.finalized_block_height;
+        if peer_finalized_block_number
+            .unwrap_or(witness.selected_at_local_finalized)
+            .saturating_add(WITHHOLDING_THRESHOLD)
+            >= local_finalized_block_number
+        {
+            witness.withholding_reported = false;
+            return None;
+        }
+
+        if witness.withholding_reported {
+            return None;
+        }
+
+        witness.withholding_reported = true;
+        Some(FinalityConsistencyEvent::Withheld {
+            peer_id: peer_id.clone(),
+            peer_finalized_block_number,
+            local_finalized_block_number,
+        })
+    }
+
+    /// Returns `true` if a witness other than `peer_id` has the same discovery origin. Peers
+    /// whose origin is unknown are considered as being their own origin.
+    fn origin_is_witnessed(&self, peer_id: &PeerId, discovery_origin: Option<&PeerId>) -> bool {
+        let key = discovery_origin.unwrap_or(peer_id);
+        self.witnesses.iter().any(|(w, witness)| {
+            w != peer_id && witness.discovery_origin.as_ref().unwrap_or(w) == key
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FinalityConsistency, FinalityConsistencyEvent, MAX_WITNESSES};
+    use smoldot::libp2p::{peer_id::PublicKey, PeerId};
+
+    fn peer(n: u8) -> PeerId {
+        PeerId::from_public_key(&PublicKey::Ed25519([n; 32]))
+    }
+
+    #[test]
+    fn distinct_origins_preferred() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        for n in 0..MAX_WITNESSES {
+            state.peer_connected(peer(n as u8), Some(peer(100)));
+        }
+        assert_eq!(state.witnesses().count(), MAX_WITNESSES);
+
+        // A peer with a new origin replaces a witness with a shared origin.
+        state.peer_connected(peer(50), Some(peer(101)));
+        assert_eq!(state.witnesses().count(), MAX_WITNESSES);
+        assert!(state.witnesses().any(|p| *p == peer(50)));
+
+        // A peer whose origin is already witnessed doesn't replace anything.
+        state.peer_connected(peer(51), Some(peer(101)));
+        assert!(!state.witnesses().any(|p| *p == peer(51)));
+
+        // Claims from non-witnesses are ignored.
+        assert!(state.commit_message(&peer(51), 5, [1; 32]).is_none());
+
+        // When a witness leaves, a candidate whose origin isn't witnessed anymore is preferred.
+        state.peer_disconnected(&peer(50));
+        assert_eq!(state.witnesses().count(), MAX_WITNESSES);
+        assert!(state.witnesses().any(|p| *p == peer(51)));
+
+        // When no such candidate exists, any candidate replaces the witness.
+        state.peer_disconnected(&peer(51));
+        assert_eq!(state.witnesses().count(), MAX_WITNESSES);
+    }
+
+    #[test]
+    fn divergence_with_single_origin() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        state.peer_connected(peer(1), Some(peer(100)));
+        state.peer_connected(peer(2), Some(peer(100)));
+
+        assert!(state.commit_message(&peer(1), 5, [1; 32]).is_none());
+        assert!(state.commit_message(&peer(2), 5, [2; 32]).is_some());
+    }
+
+    #[test]
+    fn divergence_between_peers() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        state.peer_connected(peer(1), None);
+        state.peer_connected(peer(2), None);
+
+        assert!(state.commit_message(&peer(1), 5, [1; 32]).is_none());
+        assert!(state.commit_message(&peer(2), 5, [1; 32]).is_none());
+        assert!(state.commit_message(&peer(2), 6, [2; 32]).is_none());
+        match state.commit_message(&peer(1), 6, [3; 32]) {
+            Some(FinalityConsistencyEvent::Divergence {
+                block_number: 6,
+                conflicting_peer_id: Some(p),
+                ..
+            }) if p == peer(2) => {}
+            _ => panic!(),
+        }
+        // Only reported once.
+        assert!(state.commit_message(&peer(1), 6, [3; 32]).is_none());
+    }
+
+    #[test]
+    fn claims_far_ahead_ignored() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        state.peer_connected(peer(1), None);
+        state.peer_connected(peer(2), None);
+
+        // A claim far ahead of the local finalized block doesn't prevent claims about lower
+        // heights from being tracked.
+        assert!(state.commit_message(&peer(1), u64::MAX, [1; 32]).is_none());
+        assert!(state.commit_message(&peer(1), 5, [1; 32]).is_none());
+        assert!(state.commit_message(&peer(2), 5, [2; 32]).is_some());
+    }
+
+    #[test]
+    fn divergence_with_local() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        state.peer_connected(peer(1), None);
+        assert!(state.commit_message(&peer(1), 5, [1; 32]).is_none());
+        let events = state.local_finalized(5, [2; 32]);
+        assert!(matches!(
+            &events[..],
+            [FinalityConsistencyEvent::Divergence {
+                block_number: 5,
+                conflicting_peer_id: None,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn withheld_finality() {
+        let mut state = FinalityConsistency::new((0, [0; 32]));
+        state.peer_connected(peer(1), None);
+        assert!(state.neighbor_packet(&peer(1), 10).is_none());
+        assert!(state.local_finalized(20, [0; 32]).is_empty());
+        let events = state.local_finalized(100, [0; 32]);
+        assert!(matches!(
+            &events[..],
+            [FinalityConsistencyEvent::Withheld {
+                peer_finalized_block_number: Some(10),
+                local_finalized_block_number: 100,
+                ..
+            }]
+        ));
+        assert!(state.local_finalized(101, [0; 32]).is_empty());
+        assert!(state.neighbor_packet(&peer(1), 100).is_none());
+    }
+
+    #[test]
+    fn silent_witness_withholds_finality() {
+        let mut state = FinalityConsistency::new((10, [0; 32]));
+        state.peer_connected(peer(1), None);
+        assert!(state.local_finalized(42, [0; 32]).is_empty());
+        let events = state.local_finalized(43, [0; 32]);
+        assert!(matches!(
+            &events[..],
+            [FinalityConsistencyEvent::Withheld {
+                peer_finalized_block_number: None,
+                local_finalized_block_number: 43,
+                ..
+            }]
+        ));
+        assert!(state.local_finalized(44, [0; 32]).is_empty());
+    }
+}
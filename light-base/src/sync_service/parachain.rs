@@ -20,7 +20,7 @@ use crate::{log, network_service, platform::PlatformRef, runtime_service, util};
 
 use alloc::{borrow::ToOwned as _, boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use core::{
-    mem,
+    iter, mem,
     num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
     time::Duration,
@@ -40,7 +40,9 @@ pub(super) async fn start_parachain<TPlat: PlatformRef>(
     finalized_block_header: Vec<u8>,
     block_number_bytes: usize,
     relay_chain_sync: Arc<runtime_service::RuntimeService<TPlat>>,
+    relay_chain_sync_service: Arc<super::SyncService<TPlat>>,
     parachain_id: u32,
+    head_data_format: para::HeadDataFormat,
     from_foreground: Pin<Box<async_channel::Receiver<ToBackground>>>,
     network_service: Arc<network_service::NetworkServiceChain<TPlat>>,
 ) {
@@ -49,9 +51,14 @@ pub(super) async fn start_parachain<TPlat: PlatformRef>(
         from_foreground,
         block_number_bytes,
         parachain_id,
+        head_data_format,
         from_network_service: None,
         network_service,
         obsolete_finalized_parahead: finalized_block_header,
+        finalized_parablocks_inclusion: lru::LruCache::with_hasher(
+            NonZeroUsize::new(256).unwrap(),
+            Default::default(),
+        ),
         sync_sources: HashMap::with_capacity_and_hasher(
             0,
             util::SipHasherBuild::new({
@@ -72,6 +79,7 @@ pub(super) async fn start_parachain<TPlat: PlatformRef>(
             },
         },
         relay_chain_sync,
+        relay_chain_sync_service,
         platform,
     }
     .run()
@@ -95,6 +103,9 @@ struct ParachainBackgroundTask<TPlat: PlatformRef> {
     /// Id of the parachain registered within the relay chain. Chosen by the user.
     parachain_id: u32,
 
+    /// How to extract the parachain block header from the head data found in the relay chain.
+    head_data_format: para::HeadDataFormat,
+
     /// Networking service connected to the peer-to-peer network of the parachain.
     network_service: Arc<network_service::NetworkServiceChain<TPlat>>,
 
@@ -104,12 +115,20 @@ struct ParachainBackgroundTask<TPlat: PlatformRef> {
     /// Runtime service of the relay chain.
     relay_chain_sync: Arc<runtime_service::RuntimeService<TPlat>>,
 
+    /// Sync service of the relay chain. Used to read the parachain heads from the relay chain
+    /// storage.
+    relay_chain_sync_service: Arc<super::SyncService<TPlat>>,
+
     /// Last-known finalized parachain header. Can be very old and obsolete.
     /// Updated after we successfully fetch the parachain head of a relay chain finalized block,
     /// and left untouched if the fetch fails.
     /// Initialized to the parachain genesis block header.
     obsolete_finalized_parahead: Vec<u8>,
 
+    /// Hashes of recently-finalized parachain blocks, associated with the hash of the finalized
+    /// relay chain block in which they have been included.
+    finalized_parablocks_inclusion: lru::LruCache<[u8; 32], [u8; 32], fnv::FnvBuildHasher>,
+
     /// List of parachain network sources.
     ///
     /// Values are their role, and self-reported best block when we connected to them. This best
@@ -169,6 +188,10 @@ struct ParachainBackgroundTaskAfterSubscription<TPlat: PlatformRef> {
     /// All block in the tree has an associated parachain head behind an `Option`. This `Option`
     /// always contains `Some`, except for the "virtual" root block for which it is `None`.
     ///
+    /// The finalized parachain block is the block included in the output finalized relay chain
+    /// block, while the best parachain block is the latest block pending availability in the
+    /// output best relay chain block (see [`Parahead`]).
+    ///
     /// If the output finalized block has a parachain head equal to `None`, it therefore means
     /// that no finalized parachain head is known yet.
     /// Note that, when it is the case, `SubscribeAll` messages from the frontend are still
@@ -178,7 +201,7 @@ struct ParachainBackgroundTaskAfterSubscription<TPlat: PlatformRef> {
     /// The set of blocks in this tree whose parachain block hasn't been fetched yet is the same
     /// as the set of blocks that is maintained pinned on the runtime service. Blocks are unpinned
     /// when their parachain head fetching succeeds or when they are removed from the tree.
-    async_tree: async_tree::AsyncTree<TPlat::Instant, [u8; 32], Option<Vec<u8>>>,
+    async_tree: async_tree::AsyncTree<TPlat::Instant, [u8; 32], Option<Parahead>>,
 
     /// If `true`, [`ParachainBackgroundTaskAfterSubscription::async_tree`] might need to
     /// be advanced.
@@ -191,7 +214,7 @@ struct ParachainBackgroundTaskAfterSubscription<TPlat: PlatformRef> {
     /// alive for longer than this container, and by the fact that we unpin block after a
     /// fetching operation has finished and that we never fetch twice for the same block.
     in_progress_paraheads: stream::FuturesUnordered<
-        future::BoxFuture<'static, (async_tree::AsyncOpId, Result<Vec<u8>, ParaheadError>)>,
+        future::BoxFuture<'static, (async_tree::AsyncOpId, Result<Parahead, ParaheadError>)>,
     >,
 
     /// Future that is ready when we need to start a new parachain head fetch operation.
@@ -212,7 +235,7 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                 StartParaheadFetch,
                 ParaheadFetchFinished {
                     async_op_id: async_tree::AsyncOpId,
-                    parahead_result: Result<Vec<u8>, ParaheadError>,
+                    parahead_result: Result<Parahead, ParaheadError>,
                 },
                 Notification(runtime_service::Notification),
                 SubscriptionDead,
@@ -409,14 +432,17 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
 
                         match update {
                             async_tree::OutputUpdate::Finalized {
+                                user_data: relay_block_hash,
                                 former_finalized_async_op_user_data: former_finalized_parahead,
                                 pruned_blocks,
                                 best_output_block_updated,
                                 ..
-                            } if *runtime_subscription
+                            } if runtime_subscription
                                 .async_tree
                                 .output_finalized_async_user_data()
-                                != former_finalized_parahead =>
+                                .as_ref()
+                                .map(|p| &p.included)
+                                != former_finalized_parahead.as_ref().map(|p| &p.included) =>
                             {
                                 let new_finalized_parahead = runtime_subscription
                                     .async_tree
@@ -434,11 +460,17 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                 }
 
                                 let hash = header::hash_from_scale_encoded_header(
-                                    new_finalized_parahead.as_ref().unwrap(),
+                                    &new_finalized_parahead.as_ref().unwrap().included,
                                 );
 
                                 self.obsolete_finalized_parahead =
-                                    new_finalized_parahead.clone().unwrap();
+                                    new_finalized_parahead.as_ref().unwrap().included.clone();
+
+                                // Relay chain blocks are finalized one by one. Since the
+                                // included parachain block has changed, it has been included
+                                // in this relay chain block.
+                                self.finalized_parablocks_inclusion
+                                    .put(hash, relay_block_hash);
 
                                 // Must unpin the pruned blocks if they haven't already been unpinned.
                                 let mut pruned_blocks_hashes =
@@ -458,18 +490,29 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                     Debug,
                                     &self.log_target,
                                     "subscriptions-notify-parablock-finalized",
-                                    hash = HashDisplay(&hash)
+                                    hash = HashDisplay(&hash),
+                                    relay_block_hash = HashDisplay(&relay_block_hash)
                                 );
 
-                                let best_block_hash = runtime_subscription
-                                    .async_tree
-                                    .output_best_block_index()
-                                    .map(|(_, parahead)| {
-                                        header::hash_from_scale_encoded_header(
-                                            parahead.as_ref().unwrap(),
-                                        )
-                                    })
-                                    .unwrap_or(hash);
+                                // The best parachain block might be a block pending availability
+                                // in the finalized relay chain block.
+                                let best_block_hash = header::hash_from_scale_encoded_header(
+                                    runtime_subscription
+                                        .async_tree
+                                        .output_best_block_index()
+                                        .map(|(_, parahead)| parahead.as_ref().unwrap())
+                                        .unwrap_or_else(|| {
+                                            runtime_subscription
+                                                .async_tree
+                                                .output_finalized_async_user_data()
+                                                .as_ref()
+                                                .unwrap()
+                                        })
+                                        .best(),
+                                );
+                                let best_block_changed = best_output_block_updated
+                                    || runtime_subscription.reported_best_parahead_hash
+                                        != Some(best_block_hash);
                                 runtime_subscription.reported_best_parahead_hash =
                                     Some(best_block_hash);
 
@@ -481,7 +524,7 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                         runtime_subscription.all_subscriptions.swap_remove(index);
                                     let notif = super::Notification::Finalized {
                                         hash,
-                                        best_block_hash_if_changed: if best_output_block_updated {
+                                        best_block_hash_if_changed: if best_block_changed {
                                             Some(best_block_hash)
                                         } else {
                                             None
@@ -506,15 +549,16 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                     None => continue,
                                 };
 
-                                // Calculate hash of the parablock corresponding to the new best relay
-                                // chain block.
-                                let parahash = header::hash_from_scale_encoded_header(
-                                    runtime_subscription
-                                        .async_tree
-                                        .output_best_block_index()
-                                        .map(|(_, b)| b.as_ref().unwrap())
-                                        .unwrap_or(finalized_parahead),
-                                );
+                                // Calculate hash of the best parablock corresponding to the new
+                                // best relay chain block.
+                                let best_parahead = runtime_subscription
+                                    .async_tree
+                                    .output_best_block_index()
+                                    .map(|(_, b)| b.as_ref().unwrap())
+                                    .unwrap_or(finalized_parahead)
+                                    .best();
+                                let parahash =
+                                    header::hash_from_scale_encoded_header(best_parahead);
 
                                 if runtime_subscription.reported_best_parahead_hash.as_ref()
                                     != Some(&parahash)
@@ -525,7 +569,7 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                     // The networking service needs to be kept up to date with what the local
                                     // node considers as the best block.
                                     if let Ok(header) =
-                                        header::decode(finalized_parahead, self.block_number_bytes)
+                                        header::decode(best_parahead, self.block_number_bytes)
                                     {
                                         self.network_service
                                             .set_local_best_block(parahash, header.number)
@@ -563,14 +607,12 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                 // below, so deconstruct `block` beforehand.
                                 let is_new_best = block.is_new_best;
                                 let block_index = block.index;
-                                let scale_encoded_header: Vec<u8> = runtime_subscription
+                                let parahead: Parahead = runtime_subscription
                                     .async_tree
                                     .block_async_user_data(block.index)
                                     .unwrap()
                                     .clone()
                                     .unwrap();
-                                let parahash =
-                                    header::hash_from_scale_encoded_header(&scale_encoded_header);
 
                                 // Do not report anything to subscriptions if no finalized parahead is
                                 // known yet.
@@ -582,109 +624,148 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                     None => continue,
                                 };
 
-                                // Do not report the new block if it has already been reported in the
-                                // past. This covers situations where the parahead is identical to the
-                                // relay chain's parent's parahead, but also situations where multiple
-                                // sibling relay chain blocks have the same parahead.
-                                if *finalized_parahead == scale_encoded_header
-                                    || runtime_subscription
-                                        .async_tree
-                                        .input_output_iter_unordered()
-                                        .filter(|item| item.id != block_index)
-                                        .filter_map(|item| item.async_op_user_data)
-                                        .any(|item| item.as_ref() == Some(&scale_encoded_header))
+                                // The included parachain block and the blocks pending
+                                // availability are reported one after the other. Only the last
+                                // one can become the new best block.
+                                let num_parablocks = parahead.iter().count();
+                                let mut previous_parahash = None;
+
+                                for (parablock_index, scale_encoded_header) in
+                                    parahead.iter().enumerate()
                                 {
-                                    // While the parablock has already been reported, it is possible that
-                                    // it becomes the new best block while it wasn't before, in which
-                                    // case we should send a notification.
-                                    if is_new_best
-                                        && runtime_subscription.reported_best_parahead_hash.as_ref()
-                                            != Some(&parahash)
-                                    {
-                                        runtime_subscription.reported_best_parahead_hash =
-                                            Some(parahash);
+                                    let is_new_best =
+                                        is_new_best && parablock_index == num_parablocks - 1;
+                                    let parahash = header::hash_from_scale_encoded_header(
+                                        scale_encoded_header,
+                                    );
 
-                                        // The networking service needs to be kept up to date with what the
-                                        // local node considers as the best block.
-                                        if let Ok(header) = header::decode(
-                                            finalized_parahead,
-                                            self.block_number_bytes,
-                                        ) {
-                                            self.network_service
-                                                .set_local_best_block(parahash, header.number)
-                                                .await;
-                                        }
+                                    // Do not report the parablock if it has already been reported
+                                    // in the past. This covers situations where the parahead is
+                                    // identical to the relay chain's parent's parahead, situations
+                                    // where a block pending availability in the relay chain's
+                                    // parent is now included, but also situations where multiple
+                                    // sibling relay chain blocks have the same parahead.
+                                    if finalized_parahead.iter().any(|h| h == scale_encoded_header)
+                                        || runtime_subscription
+                                            .async_tree
+                                            .input_output_iter_unordered()
+                                            .filter(|item| item.id != block_index)
+                                            .filter_map(|item| item.async_op_user_data)
+                                            .any(|item| {
+                                                matches!(item, Some(p) if p.iter().any(|h| h == scale_encoded_header))
+                                            })
+                                    {
+                                        previous_parahash = Some(parahash);
+
+                                        // While the parablock has already been reported, it is possible that
+                                        // it becomes the new best block while it wasn't before, in which
+                                        // case we should send a notification.
+                                        if is_new_best
+                                            && runtime_subscription
+                                                .reported_best_parahead_hash
+                                                .as_ref()
+                                                != Some(&parahash)
+                                        {
+                                            runtime_subscription.reported_best_parahead_hash =
+                                                Some(parahash);
+
+                                            // The networking service needs to be kept up to date with what the
+                                            // local node considers as the best block.
+                                            if let Ok(header) = header::decode(
+                                                scale_encoded_header,
+                                                self.block_number_bytes,
+                                            ) {
+                                                self.network_service
+                                                    .set_local_best_block(parahash, header.number)
+                                                    .await;
+                                            }
 
-                                        log!(
-                                            &self.platform,
-                                            Debug,
-                                            &self.log_target,
-                                            "subscriptions-notify-best-block-changed",
-                                            hash = HashDisplay(&parahash)
-                                        );
+                                            log!(
+                                                &self.platform,
+                                                Debug,
+                                                &self.log_target,
+                                                "subscriptions-notify-best-block-changed",
+                                                hash = HashDisplay(&parahash)
+                                            );
 
-                                        // Elements in `all_subscriptions` are removed one by one and
-                                        // inserted back if the channel is still open.
-                                        for index in
-                                            (0..runtime_subscription.all_subscriptions.len()).rev()
-                                        {
-                                            let sender = runtime_subscription
+                                            // Elements in `all_subscriptions` are removed one by one and
+                                            // inserted back if the channel is still open.
+                                            for index in (0..runtime_subscription
                                                 .all_subscriptions
-                                                .swap_remove(index);
-                                            let notif = super::Notification::BestBlockChanged {
-                                                hash: parahash,
-                                            };
-                                            if sender.try_send(notif).is_ok() {
-                                                runtime_subscription.all_subscriptions.push(sender);
+                                                .len())
+                                                .rev()
+                                            {
+                                                let sender = runtime_subscription
+                                                    .all_subscriptions
+                                                    .swap_remove(index);
+                                                let notif = super::Notification::BestBlockChanged {
+                                                    hash: parahash,
+                                                };
+                                                if sender.try_send(notif).is_ok() {
+                                                    runtime_subscription
+                                                        .all_subscriptions
+                                                        .push(sender);
+                                                }
                                             }
                                         }
-                                    }
 
-                                    continue;
-                                }
+                                        continue;
+                                    }
 
-                                log!(
-                                    &self.platform,
-                                    Debug,
-                                    &self.log_target,
-                                    "subscriptions-notify-new-parablock",
-                                    hash = HashDisplay(&parahash)
-                                );
+                                    log!(
+                                        &self.platform,
+                                        Debug,
+                                        &self.log_target,
+                                        "subscriptions-notify-new-parablock",
+                                        hash = HashDisplay(&parahash),
+                                        pending_availability = parablock_index != 0
+                                    );
 
-                                if is_new_best {
-                                    runtime_subscription.reported_best_parahead_hash =
-                                        Some(parahash);
-                                }
+                                    if is_new_best {
+                                        runtime_subscription.reported_best_parahead_hash =
+                                            Some(parahash);
+                                    }
 
-                                let parent_hash = header::hash_from_scale_encoded_header(
-                                    runtime_subscription
-                                        .async_tree
-                                        .parent(block_index)
-                                        .map(|idx| {
+                                    // The parent of a block pending availability is the previous
+                                    // parablock of the same relay chain block. The parent of the
+                                    // included parablock is the best parablock of the parent relay
+                                    // chain block.
+                                    let parent_hash = previous_parahash.unwrap_or_else(|| {
+                                        header::hash_from_scale_encoded_header(
                                             runtime_subscription
                                                 .async_tree
-                                                .block_async_user_data(idx)
-                                                .unwrap()
-                                                .as_ref()
-                                                .unwrap()
-                                        })
-                                        .unwrap_or(finalized_parahead),
-                                );
+                                                .parent(block_index)
+                                                .map(|idx| {
+                                                    runtime_subscription
+                                                        .async_tree
+                                                        .block_async_user_data(idx)
+                                                        .unwrap()
+                                                        .as_ref()
+                                                        .unwrap()
+                                                })
+                                                .unwrap_or(finalized_parahead)
+                                                .best(),
+                                        )
+                                    });
+                                    previous_parahash = Some(parahash);
 
-                                // Elements in `all_subscriptions` are removed one by one and
-                                // inserted back if the channel is still open.
-                                for index in (0..runtime_subscription.all_subscriptions.len()).rev()
-                                {
-                                    let sender =
-                                        runtime_subscription.all_subscriptions.swap_remove(index);
-                                    let notif =
-                                        super::Notification::Block(super::BlockNotification {
-                                            is_new_best,
-                                            parent_hash,
-                                            scale_encoded_header: scale_encoded_header.clone(),
-                                        });
-                                    if sender.try_send(notif).is_ok() {
-                                        runtime_subscription.all_subscriptions.push(sender);
+                                    // Elements in `all_subscriptions` are removed one by one and
+                                    // inserted back if the channel is still open.
+                                    for index in
+                                        (0..runtime_subscription.all_subscriptions.len()).rev()
+                                    {
+                                        let sender = runtime_subscription
+                                            .all_subscriptions
+                                            .swap_remove(index);
+                                        let notif =
+                                            super::Notification::Block(super::BlockNotification {
+                                                is_new_best,
+                                                parent_hash,
+                                                scale_encoded_header: scale_encoded_header.clone(),
+                                            });
+                                        if sender.try_send(notif).is_ok() {
+                                            runtime_subscription.all_subscriptions.push(sender);
+                                        }
                                     }
                                 }
                             }
@@ -736,22 +817,52 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
 
                             runtime_subscription.in_progress_paraheads.push({
                                 let relay_chain_sync = self.relay_chain_sync.clone();
+                                let relay_chain_sync_service =
+                                    self.relay_chain_sync_service.clone();
                                 let subscription_id =
                                     runtime_subscription.relay_chain_subscribe_all.id();
                                 let block_hash = runtime_subscription.async_tree[op.block_index];
                                 let async_op_id = op.id;
                                 let parachain_id = self.parachain_id;
+                                let head_data_format = self.head_data_format;
+                                let block_number_bytes = self.block_number_bytes;
+                                let platform = self.platform.clone();
+                                let log_target = self.log_target.clone();
                                 Box::pin(async move {
-                                    (
-                                        async_op_id,
-                                        fetch_parahead(
-                                            &relay_chain_sync,
-                                            subscription_id,
-                                            parachain_id,
-                                            &block_hash,
-                                        )
-                                        .await,
+                                    let result = fetch_parahead(
+                                        &relay_chain_sync,
+                                        &relay_chain_sync_service,
+                                        subscription_id,
+                                        parachain_id,
+                                        head_data_format,
+                                        block_number_bytes,
+                                        &block_hash,
                                     )
+                                    .await
+                                    .map(|fetch| {
+                                        if let Some(error) = fetch.storage_decode_error {
+                                            log!(
+                                                &platform,
+                                                Debug,
+                                                &log_target,
+                                                "parahead-storage-decode-error",
+                                                relay_block_hash = HashDisplay(&block_hash),
+                                                error
+                                            );
+                                        }
+                                        if let Some(error) = fetch.candidates_error {
+                                            log!(
+                                                &platform,
+                                                Debug,
+                                                &log_target,
+                                                "candidates-pending-availability-fetch-error",
+                                                relay_block_hash = HashDisplay(&block_hash),
+                                                error
+                                            );
+                                        }
+                                        fetch.parahead
+                                    });
+                                    (async_op_id, result)
                                 })
                             });
 
@@ -896,9 +1007,10 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                         Debug,
                         &self.log_target,
                         "parahead-fetch-operation-success",
-                        parahead_hash = HashDisplay(
-                            blake2_rfc::blake2b::blake2b(32, b"", &parahead).as_bytes()
-                        ),
+                        parahead_hash = HashDisplay(&header::hash_from_scale_encoded_header(
+                            &parahead.included
+                        )),
+                        num_pending_availability = parahead.pending.len(),
                         relay_blocks = runtime_subscription
                             .async_tree
                             .async_op_blocks(async_op_id)
@@ -1092,77 +1204,116 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                     {
                         // Finalized parahead is known.
                         let finalized_parahash =
-                            header::hash_from_scale_encoded_header(finalized_parahead);
+                            header::hash_from_scale_encoded_header(&finalized_parahead.included);
                         let _ = send_back.send(super::SubscribeAll {
-                            finalized_block_scale_encoded_header: finalized_parahead.clone(),
+                            finalized_block_scale_encoded_header: finalized_parahead
+                                .included
+                                .clone(),
                             finalized_block_runtime: None,
                             non_finalized_blocks_ancestry_order: {
                                 let mut list =
                                     Vec::<([u8; 32], super::BlockNotification)>::with_capacity(
                                         runtime_subscription
                                             .async_tree
-                                            .num_input_non_finalized_blocks(),
+                                            .num_input_non_finalized_blocks()
+                                            + finalized_parahead.pending.len(),
+                                    );
+
+                                // The parablocks pending availability in the finalized relay
+                                // chain block are descendants of the finalized parablock.
+                                let is_finalized_output_best = runtime_subscription
+                                    .async_tree
+                                    .output_best_block_index()
+                                    .is_none();
+                                let mut parent_hash = finalized_parahash;
+                                for (index, parablock) in
+                                    finalized_parahead.pending.iter().enumerate()
+                                {
+                                    let parablock_hash =
+                                        header::hash_from_scale_encoded_header(parablock);
+                                    list.push((
+                                        parablock_hash,
+                                        super::BlockNotification {
+                                            is_new_best: is_finalized_output_best
+                                                && index == finalized_parahead.pending.len() - 1,
+                                            scale_encoded_header: parablock.clone(),
+                                            parent_hash,
+                                        },
+                                    ));
+                                    parent_hash = parablock_hash;
+                                }
+                                let finalized_best_parahash =
+                                    header::hash_from_scale_encoded_header(
+                                        finalized_parahead.best(),
                                     );
 
                                 for relay_block in runtime_subscription
                                     .async_tree
                                     .input_output_iter_ancestry_order()
                                 {
-                                    let parablock = match relay_block.async_op_user_data {
+                                    let parahead = match relay_block.async_op_user_data {
                                         Some(b) => b.as_ref().unwrap(),
                                         None => continue,
                                     };
 
-                                    let parablock_hash =
-                                        header::hash_from_scale_encoded_header(parablock);
+                                    let num_parablocks = parahead.iter().count();
+                                    let mut previous_parablock_hash = None;
 
-                                    // TODO: O(n)
-                                    if let Some((_, entry)) =
-                                        list.iter_mut().find(|(h, _)| *h == parablock_hash)
+                                    for (parablock_index, parablock) in parahead.iter().enumerate()
                                     {
-                                        // Block is already in the list. Don't add it a second time.
-                                        if relay_block.is_output_best {
-                                            entry.is_new_best = true;
+                                        let is_best = relay_block.is_output_best
+                                            && parablock_index == num_parablocks - 1;
+                                        let parablock_hash =
+                                            header::hash_from_scale_encoded_header(parablock);
+
+                                        // TODO: O(n)
+                                        if let Some((_, entry)) =
+                                            list.iter_mut().find(|(h, _)| *h == parablock_hash)
+                                        {
+                                            // Block is already in the list. Don't add it a second time.
+                                            if is_best {
+                                                entry.is_new_best = true;
+                                            }
+                                            previous_parablock_hash = Some(parablock_hash);
+                                            continue;
+                                        }
+                                        if parablock_hash == finalized_parahash {
+                                            previous_parablock_hash = Some(parablock_hash);
+                                            continue;
                                         }
-                                        continue;
-                                    }
 
-                                    // Find the parent of the parablock. This is done by going through
-                                    // the ancestors of the corresponding relay chain block (until and
-                                    // including the finalized relay chain block) until we find one
-                                    // whose parablock is different from the parablock in question.
-                                    // If none is found, the parablock is the same as the finalized
-                                    // parablock.
-                                    let parent_hash = runtime_subscription
-                                        .async_tree
-                                        .ancestors(relay_block.id)
-                                        .find_map(|idx| {
-                                            let hash = header::hash_from_scale_encoded_header(
+                                        // Find the parent of the parablock. A parablock pending
+                                        // availability is the child of the previous parablock of
+                                        // the same relay chain block. Otherwise, this is done by
+                                        // going through the ancestors of the corresponding relay
+                                        // chain block (until and including the finalized relay
+                                        // chain block) until we find one whose best parablock is
+                                        // different from the parablock in question.
+                                        let parent_hash = previous_parablock_hash
+                                            .or_else(|| {
                                                 runtime_subscription
                                                     .async_tree
-                                                    .block_async_user_data(idx)
-                                                    .unwrap()
-                                                    .as_ref()
-                                                    .unwrap(),
-                                            );
-                                            if hash != parablock_hash {
-                                                Some(hash)
-                                            } else {
-                                                None
-                                            }
-                                        })
-                                        .or_else(|| {
-                                            if finalized_parahash != parablock_hash {
-                                                Some(finalized_parahash)
-                                            } else {
-                                                None
-                                            }
-                                        });
+                                                    .ancestors(relay_block.id)
+                                                    .find_map(|idx| {
+                                                        let hash =
+                                                            header::hash_from_scale_encoded_header(
+                                                                runtime_subscription
+                                                                    .async_tree
+                                                                    .block_async_user_data(idx)
+                                                                    .unwrap()
+                                                                    .as_ref()
+                                                                    .unwrap()
+                                                                    .best(),
+                                                            );
+                                                        if hash != parablock_hash {
+                                                            Some(hash)
+                                                        } else {
+                                                            None
+                                                        }
+                                                    })
+                                            })
+                                            .unwrap_or(finalized_best_parahash);
 
-                                    // `parent_hash` is `None` if the parablock is
-                                    // the same as the finalized parablock, in which case we
-                                    // don't add it to the list.
-                                    if let Some(parent_hash) = parent_hash {
                                         debug_assert!(
                                             list.iter().filter(|(h, _)| *h == parent_hash).count()
                                                 == 1
@@ -1171,11 +1322,12 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                                         list.push((
                                             parablock_hash,
                                             super::BlockNotification {
-                                                is_new_best: relay_block.is_output_best,
+                                                is_new_best: is_best,
                                                 scale_encoded_header: parablock.clone(),
                                                 parent_hash,
                                             },
                                         ));
+                                        previous_parablock_hash = Some(parablock_hash);
                                     }
                                 }
 
@@ -1238,6 +1390,33 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                     let _ = send_back.send(None);
                 }
 
+                (
+                    WakeUpReason::ForegroundMessage(ToBackground::SubscribeFinalityConsistency {
+                        send_back,
+                        buffer_size,
+                    }),
+                    _,
+                ) => {
+                    // Finality consistency checks aren't supported for parachains. The sender is
+                    // immediately dropped, which closes the channel.
+                    let (_, rx) = async_channel::bounded(buffer_size);
+                    let _ = send_back.send(rx);
+                }
+
+                (
+                    WakeUpReason::ForegroundMessage(ToBackground::ParachainBlockInclusion {
+                        block_hash,
+                        send_back,
+                    }),
+                    _,
+                ) => {
+                    let _ = send_back.send(
+                        self.finalized_parablocks_inclusion
+                            .get(&block_hash)
+                            .copied(),
+                    );
+                }
+
                 (WakeUpReason::MustSubscribeNetworkEvents, _) => {
                     debug_assert!(self.from_network_service.is_none());
                     self.sync_sources.clear();
@@ -1253,6 +1432,7 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
                         role,
                         best_block_number,
                         best_block_hash,
+                        ..
                     }),
                     _,
                 ) => {
@@ -1299,55 +1479,354 @@ impl<TPlat: PlatformRef> ParachainBackgroundTask<TPlat> {
     }
 }
 
+/// Parachain blocks corresponding to a relay chain block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Parahead {
+    /// SCALE-encoded header of the parachain block included in the relay chain block.
+    included: Vec<u8>,
+
+    /// SCALE-encoded headers of the parachain blocks pending availability in the relay chain
+    /// block. The first element is a child of [`Parahead::included`], and each following
+    /// element is a child of the previous one.
+    ///
+    /// Blocks pending availability have been backed by the relay chain, and are expected to be
+    /// included in a later relay chain block, but might also time out.
+    pending: Vec<Vec<u8>>,
+}
+
+impl Parahead {
+    /// Returns the SCALE-encoded header of the latest parachain block pending availability, or
+    /// of the included parachain block if there is none.
+    fn best(&self) -> &[u8] {
+        self.pending.last().unwrap_or(&self.included)
+    }
+
+    /// Iterates over the SCALE-encoded headers of the parachain blocks, starting with the
+    /// included block and followed with the blocks pending availability.
+    fn iter(&self) -> impl Iterator<Item = &Vec<u8>> {
+        iter::once(&self.included).chain(self.pending.iter())
+    }
+}
+
+/// Successful outcome of [`fetch_parahead`].
+struct ParaheadFetch {
+    /// Parachain blocks corresponding to the relay chain block.
+    parahead: Parahead,
+    /// Error while decoding the storage of the relay chain, in which case runtime calls have been
+    /// performed instead. This indicates that the storage layout of the relay chain is different
+    /// from the one we expect.
+    storage_decode_error: Option<para::Error>,
+    /// Error while obtaining the candidates pending availability, in which case
+    /// [`Parahead::pending`] is empty.
+    ///
+    /// The candidates pending availability are only used in order to report parachain blocks
+    /// earlier than when they are included. Failing to obtain them isn't a fatal error.
+    candidates_error: Option<ParaheadError>,
+}
+
+/// Fetches the parachain blocks corresponding to the given relay chain block.
 async fn fetch_parahead<TPlat: PlatformRef>(
     relay_chain_sync: &Arc<runtime_service::RuntimeService<TPlat>>,
+    relay_chain_sync_service: &Arc<super::SyncService<TPlat>>,
     subscription_id: runtime_service::SubscriptionId,
     parachain_id: u32,
+    head_data_format: para::HeadDataFormat,
+    block_number_bytes: usize,
     block_hash: &[u8; 32],
-) -> Result<Vec<u8>, ParaheadError> {
-    // Call `ParachainHost_persisted_validation_data` in order to know where the parachain is.
+) -> Result<ParaheadFetch, ParaheadError> {
     let (pinned_runtime, block_state_trie_root, block_number) = relay_chain_sync
         .pin_pinned_block_runtime(subscription_id, *block_hash)
         .await
         .map_err(ParaheadError::PinRuntimeError)?;
-    let success = relay_chain_sync
-        .runtime_call(
-            pinned_runtime,
+
+    // Try reading the head data and the candidates pending availability from the storage of the
+    // relay chain, as this is considerably cheaper than performing runtime calls.
+    let ParaheadFromStorage {
+        included_head_data,
+        candidates_head_data,
+        decode_error: storage_decode_error,
+    } = fetch_parahead_from_storage(
+        relay_chain_sync_service,
+        parachain_id,
+        block_number,
+        *block_hash,
+        block_state_trie_root,
+    )
+    .await?;
+
+    // If the head data isn't found in the storage, the storage layout of the relay chain is
+    // unknown, and we fall back to calling `ParachainHost_persisted_validation_data`.
+    let included_head_data = match included_head_data {
+        Some(head_data) => head_data,
+        None => {
+            let success = relay_chain_sync
+                .runtime_call(
+                    pinned_runtime.clone(),
+                    *block_hash,
+                    block_number,
+                    block_state_trie_root,
+                    para::PERSISTED_VALIDATION_FUNCTION_NAME.to_owned(),
+                    None, // TODO: /!\
+                    para::persisted_validation_data_parameters(
+                        parachain_id,
+                        para::OccupiedCoreAssumption::TimedOut,
+                    )
+                    .fold(Vec::new(), |mut a, b| {
+                        a.extend_from_slice(b.as_ref());
+                        a
+                    }),
+                    None,
+                    6,
+                    Duration::from_secs(10),
+                    NonZeroU32::new(2).unwrap(),
+                )
+                .await
+                .map_err(ParaheadError::RuntimeCall)?;
+
+            // Try decode the result of the runtime call.
+            // If this fails, it indicates an incompatibility between smoldot and the relay chain.
+            match para::decode_persisted_validation_data_return_value(
+                &success.output,
+                relay_chain_sync.block_number_bytes(),
+            ) {
+                Ok(Some(pvd)) => pvd.parent_head.to_vec(),
+                Ok(None) => return Err(ParaheadError::NoCore),
+                Err(error) => return Err(ParaheadError::InvalidRuntimeOutput(error)),
+            }
+        }
+    };
+
+    let included =
+        para::decode_head_data(&included_head_data, head_data_format, block_number_bytes)
+            .map_err(ParaheadError::InvalidHeadData)?
+            .to_vec();
+
+    // Similarly, perform a runtime call if the candidates pending availability couldn't be read
+    // from the storage.
+    let mut candidates_error = None;
+    let candidates_head_data = match candidates_head_data {
+        Some(candidates_head_data) => candidates_head_data,
+        None => {
+            match fetch_candidates_pending_availability(
+                relay_chain_sync,
+                &pinned_runtime,
+                parachain_id,
+                block_number,
+                block_hash,
+                block_state_trie_root,
+            )
+            .await
+            {
+                Ok(candidates_head_data) => candidates_head_data,
+                Err(error) => {
+                    candidates_error = Some(error);
+                    Vec::new()
+                }
+            }
+        }
+    };
+
+    // Only keep the candidates that form a chain starting from the included block. Candidates
+    // that can't be decoded, or that aren't a child of the previous block, are ignored, as well
+    // as all the candidates after them.
+    let mut pending = Vec::with_capacity(candidates_head_data.len());
+    for head_data in &candidates_head_data {
+        let Ok(scale_encoded_header) =
+            para::decode_head_data(head_data, head_data_format, block_number_bytes)
+        else {
+            break;
+        };
+        let Ok(decoded) = header::decode(scale_encoded_header, block_number_bytes) else {
+            break;
+        };
+        if *decoded.parent_hash
+            != header::hash_from_scale_encoded_header(pending.last().unwrap_or(&included))
+        {
+            break;
+        }
+        pending.push(scale_encoded_header.to_vec());
+    }
+
+    Ok(ParaheadFetch {
+        parahead: Parahead { included, pending },
+        storage_decode_error,
+        candidates_error,
+    })
+}
+
+/// Calls `ParachainHost_candidates_pending_availability` and returns the head data of the
+/// candidates pending availability of the parachain. Older relay chains only support
+/// `ParachainHost_candidate_pending_availability`, in which case we fall back to it.
+///
+/// > **Note**: This costs one additional runtime call proof per relay chain block, or two if the
+/// >           relay chain only supports the older function.
+async fn fetch_candidates_pending_availability<TPlat: PlatformRef>(
+    relay_chain_sync: &Arc<runtime_service::RuntimeService<TPlat>>,
+    pinned_runtime: &runtime_service::PinnedRuntime,
+    parachain_id: u32,
+    block_number: u64,
+    block_hash: &[u8; 32],
+    block_state_trie_root: [u8; 32],
+) -> Result<Vec<Vec<u8>>, ParaheadError> {
+    let candidates_call = |function_name: &'static str| {
+        relay_chain_sync.runtime_call(
+            pinned_runtime.clone(),
             *block_hash,
             block_number,
             block_state_trie_root,
-            para::PERSISTED_VALIDATION_FUNCTION_NAME.to_owned(),
-            None, // TODO: /!\
-            para::persisted_validation_data_parameters(
-                parachain_id,
-                para::OccupiedCoreAssumption::TimedOut,
+            function_name.to_owned(),
+            None,
+            parachain_id.to_le_bytes().to_vec(),
+            None,
+            6,
+            Duration::from_secs(10),
+            NonZeroU32::new(2).unwrap(),
+        )
+    };
+
+    let candidates =
+        match candidates_call(para::CANDIDATES_PENDING_AVAILABILITY_FUNCTION_NAME).await {
+            Ok(success) => para::decode_candidates_pending_availability_return_value(
+                &success.output,
+                relay_chain_sync.block_number_bytes(),
             )
-            .fold(Vec::new(), |mut a, b| {
-                a.extend_from_slice(b.as_ref());
-                a
-            }),
+            .map_err(ParaheadError::InvalidRuntimeOutput)?
+            .into_iter()
+            .map(|candidate| candidate.head_data.to_vec())
+            .collect(),
+            Err(runtime_service::RuntimeCallError::Execution(_)) => {
+                let success = candidates_call(para::CANDIDATE_PENDING_AVAILABILITY_FUNCTION_NAME)
+                    .await
+                    .map_err(ParaheadError::RuntimeCall)?;
+                para::decode_candidate_pending_availability_return_value(
+                    &success.output,
+                    relay_chain_sync.block_number_bytes(),
+                )
+                .map_err(ParaheadError::InvalidRuntimeOutput)?
+                .into_iter()
+                .map(|candidate| candidate.head_data.to_vec())
+                .collect()
+            }
+            Err(error) => return Err(ParaheadError::RuntimeCall(error)),
+        };
+
+    Ok(candidates)
+}
+
+/// Head data read from the storage of the relay chain. See [`fetch_parahead_from_storage`].
+struct ParaheadFromStorage {
+    /// Head data of the parachain. `None` if it isn't found in the storage or couldn't be
+    /// decoded.
+    included_head_data: Option<Vec<u8>>,
+    /// Head data of the candidates pending availability of the parachain. `None` if they
+    /// couldn't be decoded, or if [`ParaheadFromStorage::included_head_data`] is `None`.
+    candidates_head_data: Option<Vec<Vec<u8>>>,
+    /// Error that happened when decoding one of the storage values, if any.
+    decode_error: Option<para::Error>,
+}
+
+/// Reads from the storage of the relay chain the head data of the parachain and the head data of
+/// its candidates pending availability.
+///
+/// If the head data or the candidates can't be obtained, the storage layout of the relay chain is
+/// different from the one we expect, and runtime calls must be performed instead.
+async fn fetch_parahead_from_storage<TPlat: PlatformRef>(
+    relay_chain_sync_service: &Arc<super::SyncService<TPlat>>,
+    parachain_id: u32,
+    block_number: u64,
+    block_hash: [u8; 32],
+    block_state_trie_root: [u8; 32],
+) -> Result<ParaheadFromStorage, ParaheadError> {
+    let heads_key = para::paras_heads_storage_key(parachain_id);
+    let pending_availability_key = para::pending_availability_storage_key(parachain_id);
+
+    let items = relay_chain_sync_service
+        .clone()
+        .storage_query(
+            block_number,
+            block_hash,
+            block_state_trie_root,
+            [heads_key.clone(), pending_availability_key]
+                .into_iter()
+                .map(|key| super::StorageRequestItem {
+                    key,
+                    ty: super::StorageRequestItemTy::Value,
+                }),
             6,
             Duration::from_secs(10),
             NonZeroU32::new(2).unwrap(),
         )
+        .collect_all()
         .await
-        .map_err(ParaheadError::RuntimeCall)?;
-
-    // Try decode the result of the runtime call.
-    // If this fails, it indicates an incompatibility between smoldot and the relay chain.
-    match para::decode_persisted_validation_data_return_value(
-        &success.output,
-        relay_chain_sync.block_number_bytes(),
-    ) {
-        Ok(Some(pvd)) => Ok(pvd.parent_head.to_vec()),
-        Ok(None) => Err(ParaheadError::NoCore),
-        Err(error) => Err(ParaheadError::InvalidRuntimeOutput(error)),
+        .map_err(ParaheadError::StorageQuery)?;
+
+    let mut heads_value = None;
+    let mut pending_availability_value = None;
+    for item in items {
+        let super::StorageResultItem::Value { key, value } = item else {
+            unreachable!()
+        };
+        if key == heads_key {
+            heads_value = value;
+        } else {
+            pending_availability_value = value;
+        }
     }
+
+    let included_head_data = match heads_value
+        .as_deref()
+        .map(para::decode_paras_heads_storage_value)
+    {
+        Some(Ok(head_data)) => head_data.to_vec(),
+        Some(Err(error)) => {
+            return Ok(ParaheadFromStorage {
+                included_head_data: None,
+                candidates_head_data: None,
+                decode_error: Some(error),
+            })
+        }
+        None => {
+            return Ok(ParaheadFromStorage {
+                included_head_data: None,
+                candidates_head_data: None,
+                decode_error: None,
+            })
+        }
+    };
+
+    // The absence of a storage value means that no candidate is pending availability.
+    let (candidates_head_data, decode_error) = match pending_availability_value {
+        None => (Some(Vec::new()), None),
+        Some(value) => match para::decode_pending_availability_storage_value(
+            &value,
+            relay_chain_sync_service.block_number_bytes(),
+        ) {
+            Ok(candidates) => (
+                Some(
+                    candidates
+                        .into_iter()
+                        .map(|candidate| candidate.head_data.to_vec())
+                        .collect(),
+                ),
+                None,
+            ),
+            Err(error) => (None, Some(error)),
+        },
+    };
+
+    Ok(ParaheadFromStorage {
+        included_head_data: Some(included_head_data),
+        candidates_head_data,
+        decode_error,
+    })
 }
 
 /// Error that can happen when fetching the parachain head corresponding to a relay chain block.
 #[derive(Debug, derive_more::Display)]
 enum ParaheadError {
+    /// Error while reading the storage of the relay chain.
+    #[display(fmt = "Error while reading the relay chain storage: {_0}")]
+    StorageQuery(super::StorageQueryError),
     /// Error while performing call request over the network.
     #[display(fmt = "Error while performing call request over the network: {_0}")]
     RuntimeCall(runtime_service::RuntimeCallError),
@@ -1360,6 +1839,11 @@ enum ParaheadError {
     /// This indicates some kind of incompatibility between smoldot and the relay chain.
     #[display(fmt = "Error while decoding the output of the call: {_0}")]
     InvalidRuntimeOutput(para::Error),
+    /// Error while decoding the parachain block header from the head data.
+    ///
+    /// This indicates that the head data format of the parachain is incorrect.
+    #[display(fmt = "Error while decoding the parachain head data: {_0}")]
+    InvalidHeadData(header::Error),
 }
 
 impl ParaheadError {
@@ -1367,6 +1851,7 @@ impl ParaheadError {
     /// issue.
     fn is_network_problem(&self) -> bool {
         match self {
+            ParaheadError::StorageQuery(error) => error.is_network_problem(),
             ParaheadError::RuntimeCall(runtime_service::RuntimeCallError::Inaccessible(_)) => true,
             ParaheadError::RuntimeCall(
                 runtime_service::RuntimeCallError::Execution(_)
@@ -1377,6 +1862,7 @@ impl ParaheadError {
             ParaheadError::PinRuntimeError(_) => false,
             ParaheadError::NoCore => false,
             ParaheadError::InvalidRuntimeOutput(_) => false,
+            ParaheadError::InvalidHeadData(_) => false,
         }
     }
 }
@@ -16,8 +16,8 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::{
-    BlockNotification, ConfigRelayChainRuntimeCodeHint, FinalizedBlockRuntime, Notification,
-    SubscribeAll, ToBackground,
+    finality_consistency, BlockNotification, ConfigRelayChainRuntimeCodeHint,
+    FinalityConsistencyEvent, FinalizedBlockRuntime, Notification, SubscribeAll, ToBackground,
 };
 use crate::{log, network_service, platform::PlatformRef, util};
 
@@ -39,7 +39,7 @@ use futures_lite::FutureExt as _;
 use futures_util::{future, stream, FutureExt as _, StreamExt as _};
 use hashbrown::HashMap;
 use smoldot::{
-    chain, header,
+    chain, finality, header,
     informant::HashDisplay,
     libp2p,
     network::{self, codec},
@@ -53,9 +53,21 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
     chain_information: chain::chain_information::ValidChainInformation,
     block_number_bytes: usize,
     runtime_code_hint: Option<ConfigRelayChainRuntimeCodeHint>,
+    non_finalized_headers_hint: Vec<Vec<u8>>,
+    finality_consistency_checks: bool,
     mut from_foreground: Pin<Box<async_channel::Receiver<ToBackground>>>,
     network_service: Arc<network_service::NetworkServiceChain<TPlat>>,
 ) {
+    let finality_consistency = if finality_consistency_checks {
+        let finalized_block_header = &chain_information.as_ref().finalized_block_header;
+        Some(finality_consistency::FinalityConsistency::new((
+            finalized_block_header.number,
+            finalized_block_header.hash(block_number_bytes),
+        )))
+    } else {
+        None
+    };
+
     let mut task = Task {
         sync: Some(all::AllSync::new(all::Config {
             chain_information,
@@ -97,6 +109,7 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 merkle_value: hint.merkle_value,
                 storage_value: hint.storage_value,
                 closest_ancestor_excluding: hint.closest_ancestor_excluding,
+                runtime_version: hint.runtime_version,
             }),
         })),
         network_up_to_date_best: true,
@@ -119,9 +132,54 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 seed
             }),
         ),
+        finality_consistency,
+        finality_consistency_subscribers: Vec::new(),
+        non_finalized_headers_hint: {
+            let mut hint = HashMap::with_capacity_and_hasher(
+                non_finalized_headers_hint.len(),
+                util::SipHasherBuild::new({
+                    let mut seed = [0; 16];
+                    platform.fill_random_bytes(&mut seed);
+                    seed
+                }),
+            );
+            for scale_encoded_header in non_finalized_headers_hint {
+                hint.insert(
+                    header::hash_from_scale_encoded_header(&scale_encoded_header),
+                    scale_encoded_header,
+                );
+            }
+            hint
+        },
+        non_finalized_headers_hint_source: None,
         platform,
     };
 
+    // The headers of the hint are provided to the syncing state machine through a virtual
+    // source, whose best block is the highest block of the hint. This source is never asked
+    // anything else than the headers found in the hint.
+    if let Some((best_block_number, best_block_hash)) = task
+        .non_finalized_headers_hint
+        .iter()
+        .filter_map(|(hash, scale_encoded_header)| {
+            Some((
+                header::decode(scale_encoded_header, block_number_bytes)
+                    .ok()?
+                    .number,
+                *hash,
+            ))
+        })
+        .max()
+    {
+        task.non_finalized_headers_hint_source = Some(
+            task.sync
+                .as_mut()
+                .unwrap_or_else(|| unreachable!())
+                .prepare_add_source(best_block_number, best_block_hash)
+                .add_source(None, ()),
+        );
+    }
+
     // Main loop of the syncing logic.
     //
     // This loop contains some CPU-heavy operations (e.g. verifying finality proofs and warp sync
@@ -136,7 +194,7 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
         // Now waiting for some event to happen: a network event, a request from the frontend
         // of the sync service, or a request being finished.
         enum WakeUpReason {
-            SyncProcess(all::ProcessOne<future::AbortHandle, (libp2p::PeerId, codec::Role), ()>),
+            SyncProcess(all::ProcessOne<future::AbortHandle, SyncSource, ()>),
             MustUpdateNetworkWithBestBlock,
             MustUpdateNetworkWithFinalizedBlock,
             MustSubscribeNetworkEvents,
@@ -199,15 +257,27 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
             })
             .or({
                 let sync = &mut task.sync;
+                let non_finalized_headers_hint = &task.non_finalized_headers_hint;
                 async move {
                     // `desired_requests()` returns, in decreasing order of priority, the requests
                     // that should be started in order for the syncing to proceed. The fact that
                     // multiple requests are returned could be used to filter out undesired one. We
                     // use this filtering to enforce a maximum of one ongoing request per source.
+                    // Requests towards the virtual source of the headers hint are only started if
+                    // they can be answered using the hint.
                     let Some(s) = &sync else { unreachable!() };
-                    if let Some((source_id, _, request_detail)) = s
-                        .desired_requests()
-                        .find(|(source_id, _, _)| s.source_num_ongoing_requests(*source_id) == 0)
+                    if let Some((source_id, _, request_detail)) =
+                        s.desired_requests().find(|(source_id, source, request_detail)| {
+                            s.source_num_ongoing_requests(*source_id) == 0
+                                && (source.is_some()
+                                    || matches!(request_detail,
+                                        all::DesiredRequest::BlocksRequest {
+                                            first_block_hash,
+                                            request_headers: true,
+                                            request_bodies: false,
+                                            ..
+                                        } if non_finalized_headers_hint.contains_key(first_block_hash)))
+                        })
                     {
                         return WakeUpReason::StartRequest(source_id, request_detail);
                     }
@@ -244,8 +314,13 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 // Because the runtime being compiled has been validated by 2/3rds of the
                 // validators of the chain, we can assume that it is valid. Doing so significantly
                 // increases the compilation speed.
-                let (new_sync, error) =
-                    req.build(all::ExecHint::CompileWithNonDeterministicValidation, true);
+                // Fuel metering is enabled because the runtime is later used to answer JSON-RPC
+                // requests, which can be subject to a fuel limit.
+                let (new_sync, error) = req.build(
+                    all::ExecHint::CompileWithNonDeterministicValidation,
+                    true,
+                    true,
+                );
                 let elapsed = task.platform.now() - before_instant;
                 match error {
                     Ok(()) => {
@@ -361,7 +436,7 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 // Grandpa warp sync fragment to verify.
                 let sender_if_still_connected = verify
                     .proof_sender()
-                    .map(|(_, (peer_id, _))| peer_id.clone());
+                    .and_then(|(_, source)| source.as_ref().map(|(peer_id, _)| peer_id.clone()));
 
                 let (sync, result) = verify.perform({
                     let mut seed = [0; 32];
@@ -506,14 +581,17 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
 
             WakeUpReason::SyncProcess(all::ProcessOne::VerifyFinalityProof(verify)) => {
                 // Finality proof to verify.
-                let sender = verify.sender().1 .0.clone();
+                // The virtual source of the headers hint never provides any justification.
+                let Some((sender, _)) = verify.sender().1.clone() else {
+                    unreachable!()
+                };
                 match verify.perform({
                     let mut seed = [0; 32];
                     task.platform.fill_random_bytes(&mut seed);
                     seed
                 }) {
                     (
-                        sync,
+                        mut sync,
                         all::FinalityProofVerifyOutcome::NewFinalized {
                             updates_best_block,
                             finalized_blocks_newest_to_oldest,
@@ -553,6 +631,20 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                             pruned_blocks,
                         });
 
+                        // The headers of the hint that haven't been requested yet are useless
+                        // once the best block of the hint is finalized.
+                        if let Some(source_id) = task.non_finalized_headers_hint_source {
+                            if sync.source_best_block(source_id).0 <= sync.finalized_block_number()
+                            {
+                                let (_, requests) = sync.remove_source(source_id);
+                                for (_, abort) in requests {
+                                    abort.abort();
+                                }
+                                task.non_finalized_headers_hint_source = None;
+                                task.non_finalized_headers_hint.clear();
+                            }
+                        }
+
                         task.sync = Some(sync);
                     }
 
@@ -635,18 +727,27 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 role,
                 best_block_number,
                 best_block_hash,
+                discovery_origin,
             }) => {
+                if let Some(finality_consistency) = &mut task.finality_consistency {
+                    finality_consistency.peer_connected(peer_id.clone(), discovery_origin);
+                }
+
                 task.peers_source_id_map.insert(
                     peer_id.clone(),
                     task.sync
                         .as_mut()
                         .unwrap_or_else(|| unreachable!())
                         .prepare_add_source(best_block_number, best_block_hash)
-                        .add_source((peer_id, role), ()),
+                        .add_source(Some((peer_id, role)), ()),
                 );
             }
 
             WakeUpReason::NetworkEvent(network_service::Event::Disconnected { peer_id }) => {
+                if let Some(finality_consistency) = &mut task.finality_consistency {
+                    finality_consistency.peer_disconnected(&peer_id);
+                }
+
                 let sync_source_id = task.peers_source_id_map.remove(&peer_id).unwrap();
                 let (_, requests) = task
                     .sync
@@ -771,6 +872,14 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 peer_id,
                 finalized_block_height,
             }) => {
+                if let Some(event) = task
+                    .finality_consistency
+                    .as_mut()
+                    .and_then(|f| f.neighbor_packet(&peer_id, finalized_block_height))
+                {
+                    task.report_finality_consistency(event);
+                }
+
                 let sync_source_id = *task.peers_source_id_map.get(&peer_id).unwrap();
                 task.sync
                     .as_mut()
@@ -782,6 +891,28 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 peer_id,
                 message,
             }) => {
+                if let Some(finality_consistency) = &mut task.finality_consistency {
+                    // Only commits whose signatures are valid are cross-checked, as otherwise
+                    // any peer could make up divergences.
+                    let Some(sync) = &task.sync else {
+                        unreachable!()
+                    };
+                    if verify_commit_signatures(sync, message.as_encoded(), {
+                        let mut seed = [0; 32];
+                        task.platform.fill_random_bytes(&mut seed);
+                        seed
+                    }) {
+                        let decoded = message.decode();
+                        if let Some(event) = finality_consistency.commit_message(
+                            &peer_id,
+                            decoded.target_number,
+                            *decoded.target_hash,
+                        ) {
+                            task.report_finality_consistency(event);
+                        }
+                    }
+                }
+
                 let sync_source_id = *task.peers_source_id_map.get(&peer_id).unwrap();
                 match task
                     .sync
@@ -876,6 +1007,16 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                         .await;
                 }
 
+                if let Some(finality_consistency) = &mut task.finality_consistency {
+                    let sync = task.sync.as_ref().unwrap_or_else(|| unreachable!());
+                    for event in finality_consistency.local_finalized(
+                        sync.finalized_block_number(),
+                        *sync.finalized_block_hash(),
+                    ) {
+                        task.report_finality_consistency(event);
+                    }
+                }
+
                 task.network_up_to_date_finalized = true;
             }
 
@@ -950,13 +1091,13 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                             source_best.0 > block_number
                                 || (source_best.0 == block_number && *source_best.1 == block_hash)
                         })
-                        .map(|id| sync[id].0.clone())
+                        .filter_map(|id| sync[id].as_ref().map(|(peer_id, _)| peer_id.clone()))
                         .collect()
                 } else {
                     // As documented, `knows_non_finalized_block` would panic if the
                     // block height was below the one of the known finalized block.
                     sync.knows_non_finalized_block(block_number, &block_hash)
-                        .map(|id| sync[id].0.clone())
+                        .filter_map(|id| sync[id].as_ref().map(|(peer_id, _)| peer_id.clone()))
                         .collect()
                 };
 
@@ -971,16 +1112,35 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
 
                 let out = sync
                     .sources()
-                    .map(|src| {
-                        let (peer_id, role) = sync[src].clone();
+                    .filter_map(|src| {
+                        let (peer_id, role) = sync[src].clone()?;
                         let (height, hash) = sync.source_best_block(src);
-                        (peer_id, role, height, *hash)
+                        Some((peer_id, role, height, *hash))
                     })
                     .collect::<Vec<_>>();
 
                 let _ = send_back.send(out);
             }
 
+            WakeUpReason::ForegroundMessage(ToBackground::SubscribeFinalityConsistency {
+                send_back,
+                buffer_size,
+            }) => {
+                let (tx, rx) = async_channel::bounded(buffer_size);
+                if task.finality_consistency.is_some() {
+                    task.finality_consistency_subscribers.push(tx);
+                }
+                let _ = send_back.send(rx);
+            }
+
+            WakeUpReason::ForegroundMessage(ToBackground::ParachainBlockInclusion {
+                send_back,
+                ..
+            }) => {
+                // Not a parachain.
+                let _ = send_back.send(None);
+            }
+
             WakeUpReason::ForegroundMessage(ToBackground::SerializeChainInformation {
                 send_back,
             }) => {
@@ -1006,45 +1166,65 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
 
             WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::Block(Ok(v)))) => {
                 // Successful block request.
-                task.sync
-                    .as_mut()
-                    .unwrap_or_else(|| unreachable!())
-                    .blocks_request_response(
-                        request_id,
-                        v.into_iter().filter_map(|block| {
-                            Some(all::BlockRequestSuccessBlock {
-                                scale_encoded_header: block.header?,
-                                scale_encoded_justifications: block
-                                    .justifications
-                                    .unwrap_or(Vec::new())
-                                    .into_iter()
-                                    .map(|j| all::Justification {
-                                        engine_id: j.engine_id,
-                                        justification: j.justification,
-                                    })
-                                    .collect(),
-                                scale_encoded_extrinsics: Vec::new(),
-                                user_data: (),
-                            })
-                        }),
-                    );
+                let Some(sync) = &mut task.sync else {
+                    unreachable!()
+                };
+
+                let source_id = sync.request_source_id(request_id);
+                sync.blocks_request_response(
+                    request_id,
+                    v.into_iter().filter_map(|block| {
+                        Some(all::BlockRequestSuccessBlock {
+                            scale_encoded_header: block.header?,
+                            scale_encoded_justifications: block
+                                .justifications
+                                .unwrap_or(Vec::new())
+                                .into_iter()
+                                .map(|j| all::Justification {
+                                    engine_id: j.engine_id,
+                                    justification: j.justification,
+                                })
+                                .collect(),
+                            scale_encoded_extrinsics: Vec::new(),
+                            user_data: (),
+                        })
+                    }),
+                );
+
+                // Once the hint is exhausted, its virtual source is no longer useful.
+                if task.non_finalized_headers_hint_source == Some(source_id)
+                    && task.non_finalized_headers_hint.is_empty()
+                {
+                    let (_, requests) = sync.remove_source(source_id);
+                    for (_, abort) in requests {
+                        abort.abort();
+                    }
+                    task.non_finalized_headers_hint_source = None;
+                }
             }
 
-            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::Block(Err(_)))) => {
+            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::Block(Err(error)))) => {
                 // Failed block request.
                 let Some(sync) = &mut task.sync else {
                     unreachable!()
                 };
 
-                let source_peer_id = sync[sync.request_source_id(request_id)].0.clone();
+                // Requests towards the virtual source of the headers hint never fail.
+                let Some((source_peer_id, _)) = sync[sync.request_source_id(request_id)].clone()
+                else {
+                    unreachable!()
+                };
 
-                task.network_service
-                    .ban_and_disconnect(
-                        source_peer_id,
-                        network_service::BanSeverity::Low,
-                        "failed-blocks-request",
-                    )
-                    .await;
+                // The peer isn't at fault if the chain has used up its bandwidth budget.
+                if !matches!(error, network_service::BlocksRequestError::BudgetExhausted) {
+                    task.network_service
+                        .ban_and_disconnect(
+                            source_peer_id,
+                            network_service::BanSeverity::Low,
+                            "failed-blocks-request",
+                        )
+                        .await;
+                }
 
                 sync.remove_request(request_id);
             }
@@ -1066,19 +1246,32 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                     .grandpa_warp_sync_response(request_id, fragments, decoded.is_finished);
             }
 
-            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::WarpSync(Err(_)))) => {
+            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::WarpSync(Err(error)))) => {
                 // Failed warp sync request.
                 let Some(sync) = &mut task.sync else {
                     unreachable!()
                 };
 
-                task.network_service
-                    .ban_and_disconnect(
-                        sync[sync.request_source_id(request_id)].0.clone(),
-                        network_service::BanSeverity::Low,
-                        "failed-warp-sync-request",
-                    )
-                    .await;
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((source_peer_id, _)) = sync[sync.request_source_id(request_id)].clone()
+                else {
+                    unreachable!()
+                };
+
+                // The peer isn't at fault if the chain has used up its bandwidth budget.
+                if !matches!(
+                    error,
+                    network_service::WarpSyncRequestError::BudgetExhausted
+                ) {
+                    task.network_service
+                        .ban_and_disconnect(
+                            source_peer_id,
+                            network_service::BanSeverity::Low,
+                            "failed-warp-sync-request",
+                        )
+                        .await;
+                }
 
                 sync.remove_request(request_id);
             }
@@ -1092,19 +1285,32 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 sync.storage_get_response(request_id, r);
             }
 
-            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::Storage(Err(_)))) => {
+            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::Storage(Err(error)))) => {
                 // Storage proof request.
                 let Some(sync) = &mut task.sync else {
                     unreachable!()
                 };
 
-                task.network_service
-                    .ban_and_disconnect(
-                        sync[sync.request_source_id(request_id)].0.clone(),
-                        network_service::BanSeverity::Low,
-                        "failed-storage-request",
-                    )
-                    .await;
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((source_peer_id, _)) = sync[sync.request_source_id(request_id)].clone()
+                else {
+                    unreachable!()
+                };
+
+                // The peer isn't at fault if the chain has used up its bandwidth budget.
+                if !matches!(
+                    error,
+                    network_service::StorageProofRequestError::BudgetExhausted
+                ) {
+                    task.network_service
+                        .ban_and_disconnect(
+                            source_peer_id,
+                            network_service::BanSeverity::Low,
+                            "failed-storage-request",
+                        )
+                        .await;
+                }
 
                 sync.remove_request(request_id);
             }
@@ -1118,19 +1324,35 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 // TODO: need help from networking service to avoid this to_owned
             }
 
-            WakeUpReason::RequestFinished(request_id, Ok(RequestOutcome::CallProof(Err(_)))) => {
+            WakeUpReason::RequestFinished(
+                request_id,
+                Ok(RequestOutcome::CallProof(Err(error))),
+            ) => {
                 // Failed call proof request.
                 let Some(sync) = &mut task.sync else {
                     unreachable!()
                 };
 
-                task.network_service
-                    .ban_and_disconnect(
-                        sync[sync.request_source_id(request_id)].0.clone(),
-                        network_service::BanSeverity::Low,
-                        "failed-call-proof-request",
-                    )
-                    .await;
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((source_peer_id, _)) = sync[sync.request_source_id(request_id)].clone()
+                else {
+                    unreachable!()
+                };
+
+                // The peer isn't at fault if the chain has used up its bandwidth budget.
+                if !matches!(
+                    error,
+                    network_service::CallProofRequestError::BudgetExhausted
+                ) {
+                    task.network_service
+                        .ban_and_disconnect(
+                            source_peer_id,
+                            network_service::BanSeverity::Low,
+                            "failed-call-proof-request",
+                        )
+                        .await;
+                }
 
                 sync.remove_request(request_id);
             }
@@ -1167,7 +1389,68 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 // work but less efficiently.
                 let num_blocks = NonZeroU64::new(cmp::min(64, num_blocks.get())).unwrap();
 
-                let peer_id = sync[source_id].0.clone(); // TODO: why does this require cloning? weird borrow chk issue
+                // Requests towards the virtual source of the headers hint are answered
+                // immediately using the hint. They are only ever started if the hint contains the
+                // first requested block.
+                if sync[source_id].is_none() {
+                    let mut blocks = Vec::new();
+                    let mut next_hash = first_block_hash;
+                    while u64::try_from(blocks.len()).unwrap() < num_blocks.get() {
+                        let Some(scale_encoded_header) =
+                            task.non_finalized_headers_hint.remove(&next_hash)
+                        else {
+                            break;
+                        };
+                        let hash = next_hash;
+                        if let Ok(decoded) =
+                            header::decode(&scale_encoded_header, sync.block_number_bytes())
+                        {
+                            next_hash = *decoded.parent_hash;
+                        }
+                        blocks.push(codec::BlockData {
+                            hash,
+                            header: Some(scale_encoded_header),
+                            body: None,
+                            // Justifications aren't known.
+                            justifications: None,
+                        });
+                        if next_hash == hash {
+                            break;
+                        }
+                    }
+
+                    log!(
+                        &task.platform,
+                        Debug,
+                        &task.log_target,
+                        "blocks-request-answered-from-hint",
+                        first_block_hash = HashDisplay(&first_block_hash),
+                        num_blocks = blocks.len()
+                    );
+
+                    let (abort, _) = future::AbortHandle::new_pair();
+                    let request_id = sync.add_request(
+                        source_id,
+                        all::RequestDetail::BlocksRequest {
+                            first_block_hash,
+                            first_block_height,
+                            num_blocks,
+                            request_headers,
+                            request_bodies,
+                            request_justification,
+                        },
+                        abort,
+                    );
+
+                    task.pending_requests.push(Box::pin(async move {
+                        (request_id, Ok(RequestOutcome::Block(Ok(blocks))))
+                    }));
+                    continue;
+                }
+
+                let Some((peer_id, _)) = sync[source_id].clone() else {
+                    unreachable!()
+                };
 
                 let block_request = task.network_service.clone().blocks_request(
                     peer_id,
@@ -1218,7 +1501,11 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                     unreachable!()
                 };
 
-                let peer_id = sync[source_id].0.clone(); // TODO: why does this require cloning? weird borrow chk issue
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((peer_id, _)) = sync[source_id].clone() else {
+                    unreachable!()
+                };
 
                 let grandpa_request = task.network_service.clone().grandpa_warp_sync_request(
                     peer_id,
@@ -1257,7 +1544,11 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                     unreachable!()
                 };
 
-                let peer_id = sync[source_id].0.clone(); // TODO: why does this require cloning? weird borrow chk issue
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((peer_id, _)) = sync[source_id].clone() else {
+                    unreachable!()
+                };
 
                 let storage_request = task.network_service.clone().storage_proof_request(
                     peer_id,
@@ -1269,12 +1560,10 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                 );
 
                 let storage_request = async move {
-                    if let Ok(outcome) = storage_request.await {
-                        // TODO: log what happens
-                        Ok(outcome.decode().to_vec()) // TODO: no to_vec() here, needs some API change on the networking
-                    } else {
-                        Err(())
-                    }
+                    // TODO: log what happens
+                    storage_request
+                        .await
+                        .map(|outcome| outcome.decode().to_vec()) // TODO: no to_vec() here, needs some API change on the networking
                 };
 
                 let (storage_request, abort) = future::abortable(storage_request);
@@ -1304,7 +1593,11 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                     unreachable!()
                 };
 
-                let peer_id = sync[source_id].0.clone(); // TODO: why does this require cloning? weird borrow chk issue
+                // Only blocks requests are started towards the virtual source of the headers
+                // hint.
+                let Some((peer_id, _)) = sync[source_id].clone() else {
+                    unreachable!()
+                };
 
                 let call_proof_request = {
                     // TODO: all this copying is done because of lifetime requirements in NetworkService::call_proof_request; maybe check if it can be avoided
@@ -1322,10 +1615,7 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
                             Duration::from_secs(16),
                         );
 
-                        match rq.await {
-                            Ok(p) => Ok(p),
-                            Err(_) => Err(()),
-                        }
+                        rq.await
                     }
                 };
 
@@ -1398,6 +1688,11 @@ pub(super) async fn start_standalone_chain<TPlat: PlatformRef>(
     }
 }
 
+/// User data of the sources of [`Task::sync`]. Each source is either a networking peer, or
+/// `None` for the virtual source that serves the headers of
+/// [`Task::non_finalized_headers_hint`].
+type SyncSource = Option<(libp2p::PeerId, codec::Role)>;
+
 struct Task<TPlat: PlatformRef> {
     /// Log target to use for all logs that are emitted.
     log_target: String,
@@ -1412,7 +1707,7 @@ struct Task<TPlat: PlatformRef> {
     /// request if desired.
     ///
     /// Always `Some`, except for temporary extraction.
-    sync: Option<all::AllSync<future::AbortHandle, (libp2p::PeerId, codec::Role), ()>>,
+    sync: Option<all::AllSync<future::AbortHandle, SyncSource, ()>>,
 
     /// If `Some`, contains the runtime of the current finalized block.
     known_finalized_runtime: Option<FinalizedBlockRuntime>,
@@ -1420,6 +1715,21 @@ struct Task<TPlat: PlatformRef> {
     /// For each networking peer, the index of the corresponding peer within the [`Task::sync`].
     peers_source_id_map: HashMap<libp2p::PeerId, all::SourceId, util::SipHasherBuild>,
 
+    /// State of the finality consistency checks. `None` if disabled.
+    /// See [`super::ConfigRelayChain::finality_consistency_checks`].
+    finality_consistency: Option<finality_consistency::FinalityConsistency>,
+
+    /// List of senders to report [`FinalityConsistencyEvent`]s to.
+    finality_consistency_subscribers: Vec<async_channel::Sender<FinalityConsistencyEvent>>,
+
+    /// See [`super::ConfigRelayChain::non_finalized_headers_hint`]. Indexed by block hash.
+    /// Entries are removed once they have been provided to [`Task::sync`].
+    non_finalized_headers_hint: HashMap<[u8; 32], Vec<u8>, util::SipHasherBuild>,
+
+    /// Source within [`Task::sync`] whose requests are answered using
+    /// [`Task::non_finalized_headers_hint`]. `None` if the hint is empty or no longer useful.
+    non_finalized_headers_hint_source: Option<all::SourceId>,
+
     /// `false` after the best block in the [`Task::sync`] has changed. Set back to `true`
     /// after the networking has been notified of this change.
     network_up_to_date_best: bool,
@@ -1455,8 +1765,8 @@ enum RequestOutcome {
             network_service::WarpSyncRequestError,
         >,
     ),
-    Storage(Result<Vec<u8>, ()>),
-    CallProof(Result<network::service::EncodedMerkleProof, ()>),
+    Storage(Result<Vec<u8>, network_service::StorageProofRequestError>),
+    CallProof(Result<network::service::EncodedMerkleProof, network_service::CallProofRequestError>),
 }
 
 impl<TPlat: PlatformRef> Task<TPlat> {
@@ -1473,4 +1783,136 @@ impl<TPlat: PlatformRef> Task<TPlat> {
             self.all_notifications.push(subscription);
         }
     }
+
+    /// Prints a warning about the given event and sends it to all the subscribers of
+    /// [`Task::finality_consistency_subscribers`].
+    fn report_finality_consistency(&mut self, event: FinalityConsistencyEvent) {
+        match &event {
+            FinalityConsistencyEvent::Divergence {
+                block_number,
+                peer_id,
+                block_hash,
+                conflicting_peer_id: Some(conflicting_peer_id),
+                conflicting_block_hash,
+            } => log!(
+                &self.platform,
+                Warn,
+                &self.log_target,
+                format!(
+                    "Peers {} and {} disagree about the finalized block at height #{}: {} vs {}. \
+                    The node might be the target of an eclipse attack.",
+                    peer_id,
+                    conflicting_peer_id,
+                    block_number,
+                    HashDisplay(block_hash),
+                    HashDisplay(conflicting_block_hash)
+                )
+            ),
+            FinalityConsistencyEvent::Divergence {
+                block_number,
+                peer_id,
+                block_hash,
+                conflicting_peer_id: None,
+                conflicting_block_hash,
+            } => log!(
+                &self.platform,
+                Warn,
+                &self.log_target,
+                format!(
+                    "Peer {} claims that block {} is finalized at height #{}, while the local \
+                    finalized block at this height is {}.",
+                    peer_id,
+                    HashDisplay(block_hash),
+                    block_number,
+                    HashDisplay(conflicting_block_hash)
+                )
+            ),
+            FinalityConsistencyEvent::Withheld {
+                peer_id,
+                peer_finalized_block_number: Some(peer_finalized_block_number),
+                local_finalized_block_number,
+            } => log!(
+                &self.platform,
+                Warn,
+                &self.log_target,
+                format!(
+                    "Peer {} reports a finalized block at height #{} while the local finalized \
+                    block is at height #{}. The peer might be withholding finality.",
+                    peer_id, peer_finalized_block_number, local_finalized_block_number
+                )
+            ),
+            FinalityConsistencyEvent::Withheld {
+                peer_id,
+                peer_finalized_block_number: None,
+                local_finalized_block_number,
+            } => log!(
+                &self.platform,
+                Warn,
+                &self.log_target,
+                format!(
+                    "Peer {} hasn't reported any finalized block while the local finalized \
+                    block has advanced to height #{}. The peer might be withholding finality.",
+                    peer_id, local_finalized_block_number
+                )
+            ),
+        }
+
+        // Elements are removed one by one and inserted back if the channel is still open.
+        for index in (0..self.finality_consistency_subscribers.len()).rev() {
+            let subscription = self.finality_consistency_subscribers.swap_remove(index);
+            if let Err(async_channel::TrySendError::Closed(_)) =
+                subscription.try_send(event.clone())
+            {
+                continue;
+            }
+
+            self.finality_consistency_subscribers.push(subscription);
+        }
+    }
+}
+
+/// Verifies the signatures of the given GrandPa commit against the authorities that follow the
+/// current finalized block of `sync`.
+///
+/// Contrary to a complete verification, the blocks that the pre-commits vote for aren't
+/// required to be descendants of the target of the commit, as these blocks might not be known
+/// locally. Returns `false` if the commit is about a different authorities set.
+fn verify_commit_signatures<TRq, TSrc, TBl>(
+    sync: &all::AllSync<TRq, TSrc, TBl>,
+    scale_encoded_commit: &[u8],
+    randomness_seed: [u8; 32],
+) -> bool {
+    let chain_information = sync.as_chain_information();
+    let chain::chain_information::ChainInformationFinalityRef::Grandpa {
+        after_finalized_block_authorities_set_id,
+        finalized_triggered_authorities,
+        ..
+    } = chain_information.as_ref().finality
+    else {
+        return false;
+    };
+
+    let mut verification = finality::verify::verify_commit(finality::verify::CommitVerifyConfig {
+        commit: scale_encoded_commit,
+        block_number_bytes: sync.block_number_bytes(),
+        expected_authorities_set_id: after_finalized_block_authorities_set_id,
+        num_authorities: u32::try_from(finalized_triggered_authorities.len()).unwrap_or(u32::MAX),
+        randomness_seed,
+    });
+
+    loop {
+        match verification {
+            finality::verify::CommitVerify::Finished(result) => return result.is_ok(),
+            finality::verify::CommitVerify::FinishedUnknown => return false,
+            finality::verify::CommitVerify::IsAuthority(is_authority) => {
+                let result = finalized_triggered_authorities
+                    .iter()
+                    .any(|a| a.public_key == *is_authority.authority_public_key());
+                verification = is_authority.resume(result);
+            }
+            finality::verify::CommitVerify::IsParent(is_parent) => {
+                verification = is_parent.resume(None);
+            }
+        }
+    }
 }
@@ -0,0 +1,316 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of storage values, storage proofs and call proofs that have been verified, shared
+//! between all the storage queries and runtime calls of a chain.
+//!
+//! Entries are indexed by the hash of the root of the main trie of the block they have been
+//! retrieved from. Since this hash commits to the entire storage of a block, entries never need
+//! to be invalidated, and multiple blocks with the same storage share the same entry.
+//!
+//! Storage proofs are kept in their decoded form, so that they are only ever decoded and verified
+//! once, no matter how many queries are answered from them.
+//!
+//! The total size of the values and proofs in the cache is bounded by a memory budget. When the
+//! budget is exceeded, the entries of the state roots that have been accessed the least recently
+//! are removed.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use smoldot::trie::proof_decode;
+
+pub(super) struct StorageCache {
+    /// Entries of the cache, indexed by state trie root hash and ordered by last access.
+    entries: lru::LruCache<[u8; 32], Entry, fnv::FnvBuildHasher>,
+
+    /// Maximum value of [`StorageCache::memory_used`].
+    memory_budget: usize,
+
+    /// Sum of all the [`Entry::memory_used`].
+    memory_used: usize,
+
+    /// Number of requests that have been answered from the cache.
+    hits: u64,
+
+    /// Number of requests that couldn't be answered from the cache.
+    misses: u64,
+}
+
+struct Entry {
+    /// Storage values that have been verified. `None` if the key has no storage value.
+    values: hashbrown::HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// Storage proofs that have been decoded and verified against the state trie root hash.
+    proofs: Vec<Arc<proof_decode::DecodedTrieProof<Vec<u8>>>>,
+
+    /// Call proofs that have successfully been used to perform a runtime call.
+    call_proofs: Vec<CallProof>,
+
+    /// Approximate number of bytes used by the values and proofs of this entry.
+    memory_used: usize,
+}
+
+struct CallProof {
+    /// Name of the runtime function that has been called.
+    function_name: String,
+    /// Parameters that have been passed to the runtime function.
+    parameters_vectored: Vec<u8>,
+    /// SCALE-encoded call proof.
+    proof: Arc<Vec<u8>>,
+}
+
+impl StorageCache {
+    /// Initializes a new empty cache.
+    pub(super) fn new(memory_budget: usize) -> Self {
+        StorageCache {
+            entries: lru::LruCache::unbounded_with_hasher(Default::default()),
+            memory_budget,
+            memory_used: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the storage value of the given key in the trie whose root hash is passed as
+    /// parameter, if it is in the cache.
+    ///
+    /// Returns `Some(None)` if it is known that the key has no storage value.
+    pub(super) fn storage_value(
+        &mut self,
+        main_trie_root_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Option<Option<&[u8]>> {
+        self.entries
+            .get(main_trie_root_hash)?
+            .values
+            .get(key)
+            .map(|value| value.as_deref())
+    }
+
+    /// Returns the list of decoded storage proofs in the cache that concern the trie whose root
+    /// hash is passed as parameter.
+    pub(super) fn proofs(
+        &mut self,
+        main_trie_root_hash: &[u8; 32],
+    ) -> Vec<Arc<proof_decode::DecodedTrieProof<Vec<u8>>>> {
+        self.entries
+            .get(main_trie_root_hash)
+            .map(|entry| entry.proofs.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the SCALE-encoded call proof of the given runtime call against the trie whose
+    /// root hash is passed as parameter, if it is in the cache.
+    pub(super) fn call_proof(
+        &mut self,
+        main_trie_root_hash: &[u8; 32],
+        function_name: &str,
+        parameters_vectored: &[u8],
+    ) -> Option<Arc<Vec<u8>>> {
+        self.entries
+            .get(main_trie_root_hash)?
+            .call_proofs
+            .iter()
+            .find(|call_proof| {
+                call_proof.function_name == function_name
+                    && call_proof.parameters_vectored == parameters_vectored
+            })
+            .map(|call_proof| call_proof.proof.clone())
+    }
+
+    /// Inserts in the cache a storage value that has been verified.
+    pub(super) fn insert_value(
+        &mut self,
+        main_trie_root_hash: [u8; 32],
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+    ) {
+        let size = key.len() + value.as_ref().map_or(0, |v| v.len());
+        if size > self.memory_budget {
+            return;
+        }
+
+        let entry = self.entry_mut(main_trie_root_hash);
+        if entry.values.contains_key(&key) {
+            return;
+        }
+        entry.values.insert(key, value);
+        entry.memory_used += size;
+        self.memory_used += size;
+        self.shrink_to_budget();
+    }
+
+    /// Inserts in the cache a storage proof that has been decoded and verified against the given
+    /// trie root hash.
+    ///
+    /// `encoded_len` is the size in bytes of the SCALE-encoded proof, and is used to estimate the
+    /// memory used by the decoded proof.
+    pub(super) fn insert_proof(
+        &mut self,
+        main_trie_root_hash: [u8; 32],
+        proof: Arc<proof_decode::DecodedTrieProof<Vec<u8>>>,
+        encoded_len: usize,
+    ) {
+        let size = encoded_len;
+        if size > self.memory_budget {
+            return;
+        }
+
+        let entry = self.entry_mut(main_trie_root_hash);
+        entry.proofs.push(proof);
+        entry.memory_used += size;
+        self.memory_used += size;
+        self.shrink_to_budget();
+    }
+
+    /// Inserts in the cache a SCALE-encoded call proof that has successfully been used to perform
+    /// the given runtime call against the given trie root hash.
+    pub(super) fn insert_call_proof(
+        &mut self,
+        main_trie_root_hash: [u8; 32],
+        function_name: String,
+        parameters_vectored: Vec<u8>,
+        proof: Arc<Vec<u8>>,
+    ) {
+        let size = function_name.len() + parameters_vectored.len() + proof.len();
+        if size > self.memory_budget {
+            return;
+        }
+
+        let entry = self.entry_mut(main_trie_root_hash);
+        if entry.call_proofs.iter().any(|call_proof| {
+            call_proof.function_name == function_name
+                && call_proof.parameters_vectored == parameters_vectored
+        }) {
+            return;
+        }
+        entry.call_proofs.push(CallProof {
+            function_name,
+            parameters_vectored,
+            proof,
+        });
+        entry.memory_used += size;
+        self.memory_used += size;
+        self.shrink_to_budget();
+    }
+
+    /// Updates the statistics of the cache.
+    pub(super) fn record_lookups(&mut self, hits: usize, misses: usize) {
+        self.hits = self
+            .hits
+            .saturating_add(u64::try_from(hits).unwrap_or(u64::MAX));
+        self.misses = self
+            .misses
+            .saturating_add(u64::try_from(misses).unwrap_or(u64::MAX));
+    }
+
+    /// Returns the total number of requests that have been answered from the cache.
+    pub(super) fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the total number of requests that couldn't be answered from the cache.
+    pub(super) fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// Returns the approximate number of bytes used by the content of the cache.
+    pub(super) fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    fn entry_mut(&mut self, main_trie_root_hash: [u8; 32]) -> &mut Entry {
+        self.entries
+            .get_or_insert_mut(main_trie_root_hash, || Entry {
+                values: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+                proofs: Vec::new(),
+                call_proofs: Vec::new(),
+                memory_used: 0,
+            })
+    }
+
+    fn shrink_to_budget(&mut self) {
+        while self.memory_used > self.memory_budget {
+            let Some((_, entry)) = self.entries.pop_lru() else {
+                break;
+            };
+            self.memory_used -= entry.memory_used;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageCache;
+    use alloc::sync::Arc;
+    use smoldot::trie::proof_decode;
+
+    /// Returns a decoded proof that doesn't contain any entry.
+    fn empty_proof() -> Arc<proof_decode::DecodedTrieProof<Vec<u8>>> {
+        Arc::new(
+            proof_decode::decode_and_verify_proof(proof_decode::Config { proof: vec![0] }).unwrap(),
+        )
+    }
+
+    #[test]
+    fn values_and_proofs() {
+        let mut cache = StorageCache::new(1024);
+        assert!(cache.storage_value(&[1; 32], b"foo").is_none());
+
+        cache.insert_value([1; 32], b"foo".to_vec(), Some(b"bar".to_vec()));
+        cache.insert_value([1; 32], b"baz".to_vec(), None);
+        cache.insert_proof([1; 32], empty_proof(), 10);
+        cache.insert_call_proof([1; 32], "foo".into(), vec![1, 2], Arc::new(vec![0; 5]));
+
+        assert_eq!(
+            cache.storage_value(&[1; 32], b"foo"),
+            Some(Some(&b"bar"[..]))
+        );
+        assert_eq!(cache.storage_value(&[1; 32], b"baz"), Some(None));
+        assert!(cache.storage_value(&[2; 32], b"foo").is_none());
+        assert_eq!(cache.proofs(&[1; 32]).len(), 1);
+        assert!(cache.proofs(&[2; 32]).is_empty());
+        assert_eq!(cache.call_proof(&[1; 32], "foo", &[1, 2]).unwrap().len(), 5);
+        assert!(cache.call_proof(&[1; 32], "foo", &[1]).is_none());
+        assert!(cache.call_proof(&[1; 32], "bar", &[1, 2]).is_none());
+        assert!(cache.call_proof(&[2; 32], "foo", &[1, 2]).is_none());
+        assert_eq!(cache.memory_used(), 29);
+    }
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let mut cache = StorageCache::new(100);
+        cache.insert_proof([1; 32], empty_proof(), 40);
+        cache.insert_proof([2; 32], empty_proof(), 40);
+
+        // Access the first entry so that the second one becomes the least recently used.
+        assert_eq!(cache.proofs(&[1; 32]).len(), 1);
+
+        cache.insert_proof([3; 32], empty_proof(), 40);
+        assert_eq!(cache.proofs(&[1; 32]).len(), 1);
+        assert!(cache.proofs(&[2; 32]).is_empty());
+        assert_eq!(cache.proofs(&[3; 32]).len(), 1);
+        assert_eq!(cache.memory_used(), 80);
+    }
+
+    #[test]
+    fn too_large_ignored() {
+        let mut cache = StorageCache::new(16);
+        cache.insert_proof([1; 32], empty_proof(), 17);
+        assert!(cache.proofs(&[1; 32]).is_empty());
+        assert_eq!(cache.memory_used(), 0);
+    }
+}
@@ -66,6 +66,21 @@
 //! between UIs. Instead, the smoldot Wasm client return another sender to the same already-pending
 //! transaction.
 //!
+//! # About replacing transactions
+//!
+//! Transactions whose validation indicates that they provide a tag in common with another
+//! pending transaction (typically, two transactions with the same sender and nonce) are mutually
+//! exclusive. In that situation, only the transaction with the highest priority (for example the
+//! one with the highest tip) is kept, and the other one is dropped with
+//! [`DropReason::Replaced`] or [`DropReason::PriorityTooLow`].
+//!
+//! # About broadcasting transactions
+//!
+//! Once validated, transactions are periodically sent out to the peers we are connected to. The
+//! delay between two attempts is doubled after every attempt, and every attempt only targets the
+//! peers that haven't received the transaction yet. A [`TransactionStatus::BroadcastFailed`] is
+//! generated if an attempt doesn't reach any peer.
+//!
 
 use crate::{log, network_service, platform::PlatformRef, runtime_service, sync_service};
 
@@ -304,11 +319,39 @@ impl TransactionWatcher {
 #[derive(Debug, Clone)]
 pub enum TransactionStatus {
     /// Transaction has been broadcasted to the given peers.
+    ///
+    /// The transaction is periodically broadcasted again, and each of these events only contains
+    /// the peers that have received the transaction during the latest attempt. Once all the peers
+    /// we are connected to have received the transaction, it is sent to all of them again, meaning
+    /// that the same peer can appear in multiple events.
     Broadcast(Vec<PeerId>),
 
-    /// Transaction is now known to be valid. If it ever becomes invalid in the future, a
+    /// An attempt at broadcasting the transaction hasn't reached any peer, typically because we
+    /// aren't connected to any peer. Another attempt will be made later.
+    BroadcastFailed {
+        /// Number of attempts at broadcasting the transaction so far, including this one.
+        attempts: u32,
+    },
+
+    /// Transaction is now known to be valid against the given block of the best chain. If it
+    /// ever becomes invalid in the future, a [`TransactionStatus::ValidationFailed`] or
     /// [`TransactionStatus::Dropped`] will be generated.
-    Validated,
+    Validated {
+        /// Hash of the block the transaction has been validated against.
+        block_hash: [u8; 32],
+    },
+
+    /// Transaction has failed to be validated against the given block of the best chain.
+    ///
+    /// The transaction is only dropped once it is invalid against a finalized block. In the
+    /// meanwhile, it might become valid again against a different block, for example in case
+    /// of a re-org.
+    ValidationFailed {
+        /// Hash of the block the transaction has been validated against.
+        block_hash: [u8; 32],
+        /// Reason why the validation has failed.
+        error: InvalidOrError,
+    },
 
     /// The block in which a block is included has changed.
     IncludedBlockUpdate {
@@ -346,6 +389,20 @@ pub enum DropReason {
     /// Transaction has been dropped because we have failed to validate it.
     ValidateError(ValidateTransactionError),
 
+    /// Transaction has been replaced with another transaction that is mutually exclusive with it
+    /// and has a higher priority.
+    Replaced {
+        /// BLAKE2 hash of the transaction that replaces this one.
+        by: [u8; 32],
+    },
+
+    /// Transaction is mutually exclusive with another pending transaction whose priority is
+    /// superior or equal, and has thus not replaced it.
+    PriorityTooLow {
+        /// BLAKE2 hash of the transaction that is kept in the pool.
+        existing: [u8; 32],
+    },
+
     /// Transaction service background task has crashed.
     Crashed,
 }
@@ -383,9 +440,12 @@ pub enum ValidateTransactionError {
     OutputDecodeError(validate::DecodeError),
 }
 
-#[derive(Debug, Clone)]
-enum InvalidOrError {
+/// See [`TransactionStatus::ValidationFailed`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum InvalidOrError {
+    /// Transaction is invalid.
     Invalid(validate::TransactionValidityError),
+    /// Failed to check the validity of the transaction.
     ValidateError(ValidateTransactionError),
 }
 
@@ -416,6 +476,13 @@ struct BackgroundTaskConfig<TPlat: PlatformRef> {
     max_concurrent_validations: usize,
 }
 
+/// Delay between the first and second attempts at broadcasting a transaction. This delay is
+/// doubled after every attempt, up to [`MAX_REANNOUNCE_DELAY`].
+const MIN_REANNOUNCE_DELAY: Duration = Duration::from_secs(5);
+
+/// Maximum delay between two attempts at broadcasting a transaction.
+const MAX_REANNOUNCE_DELAY: Duration = Duration::from_secs(120);
+
 /// Background task running in parallel of the front service.
 async fn background_task<TPlat: PlatformRef>(
     config: BackgroundTaskConfig<TPlat>,
@@ -989,17 +1056,26 @@ async fn background_task<TPlat: PlatformRef>(
 
                     // TODO: only announce if propagate is true
 
-                    // Update transaction state for the next re-announce.
-                    tx.when_reannounce = now + Duration::from_secs(5);
+                    // Update transaction state for the next re-announce. The delay between two
+                    // attempts is doubled every time.
+                    let delay = cmp::min(
+                        MIN_REANNOUNCE_DELAY
+                            .saturating_mul(1 << cmp::min(tx.announce_attempts, 16)),
+                        MAX_REANNOUNCE_DELAY,
+                    );
+                    tx.announce_attempts = tx.announce_attempts.saturating_add(1);
+                    tx.when_reannounce = now + delay;
                     worker.next_reannounce.push({
                         let platform = worker.platform.clone();
                         Box::pin(async move {
-                            platform.sleep(Duration::from_secs(5)).await;
+                            platform.sleep(delay).await;
                             maybe_reannounce_tx_id
                         })
                     });
 
-                    // Perform the announce.
+                    // Perform the announce. Peers that have already received the transaction
+                    // are skipped, so that every attempt targets different peers.
+                    let excluded_peers = tx.peers_sent.clone();
                     let peers_sent = worker
                         .network_service
                         .clone()
@@ -1008,6 +1084,7 @@ async fn background_task<TPlat: PlatformRef>(
                                 .pending_transactions
                                 .scale_encoding(maybe_reannounce_tx_id)
                                 .unwrap(),
+                            &excluded_peers,
                         )
                         .await;
                     log!(
@@ -1024,13 +1101,21 @@ async fn background_task<TPlat: PlatformRef>(
                         peers = peers_sent.iter().join(", ")
                     );
 
-                    // TODO: is this correct? and what should we do if announcing the same transaction multiple times? is it cumulative? `Broadcast` isn't super well documented
+                    let tx = worker
+                        .pending_transactions
+                        .transaction_user_data_mut(maybe_reannounce_tx_id)
+                        .unwrap();
                     if !peers_sent.is_empty() {
-                        worker
-                            .pending_transactions
-                            .transaction_user_data_mut(maybe_reannounce_tx_id)
-                            .unwrap()
-                            .update_status(TransactionStatus::Broadcast(peers_sent));
+                        tx.peers_sent.extend(peers_sent.iter().cloned());
+                        tx.update_status(TransactionStatus::Broadcast(peers_sent));
+                    } else if !tx.peers_sent.is_empty() {
+                        // All the peers we are connected to have already received the
+                        // transaction. Forget about them, so that the next attempt sends it to
+                        // all of them again in case they have discarded it in the meanwhile.
+                        tx.peers_sent.clear();
+                    } else {
+                        let attempts = tx.announce_attempts;
+                        tx.update_status(TransactionStatus::BroadcastFailed { attempts });
                     }
                 }
 
@@ -1108,11 +1193,79 @@ async fn background_task<TPlat: PlatformRef>(
                                 )
                             );
 
-                            worker
+                            // Transactions that provide a tag in common with this one are
+                            // mutually exclusive with it. This is typically the case of two
+                            // transactions with the same sender and nonce. Only the transaction
+                            // with the highest priority is kept.
+                            let conflicting = worker
+                                .pending_transactions
+                                .transactions_iter()
+                                .filter(|(tx_id, tx)| {
+                                    *tx_id != maybe_validated_tx_id
+                                        && tx
+                                            .provides
+                                            .iter()
+                                            .any(|tag| result.provides.contains(tag))
+                                        && !worker
+                                            .pending_transactions
+                                            .is_included_best_chain(*tx_id)
+                                })
+                                .map(|(tx_id, tx)| (tx_id, tx.priority))
+                                .collect::<Vec<_>>();
+
+                            if let Some((existing_tx_id, _)) = conflicting
+                                .iter()
+                                .find(|(_, priority)| *priority >= result.priority)
+                            {
+                                let existing_tx_hash = blake2_hash(
+                                    worker
+                                        .pending_transactions
+                                        .scale_encoding(*existing_tx_id)
+                                        .unwrap(),
+                                );
+                                log!(
+                                    &worker.platform,
+                                    Debug,
+                                    &config.log_target,
+                                    "transaction-replacement-rejected",
+                                    transaction = HashDisplay(&tx_hash),
+                                    existing = HashDisplay(&existing_tx_hash),
+                                );
+                                let (_, mut transaction) = worker
+                                    .pending_transactions
+                                    .remove_transaction(maybe_validated_tx_id);
+                                transaction.update_status(TransactionStatus::Dropped(
+                                    DropReason::PriorityTooLow {
+                                        existing: existing_tx_hash,
+                                    },
+                                ));
+                                continue;
+                            }
+
+                            for (replaced_tx_id, _) in conflicting {
+                                let (replaced_tx_body, mut replaced_tx) = worker
+                                    .pending_transactions
+                                    .remove_transaction(replaced_tx_id);
+                                log!(
+                                    &worker.platform,
+                                    Debug,
+                                    &config.log_target,
+                                    "transaction-replaced",
+                                    transaction = HashDisplay(&blake2_hash(&replaced_tx_body)),
+                                    by = HashDisplay(&tx_hash),
+                                );
+                                replaced_tx.update_status(TransactionStatus::Dropped(
+                                    DropReason::Replaced { by: tx_hash },
+                                ));
+                            }
+
+                            let tx = worker
                                 .pending_transactions
                                 .transaction_user_data_mut(maybe_validated_tx_id)
-                                .unwrap_or_else(|| unreachable!())
-                                .update_status(TransactionStatus::Validated);
+                                .unwrap_or_else(|| unreachable!());
+                            tx.priority = result.priority;
+                            tx.provides = result.provides.clone();
+                            tx.update_status(TransactionStatus::Validated { block_hash });
 
                             // Schedule this transaction for announcement.
                             worker
@@ -1179,6 +1332,18 @@ async fn background_task<TPlat: PlatformRef>(
                         }
                     };
 
+                    if let Err(error) = &validation_result {
+                        let tx = worker
+                            .pending_transactions
+                            .transaction_user_data_mut(maybe_validated_tx_id)
+                            .unwrap_or_else(|| unreachable!());
+                        tx.provides.clear();
+                        tx.update_status(TransactionStatus::ValidationFailed {
+                            block_hash,
+                            error: error.clone(),
+                        });
+                    }
+
                     // No matter whether the validation is successful, we store the result in
                     // the transactions pool. This will later be picked up by the code that removes
                     // invalid transactions from the pool.
@@ -1247,6 +1412,10 @@ async fn background_task<TPlat: PlatformRef>(
                             },
                             latest_status: None,
                             validation_in_progress: None,
+                            announce_attempts: 0,
+                            peers_sent: Vec::new(),
+                            priority: 0,
+                            provides: Vec::new(),
                         },
                     );
                 }
@@ -1357,6 +1526,12 @@ impl<TPlat: PlatformRef> Worker<TPlat> {
                 .transaction_user_data_mut(tx_id)
                 .unwrap();
             tx.update_status(TransactionStatus::IncludedBlockUpdate { block_hash: None });
+
+            // The transaction is no longer in the best chain. Start broadcasting it again from
+            // scratch.
+            tx.announce_attempts = 0;
+            tx.when_reannounce = self.platform.now();
+            self.next_reannounce.push(Box::pin(async move { tx_id }));
         }
 
         for (tx_id, block_hash, block_body_index) in updates.included_transactions {
@@ -1394,6 +1569,22 @@ struct PendingTransaction<TPlat: PlatformRef> {
     /// that is not validated.
     when_reannounce: TPlat::Instant,
 
+    /// Number of attempts at broadcasting the transaction since it has been submitted or
+    /// retracted from the best chain. Used to determine the delay before the next attempt.
+    announce_attempts: u32,
+
+    /// List of peers the transaction has been sent to, and that are excluded from the next
+    /// broadcasting attempt.
+    peers_sent: Vec<PeerId>,
+
+    /// Priority of the transaction according to its latest successful validation. `0` if it
+    /// hasn't been validated yet.
+    priority: u64,
+
+    /// Tags provided by the transaction according to its latest successful validation. Empty if
+    /// it hasn't been validated yet or if its latest validation has failed.
+    provides: Vec<Vec<u8>>,
+
     /// List of channels that should receive changes to the transaction status.
     status_update: Vec<async_channel::Sender<TransactionStatus>>,
 
@@ -1495,6 +1686,7 @@ async fn validate_transaction<TPlat: PlatformRef>(
             a.extend_from_slice(b.as_ref());
             a
         }),
+        None,
         3,
         Duration::from_secs(8),
         NonZeroU32::new(1).unwrap(),
@@ -132,11 +132,16 @@ fn add_chain(
                             max_pending_requests: json_rpc_max_pending_requests,
                             // Note: the PolkadotJS UI is very heavy in terms of subscriptions.
                             max_subscriptions: json_rpc_max_subscriptions,
+                            // The JSON-RPC client is the JavaScript code embedding smoldot,
+                            // which is trusted.
+                            runtime_call_fuel_limit: None,
                         }
                     } else {
                         smoldot_light::AddChainConfigJsonRpc::Disabled
                     },
                     potential_relay_chains: potential_relay_chains.into_iter(),
+                    finality_consistency_checks: false,
+                    network_budget: Default::default(),
                 }) {
                 Ok(c) => c,
                 Err(error) => {